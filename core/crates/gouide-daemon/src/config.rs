@@ -1,6 +1,14 @@
 //! Daemon configuration.
 
+use std::env;
+use std::path::PathBuf;
+
+use gouide_lsp::{RestartPolicy, ServerConfig};
 use gouide_protocol::{Capabilities, WorkspaceLimits};
+use gouide_workspace::BufferLimits;
+
+use crate::formatters::FormatterConfig;
+use crate::settings::Settings;
 
 /// Daemon configuration loaded from environment/args.
 #[derive(Debug, Clone)]
@@ -15,6 +23,38 @@ pub struct DaemonConfig {
     pub workspace_limits: WorkspaceLimits,
     /// Graceful shutdown timeout in seconds.
     pub shutdown_timeout_secs: u64,
+    /// Files larger than this are opened read-only via memory mapping.
+    pub large_file_threshold_bytes: u64,
+    /// Maximum buffer content returned by a single OpenBuffer/GetBufferContent.
+    pub content_chunk_bytes: usize,
+    /// Recent versions per buffer that stale edits can still be rebased onto.
+    pub edit_history_versions: usize,
+    /// How long the file watcher collects events before acting on them.
+    pub watch_debounce_ms: u64,
+    /// Events buffered per stream subject before slow subscribers are reset.
+    pub stream_capacity: usize,
+    /// Language servers started for the languages they handle.
+    pub language_servers: Vec<ServerConfig>,
+    /// When crashed language servers are restarted.
+    pub lsp_restart: RestartPolicy,
+    /// External formatters, by language. The first one for a language wins.
+    pub formatters: Vec<FormatterConfig>,
+    /// How long an external formatter may run before it is killed.
+    pub format_timeout_ms: u64,
+    /// User editor settings, overridden by `.editorconfig` and workspace
+    /// settings files.
+    pub settings: Settings,
+    /// Matches a text search returns when the client sets no limit.
+    pub search_max_results: u32,
+    /// Files a FindFiles query returns when the client sets no limit.
+    pub find_files_max_results: u32,
+    /// Where workspace indexes are cached between runs; `None` disables
+    /// the cache.
+    pub index_cache_dir: Option<PathBuf>,
+    /// Whether workspace indexes record trigrams of file contents, which
+    /// text searches use to skip files that cannot match. Costs about a
+    /// byte of memory and cache per distinct trigram of each file.
+    pub content_index: bool,
 }
 
 impl DaemonConfig {
@@ -27,6 +67,19 @@ impl DaemonConfig {
             supports_compression: false,   // Future
         }
     }
+
+    /// Buffer limits derived from this configuration.
+    ///
+    /// The chunk size is capped below the negotiated message size so a chunk
+    /// plus its envelope always fits in one message.
+    pub fn buffer_limits(&self) -> BufferLimits {
+        let max_message = self.workspace_limits.max_message_bytes as usize;
+        BufferLimits {
+            large_file_threshold: self.large_file_threshold_bytes,
+            chunk_bytes: self.content_chunk_bytes.min(max_message / 2).max(1),
+            edit_history: self.edit_history_versions,
+        }
+    }
 }
 
 impl Default for DaemonConfig {
@@ -42,10 +95,45 @@ impl Default for DaemonConfig {
                 max_concurrent_streams: 32,
             },
             shutdown_timeout_secs: 30,
+            large_file_threshold_bytes: 16 * 1024 * 1024, // 16MB
+            content_chunk_bytes: 1024 * 1024,             // 1MB
+            edit_history_versions: 256,
+            watch_debounce_ms: 50,
+            stream_capacity: 256,
+            language_servers: Vec::new(),
+            lsp_restart: RestartPolicy::default(),
+            formatters: FormatterConfig::defaults(),
+            format_timeout_ms: 10_000,
+            settings: Settings::default(),
+            search_max_results: 20_000,
+            find_files_max_results: 100,
+            index_cache_dir: default_cache_dir().map(|dir| dir.join("index")),
+            content_index: true,
         }
     }
 }
 
+/// The user's cache directory for the daemon.
+///
+/// That is `$XDG_CACHE_HOME/gouide` or `~/.cache/gouide`, except for
+/// `~/Library/Caches/gouide` on macOS and `%LOCALAPPDATA%\Gouide\cache` on
+/// Windows. `None` if the environment names no home.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let var = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+    if cfg!(windows) {
+        return var("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join("Gouide").join("cache"));
+    }
+    let home = var("HOME").map(PathBuf::from);
+    if cfg!(target_os = "macos") {
+        return home.map(|home| home.join("Library/Caches/gouide"));
+    }
+    var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home.map(|home| home.join(".cache")))
+        .map(|dir| dir.join("gouide"))
+}
+
 #[cfg(test)]
 #[allow(
     clippy::unwrap_used,
@@ -62,5 +150,18 @@ mod tests {
         assert_eq!(config.protocol_version, "1.0.0");
         assert_eq!(config.max_clients, 16);
         assert!(config.daemon_capabilities().supports_chunking);
+        assert!(config
+            .index_cache_dir
+            .map_or(true, |dir| dir.ends_with("gouide/index")));
+    }
+
+    #[test]
+    fn test_chunk_size_fits_message_limit() {
+        let config = DaemonConfig {
+            content_chunk_bytes: 64 * 1024 * 1024,
+            ..DaemonConfig::default()
+        };
+        let limits = config.buffer_limits();
+        assert!(limits.chunk_bytes < config.workspace_limits.max_message_bytes as usize);
     }
 }
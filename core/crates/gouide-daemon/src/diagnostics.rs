@@ -0,0 +1,351 @@
+//! Diagnostics store.
+//!
+//! Producers (language servers, linters, build tools) publish diagnostics
+//! for a file under their own source name. Each publish replaces that
+//! source's set for the file and leaves other sources alone; readers see
+//! the merged set. Files are tracked whether or not they are open, so the
+//! workspace problems view can list files nobody is editing.
+
+use std::collections::{BTreeMap, HashMap};
+
+use gouide_workspace::TextRange;
+use parking_lot::RwLock;
+use tokio::sync::broadcast;
+
+/// Severity of a diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// An error.
+    Error,
+    /// A warning.
+    Warning,
+    /// Information.
+    Info,
+    /// A hint.
+    Hint,
+}
+
+/// Extra classification of a diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticTag {
+    /// Unused or unreachable code.
+    Unnecessary,
+    /// Use of a deprecated item.
+    Deprecated,
+}
+
+/// A location related to a diagnostic, such as the other half of a
+/// conflict.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelatedInformation {
+    /// Workspace-relative path.
+    pub file_id: String,
+    /// Range in that file.
+    pub range: TextRange,
+    /// Message for this location.
+    pub message: String,
+}
+
+/// A problem reported for a range of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Affected range.
+    pub range: TextRange,
+    /// Severity.
+    pub severity: Severity,
+    /// Tool-specific code (e.g. "E0308"), may be empty.
+    pub code: String,
+    /// Tool that produced it (e.g. "rustc"), may be empty.
+    pub source: String,
+    /// Message.
+    pub message: String,
+    /// Related locations.
+    pub related: Vec<RelatedInformation>,
+    /// Tags.
+    pub tags: Vec<DiagnosticTag>,
+    /// Whether the producer offers quick fixes.
+    pub has_quick_fixes: bool,
+}
+
+impl Diagnostic {
+    /// A diagnostic with no code, source, related information or tags.
+    pub fn new(range: TextRange, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            range,
+            severity,
+            code: String::new(),
+            source: String::new(),
+            message: message.into(),
+            related: Vec::new(),
+            tags: Vec::new(),
+            has_quick_fixes: false,
+        }
+    }
+}
+
+/// The merged diagnostics of one file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileDiagnostics {
+    /// Workspace-relative path.
+    pub file_id: String,
+    /// Diagnostics from every source, sorted by position then severity.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Newest buffer version any source computed its set for, 0 if no
+    /// source reported one.
+    pub version: u64,
+}
+
+/// Notification that a file's diagnostics changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticsChanged {
+    /// Workspace of the file.
+    pub workspace_id: String,
+    /// Workspace-relative path.
+    pub file_id: String,
+}
+
+/// One source's diagnostics for a file.
+struct SourceSet {
+    version: Option<u64>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Diagnostics by source, for one file.
+type FileSets = BTreeMap<String, SourceSet>;
+
+/// Diagnostics for every file, by workspace, file and source.
+pub struct DiagnosticsStore {
+    files: RwLock<HashMap<String, BTreeMap<String, FileSets>>>,
+    changes: broadcast::Sender<DiagnosticsChanged>,
+}
+
+impl DiagnosticsStore {
+    /// Create an empty store whose change channel buffers `capacity`
+    /// notifications per subscriber.
+    pub fn new(capacity: usize) -> Self {
+        let (changes, _) = broadcast::channel(capacity.max(1));
+        Self {
+            files: RwLock::new(HashMap::new()),
+            changes,
+        }
+    }
+
+    /// Replace `source`'s diagnostics for a file. An empty set clears
+    /// them. `version` is the buffer version they were computed for, if
+    /// the producer knows it.
+    pub fn publish(
+        &self,
+        workspace_id: &str,
+        file_id: &str,
+        source: &str,
+        version: Option<u64>,
+        diagnostics: Vec<Diagnostic>,
+    ) {
+        {
+            let mut files = self.files.write();
+            if diagnostics.is_empty() {
+                let Some(workspace) = files.get_mut(workspace_id) else {
+                    return;
+                };
+                let Some(sets) = workspace.get_mut(file_id) else {
+                    return;
+                };
+                if sets.remove(source).is_none() {
+                    return;
+                }
+                if sets.is_empty() {
+                    workspace.remove(file_id);
+                }
+            } else {
+                files
+                    .entry(workspace_id.to_string())
+                    .or_default()
+                    .entry(file_id.to_string())
+                    .or_default()
+                    .insert(
+                        source.to_string(),
+                        SourceSet {
+                            version,
+                            diagnostics,
+                        },
+                    );
+            }
+        }
+        self.notify(workspace_id, file_id);
+    }
+
+    /// Clear everything `source` published in a workspace, for example
+    /// when its language server exits.
+    pub fn clear_source(&self, workspace_id: &str, source: &str) {
+        let mut cleared = Vec::new();
+        if let Some(workspace) = self.files.write().get_mut(workspace_id) {
+            workspace.retain(|file_id, sets| {
+                if sets.remove(source).is_some() {
+                    cleared.push(file_id.clone());
+                }
+                !sets.is_empty()
+            });
+        }
+        for file_id in cleared {
+            self.notify(workspace_id, &file_id);
+        }
+    }
+
+    /// Forget a closed workspace, telling subscribers its files are clear.
+    pub fn remove_workspace(&self, workspace_id: &str) {
+        let removed = self.files.write().remove(workspace_id);
+        for file_id in removed.into_iter().flat_map(BTreeMap::into_keys) {
+            self.notify(workspace_id, &file_id);
+        }
+    }
+
+    /// Merged diagnostics of a file (empty if it has none).
+    pub fn file(&self, workspace_id: &str, file_id: &str) -> FileDiagnostics {
+        let files = self.files.read();
+        files
+            .get(workspace_id)
+            .and_then(|workspace| workspace.get(file_id))
+            .map_or_else(
+                || FileDiagnostics {
+                    file_id: file_id.to_string(),
+                    ..FileDiagnostics::default()
+                },
+                |sets| merge(file_id, sets),
+            )
+    }
+
+    /// Merged diagnostics of every file in a workspace that has any,
+    /// sorted by path.
+    pub fn workspace(&self, workspace_id: &str) -> Vec<FileDiagnostics> {
+        let files = self.files.read();
+        files.get(workspace_id).map_or_else(Vec::new, |workspace| {
+            workspace
+                .iter()
+                .map(|(file_id, sets)| merge(file_id, sets))
+                .collect()
+        })
+    }
+
+    /// Subscribe to change notifications.
+    pub fn subscribe(&self) -> broadcast::Receiver<DiagnosticsChanged> {
+        self.changes.subscribe()
+    }
+
+    fn notify(&self, workspace_id: &str, file_id: &str) {
+        // Nobody listening is fine
+        let _ = self.changes.send(DiagnosticsChanged {
+            workspace_id: workspace_id.to_string(),
+            file_id: file_id.to_string(),
+        });
+    }
+}
+
+fn merge(file_id: &str, sets: &FileSets) -> FileDiagnostics {
+    let mut diagnostics: Vec<Diagnostic> = sets
+        .values()
+        .flat_map(|set| set.diagnostics.iter().cloned())
+        .collect();
+    diagnostics.sort_by_key(|d| (d.range.start, d.severity));
+    FileDiagnostics {
+        file_id: file_id.to_string(),
+        diagnostics,
+        version: sets
+            .values()
+            .filter_map(|set| set.version)
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(line: u32, severity: Severity, message: &str) -> Diagnostic {
+        Diagnostic::new(TextRange::lines(line, line + 1), severity, message)
+    }
+
+    fn messages(file: &FileDiagnostics) -> Vec<&str> {
+        file.diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_sources_replace_their_own_sets() {
+        let store = DiagnosticsStore::new(16);
+        let mut changes = store.subscribe();
+
+        store.publish(
+            "w1",
+            "a.rs",
+            "lsp",
+            Some(3),
+            vec![diagnostic(4, Severity::Error, "mismatched types")],
+        );
+        store.publish(
+            "w1",
+            "a.rs",
+            "clippy",
+            None,
+            vec![
+                diagnostic(1, Severity::Warning, "needless return"),
+                diagnostic(4, Severity::Hint, "consider"),
+            ],
+        );
+        let file = store.file("w1", "a.rs");
+        assert_eq!(
+            messages(&file),
+            ["needless return", "mismatched types", "consider"]
+        );
+        assert_eq!(file.version, 3);
+
+        // A new publish replaces only that source's set
+        store.publish("w1", "a.rs", "lsp", Some(5), vec![]);
+        let file = store.file("w1", "a.rs");
+        assert_eq!(messages(&file), ["needless return", "consider"]);
+        assert_eq!(file.version, 0);
+
+        store.publish(
+            "w1",
+            "src/b.rs",
+            "lsp",
+            Some(1),
+            vec![diagnostic(0, Severity::Error, "unresolved import")],
+        );
+        let files: Vec<String> = store
+            .workspace("w1")
+            .into_iter()
+            .map(|f| f.file_id)
+            .collect();
+        assert_eq!(files, ["a.rs", "src/b.rs"]);
+        assert!(store.workspace("w2").is_empty());
+
+        store.clear_source("w2", "clippy");
+        assert_eq!(store.file("w1", "a.rs").diagnostics.len(), 2);
+        store.clear_source("w1", "clippy");
+        assert!(store.file("w1", "a.rs").diagnostics.is_empty());
+        assert_eq!(store.workspace("w1").len(), 1);
+
+        let mut notified = Vec::new();
+        while let Ok(change) = changes.try_recv() {
+            notified.push(change.file_id);
+        }
+        assert_eq!(notified, ["a.rs", "a.rs", "a.rs", "src/b.rs", "a.rs"]);
+
+        // Clearing a source with nothing published is silent
+        store.publish("w1", "a.rs", "lsp", None, vec![]);
+        assert!(changes.try_recv().is_err());
+
+        store.remove_workspace("w1");
+        assert_eq!(changes.try_recv().unwrap().file_id, "src/b.rs");
+        assert!(store.workspace("w1").is_empty());
+    }
+}
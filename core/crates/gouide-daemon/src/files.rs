@@ -0,0 +1,604 @@
+//! Indexes of open workspaces.
+//!
+//! Each open workspace gets an [`Indexer`], run on a background thread when
+//! the workspace opens. It scans the workspace into a [`PathIndex`] for
+//! quick open, then reads every file it found, extracting the symbols
+//! source files declare and, with the content index enabled, a trigram
+//! filter of each file that text searches narrow their files by once
+//! indexing completes. File watcher events keep the index current from
+//! then on, including while the run is still going, and queries use
+//! whatever has been indexed so far. Open buffers' symbols are set from
+//! their text and replace those of their files.
+//!
+//! With a cache directory configured, each workspace's index is saved there
+//! when indexing completes and when the workspace closes, and the next run
+//! starts from it, reading only files that changed since.
+//!
+//! Indexing stays out of the way of interactive work: it runs at a lower
+//! scheduling priority where the platform allows, scans with half the CPUs,
+//! and waits between batches while requests are in flight. Its status is
+//! published on a watch channel per workspace, which keeps only the latest.
+//!
+//! A client's query supersedes its previous one of the same kind for the
+//! same workspace, which stops at its next cancellation check.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Instant;
+
+use gouide_fs::FsEvent;
+use gouide_index::{
+    cache_path, FileMatch, FileQuery, IndexError, IndexState, IndexStatus, IndexedSymbol, Indexer,
+    PathFilter, SymbolMatch, SymbolQuery,
+};
+use gouide_search::Trigrams;
+use gouide_workspace::Workspace;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::languages::LanguageRegistry;
+use crate::requests::RequestTracker;
+
+/// Nice value of indexing threads; higher runs less.
+#[cfg(target_os = "linux")]
+const INDEX_NICENESS: libc::c_int = 10;
+
+/// Files matching a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundFiles {
+    /// Matches, best first.
+    pub matches: Vec<FileMatch>,
+    /// Whether the first scan of the workspace had finished.
+    pub complete: bool,
+    /// Files in the index.
+    pub file_count: usize,
+}
+
+/// Symbols matching a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundSymbols {
+    /// Matches, best first.
+    pub matches: Vec<SymbolMatch>,
+    /// Whether every file of the workspace had been read.
+    pub complete: bool,
+}
+
+/// What a query searches. A client's queries only supersede those of the
+/// same kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryKind {
+    /// File paths.
+    Files,
+    /// Symbol names.
+    Symbols,
+}
+
+/// Running queries by kind, client and workspace.
+type QueryKey = (QueryKind, String, String);
+
+/// The index of one workspace.
+struct WorkspaceFiles {
+    /// Replaced when the cache is cleared.
+    indexer: RwLock<Arc<Indexer>>,
+    /// Bumped with each new indexer, whose predecessors' reports are then
+    /// dropped.
+    generation: AtomicU64,
+    /// Latest status of the indexer. Dropped with the workspace, which ends
+    /// the subscriptions.
+    status: watch::Sender<IndexStatus>,
+    /// Symbols of open buffers by path, kept across indexers.
+    buffer_symbols: RwLock<HashMap<String, Vec<IndexedSymbol>>>,
+}
+
+/// Indexes by workspace, and the queries running against them.
+pub struct FileIndexes {
+    workspaces: RwLock<HashMap<String, Arc<WorkspaceFiles>>>,
+    /// Cancel flag of the running query, by kind, client and workspace.
+    queries: Mutex<HashMap<QueryKey, Arc<AtomicBool>>>,
+    /// Requests that indexing gives way to.
+    requests: Arc<RequestTracker>,
+    languages: Arc<LanguageRegistry>,
+    cache_dir: Option<PathBuf>,
+    /// Whether indexers record trigram filters of file contents.
+    content_index: bool,
+}
+
+impl FileIndexes {
+    /// Create an empty set of indexes whose indexing yields to `requests`,
+    /// cached in `cache_dir`.
+    pub fn new(
+        requests: Arc<RequestTracker>,
+        languages: Arc<LanguageRegistry>,
+        cache_dir: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            workspaces: RwLock::new(HashMap::new()),
+            queries: Mutex::new(HashMap::new()),
+            requests,
+            languages,
+            cache_dir,
+            content_index: false,
+        }
+    }
+
+    /// Also index file contents by trigram, so text searches only read the
+    /// files that may match.
+    #[must_use]
+    pub const fn with_content_index(mut self) -> Self {
+        self.content_index = true;
+        self
+    }
+
+    /// Start indexing an opened workspace on a background thread. Does
+    /// nothing if it is already indexed.
+    ///
+    /// Files get languages from the registry, so the workspace's language
+    /// overrides should be set first. A workspace whose exclude patterns do
+    /// not compile is logged and left unindexed.
+    pub fn open_workspace(&self, workspace: &Workspace) {
+        let mut workspaces = self.workspaces.write();
+        if workspaces.contains_key(workspace.id()) {
+            return;
+        }
+        let Some(filter) = filter(workspace) else {
+            return;
+        };
+        let files = Arc::new_cyclic(|files| {
+            let indexer = self.build(workspace, filter, files.clone(), 0);
+            WorkspaceFiles {
+                status: watch::channel(indexer.status()).0,
+                indexer: RwLock::new(Arc::new(indexer)),
+                generation: AtomicU64::new(0),
+                buffer_symbols: RwLock::new(HashMap::new()),
+            }
+        });
+        workspaces.insert(workspace.id().to_string(), files.clone());
+        drop(workspaces);
+        let indexer = files.indexer.read().clone();
+        self.start(workspace.id(), indexer);
+    }
+
+    /// Drop the index of a closed workspace, stopping its indexing and
+    /// saving it to the cache if it was complete.
+    pub fn close_workspace(&self, workspace_id: &str) {
+        let removed = self.workspaces.write().remove(workspace_id);
+        if let Some(files) = removed {
+            let indexer = files.indexer.read().clone();
+            indexer.stop();
+            let workspace_id = workspace_id.to_string();
+            let spawned = thread::Builder::new()
+                .name("index-cache".to_string())
+                .spawn(move || save(&workspace_id, &indexer));
+            if let Err(e) = spawned {
+                warn!(error = %e, "Failed to save the index cache");
+            }
+        }
+        self.queries
+            .lock()
+            .retain(|(_, _, workspace), _| workspace != workspace_id);
+    }
+
+    /// Delete the cached index of a workspace and index it again from
+    /// scratch. `None` if it is not indexed.
+    pub fn clear_cache(&self, workspace: &Workspace) -> Result<Option<IndexStatus>, IndexError> {
+        let Some(files) = self.workspaces.read().get(workspace.id()).cloned() else {
+            return Ok(None);
+        };
+        let Some(filter) = filter(workspace) else {
+            return Ok(None);
+        };
+        let previous = files.indexer.read().clone();
+        previous.stop();
+        previous.clear_cache()?;
+
+        let generation = files.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        let indexer = Arc::new(self.build(workspace, filter, Arc::downgrade(&files), generation));
+        let status = indexer.status();
+        *files.indexer.write() = indexer.clone();
+        files.status.send_replace(status.clone());
+        self.start(workspace.id(), indexer);
+        debug!(workspace_id = %workspace.id(), "Index cache cleared");
+        Ok(Some(status))
+    }
+
+    /// Whether a workspace has an index.
+    pub fn is_indexed(&self, workspace_id: &str) -> bool {
+        self.workspaces.read().contains_key(workspace_id)
+    }
+
+    /// Indexing status of a workspace. `None` if it is not indexed.
+    pub fn status(&self, workspace_id: &str) -> Option<IndexStatus> {
+        Some(self.indexer(workspace_id)?.status())
+    }
+
+    /// Follow the indexing status of a workspace. The receiver sees its
+    /// sender dropped when the workspace closes.
+    pub fn subscribe(&self, workspace_id: &str) -> Option<watch::Receiver<IndexStatus>> {
+        let files = self.workspaces.read().get(workspace_id).cloned()?;
+        Some(files.status.subscribe())
+    }
+
+    /// Pause indexing a workspace, returning the status after.
+    pub fn pause(&self, workspace_id: &str) -> Option<IndexStatus> {
+        Some(self.indexer(workspace_id)?.pause())
+    }
+
+    /// Resume indexing a paused workspace, returning the status after.
+    pub fn resume(&self, workspace_id: &str) -> Option<IndexStatus> {
+        Some(self.indexer(workspace_id)?.resume())
+    }
+
+    /// Apply a batch of file system events in a workspace to its index.
+    pub fn files_changed(&self, workspace_id: &str, events: &[FsEvent]) {
+        if let Some(indexer) = self.indexer(workspace_id) {
+            indexer.apply_events(events);
+        }
+    }
+
+    /// Use the symbols of an open buffer for its file, or with `None` go
+    /// back to the file's once the buffer is closed.
+    pub fn set_buffer_symbols(
+        &self,
+        workspace_id: &str,
+        path: &str,
+        symbols: Option<Vec<IndexedSymbol>>,
+    ) {
+        let Some(files) = self.workspaces.read().get(workspace_id).cloned() else {
+            return;
+        };
+        let mut buffers = files.buffer_symbols.write();
+        match symbols {
+            Some(symbols) => buffers.insert(path.to_string(), symbols),
+            None => buffers.remove(path),
+        };
+    }
+
+    /// Register a client's query against a workspace, superseding its
+    /// previous one of the same kind. The query should stop once the
+    /// returned flag is set.
+    pub fn begin_query(
+        &self,
+        kind: QueryKind,
+        client_id: &str,
+        workspace_id: &str,
+    ) -> Arc<AtomicBool> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let key = (kind, client_id.to_string(), workspace_id.to_string());
+        let previous = self.queries.lock().insert(key, cancel.clone());
+        if let Some(previous) = previous {
+            previous.store(true, Ordering::Relaxed);
+        }
+        cancel
+    }
+
+    /// Unregister a query once it has finished.
+    pub fn end_query(
+        &self,
+        kind: QueryKind,
+        client_id: &str,
+        workspace_id: &str,
+        cancel: &Arc<AtomicBool>,
+    ) {
+        let key = (kind, client_id.to_string(), workspace_id.to_string());
+        let mut queries = self.queries.lock();
+        if queries
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, cancel))
+        {
+            queries.remove(&key);
+        }
+    }
+
+    /// Find a workspace's files. `None` if the query was cancelled or the
+    /// workspace is not indexed.
+    pub fn find(
+        &self,
+        workspace_id: &str,
+        query: &FileQuery,
+        cancel: &AtomicBool,
+    ) -> Option<FoundFiles> {
+        let indexer = self.indexer(workspace_id)?;
+        // Read before searching, so a scan finishing meanwhile is not
+        // reported complete with part of its files
+        let complete = indexer.is_scanned();
+        let paths = indexer.paths();
+        let matches = paths.find(query, cancel)?;
+        Some(FoundFiles {
+            matches,
+            complete,
+            file_count: paths.len(),
+        })
+    }
+
+    /// Find the symbols declared in a workspace. `None` if the query was
+    /// cancelled or the workspace is not indexed.
+    pub fn find_symbols(
+        &self,
+        workspace_id: &str,
+        query: &SymbolQuery,
+        cancel: &AtomicBool,
+    ) -> Option<FoundSymbols> {
+        let files = self.workspaces.read().get(workspace_id).cloned()?;
+        let indexer = files.indexer.read().clone();
+        let complete = indexer.status().state == IndexState::Complete;
+        let buffers = files.buffer_symbols.read();
+        let matches = indexer.find_symbols(query, &buffers, cancel);
+        drop(buffers);
+        Some(FoundSymbols {
+            matches: matches?,
+            complete,
+        })
+    }
+
+    /// Paths of a workspace's files that may contain every one of
+    /// `trigrams`. `None` if the workspace has no complete content index,
+    /// in which case any file may.
+    pub fn candidates(&self, workspace_id: &str, trigrams: &Trigrams) -> Option<Vec<String>> {
+        self.indexer(workspace_id)?.candidates(trigrams)
+    }
+
+    fn indexer(&self, workspace_id: &str) -> Option<Arc<Indexer>> {
+        let files = self.workspaces.read().get(workspace_id).cloned()?;
+        let indexer = files.indexer.read().clone();
+        Some(indexer)
+    }
+
+    /// A new indexer for a workspace, reporting to `files` while it is the
+    /// indexer of `generation`.
+    fn build(
+        &self,
+        workspace: &Workspace,
+        filter: PathFilter,
+        files: Weak<WorkspaceFiles>,
+        generation: u64,
+    ) -> Indexer {
+        let threads = thread::available_parallelism().map_or(1, |n| (n.get() / 2).max(1));
+        let (languages, workspace_id) = (self.languages.clone(), workspace.id().to_string());
+        let indexer = Indexer::new(filter.with_scan_threads(threads), move |status| {
+            let current = files
+                .upgrade()
+                .filter(|files| files.generation.load(Ordering::Relaxed) == generation);
+            if let Some(files) = current {
+                files.status.send_replace(status.clone());
+            }
+        })
+        .with_languages(move |path| languages.detect_path(&workspace_id, path))
+        .with_symbols();
+        let indexer = if self.content_index {
+            indexer.with_trigrams()
+        } else {
+            indexer
+        };
+        match &self.cache_dir {
+            Some(dir) => indexer.with_cache(
+                cache_path(dir, workspace.root()),
+                self.languages.rules(workspace.id()),
+            ),
+            None => indexer,
+        }
+    }
+
+    /// Run an indexer on a background thread, saving its index once it
+    /// completes.
+    fn start(&self, workspace_id: &str, indexer: Arc<Indexer>) {
+        let requests = self.requests.clone();
+        let id = workspace_id.to_string();
+        let spawned = thread::Builder::new()
+            .name("file-index".to_string())
+            .spawn(move || {
+                lower_priority();
+                let started = Instant::now();
+                indexer.run(&|| requests.in_flight() > 0);
+                let status = indexer.status();
+                debug!(
+                    workspace_id = %id,
+                    state = ?status.state,
+                    files = status.file_count,
+                    reindexed = indexer.reindexed(),
+                    elapsed_ms = started.elapsed().as_millis(),
+                    "Workspace indexing ended"
+                );
+                save(&id, &indexer);
+            });
+        if let Err(e) = spawned {
+            warn!(workspace_id = %workspace_id, error = %e, "Failed to start indexing");
+        }
+    }
+}
+
+/// Which files of a workspace to index. `None`, logged, if its exclude
+/// patterns do not compile.
+fn filter(workspace: &Workspace) -> Option<PathFilter> {
+    PathFilter::new(workspace.root(), workspace.exclude_patterns(), true)
+        .map_err(|e| {
+            warn!(workspace_id = %workspace.id(), error = %e, "Cannot index workspace");
+        })
+        .ok()
+}
+
+/// Save an index to its cache, logging failures.
+fn save(workspace_id: &str, indexer: &Indexer) {
+    match indexer.save_cache() {
+        Ok(true) => debug!(workspace_id = %workspace_id, "Index cache saved"),
+        Ok(false) => {}
+        Err(e) => warn!(workspace_id = %workspace_id, error = %e, "Failed to save the index cache"),
+    }
+}
+
+/// Lower the scheduling priority of the calling thread, and of the threads
+/// it starts from then on.
+#[allow(unsafe_code)]
+fn lower_priority() {
+    // On Linux, a nice value belongs to the thread; elsewhere it would
+    // apply to the whole daemon
+    #[cfg(target_os = "linux")]
+    // SAFETY: setpriority only reads its arguments; 0 names the calling
+    // thread
+    unsafe {
+        if libc::setpriority(libc::PRIO_PROCESS, 0, INDEX_NICENESS) != 0 {
+            debug!("Could not lower the indexing thread's priority");
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use gouide_fs::FsEventKind;
+    use gouide_index::IndexState;
+    use gouide_workspace::WorkspaceManager;
+    use std::fs;
+    use std::time::Duration;
+
+    /// Wait for the first scan of a workspace to finish.
+    fn wait_scanned(indexes: &FileIndexes, workspace_id: &str) {
+        for _ in 0..500 {
+            let found = indexes.find(workspace_id, &FileQuery::default(), &AtomicBool::new(false));
+            if found.is_some_and(|found| found.complete) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Scan did not finish");
+    }
+
+    fn find(indexes: &FileIndexes, workspace_id: &str, pattern: &str) -> Vec<String> {
+        let query = FileQuery {
+            pattern: pattern.to_string(),
+            ..FileQuery::default()
+        };
+        let found = indexes
+            .find(workspace_id, &query, &AtomicBool::new(false))
+            .unwrap();
+        found.matches.into_iter().map(|m| m.path).collect()
+    }
+
+    #[test]
+    fn test_index_follows_workspace_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::create_dir_all(dir.path().join("vendor")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        fs::write(dir.path().join("vendor/main.rs"), "").unwrap();
+        let workspaces = WorkspaceManager::new();
+        let workspace = workspaces
+            .open_workspace(dir.path(), None, vec!["vendor".to_string()])
+            .unwrap();
+        let indexes = FileIndexes::new(
+            Arc::new(RequestTracker::new()),
+            Arc::new(LanguageRegistry::new()),
+            None,
+        );
+        indexes.open_workspace(&workspace);
+        wait_scanned(&indexes, workspace.id());
+        assert_eq!(find(&indexes, workspace.id(), "main"), ["src/main.rs"]);
+        let mut status = indexes.subscribe(workspace.id()).unwrap();
+        for _ in 0..500 {
+            if status.borrow_and_update().state == IndexState::Complete {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(indexes.status(workspace.id()).unwrap().file_count, 1);
+
+        fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+        indexes.files_changed(
+            workspace.id(),
+            &[FsEvent {
+                path: dir.path().join("src/lib.rs"),
+                kind: FsEventKind::Created,
+            }],
+        );
+        assert_eq!(find(&indexes, workspace.id(), "lib"), ["src/lib.rs"]);
+
+        indexes.close_workspace(workspace.id());
+        assert!(!indexes.is_indexed(workspace.id()));
+        // Subscribers see the workspace go
+        assert!(status.has_changed().is_err());
+    }
+
+    /// Wait until an indexer has completed and saved its cache.
+    fn wait_saved(indexes: &FileIndexes, workspace_id: &str, cache: &std::path::Path) {
+        for _ in 0..500 {
+            let status = indexes.status(workspace_id).unwrap();
+            if status.state == IndexState::Complete && cache.exists() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Index was not saved");
+    }
+
+    #[test]
+    fn test_index_cache_across_openings() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("main.rs"), "").unwrap();
+        fs::write(dir.path().join("README.md"), "").unwrap();
+        let workspaces = WorkspaceManager::new();
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let cache = cache_path(cache_dir.path(), workspace.root());
+        let indexes = || {
+            FileIndexes::new(
+                Arc::new(RequestTracker::new()),
+                Arc::new(LanguageRegistry::new()),
+                Some(cache_dir.path().to_path_buf()),
+            )
+        };
+
+        let first = indexes();
+        first.open_workspace(&workspace);
+        wait_saved(&first, workspace.id(), &cache);
+        let indexer = first.indexer(workspace.id()).unwrap();
+        assert_eq!(indexer.reindexed(), 2);
+        assert_eq!(indexer.file_info("main.rs").unwrap().language, "rust");
+        first.close_workspace(workspace.id());
+
+        let second = indexes();
+        second.open_workspace(&workspace);
+        wait_saved(&second, workspace.id(), &cache);
+        assert_eq!(second.indexer(workspace.id()).unwrap().reindexed(), 0);
+
+        // Clearing starts over without the cache
+        let status = second.subscribe(workspace.id()).unwrap();
+        second.clear_cache(&workspace).unwrap().unwrap();
+        assert!(status.has_changed().unwrap());
+        wait_saved(&second, workspace.id(), &cache);
+        assert_eq!(second.indexer(workspace.id()).unwrap().reindexed(), 2);
+    }
+
+    #[test]
+    fn test_new_query_supersedes_previous() {
+        let indexes = FileIndexes::new(
+            Arc::new(RequestTracker::new()),
+            Arc::new(LanguageRegistry::new()),
+            None,
+        );
+        let files = QueryKind::Files;
+        let first = indexes.begin_query(files, "client", "ws");
+        let other = indexes.begin_query(files, "other", "ws");
+        let symbols = indexes.begin_query(QueryKind::Symbols, "client", "ws");
+        let second = indexes.begin_query(files, "client", "ws");
+        assert!(first.load(Ordering::Relaxed));
+        assert!(!other.load(Ordering::Relaxed));
+        assert!(!symbols.load(Ordering::Relaxed));
+        assert!(!second.load(Ordering::Relaxed));
+
+        // A superseded query finishing leaves the newer one registered
+        indexes.end_query(files, "client", "ws", &first);
+        let third = indexes.begin_query(files, "client", "ws");
+        assert!(second.load(Ordering::Relaxed));
+        indexes.end_query(files, "client", "ws", &third);
+        assert_eq!(indexes.queries.lock().len(), 2);
+    }
+}
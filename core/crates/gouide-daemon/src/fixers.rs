@@ -0,0 +1,169 @@
+//! Built-in fixes that work without a language server.
+//!
+//! A fixer looks at a buffer's whole text and returns the edits that fix
+//! it, or none when there is nothing to fix. Edits are ordered from the end
+//! of the text to the start, so they can be applied one after another.
+
+use gouide_workspace::{Position, TextEdit, TextRange};
+
+/// A built-in fix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fixer {
+    /// Remove whitespace at the end of lines.
+    TrimTrailingWhitespace,
+    /// End the text with a line break.
+    InsertFinalNewline,
+}
+
+impl Fixer {
+    /// Every built-in fixer.
+    pub const ALL: [Self; 2] = [Self::TrimTrailingWhitespace, Self::InsertFinalNewline];
+
+    /// LSP code action kind of every built-in fix.
+    pub const KIND: &'static str = "source.fixAll";
+
+    /// Title to show for the fix.
+    pub const fn title(self) -> &'static str {
+        match self {
+            Self::TrimTrailingWhitespace => "Remove trailing whitespace",
+            Self::InsertFinalNewline => "Add missing final newline",
+        }
+    }
+
+    /// Edits that fix `text`, empty if it needs no fixing. `line_ending` is
+    /// the line break to insert.
+    pub fn edits(self, text: &str, line_ending: &str) -> Vec<TextEdit> {
+        match self {
+            Self::TrimTrailingWhitespace => trailing_whitespace(text),
+            Self::InsertFinalNewline => final_newline(text, line_ending).into_iter().collect(),
+        }
+    }
+
+    /// Whether the fix would change `text`.
+    pub fn applies_to(self, text: &str) -> bool {
+        match self {
+            Self::TrimTrailingWhitespace => lines(text).any(|line| trimmed(line) != line),
+            Self::InsertFinalNewline => !text.is_empty() && !text.ends_with(['\n', '\r']),
+        }
+    }
+}
+
+/// Lines of `text` without their line breaks. CR, LF and CRLF all end a
+/// line, as they do for buffer positions.
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(text);
+    std::iter::from_fn(move || {
+        let current = rest?;
+        let Some(end) = current.find(['\n', '\r']) else {
+            rest = None;
+            return Some(current);
+        };
+        let next = if current[end..].starts_with("\r\n") {
+            end + 2
+        } else {
+            end + 1
+        };
+        rest = Some(&current[next..]);
+        Some(&current[..end])
+    })
+}
+
+fn trimmed(line: &str) -> &str {
+    line.trim_end_matches(|c: char| c.is_whitespace())
+}
+
+fn utf16_len(text: &str) -> u32 {
+    u32::try_from(text.encode_utf16().count()).unwrap_or(u32::MAX)
+}
+
+fn trailing_whitespace(text: &str) -> Vec<TextEdit> {
+    let mut edits: Vec<TextEdit> = lines(text)
+        .zip(0u32..)
+        .filter_map(|(line, number)| {
+            let content = trimmed(line);
+            (content != line).then(|| {
+                TextEdit::new(
+                    TextRange::new(
+                        Position::new(number, utf16_len(content)),
+                        Position::new(number, utf16_len(line)),
+                    ),
+                    String::new(),
+                )
+            })
+        })
+        .collect();
+    edits.reverse();
+    edits
+}
+
+fn final_newline(text: &str, line_ending: &str) -> Option<TextEdit> {
+    if !Fixer::InsertFinalNewline.applies_to(text) {
+        return None;
+    }
+    let (count, last) = lines(text).fold((0u32, ""), |(count, _), line| (count + 1, line));
+    let end = Position::new(count - 1, utf16_len(last));
+    Some(TextEdit::new(
+        TextRange::new(end, end),
+        line_ending.to_string(),
+    ))
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    /// Apply edits in order to a single-line-ending text.
+    fn apply(text: &str, edits: &[TextEdit]) -> String {
+        let mut lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        for edit in edits {
+            let line = &mut lines[edit.range.start.line as usize];
+            let units: Vec<u16> = line.encode_utf16().collect();
+            let start = edit.range.start.character as usize;
+            let end = edit.range.end.character as usize;
+            *line = format!(
+                "{}{}{}",
+                String::from_utf16(&units[..start]).unwrap(),
+                edit.new_text,
+                String::from_utf16(&units[end..]).unwrap()
+            );
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn test_trailing_whitespace() {
+        let text = "fn a() {  \n\tlet é = 1;\t \n}\n   ";
+        let fixer = Fixer::TrimTrailingWhitespace;
+        assert!(fixer.applies_to(text));
+
+        let edits = fixer.edits(text, "\n");
+        let lines: Vec<_> = edits.iter().map(|e| e.range.start.line).collect();
+        assert_eq!(lines, [3, 1, 0]);
+        // Columns count UTF-16 units
+        assert_eq!(edits[1].range.start.character, 11);
+        assert_eq!(apply(text, &edits), "fn a() {\n\tlet é = 1;\n}\n");
+
+        assert!(!fixer.applies_to("a\r\nb\r\n"));
+        assert!(fixer.edits("a\r\nb\r\n", "\r\n").is_empty());
+    }
+
+    #[test]
+    fn test_final_newline() {
+        let fixer = Fixer::InsertFinalNewline;
+        let edits = fixer.edits("a\nbc", "\n");
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].range.start, Position::new(1, 2));
+        assert_eq!(apply("a\nbc", &edits), "a\nbc\n");
+
+        assert_eq!(fixer.edits("a\r\nb", "\r\n")[0].new_text, "\r\n");
+        assert!(!fixer.applies_to("a\n"));
+        assert!(!fixer.applies_to("a\r"));
+        assert!(!fixer.applies_to(""));
+    }
+}
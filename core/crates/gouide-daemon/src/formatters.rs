@@ -0,0 +1,401 @@
+//! Formatter registry.
+//!
+//! A buffer is formatted by the first of these that can handle it:
+//!
+//! 1. The external formatter configured for its language: a command that
+//!    reads the text on stdin and writes the formatted text to stdout.
+//!    Formatters that are not installed are skipped.
+//! 2. A language server for the buffer that supports formatting.
+//! 3. The built-in formatter, which only applies the formatting
+//!    options (see [`apply_options`]).
+//!
+//! Every formatter produces the full formatted text; callers diff it against
+//! the buffer to get edits.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Placeholder in formatter arguments replaced by the file's path.
+pub const PATH_PLACEHOLDER: &str = "{path}";
+
+/// An external formatter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatterConfig {
+    /// Name, shown in results and errors (e.g. "rustfmt").
+    pub name: String,
+    /// Program to run, looked up on `PATH` if not absolute.
+    pub command: String,
+    /// Arguments. [`PATH_PLACEHOLDER`] is replaced by the file's path, for
+    /// tools that pick their settings by file name.
+    pub args: Vec<String>,
+    /// Language IDs the formatter handles.
+    pub languages: Vec<String>,
+}
+
+impl FormatterConfig {
+    /// A formatter run with `args`.
+    pub fn new(
+        name: impl Into<String>,
+        command: impl Into<String>,
+        args: impl IntoIterator<Item = impl Into<String>>,
+        languages: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            command: command.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            languages: languages.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Formatters for common languages, used unless configured otherwise.
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::new(
+                "rustfmt",
+                "rustfmt",
+                ["--emit", "stdout", "--edition", "2021"],
+                ["rust"],
+            ),
+            Self::new(
+                "prettier",
+                "prettier",
+                ["--stdin-filepath", PATH_PLACEHOLDER],
+                [
+                    "css",
+                    "html",
+                    "javascript",
+                    "javascriptreact",
+                    "json",
+                    "jsonc",
+                    "markdown",
+                    "typescript",
+                    "typescriptreact",
+                    "yaml",
+                ],
+            ),
+            Self::new(
+                "black",
+                "black",
+                ["--quiet", "--stdin-filename", PATH_PLACEHOLDER, "-"],
+                ["python"],
+            ),
+        ]
+    }
+
+    /// Whether the formatter handles a language.
+    pub fn handles(&self, language_id: &str) -> bool {
+        self.languages.iter().any(|l| l == language_id)
+    }
+
+    /// Format `text`, the content of the file at `path`.
+    ///
+    /// The formatter runs in the file's directory so it finds the project's
+    /// settings, and is killed if it takes longer than `timeout`.
+    pub async fn run(
+        &self,
+        text: &str,
+        path: &Path,
+        timeout: Duration,
+    ) -> Result<String, FormatError> {
+        let file = path.to_string_lossy();
+        let mut command = Command::new(&self.command);
+        command
+            .args(
+                self.args
+                    .iter()
+                    .map(|arg| arg.replace(PATH_PLACEHOLDER, &file)),
+            )
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(dir) = path.parent().filter(|dir| dir.is_dir()) {
+            command.current_dir(dir);
+        }
+        let mut child = command.spawn().map_err(|source| match source.kind() {
+            std::io::ErrorKind::NotFound => FormatError::NotInstalled(self.name.clone()),
+            _ => FormatError::Io {
+                name: self.name.clone(),
+                source,
+            },
+        })?;
+
+        // Write while reading, so a formatter that streams its output
+        // cannot fill the pipe and stall
+        let mut stdin = child.stdin.take();
+        let input = text.as_bytes().to_vec();
+        let write = async move {
+            if let Some(stdin) = stdin.as_mut() {
+                stdin.write_all(&input).await?;
+            }
+            drop(stdin);
+            Ok::<_, std::io::Error>(())
+        };
+        let run = async { tokio::try_join!(write, child.wait_with_output()) };
+        let ((), output) = tokio::time::timeout(timeout, run)
+            .await
+            .map_err(|_| FormatError::Timeout(self.name.clone()))?
+            .map_err(|source| FormatError::Io {
+                name: self.name.clone(),
+                source,
+            })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(FormatError::Failed {
+                name: self.name.clone(),
+                message: stderr.trim().to_string(),
+            });
+        }
+        String::from_utf8(output.stdout).map_err(|_| FormatError::InvalidOutput(self.name.clone()))
+    }
+}
+
+/// Errors from running an external formatter.
+#[derive(Error, Debug)]
+pub enum FormatError {
+    /// The formatter's command was not found.
+    #[error("Formatter {0} is not installed")]
+    NotInstalled(String),
+
+    /// The formatter could not be run or talked to.
+    #[error("Failed to run formatter {name}: {source}")]
+    Io {
+        /// Formatter name.
+        name: String,
+        /// Underlying error.
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The formatter exited with an error, usually a syntax error.
+    #[error("{name} failed: {message}")]
+    Failed {
+        /// Formatter name.
+        name: String,
+        /// What the formatter printed to stderr.
+        message: String,
+    },
+
+    /// The formatter did not finish in time.
+    #[error("Formatter {0} timed out")]
+    Timeout(String),
+
+    /// The formatter printed text that is not UTF-8.
+    #[error("Formatter {0} produced invalid UTF-8")]
+    InvalidOutput(String),
+}
+
+/// External formatters by language.
+#[derive(Debug, Clone)]
+pub struct FormatterRegistry {
+    formatters: Vec<FormatterConfig>,
+    timeout: Duration,
+}
+
+impl FormatterRegistry {
+    /// A registry of `formatters`, each given `timeout` to finish. When
+    /// several handle a language, the first one wins.
+    pub const fn new(formatters: Vec<FormatterConfig>, timeout: Duration) -> Self {
+        Self {
+            formatters,
+            timeout,
+        }
+    }
+
+    /// The external formatter for a language.
+    pub fn for_language(&self, language_id: &str) -> Option<&FormatterConfig> {
+        self.formatters.iter().find(|f| f.handles(language_id))
+    }
+
+    /// How long a formatter may run.
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+/// Formatting options, sent by the client or taken from the file's effective
+/// settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FormatOptions {
+    /// Columns per tab; 0 means 4.
+    pub tab_size: u32,
+    /// Indent with spaces instead of tabs.
+    pub insert_spaces: bool,
+    /// Remove whitespace at the end of lines.
+    pub trim_trailing_whitespace: bool,
+    /// End the text with a line break.
+    pub insert_final_newline: bool,
+    /// Remove blank lines at the end of the text.
+    pub trim_final_newlines: bool,
+}
+
+impl FormatOptions {
+    /// Columns per tab.
+    pub const fn tab_width(self) -> u32 {
+        if self.tab_size == 0 {
+            4
+        } else {
+            self.tab_size
+        }
+    }
+}
+
+/// Format `text` with the built-in formatter, which applies `options` and
+/// nothing else.
+///
+/// Tabs are only expanded in indentation, where doing so cannot change the
+/// meaning of the text. `line_ending` is the line break to insert.
+pub fn apply_options(text: &str, options: FormatOptions, line_ending: &str) -> String {
+    let tab_size = options.tab_width() as usize;
+    let mut formatted = String::with_capacity(text.len());
+    for (content, line_break) in lines(text) {
+        let content = if options.trim_trailing_whitespace {
+            content.trim_end()
+        } else {
+            content
+        };
+        let indent_len = content.len() - content.trim_start_matches([' ', '\t']).len();
+        let (indent, rest) = content.split_at(indent_len);
+        if options.insert_spaces && indent.contains('\t') {
+            let width = indent.chars().fold(0, |column, c| match c {
+                '\t' => (column / tab_size + 1) * tab_size,
+                _ => column + 1,
+            });
+            formatted.push_str(&" ".repeat(width));
+        } else {
+            formatted.push_str(indent);
+        }
+        formatted.push_str(rest);
+        formatted.push_str(line_break);
+    }
+
+    if options.trim_final_newlines {
+        // Keep only the line break that ends the last line
+        let content_end = formatted.trim_end_matches(['\n', '\r']).len();
+        let line_break = match &formatted[content_end..] {
+            rest if rest.starts_with("\r\n") => 2,
+            "" => 0,
+            _ => 1,
+        };
+        formatted.truncate(content_end + line_break);
+    }
+    if options.insert_final_newline && !formatted.is_empty() && !formatted.ends_with(['\n', '\r']) {
+        formatted.push_str(line_ending);
+    }
+    formatted
+}
+
+/// Lines of `text` and the line break that ends each (empty for the last
+/// line when the text does not end with one).
+fn lines(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut rest = Some(text).filter(|text| !text.is_empty());
+    std::iter::from_fn(move || {
+        let current = rest?;
+        let Some(end) = current.find(['\n', '\r']) else {
+            rest = None;
+            return Some((current, ""));
+        };
+        let next = if current[end..].starts_with("\r\n") {
+            end + 2
+        } else {
+            end + 1
+        };
+        rest = Some(&current[next..]).filter(|rest| !rest.is_empty());
+        Some((&current[..end], &current[end..next]))
+    })
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    fn options() -> FormatOptions {
+        FormatOptions {
+            tab_size: 4,
+            insert_spaces: true,
+            trim_trailing_whitespace: true,
+            insert_final_newline: true,
+            trim_final_newlines: true,
+        }
+    }
+
+    #[test]
+    fn test_builtin_formatter_applies_options() {
+        let text = "fn a() {  \r\n\tlet s = \"\t\";\r\n  \t}\r\n\r\n\r\n";
+        assert_eq!(
+            apply_options(text, options(), "\r\n"),
+            "fn a() {\r\n    let s = \"\t\";\r\n    }\r\n"
+        );
+        assert_eq!(apply_options("a\nb", options(), "\n"), "a\nb\n");
+        assert_eq!(apply_options("", options(), "\n"), "");
+
+        // Options that are off leave the text alone
+        let text = "\ta  \n\n";
+        assert_eq!(apply_options(text, FormatOptions::default(), "\n"), text);
+    }
+
+    #[test]
+    fn test_registry_picks_first_formatter() {
+        let registry = FormatterRegistry::new(
+            vec![
+                FormatterConfig::new("first", "a", ["x"], ["rust"]),
+                FormatterConfig::new("second", "b", ["y"], ["rust", "python"]),
+            ],
+            Duration::from_secs(1),
+        );
+        assert_eq!(registry.for_language("rust").unwrap().name, "first");
+        assert_eq!(registry.for_language("python").unwrap().name, "second");
+        assert!(registry.for_language("go").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_external_formatter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        let timeout = Duration::from_secs(5);
+
+        let upper = FormatterConfig::new("upper", "tr", ["a-z", "A-Z"], ["plaintext"]);
+        assert_eq!(upper.run("abc\n", &path, timeout).await.unwrap(), "ABC\n");
+
+        let failing = FormatterConfig::new(
+            "failing",
+            "sh",
+            [
+                "-c",
+                "echo \"syntax error in $0\" >&2; exit 1",
+                PATH_PLACEHOLDER,
+            ],
+            ["plaintext"],
+        );
+        let err = failing.run("", &path, timeout).await.unwrap_err();
+        assert!(
+            matches!(&err, FormatError::Failed { message, .. } if message.ends_with("a.txt")),
+            "{err}"
+        );
+
+        let missing = FormatterConfig::new("missing", "gouide-no-such-formatter", [""; 0], [""; 0]);
+        assert!(matches!(
+            missing.run("", &path, timeout).await,
+            Err(FormatError::NotInstalled(_))
+        ));
+
+        let slow = FormatterConfig::new("slow", "sleep", ["5"], ["plaintext"]);
+        assert!(matches!(
+            slow.run("", &path, Duration::from_millis(50)).await,
+            Err(FormatError::Timeout(_))
+        ));
+    }
+}
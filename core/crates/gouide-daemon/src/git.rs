@@ -0,0 +1,340 @@
+//! Git status of open workspaces.
+//!
+//! Each open workspace gets a [`StatusTracker`] on a background thread,
+//! which reads the status of its repositories when the workspace opens and
+//! then takes batches of file watcher events from a queue, refreshing only
+//! what they touch. Events arriving while a refresh runs are merged into
+//! the next one. Repositories whose git directory is outside the workspace
+//! get a watcher of their own, so commits and checkouts made there are
+//! seen too.
+//!
+//! Every refresh that changes statuses publishes the changed paths on a
+//! broadcast channel per workspace; the first one publishes every path
+//! with a status. Refreshes after a repository's index or `HEAD` moved are
+//! published even when no status changed, since staged and committed
+//! content did.
+
+use std::collections::HashMap;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Instant;
+
+use gouide_fs::{FileWatcher, FsEvent, FsEventKind, WatchOptions};
+use gouide_git::{FileStatus, StatusTracker};
+use gouide_workspace::Workspace;
+use parking_lot::{Condvar, Mutex, RwLock};
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+/// A published status refresh.
+pub type StatusChanges = Arc<StatusChange>;
+
+/// What a status refresh changed.
+#[derive(Debug, Default)]
+pub struct StatusChange {
+    /// Workspace-relative paths whose git status changed.
+    pub paths: Vec<String>,
+    /// Whether the index, `HEAD` or refs of a repository changed, which can
+    /// change the staged or committed content of any file.
+    pub revised: bool,
+}
+
+/// Git status of one workspace.
+struct WorkspaceGit {
+    root: PathBuf,
+    /// `None` until the first status is read, or if it could not be.
+    tracker: RwLock<Option<StatusTracker>>,
+    queue: Mutex<Queue>,
+    wake: Condvar,
+    /// Dropped with the workspace, which ends the subscriptions.
+    changes: broadcast::Sender<StatusChanges>,
+    /// Watchers of git directories outside the workspace, by directory.
+    external: Mutex<HashMap<PathBuf, FileWatcher>>,
+}
+
+/// Work for a workspace's status thread.
+#[derive(Default)]
+struct Queue {
+    /// Changed absolute paths not yet refreshed.
+    paths: Vec<PathBuf>,
+    closed: bool,
+}
+
+/// Git status by workspace.
+pub struct GitStatuses {
+    workspaces: RwLock<HashMap<String, Arc<WorkspaceGit>>>,
+    /// Change batches kept per workspace for slow subscribers.
+    capacity: usize,
+}
+
+impl GitStatuses {
+    /// Create an empty set, keeping `capacity` change batches per
+    /// workspace for subscribers.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            workspaces: RwLock::new(HashMap::new()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Start tracking the git status of an opened workspace on a
+    /// background thread. Does nothing if it is already tracked.
+    pub fn open_workspace(&self, workspace: &Workspace) {
+        let mut workspaces = self.workspaces.write();
+        if workspaces.contains_key(workspace.id()) {
+            return;
+        }
+        let git = Arc::new(WorkspaceGit {
+            root: workspace.root().to_path_buf(),
+            tracker: RwLock::new(None),
+            queue: Mutex::new(Queue::default()),
+            wake: Condvar::new(),
+            changes: broadcast::channel(self.capacity).0,
+            external: Mutex::new(HashMap::new()),
+        });
+        workspaces.insert(workspace.id().to_string(), git.clone());
+        drop(workspaces);
+
+        let workspace_id = workspace.id().to_string();
+        let spawned = thread::Builder::new()
+            .name("git-status".to_string())
+            .spawn(move || run(&workspace_id, &git));
+        if let Err(e) = spawned {
+            warn!(workspace_id = %workspace.id(), error = %e, "Failed to start git status");
+        }
+    }
+
+    /// Stop tracking a closed workspace.
+    pub fn close_workspace(&self, workspace_id: &str) {
+        let removed = self.workspaces.write().remove(workspace_id);
+        if let Some(git) = removed {
+            git.queue.lock().closed = true;
+            git.wake.notify_one();
+            git.external.lock().clear();
+        }
+    }
+
+    /// Queue a batch of file system events in a workspace for a status
+    /// refresh.
+    pub fn files_changed(&self, workspace_id: &str, events: &[FsEvent]) {
+        if let Some(git) = self.workspace(workspace_id) {
+            git.enqueue(events);
+        }
+    }
+
+    /// Git status of a workspace-relative path; `None` when it is clean,
+    /// not in a repository, or not read yet.
+    pub fn status(&self, workspace_id: &str, file_id: &str) -> Option<FileStatus> {
+        let git = self.workspace(workspace_id)?;
+        let tracker = git.tracker.read();
+        tracker.as_ref()?.status(file_id)
+    }
+
+    /// Whether a workspace's status has been read.
+    pub fn is_ready(&self, workspace_id: &str) -> bool {
+        self.workspace(workspace_id)
+            .is_some_and(|git| git.tracker.read().is_some())
+    }
+
+    /// Follow the paths whose status changes in a workspace. The receiver
+    /// sees its sender dropped when the workspace closes.
+    pub fn subscribe(&self, workspace_id: &str) -> Option<broadcast::Receiver<StatusChanges>> {
+        Some(self.workspace(workspace_id)?.changes.subscribe())
+    }
+
+    fn workspace(&self, workspace_id: &str) -> Option<Arc<WorkspaceGit>> {
+        self.workspaces.read().get(workspace_id).cloned()
+    }
+}
+
+impl WorkspaceGit {
+    fn enqueue(&self, events: &[FsEvent]) {
+        let mut queue = self.queue.lock();
+        for event in events {
+            queue.paths.push(event.path.clone());
+            if let FsEventKind::Renamed { from } = &event.kind {
+                queue.paths.push(from.clone());
+            }
+        }
+        drop(queue);
+        self.wake.notify_one();
+    }
+
+    /// Wait for changed paths. `None` once the workspace is closed.
+    fn next_batch(&self) -> Option<Vec<PathBuf>> {
+        let mut queue = self.queue.lock();
+        while queue.paths.is_empty() && !queue.closed {
+            self.wake.wait(&mut queue);
+        }
+        let batch = (!queue.closed).then(|| mem::take(&mut queue.paths));
+        drop(queue);
+        batch
+    }
+
+    fn publish(&self, change: StatusChange) {
+        if !change.paths.is_empty() || change.revised {
+            // No receivers is fine: nobody is watching statuses right now
+            let _ = self.changes.send(Arc::new(change));
+        }
+    }
+
+    /// Watch the git directories outside the workspace, dropping watchers
+    /// of those no longer used.
+    fn watch_external(self: &Arc<Self>, dirs: Vec<PathBuf>) {
+        let mut external = self.external.lock();
+        if self.queue.lock().closed {
+            return;
+        }
+        external.retain(|dir, _| dirs.contains(dir));
+        for dir in dirs {
+            if external.contains_key(&dir) {
+                continue;
+            }
+            let git: Weak<Self> = Arc::downgrade(self);
+            let handler = move |events: Vec<FsEvent>| {
+                if let Some(git) = git.upgrade() {
+                    git.enqueue(&events);
+                }
+            };
+            match FileWatcher::watch(&dir, WatchOptions::default(), handler) {
+                Ok(watcher) => {
+                    external.insert(dir, watcher);
+                }
+                Err(e) => warn!(dir = %dir.display(), error = %e, "Failed to watch git directory"),
+            }
+        }
+        drop(external);
+    }
+}
+
+/// Read a workspace's status, then keep it current until the workspace
+/// closes.
+fn run(workspace_id: &str, git: &Arc<WorkspaceGit>) {
+    let started = Instant::now();
+    let tracker = match StatusTracker::open(&git.root) {
+        Ok(tracker) => tracker,
+        Err(e) => {
+            warn!(workspace_id = %workspace_id, error = %e, "Cannot read git status");
+            return;
+        }
+    };
+    debug!(
+        workspace_id = %workspace_id,
+        repository = tracker.is_repository(),
+        elapsed_ms = started.elapsed().as_millis(),
+        "Git status read"
+    );
+    let change = StatusChange {
+        paths: tracker.statuses().into_keys().collect(),
+        revised: true,
+    };
+    let external = tracker.external_git_dirs();
+    *git.tracker.write() = Some(tracker);
+    git.watch_external(external);
+    git.publish(change);
+
+    while let Some(paths) = git.next_batch() {
+        let mut guard = git.tracker.write();
+        let Some(tracker) = guard.as_mut() else {
+            break;
+        };
+        let revision = tracker.revision();
+        let updated = tracker.update(&paths);
+        let revised = tracker.revision() != revision;
+        let external = tracker.external_git_dirs();
+        drop(guard);
+        match updated {
+            Ok(paths) => git.publish(StatusChange { paths, revised }),
+            Err(e) => warn!(workspace_id = %workspace_id, error = %e, "Git status refresh failed"),
+        }
+        git.watch_external(external);
+    }
+    debug!(workspace_id = %workspace_id, "Git status stopped");
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+    use std::time::Duration;
+
+    use gouide_workspace::WorkspaceManager;
+
+    use super::*;
+
+    fn git(dir: &std::path::Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[tokio::test]
+    async fn test_changes_follow_events() {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "-q"]);
+        fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+        git(dir.path(), &["add", "a.txt"]);
+        git(dir.path(), &["commit", "-q", "-m", "a"]);
+        fs::write(dir.path().join("new.txt"), "").unwrap();
+
+        let workspace = WorkspaceManager::new()
+            .open_workspace(dir.path(), None, vec![])
+            .unwrap();
+        let id = workspace.id();
+        let statuses = GitStatuses::new(8);
+        statuses.open_workspace(&workspace);
+        let mut changes = statuses.subscribe(id).unwrap();
+        let first = tokio::time::timeout(Duration::from_secs(10), changes.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.paths, ["new.txt"]);
+        assert!(statuses.is_ready(id));
+        assert_eq!(statuses.status(id, "new.txt"), Some(FileStatus::Untracked));
+        assert_eq!(statuses.status(id, "a.txt"), None);
+
+        fs::write(dir.path().join("a.txt"), "b\n").unwrap();
+        let event = FsEvent {
+            path: dir.path().join("a.txt"),
+            kind: FsEventKind::Modified,
+        };
+        statuses.files_changed(id, &[event]);
+        let next = tokio::time::timeout(Duration::from_secs(10), changes.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(next.paths, ["a.txt"]);
+        assert!(!next.revised);
+        assert_eq!(statuses.status(id, "a.txt"), Some(FileStatus::Modified));
+
+        // Staging keeps the status but moves the index
+        git(dir.path(), &["add", "a.txt"]);
+        let event = FsEvent {
+            path: dir.path().join(".git/index"),
+            kind: FsEventKind::Modified,
+        };
+        statuses.files_changed(id, &[event]);
+        let staged = tokio::time::timeout(Duration::from_secs(10), changes.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(staged.paths.is_empty());
+        assert!(staged.revised);
+
+        statuses.close_workspace(id);
+        assert!(statuses.subscribe(id).is_none());
+    }
+}
@@ -0,0 +1,493 @@
+//! Language detection.
+//!
+//! Every feature that depends on a file's language (syntax, language
+//! servers, formatters) asks the [`LanguageRegistry`], so a file is never
+//! highlighted as one language and formatted as another.
+//!
+//! Detection order, first match wins:
+//!
+//! 1. Workspace overrides (glob pattern to language ID)
+//! 2. A vim or emacs modeline in the first or last lines
+//! 3. The exact file name (`Dockerfile`, `Cargo.lock`)
+//! 4. The file extension
+//! 5. A shebang line
+//!
+//! Anything else is `plaintext`. Listings only look at paths, since reading
+//! every file for a modeline would be too slow.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use globset::{Glob, GlobMatcher};
+use gouide_workspace::{Buffer, TextRange};
+use parking_lot::RwLock;
+
+/// Language ID for files no rule matches.
+pub const PLAINTEXT: &str = "plaintext";
+
+/// Lines at each end of a buffer searched for a modeline.
+const MODELINE_LINES: u32 = 5;
+
+/// Most bytes read from each end of a buffer for detection.
+const MODELINE_BYTES: usize = 4096;
+
+/// A language the registry knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LanguageInfo {
+    /// Stable language ID (LSP-style, e.g. "rust", "typescriptreact").
+    pub id: &'static str,
+    /// Display name.
+    pub name: &'static str,
+    /// File extensions, lowercase and without the dot.
+    pub extensions: &'static [&'static str],
+    /// Exact file names.
+    pub filenames: &'static [&'static str],
+    /// Shebang interpreters.
+    pub interpreters: &'static [&'static str],
+    /// Other names used in modelines.
+    pub aliases: &'static [&'static str],
+}
+
+const fn language(
+    id: &'static str,
+    name: &'static str,
+    extensions: &'static [&'static str],
+    filenames: &'static [&'static str],
+    interpreters: &'static [&'static str],
+    aliases: &'static [&'static str],
+) -> LanguageInfo {
+    LanguageInfo {
+        id,
+        name,
+        extensions,
+        filenames,
+        interpreters,
+        aliases,
+    }
+}
+
+/// Every known language, sorted by ID.
+static LANGUAGES: &[LanguageInfo] = &[
+    language("c", "C", &["c", "h"], &[], &[], &[]),
+    language(
+        "cpp",
+        "C++",
+        &["cpp", "cc", "cxx", "hpp", "hh", "hxx"],
+        &[],
+        &[],
+        &["c++"],
+    ),
+    language("css", "CSS", &["css"], &[], &[], &[]),
+    language(
+        "dockerfile",
+        "Dockerfile",
+        &["dockerfile"],
+        &["Dockerfile", "Containerfile"],
+        &[],
+        &["docker"],
+    ),
+    language("go", "Go", &["go"], &[], &[], &["golang"]),
+    language("html", "HTML", &["html", "htm"], &[], &[], &[]),
+    language(
+        "ignore",
+        "Ignore",
+        &[],
+        &[".gitignore", ".dockerignore", ".npmignore"],
+        &[],
+        &["gitignore"],
+    ),
+    language("java", "Java", &["java"], &[], &[], &[]),
+    language(
+        "javascript",
+        "JavaScript",
+        &["js", "mjs", "cjs"],
+        &[],
+        &["node", "nodejs", "bun"],
+        &["js"],
+    ),
+    language(
+        "javascriptreact",
+        "JavaScript JSX",
+        &["jsx"],
+        &[],
+        &[],
+        &["jsx"],
+    ),
+    language("json", "JSON", &["json"], &[".prettierrc"], &[], &[]),
+    language(
+        "jsonc",
+        "JSON with Comments",
+        &["jsonc"],
+        &["tsconfig.json", "jsconfig.json", "devcontainer.json"],
+        &[],
+        &[],
+    ),
+    language(
+        "makefile",
+        "Makefile",
+        &["mk", "mak"],
+        &["Makefile", "makefile", "GNUmakefile"],
+        &["make"],
+        &["make"],
+    ),
+    language(
+        "markdown",
+        "Markdown",
+        &["md", "markdown"],
+        &[],
+        &[],
+        &["md"],
+    ),
+    language(PLAINTEXT, "Plain Text", &["txt"], &[], &[], &["text"]),
+    language(
+        "python",
+        "Python",
+        &["py", "pyi", "pyw"],
+        &[],
+        &["python", "python2", "python3", "pypy", "pypy3"],
+        &["py"],
+    ),
+    language(
+        "ruby",
+        "Ruby",
+        &["rb"],
+        &["Gemfile", "Rakefile"],
+        &["ruby"],
+        &["rb"],
+    ),
+    language("rust", "Rust", &["rs"], &[], &[], &["rs"]),
+    language(
+        "shellscript",
+        "Shell Script",
+        &["sh", "bash", "zsh", "ksh"],
+        &[".bashrc", ".bash_profile", ".zshrc", ".profile"],
+        &["sh", "bash", "zsh", "ksh", "dash"],
+        &["sh", "bash", "zsh", "shell"],
+    ),
+    language("sql", "SQL", &["sql"], &[], &[], &[]),
+    language(
+        "toml",
+        "TOML",
+        &["toml"],
+        &["Cargo.lock", "Pipfile", "poetry.lock", "uv.lock"],
+        &[],
+        &[],
+    ),
+    language(
+        "typescript",
+        "TypeScript",
+        &["ts", "mts", "cts"],
+        &[],
+        &["deno", "ts-node", "tsx"],
+        &["ts"],
+    ),
+    language("typescriptreact", "TypeScript JSX", &["tsx"], &[], &[], &[]),
+    language("xml", "XML", &["xml", "svg", "xsd"], &[], &[], &[]),
+    language("yaml", "YAML", &["yaml", "yml"], &[], &[], &[]),
+];
+
+/// An override from workspace settings.
+struct Override {
+    pattern: String,
+    matcher: GlobMatcher,
+    /// Patterns without a `/` match the file name in any directory.
+    whole_path: bool,
+    language_id: String,
+}
+
+/// A workspace's language overrides, most specific pattern first.
+#[derive(Default)]
+pub struct LanguageOverrides(Vec<Override>);
+
+impl LanguageOverrides {
+    /// Compile a map from glob pattern to language ID (`"*.tpl": "html"`,
+    /// `"ci/Jenkinsfile": "groovy"`).
+    ///
+    /// IDs are not checked against the known languages, so overrides can
+    /// name languages that only a language server knows.
+    pub fn new(overrides: &HashMap<String, String>) -> Result<Self, globset::Error> {
+        let mut parsed = overrides
+            .iter()
+            .map(|(pattern, language_id)| {
+                Ok((
+                    pattern.as_str(),
+                    Override {
+                        pattern: pattern.clone(),
+                        matcher: Glob::new(pattern)?.compile_matcher(),
+                        whole_path: pattern.contains('/'),
+                        language_id: language_id.clone(),
+                    },
+                ))
+            })
+            .collect::<Result<Vec<_>, globset::Error>>()?;
+        // Longer patterns are more specific; ties go in pattern order so the
+        // result does not depend on map order
+        parsed.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        Ok(Self(parsed.into_iter().map(|(_, o)| o).collect()))
+    }
+}
+
+/// Maps files to language IDs.
+#[derive(Default)]
+pub struct LanguageRegistry {
+    /// Overrides by workspace ID.
+    overrides: RwLock<HashMap<String, LanguageOverrides>>,
+}
+
+impl LanguageRegistry {
+    /// Create a registry with no overrides.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every known language, sorted by ID.
+    pub fn languages(&self) -> &'static [LanguageInfo] {
+        LANGUAGES
+    }
+
+    /// Replace a workspace's overrides.
+    pub fn set_overrides(&self, workspace_id: &str, overrides: LanguageOverrides) {
+        let mut all = self.overrides.write();
+        if overrides.0.is_empty() {
+            all.remove(workspace_id);
+        } else {
+            all.insert(workspace_id.to_string(), overrides);
+        }
+    }
+
+    /// Forget a closed workspace's overrides.
+    pub fn remove_workspace(&self, workspace_id: &str) {
+        self.overrides.write().remove(workspace_id);
+    }
+
+    /// Language of a file from its workspace-relative path alone.
+    pub fn detect_path(&self, workspace_id: &str, file_id: &str) -> String {
+        self.detect(workspace_id, file_id, None)
+    }
+
+    /// Describes the rules [`detect_path`](Self::detect_path) follows in a
+    /// workspace. Paths detected under rules with the same description get
+    /// the same language.
+    pub fn rules(&self, workspace_id: &str) -> String {
+        let mut rules = vec![format!("gouide {}", env!("CARGO_PKG_VERSION"))];
+        if let Some(overrides) = self.overrides.read().get(workspace_id) {
+            rules.extend(
+                overrides
+                    .0
+                    .iter()
+                    .map(|o| format!("{}={}", o.pattern, o.language_id)),
+            );
+        }
+        rules.join("\n")
+    }
+
+    /// Language of an open buffer, including its modeline and shebang.
+    pub fn detect_buffer(&self, buffer: &Buffer) -> String {
+        let lines = buffer.line_count();
+        let head = buffer.read_range(Some(TextRange::lines(0, MODELINE_LINES)), MODELINE_BYTES);
+        let tail = buffer.read_range(
+            Some(TextRange::lines(
+                lines.saturating_sub(MODELINE_LINES).max(MODELINE_LINES),
+                lines,
+            )),
+            MODELINE_BYTES,
+        );
+        let content = Content {
+            head: &head.text,
+            tail: &tail.text,
+        };
+        self.detect(buffer.workspace_id(), buffer.file_id(), Some(&content))
+    }
+
+    fn detect(&self, workspace_id: &str, file_id: &str, content: Option<&Content<'_>>) -> String {
+        let name = Path::new(file_id)
+            .file_name()
+            .map_or(file_id, |name| name.to_str().unwrap_or(file_id));
+
+        if let Some(id) = self.override_for(workspace_id, file_id, name) {
+            return id;
+        }
+        let modeline =
+            content.and_then(|c| c.head.lines().chain(c.tail.lines()).find_map(modeline));
+        let detected = modeline
+            .and_then(by_alias)
+            .or_else(|| LANGUAGES.iter().find(|l| l.filenames.contains(&name)))
+            .or_else(|| by_extension(name))
+            .or_else(|| {
+                content
+                    .and_then(|c| shebang(c.head))
+                    .and_then(by_interpreter)
+            });
+        detected
+            .map_or(PLAINTEXT, |language| language.id)
+            .to_string()
+    }
+
+    fn override_for(&self, workspace_id: &str, file_id: &str, name: &str) -> Option<String> {
+        self.overrides
+            .read()
+            .get(workspace_id)?
+            .0
+            .iter()
+            .find(|o| {
+                o.matcher
+                    .is_match(if o.whole_path { file_id } else { name })
+            })
+            .map(|o| o.language_id.clone())
+    }
+}
+
+/// Text from the ends of a buffer.
+struct Content<'a> {
+    head: &'a str,
+    tail: &'a str,
+}
+
+fn by_alias(name: &str) -> Option<&'static LanguageInfo> {
+    let name = name.to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|l| l.id == name || l.aliases.contains(&name.as_str()))
+}
+
+fn by_extension(name: &str) -> Option<&'static LanguageInfo> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    if stem.is_empty() {
+        // A dotfile like `.profile` has no extension
+        return None;
+    }
+    let extension = extension.to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|l| l.extensions.contains(&extension.as_str()))
+}
+
+/// Find a language by shebang interpreter, ignoring version suffixes
+/// (`python3.12`).
+fn by_interpreter(interpreter: &str) -> Option<&'static LanguageInfo> {
+    let find = |name: &str| LANGUAGES.iter().find(|l| l.interpreters.contains(&name));
+    find(interpreter)
+        .or_else(|| find(interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.')))
+}
+
+/// Interpreter named by a `#!` line, looking through `env`.
+fn shebang(head: &str) -> Option<&str> {
+    let line = head.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // `env -S python3 -u` and `env python3`
+        words.find(|word| !word.starts_with('-'))
+    } else {
+        Some(program)
+    }
+}
+
+/// Language named by a vim (`vim: set ft=rust:`) or emacs
+/// (`-*- mode: rust -*-`) modeline.
+fn modeline(line: &str) -> Option<&str> {
+    if let Some((_, rest)) = line.split_once("-*-") {
+        let (inner, _) = rest.split_once("-*-")?;
+        let inner = inner.trim();
+        if !inner.contains(':') {
+            return Some(inner).filter(|mode| !mode.is_empty());
+        }
+        return inner.split(';').find_map(|pair| {
+            let (key, value) = pair.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case("mode")
+                .then(|| value.trim())
+        });
+    }
+
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        let index = line.find(marker)?;
+        // The marker must start the line or follow whitespace
+        let preceded = line[..index].chars().next_back();
+        (index == 0 || preceded.is_some_and(char::is_whitespace)).then(|| index + marker.len())
+    })?;
+    line[start..]
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            matches!(key, "ft" | "filetype" | "syntax" | "syn").then_some(value)
+        })
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    fn detect(file_id: &str, head: &str) -> String {
+        let content = Content { head, tail: "" };
+        LanguageRegistry::new().detect("w1", file_id, Some(&content))
+    }
+
+    #[test]
+    fn test_detect_by_name() {
+        let registry = LanguageRegistry::new();
+        assert_eq!(registry.detect_path("w1", "src/main.rs"), "rust");
+        assert_eq!(registry.detect_path("w1", "App.TSX"), "typescriptreact");
+        assert_eq!(
+            registry.detect_path("w1", "docker/Dockerfile"),
+            "dockerfile"
+        );
+        assert_eq!(registry.detect_path("w1", "Makefile"), "makefile");
+        assert_eq!(registry.detect_path("w1", "Cargo.lock"), "toml");
+        assert_eq!(registry.detect_path("w1", "tsconfig.json"), "jsonc");
+        assert_eq!(registry.detect_path("w1", ".profile"), "shellscript");
+        assert_eq!(registry.detect_path("w1", "LICENSE"), PLAINTEXT);
+    }
+
+    #[test]
+    fn test_detect_by_content() {
+        assert_eq!(detect("bin/tool", "#!/usr/bin/env python3.12\n"), "python");
+        assert_eq!(
+            detect("bin/tool", "#!/usr/bin/env -S node --flag\n"),
+            "javascript"
+        );
+        assert_eq!(detect("bin/tool", "#!/bin/bash\n"), "shellscript");
+        // Modelines beat the extension
+        assert_eq!(detect("notes.txt", "# vim: set ft=markdown:\n"), "markdown");
+        assert_eq!(
+            detect("x.conf", "# -*- mode: ruby; coding: utf-8 -*-\n"),
+            "ruby"
+        );
+        assert_eq!(detect("x.conf", "// -*- c++ -*-\n"), "cpp");
+        // "vim:" inside a word is not a modeline
+        assert_eq!(detect("a.rs", "// novim:ft=python\n"), "rust");
+    }
+
+    #[test]
+    fn test_workspace_overrides() {
+        let registry = LanguageRegistry::new();
+        let overrides = HashMap::from([
+            ("*.tpl".to_string(), "html".to_string()),
+            ("Jenkinsfile".to_string(), "groovy".to_string()),
+            ("config/*.txt".to_string(), "yaml".to_string()),
+        ]);
+        registry.set_overrides("w1", LanguageOverrides::new(&overrides).unwrap());
+
+        assert_eq!(registry.detect_path("w1", "views/page.tpl"), "html");
+        assert_eq!(registry.detect_path("w1", "ci/Jenkinsfile"), "groovy");
+        assert_eq!(registry.detect_path("w1", "config/app.txt"), "yaml");
+        assert_eq!(registry.detect_path("w1", "notes.txt"), PLAINTEXT);
+        // Other workspaces are unaffected
+        assert_eq!(registry.detect_path("w2", "page.tpl"), PLAINTEXT);
+        assert_ne!(registry.rules("w1"), registry.rules("w2"));
+
+        registry.remove_workspace("w1");
+        assert_eq!(registry.rules("w1"), registry.rules("w2"));
+        assert_eq!(registry.detect_path("w1", "page.tpl"), PLAINTEXT);
+
+        let invalid = HashMap::from([("[".to_string(), "x".to_string())]);
+        assert!(LanguageOverrides::new(&invalid).is_err());
+    }
+}
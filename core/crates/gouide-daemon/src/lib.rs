@@ -27,10 +27,18 @@
 #![allow(unused_crate_dependencies)]
 
 pub mod config;
+pub mod diagnostics;
 pub mod discovery;
+pub mod files;
+pub mod fixers;
+pub mod formatters;
+pub mod git;
+pub mod languages;
+pub mod requests;
 pub mod server;
 pub mod services;
 pub mod session;
+pub mod settings;
 pub mod shutdown;
 pub mod transport;
 
@@ -0,0 +1,176 @@
+//! Tracking of in-flight requests for cancellation.
+//!
+//! Long-running handlers register the client's `RequestId` and race their
+//! work against [`RequestGuard::cancelled`]; `ControlService.Cancel` flips
+//! the flag. The registration is removed when the guard is dropped, so
+//! cancelling a finished request reports that nothing was cancelled.
+//!
+//! The tracker also counts the requests in flight, with or without an ID,
+//! so background work can step aside while interactive requests run.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::watch;
+
+struct Registration {
+    generation: u64,
+    cancel: watch::Sender<bool>,
+}
+
+/// In-flight requests by request ID.
+#[derive(Default)]
+pub struct RequestTracker {
+    requests: Mutex<HashMap<String, Registration>>,
+    generation: AtomicU64,
+    in_flight: AtomicUsize,
+}
+
+impl RequestTracker {
+    /// Create an empty tracker.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a request. An empty ID gives a guard that is never
+    /// cancelled. Reusing the ID of a request still in flight takes the ID
+    /// over; the earlier request can then no longer be cancelled.
+    pub fn register(self: &Arc<Self>, request_id: &str) -> RequestGuard {
+        let (cancel, cancelled) = watch::channel(false);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        if request_id.is_empty() {
+            return RequestGuard {
+                tracker: Arc::clone(self),
+                request_id: String::new(),
+                generation: 0,
+                cancelled,
+                _cancel: Some(cancel),
+            };
+        }
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        self.requests
+            .lock()
+            .insert(request_id.to_string(), Registration { generation, cancel });
+        RequestGuard {
+            tracker: Arc::clone(self),
+            request_id: request_id.to_string(),
+            generation,
+            cancelled,
+            _cancel: None,
+        }
+    }
+
+    /// Cancel an in-flight request. Returns false if there is none with
+    /// this ID.
+    pub fn cancel(&self, request_id: &str) -> bool {
+        self.requests
+            .lock()
+            .get(request_id)
+            .is_some_and(|registration| registration.cancel.send(true).is_ok())
+    }
+
+    /// Number of registered requests that have not finished.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+}
+
+/// Registration of one request, removed on drop.
+pub struct RequestGuard {
+    tracker: Arc<RequestTracker>,
+    request_id: String,
+    generation: u64,
+    cancelled: watch::Receiver<bool>,
+    // Keeps the channel of an untracked request open
+    _cancel: Option<watch::Sender<bool>>,
+}
+
+impl RequestGuard {
+    /// Whether the request has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    /// Wait until the request is cancelled. Never completes for requests
+    /// without an ID.
+    pub async fn cancelled(&mut self) {
+        if self
+            .cancelled
+            .wait_for(|cancelled| *cancelled)
+            .await
+            .is_err()
+        {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.tracker.in_flight.fetch_sub(1, Ordering::Relaxed);
+        if self.request_id.is_empty() {
+            return;
+        }
+        let mut requests = self.tracker.requests.lock();
+        if requests
+            .get(&self.request_id)
+            .is_some_and(|registration| registration.generation == self.generation)
+        {
+            requests.remove(&self.request_id);
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cancel_in_flight_request() {
+        let tracker = Arc::new(RequestTracker::new());
+        assert!(!tracker.cancel("r1"));
+
+        let mut guard = tracker.register("r1");
+        let waiter = tokio::spawn(async move {
+            guard.cancelled().await;
+        });
+        assert!(tracker.cancel("r1"));
+        tokio::time::timeout(Duration::from_secs(5), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        // The guard was dropped with its task
+        assert!(!tracker.cancel("r1"));
+
+        // A request without an ID is never cancelled
+        let mut untracked = tracker.register("");
+        assert!(!tracker.cancel(""));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), untracked.cancelled())
+                .await
+                .is_err()
+        );
+
+        // A reused ID belongs to the newest request
+        let first = tracker.register("r2");
+        let second = tracker.register("r2");
+        drop(first);
+        assert!(tracker.cancel("r2"));
+        drop(second);
+        assert!(!tracker.cancel("r2"));
+
+        // Requests count while in flight, with an ID or not
+        assert_eq!(tracker.in_flight(), 1);
+        drop(untracked);
+        assert_eq!(tracker.in_flight(), 0);
+    }
+}
@@ -3,23 +3,42 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use gouide_fs::FsEventKind;
+use gouide_protocol::buffer_service_server::BufferServiceServer;
 use gouide_protocol::control_service_server::ControlServiceServer;
+use gouide_protocol::editor_service_server::EditorServiceServer;
+use gouide_protocol::git_service_server::GitServiceServer;
 use gouide_protocol::handshake_service_server::HandshakeServiceServer;
+use gouide_protocol::language_service_server::LanguageServiceServer;
+use gouide_protocol::search_service_server::SearchServiceServer;
+use gouide_protocol::workspace_service_server::WorkspaceServiceServer;
+use gouide_syntax::SyntaxManager;
+use gouide_workspace::WorkspaceManager;
+use http_body_util::BodyExt;
 use hyper::body::Incoming;
-use hyper::service::service_fn;
 use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::service::TowerToHyperService;
 use tokio::net::UnixStream;
-use tonic::body::BoxBody;
-use tonic::server::NamedService;
+use tonic::service::Routes;
+use tonic::Status;
 use tower::ServiceExt;
-use tower_service::Service;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::config::DaemonConfig;
+use crate::diagnostics::DiagnosticsStore;
 use crate::discovery::{DaemonMetadata, LockFile};
-use crate::services::{ControlService, HandshakeService};
+use crate::files::FileIndexes;
+use crate::formatters::FormatterRegistry;
+use crate::git::GitStatuses;
+use crate::languages::LanguageRegistry;
+use crate::requests::RequestTracker;
+use crate::services::{
+    BufferService, BufferSync, ControlService, EditorService, GitService, HandshakeService,
+    LanguageService, LspBridge, SearchService, WorkspaceService,
+};
 use crate::session::SessionManager;
+use crate::settings::SettingsResolver;
 use crate::shutdown::ShutdownCoordinator;
 use crate::transport::UnixListener;
 
@@ -27,6 +46,16 @@ use crate::transport::UnixListener;
 pub struct DaemonServer {
     config: Arc<DaemonConfig>,
     session_manager: Arc<SessionManager>,
+    workspaces: Arc<WorkspaceManager>,
+    sync: Arc<BufferSync>,
+    languages: Arc<LanguageRegistry>,
+    syntax: Arc<SyntaxManager>,
+    diagnostics: Arc<DiagnosticsStore>,
+    requests: Arc<RequestTracker>,
+    formatters: Arc<FormatterRegistry>,
+    settings: Arc<SettingsResolver>,
+    files: Arc<FileIndexes>,
+    git: Arc<GitStatuses>,
     shutdown: Arc<ShutdownCoordinator>,
 }
 
@@ -34,8 +63,66 @@ impl DaemonServer {
     /// Create a new daemon server.
     pub fn new(config: DaemonConfig) -> Self {
         let config = Arc::new(config);
+        let workspaces = Arc::new(WorkspaceManager::with_limits(config.buffer_limits()));
+        let sync = Arc::new(BufferSync::new(
+            workspaces.clone(),
+            Duration::from_millis(config.watch_debounce_ms),
+            config.stream_capacity,
+        ));
+        let syntax = Arc::new(SyntaxManager::new());
+        let closed = syntax.clone();
+        sync.on_buffer_closed(move |buffer_id| closed.close(buffer_id));
+        let settings = Arc::new(SettingsResolver::new(
+            config.settings.clone(),
+            config.stream_capacity,
+        ));
+        let changed = settings.clone();
+        sync.on_files_changed(move |workspace_id, events| {
+            let paths = events.iter().flat_map(|event| {
+                let from = match &event.kind {
+                    FsEventKind::Renamed { from } => Some(from.as_path()),
+                    _ => None,
+                };
+                std::iter::once(event.path.as_path()).chain(from)
+            });
+            changed.files_changed(workspace_id, paths);
+        });
+        let requests = Arc::new(RequestTracker::new());
+        let languages = Arc::new(LanguageRegistry::new());
+        let files = FileIndexes::new(
+            requests.clone(),
+            languages.clone(),
+            config.index_cache_dir.clone(),
+        );
+        let files = Arc::new(if config.content_index {
+            files.with_content_index()
+        } else {
+            files
+        });
+        let indexed = files.clone();
+        sync.on_files_changed(move |workspace_id, events| {
+            indexed.files_changed(workspace_id, events);
+        });
+        let git = Arc::new(GitStatuses::new(config.stream_capacity));
+        let tracked = git.clone();
+        sync.on_files_changed(move |workspace_id, events| {
+            tracked.files_changed(workspace_id, events);
+        });
         Self {
             session_manager: Arc::new(SessionManager::new((*config).clone())),
+            workspaces,
+            sync,
+            languages,
+            syntax,
+            diagnostics: Arc::new(DiagnosticsStore::new(config.stream_capacity)),
+            requests,
+            formatters: Arc::new(FormatterRegistry::new(
+                config.formatters.clone(),
+                Duration::from_millis(config.format_timeout_ms),
+            )),
+            settings,
+            files,
+            git,
             config,
             shutdown: Arc::new(ShutdownCoordinator::new()),
         }
@@ -62,17 +149,81 @@ impl DaemonServer {
             daemon_id: daemon_id.clone(),
         })?;
 
+        // Language servers start as buffers of their languages open
+        let lsp = LspBridge::start(
+            &self.config,
+            self.workspaces.clone(),
+            self.languages.clone(),
+            self.diagnostics.clone(),
+        );
+        self.sync.observe(lsp.clone());
+
         // Create services
         let handshake_service = HandshakeService::new(
             self.session_manager.clone(),
+            self.workspaces.clone(),
+            self.sync.clone(),
             self.config.clone(),
             daemon_id.clone(),
         );
-        let control_service = ControlService::new();
+        let control_service = ControlService::new(self.requests.clone());
+        let workspace_service = WorkspaceService::new(
+            self.workspaces.clone(),
+            self.sync.clone(),
+            self.languages.clone(),
+            self.diagnostics.clone(),
+            self.settings.clone(),
+            self.files.clone(),
+            self.git.clone(),
+            self.config.workspace_limits.recommended_page_size,
+        );
+        let buffer_service = BufferService::new(
+            self.workspaces.clone(),
+            self.sync.clone(),
+            self.languages.clone(),
+            self.syntax.clone(),
+            self.settings.clone(),
+        );
+        let editor_service = EditorService::new(
+            self.workspaces.clone(),
+            self.sync.clone(),
+            self.languages.clone(),
+            self.syntax.clone(),
+            self.diagnostics.clone(),
+            lsp.clone(),
+            self.formatters.clone(),
+            self.settings.clone(),
+        );
+        let language_service = LanguageService::new(
+            self.workspaces.clone(),
+            self.sync.clone(),
+            self.syntax.clone(),
+            lsp.clone(),
+            self.requests.clone(),
+        );
+        let search_service = SearchService::new(
+            self.workspaces.clone(),
+            self.sync.clone(),
+            self.languages.clone(),
+            self.syntax.clone(),
+            self.requests.clone(),
+            self.files.clone(),
+            self.config.search_max_results,
+            self.config.find_files_max_results,
+        );
+        let git_service =
+            GitService::new(self.workspaces.clone(), self.sync.clone(), self.git.clone());
 
         // Build the gRPC router
-        let handshake_server = HandshakeServiceServer::new(handshake_service);
-        let control_server = ControlServiceServer::new(control_service);
+        let routes = Routes::new(HandshakeServiceServer::new(handshake_service))
+            .add_service(ControlServiceServer::new(control_service))
+            .add_service(WorkspaceServiceServer::new(workspace_service))
+            .add_service(BufferServiceServer::new(buffer_service))
+            .add_service(EditorServiceServer::new(editor_service))
+            .add_service(LanguageServiceServer::new(language_service))
+            .add_service(SearchServiceServer::new(search_service))
+            .add_service(GitServiceServer::new(git_service))
+            .prepare();
 
         info!(
             endpoint = %endpoint,
@@ -96,10 +247,9 @@ impl DaemonServer {
                 accept_result = listener.accept() => {
                     match accept_result {
                         Ok(stream) => {
-                            let handshake = handshake_server.clone();
-                            let control = control_server.clone();
+                            let routes = routes.clone();
                             tokio::spawn(async move {
-                                if let Err(e) = serve_connection(stream, handshake, control).await {
+                                if let Err(e) = serve_connection(stream, routes).await {
                                     warn!(error = %e, "Connection error");
                                 }
                             });
@@ -116,6 +266,7 @@ impl DaemonServer {
             }
         }
 
+        lsp.shutdown().await;
         info!("Daemon shutdown complete");
 
         // Lock file and listener are cleaned up automatically when dropped
@@ -127,64 +278,18 @@ impl DaemonServer {
 }
 
 /// Serve a single connection with the gRPC services.
-async fn serve_connection<H, C>(stream: UnixStream, handshake: H, control: C) -> anyhow::Result<()>
-where
-    H: Service<
-            hyper::Request<Incoming>,
-            Response = hyper::Response<BoxBody>,
-            Error = std::convert::Infallible,
-        >
-        + Clone
-        + Send
-        + 'static
-        + NamedService,
-    H::Future: Send,
-    C: Service<
-            hyper::Request<Incoming>,
-            Response = hyper::Response<BoxBody>,
-            Error = std::convert::Infallible,
-        >
-        + Clone
-        + Send
-        + 'static
-        + NamedService,
-    C::Future: Send,
-{
+///
+/// Requests are dispatched by gRPC path (`/<package>.<service>/<method>`);
+/// unknown services get an `UNIMPLEMENTED` status from the router.
+async fn serve_connection(stream: UnixStream, routes: Routes) -> anyhow::Result<()> {
     let io = TokioIo::new(stream);
 
-    // Create a service that routes to the appropriate gRPC service based on path
-    let service = service_fn(move |req: hyper::Request<Incoming>| {
-        let handshake = handshake.clone();
-        let control = control.clone();
-
-        async move {
-            let path = req.uri().path();
-
-            // Route based on service name in the path
-            // gRPC paths are like /<package>.<service>/<method>
-            if path.starts_with(&format!("/{}/", H::NAME)) {
-                handshake.oneshot(req).await
-            } else if path.starts_with(&format!("/{}/", C::NAME)) {
-                control.oneshot(req).await
-            } else {
-                // Unknown service - return 404
-                // This builder operation should never fail for a simple 404
-                #[allow(clippy::option_if_let_else)]
-                Ok(
-                    match hyper::Response::builder()
-                        .status(404)
-                        .body(tonic::body::empty_body())
-                    {
-                        Ok(resp) => resp,
-                        Err(_) => {
-                            // Fallback to a minimal 500 response if 404 build somehow fails
-                            hyper::Response::new(tonic::body::empty_body())
-                        }
-                    },
-                )
-            }
-        }
-    });
+    let service = TowerToHyperService::new(routes.map_request(|req: hyper::Request<Incoming>| {
+        req.map(|body| {
+            body.map_err(|e| Status::from_error(Box::new(e)))
+                .boxed_unsync()
+        })
+    }));
 
     hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
         .http2_only()
@@ -0,0 +1,1029 @@
+//! Buffer service implementation.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use gouide_protocol::buffer_service_server::BufferService as BufferServiceTrait;
+use gouide_protocol::{
+    close_buffer_response, get_buffer_content_response, open_buffer_response, save_buffer_response,
+    BufferChangeType, BufferId, BufferInfo, CloseBufferRequest, CloseBufferResponse,
+    CloseBufferSuccess, Error, FileId, GetBufferContentRequest, GetBufferContentResponse,
+    GetBufferContentSuccess, ListBuffersRequest, ListBuffersResponse, OpenBufferRequest,
+    OpenBufferResponse, OpenBufferSuccess, SaveBufferRequest, SaveBufferResponse,
+    SaveBufferSuccess,
+};
+use gouide_syntax::SyntaxManager;
+use gouide_workspace::{
+    diff, Buffer, CloseOutcome, SaveOptions, SaveOutcome, TextEdit, WorkspaceError,
+    WorkspaceManager, ENCODING_UTF8, ENCODING_UTF8_BOM,
+};
+use tonic::{Request, Response, Status};
+use tracing::{debug, info};
+
+use super::client_id;
+use super::convert::{
+    from_proto_line_ending, from_proto_range, to_proto_edit, to_proto_line_ending, to_proto_range,
+    to_timestamp,
+};
+use super::edits::WorkspaceEditor;
+use super::errors::{error, invalid_argument, workspace_error};
+use super::settings::SettingsLookup;
+use super::BufferSync;
+use crate::formatters::apply_options;
+use crate::languages::LanguageRegistry;
+use crate::settings::SettingsResolver;
+
+/// Error source label for this service.
+const SOURCE: &str = "buffer";
+
+/// Buffer service for managing open file buffers.
+pub struct BufferService {
+    workspaces: Arc<WorkspaceManager>,
+    sync: Arc<BufferSync>,
+    languages: Arc<LanguageRegistry>,
+    settings: SettingsLookup,
+    editor: WorkspaceEditor,
+}
+
+impl BufferService {
+    /// Create a new buffer service.
+    pub fn new(
+        workspaces: Arc<WorkspaceManager>,
+        sync: Arc<BufferSync>,
+        languages: Arc<LanguageRegistry>,
+        syntax: Arc<SyntaxManager>,
+        settings: Arc<SettingsResolver>,
+    ) -> Self {
+        Self {
+            settings: SettingsLookup::new(workspaces.clone(), languages.clone(), settings),
+            editor: WorkspaceEditor::new(workspaces.clone(), syntax, sync.clone()),
+            workspaces,
+            sync,
+            languages,
+        }
+    }
+}
+
+/// Resolve an encoding name the daemon can read and write.
+///
+/// An empty name yields `None` (keep the detected encoding).
+fn parse_encoding(name: &str) -> Result<Option<&'static str>, Error> {
+    if name.is_empty() {
+        Ok(None)
+    } else if name.eq_ignore_ascii_case(ENCODING_UTF8) || name.eq_ignore_ascii_case("utf8") {
+        Ok(Some(ENCODING_UTF8))
+    } else if name.eq_ignore_ascii_case(ENCODING_UTF8_BOM) {
+        Ok(Some(ENCODING_UTF8_BOM))
+    } else {
+        Err(error(
+            "UNSUPPORTED_ENCODING",
+            format!("Encoding {name} is not supported"),
+            SOURCE,
+        ))
+    }
+}
+
+/// Open a buffer and build the response, including the head content window.
+///
+/// With `reload`, unsaved changes are discarded for every session and the
+/// others receive the file's content as a `MODIFIED` change.
+fn open_buffer(
+    workspaces: &WorkspaceManager,
+    sync: &BufferSync,
+    languages: &LanguageRegistry,
+    req: OpenBufferRequest,
+    session: &str,
+) -> Result<OpenBufferSuccess, WorkspaceError> {
+    let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+    let file_id = req.file_id.map(|f| f.path).unwrap_or_default();
+    let buffer_id = req.buffer_id.map(|b| b.value).filter(|v| !v.is_empty());
+    let shared = workspaces.open_buffer(&workspace_id, &file_id, buffer_id, session)?;
+    if req.reload {
+        let mut buffer = shared.write();
+        if buffer.is_dirty() {
+            buffer.reload(workspaces.limits())?;
+            sync.publish_change(&buffer, BufferChangeType::Modified, session);
+        }
+        drop(buffer);
+    }
+    let buffer = shared.read();
+    sync.publish_opened(&buffer);
+    let content = buffer.read_range(None, workspaces.limits().chunk_bytes);
+
+    Ok(OpenBufferSuccess {
+        buffer_id: Some(BufferId {
+            value: buffer.id().to_string(),
+        }),
+        file_id: Some(FileId {
+            path: buffer.file_id().to_string(),
+        }),
+        content: content.text,
+        encoding: buffer.encoding().to_string(),
+        line_ending: to_proto_line_ending(buffer.line_ending()),
+        version: buffer.version(),
+        language_id: languages.detect_buffer(&buffer),
+        modified_at: buffer.disk_modified_at().map(to_timestamp),
+        read_only: buffer.read_only(),
+        checksum: String::new(),
+        content_truncated: content.truncated,
+        total_size: buffer.total_size(),
+        line_count: content.line_count,
+    })
+}
+
+/// Close a buffer for a session, saving it first if asked to.
+///
+/// The save goes through [`save_locked`] like `SaveBuffer`'s, so it follows
+/// the file's effective settings and reaches the other clients and the
+/// observers the same way.
+fn close_buffer(
+    workspaces: &WorkspaceManager,
+    sync: &BufferSync,
+    editor: &WorkspaceEditor,
+    settings: &SettingsLookup,
+    req: CloseBufferRequest,
+    session: &str,
+) -> Result<CloseOutcome, WorkspaceError> {
+    let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+    let mut saved = false;
+    if req.save_before_close && workspaces.holds(&buffer_id, session) {
+        let shared = workspaces.buffer(&buffer_id)?;
+        let mut buffer = shared.write();
+        if buffer.is_dirty() {
+            save_locked(
+                sync,
+                editor,
+                settings,
+                &mut buffer,
+                None,
+                SaveOptions::default(),
+                session,
+            )?;
+            saved = true;
+        }
+    }
+    let outcome = workspaces.close_buffer(&buffer_id, session, None, req.force)?;
+    Ok(CloseOutcome { saved, ..outcome })
+}
+
+/// Save a buffer, first replacing its content if the client sent any.
+fn save_buffer(
+    workspaces: &WorkspaceManager,
+    sync: &BufferSync,
+    editor: &WorkspaceEditor,
+    settings: &SettingsLookup,
+    req: SaveBufferRequest,
+    encoding: Option<&'static str>,
+    session: &str,
+) -> Result<SaveBufferSuccess, WorkspaceError> {
+    let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+    let shared = workspaces.buffer(&buffer_id)?;
+    let mut buffer = shared.write();
+    buffer.check_version(req.expected_version)?;
+    if !req.content.is_empty() {
+        buffer.set_text(&req.content)?;
+        sync.publish_change(&buffer, BufferChangeType::Modified, session);
+    }
+
+    let target = match req
+        .target_file_id
+        .map(|f| f.path)
+        .filter(|path| !path.is_empty() && path != buffer.file_id())
+    {
+        Some(target) => {
+            let path = workspaces
+                .workspace(buffer.workspace_id())?
+                .resolve_path(&target)?;
+            Some((target, path))
+        }
+        None => None,
+    };
+    let options = SaveOptions {
+        encoding,
+        line_ending: from_proto_line_ending(req.line_ending),
+    };
+    let (outcome, applied_edits) = save_locked(
+        sync,
+        editor,
+        settings,
+        &mut buffer,
+        target,
+        options,
+        session,
+    )?;
+
+    Ok(SaveBufferSuccess {
+        version: outcome.version,
+        modified_at: outcome.modified_at.map(to_timestamp),
+        created: outcome.created,
+        file_id: Some(FileId {
+            path: buffer.file_id().to_string(),
+        }),
+        applied_edits: applied_edits.iter().map(to_proto_edit).collect(),
+    })
+}
+
+/// Write a locked buffer, to `target` if given, and tell its clients.
+///
+/// The file's effective settings pick the line ending when `options` do
+/// not. Their trailing whitespace and final newline clean-up, and the line
+/// ending change, is applied as an edit by `session` once the file is
+/// written: the other clients receive it as such, and it is returned for
+/// the saving one. A failed write leaves the buffer as it was.
+fn save_locked(
+    sync: &BufferSync,
+    editor: &WorkspaceEditor,
+    settings: &SettingsLookup,
+    buffer: &mut Buffer,
+    target: Option<(String, PathBuf)>,
+    options: SaveOptions,
+    session: &str,
+) -> Result<(SaveOutcome, Vec<TextEdit>), WorkspaceError> {
+    let settings = match &target {
+        Some((target, _)) => settings.buffer_as(buffer, target)?,
+        None => settings.buffer(buffer)?,
+    };
+    let options = SaveOptions {
+        line_ending: options.line_ending.or(settings.end_of_line),
+        ..options
+    };
+
+    // Cleaned up ahead of the write, and rolled back if it fails
+    let savepoint = buffer.savepoint();
+    let mut applied = None;
+    if !buffer.read_only() {
+        let text = buffer.text();
+        let line_ending = options.line_ending.unwrap_or_else(|| buffer.line_ending());
+        let mut cleaned = apply_options(&text, settings.save_options(), line_ending.as_str());
+        if options.line_ending.is_some() {
+            cleaned = line_ending.normalize(&cleaned);
+        }
+        let edits = diff(&text, &cleaned);
+        if !edits.is_empty() {
+            applied = Some(buffer.apply_edits_at(0, &edits)?);
+        }
+    }
+    let renamed = target.is_some();
+    let saved = match target {
+        Some((target, path)) => buffer.save_as(target, path, options),
+        None => buffer.save(options),
+    };
+    let outcome = match saved {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            buffer.roll_back(savepoint);
+            return Err(e);
+        }
+    };
+
+    if let Some(applied) = &applied {
+        editor.share(buffer, applied, session);
+    }
+    if renamed {
+        sync.publish_change(buffer, BufferChangeType::Renamed, session);
+    }
+    sync.publish_saved(buffer);
+    Ok((
+        outcome,
+        applied.map(|applied| applied.edits).unwrap_or_default(),
+    ))
+}
+
+#[tonic::async_trait]
+impl BufferServiceTrait for BufferService {
+    async fn open_buffer(
+        &self,
+        request: Request<OpenBufferRequest>,
+    ) -> Result<Response<OpenBufferResponse>, Status> {
+        let session = client_id(&request);
+        let req = request.into_inner();
+
+        if req
+            .file_id
+            .as_ref()
+            .map_or("", |f| f.path.as_str())
+            .is_empty()
+        {
+            return Ok(Response::new(OpenBufferResponse {
+                result: Some(open_buffer_response::Result::Error(invalid_argument(
+                    "file_id is required",
+                    SOURCE,
+                ))),
+            }));
+        }
+        if let Err(e) = parse_encoding(&req.encoding) {
+            return Ok(Response::new(OpenBufferResponse {
+                result: Some(open_buffer_response::Result::Error(e)),
+            }));
+        }
+
+        // Loading (or mapping) the file is blocking I/O
+        let workspaces = self.workspaces.clone();
+        let sync = self.sync.clone();
+        let languages = self.languages.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            open_buffer(&workspaces, &sync, &languages, req, &session)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("OpenBuffer task failed: {e}")))?;
+
+        let result = match result {
+            Ok(success) => {
+                info!(
+                    buffer_id = ?success.buffer_id.as_ref().map(|b| &b.value),
+                    total_size = success.total_size,
+                    truncated = success.content_truncated,
+                    "Buffer opened"
+                );
+                open_buffer_response::Result::Success(success)
+            }
+            Err(e) => open_buffer_response::Result::Error(workspace_error(&e, SOURCE)),
+        };
+
+        Ok(Response::new(OpenBufferResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn close_buffer(
+        &self,
+        request: Request<CloseBufferRequest>,
+    ) -> Result<Response<CloseBufferResponse>, Status> {
+        let session = client_id(&request);
+        let req = request.into_inner();
+        let buffer_id = req
+            .buffer_id
+            .as_ref()
+            .map(|b| b.value.clone())
+            .unwrap_or_default();
+
+        // Saving before close writes to disk
+        let workspaces = self.workspaces.clone();
+        let sync = self.sync.clone();
+        let editor = self.editor.clone();
+        let settings = self.settings.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            close_buffer(&workspaces, &sync, &editor, &settings, req, &session)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("CloseBuffer task failed: {e}")))?;
+
+        let result = match result {
+            Ok(outcome) => {
+                if outcome.released {
+                    self.sync.forget_buffers(std::slice::from_ref(&buffer_id));
+                }
+                info!(
+                    buffer_id = %buffer_id,
+                    saved = outcome.saved,
+                    released = outcome.released,
+                    "Buffer closed"
+                );
+                close_buffer_response::Result::Success(CloseBufferSuccess {
+                    closed: outcome.closed,
+                    saved: outcome.saved,
+                    still_open: !outcome.released,
+                })
+            }
+            Err(e) => close_buffer_response::Result::Error(workspace_error(&e, SOURCE)),
+        };
+
+        Ok(Response::new(CloseBufferResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn save_buffer(
+        &self,
+        request: Request<SaveBufferRequest>,
+    ) -> Result<Response<SaveBufferResponse>, Status> {
+        let session = client_id(&request);
+        let req = request.into_inner();
+        let encoding = match parse_encoding(&req.encoding) {
+            Ok(encoding) => encoding,
+            Err(e) => {
+                return Ok(Response::new(SaveBufferResponse {
+                    result: Some(save_buffer_response::Result::Error(e)),
+                }));
+            }
+        };
+
+        let workspaces = self.workspaces.clone();
+        let sync = self.sync.clone();
+        let editor = self.editor.clone();
+        let settings = self.settings.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            save_buffer(
+                &workspaces,
+                &sync,
+                &editor,
+                &settings,
+                req,
+                encoding,
+                &session,
+            )
+        })
+        .await
+        .map_err(|e| Status::internal(format!("SaveBuffer task failed: {e}")))?;
+
+        let result = match result {
+            Ok(success) => {
+                info!(
+                    file_id = ?success.file_id.as_ref().map(|f| &f.path),
+                    version = success.version,
+                    "Buffer saved"
+                );
+                save_buffer_response::Result::Success(success)
+            }
+            Err(e) => save_buffer_response::Result::Error(workspace_error(&e, SOURCE)),
+        };
+
+        Ok(Response::new(SaveBufferResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_buffer_content(
+        &self,
+        request: Request<GetBufferContentRequest>,
+    ) -> Result<Response<GetBufferContentResponse>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let range = req.range.as_ref().map(from_proto_range);
+
+        let shared = match self.workspaces.buffer(&buffer_id) {
+            Ok(shared) => shared,
+            Err(e) => {
+                return Ok(Response::new(GetBufferContentResponse {
+                    result: Some(get_buffer_content_response::Result::Error(workspace_error(
+                        &e, SOURCE,
+                    ))),
+                }));
+            }
+        };
+
+        // Mapped buffers may fault pages in from disk
+        let chunk_bytes = self.workspaces.limits().chunk_bytes;
+        let success = tokio::task::spawn_blocking(move || {
+            let buffer = shared.read();
+            let content = buffer.read_range(range, chunk_bytes);
+            GetBufferContentSuccess {
+                content: content.text,
+                version: buffer.version(),
+                range: Some(to_proto_range(content.range)),
+                line_count: content.line_count,
+            }
+        })
+        .await
+        .map_err(|e| Status::internal(format!("GetBufferContent task failed: {e}")))?;
+
+        debug!(
+            buffer_id = %buffer_id,
+            bytes = success.content.len(),
+            "Buffer content read"
+        );
+
+        Ok(Response::new(GetBufferContentResponse {
+            result: Some(get_buffer_content_response::Result::Success(success)),
+        }))
+    }
+
+    async fn list_buffers(
+        &self,
+        request: Request<ListBuffersRequest>,
+    ) -> Result<Response<ListBuffersResponse>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+
+        // The response has no error field, so an unknown workspace is a status error
+        let mut shared = self
+            .workspaces
+            .list_buffers(&workspace_id)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        shared.sort_by_key(|buffer| buffer.read().opened_at());
+
+        let buffers = shared
+            .iter()
+            .map(|shared| {
+                let buffer = shared.read();
+                BufferInfo {
+                    buffer_id: Some(BufferId {
+                        value: buffer.id().to_string(),
+                    }),
+                    file_id: Some(FileId {
+                        path: buffer.file_id().to_string(),
+                    }),
+                    language_id: self.languages.detect_buffer(&buffer),
+                    version: buffer.version(),
+                    is_dirty: buffer.is_dirty(),
+                    read_only: buffer.read_only(),
+                    opened_at: Some(to_timestamp(buffer.opened_at())),
+                    last_modified_at: Some(to_timestamp(buffer.last_modified_at())),
+                    session_count: u32::try_from(self.workspaces.session_count(buffer.id()))
+                        .unwrap_or(u32::MAX),
+                }
+            })
+            .collect();
+
+        Ok(Response::new(ListBuffersResponse { buffers }))
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fmt::Write as _;
+    use std::fs;
+    use std::time::Duration;
+
+    use super::super::sync::BufferObserver;
+    use crate::settings::{Settings, EDITORCONFIG};
+    use gouide_protocol::{Position, Range, WorkspaceId};
+    use gouide_workspace::{BufferLimits, DiskChange, TextEdit, TextRange};
+    use tempfile::TempDir;
+
+    use super::super::CLIENT_ID_METADATA;
+    use super::*;
+
+    fn as_client<T>(message: T, client: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert(CLIENT_ID_METADATA, client.parse().unwrap());
+        request
+    }
+
+    fn setup(limits: BufferLimits) -> (TempDir, BufferService, String) {
+        let dir = TempDir::new().unwrap();
+        let mut log = String::new();
+        for i in 0..10_000 {
+            writeln!(log, "2024-01-01T00:00:00Z INFO request {i} handled").unwrap();
+        }
+        fs::write(dir.path().join("app.log"), log).unwrap();
+        fs::write(dir.path().join("small.txt"), "hello\nworld\n").unwrap();
+
+        let workspaces = Arc::new(WorkspaceManager::with_limits(limits));
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let id = workspace.id().to_string();
+        let sync = Arc::new(BufferSync::new(
+            workspaces.clone(),
+            Duration::from_millis(10),
+            8,
+        ));
+        let service = BufferService::new(
+            workspaces,
+            sync,
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(SyntaxManager::new()),
+            Arc::new(SettingsResolver::new(Settings::default(), 8)),
+        );
+        (dir, service, id)
+    }
+
+    async fn open(service: &BufferService, workspace_id: &str, path: &str) -> OpenBufferSuccess {
+        open_as(service, workspace_id, path, "client-1").await
+    }
+
+    async fn open_as(
+        service: &BufferService,
+        workspace_id: &str,
+        path: &str,
+        client: &str,
+    ) -> OpenBufferSuccess {
+        let response = service
+            .open_buffer(as_client(
+                OpenBufferRequest {
+                    request_id: None,
+                    workspace_id: Some(WorkspaceId {
+                        value: workspace_id.to_string(),
+                    }),
+                    file_id: Some(FileId {
+                        path: path.to_string(),
+                    }),
+                    buffer_id: None,
+                    encoding: String::new(),
+                    reload: false,
+                },
+                client,
+            ))
+            .await
+            .unwrap();
+        match response.into_inner().result.unwrap() {
+            open_buffer_response::Result::Success(success) => success,
+            open_buffer_response::Result::Error(e) => panic!("Open failed: {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_small_file_is_complete() {
+        let (_dir, service, workspace_id) = setup(BufferLimits::default());
+        let success = open(&service, &workspace_id, "small.txt").await;
+
+        assert_eq!(success.content, "hello\nworld\n");
+        assert!(!success.content_truncated);
+        assert!(!success.read_only);
+        assert_eq!(success.line_count, 3);
+        assert_eq!(success.total_size, 12);
+        assert_eq!(success.language_id, "plaintext");
+    }
+
+    #[tokio::test]
+    async fn test_large_file_opens_with_head_window() {
+        let limits = BufferLimits {
+            large_file_threshold: 64 * 1024,
+            chunk_bytes: 4096,
+            ..BufferLimits::default()
+        };
+        let (_dir, service, workspace_id) = setup(limits);
+        let success = open(&service, &workspace_id, "app.log").await;
+
+        assert!(success.content_truncated);
+        assert!(success.read_only);
+        assert!(success.content.len() <= 4096);
+        assert!(success.content.ends_with('\n'));
+        assert_eq!(success.line_count, 10_001);
+        assert!(success.total_size > 64 * 1024);
+
+        // Lazily fetch a window from the middle of the file
+        let response = service
+            .get_buffer_content(Request::new(GetBufferContentRequest {
+                buffer_id: success.buffer_id,
+                range: Some(Range {
+                    start: Some(Position {
+                        line: 5000,
+                        character: 0,
+                    }),
+                    end: Some(Position {
+                        line: 5002,
+                        character: 0,
+                    }),
+                }),
+            }))
+            .await
+            .unwrap();
+        let Some(get_buffer_content_response::Result::Success(window)) =
+            response.into_inner().result
+        else {
+            panic!("Expected content");
+        };
+        assert_eq!(
+            window.content,
+            "2024-01-01T00:00:00Z INFO request 5000 handled\n\
+             2024-01-01T00:00:00Z INFO request 5001 handled\n"
+        );
+        assert_eq!(window.line_count, 10_001);
+        assert_eq!(window.range.unwrap().end.unwrap().line, 5002);
+    }
+
+    #[tokio::test]
+    async fn test_get_content_unknown_buffer() {
+        let (_dir, service, _workspace_id) = setup(BufferLimits::default());
+        let response = service
+            .get_buffer_content(Request::new(GetBufferContentRequest {
+                buffer_id: Some(BufferId {
+                    value: "nope".to_string(),
+                }),
+                range: None,
+            }))
+            .await
+            .unwrap();
+        let Some(get_buffer_content_response::Result::Error(error)) = response.into_inner().result
+        else {
+            panic!("Expected error");
+        };
+        assert_eq!(error.code, "BUFFER_NOT_FOUND");
+    }
+
+    fn close_request(buffer_id: &BufferId, save_before_close: bool) -> CloseBufferRequest {
+        CloseBufferRequest {
+            request_id: None,
+            buffer_id: Some(buffer_id.clone()),
+            save_before_close,
+            force: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_close_shared_dirty_buffer() {
+        let (dir, service, workspace_id) = setup(BufferLimits::default());
+        fs::write(
+            dir.path().join(EDITORCONFIG),
+            "root = true\n[*.txt]\nend_of_line = crlf\ntrim_trailing_whitespace = true\n",
+        )
+        .unwrap();
+        let saves = Arc::new(SavedVersions::default());
+        service.sync.observe(saves.clone());
+        let first = open_as(&service, &workspace_id, "small.txt", "window-1").await;
+        let second = open_as(&service, &workspace_id, "small.txt", "window-2").await;
+        let buffer_id = first.buffer_id.unwrap();
+        assert_eq!(second.buffer_id.as_ref(), Some(&buffer_id));
+
+        service
+            .workspaces
+            .buffer(&buffer_id.value)
+            .unwrap()
+            .write()
+            .apply_edits(&[TextEdit::new(TextRange::lines(0, 1), "hi  \n")])
+            .unwrap();
+        let mut changes = service.sync.subscribe(&buffer_id.value).unwrap();
+
+        let list = service
+            .list_buffers(Request::new(ListBuffersRequest {
+                workspace_id: Some(WorkspaceId {
+                    value: workspace_id.clone(),
+                }),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(list.buffers.len(), 1);
+        assert!(list.buffers[0].is_dirty);
+        assert_eq!(list.buffers[0].version, 2);
+        assert_eq!(list.buffers[0].session_count, 2);
+
+        // One window closing leaves the buffer open for the other
+        let response = service
+            .close_buffer(as_client(close_request(&buffer_id, false), "window-1"))
+            .await
+            .unwrap();
+        let Some(close_buffer_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert!(success.closed);
+        assert!(success.still_open);
+
+        // Nothing is left for that window to close
+        let response = service
+            .close_buffer(as_client(close_request(&buffer_id, true), "window-1"))
+            .await
+            .unwrap();
+        let Some(close_buffer_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert!(!success.closed);
+        assert!(!success.saved);
+        assert!(success.still_open);
+
+        // The last window cannot silently discard the edit
+        let response = service
+            .close_buffer(as_client(close_request(&buffer_id, false), "window-2"))
+            .await
+            .unwrap();
+        let Some(close_buffer_response::Result::Error(error)) = response.into_inner().result else {
+            panic!("Expected error");
+        };
+        assert_eq!(error.code, "BUFFER_DIRTY");
+
+        let response = service
+            .close_buffer(as_client(close_request(&buffer_id, true), "window-2"))
+            .await
+            .unwrap();
+        let Some(close_buffer_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert!(success.saved);
+        assert!(!success.still_open);
+        // Saved with the file's effective settings, clean-up included, and
+        // published as SaveBuffer does
+        assert_eq!(
+            fs::read_to_string(dir.path().join("small.txt")).unwrap(),
+            "hi\r\nworld\r\n"
+        );
+        let cleanup = changes.recv().await.unwrap();
+        assert_eq!(cleanup.change_type, BufferChangeType::RemoteEdit as i32);
+        assert_eq!(cleanup.origin_client_id, "window-2");
+        assert_eq!(*saves.0.lock(), vec![cleanup.version]);
+    }
+
+    /// Records the version of every saved buffer.
+    #[derive(Default)]
+    struct SavedVersions(parking_lot::Mutex<Vec<u64>>);
+
+    impl BufferObserver for SavedVersions {
+        fn opened(&self, _buffer: &Buffer) {}
+        fn edited(&self, _buffer: &Buffer, _edits: &[TextEdit]) {}
+        fn changed(&self, _buffer: &Buffer, _change: BufferChangeType) {}
+        fn saved(&self, buffer: &Buffer) {
+            self.0.lock().push(buffer.version());
+        }
+        fn closed(&self, _buffer_id: &str) {}
+        fn workspace_closed(&self, _workspace_id: &str) {}
+    }
+
+    #[tokio::test]
+    async fn test_reload_resolves_conflict_for_every_session() {
+        let (dir, service, workspace_id) = setup(BufferLimits::default());
+        let first = open_as(&service, &workspace_id, "small.txt", "window-1").await;
+        open_as(&service, &workspace_id, "small.txt", "window-2").await;
+        let buffer_id = first.buffer_id.unwrap();
+        let buffer = service.workspaces.buffer(&buffer_id.value).unwrap();
+        buffer
+            .write()
+            .apply_edits(&[TextEdit::new(TextRange::lines(0, 1), "")])
+            .unwrap();
+        fs::write(dir.path().join("small.txt"), "theirs\n").unwrap();
+        assert_eq!(
+            buffer
+                .write()
+                .sync_with_disk(service.workspaces.limits())
+                .unwrap(),
+            DiskChange::Conflict
+        );
+        let mut changes = service.sync.subscribe(&buffer_id.value).unwrap();
+
+        // Closing with force cannot discard changes another window holds
+        let response = service
+            .close_buffer(as_client(
+                CloseBufferRequest {
+                    force: true,
+                    ..close_request(&buffer_id, false)
+                },
+                "window-1",
+            ))
+            .await
+            .unwrap();
+        let Some(close_buffer_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert!(success.still_open);
+        assert!(buffer.read().is_dirty());
+
+        let response = service
+            .open_buffer(as_client(
+                OpenBufferRequest {
+                    request_id: None,
+                    workspace_id: Some(WorkspaceId {
+                        value: workspace_id.clone(),
+                    }),
+                    file_id: Some(FileId {
+                        path: "small.txt".to_string(),
+                    }),
+                    buffer_id: None,
+                    encoding: String::new(),
+                    reload: true,
+                },
+                "window-1",
+            ))
+            .await
+            .unwrap();
+        let Some(open_buffer_response::Result::Success(reopened)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert_eq!(reopened.buffer_id.as_ref(), Some(&buffer_id));
+        assert_eq!(reopened.content, "theirs\n");
+        assert_eq!(reopened.version, 3);
+        assert!(!buffer.read().is_dirty());
+        assert_eq!(service.workspaces.session_count(&buffer_id.value), 2);
+
+        // The other window receives the file's content
+        let change = changes.recv().await.unwrap();
+        assert_eq!(change.change_type, BufferChangeType::Modified as i32);
+        assert_eq!(change.origin_client_id, "window-1");
+        assert_eq!(change.content, "theirs\n");
+        assert_eq!(change.version, 3);
+    }
+
+    #[tokio::test]
+    async fn test_save_buffer_with_content() {
+        let (dir, service, workspace_id) = setup(BufferLimits::default());
+        let success = open(&service, &workspace_id, "small.txt").await;
+
+        let response = service
+            .save_buffer(Request::new(SaveBufferRequest {
+                request_id: None,
+                buffer_id: success.buffer_id,
+                content: "saved\n".to_string(),
+                expected_version: 1,
+                target_file_id: Some(FileId {
+                    path: "copy.txt".to_string(),
+                }),
+                encoding: String::new(),
+                line_ending: 0,
+            }))
+            .await
+            .unwrap();
+        let Some(save_buffer_response::Result::Success(saved)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert!(saved.created);
+        assert_eq!(saved.version, 2);
+        assert_eq!(saved.file_id.unwrap().path, "copy.txt");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("copy.txt")).unwrap(),
+            "saved\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_applies_effective_settings() {
+        let (dir, service, workspace_id) = setup(BufferLimits::default());
+        fs::write(
+            dir.path().join(EDITORCONFIG),
+            "root = true\n[*.txt]\nend_of_line = crlf\ntrim_trailing_whitespace = true\n\
+             insert_final_newline = true\n",
+        )
+        .unwrap();
+        let success = open(&service, &workspace_id, "small.txt").await;
+        let buffer_id = success.buffer_id.unwrap();
+        let mut changes = service.sync.subscribe(&buffer_id.value).unwrap();
+
+        let response = service
+            .save_buffer(as_client(
+                SaveBufferRequest {
+                    request_id: None,
+                    buffer_id: Some(buffer_id.clone()),
+                    content: "hello  \nworld".to_string(),
+                    expected_version: 1,
+                    target_file_id: None,
+                    encoding: String::new(),
+                    line_ending: 0,
+                },
+                "client-1",
+            ))
+            .await
+            .unwrap();
+        let Some(save_buffer_response::Result::Success(saved)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert_eq!(
+            fs::read_to_string(dir.path().join("small.txt")).unwrap(),
+            "hello\r\nworld\r\n"
+        );
+
+        let buffer = service.workspaces.buffer(&buffer_id.value).unwrap();
+        assert_eq!(buffer.read().text(), "hello\r\nworld\r\n");
+        assert!(!buffer.read().is_dirty());
+
+        // The clean-up, line endings included, reaches other clients as the
+        // saver's edit of the saved version, and the saver in the response
+        assert_eq!(
+            changes.recv().await.unwrap().change_type,
+            BufferChangeType::Modified as i32
+        );
+        let cleanup = changes.recv().await.unwrap();
+        assert_eq!(cleanup.change_type, BufferChangeType::RemoteEdit as i32);
+        assert_eq!(cleanup.origin_client_id, "client-1");
+        assert_eq!(cleanup.edits, saved.applied_edits);
+        assert_eq!(cleanup.version, saved.version);
+    }
+
+    #[tokio::test]
+    async fn test_failed_save_keeps_buffer_unchanged() {
+        let (dir, service, workspace_id) = setup(BufferLimits::default());
+        fs::write(
+            dir.path().join(EDITORCONFIG),
+            "root = true\n[*.txt]\ntrim_trailing_whitespace = true\n",
+        )
+        .unwrap();
+        let success = open(&service, &workspace_id, "small.txt").await;
+        let buffer_id = success.buffer_id.unwrap();
+        let buffer = service.workspaces.buffer(&buffer_id.value).unwrap();
+        buffer
+            .write()
+            .apply_edits(&[TextEdit::new(
+                TextRange::new(
+                    gouide_workspace::Position::new(0, 5),
+                    gouide_workspace::Position::new(0, 5),
+                ),
+                "  ",
+            )])
+            .unwrap();
+        let mut changes = service.sync.subscribe(&buffer_id.value).unwrap();
+        // The temporary file the save writes cannot be created
+        fs::create_dir(dir.path().join(".small.txt.gouide-save")).unwrap();
+
+        let response = service
+            .save_buffer(Request::new(SaveBufferRequest {
+                request_id: None,
+                buffer_id: Some(buffer_id),
+                content: String::new(),
+                expected_version: 2,
+                target_file_id: None,
+                encoding: String::new(),
+                line_ending: 0,
+            }))
+            .await
+            .unwrap();
+        let Some(save_buffer_response::Result::Error(error)) = response.into_inner().result else {
+            panic!("Expected error");
+        };
+        assert_eq!(error.code, "IO_ERROR");
+
+        // The clean-up was neither kept nor sent
+        assert_eq!(buffer.read().text(), "hello  \nworld\n");
+        assert_eq!(buffer.read().version(), 2);
+        assert!(changes.try_recv().is_err());
+    }
+}
@@ -1,26 +1,23 @@
 //! Control service implementation.
 
+use std::sync::Arc;
+
 use gouide_protocol::control_service_server::ControlService as ControlServiceTrait;
 use gouide_protocol::{CancelRequest, CancelResponse};
 use tonic::{Request, Response, Status};
 use tracing::info;
 
+use crate::requests::RequestTracker;
+
 /// Control service for cross-cutting operations.
-///
-/// Currently a stub implementation. Full cancellation support
-/// requires a request tracker to be implemented.
-pub struct ControlService {}
+pub struct ControlService {
+    requests: Arc<RequestTracker>,
+}
 
 impl ControlService {
     /// Create a new control service.
-    pub fn new() -> Self {
-        Self {}
-    }
-}
-
-impl Default for ControlService {
-    fn default() -> Self {
-        Self::new()
+    pub fn new(requests: Arc<RequestTracker>) -> Self {
+        Self { requests }
     }
 }
 
@@ -35,12 +32,14 @@ impl ControlServiceTrait for ControlService {
 
         info!(request_id = %request_id, "Cancel request received");
 
-        // MVP: Stub implementation
-        // Full implementation requires a request tracker that maps
-        // request IDs to cancellation tokens
+        let cancelled = self.requests.cancel(&request_id);
         Ok(Response::new(CancelResponse {
-            cancelled: false,
-            reason: "Request not found or already completed".to_string(),
+            cancelled,
+            reason: if cancelled {
+                String::new()
+            } else {
+                "Request not found or already completed".to_string()
+            },
         }))
     }
 }
@@ -56,22 +55,35 @@ mod tests {
     use super::*;
     use gouide_protocol::RequestId;
 
+    fn cancel_request(id: &str) -> Request<CancelRequest> {
+        Request::new(CancelRequest {
+            request_id: Some(RequestId {
+                value: id.to_string(),
+            }),
+        })
+    }
+
     #[tokio::test]
-    async fn test_cancel_stub() {
-        let service = ControlService::new();
+    async fn test_cancel() {
+        let requests = Arc::new(RequestTracker::new());
+        let service = ControlService::new(requests.clone());
 
-        let response = service
-            .cancel(Request::new(CancelRequest {
-                request_id: Some(RequestId {
-                    value: "test-request-123".to_string(),
-                }),
-            }))
+        let result = service
+            .cancel(cancel_request("test-request-123"))
             .await
-            .unwrap();
-
-        let result = response.into_inner();
-        // Stub always returns false
+            .unwrap()
+            .into_inner();
+        // Nothing in flight with that ID
         assert!(!result.cancelled);
         assert!(!result.reason.is_empty());
+
+        let _guard = requests.register("test-request-123");
+        let result = service
+            .cancel(cancel_request("test-request-123"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(result.cancelled);
+        assert!(result.reason.is_empty());
     }
 }
@@ -0,0 +1,499 @@
+//! Conversions between protocol messages and workspace types.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gouide_git::{Branch, FileStatus, Revision};
+use gouide_index::{IndexState, SymbolMatch};
+use gouide_protocol::{
+    BracketPair as ProtoBracketPair, Branch as ProtoBranch, Diagnostic as ProtoDiagnostic,
+    DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag as ProtoDiagnosticTag,
+    DiffBase, DiffHunk, DiffHunkKind, DocumentSymbol, EffectiveSettings as ProtoSettings,
+    FileDiagnostics as ProtoFileDiagnostics, FileEntry, FileId, FileMatches as ProtoFileMatches,
+    FileType, FoldingRange as ProtoFoldingRange, FoldingRangeKind, FormattingOptions,
+    GitFileStatus, IndentStyle as ProtoIndentStyle, IndexingState,
+    LanguageInfo as ProtoLanguageInfo, LineEnding as ProtoLineEnding, Location,
+    Position as ProtoPosition, Range, SelectionRange, SymbolKind as ProtoSymbolKind,
+    SyntaxToken as ProtoSyntaxToken, TextEdit as ProtoTextEdit, TextMatch as ProtoTextMatch,
+    Timestamp, TokenType as ProtoTokenType, WorkspaceSymbol,
+};
+use gouide_search::{FileMatches, TextMatch};
+use gouide_syntax::{
+    BracketPair, FoldKind, FoldingRange, Language, Symbol, SymbolKind, SyntaxToken, TokenType,
+};
+use gouide_workspace::{DirEntry, EntryKind, LineEnding, LineHunk, Position, TextEdit, TextRange};
+
+use crate::diagnostics::{Diagnostic, DiagnosticTag, FileDiagnostics, Severity};
+use crate::formatters::FormatOptions;
+use crate::languages::LanguageInfo;
+use crate::settings::{EffectiveSettings, IndentStyle};
+
+/// Get the current timestamp.
+pub(crate) fn current_timestamp() -> Timestamp {
+    let now = chrono::Utc::now();
+    Timestamp {
+        seconds: now.timestamp(),
+        #[allow(clippy::cast_possible_wrap)]
+        nanos: now.timestamp_subsec_nanos() as i32,
+    }
+}
+
+/// Convert a system time to a protocol timestamp.
+pub(crate) fn to_timestamp(time: SystemTime) -> Timestamp {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Timestamp {
+        seconds: i64::try_from(since_epoch.as_secs()).unwrap_or(i64::MAX),
+        #[allow(clippy::cast_possible_wrap)]
+        nanos: since_epoch.subsec_nanos() as i32,
+    }
+}
+
+/// Convert a protocol position.
+pub(crate) fn from_proto_position(position: Option<&ProtoPosition>) -> Position {
+    position.map_or_else(Position::default, |p| Position::new(p.line, p.character))
+}
+
+/// Convert a position to the protocol type.
+pub(crate) fn to_proto_position(position: Position) -> ProtoPosition {
+    ProtoPosition {
+        line: position.line,
+        character: position.character,
+    }
+}
+
+/// Convert a protocol range.
+pub(crate) fn from_proto_range(range: &Range) -> TextRange {
+    TextRange::new(
+        from_proto_position(range.start.as_ref()),
+        from_proto_position(range.end.as_ref()),
+    )
+}
+
+/// Convert a range to the protocol type.
+pub(crate) fn to_proto_range(range: TextRange) -> Range {
+    Range {
+        start: Some(to_proto_position(range.start)),
+        end: Some(to_proto_position(range.end)),
+    }
+}
+
+/// Convert a protocol text edit.
+pub(crate) fn from_proto_edit(edit: ProtoTextEdit) -> TextEdit {
+    TextEdit::new(
+        edit.range
+            .as_ref()
+            .map(from_proto_range)
+            .unwrap_or_default(),
+        edit.new_text,
+    )
+}
+
+/// Convert a text edit to the protocol type.
+pub(crate) fn to_proto_edit(edit: &TextEdit) -> ProtoTextEdit {
+    ProtoTextEdit {
+        range: Some(to_proto_range(edit.range)),
+        new_text: edit.new_text.clone(),
+    }
+}
+
+/// Convert protocol formatting options.
+pub(crate) const fn from_proto_format_options(options: FormattingOptions) -> FormatOptions {
+    FormatOptions {
+        tab_size: options.tab_size,
+        insert_spaces: options.insert_spaces,
+        trim_trailing_whitespace: options.trim_trailing_whitespace,
+        insert_final_newline: options.insert_final_newline,
+        trim_final_newlines: options.trim_final_newlines,
+    }
+}
+
+/// Convert a file's effective settings.
+pub(crate) fn to_proto_settings(file_id: &str, settings: &EffectiveSettings) -> ProtoSettings {
+    ProtoSettings {
+        file_id: Some(FileId {
+            path: file_id.to_string(),
+        }),
+        language_id: settings.language_id.clone(),
+        indent_style: match settings.indent_style {
+            IndentStyle::Space => ProtoIndentStyle::Space as i32,
+            IndentStyle::Tab => ProtoIndentStyle::Tab as i32,
+        },
+        indent_size: settings.indent_size,
+        tab_width: settings.tab_width,
+        end_of_line: settings
+            .end_of_line
+            .map_or(ProtoLineEnding::Unspecified as i32, to_proto_line_ending),
+        trim_trailing_whitespace: settings.trim_trailing_whitespace,
+        insert_final_newline: settings.insert_final_newline,
+        trim_final_newlines: settings.trim_final_newlines,
+        max_line_length: settings.max_line_length,
+        sources: settings.sources.clone(),
+        problems: settings.problems.clone(),
+    }
+}
+
+/// Convert a protocol line ending (`None` for unspecified).
+pub(crate) fn from_proto_line_ending(value: i32) -> Option<LineEnding> {
+    match ProtoLineEnding::try_from(value).ok()? {
+        ProtoLineEnding::Lf => Some(LineEnding::Lf),
+        ProtoLineEnding::Crlf => Some(LineEnding::Crlf),
+        ProtoLineEnding::Cr => Some(LineEnding::Cr),
+        ProtoLineEnding::Unspecified => None,
+    }
+}
+
+/// Convert a line ending to the protocol enum value.
+pub(crate) fn to_proto_line_ending(line_ending: LineEnding) -> i32 {
+    match line_ending {
+        LineEnding::Lf => ProtoLineEnding::Lf as i32,
+        LineEnding::Crlf => ProtoLineEnding::Crlf as i32,
+        LineEnding::Cr => ProtoLineEnding::Cr as i32,
+    }
+}
+
+/// Convert an indexing state to the protocol enum value.
+pub(crate) fn to_proto_indexing_state(state: IndexState) -> i32 {
+    let state = match state {
+        IndexState::NotStarted => IndexingState::NotStarted,
+        IndexState::Scanning => IndexingState::Scanning,
+        IndexState::Indexing => IndexingState::Indexing,
+        IndexState::Complete => IndexingState::Complete,
+        IndexState::Paused => IndexingState::Paused,
+        IndexState::Error => IndexingState::Error,
+    };
+    state as i32
+}
+
+/// Convert a syntax token to the protocol type.
+pub(crate) fn to_proto_token(token: &SyntaxToken) -> ProtoSyntaxToken {
+    ProtoSyntaxToken {
+        line: token.line,
+        start_character: token.start_character,
+        length: token.length,
+        token_type: to_proto_token_type(token.token_type) as i32,
+        modifiers: token.modifiers,
+    }
+}
+
+const fn to_proto_token_type(token_type: TokenType) -> ProtoTokenType {
+    match token_type {
+        TokenType::Comment => ProtoTokenType::Comment,
+        TokenType::String => ProtoTokenType::String,
+        TokenType::Keyword => ProtoTokenType::Keyword,
+        TokenType::Number => ProtoTokenType::Number,
+        TokenType::Regexp => ProtoTokenType::Regexp,
+        TokenType::Operator => ProtoTokenType::Operator,
+        TokenType::Namespace => ProtoTokenType::Namespace,
+        TokenType::Type => ProtoTokenType::Type,
+        TokenType::Struct => ProtoTokenType::Struct,
+        TokenType::Class => ProtoTokenType::Class,
+        TokenType::Interface => ProtoTokenType::Interface,
+        TokenType::Enum => ProtoTokenType::Enum,
+        TokenType::EnumMember => ProtoTokenType::EnumMember,
+        TokenType::TypeParameter => ProtoTokenType::TypeParameter,
+        TokenType::Function => ProtoTokenType::Function,
+        TokenType::Method => ProtoTokenType::Method,
+        TokenType::Macro => ProtoTokenType::Macro,
+        TokenType::Variable => ProtoTokenType::Variable,
+        TokenType::Parameter => ProtoTokenType::Parameter,
+        TokenType::Property => ProtoTokenType::Property,
+        TokenType::Label => ProtoTokenType::Label,
+        TokenType::Punctuation => ProtoTokenType::Punctuation,
+    }
+}
+
+/// Convert a folding range to the protocol type.
+pub(crate) fn to_proto_folding_range(range: &FoldingRange) -> ProtoFoldingRange {
+    let kind = match range.kind {
+        FoldKind::Region => FoldingRangeKind::Region,
+        FoldKind::Comment => FoldingRangeKind::Comment,
+        FoldKind::Imports => FoldingRangeKind::Imports,
+    };
+    ProtoFoldingRange {
+        start_line: range.start_line,
+        end_line: range.end_line,
+        kind: kind as i32,
+    }
+}
+
+/// Convert an outline symbol and its children to the protocol type.
+pub(crate) fn to_proto_symbol(symbol: &Symbol) -> DocumentSymbol {
+    DocumentSymbol {
+        name: symbol.name.clone(),
+        detail: symbol.detail.clone(),
+        kind: to_proto_symbol_kind(symbol.kind) as i32,
+        range: Some(to_proto_range(symbol.range)),
+        selection_range: Some(to_proto_range(symbol.selection_range)),
+        children: symbol.children.iter().map(to_proto_symbol).collect(),
+    }
+}
+
+/// Convert a symbol found in a workspace to the protocol type.
+pub(crate) fn to_proto_workspace_symbol(found: SymbolMatch) -> WorkspaceSymbol {
+    let symbol = found.symbol;
+    WorkspaceSymbol {
+        name: symbol.name,
+        kind: to_proto_symbol_kind(symbol.kind) as i32,
+        container_name: symbol.container,
+        location: Some(Location {
+            file_id: Some(FileId { path: found.path }),
+            range: Some(to_proto_range(symbol.range)),
+            path: String::new(),
+        }),
+        selection_range: Some(to_proto_range(symbol.selection_range)),
+        score: found.score,
+        highlights: found.positions,
+    }
+}
+
+const fn to_proto_symbol_kind(kind: SymbolKind) -> ProtoSymbolKind {
+    match kind {
+        SymbolKind::Module => ProtoSymbolKind::Module,
+        SymbolKind::Namespace => ProtoSymbolKind::Namespace,
+        SymbolKind::Class => ProtoSymbolKind::Class,
+        SymbolKind::Method => ProtoSymbolKind::Method,
+        SymbolKind::Property => ProtoSymbolKind::Property,
+        SymbolKind::Field => ProtoSymbolKind::Field,
+        SymbolKind::Constructor => ProtoSymbolKind::Constructor,
+        SymbolKind::Enum => ProtoSymbolKind::Enum,
+        SymbolKind::Interface => ProtoSymbolKind::Interface,
+        SymbolKind::Function => ProtoSymbolKind::Function,
+        SymbolKind::Variable => ProtoSymbolKind::Variable,
+        SymbolKind::Constant => ProtoSymbolKind::Constant,
+        SymbolKind::String => ProtoSymbolKind::String,
+        SymbolKind::Number => ProtoSymbolKind::Number,
+        SymbolKind::Boolean => ProtoSymbolKind::Boolean,
+        SymbolKind::Array => ProtoSymbolKind::Array,
+        SymbolKind::Object => ProtoSymbolKind::Object,
+        SymbolKind::Null => ProtoSymbolKind::Null,
+        SymbolKind::EnumMember => ProtoSymbolKind::EnumMember,
+        SymbolKind::Struct => ProtoSymbolKind::Struct,
+        SymbolKind::TypeParameter => ProtoSymbolKind::TypeParameter,
+    }
+}
+
+/// Convert a bracket pair to the protocol type.
+pub(crate) fn to_proto_bracket_pair(pair: &BracketPair) -> ProtoBracketPair {
+    ProtoBracketPair {
+        open: Some(to_proto_range(pair.open)),
+        close: Some(to_proto_range(pair.close)),
+        depth: pair.depth,
+    }
+}
+
+/// Convert selection-expansion ranges to the protocol type.
+pub(crate) fn to_proto_selection(ranges: &[TextRange]) -> SelectionRange {
+    SelectionRange {
+        ranges: ranges.iter().copied().map(to_proto_range).collect(),
+    }
+}
+
+/// Convert a directory listing entry to the protocol type.
+pub(crate) fn to_proto_file_entry(entry: &DirEntry, language_id: String) -> FileEntry {
+    let file_type = match entry.kind {
+        EntryKind::File => FileType::File,
+        EntryKind::Directory => FileType::Directory,
+        EntryKind::Symlink => FileType::Symlink,
+    };
+    FileEntry {
+        file_id: Some(FileId {
+            path: entry.file_id.clone(),
+        }),
+        name: entry.name.clone(),
+        file_type: file_type as i32,
+        size: entry.size,
+        modified_at: entry.modified_at.map(to_timestamp),
+        has_children: entry.child_count > 0,
+        child_count: entry.child_count,
+        parent_path: entry.parent.clone(),
+        language_id,
+        is_ignored: false,
+        git_status: 0,
+    }
+}
+
+/// Convert a git file status to the protocol enum value, unspecified for
+/// a clean file.
+pub(crate) fn to_proto_git_status(status: Option<FileStatus>) -> i32 {
+    let status = match status {
+        None => GitFileStatus::Unspecified,
+        Some(FileStatus::Untracked) => GitFileStatus::Untracked,
+        Some(FileStatus::Modified) => GitFileStatus::Modified,
+        Some(FileStatus::Added) => GitFileStatus::Added,
+        Some(FileStatus::Deleted) => GitFileStatus::Deleted,
+        Some(FileStatus::Renamed) => GitFileStatus::Renamed,
+        Some(FileStatus::Copied) => GitFileStatus::Copied,
+        Some(FileStatus::Ignored) => GitFileStatus::Ignored,
+        Some(FileStatus::Conflict) => GitFileStatus::Conflict,
+    };
+    status as i32
+}
+
+/// Convert a diff base to the revision it names, the index when
+/// unspecified.
+pub(crate) fn from_proto_diff_base(value: i32) -> Revision {
+    match DiffBase::try_from(value) {
+        Ok(DiffBase::Head) => Revision::Head,
+        _ => Revision::Index,
+    }
+}
+
+/// Convert a revision to the protocol diff base.
+pub(crate) const fn to_proto_diff_base(revision: Revision) -> DiffBase {
+    match revision {
+        Revision::Index => DiffBase::Index,
+        Revision::Head => DiffBase::Head,
+    }
+}
+
+/// Convert a block of changed lines, base on the old side, to a protocol
+/// hunk.
+pub(crate) fn to_proto_hunk(hunk: &LineHunk) -> DiffHunk {
+    let kind = if hunk.old.is_empty() {
+        DiffHunkKind::Added
+    } else if hunk.new.is_empty() {
+        DiffHunkKind::Deleted
+    } else {
+        DiffHunkKind::Modified
+    };
+    let line = |value: usize| u32::try_from(value).unwrap_or(u32::MAX);
+    DiffHunk {
+        base_start: line(hunk.old.start),
+        base_count: line(hunk.old.len()),
+        buffer_start: line(hunk.new.start),
+        buffer_count: line(hunk.new.len()),
+        kind: kind as i32,
+    }
+}
+
+/// Convert a local branch.
+pub(crate) fn to_proto_branch(branch: Branch) -> ProtoBranch {
+    ProtoBranch {
+        name: branch.name,
+        commit_id: branch.commit,
+        current: branch.current,
+        upstream: branch.upstream.unwrap_or_default(),
+        subject: branch.subject,
+    }
+}
+
+/// Convert a registry language to the protocol type.
+pub(crate) fn to_proto_language(language: &LanguageInfo) -> ProtoLanguageInfo {
+    let strings = |values: &[&str]| values.iter().map(ToString::to_string).collect();
+    ProtoLanguageInfo {
+        language_id: language.id.to_string(),
+        name: language.name.to_string(),
+        extensions: strings(language.extensions),
+        filenames: strings(language.filenames),
+        interpreters: strings(language.interpreters),
+        has_grammar: Language::from_id(language.id).is_some(),
+    }
+}
+
+/// Convert a diagnostic to the protocol type.
+pub(crate) fn to_proto_diagnostic(diagnostic: &Diagnostic) -> ProtoDiagnostic {
+    let severity = match diagnostic.severity {
+        Severity::Error => DiagnosticSeverity::Error,
+        Severity::Warning => DiagnosticSeverity::Warning,
+        Severity::Info => DiagnosticSeverity::Info,
+        Severity::Hint => DiagnosticSeverity::Hint,
+    };
+    ProtoDiagnostic {
+        range: Some(to_proto_range(diagnostic.range)),
+        severity: severity as i32,
+        code: diagnostic.code.clone(),
+        source: diagnostic.source.clone(),
+        message: diagnostic.message.clone(),
+        related_information: diagnostic
+            .related
+            .iter()
+            .map(|related| DiagnosticRelatedInformation {
+                file_id: Some(FileId {
+                    path: related.file_id.clone(),
+                }),
+                range: Some(to_proto_range(related.range)),
+                message: related.message.clone(),
+            })
+            .collect(),
+        tags: diagnostic
+            .tags
+            .iter()
+            .map(|tag| match tag {
+                DiagnosticTag::Unnecessary => ProtoDiagnosticTag::Unnecessary as i32,
+                DiagnosticTag::Deprecated => ProtoDiagnosticTag::Deprecated as i32,
+            })
+            .collect(),
+        has_quick_fixes: diagnostic.has_quick_fixes,
+    }
+}
+
+/// Convert a file's merged diagnostics to the protocol type.
+pub(crate) fn to_proto_file_diagnostics(file: &FileDiagnostics) -> ProtoFileDiagnostics {
+    ProtoFileDiagnostics {
+        file_id: Some(FileId {
+            path: file.file_id.clone(),
+        }),
+        diagnostics: file.diagnostics.iter().map(to_proto_diagnostic).collect(),
+        version: file.version,
+    }
+}
+
+/// Convert a file's search matches to the protocol type.
+pub(crate) fn to_proto_file_matches(file: FileMatches) -> ProtoFileMatches {
+    ProtoFileMatches {
+        file_id: Some(FileId { path: file.path }),
+        matches: file.matches.into_iter().map(to_proto_text_match).collect(),
+    }
+}
+
+/// Convert a search match to the protocol type.
+pub(crate) fn to_proto_text_match(found: TextMatch) -> ProtoTextMatch {
+    let position =
+        |p: gouide_search::Position| to_proto_position(Position::new(p.line, p.character));
+    ProtoTextMatch {
+        range: Some(Range {
+            start: Some(position(found.start)),
+            end: Some(position(found.end)),
+        }),
+        preview: found.preview.text,
+        preview_start: found.preview.start,
+        preview_end: found.preview.end,
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_to_timestamp() {
+        let time = UNIX_EPOCH + Duration::new(1000, 500);
+        let ts = to_timestamp(time);
+        assert_eq!(ts.seconds, 1000);
+        assert_eq!(ts.nanos, 500);
+    }
+
+    #[test]
+    fn test_range_round_trip() {
+        let range = TextRange::new(Position::new(1, 2), Position::new(3, 4));
+        assert_eq!(from_proto_range(&to_proto_range(range)), range);
+    }
+
+    #[test]
+    fn test_line_ending_round_trip() {
+        for line_ending in [LineEnding::Lf, LineEnding::Crlf, LineEnding::Cr] {
+            assert_eq!(
+                from_proto_line_ending(to_proto_line_ending(line_ending)),
+                Some(line_ending)
+            );
+        }
+        assert_eq!(from_proto_line_ending(0), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Diagnostics queries and streams for the editor service.
+//!
+//! A `WatchDiagnostics` stream is a task that wakes on the store's change
+//! notifications and sends the full merged set of each changed file in its
+//! scope. Buffer streams look up the buffer's file on every notification,
+//! so they follow a save-as, and end when the buffer is closed.
+
+use std::sync::Arc;
+
+use gouide_protocol::{
+    DeltaType, FileId, StreamMeta, WatchBufferChangesResponse, WatchDiagnosticsResponse,
+};
+use gouide_workspace::{WorkspaceError, WorkspaceManager};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+
+use super::convert::to_proto_diagnostic;
+use super::stream::StreamSender;
+use super::ResponseStream;
+use crate::diagnostics::{DiagnosticsChanged, DiagnosticsStore, FileDiagnostics};
+
+/// What a diagnostics stream covers.
+pub(crate) enum Scope {
+    /// The file of one open buffer.
+    Buffer {
+        /// Buffer ID.
+        buffer_id: String,
+        /// The buffer's change stream, which closes with the buffer.
+        changes: broadcast::Receiver<WatchBufferChangesResponse>,
+    },
+    /// Every file in a workspace.
+    Workspace(String),
+}
+
+/// Workspace and file ID of an open buffer.
+pub(crate) fn buffer_file(
+    workspaces: &WorkspaceManager,
+    buffer_id: &str,
+) -> Result<(String, String), WorkspaceError> {
+    let shared = workspaces.buffer(buffer_id)?;
+    let buffer = shared.read();
+    Ok((
+        buffer.workspace_id().to_string(),
+        buffer.file_id().to_string(),
+    ))
+}
+
+fn message(file: &FileDiagnostics, delta_type: DeltaType) -> WatchDiagnosticsResponse {
+    WatchDiagnosticsResponse {
+        meta: Some(StreamMeta {
+            delta_type: delta_type as i32,
+            ..StreamMeta::default()
+        }),
+        file_id: Some(FileId {
+            path: file.file_id.clone(),
+        }),
+        diagnostics: file.diagnostics.iter().map(to_proto_diagnostic).collect(),
+        version: file.version,
+    }
+}
+
+/// Start a diagnostics stream.
+///
+/// Subscribes before taking the snapshot, so no update is missed.
+pub(crate) fn watch_diagnostics(
+    workspaces: Arc<WorkspaceManager>,
+    store: Arc<DiagnosticsStore>,
+    scope: Scope,
+) -> ResponseStream<WatchDiagnosticsResponse> {
+    let (sender, stream) = StreamSender::channel();
+    let updates = store.subscribe();
+    tokio::spawn(run(sender, workspaces, store, scope, updates));
+    stream
+}
+
+async fn run(
+    mut sender: StreamSender<WatchDiagnosticsResponse>,
+    workspaces: Arc<WorkspaceManager>,
+    store: Arc<DiagnosticsStore>,
+    scope: Scope,
+    mut updates: broadcast::Receiver<DiagnosticsChanged>,
+) {
+    let stream_id = sender.stream_id().to_string();
+    debug!(stream_id = %stream_id, "Watching diagnostics");
+
+    let (buffer_id, mut changes, workspace_id) = match scope {
+        Scope::Buffer { buffer_id, changes } => (Some(buffer_id), Some(changes), None),
+        Scope::Workspace(workspace_id) => (None, None, Some(workspace_id)),
+    };
+    // The file a notification must be for to be sent
+    let wanted = |change: &DiagnosticsChanged| match (&buffer_id, &workspace_id) {
+        (Some(buffer_id), _) => {
+            buffer_file(&workspaces, buffer_id).is_ok_and(|(workspace, file)| {
+                workspace == change.workspace_id && file == change.file_id
+            })
+        }
+        (None, Some(workspace_id)) => *workspace_id == change.workspace_id,
+        (None, None) => false,
+    };
+
+    let snapshot = match (&buffer_id, &workspace_id) {
+        (Some(buffer_id), _) => match buffer_file(&workspaces, buffer_id) {
+            Ok((workspace, file)) => vec![store.file(&workspace, &file)],
+            Err(_) => Vec::new(),
+        },
+        (None, Some(workspace_id)) => store.workspace(workspace_id),
+        (None, None) => Vec::new(),
+    };
+    for file in &snapshot {
+        if !sender.send(message(file, DeltaType::Snapshot)).await {
+            return;
+        }
+    }
+
+    loop {
+        let buffer_closed = async {
+            match changes.as_mut() {
+                Some(changes) => while !matches!(changes.recv().await, Err(RecvError::Closed)) {},
+                None => std::future::pending().await,
+            }
+        };
+        let change = tokio::select! {
+            update = updates.recv() => update,
+            () = buffer_closed => break,
+            () = sender.closed() => break,
+        };
+        match change {
+            Ok(change) if wanted(&change) => {
+                let file = store.file(&change.workspace_id, &change.file_id);
+                if !sender.send(message(&file, DeltaType::Update)).await {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(skipped)) => {
+                debug!(stream_id = %stream_id, skipped, "Diagnostics subscriber lagged");
+                sender.reset().await;
+                break;
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    debug!(stream_id = %stream_id, "Diagnostics stream ended");
+}
@@ -0,0 +1,1351 @@
+//! Editor service implementation.
+
+use std::sync::Arc;
+
+use gouide_protocol::editor_service_server::EditorService as EditorServiceTrait;
+use gouide_protocol::{
+    apply_edits_response, format_buffer_response, format_selection_response,
+    get_bracket_pairs_response, get_diagnostics_response, get_document_symbols_response,
+    get_folding_ranges_response, get_selection_ranges_response, get_syntax_tokens_response,
+    update_visible_range_response, watch_diagnostics_request, ApplyEditsRequest,
+    ApplyEditsResponse, BufferChangeType, Error, FormatBufferRequest, FormatBufferResponse,
+    FormatSelectionRequest, FormatSelectionResponse, GetBracketPairsRequest,
+    GetBracketPairsResponse, GetBracketPairsSuccess, GetDiagnosticsRequest, GetDiagnosticsResponse,
+    GetDiagnosticsSuccess, GetDocumentSymbolsRequest, GetDocumentSymbolsResponse,
+    GetDocumentSymbolsSuccess, GetFoldingRangesRequest, GetFoldingRangesResponse,
+    GetFoldingRangesSuccess, GetSelectionRangesRequest, GetSelectionRangesResponse,
+    GetSelectionRangesSuccess, GetSyntaxTokensRequest, GetSyntaxTokensResponse,
+    UpdateVisibleRangeRequest, UpdateVisibleRangeResponse, UpdateVisibleRangeSuccess,
+    WatchBufferChangesRequest, WatchBufferChangesResponse, WatchDiagnosticsRequest,
+    WatchDiagnosticsResponse, WatchSyntaxTokensRequest, WatchSyntaxTokensResponse,
+};
+use gouide_syntax::{Language, SyntaxError, SyntaxManager};
+use gouide_workspace::{Position, TextEdit, WorkspaceError, WorkspaceManager};
+use tonic::{Request, Response, Status};
+use tracing::debug;
+
+use super::client_id;
+use super::convert::{
+    from_proto_edit, from_proto_format_options, from_proto_position, from_proto_range,
+    to_proto_bracket_pair, to_proto_diagnostic, to_proto_file_diagnostics, to_proto_folding_range,
+    to_proto_selection, to_proto_symbol,
+};
+use super::diagnostics::{buffer_file, watch_diagnostics, Scope};
+use super::edits::WorkspaceEditor;
+use super::errors::{error, invalid_argument, workspace_error};
+use super::format::BufferFormatter;
+use super::settings::SettingsLookup;
+use super::stream::forward;
+use super::syntax::{structure, syntax_tokens, watch_tokens, Snapshot, TokenSource, TokenStreams};
+use super::{BufferSync, LspBridge, ResponseStream};
+use crate::diagnostics::DiagnosticsStore;
+use crate::formatters::FormatterRegistry;
+use crate::languages::LanguageRegistry;
+use crate::settings::SettingsResolver;
+
+/// Error source label for this service.
+const SOURCE: &str = "editor";
+
+/// Editor service for text editing and enrichment.
+pub struct EditorService {
+    workspaces: Arc<WorkspaceManager>,
+    sync: Arc<BufferSync>,
+    languages: Arc<LanguageRegistry>,
+    syntax: Arc<SyntaxManager>,
+    diagnostics: Arc<DiagnosticsStore>,
+    streams: Arc<TokenStreams>,
+    editor: WorkspaceEditor,
+    formatter: BufferFormatter,
+}
+
+impl EditorService {
+    /// Create a new editor service.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        workspaces: Arc<WorkspaceManager>,
+        sync: Arc<BufferSync>,
+        languages: Arc<LanguageRegistry>,
+        syntax: Arc<SyntaxManager>,
+        diagnostics: Arc<DiagnosticsStore>,
+        lsp: Arc<LspBridge>,
+        formatters: Arc<FormatterRegistry>,
+        settings: Arc<SettingsResolver>,
+    ) -> Self {
+        let lookup = SettingsLookup::new(workspaces.clone(), languages.clone(), settings);
+        Self {
+            editor: WorkspaceEditor::new(workspaces.clone(), syntax.clone(), sync.clone()),
+            formatter: BufferFormatter::new(
+                workspaces.clone(),
+                languages.clone(),
+                lsp,
+                formatters,
+                lookup,
+            ),
+            workspaces,
+            sync,
+            languages,
+            syntax,
+            diagnostics,
+            streams: Arc::new(TokenStreams::default()),
+        }
+    }
+
+    /// Run a structure query on a buffer's syntax tree on a blocking thread.
+    async fn structure<T: Default + Send + 'static>(
+        &self,
+        task: &str,
+        buffer_id: String,
+        query: impl FnOnce(&SyntaxManager, &str, Language, &Snapshot) -> Result<T, SyntaxError>
+            + Send
+            + 'static,
+    ) -> Result<Result<(T, u64), Error>, Status> {
+        let workspaces = self.workspaces.clone();
+        let languages = self.languages.clone();
+        let syntax = self.syntax.clone();
+        tokio::task::spawn_blocking(move || {
+            structure(&workspaces, &languages, &syntax, &buffer_id, SOURCE, query)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("{task} task failed: {e}")))
+    }
+}
+
+#[tonic::async_trait]
+impl EditorServiceTrait for EditorService {
+    type WatchSyntaxTokensStream = ResponseStream<WatchSyntaxTokensResponse>;
+    type WatchDiagnosticsStream = ResponseStream<WatchDiagnosticsResponse>;
+    type WatchBufferChangesStream = ResponseStream<WatchBufferChangesResponse>;
+
+    async fn apply_edits(
+        &self,
+        request: Request<ApplyEditsRequest>,
+    ) -> Result<Response<ApplyEditsResponse>, Status> {
+        let session = client_id(&request);
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let edits: Vec<TextEdit> = req.edits.into_iter().map(from_proto_edit).collect();
+
+        let edited = self.editor.edit_buffer(
+            &buffer_id,
+            &edits,
+            req.expected_version,
+            req.create_undo_checkpoint,
+            &session,
+        );
+        let result = match edited {
+            Ok(success) => {
+                debug!(
+                    buffer_id = %buffer_id,
+                    edits = edits.len(),
+                    version = success.version,
+                    rebased = success.rebased,
+                    "Edits applied"
+                );
+                apply_edits_response::Result::Success(success)
+            }
+            Err(e) => apply_edits_response::Result::Error(workspace_error(&e, SOURCE)),
+        };
+
+        Ok(Response::new(ApplyEditsResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_syntax_tokens(
+        &self,
+        request: Request<GetSyntaxTokensRequest>,
+    ) -> Result<Response<GetSyntaxTokensResponse>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let Some(range) = req.range.as_ref().map(from_proto_range) else {
+            return Ok(Response::new(GetSyntaxTokensResponse {
+                result: Some(get_syntax_tokens_response::Result::Error(invalid_argument(
+                    "range is required",
+                    SOURCE,
+                ))),
+            }));
+        };
+
+        let workspaces = self.workspaces.clone();
+        let languages = self.languages.clone();
+        let syntax = self.syntax.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            syntax_tokens(&workspaces, &languages, &syntax, &buffer_id, range, SOURCE)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("GetSyntaxTokens task failed: {e}")))?;
+
+        let result = match result {
+            Ok(success) => get_syntax_tokens_response::Result::Success(success),
+            Err(error) => get_syntax_tokens_response::Result::Error(error),
+        };
+        Ok(Response::new(GetSyntaxTokensResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn watch_syntax_tokens(
+        &self,
+        request: Request<WatchSyntaxTokensRequest>,
+    ) -> Result<Response<Self::WatchSyntaxTokensStream>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let visible = req
+            .visible_range
+            .as_ref()
+            .map(from_proto_range)
+            .ok_or_else(|| Status::invalid_argument("visible_range is required"))?;
+
+        // Subscribe before the first snapshot so no edit is missed
+        let changes = self.sync.subscribe(&buffer_id).map_err(|e| match e {
+            WorkspaceError::BufferNotFound(_) => Status::not_found(e.to_string()),
+            _ => Status::internal(e.to_string()),
+        })?;
+        let source = TokenSource {
+            workspaces: self.workspaces.clone(),
+            languages: self.languages.clone(),
+            syntax: self.syntax.clone(),
+            streams: self.streams.clone(),
+            buffer_id,
+        };
+        Ok(Response::new(watch_tokens(source, changes, visible)))
+    }
+
+    async fn update_visible_range(
+        &self,
+        request: Request<UpdateVisibleRangeRequest>,
+    ) -> Result<Response<UpdateVisibleRangeResponse>, Status> {
+        let req = request.into_inner();
+        let result = match req.visible_range.as_ref().map(from_proto_range) {
+            None => update_visible_range_response::Result::Error(invalid_argument(
+                "visible_range is required",
+                SOURCE,
+            )),
+            Some(range) if self.streams.update(&req.stream_id, range) => {
+                update_visible_range_response::Result::Success(UpdateVisibleRangeSuccess {
+                    updated: true,
+                })
+            }
+            Some(_) => update_visible_range_response::Result::Error(error(
+                "STREAM_NOT_FOUND",
+                format!("No syntax token stream {}", req.stream_id),
+                SOURCE,
+            )),
+        };
+        Ok(Response::new(UpdateVisibleRangeResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_folding_ranges(
+        &self,
+        request: Request<GetFoldingRangesRequest>,
+    ) -> Result<Response<GetFoldingRangesResponse>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+
+        let result = self
+            .structure(
+                "GetFoldingRanges",
+                buffer_id,
+                |syntax, id, language, snapshot| {
+                    syntax.folding_ranges(id, language, snapshot.version, &snapshot.text)
+                },
+            )
+            .await?;
+        let result = match result {
+            Ok((ranges, version)) => {
+                get_folding_ranges_response::Result::Success(GetFoldingRangesSuccess {
+                    ranges: ranges.iter().map(to_proto_folding_range).collect(),
+                    version,
+                })
+            }
+            Err(error) => get_folding_ranges_response::Result::Error(error),
+        };
+        Ok(Response::new(GetFoldingRangesResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_document_symbols(
+        &self,
+        request: Request<GetDocumentSymbolsRequest>,
+    ) -> Result<Response<GetDocumentSymbolsResponse>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+
+        let result = self
+            .structure(
+                "GetDocumentSymbols",
+                buffer_id,
+                |syntax, id, language, snapshot| {
+                    syntax.outline(id, language, snapshot.version, &snapshot.text)
+                },
+            )
+            .await?;
+        let result = match result {
+            Ok((symbols, version)) => {
+                get_document_symbols_response::Result::Success(GetDocumentSymbolsSuccess {
+                    symbols: symbols.iter().map(to_proto_symbol).collect(),
+                    version,
+                })
+            }
+            Err(error) => get_document_symbols_response::Result::Error(error),
+        };
+        Ok(Response::new(GetDocumentSymbolsResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_bracket_pairs(
+        &self,
+        request: Request<GetBracketPairsRequest>,
+    ) -> Result<Response<GetBracketPairsResponse>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let Some(range) = req.range.as_ref().map(from_proto_range) else {
+            return Ok(Response::new(GetBracketPairsResponse {
+                result: Some(get_bracket_pairs_response::Result::Error(invalid_argument(
+                    "range is required",
+                    SOURCE,
+                ))),
+            }));
+        };
+
+        let result = self
+            .structure(
+                "GetBracketPairs",
+                buffer_id,
+                move |syntax, id, language, snapshot| {
+                    syntax.bracket_pairs(id, language, snapshot.version, &snapshot.text, range)
+                },
+            )
+            .await?;
+        let result = match result {
+            Ok((pairs, version)) => {
+                get_bracket_pairs_response::Result::Success(GetBracketPairsSuccess {
+                    pairs: pairs.iter().map(to_proto_bracket_pair).collect(),
+                    version,
+                })
+            }
+            Err(error) => get_bracket_pairs_response::Result::Error(error),
+        };
+        Ok(Response::new(GetBracketPairsResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_selection_ranges(
+        &self,
+        request: Request<GetSelectionRangesRequest>,
+    ) -> Result<Response<GetSelectionRangesResponse>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let positions: Vec<Position> = req
+            .positions
+            .iter()
+            .map(|p| from_proto_position(Some(p)))
+            .collect();
+
+        let result = self
+            .structure(
+                "GetSelectionRanges",
+                buffer_id,
+                move |syntax, id, language, snapshot| {
+                    syntax.selection_ranges(
+                        id,
+                        language,
+                        snapshot.version,
+                        &snapshot.text,
+                        &positions,
+                    )
+                },
+            )
+            .await?;
+        let result = match result {
+            Ok((selections, version)) => {
+                get_selection_ranges_response::Result::Success(GetSelectionRangesSuccess {
+                    selections: selections.iter().map(|s| to_proto_selection(s)).collect(),
+                    version,
+                })
+            }
+            Err(error) => get_selection_ranges_response::Result::Error(error),
+        };
+        Ok(Response::new(GetSelectionRangesResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_diagnostics(
+        &self,
+        request: Request<GetDiagnosticsRequest>,
+    ) -> Result<Response<GetDiagnosticsResponse>, Status> {
+        let req = request.into_inner();
+        let result = match (req.buffer_id, req.workspace_id) {
+            (Some(buffer_id), _) => match buffer_file(&self.workspaces, &buffer_id.value) {
+                Ok((workspace_id, file_id)) => {
+                    let file = self.diagnostics.file(&workspace_id, &file_id);
+                    get_diagnostics_response::Result::Success(GetDiagnosticsSuccess {
+                        diagnostics: file.diagnostics.iter().map(to_proto_diagnostic).collect(),
+                        version: file.version,
+                        files: Vec::new(),
+                    })
+                }
+                Err(e) => get_diagnostics_response::Result::Error(workspace_error(&e, SOURCE)),
+            },
+            (None, Some(workspace_id)) => match self.workspaces.workspace(&workspace_id.value) {
+                Ok(_) => get_diagnostics_response::Result::Success(GetDiagnosticsSuccess {
+                    diagnostics: Vec::new(),
+                    version: 0,
+                    files: self
+                        .diagnostics
+                        .workspace(&workspace_id.value)
+                        .iter()
+                        .map(to_proto_file_diagnostics)
+                        .collect(),
+                }),
+                Err(e) => get_diagnostics_response::Result::Error(workspace_error(&e, SOURCE)),
+            },
+            (None, None) => get_diagnostics_response::Result::Error(invalid_argument(
+                "buffer_id or workspace_id is required",
+                SOURCE,
+            )),
+        };
+
+        Ok(Response::new(GetDiagnosticsResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn watch_diagnostics(
+        &self,
+        request: Request<WatchDiagnosticsRequest>,
+    ) -> Result<Response<Self::WatchDiagnosticsStream>, Status> {
+        let scope = match request.into_inner().scope {
+            Some(watch_diagnostics_request::Scope::BufferId(buffer_id)) => {
+                let changes = self.sync.subscribe(&buffer_id.value).map_err(|e| match e {
+                    WorkspaceError::BufferNotFound(_) => Status::not_found(e.to_string()),
+                    _ => Status::internal(e.to_string()),
+                })?;
+                Scope::Buffer {
+                    buffer_id: buffer_id.value,
+                    changes,
+                }
+            }
+            Some(watch_diagnostics_request::Scope::WorkspaceId(workspace_id)) => {
+                self.workspaces
+                    .workspace(&workspace_id.value)
+                    .map_err(|e| Status::not_found(e.to_string()))?;
+                Scope::Workspace(workspace_id.value)
+            }
+            None => return Err(Status::invalid_argument("scope is required")),
+        };
+
+        Ok(Response::new(watch_diagnostics(
+            self.workspaces.clone(),
+            self.diagnostics.clone(),
+            scope,
+        )))
+    }
+
+    async fn watch_buffer_changes(
+        &self,
+        request: Request<WatchBufferChangesRequest>,
+    ) -> Result<Response<Self::WatchBufferChangesStream>, Status> {
+        let session = client_id(&request);
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+
+        // Streams have no error envelope, so failures map to a status
+        let rx = self.sync.subscribe(&buffer_id).map_err(|e| match e {
+            WorkspaceError::BufferNotFound(_) => Status::not_found(e.to_string()),
+            _ => Status::internal(e.to_string()),
+        })?;
+        debug!(buffer_id = %buffer_id, session = %session, "Watching buffer changes");
+
+        // A client's own edits are not echoed back to it
+        let own_edit = move |message: &WatchBufferChangesResponse| {
+            message.change_type == BufferChangeType::RemoteEdit as i32
+                && !session.is_empty()
+                && message.origin_client_id == session
+        };
+        Ok(Response::new(forward(rx, own_edit)))
+    }
+
+    async fn format_buffer(
+        &self,
+        request: Request<FormatBufferRequest>,
+    ) -> Result<Response<FormatBufferResponse>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let options = req.options.map(from_proto_format_options);
+
+        let result = match self
+            .formatter
+            .format(&buffer_id, None, options, SOURCE)
+            .await
+        {
+            Ok(success) => {
+                debug!(
+                    buffer_id = %buffer_id,
+                    formatter = %success.formatter,
+                    edits = success.edits.len(),
+                    "Buffer formatted"
+                );
+                format_buffer_response::Result::Success(success)
+            }
+            Err(e) => format_buffer_response::Result::Error(e),
+        };
+        Ok(Response::new(FormatBufferResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn format_selection(
+        &self,
+        request: Request<FormatSelectionRequest>,
+    ) -> Result<Response<FormatSelectionResponse>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let options = req.options.map(from_proto_format_options);
+
+        let result = async {
+            let range = req
+                .range
+                .as_ref()
+                .map(from_proto_range)
+                .ok_or_else(|| invalid_argument("range is required", SOURCE))?;
+            self.formatter
+                .format(&buffer_id, Some(range), options, SOURCE)
+                .await
+        }
+        .await;
+        let result = match result {
+            Ok(success) => format_selection_response::Result::Success(success),
+            Err(e) => format_selection_response::Result::Error(e),
+        };
+        Ok(Response::new(FormatSelectionResponse {
+            result: Some(result),
+        }))
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use gouide_protocol::{
+        BufferId, DeltaType, FormatSuccess, FormattingOptions, Position, Range,
+        TextEdit as ProtoTextEdit, TokenType as ProtoTokenType, WorkspaceId,
+    };
+    use gouide_workspace::{apply_edits, TextRange};
+    use tempfile::TempDir;
+    use tokio_stream::StreamExt;
+
+    use super::super::CLIENT_ID_METADATA;
+    use super::*;
+    use crate::config::DaemonConfig;
+    use crate::diagnostics::{Diagnostic, Severity};
+    use crate::formatters::FormatterConfig;
+    use crate::settings::{Settings, EDITORCONFIG};
+
+    fn as_client<T>(message: T, client: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert(CLIENT_ID_METADATA, client.parse().unwrap());
+        request
+    }
+
+    fn watch_request(buffer_id: &str) -> WatchBufferChangesRequest {
+        WatchBufferChangesRequest {
+            buffer_id: Some(BufferId {
+                value: buffer_id.to_string(),
+            }),
+        }
+    }
+
+    fn lsp(workspaces: &Arc<WorkspaceManager>) -> Arc<LspBridge> {
+        LspBridge::start(
+            &DaemonConfig::default(),
+            workspaces.clone(),
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(DiagnosticsStore::new(8)),
+        )
+    }
+
+    fn no_formatters() -> Arc<FormatterRegistry> {
+        Arc::new(FormatterRegistry::new(Vec::new(), Duration::from_secs(5)))
+    }
+
+    fn no_settings() -> Arc<SettingsResolver> {
+        Arc::new(SettingsResolver::new(Settings::default(), 8))
+    }
+
+    fn sync(workspaces: &Arc<WorkspaceManager>) -> Arc<BufferSync> {
+        Arc::new(BufferSync::new(
+            workspaces.clone(),
+            Duration::from_millis(10),
+            8,
+        ))
+    }
+
+    fn edit_request(buffer_id: &str, expected_version: u64, text: &str) -> ApplyEditsRequest {
+        insert_request(buffer_id, expected_version, 0, text)
+    }
+
+    fn insert_request(
+        buffer_id: &str,
+        expected_version: u64,
+        line: u32,
+        text: &str,
+    ) -> ApplyEditsRequest {
+        let position = Position { line, character: 0 };
+        ApplyEditsRequest {
+            request_id: None,
+            buffer_id: Some(BufferId {
+                value: buffer_id.to_string(),
+            }),
+            edits: vec![ProtoTextEdit {
+                range: Some(Range {
+                    start: Some(position),
+                    end: Some(position),
+                }),
+                new_text: text.to_string(),
+            }],
+            expected_version,
+            create_undo_checkpoint: false,
+            edit_reason: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_edits_rebases_stale_edits() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), "world\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "a.txt", None, "")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let service = EditorService::new(
+            workspaces.clone(),
+            sync(&workspaces),
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(SyntaxManager::new()),
+            Arc::new(DiagnosticsStore::new(8)),
+            lsp(&workspaces),
+            no_formatters(),
+            no_settings(),
+        );
+
+        let response = service
+            .apply_edits(Request::new(edit_request(&buffer_id, 1, "hello ")))
+            .await
+            .unwrap();
+        let Some(apply_edits_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert_eq!(success.version, 2);
+        assert_eq!(success.cursors[0].character, 6);
+        assert_eq!(buffer.read().text(), "hello world\n");
+        assert!(!success.rebased);
+        assert!(!success.undo_checkpoint_created);
+
+        // A second edit based on the old version lands after the first one
+        let mut request = edit_request(&buffer_id, 1, "stale ");
+        request.create_undo_checkpoint = true;
+        let response = service.apply_edits(Request::new(request)).await.unwrap();
+        let Some(apply_edits_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert!(success.rebased);
+        assert_eq!(success.version, 3);
+        assert!(success.undo_checkpoint_created);
+        assert_eq!(buffer.read().undo_checkpoints().collect::<Vec<_>>(), [3]);
+        let range = success.applied_edits[0].range.unwrap();
+        assert_eq!(range.start.unwrap().character, 6);
+        assert_eq!(buffer.read().text(), "hello stale world\n");
+
+        // Versions the buffer never had are rejected
+        let response = service
+            .apply_edits(Request::new(edit_request(&buffer_id, 9, "future ")))
+            .await
+            .unwrap();
+        let Some(apply_edits_response::Result::Error(error)) = response.into_inner().result else {
+            panic!("Expected error");
+        };
+        assert_eq!(error.code, "VERSION_CONFLICT");
+    }
+
+    #[tokio::test]
+    async fn test_watch_buffer_changes_reports_external_edit() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), "before\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "a.txt", None, "")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let sync = sync(&workspaces);
+        sync.watch_workspace(&workspace);
+        let service = EditorService::new(
+            workspaces.clone(),
+            sync,
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(SyntaxManager::new()),
+            Arc::new(DiagnosticsStore::new(8)),
+            lsp(&workspaces),
+            no_formatters(),
+            no_settings(),
+        );
+
+        let mut stream = service
+            .watch_buffer_changes(Request::new(WatchBufferChangesRequest {
+                buffer_id: Some(BufferId {
+                    value: buffer_id.clone(),
+                }),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // What a branch switch does: replace the file wholesale
+        fs::remove_file(dir.path().join("a.txt")).unwrap();
+        fs::write(dir.path().join("a.txt"), "after\n").unwrap();
+
+        let message = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.change_type, BufferChangeType::Modified as i32);
+        assert_eq!(message.content, "after\n");
+        assert_eq!(message.meta.unwrap().sequence, 1);
+        assert_eq!(buffer.read().text(), "after\n");
+
+        let missing = service
+            .watch_buffer_changes(Request::new(WatchBufferChangesRequest { buffer_id: None }))
+            .await;
+        assert_eq!(missing.err().unwrap().code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_edits_are_broadcast_to_other_clients() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), "shared\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "a.txt", None, "window-1")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        workspaces
+            .open_buffer(workspace.id(), "a.txt", Some(buffer_id.clone()), "cli")
+            .unwrap();
+        let service = EditorService::new(
+            workspaces.clone(),
+            sync(&workspaces),
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(SyntaxManager::new()),
+            Arc::new(DiagnosticsStore::new(8)),
+            lsp(&workspaces),
+            no_formatters(),
+            no_settings(),
+        );
+
+        let mut own = service
+            .watch_buffer_changes(as_client(watch_request(&buffer_id), "window-1"))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut other = service
+            .watch_buffer_changes(as_client(watch_request(&buffer_id), "cli"))
+            .await
+            .unwrap()
+            .into_inner();
+
+        for (version, text) in [(1, "one "), (2, "two ")] {
+            service
+                .apply_edits(as_client(
+                    edit_request(&buffer_id, version, text),
+                    "window-1",
+                ))
+                .await
+                .unwrap();
+        }
+        service
+            .apply_edits(as_client(edit_request(&buffer_id, 3, "cli "), "cli"))
+            .await
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        for (sequence, version, text) in [(1, 2, "one "), (2, 3, "two ")] {
+            let message = tokio::time::timeout(timeout, other.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(message.change_type, BufferChangeType::RemoteEdit as i32);
+            assert_eq!(message.meta.unwrap().sequence, sequence);
+            assert_eq!(message.version, version);
+            assert_eq!(message.origin_client_id, "window-1");
+            assert_eq!(message.edits[0].new_text, text);
+        }
+
+        // The editing window only sees the other client's edit
+        let message = tokio::time::timeout(timeout, own.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.origin_client_id, "cli");
+        assert_eq!(message.version, 4);
+        assert_eq!(message.meta.unwrap().sequence, 1);
+        assert_eq!(buffer.read().text(), "cli two one shared\n");
+    }
+
+    #[tokio::test]
+    async fn test_syntax_tokens_follow_edits() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "main.rs", None, "")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let syntax = Arc::new(SyntaxManager::new());
+        let service = EditorService::new(
+            workspaces.clone(),
+            sync(&workspaces),
+            Arc::new(LanguageRegistry::new()),
+            syntax.clone(),
+            Arc::new(DiagnosticsStore::new(8)),
+            lsp(&workspaces),
+            no_formatters(),
+            no_settings(),
+        );
+
+        let request = |line| GetSyntaxTokensRequest {
+            buffer_id: Some(BufferId {
+                value: buffer_id.clone(),
+            }),
+            range: Some(Range {
+                start: Some(Position { line, character: 0 }),
+                end: Some(Position { line, character: 0 }),
+            }),
+        };
+        let tokens = |response: Response<GetSyntaxTokensResponse>| {
+            let Some(get_syntax_tokens_response::Result::Success(success)) =
+                response.into_inner().result
+            else {
+                panic!("Expected success");
+            };
+            success
+        };
+
+        let first = tokens(
+            service
+                .get_syntax_tokens(Request::new(request(0)))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(first.version, 1);
+        let keyword = first.tokens[0];
+        assert_eq!((keyword.start_character, keyword.length), (0, 2));
+        assert_eq!(keyword.token_type, ProtoTokenType::Keyword as i32);
+
+        // The edit is replayed onto the tree; the new line is a comment
+        service
+            .apply_edits(Request::new(edit_request(&buffer_id, 1, "// hi\n")))
+            .await
+            .unwrap();
+        let second = tokens(
+            service
+                .get_syntax_tokens(Request::new(request(0)))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(second.version, 2);
+        assert_eq!(second.tokens.len(), 1);
+        assert_eq!(second.tokens[0].token_type, ProtoTokenType::Comment as i32);
+        let range = second.range.unwrap();
+        assert_eq!(range.end.unwrap().character, 5);
+
+        let missing = service
+            .get_syntax_tokens(Request::new(GetSyntaxTokensRequest {
+                buffer_id: None,
+                range: None,
+            }))
+            .await
+            .unwrap();
+        let Some(get_syntax_tokens_response::Result::Error(error)) = missing.into_inner().result
+        else {
+            panic!("Expected error");
+        };
+        assert_eq!(error.code, "INVALID_ARGUMENT");
+        assert_eq!(syntax.document_count(), 1);
+    }
+
+    async fn next_message<T>(stream: &mut ResponseStream<T>) -> T {
+        tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_watch_syntax_tokens_follows_visible_range() {
+        let dir = TempDir::new().unwrap();
+        fs::write(
+            dir.path().join("lib.rs"),
+            "fn a() {}\nfn b() {}\nfn c() {}\n",
+        )
+        .unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "lib.rs", None, "")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let service = EditorService::new(
+            workspaces.clone(),
+            sync(&workspaces),
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(SyntaxManager::new()),
+            Arc::new(DiagnosticsStore::new(8)),
+            lsp(&workspaces),
+            no_formatters(),
+            no_settings(),
+        );
+        let lines = |first, last| Range {
+            start: Some(Position {
+                line: first,
+                character: 0,
+            }),
+            end: Some(Position {
+                line: last,
+                character: 0,
+            }),
+        };
+
+        let mut stream = service
+            .watch_syntax_tokens(Request::new(WatchSyntaxTokensRequest {
+                buffer_id: Some(BufferId {
+                    value: buffer_id.clone(),
+                }),
+                visible_range: Some(lines(0, 0)),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let snapshot = next_message(&mut stream).await;
+        let meta = snapshot.meta.unwrap();
+        assert_eq!(meta.delta_type, DeltaType::Snapshot as i32);
+        assert_eq!(meta.sequence, 1);
+        assert_eq!(snapshot.version, 1);
+        assert!(snapshot.tokens.iter().all(|t| t.line == 0));
+
+        // An edit off screen sends nothing; the next one on screen does
+        service
+            .apply_edits(Request::new(insert_request(&buffer_id, 1, 2, "pub ")))
+            .await
+            .unwrap();
+        service
+            .apply_edits(Request::new(insert_request(&buffer_id, 2, 0, "pub ")))
+            .await
+            .unwrap();
+        let update = next_message(&mut stream).await;
+        assert_eq!(update.meta.unwrap().delta_type, DeltaType::Update as i32);
+        assert_eq!(update.version, 3);
+        assert_eq!(update.range.unwrap().end.unwrap().line, 0);
+        assert_eq!(update.tokens[0].token_type, ProtoTokenType::Keyword as i32);
+        assert_eq!(update.tokens[0].length, 3);
+
+        // Scrolling sends a snapshot of the new range
+        let response = service
+            .update_visible_range(Request::new(UpdateVisibleRangeRequest {
+                stream_id: meta.stream_id.clone(),
+                visible_range: Some(lines(2, 2)),
+            }))
+            .await
+            .unwrap();
+        assert!(matches!(
+            response.into_inner().result,
+            Some(update_visible_range_response::Result::Success(_))
+        ));
+        let scrolled = next_message(&mut stream).await;
+        assert_eq!(
+            scrolled.meta.unwrap().delta_type,
+            DeltaType::Snapshot as i32
+        );
+        assert!(scrolled.tokens.iter().all(|t| t.line == 2));
+        assert_eq!(scrolled.tokens[0].length, 3);
+
+        let response = service
+            .update_visible_range(Request::new(UpdateVisibleRangeRequest {
+                stream_id: "missing".to_string(),
+                visible_range: Some(lines(0, 0)),
+            }))
+            .await
+            .unwrap();
+        let Some(update_visible_range_response::Result::Error(error)) =
+            response.into_inner().result
+        else {
+            panic!("Expected error");
+        };
+        assert_eq!(error.code, "STREAM_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_structure_follows_edits() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("lib.rs"), "fn a() {\n    f(1);\n}\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "lib.rs", None, "")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let service = EditorService::new(
+            workspaces.clone(),
+            sync(&workspaces),
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(SyntaxManager::new()),
+            Arc::new(DiagnosticsStore::new(8)),
+            lsp(&workspaces),
+            no_formatters(),
+            no_settings(),
+        );
+        let id = || {
+            Some(BufferId {
+                value: buffer_id.clone(),
+            })
+        };
+
+        let response = service
+            .get_folding_ranges(Request::new(GetFoldingRangesRequest { buffer_id: id() }))
+            .await
+            .unwrap();
+        let Some(get_folding_ranges_response::Result::Success(folds)) =
+            response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert_eq!(folds.version, 1);
+        assert_eq!(
+            (folds.ranges[0].start_line, folds.ranges[0].end_line),
+            (0, 1)
+        );
+
+        service
+            .apply_edits(Request::new(edit_request(&buffer_id, 1, "struct B;\n")))
+            .await
+            .unwrap();
+        let response = service
+            .get_document_symbols(Request::new(GetDocumentSymbolsRequest { buffer_id: id() }))
+            .await
+            .unwrap();
+        let Some(get_document_symbols_response::Result::Success(outline)) =
+            response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert_eq!(outline.version, 2);
+        let names: Vec<&str> = outline.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["B", "a"]);
+        assert_eq!(outline.symbols[1].range.unwrap().start.unwrap().line, 1);
+
+        let response = service
+            .get_bracket_pairs(Request::new(GetBracketPairsRequest {
+                buffer_id: id(),
+                range: None,
+            }))
+            .await
+            .unwrap();
+        let Some(get_bracket_pairs_response::Result::Error(error)) = response.into_inner().result
+        else {
+            panic!("Expected error");
+        };
+        assert_eq!(error.code, "INVALID_ARGUMENT");
+        let line = |line| Position { line, character: 0 };
+        let response = service
+            .get_bracket_pairs(Request::new(GetBracketPairsRequest {
+                buffer_id: id(),
+                range: Some(Range {
+                    start: Some(line(1)),
+                    end: Some(line(2)),
+                }),
+            }))
+            .await
+            .unwrap();
+        let Some(get_bracket_pairs_response::Result::Success(brackets)) =
+            response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        let depths: Vec<u32> = brackets.pairs.iter().map(|p| p.depth).collect();
+        assert_eq!(depths, [0, 0, 1]);
+
+        let response = service
+            .get_selection_ranges(Request::new(GetSelectionRangesRequest {
+                buffer_id: id(),
+                positions: vec![Position {
+                    line: 2,
+                    character: 6,
+                }],
+            }))
+            .await
+            .unwrap();
+        let Some(get_selection_ranges_response::Result::Success(selections)) =
+            response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        let first = selections.selections[0].ranges[0];
+        assert_eq!(first.start.unwrap().character, 6);
+        assert_eq!(first.end.unwrap().character, 7);
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics_by_buffer_and_workspace() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let workspace_id = workspace.id().to_string();
+        let buffer = workspaces
+            .open_buffer(&workspace_id, "a.rs", None, "")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let store = Arc::new(DiagnosticsStore::new(8));
+        let service = EditorService::new(
+            workspaces.clone(),
+            sync(&workspaces),
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(SyntaxManager::new()),
+            store.clone(),
+            lsp(&workspaces),
+            no_formatters(),
+            no_settings(),
+        );
+        let error =
+            |message: &str| Diagnostic::new(TextRange::lines(0, 1), Severity::Error, message);
+        // Files that are not open are part of the workspace view
+        store.publish(&workspace_id, "b.rs", "lsp", None, vec![error("unused")]);
+
+        let watch = |scope| WatchDiagnosticsRequest { scope: Some(scope) };
+        let mut by_buffer = service
+            .watch_diagnostics(Request::new(watch(
+                watch_diagnostics_request::Scope::BufferId(BufferId {
+                    value: buffer_id.clone(),
+                }),
+            )))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut by_workspace = service
+            .watch_diagnostics(Request::new(watch(
+                watch_diagnostics_request::Scope::WorkspaceId(WorkspaceId {
+                    value: workspace_id.clone(),
+                }),
+            )))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let snapshot = next_message(&mut by_buffer).await;
+        assert_eq!(
+            snapshot.meta.unwrap().delta_type,
+            DeltaType::Snapshot as i32
+        );
+        assert!(snapshot.diagnostics.is_empty());
+        let snapshot = next_message(&mut by_workspace).await;
+        assert_eq!(snapshot.file_id.unwrap().path, "b.rs");
+
+        store.publish(&workspace_id, "a.rs", "lsp", Some(1), vec![error("E0308")]);
+        let update = next_message(&mut by_buffer).await;
+        let meta = update.meta.unwrap();
+        assert_eq!(
+            (meta.delta_type, meta.sequence),
+            (DeltaType::Update as i32, 2)
+        );
+        assert_eq!(update.diagnostics[0].message, "E0308");
+        assert_eq!(update.version, 1);
+        let update = next_message(&mut by_workspace).await;
+        assert_eq!(update.file_id.unwrap().path, "a.rs");
+
+        // Changes to other files only reach the workspace stream
+        store.publish(&workspace_id, "b.rs", "lsp", None, vec![]);
+        let cleared = next_message(&mut by_workspace).await;
+        assert_eq!(cleared.file_id.unwrap().path, "b.rs");
+        assert!(cleared.diagnostics.is_empty());
+
+        let response = service
+            .get_diagnostics(Request::new(GetDiagnosticsRequest {
+                buffer_id: None,
+                workspace_id: Some(WorkspaceId {
+                    value: workspace_id.clone(),
+                }),
+            }))
+            .await
+            .unwrap();
+        let Some(get_diagnostics_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert_eq!(success.files.len(), 1);
+        assert_eq!(success.files[0].diagnostics[0].message, "E0308");
+
+        // The buffer stream ends when the buffer closes
+        workspaces.close_buffer(&buffer_id, "", None, true).unwrap();
+        service.sync.forget_buffers(&[buffer_id]);
+        let ended = tokio::time::timeout(Duration::from_secs(5), by_buffer.next()).await;
+        assert!(ended.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_format_buffer_and_selection() {
+        let dir = TempDir::new().unwrap();
+        let text = "one  \n\ttwo  \nthree";
+        fs::write(dir.path().join("a.txt"), text).unwrap();
+        fs::write(dir.path().join("a.md"), "# Title\n").unwrap();
+        fs::write(
+            dir.path().join(EDITORCONFIG),
+            "root = true\n[*.txt]\ntrim_trailing_whitespace = true\ninsert_final_newline = true\n",
+        )
+        .unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let open = |file: &str| {
+            let buffer = workspaces
+                .open_buffer(workspace.id(), file, None, "")
+                .unwrap();
+            let id = buffer.read().id().to_string();
+            id
+        };
+        let (txt, md) = (open("a.txt"), open("a.md"));
+        let formatters = FormatterRegistry::new(
+            vec![FormatterConfig::new(
+                "sed",
+                "sed",
+                ["s/^#/##/"],
+                ["markdown"],
+            )],
+            Duration::from_secs(5),
+        );
+        let service = EditorService::new(
+            workspaces.clone(),
+            sync(&workspaces),
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(SyntaxManager::new()),
+            Arc::new(DiagnosticsStore::new(8)),
+            lsp(&workspaces),
+            Arc::new(formatters),
+            no_settings(),
+        );
+        let options = FormattingOptions {
+            tab_size: 2,
+            insert_spaces: true,
+            trim_trailing_whitespace: true,
+            insert_final_newline: true,
+            trim_final_newlines: true,
+        };
+        let edits = |success: &FormatSuccess| -> Vec<TextEdit> {
+            success.edits.iter().cloned().map(from_proto_edit).collect()
+        };
+
+        // No formatter is configured for plain text: the options are applied
+        let response = service
+            .format_buffer(Request::new(FormatBufferRequest {
+                request_id: None,
+                buffer_id: Some(BufferId { value: txt.clone() }),
+                options: Some(options),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(format_buffer_response::Result::Success(success)) = response.result else {
+            panic!("expected success, got {:?}", response.result);
+        };
+        assert_eq!(success.formatter, "builtin");
+        assert_eq!(success.version, 1);
+        // Only what changed is edited: here the first line's trailing spaces
+        let first = TextRange::new(
+            gouide_workspace::Position::new(0, 3),
+            gouide_workspace::Position::new(0, 5),
+        );
+        assert_eq!(edits(&success).last(), Some(&TextEdit::new(first, "")));
+        assert_eq!(apply_edits(text, &edits(&success)), "one\n  two\nthree\n");
+
+        let response = service
+            .format_selection(Request::new(FormatSelectionRequest {
+                request_id: None,
+                buffer_id: Some(BufferId { value: txt.clone() }),
+                range: Some(Range {
+                    start: Some(Position {
+                        line: 1,
+                        character: 0,
+                    }),
+                    end: Some(Position {
+                        line: 1,
+                        character: 3,
+                    }),
+                }),
+                options: Some(options),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(format_selection_response::Result::Success(success)) = response.result else {
+            panic!("expected success, got {:?}", response.result);
+        };
+        assert_eq!(apply_edits(text, &edits(&success)), "one  \n  two\nthree");
+
+        // Without options the effective settings decide, keeping the tabs
+        // detected in the text
+        let response = service
+            .format_buffer(Request::new(FormatBufferRequest {
+                request_id: None,
+                buffer_id: Some(BufferId { value: txt }),
+                options: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(format_buffer_response::Result::Success(success)) = response.result else {
+            panic!("expected success, got {:?}", response.result);
+        };
+        assert_eq!(apply_edits(text, &edits(&success)), "one\n\ttwo\nthree\n");
+
+        // Markdown goes through the external formatter
+        let response = service
+            .format_buffer(Request::new(FormatBufferRequest {
+                request_id: None,
+                buffer_id: Some(BufferId { value: md }),
+                options: Some(options),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(format_buffer_response::Result::Success(success)) = response.result else {
+            panic!("expected success, got {:?}", response.result);
+        };
+        assert_eq!(success.formatter, "sed");
+        assert_eq!(success.edits.len(), 1);
+        assert_eq!(success.edits[0].new_text, "#");
+    }
+}
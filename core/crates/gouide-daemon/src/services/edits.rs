@@ -0,0 +1,573 @@
+//! Changes to buffers and files made on behalf of clients.
+//!
+//! [`WorkspaceEditor::edit_buffer`] is the path every edit to an open buffer
+//! takes, whether it comes from ApplyEdits or a code action: edits are
+//! rebased onto the current version, handed to the syntax trees, and
+//! published to the buffer's other clients. [`WorkspaceEditor::apply`]
+//! applies a change set that may span files: edits to open buffers take
+//! that path, edits to other files are written to disk, and file creates,
+//! renames and deletes keep open buffers bound to the right files.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use gouide_protocol::{
+    AppliedChange, AppliedChangeKind, ApplyEditsSuccess, BufferChangeType, BufferId, Error, FileId,
+};
+use gouide_syntax::SyntaxManager;
+use gouide_workspace::{
+    AppliedEdits, Buffer, SaveOptions, SharedBuffer, TextEdit, Workspace, WorkspaceError,
+    WorkspaceManager,
+};
+use tracing::debug;
+use uuid::Uuid;
+
+use super::convert::{to_proto_edit, to_proto_position};
+use super::errors::{error, workspace_error};
+use super::BufferSync;
+
+/// One change of a multi-file change set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Change {
+    /// Edits to a file, applied in order.
+    Edit {
+        /// Workspace-relative path.
+        file_id: String,
+        /// Buffer version the edits were made against, 0 for the current
+        /// version.
+        version: u64,
+        /// Edits.
+        edits: Vec<TextEdit>,
+    },
+    /// Create an empty file.
+    Create {
+        /// Workspace-relative path.
+        file_id: String,
+        /// Truncate the file if it exists.
+        overwrite: bool,
+        /// Do nothing if the file exists.
+        ignore_if_exists: bool,
+    },
+    /// Rename a file or directory.
+    Rename {
+        /// Current workspace-relative path.
+        from: String,
+        /// New workspace-relative path.
+        to: String,
+        /// Replace the target if it exists.
+        overwrite: bool,
+        /// Do nothing if the target exists.
+        ignore_if_exists: bool,
+    },
+    /// Delete a file or directory.
+    Delete {
+        /// Workspace-relative path.
+        file_id: String,
+        /// Delete a directory with its contents.
+        recursive: bool,
+        /// Do nothing if the file does not exist.
+        ignore_if_not_exists: bool,
+    },
+}
+
+impl Change {
+    /// Paths the change touches.
+    fn file_ids(&self) -> Vec<&str> {
+        match self {
+            Self::Edit { file_id, .. }
+            | Self::Create { file_id, .. }
+            | Self::Delete { file_id, .. } => vec![file_id],
+            Self::Rename { from, to, .. } => vec![from, to],
+        }
+    }
+}
+
+/// Applies edits to buffers and change sets to workspaces.
+#[derive(Clone)]
+pub(super) struct WorkspaceEditor {
+    workspaces: Arc<WorkspaceManager>,
+    syntax: Arc<SyntaxManager>,
+    sync: Arc<BufferSync>,
+}
+
+impl WorkspaceEditor {
+    pub(super) fn new(
+        workspaces: Arc<WorkspaceManager>,
+        syntax: Arc<SyntaxManager>,
+        sync: Arc<BufferSync>,
+    ) -> Self {
+        Self {
+            workspaces,
+            syntax,
+            sync,
+        }
+    }
+
+    /// Apply edits to a buffer, rebasing them if they were made against an
+    /// older version, and share them with the buffer's other clients.
+    /// With `undo_checkpoint`, the new version ends an undo group.
+    pub(super) fn edit_buffer(
+        &self,
+        buffer_id: &str,
+        edits: &[TextEdit],
+        expected_version: u64,
+        undo_checkpoint: bool,
+        session: &str,
+    ) -> Result<ApplyEditsSuccess, WorkspaceError> {
+        let shared = self.workspaces.buffer(buffer_id)?;
+        let mut buffer = shared.write();
+        self.edit_locked(
+            &mut buffer,
+            edits,
+            expected_version,
+            undo_checkpoint,
+            session,
+        )
+    }
+
+    /// [`Self::edit_buffer`] for a buffer the caller has already locked,
+    /// so changes spanning buffers can lock them all before editing any.
+    pub(super) fn edit_locked(
+        &self,
+        buffer: &mut Buffer,
+        edits: &[TextEdit],
+        expected_version: u64,
+        undo_checkpoint: bool,
+        session: &str,
+    ) -> Result<ApplyEditsSuccess, WorkspaceError> {
+        let applied = buffer.apply_edits_at(expected_version, edits)?;
+        self.share(buffer, &applied, session);
+        let undo_checkpoint_created = undo_checkpoint && buffer.create_undo_checkpoint();
+
+        Ok(ApplyEditsSuccess {
+            version: buffer.version(),
+            cursors: applied.cursors.into_iter().map(to_proto_position).collect(),
+            undo_checkpoint_created,
+            applied_edits: applied.edits.iter().map(to_proto_edit).collect(),
+            rebased: applied.rebased,
+        })
+    }
+
+    /// Bring syntax trees up to date with edits just applied to a buffer and
+    /// send them to its other clients.
+    pub(super) fn share(&self, buffer: &Buffer, applied: &AppliedEdits, session: &str) {
+        self.syntax
+            .edit(buffer.id(), buffer.version(), &applied.byte_edits);
+        self.sync.publish_edits(buffer, &applied.edits, session);
+    }
+
+    /// Apply a change set to a workspace, in order, stopping at the first
+    /// change that fails. Every path is checked before anything changes.
+    pub(super) fn apply(
+        &self,
+        workspace: &Workspace,
+        changes: Vec<Change>,
+        session: &str,
+        source: &str,
+    ) -> Result<Vec<AppliedChange>, Error> {
+        for file_id in changes.iter().flat_map(Change::file_ids) {
+            workspace
+                .resolve_path(file_id)
+                .map_err(|e| workspace_error(&e, source))?;
+        }
+
+        let mut applied = Vec::new();
+        for change in changes {
+            let result = match change {
+                Change::Edit {
+                    file_id,
+                    version,
+                    edits,
+                } => self
+                    .edit_file(workspace, &file_id, version, &edits, session)
+                    .map(Some)
+                    .map_err(|e| workspace_error(&e, source)),
+                Change::Create {
+                    file_id,
+                    overwrite,
+                    ignore_if_exists,
+                } => create_file(workspace, &file_id, overwrite, ignore_if_exists, source),
+                Change::Rename {
+                    from,
+                    to,
+                    overwrite,
+                    ignore_if_exists,
+                } => self.rename(workspace, &from, &to, overwrite, ignore_if_exists, source),
+                Change::Delete {
+                    file_id,
+                    recursive,
+                    ignore_if_not_exists,
+                } => delete(workspace, &file_id, recursive, ignore_if_not_exists, source),
+            };
+            applied.extend(result?);
+        }
+        Ok(applied)
+    }
+
+    /// The open buffer of a file, if any.
+    fn open_buffer(&self, workspace: &Workspace, file_id: &str) -> Option<SharedBuffer> {
+        self.workspaces
+            .list_buffers(workspace.id())
+            .ok()?
+            .into_iter()
+            .find(|shared| shared.read().file_id() == file_id)
+    }
+
+    /// Edit a file through its open buffer, or on disk if it is not open.
+    fn edit_file(
+        &self,
+        workspace: &Workspace,
+        file_id: &str,
+        version: u64,
+        edits: &[TextEdit],
+        session: &str,
+    ) -> Result<AppliedChange, WorkspaceError> {
+        if let Some(shared) = self.open_buffer(workspace, file_id) {
+            let buffer_id = shared.read().id().to_string();
+            let success = self.edit_buffer(&buffer_id, edits, version, false, session)?;
+            return Ok(AppliedChange {
+                kind: AppliedChangeKind::Edited as i32,
+                file_id: Some(file(file_id)),
+                buffer_id: Some(BufferId { value: buffer_id }),
+                version: success.version,
+                undo_checkpoint_created: success.undo_checkpoint_created,
+                ..AppliedChange::default()
+            });
+        }
+
+        let path = workspace.resolve_path(file_id)?;
+        let mut buffer = Buffer::load(
+            Uuid::new_v4().to_string(),
+            workspace.id().to_string(),
+            file_id.to_string(),
+            &path,
+            self.workspaces.limits(),
+        )?;
+        buffer.apply_edits(edits)?;
+        buffer.save(SaveOptions::default())?;
+        debug!(file_id = %file_id, edits = edits.len(), "Edited file on disk");
+        Ok(AppliedChange {
+            kind: AppliedChangeKind::Edited as i32,
+            file_id: Some(file(file_id)),
+            ..AppliedChange::default()
+        })
+    }
+
+    /// Rename a file or directory and rebind the open buffers inside it.
+    fn rename(
+        &self,
+        workspace: &Workspace,
+        from: &str,
+        to: &str,
+        overwrite: bool,
+        ignore_if_exists: bool,
+        source: &str,
+    ) -> Result<Option<AppliedChange>, Error> {
+        let from_path = workspace
+            .resolve_path(from)
+            .map_err(|e| workspace_error(&e, source))?;
+        let to_path = workspace
+            .resolve_path(to)
+            .map_err(|e| workspace_error(&e, source))?;
+        if to_path.exists() && !overwrite {
+            if ignore_if_exists {
+                return Ok(None);
+            }
+            return Err(file_exists(to, source));
+        }
+        create_parent(&to_path)
+            .and_then(|()| fs::rename(&from_path, &to_path))
+            .map_err(|e| io_error(e, from, source))?;
+
+        // Rebound now rather than when the watcher catches up, which may
+        // report the move as a delete and a create
+        for shared in self
+            .workspaces
+            .list_buffers(workspace.id())
+            .unwrap_or_default()
+        {
+            let mut buffer = shared.write();
+            let Some(rest) = buffer.file_id().strip_prefix(from) else {
+                continue;
+            };
+            if !(rest.is_empty() || rest.starts_with('/')) {
+                continue;
+            }
+            let moved = format!("{to}{rest}");
+            let path = workspace.root().join(&moved);
+            buffer.rebind(moved, path);
+            self.sync
+                .publish_change(&buffer, BufferChangeType::Renamed, "");
+            drop(buffer);
+        }
+        Ok(Some(AppliedChange {
+            kind: AppliedChangeKind::Renamed as i32,
+            file_id: Some(file(from)),
+            new_file_id: Some(file(to)),
+            ..AppliedChange::default()
+        }))
+    }
+}
+
+fn file(file_id: &str) -> FileId {
+    FileId {
+        path: file_id.to_string(),
+    }
+}
+
+fn file_exists(file_id: &str, source: &str) -> Error {
+    error("FILE_EXISTS", format!("{file_id} already exists"), source)
+}
+
+pub(super) fn io_error(err: io::Error, file_id: &str, source: &str) -> Error {
+    let err = match err.kind() {
+        io::ErrorKind::NotFound => WorkspaceError::FileNotFound(file_id.to_string()),
+        io::ErrorKind::PermissionDenied => WorkspaceError::PermissionDenied(file_id.to_string()),
+        _ => WorkspaceError::Io(err),
+    };
+    workspace_error(&err, source)
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    path.parent().map_or(Ok(()), fs::create_dir_all)
+}
+
+fn create_file(
+    workspace: &Workspace,
+    file_id: &str,
+    overwrite: bool,
+    ignore_if_exists: bool,
+    source: &str,
+) -> Result<Option<AppliedChange>, Error> {
+    let path = workspace
+        .resolve_path(file_id)
+        .map_err(|e| workspace_error(&e, source))?;
+    if path.exists() && !overwrite {
+        if ignore_if_exists {
+            return Ok(None);
+        }
+        return Err(file_exists(file_id, source));
+    }
+    create_parent(&path)
+        .and_then(|()| fs::write(&path, ""))
+        .map_err(|e| io_error(e, file_id, source))?;
+    Ok(Some(AppliedChange {
+        kind: AppliedChangeKind::Created as i32,
+        file_id: Some(file(file_id)),
+        ..AppliedChange::default()
+    }))
+}
+
+/// Delete a file or directory. Open buffers of deleted files learn about it
+/// from the file watcher, like any other deletion.
+fn delete(
+    workspace: &Workspace,
+    file_id: &str,
+    recursive: bool,
+    ignore_if_not_exists: bool,
+    source: &str,
+) -> Result<Option<AppliedChange>, Error> {
+    let path = workspace
+        .resolve_path(file_id)
+        .map_err(|e| workspace_error(&e, source))?;
+    let result = match fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.is_dir() && recursive => fs::remove_dir_all(&path),
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(&path),
+        Ok(_) => fs::remove_file(&path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && ignore_if_not_exists => {
+            return Ok(None);
+        }
+        Err(e) => Err(e),
+    };
+    result.map_err(|e| io_error(e, file_id, source))?;
+    Ok(Some(AppliedChange {
+        kind: AppliedChangeKind::Deleted as i32,
+        file_id: Some(file(file_id)),
+        ..AppliedChange::default()
+    }))
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use gouide_workspace::{Position, TextRange};
+    use tempfile::TempDir;
+
+    fn setup(dir: &TempDir) -> (WorkspaceEditor, Arc<Workspace>, Arc<BufferSync>) {
+        fs::write(dir.path().join("open.rs"), "fn open() {}\n").unwrap();
+        fs::write(dir.path().join("closed.rs"), "fn closed() {}\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let sync = Arc::new(BufferSync::new(
+            workspaces.clone(),
+            Duration::from_millis(10),
+            16,
+        ));
+        let editor = WorkspaceEditor::new(workspaces, Arc::new(SyntaxManager::new()), sync.clone());
+        (editor, workspace, sync)
+    }
+
+    fn insert(line: u32, character: u32, text: &str) -> TextEdit {
+        let at = Position::new(line, character);
+        TextEdit::new(TextRange::new(at, at), text.to_string())
+    }
+
+    #[test]
+    fn test_edits_go_to_open_buffers_and_to_disk() {
+        let dir = TempDir::new().unwrap();
+        let (editor, workspace, sync) = setup(&dir);
+        let buffer = editor
+            .workspaces
+            .open_buffer(workspace.id(), "open.rs", None, "")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let mut changes_rx = sync.subscribe(&buffer_id).unwrap();
+
+        let applied = editor
+            .apply(
+                &workspace,
+                vec![
+                    Change::Edit {
+                        file_id: "open.rs".into(),
+                        version: 1,
+                        edits: vec![insert(0, 0, "pub ")],
+                    },
+                    Change::Edit {
+                        file_id: "closed.rs".into(),
+                        version: 0,
+                        edits: vec![insert(0, 0, "pub ")],
+                    },
+                ],
+                "cli",
+                "test",
+            )
+            .unwrap();
+
+        assert_eq!(applied[0].buffer_id.as_ref().unwrap().value, buffer_id);
+        assert_eq!(applied[0].version, 2);
+        assert_eq!(buffer.read().text(), "pub fn open() {}\n");
+        // The open buffer is edited, not the file
+        assert_eq!(
+            fs::read_to_string(dir.path().join("open.rs")).unwrap(),
+            "fn open() {}\n"
+        );
+        let message = changes_rx.try_recv().unwrap();
+        assert_eq!(message.change_type, BufferChangeType::RemoteEdit as i32);
+        assert_eq!(message.origin_client_id, "cli");
+
+        assert!(applied[1].buffer_id.is_none());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("closed.rs")).unwrap(),
+            "pub fn closed() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_file_operations() {
+        let dir = TempDir::new().unwrap();
+        let (editor, workspace, sync) = setup(&dir);
+        let buffer = editor
+            .workspaces
+            .open_buffer(workspace.id(), "open.rs", None, "")
+            .unwrap();
+        let mut changes_rx = sync.subscribe(buffer.read().id()).unwrap();
+
+        let applied = editor
+            .apply(
+                &workspace,
+                vec![
+                    Change::Create {
+                        file_id: "src/new.rs".into(),
+                        overwrite: false,
+                        ignore_if_exists: false,
+                    },
+                    Change::Edit {
+                        file_id: "src/new.rs".into(),
+                        version: 0,
+                        edits: vec![insert(0, 0, "mod new;\n")],
+                    },
+                    Change::Rename {
+                        from: "open.rs".into(),
+                        to: "src/open.rs".into(),
+                        overwrite: false,
+                        ignore_if_exists: false,
+                    },
+                    Change::Delete {
+                        file_id: "closed.rs".into(),
+                        recursive: false,
+                        ignore_if_not_exists: false,
+                    },
+                    Change::Delete {
+                        file_id: "missing.rs".into(),
+                        recursive: false,
+                        ignore_if_not_exists: true,
+                    },
+                ],
+                "",
+                "test",
+            )
+            .unwrap();
+
+        let kinds: Vec<_> = applied.iter().map(|c| c.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                AppliedChangeKind::Created as i32,
+                AppliedChangeKind::Edited as i32,
+                AppliedChangeKind::Renamed as i32,
+                AppliedChangeKind::Deleted as i32,
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("src/new.rs")).unwrap(),
+            "mod new;\n"
+        );
+        assert!(dir.path().join("src/open.rs").exists());
+        assert!(!dir.path().join("closed.rs").exists());
+        assert_eq!(buffer.read().file_id(), "src/open.rs");
+        let message = changes_rx.try_recv().unwrap();
+        assert_eq!(message.change_type, BufferChangeType::Renamed as i32);
+    }
+
+    #[test]
+    fn test_conflicts_and_paths_outside_the_workspace() {
+        let dir = TempDir::new().unwrap();
+        let (editor, workspace, _sync) = setup(&dir);
+        let create = |file_id: &str, ignore_if_exists| Change::Create {
+            file_id: file_id.into(),
+            overwrite: false,
+            ignore_if_exists,
+        };
+
+        let err = editor
+            .apply(&workspace, vec![create("open.rs", false)], "", "test")
+            .unwrap_err();
+        assert_eq!(err.code, "FILE_EXISTS");
+        let applied = editor
+            .apply(&workspace, vec![create("open.rs", true)], "", "test")
+            .unwrap();
+        assert!(applied.is_empty());
+
+        // Nothing is applied when any path is invalid
+        let err = editor
+            .apply(
+                &workspace,
+                vec![create("new.rs", false), create("../escape.rs", false)],
+                "",
+                "test",
+            )
+            .unwrap_err();
+        assert_eq!(err.code, "INVALID_PATH");
+        assert!(!dir.path().join("new.rs").exists());
+    }
+}
@@ -0,0 +1,174 @@
+//! Mapping of daemon errors to structured protocol errors.
+
+use gouide_git::GitError;
+use gouide_lsp::LspError;
+use gouide_protocol::{Error, RetryHint, Severity};
+use gouide_syntax::SyntaxError;
+use gouide_workspace::WorkspaceError;
+
+use crate::formatters::FormatError;
+
+/// Build a protocol error.
+pub(crate) fn error(code: &str, user_message: impl Into<String>, source: &str) -> Error {
+    Error {
+        code: code.to_string(),
+        user_message: user_message.into(),
+        details: String::new(),
+        severity: Severity::Error as i32,
+        source: source.to_string(),
+        retry_hint: None,
+    }
+}
+
+/// Error for a request that is missing a required field or is malformed.
+pub(crate) fn invalid_argument(user_message: impl Into<String>, source: &str) -> Error {
+    error("INVALID_ARGUMENT", user_message, source)
+}
+
+/// Convert a workspace error into a protocol error.
+pub(crate) fn workspace_error(err: &WorkspaceError, source: &str) -> Error {
+    let code = match err {
+        WorkspaceError::NotFound(_) => "WORKSPACE_NOT_FOUND",
+        WorkspaceError::BufferNotFound(_) => "BUFFER_NOT_FOUND",
+        WorkspaceError::BufferIdInUse(_) => "BUFFER_ID_IN_USE",
+        WorkspaceError::BufferDirty(_) => "BUFFER_DIRTY",
+        WorkspaceError::ReadOnly(_) => "BUFFER_READ_ONLY",
+        WorkspaceError::VersionConflict { .. } => "VERSION_CONFLICT",
+        WorkspaceError::InvalidRange(_) => "INVALID_RANGE",
+        WorkspaceError::FileNotFound(_) => "FILE_NOT_FOUND",
+        WorkspaceError::PermissionDenied(_) => "PERMISSION_DENIED",
+        WorkspaceError::InvalidPath(_) => "INVALID_PATH",
+        WorkspaceError::UnsupportedEncoding(_) => "UNSUPPORTED_ENCODING",
+        WorkspaceError::Io(_) => "IO_ERROR",
+    };
+    let mut error = error(code, err.to_string(), source);
+    match err {
+        WorkspaceError::Io(io) => error.details = format!("{io:?}"),
+        WorkspaceError::BufferDirty(file) => {
+            error.user_message = format!(
+                "{file} has unsaved changes. Save it first, or close with \
+                 save_before_close to save or force to discard the changes."
+            );
+        }
+        _ => {}
+    }
+    error
+}
+
+/// Convert a syntax error into a protocol error.
+pub(crate) fn syntax_error(err: &SyntaxError, source: &str) -> Error {
+    let code = match err {
+        SyntaxError::Grammar { .. } | SyntaxError::Query { .. } => "SYNTAX_UNAVAILABLE",
+        SyntaxError::ParseFailed(_) => "PARSE_FAILED",
+    };
+    error(code, err.to_string(), source)
+}
+
+/// Convert an external formatter error into a protocol error.
+pub(crate) fn format_error(err: &FormatError, source: &str) -> Error {
+    let code = match err {
+        FormatError::NotInstalled(_) => "FORMATTER_UNAVAILABLE",
+        FormatError::Timeout(_) => "TIMEOUT",
+        FormatError::Failed { .. } | FormatError::Io { .. } | FormatError::InvalidOutput(_) => {
+            "FORMATTER_FAILED"
+        }
+    };
+    let mut error = error(code, err.to_string(), source);
+    if let FormatError::Io { source, .. } = err {
+        error.details = format!("{source:?}");
+    }
+    error
+}
+
+/// Convert a language server error into a protocol error.
+pub(crate) fn lsp_error(err: &LspError, source: &str) -> Error {
+    let code = match err {
+        LspError::Spawn { .. } | LspError::NotRunning(_) => "LANGUAGE_SERVER_UNAVAILABLE",
+        LspError::Timeout(_) => "TIMEOUT",
+        // RequestCancelled: the server gave up on the request itself
+        LspError::Server { code: -32800, .. } => "CANCELLED",
+        LspError::Server { .. } | LspError::Io(_) | LspError::Protocol(_) => {
+            "LANGUAGE_SERVER_ERROR"
+        }
+    };
+    let mut error = error(code, err.to_string(), source);
+    match err {
+        // Servers restart on their own, and slow ones catch up
+        LspError::NotRunning(_) | LspError::Timeout(_) => {
+            error.retry_hint = Some(RetryHint {
+                retryable: true,
+                retry_after_ms: 1000,
+            });
+        }
+        LspError::Io(io) => error.details = format!("{io:?}"),
+        _ => {}
+    }
+    error
+}
+
+/// Convert a git error into a protocol error.
+pub(crate) fn git_error(err: &GitError, source: &str) -> Error {
+    let code = match err {
+        GitError::NotInRepository(_) => "NOT_IN_REPOSITORY",
+        GitError::Spawn(_) => "GIT_UNAVAILABLE",
+        GitError::NothingToCommit => "NOTHING_TO_COMMIT",
+        GitError::InvalidBranchName(_) => "INVALID_BRANCH_NAME",
+        GitError::BranchExists(_) => "BRANCH_EXISTS",
+        GitError::BranchNotFound(_) => "BRANCH_NOT_FOUND",
+        GitError::LocalChanges(_) => "UNCOMMITTED_CHANGES",
+        GitError::NothingToStash => "NOTHING_TO_STASH",
+        GitError::NoStash => "NO_STASH",
+        GitError::Command { .. } | GitError::Parse(_) => "GIT_FAILED",
+    };
+    let mut error = error(code, err.to_string(), source);
+    if let GitError::Spawn(io) = err {
+        error.details = format!("{io:?}");
+    }
+    error
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workspace_error_codes() {
+        let err = workspace_error(&WorkspaceError::FileNotFound("a.rs".to_string()), "buffer");
+        assert_eq!(err.code, "FILE_NOT_FOUND");
+        assert_eq!(err.source, "buffer");
+        assert_eq!(err.severity, Severity::Error as i32);
+        assert!(err.user_message.contains("a.rs"));
+    }
+
+    #[test]
+    fn test_dirty_error_is_actionable() {
+        let err = workspace_error(&WorkspaceError::BufferDirty("a.rs".to_string()), "buffer");
+        assert_eq!(err.code, "BUFFER_DIRTY");
+        assert!(err.user_message.contains("save_before_close"));
+        assert!(err.user_message.contains("force"));
+    }
+
+    #[test]
+    fn test_lsp_error_codes() {
+        let err = lsp_error(&LspError::NotRunning("fake".to_string()), "language");
+        assert_eq!(err.code, "LANGUAGE_SERVER_UNAVAILABLE");
+        assert!(err.retry_hint.unwrap().retryable);
+
+        let err = lsp_error(
+            &LspError::Server {
+                code: -32602,
+                message: "bad position".to_string(),
+            },
+            "language",
+        );
+        assert_eq!(err.code, "LANGUAGE_SERVER_ERROR");
+        assert!(err.user_message.contains("bad position"));
+        assert!(err.retry_hint.is_none());
+    }
+}
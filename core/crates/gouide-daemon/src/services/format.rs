@@ -0,0 +1,228 @@
+//! Buffer formatting for FormatBuffer and FormatSelection.
+//!
+//! The formatter is picked per request as described in
+//! [`crate::formatters`]. Whichever runs, its output is diffed against the
+//! buffer text, so clients get edits for what changed rather than a
+//! replacement of the whole buffer. Requests without formatting options use
+//! the buffer's effective settings.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use gouide_lsp::lsp_types::request::{Formatting, RangeFormatting};
+use gouide_lsp::lsp_types::{
+    self, DocumentFormattingParams, DocumentRangeFormattingParams, ServerCapabilities,
+    TextDocumentIdentifier,
+};
+use gouide_protocol::{Error, FormatSuccess};
+use gouide_workspace::{apply_edits, diff, TextRange, WorkspaceError, WorkspaceManager};
+use tracing::debug;
+
+use super::convert::to_proto_edit;
+use super::errors::{format_error, lsp_error, workspace_error};
+use super::lsp::{self, enabled, end_to_start, to_lsp_range};
+use super::settings::SettingsLookup;
+use super::LspBridge;
+use crate::formatters::{apply_options, FormatError, FormatOptions, FormatterRegistry};
+use crate::languages::LanguageRegistry;
+
+/// Formatter name reported for the built-in formatter.
+const BUILTIN: &str = "builtin";
+
+/// Formats buffers with external tools, language servers or the built-in
+/// formatter.
+pub(super) struct BufferFormatter {
+    workspaces: Arc<WorkspaceManager>,
+    languages: Arc<LanguageRegistry>,
+    lsp: Arc<LspBridge>,
+    formatters: Arc<FormatterRegistry>,
+    settings: SettingsLookup,
+}
+
+/// A buffer being formatted.
+struct Target<'a> {
+    buffer_id: &'a str,
+    text: &'a str,
+    path: &'a Path,
+    range: Option<TextRange>,
+    options: FormatOptions,
+    /// Error source label of the calling service.
+    source: &'a str,
+}
+
+impl BufferFormatter {
+    pub(super) const fn new(
+        workspaces: Arc<WorkspaceManager>,
+        languages: Arc<LanguageRegistry>,
+        lsp: Arc<LspBridge>,
+        formatters: Arc<FormatterRegistry>,
+        settings: SettingsLookup,
+    ) -> Self {
+        Self {
+            workspaces,
+            languages,
+            lsp,
+            formatters,
+            settings,
+        }
+    }
+
+    /// Format a buffer, keeping only the edits within the lines of `range`
+    /// when one is given. Without `options`, the buffer's effective settings
+    /// decide.
+    pub(super) async fn format(
+        &self,
+        buffer_id: &str,
+        range: Option<TextRange>,
+        options: Option<FormatOptions>,
+        source: &str,
+    ) -> Result<FormatSuccess, Error> {
+        let shared = self
+            .workspaces
+            .buffer(buffer_id)
+            .map_err(|e| workspace_error(&e, source))?;
+        let (text, version, path, language_id, line_ending, options) = {
+            let buffer = shared.read();
+            if buffer.read_only() {
+                let err = WorkspaceError::ReadOnly(buffer.file_id().to_string());
+                return Err(workspace_error(&err, source));
+            }
+            let options = match options {
+                Some(options) => options,
+                None => self
+                    .settings
+                    .buffer(&buffer)
+                    .map_err(|e| workspace_error(&e, source))?
+                    .format_options(),
+            };
+            (
+                buffer.text(),
+                buffer.version(),
+                buffer.path().to_path_buf(),
+                self.languages.detect_buffer(&buffer),
+                buffer.line_ending(),
+                options,
+            )
+        };
+        let target = Target {
+            buffer_id,
+            text: &text,
+            path: &path,
+            range,
+            options,
+            source,
+        };
+
+        let formatted = match self.external(&target, &language_id).await? {
+            Some(formatted) => Some(formatted),
+            None => self.language_server(&target).await?,
+        };
+        let (formatted, formatter) = formatted.unwrap_or_else(|| {
+            (
+                apply_options(&text, options, line_ending.as_str()),
+                BUILTIN.to_string(),
+            )
+        });
+
+        let mut edits = diff(&text, &formatted);
+        if let Some(range) = range {
+            let lines = selected_lines(range);
+            edits.retain(|edit| edit.range.start >= lines.start && edit.range.end <= lines.end);
+        }
+        Ok(FormatSuccess {
+            edits: edits.iter().map(to_proto_edit).collect(),
+            version,
+            formatter,
+        })
+    }
+
+    /// Run the external formatter for the language, if there is one and it
+    /// is installed. Returns the formatted text and the formatter's name.
+    async fn external(
+        &self,
+        target: &Target<'_>,
+        language_id: &str,
+    ) -> Result<Option<(String, String)>, Error> {
+        let Some(formatter) = self.formatters.for_language(language_id) else {
+            return Ok(None);
+        };
+        let timeout = self.formatters.timeout();
+        match formatter.run(target.text, target.path, timeout).await {
+            Ok(formatted) => Ok(Some((formatted, formatter.name.clone()))),
+            Err(FormatError::NotInstalled(name)) => {
+                debug!(formatter = %name, "Formatter not installed, falling back");
+                Ok(None)
+            }
+            Err(e) => Err(format_error(&e, target.source)),
+        }
+    }
+
+    /// Format with a language server of the buffer, if one supports it.
+    /// Returns the formatted text and the server's source name.
+    ///
+    /// Selections use range formatting when the server offers it, and whole
+    /// buffer formatting otherwise.
+    async fn language_server(
+        &self,
+        target: &Target<'_>,
+    ) -> Result<Option<(String, String)>, Error> {
+        let Some((uri, servers)) = self.lsp.document(target.buffer_id) else {
+            return Ok(None);
+        };
+        let capable = |supports: fn(&ServerCapabilities) -> bool| {
+            servers
+                .iter()
+                .find(|server| server.capabilities().is_some_and(|c| supports(&c)))
+        };
+        let text_document = TextDocumentIdentifier { uri };
+        let ranged = target.range.and_then(|range| {
+            capable(|c| enabled(c.document_range_formatting_provider.as_ref()))
+                .map(|server| (server, range))
+        });
+        let (server, edits) = if let Some((server, range)) = ranged {
+            let params = DocumentRangeFormattingParams {
+                text_document,
+                range: to_lsp_range(range),
+                options: lsp_options(target.options),
+                work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            };
+            (server, server.request::<RangeFormatting>(params).await)
+        } else if let Some(server) = capable(|c| enabled(c.document_formatting_provider.as_ref())) {
+            let params = DocumentFormattingParams {
+                text_document,
+                options: lsp_options(target.options),
+                work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            };
+            (server, server.request::<Formatting>(params).await)
+        } else {
+            return Ok(None);
+        };
+
+        let edits = edits.map_err(|e| lsp_error(&e, target.source))?;
+        let formatted = apply_edits(target.text, &end_to_start(edits.unwrap_or_default()));
+        Ok(Some((formatted, lsp::source(server.name()))))
+    }
+}
+
+fn lsp_options(options: FormatOptions) -> lsp_types::FormattingOptions {
+    lsp_types::FormattingOptions {
+        tab_size: options.tab_width(),
+        insert_spaces: options.insert_spaces,
+        properties: HashMap::new(),
+        trim_trailing_whitespace: Some(options.trim_trailing_whitespace),
+        insert_final_newline: Some(options.insert_final_newline),
+        trim_final_newlines: Some(options.trim_final_newlines),
+    }
+}
+
+/// Whole lines a selection touches. A selection that ends at the start of
+/// a line does not include that line.
+const fn selected_lines(range: TextRange) -> TextRange {
+    let end = if range.end.character == 0 && range.end.line > range.start.line {
+        range.end.line
+    } else {
+        range.end.line.saturating_add(1)
+    };
+    TextRange::lines(range.start.line, end)
+}
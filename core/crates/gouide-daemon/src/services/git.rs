@@ -0,0 +1,750 @@
+//! Git service implementation.
+//!
+//! A diff compares a buffer's current text with the version of its file in
+//! the index or `HEAD`, read with [`file_content`]. A `WatchDiff` stream
+//! keeps that base text between changes and reads it again only when the
+//! file's git status changes, a repository's index or `HEAD` moves, or the
+//! buffer is renamed. Edits only diff the text again, and the line diff
+//! skips the lines before the first change and after the last.
+//!
+//! Staging, commits, branches and stashes are in [`source_control`].
+
+use std::path::Path;
+use std::sync::Arc;
+
+use gouide_git::{file_content, Revision};
+use gouide_protocol::git_service_server::GitService as GitServiceTrait;
+use gouide_protocol::{
+    commit_response, create_branch_response, get_diff_response, list_branches_response,
+    stage_files_response, stage_hunks_response, stash_pop_response, stash_push_response,
+    switch_branch_response, unstage_files_response, unstage_hunks_response, BufferDiff, BufferId,
+    CommitRequest, CommitResponse, CreateBranchRequest, CreateBranchResponse, DeltaType, Error,
+    FileId, GetDiffRequest, GetDiffResponse, GetDiffSuccess, ListBranchesRequest,
+    ListBranchesResponse, StageFilesRequest, StageFilesResponse, StageHunksRequest,
+    StageHunksResponse, StashPopRequest, StashPopResponse, StashPushRequest, StashPushResponse,
+    StreamMeta, SwitchBranchRequest, SwitchBranchResponse, UnstageFilesRequest,
+    UnstageFilesResponse, UnstageHunksRequest, UnstageHunksResponse, WatchBufferChangesResponse,
+    WatchDiffRequest, WatchDiffResponse,
+};
+use gouide_workspace::{line_hunks, WorkspaceManager};
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+use tonic::{Request, Response, Status};
+use tracing::{debug, warn};
+
+use super::convert::{from_proto_diff_base, to_proto_diff_base, to_proto_hunk};
+use super::errors::{error, git_error, invalid_argument, workspace_error};
+use super::stream::StreamSender;
+use super::tree::next_statuses;
+use super::{BufferSync, ResponseStream};
+use crate::git::{GitStatuses, StatusChanges};
+
+mod source_control;
+
+/// Error source label for this service.
+const SOURCE: &str = "git";
+
+/// Byte order mark some files start with, which buffers leave out.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Git service: diffs of open buffers and source control.
+pub struct GitService {
+    workspaces: Arc<WorkspaceManager>,
+    sync: Arc<BufferSync>,
+    git: Arc<GitStatuses>,
+}
+
+impl GitService {
+    /// Create a new git service.
+    pub const fn new(
+        workspaces: Arc<WorkspaceManager>,
+        sync: Arc<BufferSync>,
+        git: Arc<GitStatuses>,
+    ) -> Self {
+        Self {
+            workspaces,
+            sync,
+            git,
+        }
+    }
+
+    fn source(&self, buffer_id: String, revision: Revision, include_content: bool) -> DiffSource {
+        DiffSource {
+            workspaces: self.workspaces.clone(),
+            buffer_id,
+            revision,
+            include_content,
+        }
+    }
+}
+
+/// The text a buffer's file has in the revision diffed against.
+struct Base {
+    file_id: String,
+    /// Whether the revision has the file; the text is empty if not.
+    exists: bool,
+    text: String,
+}
+
+impl Base {
+    fn read(file_id: String, path: &Path, revision: Revision) -> Result<Self, Error> {
+        let content = file_content(path, revision).map_err(|e| git_error(&e, SOURCE))?;
+        Ok(Self {
+            file_id,
+            exists: content.is_some(),
+            text: content.map(decode).unwrap_or_default(),
+        })
+    }
+}
+
+/// Base content as buffer text: without a byte order mark, and with
+/// invalid UTF-8 replaced.
+fn decode(mut bytes: Vec<u8>) -> String {
+    if bytes.starts_with(UTF8_BOM) {
+        bytes.drain(..UTF8_BOM.len());
+    }
+    String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+/// Diffs of one buffer against one revision.
+#[derive(Clone)]
+struct DiffSource {
+    workspaces: Arc<WorkspaceManager>,
+    buffer_id: String,
+    revision: Revision,
+    include_content: bool,
+}
+
+impl DiffSource {
+    /// The buffer's current diff, and the base it was computed against.
+    /// `base` is reused if it is for the buffer's file; `None` reads it.
+    fn diff(&self, base: Option<Arc<Base>>) -> Result<(Arc<Base>, BufferDiff), Error> {
+        let shared = self
+            .workspaces
+            .buffer(&self.buffer_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let buffer = shared.read();
+        // Memory-mapped files are too large to compare line by line
+        if buffer.is_mapped() {
+            return Err(error(
+                "BUFFER_TOO_LARGE",
+                format!("{} is too large to diff", buffer.file_id()),
+                SOURCE,
+            ));
+        }
+        let (file_id, path) = (buffer.file_id().to_string(), buffer.path().to_path_buf());
+        let (version, text) = (buffer.version(), buffer.text());
+        drop(buffer);
+
+        let base = match base {
+            Some(base) if base.file_id == file_id => base,
+            _ => Arc::new(Base::read(file_id.clone(), &path, self.revision)?),
+        };
+        let hunks = line_hunks(&base.text, &text)
+            .iter()
+            .map(to_proto_hunk)
+            .collect();
+        let diff = BufferDiff {
+            buffer_id: Some(BufferId {
+                value: self.buffer_id.clone(),
+            }),
+            file_id: Some(FileId { path: file_id }),
+            base: to_proto_diff_base(self.revision) as i32,
+            version,
+            base_exists: base.exists,
+            hunks,
+            base_content: if self.include_content {
+                base.text.clone()
+            } else {
+                String::new()
+            },
+            buffer_content: if self.include_content {
+                text
+            } else {
+                String::new()
+            },
+        };
+        Ok((base, diff))
+    }
+
+    /// Compute a diff off the async runtime.
+    async fn diff_blocking(
+        &self,
+        base: Option<Arc<Base>>,
+    ) -> Result<(Arc<Base>, BufferDiff), Error> {
+        let source = self.clone();
+        tokio::task::spawn_blocking(move || source.diff(base))
+            .await
+            .map_err(|e| error("INTERNAL", e.to_string(), SOURCE))?
+    }
+}
+
+#[tonic::async_trait]
+impl GitServiceTrait for GitService {
+    type WatchDiffStream = ResponseStream<WatchDiffResponse>;
+
+    async fn get_diff(
+        &self,
+        request: Request<GetDiffRequest>,
+    ) -> Result<Response<GetDiffResponse>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let result = if buffer_id.is_empty() {
+            Err(invalid_argument("buffer_id is required", SOURCE))
+        } else {
+            let source = self.source(
+                buffer_id,
+                from_proto_diff_base(req.base),
+                req.include_content,
+            );
+            source.diff_blocking(None).await
+        };
+        let result = match result {
+            Ok((_, diff)) => {
+                debug!(
+                    buffer_id = ?diff.buffer_id.as_ref().map(|b| &b.value),
+                    hunks = diff.hunks.len(),
+                    "Buffer diffed"
+                );
+                get_diff_response::Result::Success(GetDiffSuccess { diff: Some(diff) })
+            }
+            Err(e) => get_diff_response::Result::Error(e),
+        };
+        Ok(Response::new(GetDiffResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn watch_diff(
+        &self,
+        request: Request<WatchDiffRequest>,
+    ) -> Result<Response<Self::WatchDiffStream>, Status> {
+        let req = request.into_inner();
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+
+        // Streams have no error envelope, so failures map to a status
+        let changes = self
+            .sync
+            .subscribe(&buffer_id)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        let workspace_id = self
+            .workspaces
+            .buffer(&buffer_id)
+            .map_err(|e| Status::not_found(e.to_string()))?
+            .read()
+            .workspace_id()
+            .to_string();
+        let statuses = self.git.subscribe(&workspace_id);
+        let source = self.source(
+            buffer_id,
+            from_proto_diff_base(req.base),
+            req.include_content,
+        );
+        let (base, first) =
+            source
+                .diff_blocking(None)
+                .await
+                .map_err(|e| match e.code.as_str() {
+                    "BUFFER_NOT_FOUND" => Status::not_found(e.user_message),
+                    "NOT_IN_REPOSITORY" | "BUFFER_TOO_LARGE" => {
+                        Status::failed_precondition(e.user_message)
+                    }
+                    _ => Status::internal(e.user_message),
+                })?;
+        debug!(buffer_id = %source.buffer_id, "Watching diff");
+        Ok(Response::new(watch_diff(
+            source, base, first, changes, statuses,
+        )))
+    }
+
+    async fn stage_files(
+        &self,
+        request: Request<StageFilesRequest>,
+    ) -> Result<Response<StageFilesResponse>, Status> {
+        let result = match self.stage_files(request.into_inner()).await {
+            Ok(success) => stage_files_response::Result::Success(success),
+            Err(error) => stage_files_response::Result::Error(error),
+        };
+        Ok(Response::new(StageFilesResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn unstage_files(
+        &self,
+        request: Request<UnstageFilesRequest>,
+    ) -> Result<Response<UnstageFilesResponse>, Status> {
+        let result = match self.unstage_files(request.into_inner()).await {
+            Ok(success) => unstage_files_response::Result::Success(success),
+            Err(error) => unstage_files_response::Result::Error(error),
+        };
+        Ok(Response::new(UnstageFilesResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn stage_hunks(
+        &self,
+        request: Request<StageHunksRequest>,
+    ) -> Result<Response<StageHunksResponse>, Status> {
+        let result = match self.stage_hunks(request.into_inner()).await {
+            Ok(success) => stage_hunks_response::Result::Success(success),
+            Err(error) => stage_hunks_response::Result::Error(error),
+        };
+        Ok(Response::new(StageHunksResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn unstage_hunks(
+        &self,
+        request: Request<UnstageHunksRequest>,
+    ) -> Result<Response<UnstageHunksResponse>, Status> {
+        let result = match self.unstage_hunks(request.into_inner()).await {
+            Ok(success) => unstage_hunks_response::Result::Success(success),
+            Err(error) => unstage_hunks_response::Result::Error(error),
+        };
+        Ok(Response::new(UnstageHunksResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn commit(
+        &self,
+        request: Request<CommitRequest>,
+    ) -> Result<Response<CommitResponse>, Status> {
+        let result = match self.commit(request.into_inner()).await {
+            Ok(success) => commit_response::Result::Success(success),
+            Err(error) => commit_response::Result::Error(error),
+        };
+        Ok(Response::new(CommitResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn list_branches(
+        &self,
+        request: Request<ListBranchesRequest>,
+    ) -> Result<Response<ListBranchesResponse>, Status> {
+        let result = match self.list_branches(request.into_inner()).await {
+            Ok(success) => list_branches_response::Result::Success(success),
+            Err(error) => list_branches_response::Result::Error(error),
+        };
+        Ok(Response::new(ListBranchesResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn create_branch(
+        &self,
+        request: Request<CreateBranchRequest>,
+    ) -> Result<Response<CreateBranchResponse>, Status> {
+        let result = match self.create_branch(request.into_inner()).await {
+            Ok(success) => create_branch_response::Result::Success(success),
+            Err(error) => create_branch_response::Result::Error(error),
+        };
+        Ok(Response::new(CreateBranchResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn switch_branch(
+        &self,
+        request: Request<SwitchBranchRequest>,
+    ) -> Result<Response<SwitchBranchResponse>, Status> {
+        let result = match self.switch_branch(request.into_inner()).await {
+            Ok(success) => switch_branch_response::Result::Success(success),
+            Err(error) => switch_branch_response::Result::Error(error),
+        };
+        Ok(Response::new(SwitchBranchResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn stash_push(
+        &self,
+        request: Request<StashPushRequest>,
+    ) -> Result<Response<StashPushResponse>, Status> {
+        let result = match self.stash_push(request.into_inner()).await {
+            Ok(success) => stash_push_response::Result::Success(success),
+            Err(error) => stash_push_response::Result::Error(error),
+        };
+        Ok(Response::new(StashPushResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn stash_pop(
+        &self,
+        request: Request<StashPopRequest>,
+    ) -> Result<Response<StashPopResponse>, Status> {
+        let result = match self.stash_pop(request.into_inner()).await {
+            Ok(success) => stash_pop_response::Result::Success(success),
+            Err(error) => stash_pop_response::Result::Error(error),
+        };
+        Ok(Response::new(StashPopResponse {
+            result: Some(result),
+        }))
+    }
+}
+
+/// Stream a buffer's diff, starting with `first`, which was computed
+/// against `base`.
+fn watch_diff(
+    source: DiffSource,
+    base: Arc<Base>,
+    first: BufferDiff,
+    changes: broadcast::Receiver<WatchBufferChangesResponse>,
+    statuses: Option<broadcast::Receiver<StatusChanges>>,
+) -> ResponseStream<WatchDiffResponse> {
+    let (sender, stream) = StreamSender::channel();
+    tokio::spawn(run(sender, source, base, first, changes, statuses));
+    stream
+}
+
+async fn run(
+    mut sender: StreamSender<WatchDiffResponse>,
+    source: DiffSource,
+    base: Arc<Base>,
+    first: BufferDiff,
+    mut changes: broadcast::Receiver<WatchBufferChangesResponse>,
+    mut statuses: Option<broadcast::Receiver<StatusChanges>>,
+) {
+    let stream_id = sender.stream_id().to_string();
+    let mut sent = first.hunks.clone();
+    if !sender.send(message(DeltaType::Snapshot, first)).await {
+        return;
+    }
+
+    let mut base = Some(base);
+    'stream: loop {
+        // Wait for a change that can change the diff
+        loop {
+            tokio::select! {
+                changed = changes.recv() => match changed {
+                    Ok(_) | Err(RecvError::Lagged(_)) => break,
+                    Err(RecvError::Closed) => break 'stream,
+                },
+                change = next_statuses(&mut statuses) => match change {
+                    Ok(change) => {
+                        let file_id = base.as_ref().map(|base| base.file_id.as_str());
+                        if change.revised
+                            || file_id.is_some_and(|id| change.paths.iter().any(|p| p == id))
+                        {
+                            base = None;
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {
+                        base = None;
+                        break;
+                    }
+                    Err(RecvError::Closed) => statuses = None,
+                },
+                () = sender.closed() => break 'stream,
+            }
+        }
+        // Edits queued up meanwhile are covered by this diff
+        loop {
+            match changes.try_recv() {
+                Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => break 'stream,
+            }
+        }
+
+        let diff = match source.diff_blocking(base.clone()).await {
+            Ok((read, diff)) => {
+                base = Some(read);
+                diff
+            }
+            Err(e) if e.code == "BUFFER_NOT_FOUND" => break,
+            Err(e) => {
+                // Git may fail mid-operation; the next change tries again
+                warn!(buffer_id = %source.buffer_id, error = %e.user_message, "Diff failed");
+                continue;
+            }
+        };
+        if !source.include_content && diff.hunks == sent {
+            continue;
+        }
+        sent = diff.hunks.clone();
+        if !sender.send(message(DeltaType::Update, diff)).await {
+            break;
+        }
+    }
+    debug!(stream_id = %stream_id, "Diff stream ended");
+}
+
+fn message(delta_type: DeltaType, diff: BufferDiff) -> WatchDiffResponse {
+    WatchDiffResponse {
+        meta: Some(StreamMeta {
+            delta_type: delta_type as i32,
+            ..StreamMeta::default()
+        }),
+        diff: Some(diff),
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+    use std::time::Duration;
+
+    use gouide_fs::{FsEvent, FsEventKind};
+    use gouide_protocol::{DiffBase, DiffHunk, DiffHunkKind};
+    use gouide_workspace::{BufferLimits, Position, TextEdit, TextRange};
+    use tempfile::TempDir;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+
+    pub(super) fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    /// A repository with `a.txt` committed on `main`, opened as a
+    /// workspace.
+    pub(super) fn setup(dir: &TempDir) -> (GitService, String) {
+        setup_with_limits(dir, BufferLimits::default())
+    }
+
+    fn setup_with_limits(dir: &TempDir, limits: BufferLimits) -> (GitService, String) {
+        git(dir.path(), &["init", "-q", "-b", "main"]);
+        // For the commits the service makes
+        git(dir.path(), &["config", "user.name", "Test"]);
+        git(dir.path(), &["config", "user.email", "test@example.com"]);
+        fs::write(dir.path().join("a.txt"), "one\ntwo\nthree\n").unwrap();
+        git(dir.path(), &["add", "-A"]);
+        git(dir.path(), &["commit", "-q", "-m", "initial"]);
+
+        let workspaces = Arc::new(WorkspaceManager::with_limits(limits));
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let sync = Arc::new(BufferSync::new(
+            workspaces.clone(),
+            Duration::from_millis(10),
+            8,
+        ));
+        let statuses = Arc::new(GitStatuses::new(8));
+        statuses.open_workspace(&workspace);
+        let service = GitService::new(workspaces, sync, statuses);
+        (service, workspace.id().to_string())
+    }
+
+    pub(super) fn open(service: &GitService, workspace_id: &str, file_id: &str) -> String {
+        let buffer = service
+            .workspaces
+            .open_buffer(workspace_id, file_id, None, "")
+            .unwrap();
+        let id = buffer.read().id().to_string();
+        id
+    }
+
+    /// Replace a line's text, as a client edit.
+    pub(super) fn edit_line(
+        service: &GitService,
+        buffer_id: &str,
+        line: u32,
+        old: &str,
+        new: &str,
+    ) {
+        let shared = service.workspaces.buffer(buffer_id).unwrap();
+        let mut buffer = shared.write();
+        let end = Position::new(line, u32::try_from(old.len()).unwrap());
+        let edits = [TextEdit::new(
+            TextRange::new(Position::new(line, 0), end),
+            new,
+        )];
+        buffer.apply_edits(&edits).unwrap();
+        service.sync.publish_edits(&buffer, &edits, "");
+        drop(buffer);
+    }
+
+    async fn get_diff(
+        service: &GitService,
+        buffer_id: &str,
+        base: DiffBase,
+        include_content: bool,
+    ) -> Result<BufferDiff, Error> {
+        let response = service
+            .get_diff(Request::new(GetDiffRequest {
+                request_id: None,
+                buffer_id: Some(BufferId {
+                    value: buffer_id.to_string(),
+                }),
+                base: base as i32,
+                include_content,
+            }))
+            .await
+            .unwrap();
+        match response.into_inner().result.unwrap() {
+            get_diff_response::Result::Success(success) => Ok(success.diff.unwrap()),
+            get_diff_response::Result::Error(e) => Err(e),
+        }
+    }
+
+    pub(super) fn hunk(base: (u32, u32), buffer: (u32, u32), kind: DiffHunkKind) -> DiffHunk {
+        DiffHunk {
+            base_start: base.0,
+            base_count: base.1,
+            buffer_start: buffer.0,
+            buffer_count: buffer.1,
+            kind: kind as i32,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_diff_against_index_and_head() {
+        let dir = TempDir::new().unwrap();
+        let (service, workspace_id) = setup(&dir);
+        let buffer_id = open(&service, &workspace_id, "a.txt");
+        edit_line(&service, &buffer_id, 1, "two", "TWO");
+        fs::write(dir.path().join("a.txt"), "zero\none\ntwo\nthree\n").unwrap();
+        git(dir.path(), &["add", "a.txt"]);
+
+        let diff = get_diff(&service, &buffer_id, DiffBase::Head, true)
+            .await
+            .unwrap();
+        assert_eq!(diff.hunks, [hunk((1, 1), (1, 1), DiffHunkKind::Modified)]);
+        assert_eq!(diff.base, DiffBase::Head as i32);
+        assert_eq!(diff.version, 2);
+        assert!(diff.base_exists);
+        assert_eq!(diff.base_content, "one\ntwo\nthree\n");
+        assert_eq!(diff.buffer_content, "one\nTWO\nthree\n");
+
+        // The buffer's unsaved text is diffed, not the file on disk
+        let diff = get_diff(&service, &buffer_id, DiffBase::Unspecified, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            diff.hunks,
+            [
+                hunk((0, 1), (0, 0), DiffHunkKind::Deleted),
+                hunk((2, 1), (1, 1), DiffHunkKind::Modified),
+            ]
+        );
+        assert_eq!(diff.base, DiffBase::Index as i32);
+        assert!(diff.base_content.is_empty());
+
+        fs::write(dir.path().join("new.txt"), "a\nb\n").unwrap();
+        let new_id = open(&service, &workspace_id, "new.txt");
+        let diff = get_diff(&service, &new_id, DiffBase::Index, false)
+            .await
+            .unwrap();
+        assert!(!diff.base_exists);
+        assert_eq!(diff.hunks, [hunk((0, 0), (0, 2), DiffHunkKind::Added)]);
+
+        let error = get_diff(&service, "missing", DiffBase::Index, false)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, "BUFFER_NOT_FOUND");
+        let error = get_diff(&service, "", DiffBase::Index, false)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, "INVALID_ARGUMENT");
+
+        let outside = TempDir::new().unwrap();
+        fs::write(outside.path().join("b.txt"), "b\n").unwrap();
+        let workspace = service
+            .workspaces
+            .open_workspace(outside.path(), None, vec![])
+            .unwrap();
+        let outside_id = open(&service, workspace.id(), "b.txt");
+        let error = get_diff(&service, &outside_id, DiffBase::Index, false)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, "NOT_IN_REPOSITORY");
+    }
+
+    #[tokio::test]
+    async fn test_large_files_are_not_diffed() {
+        let dir = TempDir::new().unwrap();
+        let limits = BufferLimits {
+            large_file_threshold: 8,
+            ..BufferLimits::default()
+        };
+        let (service, workspace_id) = setup_with_limits(&dir, limits);
+        let buffer_id = open(&service, &workspace_id, "a.txt");
+
+        let error = get_diff(&service, &buffer_id, DiffBase::Index, false)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, "BUFFER_TOO_LARGE");
+        let request = WatchDiffRequest {
+            buffer_id: Some(BufferId { value: buffer_id }),
+            base: DiffBase::Index as i32,
+            include_content: false,
+        };
+        let Err(status) = service.watch_diff(Request::new(request)).await else {
+            panic!("Expected an error");
+        };
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    }
+
+    async fn next(stream: &mut ResponseStream<WatchDiffResponse>) -> WatchDiffResponse {
+        tokio::time::timeout(Duration::from_secs(10), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_watch_diff_follows_edits_and_staging() {
+        let dir = TempDir::new().unwrap();
+        let (service, workspace_id) = setup(&dir);
+        let buffer_id = open(&service, &workspace_id, "a.txt");
+        let request = WatchDiffRequest {
+            buffer_id: Some(BufferId {
+                value: buffer_id.clone(),
+            }),
+            base: DiffBase::Index as i32,
+            include_content: false,
+        };
+        let mut stream = service
+            .watch_diff(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = next(&mut stream).await;
+        assert_eq!(first.meta.unwrap().delta_type, DeltaType::Snapshot as i32);
+        assert!(first.diff.unwrap().hunks.is_empty());
+
+        edit_line(&service, &buffer_id, 2, "three", "3");
+        let update = next(&mut stream).await;
+        assert_eq!(update.meta.unwrap().delta_type, DeltaType::Update as i32);
+        let diff = update.diff.unwrap();
+        assert_eq!(diff.version, 2);
+        assert_eq!(diff.hunks, [hunk((2, 1), (2, 1), DiffHunkKind::Modified)]);
+
+        // Staging the same text leaves nothing to diff against the index
+        fs::write(dir.path().join("a.txt"), "one\ntwo\n3\n").unwrap();
+        git(dir.path(), &["add", "a.txt"]);
+        let event = FsEvent {
+            path: dir.path().join(".git/index"),
+            kind: FsEventKind::Modified,
+        };
+        service.git.files_changed(&workspace_id, &[event]);
+        let update = next(&mut stream).await;
+        assert!(update.diff.unwrap().hunks.is_empty());
+
+        service.sync.forget_buffers(&[buffer_id]);
+        assert!(stream.next().await.is_none());
+    }
+}
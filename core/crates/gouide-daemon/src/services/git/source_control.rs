@@ -0,0 +1,813 @@
+//! Source control: staging, commits, branches and stashes.
+//!
+//! Git runs on the blocking pool. Once an operation succeeds, its
+//! repository's index and `HEAD`, and the work tree files a checkout or
+//! stash touched, are handed to [`BufferSync`] as file events, the way the
+//! watcher would report them later. File trees, open buffers, git statuses
+//! and diff streams have caught up with the operation by the time the
+//! client hears back.
+//!
+//! Hunks are picked by overlap rather than matched exactly, so a client
+//! can pass a range of lines, and stage or unstage everything changed in
+//! it.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use gouide_fs::{FsEvent, FsEventKind};
+use gouide_git::{
+    branches, commit, create_branch, file_content, git_dir, stage, stage_content, stash_pop,
+    stash_push, switch_branch, toplevel, unstage, ChangeKind, Revision, WorkTreeChange,
+};
+use gouide_protocol::{
+    CommitRequest, CommitSuccess, CreateBranchRequest, CreateBranchSuccess, DiffHunk, Error,
+    FileId, ListBranchesRequest, ListBranchesSuccess, StageFilesRequest, StageFilesSuccess,
+    StageHunksRequest, StageHunksSuccess, StashPopRequest, StashPopSuccess, StashPushRequest,
+    StashPushSuccess, SwitchBranchRequest, SwitchBranchSuccess, UnstageFilesRequest,
+    UnstageFilesSuccess, UnstageHunksRequest, UnstageHunksSuccess, WorkspaceId,
+};
+use gouide_workspace::{apply_line_hunks, line_hunks, LineHunk, Workspace, WorkspaceManager};
+use tracing::{debug, info};
+
+use super::{decode, GitService, SOURCE, UTF8_BOM};
+use crate::services::convert::to_proto_branch;
+use crate::services::errors::{error, git_error, invalid_argument, workspace_error};
+
+/// What a git operation returned, and what it changed.
+struct Changed<T> {
+    value: T,
+    /// Repositories whose index or `HEAD` may have moved, by a directory in
+    /// their work tree.
+    repositories: Vec<PathBuf>,
+    /// Files changed in the work tree.
+    files: Vec<WorkTreeChange>,
+}
+
+impl<T> Changed<T> {
+    fn new(value: T, repository: PathBuf) -> Self {
+        Self {
+            value,
+            repositories: vec![repository],
+            files: Vec::new(),
+        }
+    }
+}
+
+impl GitService {
+    /// Stage files as they are on disk.
+    pub(super) async fn stage_files(
+        &self,
+        req: StageFilesRequest,
+    ) -> Result<StageFilesSuccess, Error> {
+        let (workspace, paths) = self.files(req.workspace_id, &req.file_ids)?;
+        self.run(&workspace, move || {
+            stage(&paths).map_err(|e| git_error(&e, SOURCE))?;
+            Ok(files_changed((), &paths))
+        })
+        .await?;
+        debug!(files = req.file_ids.len(), "Files staged");
+        Ok(StageFilesSuccess {})
+    }
+
+    /// Unstage files.
+    pub(super) async fn unstage_files(
+        &self,
+        req: UnstageFilesRequest,
+    ) -> Result<UnstageFilesSuccess, Error> {
+        let (workspace, paths) = self.files(req.workspace_id, &req.file_ids)?;
+        self.run(&workspace, move || {
+            unstage(&paths).map_err(|e| git_error(&e, SOURCE))?;
+            Ok(files_changed((), &paths))
+        })
+        .await?;
+        debug!(files = req.file_ids.len(), "Files unstaged");
+        Ok(UnstageFilesSuccess {})
+    }
+
+    /// Stage the buffer's changes under some hunks of its diff against the
+    /// index.
+    pub(super) async fn stage_hunks(
+        &self,
+        req: StageHunksRequest,
+    ) -> Result<StageHunksSuccess, Error> {
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        if buffer_id.is_empty() {
+            return Err(invalid_argument("buffer_id is required", SOURCE));
+        }
+        let shared = self
+            .workspaces
+            .buffer(&buffer_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let (workspace_id, path, text) = {
+            let buffer = shared.read();
+            if buffer.version() != req.version {
+                return Err(error(
+                    "VERSION_CONFLICT",
+                    format!(
+                        "Buffer is at version {}, not {}; diff it again",
+                        buffer.version(),
+                        req.version
+                    ),
+                    SOURCE,
+                ));
+            }
+            (
+                buffer.workspace_id().to_string(),
+                buffer.path().to_path_buf(),
+                buffer.text(),
+            )
+        };
+
+        let workspace = self.workspace(&workspace_id)?;
+        let wanted = req.hunks;
+        self.run(&workspace, move || {
+            let staged = file_content(&path, Revision::Index).map_err(|e| git_error(&e, SOURCE))?;
+            let bom = staged
+                .as_ref()
+                .is_some_and(|bytes| bytes.starts_with(UTF8_BOM));
+            let staged = staged.map(decode).unwrap_or_default();
+            let hunks: Vec<LineHunk> = line_hunks(&staged, &text)
+                .into_iter()
+                .filter(|hunk| wanted.iter().any(|w| overlaps(&hunk.new, buffer_lines(w))))
+                .collect();
+            if hunks.is_empty() {
+                return Err(no_hunks("unstaged"));
+            }
+            let text = apply_line_hunks(&staged, &text, &hunks);
+            stage_content(&path, &encode(text, bom)).map_err(|e| git_error(&e, SOURCE))?;
+            Ok(files_changed((), &[path]))
+        })
+        .await?;
+        debug!(buffer_id = %buffer_id, "Hunks staged");
+        Ok(StageHunksSuccess {})
+    }
+
+    /// Return the staged changes under some hunks of a buffer's diff against
+    /// `HEAD` to their `HEAD` version.
+    pub(super) async fn unstage_hunks(
+        &self,
+        req: UnstageHunksRequest,
+    ) -> Result<UnstageHunksSuccess, Error> {
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        if buffer_id.is_empty() {
+            return Err(invalid_argument("buffer_id is required", SOURCE));
+        }
+        let shared = self
+            .workspaces
+            .buffer(&buffer_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let (workspace_id, path) = {
+            let buffer = shared.read();
+            (
+                buffer.workspace_id().to_string(),
+                buffer.path().to_path_buf(),
+            )
+        };
+
+        let workspace = self.workspace(&workspace_id)?;
+        let wanted = req.hunks;
+        self.run(&workspace, move || {
+            let Some(staged) =
+                file_content(&path, Revision::Index).map_err(|e| git_error(&e, SOURCE))?
+            else {
+                return Err(no_hunks("staged"));
+            };
+            let committed =
+                file_content(&path, Revision::Head).map_err(|e| git_error(&e, SOURCE))?;
+            let bom = staged.starts_with(UTF8_BOM);
+            let in_head = committed.is_some();
+            let (staged, committed) = (decode(staged), committed.map(decode).unwrap_or_default());
+            // The staged changes, undone: from the index back to HEAD
+            let hunks: Vec<LineHunk> = line_hunks(&committed, &staged)
+                .into_iter()
+                .filter(|hunk| wanted.iter().any(|w| overlaps(&hunk.old, base_lines(w))))
+                .map(|hunk| LineHunk {
+                    old: hunk.new,
+                    new: hunk.old,
+                })
+                .collect();
+            if hunks.is_empty() {
+                return Err(no_hunks("staged"));
+            }
+            let text = apply_line_hunks(&staged, &committed, &hunks);
+            if text.is_empty() && !in_head {
+                // All of a newly added file: it is no longer added
+                unstage(std::slice::from_ref(&path)).map_err(|e| git_error(&e, SOURCE))?;
+            } else {
+                stage_content(&path, &encode(text, bom)).map_err(|e| git_error(&e, SOURCE))?;
+            }
+            Ok(files_changed((), &[path]))
+        })
+        .await?;
+        debug!(buffer_id = %buffer_id, "Hunks unstaged");
+        Ok(UnstageHunksSuccess {})
+    }
+
+    /// Commit what is staged.
+    pub(super) async fn commit(&self, req: CommitRequest) -> Result<CommitSuccess, Error> {
+        if req.message.trim().is_empty() && !req.amend {
+            return Err(invalid_argument("message is required", SOURCE));
+        }
+        let (workspace, dir) = self.repository(req.workspace_id, &req.repository)?;
+        let commit_id = self
+            .run(&workspace, move || {
+                let id =
+                    commit(&dir, &req.message, req.amend).map_err(|e| git_error(&e, SOURCE))?;
+                Ok(Changed::new(id, dir))
+            })
+            .await?
+            .0;
+        info!(commit_id = %commit_id, amend = req.amend, "Committed");
+        Ok(CommitSuccess { commit_id })
+    }
+
+    /// List a repository's local branches.
+    pub(super) async fn list_branches(
+        &self,
+        req: ListBranchesRequest,
+    ) -> Result<ListBranchesSuccess, Error> {
+        let (_, dir) = self.repository(req.workspace_id, &req.repository)?;
+        let branches = blocking(move || branches(&dir).map_err(|e| git_error(&e, SOURCE))).await?;
+        Ok(ListBranchesSuccess {
+            branches: branches.into_iter().map(to_proto_branch).collect(),
+        })
+    }
+
+    /// Create a branch, and switch to it if asked.
+    pub(super) async fn create_branch(
+        &self,
+        req: CreateBranchRequest,
+    ) -> Result<CreateBranchSuccess, Error> {
+        if req.name.is_empty() {
+            return Err(invalid_argument("name is required", SOURCE));
+        }
+        let (workspace, dir) = self.repository(req.workspace_id, &req.repository)?;
+        let workspaces = self.workspaces.clone();
+        let workspace_id = workspace.id().to_string();
+        let (name, switch) = (req.name.clone(), req.switch_to);
+        let start = (!req.start_point.is_empty()).then_some(req.start_point);
+        let ((), changed_files) = self
+            .run(&workspace, move || {
+                if switch {
+                    check_saved(&workspaces, &workspace_id, &dir)?;
+                }
+                create_branch(&dir, &name, start.as_deref()).map_err(|e| git_error(&e, SOURCE))?;
+                let files = if switch {
+                    switch_branch(&dir, &name).map_err(|e| git_error(&e, SOURCE))?
+                } else {
+                    Vec::new()
+                };
+                Ok(Changed {
+                    files,
+                    ..Changed::new((), dir)
+                })
+            })
+            .await?;
+        info!(branch = %req.name, switched = req.switch_to, "Branch created");
+        Ok(CreateBranchSuccess { changed_files })
+    }
+
+    /// Check out a local branch.
+    pub(super) async fn switch_branch(
+        &self,
+        req: SwitchBranchRequest,
+    ) -> Result<SwitchBranchSuccess, Error> {
+        if req.name.is_empty() {
+            return Err(invalid_argument("name is required", SOURCE));
+        }
+        let (workspace, dir) = self.repository(req.workspace_id, &req.repository)?;
+        let workspaces = self.workspaces.clone();
+        let workspace_id = workspace.id().to_string();
+        let name = req.name.clone();
+        let ((), changed_files) = self
+            .run(&workspace, move || {
+                if !req.force {
+                    check_saved(&workspaces, &workspace_id, &dir)?;
+                }
+                let files = switch_branch(&dir, &name).map_err(|e| git_error(&e, SOURCE))?;
+                Ok(Changed {
+                    files,
+                    ..Changed::new((), dir)
+                })
+            })
+            .await?;
+        info!(branch = %req.name, files = changed_files.len(), "Branch switched");
+        Ok(SwitchBranchSuccess { changed_files })
+    }
+
+    /// Stash local changes.
+    pub(super) async fn stash_push(
+        &self,
+        req: StashPushRequest,
+    ) -> Result<StashPushSuccess, Error> {
+        let (workspace, dir) = self.repository(req.workspace_id, &req.repository)?;
+        let workspaces = self.workspaces.clone();
+        let workspace_id = workspace.id().to_string();
+        let ((), changed_files) = self
+            .run(&workspace, move || {
+                check_saved(&workspaces, &workspace_id, &dir)?;
+                let files = stash_push(&dir, &req.message, req.include_untracked)
+                    .map_err(|e| git_error(&e, SOURCE))?;
+                Ok(Changed {
+                    files,
+                    ..Changed::new((), dir)
+                })
+            })
+            .await?;
+        info!(files = changed_files.len(), "Changes stashed");
+        Ok(StashPushSuccess { changed_files })
+    }
+
+    /// Apply the latest stash and drop it.
+    pub(super) async fn stash_pop(&self, req: StashPopRequest) -> Result<StashPopSuccess, Error> {
+        let (workspace, dir) = self.repository(req.workspace_id, &req.repository)?;
+        let workspaces = self.workspaces.clone();
+        let workspace_id = workspace.id().to_string();
+        let (conflicts, changed_files) = self
+            .run(&workspace, move || {
+                check_saved(&workspaces, &workspace_id, &dir)?;
+                let popped = stash_pop(&dir).map_err(|e| git_error(&e, SOURCE))?;
+                Ok(Changed {
+                    files: popped.changes,
+                    ..Changed::new(popped.conflicts, dir)
+                })
+            })
+            .await?;
+        let conflicts: Vec<FileId> = conflicts
+            .iter()
+            .filter_map(|path| workspace.file_id_of(path))
+            .map(|path| FileId { path })
+            .collect();
+        info!(
+            files = changed_files.len(),
+            conflicts = conflicts.len(),
+            "Stash popped"
+        );
+        Ok(StashPopSuccess {
+            changed_files,
+            conflicts,
+        })
+    }
+
+    fn workspace(&self, workspace_id: &str) -> Result<Arc<Workspace>, Error> {
+        self.workspaces
+            .workspace(workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))
+    }
+
+    /// A workspace and the absolute paths of files in it.
+    fn files(
+        &self,
+        workspace_id: Option<WorkspaceId>,
+        file_ids: &[FileId],
+    ) -> Result<(Arc<Workspace>, Vec<PathBuf>), Error> {
+        let workspace = self.workspace(&workspace_id.map(|w| w.value).unwrap_or_default())?;
+        if file_ids.is_empty() {
+            return Err(invalid_argument("file_ids is required", SOURCE));
+        }
+        let paths = file_ids
+            .iter()
+            .map(|file_id| workspace.resolve_path(&file_id.path))
+            .collect::<Result<_, _>>()
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        Ok((workspace, paths))
+    }
+
+    /// A workspace and the directory of a request's repository in it, the
+    /// root when none is given.
+    fn repository(
+        &self,
+        workspace_id: Option<WorkspaceId>,
+        repository: &str,
+    ) -> Result<(Arc<Workspace>, PathBuf), Error> {
+        let workspace = self.workspace(&workspace_id.map(|w| w.value).unwrap_or_default())?;
+        let dir = if repository.is_empty() {
+            workspace.root().to_path_buf()
+        } else {
+            workspace
+                .resolve_path(repository)
+                .map_err(|e| workspace_error(&e, SOURCE))?
+        };
+        Ok((workspace, dir))
+    }
+
+    /// Run a git operation on the blocking pool and report what it changed,
+    /// returning its value and the workspace files it changed on disk.
+    async fn run<T: Send + 'static>(
+        &self,
+        workspace: &Workspace,
+        operation: impl FnOnce() -> Result<Changed<T>, Error> + Send + 'static,
+    ) -> Result<(T, Vec<FileId>), Error> {
+        let (changed, git_dirs) = blocking(move || {
+            let changed = operation()?;
+            // The operation is done; without these, the watcher catches up
+            let mut git_dirs: Vec<PathBuf> = changed
+                .repositories
+                .iter()
+                .filter_map(|dir| git_dir(existing(dir)).ok())
+                .collect();
+            git_dirs.sort();
+            git_dirs.dedup();
+            Ok((changed, git_dirs))
+        })
+        .await?;
+
+        let mut events: Vec<FsEvent> = changed.files.iter().map(fs_event).collect();
+        for git_dir in git_dirs {
+            for entry in ["index", "HEAD"] {
+                events.push(FsEvent {
+                    path: git_dir.join(entry),
+                    kind: FsEventKind::Modified,
+                });
+            }
+        }
+        self.sync.files_changed(workspace.id(), &events);
+
+        let files = changed
+            .files
+            .iter()
+            .filter_map(|change| workspace.file_id_of(&change.path))
+            .map(|path| FileId { path })
+            .collect();
+        Ok((changed.value, files))
+    }
+}
+
+/// Changes to the index of the repositories holding some files.
+fn files_changed<T>(value: T, paths: &[PathBuf]) -> Changed<T> {
+    Changed {
+        value,
+        repositories: paths
+            .iter()
+            .map(|path| path.parent().unwrap_or(path).to_path_buf())
+            .collect(),
+        files: Vec::new(),
+    }
+}
+
+/// Refuse to change files on disk under a repository while buffers there
+/// have unsaved edits, which would then conflict with them.
+fn check_saved(workspaces: &WorkspaceManager, workspace_id: &str, dir: &Path) -> Result<(), Error> {
+    let toplevel = toplevel(dir).map_err(|e| git_error(&e, SOURCE))?;
+    let toplevel = toplevel.canonicalize().unwrap_or(toplevel);
+    let buffers = workspaces
+        .list_buffers(workspace_id)
+        .map_err(|e| workspace_error(&e, SOURCE))?;
+    let mut dirty: Vec<String> = buffers
+        .iter()
+        .filter_map(|shared| {
+            let buffer = shared.read();
+            (buffer.is_dirty() && buffer.path().starts_with(&toplevel))
+                .then(|| buffer.file_id().to_string())
+        })
+        .collect();
+    if dirty.is_empty() {
+        return Ok(());
+    }
+    dirty.sort();
+    Err(error(
+        "BUFFER_DIRTY",
+        format!(
+            "{} {} unsaved changes. Save or revert them first.",
+            dirty.join(", "),
+            if dirty.len() == 1 { "has" } else { "have" }
+        ),
+        SOURCE,
+    ))
+}
+
+/// The closest directory holding a path that still exists, where git can
+/// run.
+fn existing(path: &Path) -> &Path {
+    path.ancestors().find(|dir| dir.is_dir()).unwrap_or(path)
+}
+
+/// The file event the watcher would report for a change.
+fn fs_event(change: &WorkTreeChange) -> FsEvent {
+    let kind = if !change.path.exists() {
+        FsEventKind::Removed
+    } else if change.kind == ChangeKind::Added {
+        FsEventKind::Created
+    } else {
+        FsEventKind::Modified
+    };
+    FsEvent {
+        path: change.path.clone(),
+        kind,
+    }
+}
+
+/// Lines a hunk covers in the buffer.
+fn buffer_lines(hunk: &DiffHunk) -> Range<usize> {
+    let start = hunk.buffer_start as usize;
+    start..start + hunk.buffer_count as usize
+}
+
+/// Lines a hunk covers in the base.
+fn base_lines(hunk: &DiffHunk) -> Range<usize> {
+    let start = hunk.base_start as usize;
+    start..start + hunk.base_count as usize
+}
+
+/// Whether two line ranges overlap, an empty one standing for the line it
+/// starts on.
+fn overlaps(a: &Range<usize>, b: Range<usize>) -> bool {
+    let end = |range: &Range<usize>| range.end.max(range.start + 1);
+    a.start < end(&b) && b.start < end(a)
+}
+
+/// Buffer text as file content, with the byte order mark the file had.
+fn encode(text: String, bom: bool) -> Vec<u8> {
+    let mut bytes = if bom { UTF8_BOM.to_vec() } else { Vec::new() };
+    bytes.extend(text.into_bytes());
+    bytes
+}
+
+fn no_hunks(state: &str) -> Error {
+    error(
+        "HUNK_NOT_FOUND",
+        format!("No {state} changes in the given hunks"),
+        SOURCE,
+    )
+}
+
+/// Run git off the async runtime.
+async fn blocking<T: Send + 'static>(
+    run: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    tokio::task::spawn_blocking(run)
+        .await
+        .map_err(|e| error("INTERNAL", e.to_string(), SOURCE))?
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use gouide_protocol::{BufferChangeType, BufferId, DiffHunkKind, WatchBufferChangesResponse};
+    use tempfile::TempDir;
+    use tokio::sync::broadcast;
+
+    use super::super::tests::{edit_line, git, hunk, open, setup};
+    use super::*;
+
+    fn workspace_id(id: &str) -> WorkspaceId {
+        WorkspaceId {
+            value: id.to_string(),
+        }
+    }
+
+    fn buffer_id(id: &str) -> BufferId {
+        BufferId {
+            value: id.to_string(),
+        }
+    }
+
+    fn staged(dir: &TempDir, file_id: &str) -> Option<String> {
+        file_content(&dir.path().join(file_id), Revision::Index)
+            .unwrap()
+            .map(|bytes| String::from_utf8(bytes).unwrap())
+    }
+
+    async fn stage_hunks(
+        service: &GitService,
+        buffer: &str,
+        version: u64,
+        hunks: Vec<DiffHunk>,
+    ) -> Result<StageHunksSuccess, Error> {
+        service
+            .stage_hunks(StageHunksRequest {
+                request_id: None,
+                buffer_id: Some(buffer_id(buffer)),
+                version,
+                hunks,
+            })
+            .await
+    }
+
+    async fn unstage_hunks(
+        service: &GitService,
+        buffer: &str,
+        hunks: Vec<DiffHunk>,
+    ) -> Result<UnstageHunksSuccess, Error> {
+        service
+            .unstage_hunks(UnstageHunksRequest {
+                request_id: None,
+                buffer_id: Some(buffer_id(buffer)),
+                hunks,
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_stage_hunks_files_and_commit() {
+        let dir = TempDir::new().unwrap();
+        let (service, id) = setup(&dir);
+        let buffer = open(&service, &id, "a.txt");
+        edit_line(&service, &buffer, 0, "one", "ONE");
+        edit_line(&service, &buffer, 2, "three", "THREE");
+
+        // Only the second change, from the buffer rather than the disk
+        let second = hunk((2, 1), (2, 1), DiffHunkKind::Modified);
+        stage_hunks(&service, &buffer, 3, vec![second])
+            .await
+            .unwrap();
+        assert_eq!(staged(&dir, "a.txt").unwrap(), "one\ntwo\nTHREE\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "one\ntwo\nthree\n"
+        );
+        let error = stage_hunks(&service, &buffer, 3, vec![second])
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, "HUNK_NOT_FOUND");
+        let error = stage_hunks(&service, &buffer, 2, vec![second])
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, "VERSION_CONFLICT");
+
+        unstage_hunks(&service, &buffer, vec![second])
+            .await
+            .unwrap();
+        assert_eq!(staged(&dir, "a.txt").unwrap(), "one\ntwo\nthree\n");
+        let error = unstage_hunks(&service, &buffer, vec![second])
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, "HUNK_NOT_FOUND");
+
+        // A new file is added by staging all of it, and removed by
+        // unstaging all of it
+        fs::write(dir.path().join("new.txt"), "a\nb\n").unwrap();
+        let new = open(&service, &id, "new.txt");
+        let all = hunk((0, 0), (0, 2), DiffHunkKind::Added);
+        stage_hunks(&service, &new, 1, vec![all]).await.unwrap();
+        assert_eq!(staged(&dir, "new.txt").unwrap(), "a\nb\n");
+        unstage_hunks(&service, &new, vec![all]).await.unwrap();
+        assert_eq!(staged(&dir, "new.txt"), None);
+
+        let files = vec![FileId {
+            path: "new.txt".to_string(),
+        }];
+        service
+            .stage_files(StageFilesRequest {
+                request_id: None,
+                workspace_id: Some(workspace_id(&id)),
+                file_ids: files.clone(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(staged(&dir, "new.txt").unwrap(), "a\nb\n");
+        service
+            .unstage_files(UnstageFilesRequest {
+                request_id: None,
+                workspace_id: Some(workspace_id(&id)),
+                file_ids: files.clone(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(staged(&dir, "new.txt"), None);
+
+        let commit = |message: &str| CommitRequest {
+            workspace_id: Some(workspace_id(&id)),
+            message: message.to_string(),
+            ..CommitRequest::default()
+        };
+        let error = service.commit(commit("")).await.unwrap_err();
+        assert_eq!(error.code, "INVALID_ARGUMENT");
+        let error = service.commit(commit("nothing")).await.unwrap_err();
+        assert_eq!(error.code, "NOTHING_TO_COMMIT");
+        service
+            .stage_files(StageFilesRequest {
+                request_id: None,
+                workspace_id: Some(workspace_id(&id)),
+                file_ids: files,
+            })
+            .await
+            .unwrap();
+        let committed = service.commit(commit("add new.txt")).await.unwrap();
+        let branches = service
+            .list_branches(ListBranchesRequest {
+                workspace_id: Some(workspace_id(&id)),
+                ..ListBranchesRequest::default()
+            })
+            .await
+            .unwrap()
+            .branches;
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].name, "main");
+        assert_eq!(branches[0].commit_id, committed.commit_id);
+        assert_eq!(branches[0].subject, "add new.txt");
+    }
+
+    async fn change(
+        changes: &mut broadcast::Receiver<WatchBufferChangesResponse>,
+    ) -> WatchBufferChangesResponse {
+        tokio::time::timeout(Duration::from_secs(10), changes.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    fn file_ids(files: &[FileId]) -> Vec<&str> {
+        let mut ids: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_branch_switch_and_stash_reload_buffers() {
+        let dir = TempDir::new().unwrap();
+        let (service, id) = setup(&dir);
+        let switch = |name: &str, force: bool| SwitchBranchRequest {
+            workspace_id: Some(workspace_id(&id)),
+            name: name.to_string(),
+            force,
+            ..SwitchBranchRequest::default()
+        };
+
+        let created = service
+            .create_branch(CreateBranchRequest {
+                workspace_id: Some(workspace_id(&id)),
+                name: "topic".to_string(),
+                switch_to: true,
+                ..CreateBranchRequest::default()
+            })
+            .await
+            .unwrap();
+        assert!(created.changed_files.is_empty());
+        fs::write(dir.path().join("a.txt"), "topic\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "b\n").unwrap();
+        git(dir.path(), &["add", "-A"]);
+        git(dir.path(), &["commit", "-q", "-m", "topic"]);
+        let error = service
+            .create_branch(CreateBranchRequest {
+                workspace_id: Some(workspace_id(&id)),
+                name: "topic".to_string(),
+                ..CreateBranchRequest::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, "BRANCH_EXISTS");
+        let error = service.switch_branch(switch("missing", false)).await;
+        assert_eq!(error.unwrap_err().code, "BRANCH_NOT_FOUND");
+
+        // Unsaved edits would conflict with the checkout
+        let b = open(&service, &id, "b.txt");
+        edit_line(&service, &b, 0, "b", "B");
+        let error = service
+            .switch_branch(switch("main", false))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, "BUFFER_DIRTY");
+        assert!(error.user_message.contains("b.txt"));
+        service.workspaces.close_buffer(&b, "", None, true).unwrap();
+
+        let a = open(&service, &id, "a.txt");
+        let mut changes = service.sync.subscribe(&a).unwrap();
+        let mut files = service.sync.subscribe_files(&id).unwrap();
+        let switched = service.switch_branch(switch("main", false)).await.unwrap();
+        assert_eq!(file_ids(&switched.changed_files), ["a.txt", "b.txt"]);
+        let reloaded = change(&mut changes).await;
+        assert_eq!(reloaded.change_type, BufferChangeType::Modified as i32);
+        assert_eq!(reloaded.content, "one\ntwo\nthree\n");
+        let events = files.recv().await.unwrap();
+        assert!(events.iter().any(|event| {
+            event.path == dir.path().join("b.txt") && event.kind == FsEventKind::Removed
+        }));
+
+        fs::write(dir.path().join("a.txt"), "local\n").unwrap();
+        let stashed = service
+            .stash_push(StashPushRequest {
+                workspace_id: Some(workspace_id(&id)),
+                ..StashPushRequest::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(file_ids(&stashed.changed_files), ["a.txt"]);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "one\ntwo\nthree\n"
+        );
+        let pop = || StashPopRequest {
+            workspace_id: Some(workspace_id(&id)),
+            ..StashPopRequest::default()
+        };
+        let popped = service.stash_pop(pop()).await.unwrap();
+        assert_eq!(file_ids(&popped.changed_files), ["a.txt"]);
+        assert!(popped.conflicts.is_empty());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "local\n"
+        );
+        let error = service.stash_pop(pop()).await.unwrap_err();
+        assert_eq!(error.code, "NO_STASH");
+    }
+}
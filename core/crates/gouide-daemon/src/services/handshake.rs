@@ -5,17 +5,23 @@ use std::sync::Arc;
 use gouide_protocol::handshake_service_server::HandshakeService as HandshakeServiceTrait;
 use gouide_protocol::{
     establish_response, DisconnectRequest, DisconnectResponse, EstablishRequest, EstablishResponse,
-    PingRequest, PingResponse, Timestamp, Welcome,
+    PingRequest, PingResponse, Welcome,
 };
+use gouide_workspace::WorkspaceManager;
 use tonic::{Request, Response, Status};
 use tracing::info;
 
+use super::client_id;
+use super::convert::current_timestamp;
+use super::BufferSync;
 use crate::config::DaemonConfig;
 use crate::session::SessionManager;
 
 /// Handshake service for establishing client connections.
 pub struct HandshakeService {
     session_manager: Arc<SessionManager>,
+    workspaces: Arc<WorkspaceManager>,
+    sync: Arc<BufferSync>,
     config: Arc<DaemonConfig>,
     daemon_id: String,
 }
@@ -24,11 +30,15 @@ impl HandshakeService {
     /// Create a new handshake service.
     pub fn new(
         session_manager: Arc<SessionManager>,
+        workspaces: Arc<WorkspaceManager>,
+        sync: Arc<BufferSync>,
         config: Arc<DaemonConfig>,
         daemon_id: String,
     ) -> Self {
         Self {
             session_manager,
+            workspaces,
+            sync,
             config,
             daemon_id,
         }
@@ -89,12 +99,21 @@ impl HandshakeServiceTrait for HandshakeService {
         &self,
         request: Request<DisconnectRequest>,
     ) -> Result<Response<DisconnectResponse>, Status> {
+        let client_id = client_id(&request);
         let req = request.into_inner();
-        info!(reason = %req.reason, "Client disconnect request");
+        info!(client_id = %client_id, reason = %req.reason, "Client disconnect request");
 
-        // Note: In a full implementation, we'd extract the client_id from
-        // request metadata or a session context. For MVP, disconnect
-        // is a no-op since we don't track connection-to-session mapping yet.
+        // Drop the client's buffer holds; unsaved buffers survive for reconnect
+        let released = self.workspaces.release_session(&client_id);
+        self.sync.forget_buffers(&released);
+        if !client_id.is_empty() {
+            self.session_manager.unregister(&client_id).await;
+        }
+        info!(
+            client_id = %client_id,
+            released_buffers = released.len(),
+            "Client session released"
+        );
 
         Ok(Response::new(DisconnectResponse { success: true }))
     }
@@ -109,16 +128,6 @@ impl HandshakeServiceTrait for HandshakeService {
     }
 }
 
-/// Get the current timestamp.
-fn current_timestamp() -> Timestamp {
-    let now = chrono::Utc::now();
-    Timestamp {
-        seconds: now.timestamp(),
-        #[allow(clippy::cast_possible_wrap)]
-        nanos: now.timestamp_subsec_nanos() as i32,
-    }
-}
-
 #[cfg(test)]
 #[allow(
     clippy::unwrap_used,
@@ -127,13 +136,27 @@ fn current_timestamp() -> Timestamp {
     clippy::uninlined_format_args
 )]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
-    use gouide_protocol::Capabilities;
+    use gouide_protocol::{Capabilities, Timestamp};
 
     fn create_service() -> HandshakeService {
         let config = Arc::new(DaemonConfig::default());
         let session_manager = Arc::new(SessionManager::new((*config).clone()));
-        HandshakeService::new(session_manager, config, "test-daemon".to_string())
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let sync = Arc::new(BufferSync::new(
+            workspaces.clone(),
+            Duration::from_millis(config.watch_debounce_ms),
+            config.stream_capacity,
+        ));
+        HandshakeService::new(
+            session_manager,
+            workspaces,
+            sync,
+            config,
+            "test-daemon".to_string(),
+        )
     }
 
     fn test_hello(client_id: &str) -> EstablishRequest {
@@ -0,0 +1,1398 @@
+//! Language service implementation.
+//!
+//! Each request goes to the first language server the buffer is open in
+//! that advertises the feature; code actions are gathered from all of them
+//! and the built-in fixers. Requests are registered with the
+//! [`RequestTracker`] under the client's request ID; cancelling one drops
+//! the pending server request, which sends `$/cancelRequest` to the server.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use gouide_lsp::lsp_types::request::{
+    CodeActionRequest, CodeActionResolveRequest, Completion, ExecuteCommand, GotoDefinition,
+    HoverRequest as LspHover, References, Rename, SignatureHelpRequest,
+};
+use gouide_lsp::lsp_types::{
+    self, CodeActionKind, CodeActionOrCommand, CompletionItemTag, CompletionTextEdit,
+    DocumentChangeOperation, DocumentChanges, Documentation, GotoDefinitionResponse, HoverContents,
+    HoverProviderCapability, MarkedString, OneOf, ParameterLabel, ServerCapabilities,
+    TextDocumentIdentifier, TextDocumentPositionParams, Url, WorkspaceEdit,
+};
+use gouide_lsp::{LanguageServer, LspError, ServerStatus};
+use gouide_protocol::language_service_server::LanguageService as LanguageServiceTrait;
+use gouide_protocol::{
+    apply_code_action_response, find_references_response, get_code_actions_response,
+    go_to_definition_response, hover_response, rename_response, signature_help_response,
+    ApplyCodeActionRequest, ApplyCodeActionResponse, ApplyCodeActionSuccess, BufferId,
+    CompletionItem, CompletionItemKind, CompletionRequest, CompletionResponse,
+    CompletionTriggerKind, DeltaType, Error, FileEdits, FileId, FindReferencesRequest,
+    FindReferencesResponse, GetCodeActionsRequest, GetCodeActionsResponse, GetCodeActionsSuccess,
+    GoToDefinitionRequest, GoToDefinitionResponse, HoverRequest, HoverResponse, HoverSuccess,
+    Location, LocationsSuccess, MarkupContent, MarkupKind, ParameterInformation,
+    Position as ProtoPosition, RenameRequest, RenameResponse, RenameSuccess, RequestId,
+    SignatureHelpRequest as ProtoSignatureHelpRequest, SignatureHelpResponse, SignatureHelpSuccess,
+    SignatureInformation, StreamMeta, TextEdit as ProtoTextEdit,
+};
+use gouide_syntax::SyntaxManager;
+use gouide_workspace::{TextEdit, Workspace, WorkspaceManager};
+use tonic::{Request, Response, Status};
+use tracing::debug;
+
+use super::client_id;
+use super::convert::{from_proto_position, from_proto_range, to_proto_edit, to_proto_range};
+use super::edits::{Change, WorkspaceEditor};
+use super::errors::{error, invalid_argument, lsp_error, workspace_error};
+use super::lsp::{enabled, end_to_start, file_id, from_lsp_range, to_lsp_position, to_lsp_range};
+use super::stream::StreamSender;
+use super::{BufferSync, LspBridge, ResponseStream};
+use crate::fixers::Fixer;
+use crate::requests::{RequestGuard, RequestTracker};
+
+mod code_actions;
+
+use code_actions::{
+    kind_of, matches_only, offers_code_actions, resolves_code_actions, to_changes,
+    to_lsp_diagnostic, to_proto_action, Action, CodeActions,
+};
+
+/// Error source label for this service.
+const SOURCE: &str = "language";
+
+/// Completion items per streamed message.
+const COMPLETION_BATCH: usize = 100;
+
+/// Language service for language-server-backed intelligence.
+pub struct LanguageService {
+    workspaces: Arc<WorkspaceManager>,
+    lsp: Arc<LspBridge>,
+    requests: Arc<RequestTracker>,
+    editor: WorkspaceEditor,
+    actions: Arc<CodeActions>,
+}
+
+/// A request resolved to its server and registered for cancellation.
+struct Pending {
+    server: LanguageServer,
+    workspace: Arc<Workspace>,
+    position: TextDocumentPositionParams,
+    guard: RequestGuard,
+}
+
+impl Pending {
+    /// Send the request, giving up if the client cancels it.
+    async fn send<R: lsp_types::request::Request>(
+        &mut self,
+        params: R::Params,
+    ) -> Result<R::Result, Error> {
+        send::<R>(&self.server, &mut self.guard, params).await
+    }
+}
+
+/// Send a request to a server, giving up if the client cancels it.
+async fn send<R: lsp_types::request::Request>(
+    server: &LanguageServer,
+    guard: &mut RequestGuard,
+    params: R::Params,
+) -> Result<R::Result, Error> {
+    tokio::select! {
+        result = server.request::<R>(params) => result.map_err(|e| lsp_error(&e, SOURCE)),
+        () = guard.cancelled() => Err(cancelled()),
+    }
+}
+
+impl LanguageService {
+    /// Create a new language service.
+    pub fn new(
+        workspaces: Arc<WorkspaceManager>,
+        sync: Arc<BufferSync>,
+        syntax: Arc<SyntaxManager>,
+        lsp: Arc<LspBridge>,
+        requests: Arc<RequestTracker>,
+    ) -> Self {
+        let actions = Arc::new(CodeActions::default());
+        let offered = actions.clone();
+        sync.on_buffer_closed(move |buffer_id| offered.forget(buffer_id));
+        Self {
+            editor: WorkspaceEditor::new(workspaces.clone(), syntax, sync),
+            workspaces,
+            lsp,
+            requests,
+            actions,
+        }
+    }
+
+    /// Resolve the server that answers `feature` requests at a buffer
+    /// position, and register the request.
+    fn prepare(
+        &self,
+        request_id: Option<RequestId>,
+        buffer_id: Option<BufferId>,
+        position: Option<&ProtoPosition>,
+        feature: &str,
+        supports: fn(&ServerCapabilities) -> bool,
+    ) -> Result<Pending, Error> {
+        let buffer_id = buffer_id.map(|b| b.value).unwrap_or_default();
+        let Some(position) = position else {
+            return Err(invalid_argument("position is required", SOURCE));
+        };
+        let shared = self
+            .workspaces
+            .buffer(&buffer_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let (workspace_id, file) = {
+            let buffer = shared.read();
+            (
+                buffer.workspace_id().to_string(),
+                buffer.file_id().to_string(),
+            )
+        };
+        let workspace = self
+            .workspaces
+            .workspace(&workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+
+        let Some((uri, servers)) = self
+            .lsp
+            .document(&buffer_id)
+            .filter(|(_, servers)| !servers.is_empty())
+        else {
+            return Err(error(
+                "NO_LANGUAGE_SERVER",
+                format!("No language server is configured for {file}"),
+                SOURCE,
+            ));
+        };
+        let server = servers
+            .iter()
+            .find(|server| server.capabilities().is_some_and(|c| supports(&c)));
+        let Some(server) = server else {
+            // Capabilities are only known once a server is running
+            if let Some(server) = servers
+                .iter()
+                .find(|server| server.status() != ServerStatus::Running)
+            {
+                return Err(lsp_error(
+                    &LspError::NotRunning(server.name().to_string()),
+                    SOURCE,
+                ));
+            }
+            return Err(error(
+                "NOT_SUPPORTED",
+                format!("The language server for {file} does not support {feature}"),
+                SOURCE,
+            ));
+        };
+
+        Ok(Pending {
+            server: server.clone(),
+            workspace,
+            position: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri),
+                to_lsp_position(from_proto_position(Some(position))),
+            ),
+            guard: self
+                .requests
+                .register(&request_id.map(|r| r.value).unwrap_or_default()),
+        })
+    }
+
+    /// Gather a buffer's code actions from its language servers and the
+    /// built-in fixers, and offer them for the buffer.
+    async fn code_actions(
+        &self,
+        req: GetCodeActionsRequest,
+    ) -> Result<GetCodeActionsSuccess, Error> {
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let Some(range) = req.range.as_ref() else {
+            return Err(invalid_argument("range is required", SOURCE));
+        };
+        let shared = self
+            .workspaces
+            .buffer(&buffer_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let (workspace_id, version, fixers) = {
+            let buffer = shared.read();
+            // Large files are read-only, so there is nothing to fix
+            let fixers: Vec<Fixer> = if buffer.is_mapped() {
+                Vec::new()
+            } else {
+                let text = buffer.text();
+                Fixer::ALL
+                    .into_iter()
+                    .filter(|fixer| fixer.applies_to(&text))
+                    .collect()
+            };
+            (buffer.workspace_id().to_string(), buffer.version(), fixers)
+        };
+        let workspace = self
+            .workspaces
+            .workspace(&workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let mut guard = self
+            .requests
+            .register(&req.request_id.map(|r| r.value).unwrap_or_default());
+
+        let mut actions = Vec::new();
+        if let Some((uri, servers)) = self.lsp.document(&buffer_id) {
+            let context = lsp_types::CodeActionContext {
+                diagnostics: req.diagnostics.iter().map(to_lsp_diagnostic).collect(),
+                only: (!req.only.is_empty())
+                    .then(|| req.only.iter().cloned().map(CodeActionKind::from).collect()),
+                trigger_kind: Some(lsp_types::CodeActionTriggerKind::INVOKED),
+            };
+            let servers = servers.iter().filter(|server| {
+                server
+                    .capabilities()
+                    .is_some_and(|c| offers_code_actions(&c))
+            });
+            for server in servers {
+                let params = lsp_types::CodeActionParams {
+                    text_document: TextDocumentIdentifier::new(uri.clone()),
+                    range: to_lsp_range(from_proto_range(range)),
+                    context: context.clone(),
+                    work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+                    partial_result_params: lsp_types::PartialResultParams::default(),
+                };
+                match send::<CodeActionRequest>(server, &mut guard, params).await {
+                    Ok(response) => actions.extend(
+                        response
+                            .into_iter()
+                            .flatten()
+                            .filter(|action| matches_only(kind_of(action), &req.only))
+                            .map(|action| Action::Lsp {
+                                server: server.clone(),
+                                action: Box::new(action),
+                            }),
+                    ),
+                    Err(error) if guard.is_cancelled() => return Err(error),
+                    // One failing server does not hide the others' actions
+                    Err(error) => debug!(
+                        server = %server.name(),
+                        error = %error.user_message,
+                        "Code action request failed"
+                    ),
+                }
+            }
+        }
+        if matches_only(Fixer::KIND, &req.only) {
+            actions.extend(fixers.into_iter().map(Action::Builtin));
+        }
+
+        let mut offered: Vec<_> = actions
+            .iter()
+            .map(|action| to_proto_action(&workspace, action))
+            .collect();
+        let ids = self.actions.offer(&buffer_id, version, actions);
+        for (action, id) in offered.iter_mut().zip(ids) {
+            action.id = id;
+        }
+        Ok(GetCodeActionsSuccess {
+            actions: offered,
+            version,
+        })
+    }
+
+    /// Apply an offered code action: its edits first, then its command.
+    async fn run_code_action(
+        &self,
+        req: ApplyCodeActionRequest,
+        session: &str,
+    ) -> Result<ApplyCodeActionSuccess, Error> {
+        let buffer_id = req.buffer_id.map(|b| b.value).unwrap_or_default();
+        let shared = self
+            .workspaces
+            .buffer(&buffer_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let Some((version, action)) = self.actions.take(&buffer_id, &req.action_id) else {
+            return Err(error(
+                "CODE_ACTION_NOT_FOUND",
+                "The code action is no longer available. Request code actions again.",
+                SOURCE,
+            ));
+        };
+        let (workspace_id, file) = {
+            let buffer = shared.read();
+            (
+                buffer.workspace_id().to_string(),
+                buffer.file_id().to_string(),
+            )
+        };
+        let workspace = self
+            .workspaces
+            .workspace(&workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+
+        let (server, action) = match action {
+            Action::Builtin(fixer) => {
+                // Computed now, so later edits to the buffer are fixed too
+                let edits = {
+                    let buffer = shared.read();
+                    fixer.edits(&buffer.text(), buffer.line_ending().as_str())
+                };
+                let changes = if edits.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![Change::Edit {
+                        file_id: file,
+                        version: 0,
+                        edits,
+                    }]
+                };
+                let changes = self.editor.apply(&workspace, changes, session, SOURCE)?;
+                return Ok(ApplyCodeActionSuccess { changes });
+            }
+            Action::Lsp { server, action } => (server, *action),
+        };
+
+        let mut guard = self
+            .requests
+            .register(&req.request_id.map(|r| r.value).unwrap_or_default());
+        let (edit, command) = match action {
+            CodeActionOrCommand::Command(command) => (None, Some(command)),
+            CodeActionOrCommand::CodeAction(action) => {
+                if let Some(disabled) = &action.disabled {
+                    return Err(error(
+                        "CODE_ACTION_DISABLED",
+                        disabled.reason.clone(),
+                        SOURCE,
+                    ));
+                }
+                // Servers may leave the edit out until the action is chosen
+                let action = if action.edit.is_none()
+                    && server
+                        .capabilities()
+                        .is_some_and(|c| resolves_code_actions(&c))
+                {
+                    send::<CodeActionResolveRequest>(&server, &mut guard, action).await?
+                } else {
+                    action
+                };
+                (action.edit, action.command)
+            }
+        };
+
+        let changes = match edit {
+            Some(edit) => to_changes(&workspace, edit, &file, version)?,
+            None => Vec::new(),
+        };
+        let changes = self.editor.apply(&workspace, changes, session, SOURCE)?;
+        if let Some(command) = command {
+            let params = lsp_types::ExecuteCommandParams {
+                command: command.command,
+                arguments: command.arguments.unwrap_or_default(),
+                work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            };
+            send::<ExecuteCommand>(&server, &mut guard, params).await?;
+        }
+        Ok(ApplyCodeActionSuccess { changes })
+    }
+}
+
+#[tonic::async_trait]
+impl LanguageServiceTrait for LanguageService {
+    type CompletionStream = ResponseStream<CompletionResponse>;
+
+    async fn hover(
+        &self,
+        request: Request<HoverRequest>,
+    ) -> Result<Response<HoverResponse>, Status> {
+        let req = request.into_inner();
+        let result = async {
+            let mut pending = self.prepare(
+                req.request_id,
+                req.buffer_id,
+                req.position.as_ref(),
+                "hover",
+                |c| {
+                    matches!(
+                        c.hover_provider,
+                        Some(
+                            HoverProviderCapability::Simple(true)
+                                | HoverProviderCapability::Options(_)
+                        )
+                    )
+                },
+            )?;
+            let params = lsp_types::HoverParams {
+                text_document_position_params: pending.position.clone(),
+                work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            };
+            pending.send::<LspHover>(params).await.map(to_hover)
+        }
+        .await;
+
+        let result = match result {
+            Ok(success) => hover_response::Result::Success(success),
+            Err(error) => hover_response::Result::Error(error),
+        };
+        Ok(Response::new(HoverResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn completion(
+        &self,
+        request: Request<CompletionRequest>,
+    ) -> Result<Response<Self::CompletionStream>, Status> {
+        let req = request.into_inner();
+        let context = completion_context(req.trigger_kind, req.trigger_character);
+        // Registered before returning, so a Cancel right after the call finds
+        // the request
+        let pending = self.prepare(
+            req.request_id,
+            req.buffer_id,
+            req.position.as_ref(),
+            "completion",
+            |c| c.completion_provider.is_some(),
+        );
+
+        let (sender, stream) = StreamSender::channel();
+        tokio::spawn(async move {
+            match pending {
+                Ok(pending) => stream_completion(pending, context, sender).await,
+                Err(error) => {
+                    finish_completion(sender, error).await;
+                }
+            }
+        });
+        Ok(Response::new(stream))
+    }
+
+    async fn signature_help(
+        &self,
+        request: Request<ProtoSignatureHelpRequest>,
+    ) -> Result<Response<SignatureHelpResponse>, Status> {
+        let req = request.into_inner();
+        let result = async {
+            let mut pending = self.prepare(
+                req.request_id,
+                req.buffer_id,
+                req.position.as_ref(),
+                "signature help",
+                |c| c.signature_help_provider.is_some(),
+            )?;
+            let params = lsp_types::SignatureHelpParams {
+                context: None,
+                text_document_position_params: pending.position.clone(),
+                work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            };
+            pending
+                .send::<SignatureHelpRequest>(params)
+                .await
+                .map(to_signature_help)
+        }
+        .await;
+
+        let result = match result {
+            Ok(success) => signature_help_response::Result::Success(success),
+            Err(error) => signature_help_response::Result::Error(error),
+        };
+        Ok(Response::new(SignatureHelpResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn go_to_definition(
+        &self,
+        request: Request<GoToDefinitionRequest>,
+    ) -> Result<Response<GoToDefinitionResponse>, Status> {
+        let req = request.into_inner();
+        let result = async {
+            let mut pending = self.prepare(
+                req.request_id,
+                req.buffer_id,
+                req.position.as_ref(),
+                "go to definition",
+                |c| enabled(c.definition_provider.as_ref()),
+            )?;
+            let params = lsp_types::GotoDefinitionParams {
+                text_document_position_params: pending.position.clone(),
+                work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+                partial_result_params: lsp_types::PartialResultParams::default(),
+            };
+            let response = pending.send::<GotoDefinition>(params).await?;
+            Ok(LocationsSuccess {
+                locations: to_definitions(&pending.workspace, response),
+            })
+        }
+        .await;
+
+        let result = match result {
+            Ok(success) => go_to_definition_response::Result::Success(success),
+            Err(error) => go_to_definition_response::Result::Error(error),
+        };
+        Ok(Response::new(GoToDefinitionResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn find_references(
+        &self,
+        request: Request<FindReferencesRequest>,
+    ) -> Result<Response<FindReferencesResponse>, Status> {
+        let req = request.into_inner();
+        let result = async {
+            let mut pending = self.prepare(
+                req.request_id,
+                req.buffer_id,
+                req.position.as_ref(),
+                "find references",
+                |c| enabled(c.references_provider.as_ref()),
+            )?;
+            let params = lsp_types::ReferenceParams {
+                text_document_position: pending.position.clone(),
+                work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+                partial_result_params: lsp_types::PartialResultParams::default(),
+                context: lsp_types::ReferenceContext {
+                    include_declaration: req.include_declaration,
+                },
+            };
+            let references = pending.send::<References>(params).await?;
+            Ok(LocationsSuccess {
+                locations: references
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|l| to_location(&pending.workspace, &l.uri, l.range))
+                    .collect(),
+            })
+        }
+        .await;
+
+        let result = match result {
+            Ok(success) => find_references_response::Result::Success(success),
+            Err(error) => find_references_response::Result::Error(error),
+        };
+        Ok(Response::new(FindReferencesResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn rename(
+        &self,
+        request: Request<RenameRequest>,
+    ) -> Result<Response<RenameResponse>, Status> {
+        let req = request.into_inner();
+        let result = async {
+            if req.new_name.is_empty() {
+                return Err(invalid_argument("new_name is required", SOURCE));
+            }
+            let mut pending = self.prepare(
+                req.request_id,
+                req.buffer_id,
+                req.position.as_ref(),
+                "rename",
+                |c| enabled(c.rename_provider.as_ref()),
+            )?;
+            let params = lsp_types::RenameParams {
+                text_document_position: pending.position.clone(),
+                new_name: req.new_name,
+                work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            };
+            let edit = pending.send::<Rename>(params).await?;
+            Ok(RenameSuccess {
+                changes: to_file_edits(&pending.workspace, edit)?,
+            })
+        }
+        .await;
+
+        let result = match result {
+            Ok(success) => {
+                debug!(files = success.changes.len(), "Rename computed");
+                rename_response::Result::Success(success)
+            }
+            Err(error) => rename_response::Result::Error(error),
+        };
+        Ok(Response::new(RenameResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_code_actions(
+        &self,
+        request: Request<GetCodeActionsRequest>,
+    ) -> Result<Response<GetCodeActionsResponse>, Status> {
+        let result = match self.code_actions(request.into_inner()).await {
+            Ok(success) => get_code_actions_response::Result::Success(success),
+            Err(error) => get_code_actions_response::Result::Error(error),
+        };
+        Ok(Response::new(GetCodeActionsResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn apply_code_action(
+        &self,
+        request: Request<ApplyCodeActionRequest>,
+    ) -> Result<Response<ApplyCodeActionResponse>, Status> {
+        let session = client_id(&request);
+        let result = match self.run_code_action(request.into_inner(), &session).await {
+            Ok(success) => {
+                debug!(changes = success.changes.len(), "Code action applied");
+                apply_code_action_response::Result::Success(success)
+            }
+            Err(error) => apply_code_action_response::Result::Error(error),
+        };
+        Ok(Response::new(ApplyCodeActionResponse {
+            result: Some(result),
+        }))
+    }
+}
+
+/// Run a completion request and stream its items in batches.
+async fn stream_completion(
+    mut pending: Pending,
+    context: lsp_types::CompletionContext,
+    mut sender: StreamSender<CompletionResponse>,
+) {
+    let params = lsp_types::CompletionParams {
+        text_document_position: pending.position.clone(),
+        work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+        partial_result_params: lsp_types::PartialResultParams::default(),
+        context: Some(context),
+    };
+    let result = tokio::select! {
+        result = pending.send::<Completion>(params) => result,
+        // Dropping the request cancels it in the server
+        () = sender.closed() => return,
+    };
+    let (items, is_incomplete) = match result {
+        Ok(Some(lsp_types::CompletionResponse::Array(items))) => (items, false),
+        Ok(Some(lsp_types::CompletionResponse::List(list))) => (list.items, list.is_incomplete),
+        Ok(None) => (Vec::new(), false),
+        Err(error) => {
+            finish_completion(sender, error).await;
+            return;
+        }
+    };
+
+    let batches = items.len().div_ceil(COMPLETION_BATCH).max(1);
+    let mut items = items.into_iter().map(to_completion_item);
+    for batch in 1..=batches {
+        if pending.guard.is_cancelled() {
+            finish_completion(sender, cancelled()).await;
+            return;
+        }
+        let message = CompletionResponse {
+            meta: Some(StreamMeta {
+                delta_type: DeltaType::Update as i32,
+                is_final: batch == batches,
+                ..StreamMeta::default()
+            }),
+            items: items.by_ref().take(COMPLETION_BATCH).collect(),
+            is_incomplete,
+            error: None,
+        };
+        if !sender.send(message).await {
+            return;
+        }
+    }
+}
+
+/// End a completion stream with an error.
+async fn finish_completion(mut sender: StreamSender<CompletionResponse>, error: Error) {
+    sender
+        .send(CompletionResponse {
+            meta: Some(StreamMeta {
+                delta_type: DeltaType::Update as i32,
+                is_final: true,
+                ..StreamMeta::default()
+            }),
+            error: Some(error),
+            ..CompletionResponse::default()
+        })
+        .await;
+}
+
+fn cancelled() -> Error {
+    error("CANCELLED", "The request was cancelled", SOURCE)
+}
+
+/// Whether a provider capability that may be a plain flag is enabled.
+fn completion_context(
+    trigger_kind: i32,
+    trigger_character: String,
+) -> lsp_types::CompletionContext {
+    let trigger_kind = match CompletionTriggerKind::try_from(trigger_kind).unwrap_or_default() {
+        CompletionTriggerKind::TriggerCharacter => {
+            lsp_types::CompletionTriggerKind::TRIGGER_CHARACTER
+        }
+        CompletionTriggerKind::Incomplete => {
+            lsp_types::CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS
+        }
+        CompletionTriggerKind::Unspecified | CompletionTriggerKind::Invoked => {
+            lsp_types::CompletionTriggerKind::INVOKED
+        }
+    };
+    lsp_types::CompletionContext {
+        trigger_kind,
+        trigger_character: (!trigger_character.is_empty()).then_some(trigger_character),
+    }
+}
+
+fn to_markup(markup: lsp_types::MarkupContent) -> MarkupContent {
+    let kind = match markup.kind {
+        lsp_types::MarkupKind::PlainText => MarkupKind::Plaintext,
+        lsp_types::MarkupKind::Markdown => MarkupKind::Markdown,
+    };
+    MarkupContent {
+        kind: kind as i32,
+        value: markup.value,
+    }
+}
+
+fn markdown(value: String) -> MarkupContent {
+    MarkupContent {
+        kind: MarkupKind::Markdown as i32,
+        value,
+    }
+}
+
+fn to_documentation(documentation: Documentation) -> MarkupContent {
+    match documentation {
+        Documentation::String(value) => MarkupContent {
+            kind: MarkupKind::Plaintext as i32,
+            value,
+        },
+        Documentation::MarkupContent(markup) => to_markup(markup),
+    }
+}
+
+/// Markdown of a deprecated `MarkedString`; code becomes a fenced block.
+fn marked_string(marked: MarkedString) -> String {
+    match marked {
+        MarkedString::String(text) => text,
+        MarkedString::LanguageString(code) => {
+            format!("```{}\n{}\n```", code.language, code.value)
+        }
+    }
+}
+
+fn to_hover(hover: Option<lsp_types::Hover>) -> HoverSuccess {
+    let Some(hover) = hover else {
+        return HoverSuccess::default();
+    };
+    let contents = match hover.contents {
+        HoverContents::Scalar(marked) => markdown(marked_string(marked)),
+        HoverContents::Array(marked) => markdown(
+            marked
+                .into_iter()
+                .map(marked_string)
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        ),
+        HoverContents::Markup(markup) => to_markup(markup),
+    };
+    if contents.value.trim().is_empty() {
+        return HoverSuccess::default();
+    }
+    HoverSuccess {
+        found: true,
+        contents: Some(contents),
+        range: hover.range.map(|r| to_proto_range(from_lsp_range(r))),
+    }
+}
+
+fn to_proto_lsp_edit(edit: lsp_types::TextEdit) -> ProtoTextEdit {
+    to_proto_edit(&TextEdit::new(from_lsp_range(edit.range), edit.new_text))
+}
+
+/// Protocol value of a completion item kind; the protocol uses the LSP
+/// numbering.
+fn to_completion_kind(kind: lsp_types::CompletionItemKind) -> i32 {
+    serde_json::to_value(kind)
+        .ok()
+        .and_then(|value| value.as_i64())
+        .and_then(|value| i32::try_from(value).ok())
+        .filter(|&value| CompletionItemKind::try_from(value).is_ok())
+        .unwrap_or_default()
+}
+
+fn to_completion_item(item: lsp_types::CompletionItem) -> CompletionItem {
+    let deprecated = item.deprecated.unwrap_or(false)
+        || item
+            .tags
+            .iter()
+            .flatten()
+            .any(|tag| *tag == CompletionItemTag::DEPRECATED);
+    let text_edit = item.text_edit.map(|edit| match edit {
+        CompletionTextEdit::Edit(edit) => to_proto_lsp_edit(edit),
+        // Insert mode: the text after the cursor is kept
+        CompletionTextEdit::InsertAndReplace(edit) => to_proto_lsp_edit(lsp_types::TextEdit {
+            range: edit.insert,
+            new_text: edit.new_text,
+        }),
+    });
+    CompletionItem {
+        label: item.label,
+        kind: item.kind.map_or(0, to_completion_kind),
+        detail: item.detail.unwrap_or_default(),
+        documentation: item.documentation.map(to_documentation),
+        sort_text: item.sort_text.unwrap_or_default(),
+        filter_text: item.filter_text.unwrap_or_default(),
+        insert_text: item.insert_text.unwrap_or_default(),
+        text_edit,
+        is_snippet: item.insert_text_format == Some(lsp_types::InsertTextFormat::SNIPPET),
+        additional_edits: item
+            .additional_text_edits
+            .into_iter()
+            .flatten()
+            .map(to_proto_lsp_edit)
+            .collect(),
+        deprecated,
+        preselect: item.preselect.unwrap_or(false),
+        commit_characters: item.commit_characters.unwrap_or_default(),
+    }
+}
+
+fn to_signature_help(help: Option<lsp_types::SignatureHelp>) -> SignatureHelpSuccess {
+    let Some(help) = help else {
+        return SignatureHelpSuccess::default();
+    };
+    let active_signature = help.active_signature.unwrap_or(0);
+    // The active signature's own active parameter takes precedence
+    let active_parameter = usize::try_from(active_signature)
+        .ok()
+        .and_then(|index| help.signatures.get(index))
+        .and_then(|signature| signature.active_parameter)
+        .or(help.active_parameter)
+        .unwrap_or(0);
+    SignatureHelpSuccess {
+        signatures: help.signatures.into_iter().map(to_signature).collect(),
+        active_signature,
+        active_parameter,
+    }
+}
+
+fn to_signature(signature: lsp_types::SignatureInformation) -> SignatureInformation {
+    let parameters = signature
+        .parameters
+        .into_iter()
+        .flatten()
+        .map(|parameter| to_parameter(&signature.label, parameter))
+        .collect();
+    SignatureInformation {
+        label: signature.label,
+        documentation: signature.documentation.map(to_documentation),
+        parameters,
+    }
+}
+
+fn to_parameter(
+    signature: &str,
+    parameter: lsp_types::ParameterInformation,
+) -> ParameterInformation {
+    let (label, label_start, label_end) = match parameter.label {
+        ParameterLabel::LabelOffsets([start, end]) => {
+            (utf16_slice(signature, start, end), start, end)
+        }
+        // A plain label refers to its first occurrence in the signature
+        ParameterLabel::Simple(label) => {
+            let (start, end) = signature.find(&label).map_or((0, 0), |index| {
+                let start = utf16_len(&signature[..index]);
+                (start, start + utf16_len(&label))
+            });
+            (label, start, end)
+        }
+    };
+    ParameterInformation {
+        label,
+        label_start,
+        label_end,
+        documentation: parameter.documentation.map(to_documentation),
+    }
+}
+
+fn utf16_len(text: &str) -> u32 {
+    u32::try_from(text.encode_utf16().count()).unwrap_or(u32::MAX)
+}
+
+/// The text between two UTF-16 offsets.
+fn utf16_slice(text: &str, start: u32, end: u32) -> String {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let offset = |value: u32| usize::try_from(value).map_or(units.len(), |v| v.min(units.len()));
+    let (start, end) = (offset(start), offset(end));
+    String::from_utf16_lossy(&units[start..end.max(start)])
+}
+
+/// A location in the workspace, or by absolute path outside it. URIs that
+/// are not files are skipped.
+fn to_location(workspace: &Workspace, uri: &Url, range: lsp_types::Range) -> Option<Location> {
+    let path = uri.to_file_path().ok()?;
+    let range = Some(to_proto_range(from_lsp_range(range)));
+    let file_id = workspace.file_id_of(&path);
+    let path = if file_id.is_some() {
+        String::new()
+    } else {
+        path.to_string_lossy().into_owned()
+    };
+    Some(Location {
+        file_id: file_id.map(|path| FileId { path }),
+        range,
+        path,
+    })
+}
+
+fn to_definitions(
+    workspace: &Workspace,
+    response: Option<GotoDefinitionResponse>,
+) -> Vec<Location> {
+    match response {
+        None => Vec::new(),
+        Some(GotoDefinitionResponse::Scalar(location)) => {
+            to_location(workspace, &location.uri, location.range)
+                .into_iter()
+                .collect()
+        }
+        Some(GotoDefinitionResponse::Array(locations)) => locations
+            .iter()
+            .filter_map(|l| to_location(workspace, &l.uri, l.range))
+            .collect(),
+        // The selection range is the name, which is where to put the cursor
+        Some(GotoDefinitionResponse::Link(links)) => links
+            .iter()
+            .filter_map(|l| to_location(workspace, &l.target_uri, l.target_selection_range))
+            .collect(),
+    }
+}
+
+/// Edits per workspace file of a workspace edit. Edits to files outside the
+/// workspace and file operations are refused.
+fn to_file_edits(
+    workspace: &Workspace,
+    edit: Option<WorkspaceEdit>,
+) -> Result<Vec<FileEdits>, Error> {
+    let Some(edit) = edit else {
+        return Ok(Vec::new());
+    };
+    let mut files: BTreeMap<String, Vec<lsp_types::TextEdit>> = BTreeMap::new();
+    let mut add = |uri: &Url, edits: Vec<lsp_types::TextEdit>| {
+        let Some(file) = file_id(workspace, uri) else {
+            return Err(error(
+                "RENAME_OUTSIDE_WORKSPACE",
+                format!("The rename would change {uri}, which is outside the workspace"),
+                SOURCE,
+            ));
+        };
+        files.entry(file).or_default().extend(edits);
+        Ok(())
+    };
+
+    // Servers send document changes when the client supports them
+    if let Some(changes) = edit.document_changes {
+        let documents = match changes {
+            DocumentChanges::Edits(documents) => documents,
+            DocumentChanges::Operations(operations) => operations
+                .into_iter()
+                .map(|operation| match operation {
+                    DocumentChangeOperation::Edit(document) => Ok(document),
+                    DocumentChangeOperation::Op(_) => Err(error(
+                        "NOT_SUPPORTED",
+                        "The rename creates, renames or deletes files, which is not supported",
+                        SOURCE,
+                    )),
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        for document in documents {
+            let edits = document
+                .edits
+                .into_iter()
+                .map(|edit| match edit {
+                    OneOf::Left(edit) => edit,
+                    OneOf::Right(annotated) => annotated.text_edit,
+                })
+                .collect();
+            add(&document.text_document.uri, edits)?;
+        }
+    } else {
+        for (uri, edits) in edit.changes.into_iter().flatten() {
+            add(&uri, edits)?;
+        }
+    }
+
+    Ok(files
+        .into_iter()
+        .map(|(file, edits)| FileEdits {
+            file_id: Some(FileId { path: file }),
+            edits: end_to_start(edits).iter().map(to_proto_edit).collect(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    use gouide_lsp::lsp_types::{
+        LocationLink, OptionalVersionedTextDocumentIdentifier, ResourceOp, TextDocumentEdit,
+    };
+
+    use crate::config::DaemonConfig;
+    use crate::diagnostics::DiagnosticsStore;
+    use crate::languages::LanguageRegistry;
+
+    fn lsp_range(line: u32, start: u32, end: u32) -> lsp_types::Range {
+        lsp_types::Range::new(
+            lsp_types::Position::new(line, start),
+            lsp_types::Position::new(line, end),
+        )
+    }
+
+    fn lsp_edit(line: u32, start: u32, end: u32, text: &str) -> lsp_types::TextEdit {
+        lsp_types::TextEdit::new(lsp_range(line, start, end), text.to_string())
+    }
+
+    fn workspace() -> (tempfile::TempDir, Arc<Workspace>) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.rs"), "fn add() {}\nfn b() { add() }\n").unwrap();
+        fs::write(dir.path().join("b.rs"), "use add;\n").unwrap();
+        let workspace = WorkspaceManager::new()
+            .open_workspace(dir.path(), None, vec![])
+            .unwrap();
+        (dir, workspace)
+    }
+
+    fn file_uri(workspace: &Workspace, file: &str) -> Url {
+        Url::from_file_path(workspace.root().join(file)).unwrap()
+    }
+
+    #[test]
+    fn test_rename_edits_per_file_end_to_start() {
+        let (_dir, workspace) = workspace();
+        let a = file_uri(&workspace, "a.rs");
+        let b = file_uri(&workspace, "b.rs");
+        let edit = WorkspaceEdit::new(HashMap::from([
+            (
+                a,
+                vec![
+                    lsp_edit(0, 3, 6, "sum"),
+                    lsp_edit(1, 9, 12, "sum"),
+                    // Two inserts at one position keep their order
+                    lsp_edit(0, 0, 0, "pub "),
+                    lsp_edit(0, 0, 0, "const "),
+                ],
+            ),
+            (b.clone(), vec![lsp_edit(0, 4, 7, "sum")]),
+        ]));
+
+        let files = to_file_edits(&workspace, Some(edit)).unwrap();
+        let paths: Vec<_> = files
+            .iter()
+            .map(|f| f.file_id.as_ref().unwrap().path.as_str())
+            .collect();
+        assert_eq!(paths, ["a.rs", "b.rs"]);
+        let edits: Vec<_> = files[0]
+            .edits
+            .iter()
+            .map(|e| {
+                let start = e.range.as_ref().unwrap().start.as_ref().unwrap();
+                (start.line, start.character, e.new_text.as_str())
+            })
+            .collect();
+        assert_eq!(
+            edits,
+            [
+                (1, 9, "sum"),
+                (0, 3, "sum"),
+                (0, 0, "const "),
+                (0, 0, "pub ")
+            ]
+        );
+
+        // Document changes are read the same way
+        let edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier {
+                    uri: b,
+                    version: None,
+                },
+                edits: vec![OneOf::Left(lsp_edit(0, 4, 7, "sum"))],
+            }])),
+            ..WorkspaceEdit::default()
+        };
+        assert_eq!(to_file_edits(&workspace, Some(edit)).unwrap().len(), 1);
+        assert!(to_file_edits(&workspace, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rename_outside_workspace_or_with_file_operations_is_refused() {
+        let (_dir, workspace) = workspace();
+        let outside = Url::parse("file:///elsewhere/lib.rs").unwrap();
+        let edit = WorkspaceEdit::new(HashMap::from([(outside, vec![lsp_edit(0, 0, 1, "x")])]));
+        let err = to_file_edits(&workspace, Some(edit)).unwrap_err();
+        assert_eq!(err.code, "RENAME_OUTSIDE_WORKSPACE");
+
+        let edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(ResourceOp::Create(lsp_types::CreateFile {
+                    uri: file_uri(&workspace, "c.rs"),
+                    options: None,
+                    annotation_id: None,
+                })),
+            ])),
+            ..WorkspaceEdit::default()
+        };
+        let err = to_file_edits(&workspace, Some(edit)).unwrap_err();
+        assert_eq!(err.code, "NOT_SUPPORTED");
+    }
+
+    #[test]
+    fn test_definitions_inside_and_outside_the_workspace() {
+        let (_dir, workspace) = workspace();
+        let link = LocationLink {
+            origin_selection_range: None,
+            target_uri: file_uri(&workspace, "a.rs"),
+            target_range: lsp_range(0, 0, 11),
+            target_selection_range: lsp_range(0, 3, 6),
+        };
+        let outside = LocationLink {
+            target_uri: Url::parse("file:///usr/lib/std.rs").unwrap(),
+            ..link
+        };
+        let remote = LocationLink {
+            target_uri: Url::parse("https://example.com/a.rs").unwrap(),
+            ..link
+        };
+
+        let locations = to_definitions(
+            &workspace,
+            Some(GotoDefinitionResponse::Link(vec![link, outside, remote])),
+        );
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[0].file_id.as_ref().unwrap().path, "a.rs");
+        let start = locations[0].range.unwrap().start.unwrap();
+        assert_eq!((start.line, start.character), (0, 3));
+        assert!(locations[1].file_id.is_none());
+        assert_eq!(locations[1].path, "/usr/lib/std.rs");
+    }
+
+    #[test]
+    fn test_hover_and_signature_conversion() {
+        let hover = to_hover(Some(lsp_types::Hover {
+            contents: HoverContents::Array(vec![
+                MarkedString::LanguageString(lsp_types::LanguageString {
+                    language: "rust".into(),
+                    value: "fn add()".into(),
+                }),
+                MarkedString::String("Adds".into()),
+            ]),
+            range: None,
+        }));
+        assert!(hover.found);
+        assert_eq!(
+            hover.contents.unwrap().value,
+            "```rust\nfn add()\n```\n\nAdds"
+        );
+        assert!(!to_hover(None).found);
+
+        let help = to_signature_help(Some(lsp_types::SignatureHelp {
+            signatures: vec![lsp_types::SignatureInformation {
+                label: "add(a: i32, b: i32)".into(),
+                documentation: None,
+                parameters: Some(vec![
+                    lsp_types::ParameterInformation {
+                        label: ParameterLabel::LabelOffsets([4, 10]),
+                        documentation: None,
+                    },
+                    lsp_types::ParameterInformation {
+                        label: ParameterLabel::Simple("b: i32".into()),
+                        documentation: None,
+                    },
+                ]),
+                active_parameter: Some(1),
+            }],
+            active_signature: None,
+            active_parameter: Some(0),
+        }));
+        assert_eq!(help.active_parameter, 1);
+        let parameters = &help.signatures[0].parameters;
+        assert_eq!(
+            (parameters[0].label.as_str(), parameters[0].label_start),
+            ("a: i32", 4)
+        );
+        assert_eq!(
+            (parameters[1].label_start, parameters[1].label_end),
+            (12, 18)
+        );
+    }
+
+    #[test]
+    fn test_completion_item_conversion() {
+        let mut item = lsp_types::CompletionItem::new_simple("add".into(), "fn()".into());
+        item.kind = Some(lsp_types::CompletionItemKind::FUNCTION);
+        item.insert_text_format = Some(lsp_types::InsertTextFormat::SNIPPET);
+        item.tags = Some(vec![CompletionItemTag::DEPRECATED]);
+        item.text_edit = Some(CompletionTextEdit::InsertAndReplace(
+            lsp_types::InsertReplaceEdit {
+                new_text: "add($1)".into(),
+                insert: lsp_range(0, 0, 2),
+                replace: lsp_range(0, 0, 5),
+            },
+        ));
+
+        let item = to_completion_item(item);
+        assert_eq!(item.kind, CompletionItemKind::Function as i32);
+        assert!(item.is_snippet);
+        assert!(item.deprecated);
+        let edit = item.text_edit.unwrap();
+        assert_eq!(edit.new_text, "add($1)");
+        assert_eq!(edit.range.unwrap().end.unwrap().character, 2);
+    }
+
+    /// A service without language servers, and a buffer open in it.
+    fn service_with_buffer(dir: &tempfile::TempDir, text: &str) -> (LanguageService, String) {
+        fs::write(dir.path().join("a.rs"), text).unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "a.rs", None, "")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let lsp = LspBridge::start(
+            &DaemonConfig::default(),
+            workspaces.clone(),
+            Arc::new(LanguageRegistry::new()),
+            Arc::new(DiagnosticsStore::new(16)),
+        );
+        let sync = Arc::new(BufferSync::new(
+            workspaces.clone(),
+            std::time::Duration::from_millis(10),
+            16,
+        ));
+        let service = LanguageService::new(
+            workspaces,
+            sync,
+            Arc::new(SyntaxManager::new()),
+            lsp,
+            Arc::new(RequestTracker::new()),
+        );
+        (service, buffer_id)
+    }
+
+    #[tokio::test]
+    async fn test_buffer_without_language_server() {
+        let dir = tempfile::tempdir().unwrap();
+        let (service, buffer_id) = service_with_buffer(&dir, "fn a() {}\n");
+        let hover = |position| HoverRequest {
+            request_id: None,
+            buffer_id: Some(BufferId {
+                value: buffer_id.clone(),
+            }),
+            position,
+        };
+
+        let response = service
+            .hover(Request::new(hover(Some(ProtoPosition::default()))))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(hover_response::Result::Error(err)) = response.result else {
+            panic!("expected an error");
+        };
+        assert_eq!(err.code, "NO_LANGUAGE_SERVER");
+        assert_eq!(err.source, SOURCE);
+
+        let response = service
+            .hover(Request::new(hover(None)))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(hover_response::Result::Error(err)) = response.result else {
+            panic!("expected an error");
+        };
+        assert_eq!(err.code, "INVALID_ARGUMENT");
+    }
+
+    #[tokio::test]
+    async fn test_builtin_code_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let (service, buffer_id) = service_with_buffer(&dir, "fn a() {}  \nfn b() {}");
+        let get = |only: Vec<String>, range| GetCodeActionsRequest {
+            request_id: None,
+            buffer_id: Some(BufferId {
+                value: buffer_id.clone(),
+            }),
+            range,
+            diagnostics: vec![],
+            only,
+        };
+        let get_actions = |request| async {
+            let response = service
+                .get_code_actions(Request::new(request))
+                .await
+                .unwrap()
+                .into_inner();
+            match response.result {
+                Some(get_code_actions_response::Result::Success(success)) => Ok(success),
+                Some(get_code_actions_response::Result::Error(err)) => Err(err),
+                None => panic!("expected a result"),
+            }
+        };
+        let apply = |action_id: &str| ApplyCodeActionRequest {
+            request_id: None,
+            buffer_id: Some(BufferId {
+                value: buffer_id.clone(),
+            }),
+            action_id: action_id.to_string(),
+        };
+
+        let err = get_actions(get(vec![], None)).await.unwrap_err();
+        assert_eq!(err.code, "INVALID_ARGUMENT");
+        let range = Some(gouide_protocol::Range::default());
+        let success = get_actions(get(vec!["quickfix".into()], range))
+            .await
+            .unwrap();
+        assert!(success.actions.is_empty());
+
+        let success = get_actions(get(vec!["source".into()], range))
+            .await
+            .unwrap();
+        assert_eq!(success.version, 1);
+        let titles: Vec<_> = success.actions.iter().map(|a| a.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            ["Remove trailing whitespace", "Add missing final newline"]
+        );
+        assert!(success.actions.iter().all(|a| a.source == "builtin"));
+
+        for action in &success.actions {
+            let response = service
+                .apply_code_action(Request::new(apply(&action.id)))
+                .await
+                .unwrap()
+                .into_inner();
+            let Some(apply_code_action_response::Result::Success(applied)) = response.result else {
+                panic!("expected the action to apply");
+            };
+            assert_eq!(applied.changes.len(), 1);
+            assert_eq!(
+                applied.changes[0].buffer_id.as_ref().unwrap().value,
+                buffer_id
+            );
+        }
+        let buffer = service.workspaces.buffer(&buffer_id).unwrap();
+        assert_eq!(buffer.read().text(), "fn a() {}\nfn b() {}\n");
+
+        // An applied action is used up
+        let response = service
+            .apply_code_action(Request::new(apply(&success.actions[0].id)))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(apply_code_action_response::Result::Error(err)) = response.result else {
+            panic!("expected an error");
+        };
+        assert_eq!(err.code, "CODE_ACTION_NOT_FOUND");
+        let success = get_actions(get(vec![], range)).await.unwrap();
+        assert!(success.actions.is_empty());
+    }
+}
@@ -0,0 +1,431 @@
+//! Code actions offered for buffers.
+//!
+//! GetCodeActions asks every language server the buffer is open in and adds
+//! the built-in fixers. The offered actions are kept, by ID, with the
+//! buffer version they were computed for, until ApplyCodeAction takes one,
+//! the buffer gets a new set, or the buffer closes.
+
+use std::collections::{BTreeMap, HashMap};
+
+use gouide_lsp::lsp_types::{
+    self, CodeActionOrCommand, CodeActionProviderCapability, DocumentChangeOperation,
+    DocumentChanges, NumberOrString, OneOf, ResourceOp, Url, WorkspaceEdit,
+};
+use gouide_lsp::LanguageServer;
+use gouide_protocol::{
+    CodeAction, Diagnostic as ProtoDiagnostic, DiagnosticSeverity,
+    DiagnosticTag as ProtoDiagnosticTag, Error,
+};
+use gouide_workspace::{TextRange, Workspace};
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+use super::SOURCE;
+use crate::fixers::Fixer;
+use crate::services::convert::{from_proto_range, to_proto_diagnostic};
+use crate::services::edits::Change;
+use crate::services::errors::error;
+use crate::services::lsp::{end_to_start, file_id, source, to_diagnostic, to_lsp_range};
+
+/// Source label of the built-in fixers' actions.
+const BUILTIN: &str = "builtin";
+
+/// An offered action.
+#[derive(Clone)]
+pub(super) enum Action {
+    /// A built-in fix, computed against the buffer when applied.
+    Builtin(Fixer),
+    /// An action from a language server.
+    Lsp {
+        /// Server that offered it, which resolves and executes it.
+        server: LanguageServer,
+        /// The action as the server sent it.
+        action: Box<CodeActionOrCommand>,
+    },
+}
+
+/// A buffer's offered actions.
+struct Offered {
+    version: u64,
+    actions: HashMap<String, Action>,
+}
+
+/// Offered actions by buffer ID.
+#[derive(Default)]
+pub(super) struct CodeActions {
+    offered: Mutex<HashMap<String, Offered>>,
+}
+
+impl CodeActions {
+    /// Replace a buffer's offered actions, returning their IDs in order.
+    pub(super) fn offer(&self, buffer_id: &str, version: u64, actions: Vec<Action>) -> Vec<String> {
+        let actions: Vec<(String, Action)> = actions
+            .into_iter()
+            .map(|action| (Uuid::new_v4().to_string(), action))
+            .collect();
+        let ids = actions.iter().map(|(id, _)| id.clone()).collect();
+        self.offered.lock().insert(
+            buffer_id.to_string(),
+            Offered {
+                version,
+                actions: actions.into_iter().collect(),
+            },
+        );
+        ids
+    }
+
+    /// Take an offered action, with the buffer version it was computed for.
+    pub(super) fn take(&self, buffer_id: &str, action_id: &str) -> Option<(u64, Action)> {
+        let mut offered = self.offered.lock();
+        let buffer = offered.get_mut(buffer_id)?;
+        let taken = buffer
+            .actions
+            .remove(action_id)
+            .map(|action| (buffer.version, action));
+        drop(offered);
+        taken
+    }
+
+    /// Drop a closed buffer's actions.
+    pub(super) fn forget(&self, buffer_id: &str) {
+        self.offered.lock().remove(buffer_id);
+    }
+}
+
+/// Whether a server answers code action requests.
+pub(super) const fn offers_code_actions(capabilities: &lsp_types::ServerCapabilities) -> bool {
+    matches!(
+        capabilities.code_action_provider,
+        Some(CodeActionProviderCapability::Simple(true) | CodeActionProviderCapability::Options(_))
+    )
+}
+
+/// Whether a server fills in the edits of its actions on request.
+pub(super) fn resolves_code_actions(capabilities: &lsp_types::ServerCapabilities) -> bool {
+    matches!(
+        &capabilities.code_action_provider,
+        Some(CodeActionProviderCapability::Options(options))
+            if options.resolve_provider == Some(true)
+    )
+}
+
+/// Whether an action kind was asked for. A kind includes its sub-kinds, and
+/// actions without a kind are only returned when no kinds were asked for.
+pub(super) fn matches_only(kind: &str, only: &[String]) -> bool {
+    only.is_empty()
+        || only.iter().any(|only| {
+            kind.strip_prefix(only.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+}
+
+/// Kind of a server action, empty for plain commands.
+pub(super) fn kind_of(action: &CodeActionOrCommand) -> &str {
+    match action {
+        CodeActionOrCommand::CodeAction(action) => {
+            action.kind.as_ref().map_or("", |kind| kind.as_str())
+        }
+        CodeActionOrCommand::Command(_) => "",
+    }
+}
+
+/// A client diagnostic as servers expect it in a code action context.
+pub(super) fn to_lsp_diagnostic(diagnostic: &ProtoDiagnostic) -> lsp_types::Diagnostic {
+    let severity = match DiagnosticSeverity::try_from(diagnostic.severity) {
+        Ok(DiagnosticSeverity::Warning) => lsp_types::DiagnosticSeverity::WARNING,
+        Ok(DiagnosticSeverity::Info) => lsp_types::DiagnosticSeverity::INFORMATION,
+        Ok(DiagnosticSeverity::Hint) => lsp_types::DiagnosticSeverity::HINT,
+        _ => lsp_types::DiagnosticSeverity::ERROR,
+    };
+    // Servers publish numeric codes as numbers and match them that way
+    let code = diagnostic.code.parse().map_or_else(
+        |_| NumberOrString::String(diagnostic.code.clone()),
+        NumberOrString::Number,
+    );
+    let tags: Vec<_> = diagnostic
+        .tags
+        .iter()
+        .filter_map(|&tag| match ProtoDiagnosticTag::try_from(tag) {
+            Ok(ProtoDiagnosticTag::Unnecessary) => Some(lsp_types::DiagnosticTag::UNNECESSARY),
+            Ok(ProtoDiagnosticTag::Deprecated) => Some(lsp_types::DiagnosticTag::DEPRECATED),
+            _ => None,
+        })
+        .collect();
+    lsp_types::Diagnostic {
+        range: to_lsp_range(
+            diagnostic
+                .range
+                .as_ref()
+                .map_or_else(TextRange::default, from_proto_range),
+        ),
+        severity: Some(severity),
+        code: (!diagnostic.code.is_empty()).then_some(code),
+        source: (!diagnostic.source.is_empty()).then(|| diagnostic.source.clone()),
+        message: diagnostic.message.clone(),
+        tags: (!tags.is_empty()).then_some(tags),
+        ..lsp_types::Diagnostic::default()
+    }
+}
+
+/// Protocol form of an offered action, without its ID.
+pub(super) fn to_proto_action(workspace: &Workspace, action: &Action) -> CodeAction {
+    let (server, action) = match action {
+        Action::Builtin(fixer) => {
+            return CodeAction {
+                title: fixer.title().to_string(),
+                kind: Fixer::KIND.to_string(),
+                source: BUILTIN.to_string(),
+                ..CodeAction::default()
+            };
+        }
+        Action::Lsp { server, action } => (server, action.as_ref()),
+    };
+    match action {
+        CodeActionOrCommand::Command(command) => CodeAction {
+            title: command.title.clone(),
+            source: source(server.name()),
+            ..CodeAction::default()
+        },
+        CodeActionOrCommand::CodeAction(action) => CodeAction {
+            id: String::new(),
+            title: action.title.clone(),
+            kind: action
+                .kind
+                .as_ref()
+                .map(|kind| kind.as_str().to_string())
+                .unwrap_or_default(),
+            source: source(server.name()),
+            is_preferred: action.is_preferred.unwrap_or(false),
+            diagnostics: action
+                .diagnostics
+                .iter()
+                .flatten()
+                .map(|d| to_proto_diagnostic(&to_diagnostic(workspace, d.clone())))
+                .collect(),
+            disabled_reason: action
+                .disabled
+                .as_ref()
+                .map(|disabled| disabled.reason.clone())
+                .unwrap_or_default(),
+        },
+    }
+}
+
+/// Changes of a server's workspace edit, in order.
+///
+/// Edits without a document version are taken to be made against the
+/// version the actions were computed for when they are to the buffer the
+/// action was offered for (`buffer_file` at `version`), and against the
+/// current version otherwise.
+pub(super) fn to_changes(
+    workspace: &Workspace,
+    edit: WorkspaceEdit,
+    buffer_file: &str,
+    version: u64,
+) -> Result<Vec<Change>, Error> {
+    let path = |uri: &Url| {
+        file_id(workspace, uri).ok_or_else(|| {
+            error(
+                "EDIT_OUTSIDE_WORKSPACE",
+                format!("The code action would change {uri}, which is outside the workspace"),
+                SOURCE,
+            )
+        })
+    };
+    let edit_change = |file_id: String, document: Option<i32>, edits| {
+        let version = match document.and_then(|v| u64::try_from(v).ok()) {
+            Some(document) => document,
+            None if file_id == buffer_file => version,
+            None => 0,
+        };
+        Change::Edit {
+            file_id,
+            version,
+            edits: end_to_start(edits),
+        }
+    };
+
+    // Servers send document changes when the client supports them
+    let Some(document_changes) = edit.document_changes else {
+        let files: BTreeMap<String, Vec<lsp_types::TextEdit>> = edit
+            .changes
+            .into_iter()
+            .flatten()
+            .map(|(uri, edits)| Ok((path(&uri)?, edits)))
+            .collect::<Result<_, Error>>()?;
+        return Ok(files
+            .into_iter()
+            .map(|(file, edits)| edit_change(file, None, edits))
+            .collect());
+    };
+    let operations = match document_changes {
+        DocumentChanges::Edits(documents) => documents
+            .into_iter()
+            .map(DocumentChangeOperation::Edit)
+            .collect(),
+        DocumentChanges::Operations(operations) => operations,
+    };
+    operations
+        .into_iter()
+        .map(|operation| {
+            Ok(match operation {
+                DocumentChangeOperation::Edit(document) => {
+                    let edits = document
+                        .edits
+                        .into_iter()
+                        .map(|edit| match edit {
+                            OneOf::Left(edit) => edit,
+                            OneOf::Right(annotated) => annotated.text_edit,
+                        })
+                        .collect();
+                    edit_change(
+                        path(&document.text_document.uri)?,
+                        document.text_document.version,
+                        edits,
+                    )
+                }
+                DocumentChangeOperation::Op(ResourceOp::Create(create)) => {
+                    let options = create.options.as_ref();
+                    Change::Create {
+                        file_id: path(&create.uri)?,
+                        overwrite: flag(options.and_then(|o| o.overwrite)),
+                        ignore_if_exists: flag(options.and_then(|o| o.ignore_if_exists)),
+                    }
+                }
+                DocumentChangeOperation::Op(ResourceOp::Rename(rename)) => {
+                    let options = rename.options.as_ref();
+                    Change::Rename {
+                        from: path(&rename.old_uri)?,
+                        to: path(&rename.new_uri)?,
+                        overwrite: flag(options.and_then(|o| o.overwrite)),
+                        ignore_if_exists: flag(options.and_then(|o| o.ignore_if_exists)),
+                    }
+                }
+                DocumentChangeOperation::Op(ResourceOp::Delete(delete)) => {
+                    let options = delete.options.as_ref();
+                    Change::Delete {
+                        file_id: path(&delete.uri)?,
+                        recursive: flag(options.and_then(|o| o.recursive)),
+                        ignore_if_not_exists: flag(options.and_then(|o| o.ignore_if_not_exists)),
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+/// An optional LSP flag, off when left out.
+fn flag(value: Option<bool>) -> bool {
+    value.unwrap_or(false)
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use gouide_lsp::lsp_types::{
+        CreateFile, OptionalVersionedTextDocumentIdentifier, RenameFile, TextDocumentEdit,
+    };
+    use gouide_workspace::WorkspaceManager;
+
+    fn lsp_edit(line: u32, start: u32, end: u32, text: &str) -> lsp_types::TextEdit {
+        lsp_types::TextEdit::new(
+            lsp_types::Range::new(
+                lsp_types::Position::new(line, start),
+                lsp_types::Position::new(line, end),
+            ),
+            text.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_workspace_edit_to_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+        let workspace = WorkspaceManager::new()
+            .open_workspace(dir.path(), None, vec![])
+            .unwrap();
+        let uri = |file: &str| Url::from_file_path(workspace.root().join(file)).unwrap();
+        let edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+                    uri: uri("b.rs"),
+                    options: None,
+                    annotation_id: None,
+                })),
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier {
+                        uri: uri("b.rs"),
+                        version: None,
+                    },
+                    edits: vec![OneOf::Left(lsp_edit(0, 0, 0, "fn b() {}\n"))],
+                }),
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier {
+                        uri: uri("a.rs"),
+                        version: Some(7),
+                    },
+                    edits: vec![OneOf::Left(lsp_edit(0, 0, 0, "pub "))],
+                }),
+                DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+                    old_uri: uri("a.rs"),
+                    new_uri: uri("src/a.rs"),
+                    options: None,
+                    annotation_id: None,
+                })),
+            ])),
+            ..WorkspaceEdit::default()
+        };
+
+        let changes = to_changes(&workspace, edit, "b.rs", 3).unwrap();
+        assert_eq!(changes.len(), 4);
+        assert!(matches!(&changes[0], Change::Create { file_id, .. } if file_id == "b.rs"));
+        // Unversioned edits to the action's buffer use the offered version
+        assert!(matches!(&changes[1], Change::Edit { version: 3, .. }));
+        assert!(matches!(&changes[2], Change::Edit { version: 7, .. }));
+        assert!(matches!(&changes[3], Change::Rename { to, .. } if to == "src/a.rs"));
+
+        let outside = WorkspaceEdit::new(HashMap::from([(
+            Url::parse("file:///elsewhere/lib.rs").unwrap(),
+            vec![lsp_edit(0, 0, 1, "x")],
+        )]));
+        let err = to_changes(&workspace, outside, "a.rs", 1).unwrap_err();
+        assert_eq!(err.code, "EDIT_OUTSIDE_WORKSPACE");
+    }
+
+    #[test]
+    fn test_kind_filter_and_diagnostic_conversion() {
+        let only = ["quickfix".to_string(), "source.fixAll".to_string()];
+        assert!(matches_only("quickfix", &only));
+        assert!(matches_only("source.fixAll.eslint", &only));
+        assert!(!matches_only("source", &only));
+        assert!(!matches_only("quickfixes", &only));
+        assert!(!matches_only("", &only));
+        assert!(matches_only("", &[]));
+
+        let diagnostic = to_lsp_diagnostic(&ProtoDiagnostic {
+            severity: DiagnosticSeverity::Warning as i32,
+            code: "42".into(),
+            message: "unused".into(),
+            tags: vec![ProtoDiagnosticTag::Unnecessary as i32],
+            ..ProtoDiagnostic::default()
+        });
+        assert_eq!(
+            diagnostic.severity,
+            Some(lsp_types::DiagnosticSeverity::WARNING)
+        );
+        assert_eq!(diagnostic.code, Some(NumberOrString::Number(42)));
+        assert!(diagnostic.source.is_none());
+        assert_eq!(
+            diagnostic.tags,
+            Some(vec![lsp_types::DiagnosticTag::UNNECESSARY])
+        );
+    }
+}
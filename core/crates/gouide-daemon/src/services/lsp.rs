@@ -0,0 +1,524 @@
+//! Bridge between open buffers and language servers.
+//!
+//! The bridge observes [`BufferSync`](super::BufferSync): buffers whose
+//! language has a configured server are opened in it, their edits are sent
+//! as incremental changes, and saves and closes are forwarded. Diagnostics
+//! the servers publish go to the [`DiagnosticsStore`] under the source
+//! `lsp:<server name>`, and are cleared when the server stops. Diagnostics
+//! from servers that offer quick fixes are marked as having them.
+
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use gouide_lsp::lsp_types::{
+    self, CodeActionKind, CodeActionProviderCapability, DiagnosticSeverity, NumberOrString, OneOf,
+    PublishDiagnosticsParams, TextDocumentContentChangeEvent, Url,
+};
+use gouide_lsp::{DocumentSource, LanguageServer, LspEvent, LspManager, TextDocument};
+use gouide_protocol::BufferChangeType;
+use gouide_workspace::{Buffer, Position, TextEdit, TextRange, Workspace, WorkspaceManager};
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+use super::sync::BufferObserver;
+use crate::config::DaemonConfig;
+use crate::diagnostics::{
+    Diagnostic, DiagnosticTag, DiagnosticsStore, RelatedInformation, Severity,
+};
+use crate::languages::LanguageRegistry;
+
+/// A buffer open in language servers.
+#[derive(Clone)]
+struct OpenDocument {
+    workspace_id: String,
+    uri: Url,
+    language_id: String,
+}
+
+/// Buffers open in language servers, by buffer ID.
+struct Documents {
+    workspaces: Arc<WorkspaceManager>,
+    open: Mutex<HashMap<String, OpenDocument>>,
+}
+
+impl Documents {
+    fn get(&self, buffer_id: &str) -> Option<OpenDocument> {
+        self.open.lock().get(buffer_id).cloned()
+    }
+}
+
+impl DocumentSource for Documents {
+    fn document(&self, uri: &Url) -> Option<TextDocument> {
+        let (buffer_id, document) = self
+            .open
+            .lock()
+            .iter()
+            .find(|(_, document)| document.uri == *uri)
+            .map(|(id, document)| (id.clone(), document.clone()))?;
+        let shared = self.workspaces.buffer(&buffer_id).ok()?;
+        let buffer = shared.read();
+        Some(TextDocument {
+            uri: document.uri,
+            language_id: document.language_id,
+            version: lsp_version(buffer.version()),
+            text: buffer.text(),
+        })
+    }
+}
+
+/// Keeps language servers in sync with open buffers.
+pub struct LspBridge {
+    manager: LspManager,
+    documents: Arc<Documents>,
+    languages: Arc<LanguageRegistry>,
+}
+
+impl LspBridge {
+    /// Start the bridge for the configured servers and forward their
+    /// diagnostics to `store`. Must be called within a Tokio runtime.
+    pub fn start(
+        config: &DaemonConfig,
+        workspaces: Arc<WorkspaceManager>,
+        languages: Arc<LanguageRegistry>,
+        store: Arc<DiagnosticsStore>,
+    ) -> Arc<Self> {
+        let documents = Arc::new(Documents {
+            workspaces: workspaces.clone(),
+            open: Mutex::new(HashMap::new()),
+        });
+        let (manager, events) = LspManager::new(
+            config.language_servers.clone(),
+            config.lsp_restart,
+            documents.clone(),
+        );
+        Arc::new_cyclic(|bridge| {
+            tokio::spawn(forward_events(events, bridge.clone(), workspaces, store));
+            Self {
+                manager,
+                documents,
+                languages,
+            }
+        })
+    }
+
+    /// Servers an open buffer was sent to.
+    pub fn servers_for(&self, buffer_id: &str) -> Vec<LanguageServer> {
+        self.documents
+            .get(buffer_id)
+            .map(|document| self.manager.servers_for(&document.uri))
+            .unwrap_or_default()
+    }
+
+    /// URI of an open buffer and the servers it was sent to.
+    pub fn document(&self, buffer_id: &str) -> Option<(Url, Vec<LanguageServer>)> {
+        let document = self.documents.get(buffer_id)?;
+        let servers = self.manager.servers_for(&document.uri);
+        Some((document.uri, servers))
+    }
+
+    /// Whether a workspace's server offers quick fixes for its diagnostics.
+    pub fn offers_quick_fixes(&self, workspace_id: &str, server: &str) -> bool {
+        let capabilities = self
+            .manager
+            .server(workspace_id, server)
+            .and_then(|server| server.capabilities());
+        match capabilities.and_then(|c| c.code_action_provider) {
+            Some(CodeActionProviderCapability::Simple(enabled)) => enabled,
+            // Servers that do not list their kinds may offer any
+            Some(CodeActionProviderCapability::Options(options)) => {
+                options.code_action_kinds.map_or(true, |kinds| {
+                    kinds.iter().any(|kind| {
+                        kind.as_str()
+                            .split('.')
+                            .next()
+                            .is_some_and(|kind| kind == CodeActionKind::QUICKFIX.as_str())
+                    })
+                })
+            }
+            None => false,
+        }
+    }
+
+    /// Shut down every server.
+    pub async fn shutdown(&self) {
+        self.manager.shutdown().await;
+    }
+}
+
+impl BufferObserver for LspBridge {
+    fn opened(&self, buffer: &Buffer) {
+        // Memory-mapped files are too large to hand to a server
+        if buffer.is_mapped() {
+            return;
+        }
+        let language_id = self.languages.detect_buffer(buffer);
+        if !self.manager.handles(&language_id) {
+            return;
+        }
+        let Ok(uri) = Url::from_file_path(buffer.path()) else {
+            return;
+        };
+        let Ok(workspace) = self.documents.workspaces.workspace(buffer.workspace_id()) else {
+            return;
+        };
+        let document = OpenDocument {
+            workspace_id: buffer.workspace_id().to_string(),
+            uri: uri.clone(),
+            language_id: language_id.clone(),
+        };
+        // Joining clients open the buffer again
+        if let Entry::Vacant(entry) = self.documents.open.lock().entry(buffer.id().to_string()) {
+            entry.insert(document);
+        } else {
+            return;
+        }
+        self.manager.did_open(
+            buffer.workspace_id(),
+            workspace.root(),
+            TextDocument {
+                uri,
+                language_id,
+                version: lsp_version(buffer.version()),
+                text: buffer.text(),
+            },
+        );
+    }
+
+    fn edited(&self, buffer: &Buffer, edits: &[TextEdit]) {
+        let Some(document) = self.documents.get(buffer.id()) else {
+            return;
+        };
+        let changes: Vec<TextDocumentContentChangeEvent> = edits
+            .iter()
+            .map(|edit| TextDocumentContentChangeEvent {
+                range: Some(to_lsp_range(edit.range)),
+                range_length: None,
+                text: edit.new_text.clone(),
+            })
+            .collect();
+        self.manager
+            .did_change(&document.uri, lsp_version(buffer.version()), &changes);
+    }
+
+    fn changed(&self, buffer: &Buffer, change: BufferChangeType) {
+        match change {
+            BufferChangeType::Modified => {
+                let Some(document) = self.documents.get(buffer.id()) else {
+                    return;
+                };
+                let change = TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: buffer.text(),
+                };
+                self.manager
+                    .did_change(&document.uri, lsp_version(buffer.version()), &[change]);
+            }
+            // Servers know documents by URI, so a move is a close and an open
+            BufferChangeType::Renamed => {
+                self.closed(buffer.id());
+                self.opened(buffer);
+            }
+            _ => {}
+        }
+    }
+
+    fn saved(&self, buffer: &Buffer) {
+        if let Some(document) = self.documents.get(buffer.id()) {
+            self.manager.did_save(&document.uri);
+        }
+    }
+
+    fn closed(&self, buffer_id: &str) {
+        let document = self.documents.open.lock().remove(buffer_id);
+        if let Some(document) = document {
+            self.manager.did_close(&document.uri);
+        }
+    }
+
+    fn workspace_closed(&self, workspace_id: &str) {
+        self.documents
+            .open
+            .lock()
+            .retain(|_, document| document.workspace_id != workspace_id);
+        self.manager.stop_workspace(workspace_id);
+    }
+}
+
+/// Whether a server capability that is either a flag or options is on.
+pub(super) const fn enabled<T>(provider: Option<&OneOf<bool, T>>) -> bool {
+    matches!(provider, Some(OneOf::Left(true) | OneOf::Right(_)))
+}
+
+/// Diagnostics source name of a server.
+pub(super) fn source(server: &str) -> String {
+    format!("lsp:{server}")
+}
+
+/// LSP document version of a buffer version. Versions beyond `i32` stop
+/// increasing, which servers tolerate.
+fn lsp_version(version: u64) -> i32 {
+    i32::try_from(version).unwrap_or(i32::MAX)
+}
+
+async fn forward_events(
+    mut events: mpsc::UnboundedReceiver<LspEvent>,
+    bridge: Weak<LspBridge>,
+    workspaces: Arc<WorkspaceManager>,
+    store: Arc<DiagnosticsStore>,
+) {
+    while let Some(event) = events.recv().await {
+        let quick_fixes = match &event {
+            LspEvent::Diagnostics {
+                workspace_id,
+                server,
+                ..
+            } => bridge
+                .upgrade()
+                .is_some_and(|bridge| bridge.offers_quick_fixes(workspace_id, server)),
+            _ => false,
+        };
+        handle_event(&workspaces, &store, event, quick_fixes);
+    }
+}
+
+/// Apply a server event to the store. `quick_fixes` is whether the server
+/// offers quick fixes for the diagnostics it published.
+fn handle_event(
+    workspaces: &WorkspaceManager,
+    store: &DiagnosticsStore,
+    event: LspEvent,
+    quick_fixes: bool,
+) {
+    match event {
+        LspEvent::Started {
+            workspace_id,
+            server,
+        } => {
+            info!(workspace_id = %workspace_id, server = %server, "Language server ready");
+        }
+        LspEvent::Diagnostics {
+            workspace_id,
+            server,
+            params,
+        } => {
+            let Ok(workspace) = workspaces.workspace(&workspace_id) else {
+                return;
+            };
+            let Some(file_id) = file_id(&workspace, &params.uri) else {
+                debug!(uri = %params.uri, "Diagnostics for a file outside the workspace");
+                return;
+            };
+            let PublishDiagnosticsParams {
+                diagnostics,
+                version,
+                ..
+            } = params;
+            let diagnostics = diagnostics
+                .into_iter()
+                .map(|d| Diagnostic {
+                    has_quick_fixes: quick_fixes,
+                    ..to_diagnostic(&workspace, d)
+                })
+                .collect();
+            store.publish(
+                &workspace_id,
+                &file_id,
+                &source(&server),
+                version.and_then(|v| u64::try_from(v).ok()),
+                diagnostics,
+            );
+        }
+        LspEvent::Stopped {
+            workspace_id,
+            server,
+        } => store.clear_source(&workspace_id, &source(&server)),
+    }
+}
+
+pub(super) fn file_id(workspace: &Workspace, uri: &Url) -> Option<String> {
+    workspace.file_id_of(&uri.to_file_path().ok()?)
+}
+
+pub(super) const fn to_lsp_position(position: Position) -> lsp_types::Position {
+    lsp_types::Position {
+        line: position.line,
+        character: position.character,
+    }
+}
+
+pub(super) const fn to_lsp_range(range: TextRange) -> lsp_types::Range {
+    lsp_types::Range {
+        start: to_lsp_position(range.start),
+        end: to_lsp_position(range.end),
+    }
+}
+
+pub(super) const fn from_lsp_range(range: lsp_types::Range) -> TextRange {
+    TextRange {
+        start: Position {
+            line: range.start.line,
+            character: range.start.character,
+        },
+        end: Position {
+            line: range.end.line,
+            character: range.end.character,
+        },
+    }
+}
+
+/// Order a file's edits from the end of the file to the start, so that
+/// applying them one after another leaves the remaining ranges valid.
+/// Edits at the same position are reversed too, which keeps their combined
+/// result.
+pub(super) fn end_to_start(edits: Vec<lsp_types::TextEdit>) -> Vec<TextEdit> {
+    let mut edits: Vec<(usize, TextEdit)> = edits
+        .into_iter()
+        .map(|edit| TextEdit::new(from_lsp_range(edit.range), edit.new_text))
+        .enumerate()
+        .collect();
+    edits.sort_by_key(|(index, edit)| Reverse((edit.range.start, *index)));
+    edits.into_iter().map(|(_, edit)| edit).collect()
+}
+
+pub(super) fn to_diagnostic(
+    workspace: &Workspace,
+    diagnostic: lsp_types::Diagnostic,
+) -> Diagnostic {
+    // The spec leaves a missing severity to the client
+    let severity = match diagnostic.severity {
+        Some(DiagnosticSeverity::WARNING) => Severity::Warning,
+        Some(DiagnosticSeverity::INFORMATION) => Severity::Info,
+        Some(DiagnosticSeverity::HINT) => Severity::Hint,
+        _ => Severity::Error,
+    };
+    let mut result = Diagnostic::new(
+        from_lsp_range(diagnostic.range),
+        severity,
+        diagnostic.message,
+    );
+    result.code = match diagnostic.code {
+        Some(NumberOrString::Number(code)) => code.to_string(),
+        Some(NumberOrString::String(code)) => code,
+        None => String::new(),
+    };
+    result.source = diagnostic.source.unwrap_or_default();
+    result.related = diagnostic
+        .related_information
+        .into_iter()
+        .flatten()
+        .filter_map(|related| {
+            Some(RelatedInformation {
+                file_id: file_id(workspace, &related.location.uri)?,
+                range: from_lsp_range(related.location.range),
+                message: related.message,
+            })
+        })
+        .collect();
+    result.tags = diagnostic
+        .tags
+        .into_iter()
+        .flatten()
+        .filter_map(|tag| match tag {
+            lsp_types::DiagnosticTag::UNNECESSARY => Some(DiagnosticTag::Unnecessary),
+            lsp_types::DiagnosticTag::DEPRECATED => Some(DiagnosticTag::Deprecated),
+            _ => None,
+        })
+        .collect();
+    result
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use gouide_lsp::lsp_types::{DiagnosticRelatedInformation, Location};
+
+    #[test]
+    fn test_server_diagnostics_reach_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        let workspaces = WorkspaceManager::new();
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let store = DiagnosticsStore::new(16);
+        let root = workspace.root();
+        let uri = Url::from_file_path(root.join("src/main.rs")).unwrap();
+        let range = lsp_types::Range::new(
+            lsp_types::Position::new(0, 3),
+            lsp_types::Position::new(0, 7),
+        );
+
+        let mut warning = lsp_types::Diagnostic::new_simple(range, "unused".into());
+        warning.severity = Some(DiagnosticSeverity::WARNING);
+        warning.code = Some(NumberOrString::Number(42));
+        warning.tags = Some(vec![lsp_types::DiagnosticTag::UNNECESSARY]);
+        warning.related_information = Some(vec![
+            DiagnosticRelatedInformation {
+                location: Location::new(uri.clone(), range),
+                message: "here".into(),
+            },
+            DiagnosticRelatedInformation {
+                location: Location::new(Url::parse("file:///elsewhere.rs").unwrap(), range),
+                message: "outside".into(),
+            },
+        ]);
+        let event = |diagnostics| LspEvent::Diagnostics {
+            workspace_id: workspace.id().to_string(),
+            server: "fake".into(),
+            params: PublishDiagnosticsParams::new(uri.clone(), diagnostics, Some(3)),
+        };
+        handle_event(&workspaces, &store, event(vec![warning]), true);
+
+        let file = store.file(workspace.id(), "src/main.rs");
+        assert_eq!(file.version, 3);
+        let diagnostic = &file.diagnostics[0];
+        assert_eq!(diagnostic.severity, Severity::Warning);
+        assert_eq!(diagnostic.code, "42");
+        assert_eq!(diagnostic.tags, [DiagnosticTag::Unnecessary]);
+        assert_eq!(
+            diagnostic.range,
+            TextRange::new(Position::new(0, 3), Position::new(0, 7))
+        );
+        assert_eq!(diagnostic.related.len(), 1);
+        assert_eq!(diagnostic.related[0].file_id, "src/main.rs");
+        assert!(diagnostic.has_quick_fixes);
+
+        // Files outside the workspace are ignored
+        handle_event(
+            &workspaces,
+            &store,
+            LspEvent::Diagnostics {
+                workspace_id: workspace.id().to_string(),
+                server: "fake".into(),
+                params: PublishDiagnosticsParams::new(
+                    Url::parse("file:///elsewhere.rs").unwrap(),
+                    vec![lsp_types::Diagnostic::new_simple(range, "x".into())],
+                    None,
+                ),
+            },
+            false,
+        );
+        assert_eq!(store.workspace(workspace.id()).len(), 1);
+
+        // A stopped server's diagnostics are stale
+        handle_event(
+            &workspaces,
+            &store,
+            LspEvent::Stopped {
+                workspace_id: workspace.id().to_string(),
+                server: "fake".into(),
+            },
+            false,
+        );
+        assert!(store.workspace(workspace.id()).is_empty());
+    }
+}
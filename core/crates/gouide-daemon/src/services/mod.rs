@@ -1,7 +1,56 @@
 //! gRPC service implementations.
 
+use std::pin::Pin;
+
+use tokio_stream::Stream;
+use tonic::{Request, Status};
+
+mod buffer;
 mod control;
+mod convert;
+mod diagnostics;
+mod editor;
+mod edits;
+mod errors;
+mod format;
+mod git;
 mod handshake;
+mod language;
+mod lsp;
+mod search;
+mod settings;
+mod status;
+mod stream;
+mod sync;
+mod syntax;
+mod tree;
+mod workspace;
 
+pub use buffer::BufferService;
 pub use control::ControlService;
+pub use editor::EditorService;
+pub use git::GitService;
 pub use handshake::HandshakeService;
+pub use language::LanguageService;
+pub use lsp::LspBridge;
+pub use search::SearchService;
+pub use sync::BufferSync;
+pub use workspace::WorkspaceService;
+
+/// Boxed server-streaming response type shared by the services.
+pub(crate) type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Request metadata key carrying the client ID from the handshake.
+pub const CLIENT_ID_METADATA: &str = "x-gouide-client-id";
+
+/// Client session a request belongs to.
+///
+/// Requests without the metadata share one anonymous (empty) session.
+pub(crate) fn client_id<T>(request: &Request<T>) -> String {
+    request
+        .metadata()
+        .get(CLIENT_ID_METADATA)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
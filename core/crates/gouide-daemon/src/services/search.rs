@@ -0,0 +1,605 @@
+//! Search service implementation.
+//!
+//! A text search walks the workspace on a blocking thread pool and streams
+//! each file with matches as soon as it has been searched. Open buffers
+//! with unsaved edits are searched with their current text. Once a
+//! workspace's content index is complete, a search whose query pins down
+//! some trigrams only reads the files the index leaves for it, along with
+//! open buffers; until then it reads every file. Searches are
+//! registered with the [`RequestTracker`] under the client's request ID and
+//! stop when cancelled, when the client drops the stream, or once they
+//! have found the requested number of matches. Search and replace is in
+//! [`replace`], finding files by path in [`find`] and symbols by name in
+//! [`symbols`].
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use gouide_protocol::search_service_server::SearchService as SearchServiceTrait;
+use gouide_protocol::{
+    find_files_response, replace_in_files_response, search_workspace_symbols_response, DeltaType,
+    Error, FindFilesRequest, FindFilesResponse, ReplaceInFilesRequest, ReplaceInFilesResponse,
+    SearchTextRequest, SearchTextResponse, SearchWorkspaceSymbolsRequest,
+    SearchWorkspaceSymbolsResponse, StreamMeta,
+};
+use gouide_search::{FileMatches, Query, SearchOptions, SearchSummary, Searcher};
+use gouide_syntax::SyntaxManager;
+use gouide_workspace::WorkspaceManager;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+use tracing::debug;
+
+use super::client_id;
+use super::convert::to_proto_file_matches;
+use super::edits::WorkspaceEditor;
+use super::errors::{error, invalid_argument, workspace_error};
+use super::stream::StreamSender;
+use super::{BufferSync, ResponseStream};
+use crate::files::FileIndexes;
+use crate::languages::LanguageRegistry;
+use crate::requests::{RequestGuard, RequestTracker};
+
+mod find;
+mod replace;
+mod symbols;
+
+use replace::Previews;
+use symbols::BufferSymbols;
+
+/// Error source label for this service.
+const SOURCE: &str = "search";
+
+/// Files with matches queued between the search threads and the stream.
+const FILE_QUEUE: usize = 64;
+
+/// Search service for workspace-wide search.
+pub struct SearchService {
+    workspaces: Arc<WorkspaceManager>,
+    requests: Arc<RequestTracker>,
+    editor: WorkspaceEditor,
+    previews: Previews,
+    files: Arc<FileIndexes>,
+    symbols: Arc<BufferSymbols>,
+    max_results: u32,
+    find_max_results: u32,
+}
+
+/// An open buffer as a search sees it.
+struct OpenBuffer {
+    file_id: String,
+    buffer_id: String,
+    version: u64,
+    /// Text to search instead of the file, if any.
+    text: Option<String>,
+}
+
+impl SearchService {
+    /// Create a new search service. `max_results` limits text searches
+    /// whose request sets no limit, and `find_max_results` file and symbol
+    /// searches.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        workspaces: Arc<WorkspaceManager>,
+        sync: Arc<BufferSync>,
+        languages: Arc<LanguageRegistry>,
+        syntax: Arc<SyntaxManager>,
+        requests: Arc<RequestTracker>,
+        files: Arc<FileIndexes>,
+        max_results: u32,
+        find_max_results: u32,
+    ) -> Self {
+        let symbols = Arc::new(BufferSymbols::new(
+            workspaces.clone(),
+            languages,
+            syntax.clone(),
+            files.clone(),
+        ));
+        sync.observe(symbols.clone());
+        Self {
+            symbols,
+            editor: WorkspaceEditor::new(workspaces.clone(), syntax, sync),
+            workspaces,
+            requests,
+            previews: Previews::default(),
+            files,
+            max_results,
+            find_max_results,
+        }
+    }
+
+    /// A workspace's open buffers. Those with unsaved edits carry their
+    /// text, and with `all_text` every buffer does.
+    fn open_buffers(&self, workspace_id: &str, all_text: bool) -> Result<Vec<OpenBuffer>, Error> {
+        let buffers = self
+            .workspaces
+            .list_buffers(workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        Ok(buffers
+            .into_iter()
+            .map(|shared| {
+                let buffer = shared.read();
+                OpenBuffer {
+                    file_id: buffer.file_id().to_string(),
+                    buffer_id: buffer.id().to_string(),
+                    version: buffer.version(),
+                    text: (all_text || buffer.is_dirty()).then(|| buffer.text()),
+                }
+            })
+            .collect())
+    }
+
+    /// Prepare the search a request asks for, over the given open buffers.
+    fn searcher(&self, req: SearchTextRequest, buffers: &[OpenBuffer]) -> Result<Searcher, Error> {
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+        let workspace = self
+            .workspaces
+            .workspace(&workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let query = Query {
+            pattern: req.query,
+            is_regex: req.is_regex,
+            case_sensitive: req.case_sensitive,
+            whole_word: req.whole_word,
+        };
+        let include_ignored = req.include_ignored;
+        let mut exclude = req.exclude;
+        exclude.extend(workspace.exclude_patterns().iter().cloned());
+        let max_results = match req.max_results {
+            0 => self.max_results,
+            max => max,
+        };
+        let options = SearchOptions {
+            include: req.include,
+            exclude,
+            use_ignore_files: !include_ignored,
+            max_results: max_results as usize,
+            ..SearchOptions::default()
+        };
+        let contents: HashMap<_, _> = buffers
+            .iter()
+            .filter_map(|buffer| Some((buffer.file_id.clone(), buffer.text.clone()?)))
+            .collect();
+
+        let searcher = Searcher::new(workspace.root(), &query, options)
+            .map_err(|e| invalid_argument(e.to_string(), SOURCE))?
+            .with_contents(contents);
+        // The index leaves out ignored files
+        let trigrams = searcher.matcher().trigrams();
+        let candidates = if include_ignored || trigrams.is_empty() {
+            None
+        } else {
+            self.files.candidates(&workspace_id, trigrams)
+        };
+        Ok(match candidates {
+            Some(mut paths) => {
+                // Buffers may hold text their files lack, on disk or in an
+                // index the watcher has yet to update
+                paths.extend(buffers.iter().map(|buffer| buffer.file_id.clone()));
+                paths.sort_unstable();
+                paths.dedup();
+                searcher.with_candidates(paths)
+            }
+            None => searcher,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl SearchServiceTrait for SearchService {
+    type SearchTextStream = ResponseStream<SearchTextResponse>;
+
+    async fn search_text(
+        &self,
+        request: Request<SearchTextRequest>,
+    ) -> Result<Response<Self::SearchTextStream>, Status> {
+        let req = request.into_inner();
+        // Registered before returning, so a Cancel right after the call finds
+        // the request
+        let guard = self
+            .requests
+            .register(&req.request_id.clone().map(|r| r.value).unwrap_or_default());
+        let workspace_id = req
+            .workspace_id
+            .clone()
+            .map(|w| w.value)
+            .unwrap_or_default();
+        // Unsaved edits are only in the buffer
+        let searcher = self
+            .open_buffers(&workspace_id, false)
+            .and_then(|buffers| self.searcher(req, &buffers));
+
+        let (sender, stream) = StreamSender::channel();
+        tokio::spawn(async move {
+            match searcher {
+                Ok(searcher) => stream_search(searcher, guard, sender).await,
+                Err(error) => finish(sender, SearchSummary::default(), Some(error)).await,
+            }
+        });
+        Ok(Response::new(stream))
+    }
+
+    async fn replace_in_files(
+        &self,
+        request: Request<ReplaceInFilesRequest>,
+    ) -> Result<Response<ReplaceInFilesResponse>, Status> {
+        let session = client_id(&request);
+        let req = request.into_inner();
+        let result = if req.preview_id.is_empty() {
+            self.preview_replace(req).await
+        } else {
+            self.apply_replace(req, &session)
+        };
+        let result = match result {
+            Ok(success) => replace_in_files_response::Result::Success(success),
+            Err(error) => replace_in_files_response::Result::Error(error),
+        };
+        Ok(Response::new(ReplaceInFilesResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn find_files(
+        &self,
+        request: Request<FindFilesRequest>,
+    ) -> Result<Response<FindFilesResponse>, Status> {
+        let session = client_id(&request);
+        let result = match self.find_in_index(request.into_inner(), &session).await {
+            Ok(success) => find_files_response::Result::Success(success),
+            Err(error) => find_files_response::Result::Error(error),
+        };
+        Ok(Response::new(FindFilesResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn search_workspace_symbols(
+        &self,
+        request: Request<SearchWorkspaceSymbolsRequest>,
+    ) -> Result<Response<SearchWorkspaceSymbolsResponse>, Status> {
+        let session = client_id(&request);
+        let result = match self.find_symbols(request.into_inner(), &session).await {
+            Ok(success) => search_workspace_symbols_response::Result::Success(success),
+            Err(error) => search_workspace_symbols_response::Result::Error(error),
+        };
+        Ok(Response::new(SearchWorkspaceSymbolsResponse {
+            result: Some(result),
+        }))
+    }
+}
+
+/// Run a search and stream its results.
+async fn stream_search(
+    searcher: Searcher,
+    mut guard: RequestGuard,
+    mut sender: StreamSender<SearchTextResponse>,
+) {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (found, mut files) = mpsc::channel(FILE_QUEUE);
+    let search = {
+        let cancel = cancel.clone();
+        tokio::task::spawn_blocking(move || {
+            searcher.search(&cancel, |file| found.blocking_send(file).is_ok())
+        })
+    };
+
+    // Returning early drops the receiver, which stops the search threads
+    let outcome = loop {
+        tokio::select! {
+            file = files.recv() => match file {
+                Some(file) => {
+                    if !sender.send(file_message(file)).await {
+                        cancel.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                }
+                None => break None,
+            },
+            () = guard.cancelled() => break Some(cancelled()),
+            () = sender.closed() => {
+                cancel.store(true, Ordering::Relaxed);
+                return;
+            }
+        }
+    };
+    cancel.store(true, Ordering::Relaxed);
+    drop(files);
+
+    match search.await {
+        Ok(summary) => {
+            debug!(
+                files = summary.files_searched,
+                matches = summary.matches,
+                limit_hit = summary.limit_hit,
+                "Text search finished"
+            );
+            finish(sender, summary, outcome).await;
+        }
+        Err(e) => {
+            let error = error("SEARCH_FAILED", format!("Search task failed: {e}"), SOURCE);
+            finish(sender, SearchSummary::default(), Some(error)).await;
+        }
+    }
+}
+
+fn file_message(file: FileMatches) -> SearchTextResponse {
+    SearchTextResponse {
+        meta: Some(StreamMeta {
+            delta_type: DeltaType::Add as i32,
+            ..StreamMeta::default()
+        }),
+        file: Some(to_proto_file_matches(file)),
+        ..SearchTextResponse::default()
+    }
+}
+
+/// End a search stream with its summary and, if it failed, the error.
+async fn finish(
+    mut sender: StreamSender<SearchTextResponse>,
+    summary: SearchSummary,
+    error: Option<Error>,
+) {
+    sender
+        .send(SearchTextResponse {
+            meta: Some(StreamMeta {
+                delta_type: DeltaType::Update as i32,
+                is_final: true,
+                ..StreamMeta::default()
+            }),
+            file: None,
+            limit_hit: summary.limit_hit,
+            files_searched: u32::try_from(summary.files_searched).unwrap_or(u32::MAX),
+            match_count: u32::try_from(summary.matches).unwrap_or(u32::MAX),
+            error,
+        })
+        .await;
+}
+
+fn cancelled() -> Error {
+    error("CANCELLED", "The search was cancelled", SOURCE)
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use gouide_protocol::{RequestId, WorkspaceId};
+    use gouide_workspace::{Position, TextEdit, TextRange};
+    use std::fs;
+    use std::time::Duration;
+    use tokio_stream::StreamExt;
+
+    fn request(workspace_id: &str, query: &str) -> SearchTextRequest {
+        SearchTextRequest {
+            workspace_id: Some(WorkspaceId {
+                value: workspace_id.to_string(),
+            }),
+            query: query.to_string(),
+            ..SearchTextRequest::default()
+        }
+    }
+
+    /// A search service over `workspaces`, with the sync it publishes to.
+    pub(super) fn service(
+        workspaces: Arc<WorkspaceManager>,
+        requests: Arc<RequestTracker>,
+        max_results: u32,
+    ) -> (SearchService, Arc<BufferSync>) {
+        let sync = Arc::new(BufferSync::new(
+            workspaces.clone(),
+            Duration::from_millis(10),
+            16,
+        ));
+        let languages = Arc::new(LanguageRegistry::new());
+        let service = SearchService::new(
+            workspaces,
+            sync.clone(),
+            languages.clone(),
+            Arc::new(SyntaxManager::new()),
+            requests.clone(),
+            Arc::new(FileIndexes::new(requests, languages, None).with_content_index()),
+            max_results,
+            100,
+        );
+        (service, sync)
+    }
+
+    /// All messages of a search stream.
+    async fn collect(service: &SearchService, req: SearchTextRequest) -> Vec<SearchTextResponse> {
+        let stream = service
+            .search_text(Request::new(req))
+            .await
+            .unwrap()
+            .into_inner();
+        tokio::time::timeout(Duration::from_secs(5), stream.map(Result::unwrap).collect())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_search_streams_files_then_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(".gitignore"), "build/\n").unwrap();
+        fs::write(dir.path().join("a.rs"), "fn todo() {}\n// TODO: more\n").unwrap();
+        fs::write(dir.path().join("b.md"), "nothing\n").unwrap();
+        fs::create_dir(dir.path().join("build")).unwrap();
+        fs::write(dir.path().join("build/out.rs"), "todo\n").unwrap();
+        fs::create_dir(dir.path().join("vendor")).unwrap();
+        fs::write(dir.path().join("vendor/lib.rs"), "todo\n").unwrap();
+
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces
+            .open_workspace(dir.path(), None, vec!["vendor".to_string()])
+            .unwrap();
+        let (service, _sync) = service(workspaces.clone(), Arc::new(RequestTracker::new()), 100);
+
+        // Unsaved edits are searched instead of the file on disk
+        let shared = workspaces
+            .open_buffer(workspace.id(), "b.md", None, "")
+            .unwrap();
+        shared
+            .write()
+            .apply_edits(&[TextEdit::new(
+                TextRange::new(Position::new(0, 0), Position::new(0, 0)),
+                "todo ".to_string(),
+            )])
+            .unwrap();
+
+        let req = SearchTextRequest {
+            whole_word: true,
+            ..request(workspace.id(), "todo")
+        };
+        let messages = collect(&service, req).await;
+        let (summary, files) = messages.split_last().unwrap();
+        let mut found: Vec<_> = files
+            .iter()
+            .map(|m| {
+                let file = m.file.as_ref().unwrap();
+                (
+                    file.file_id.as_ref().unwrap().path.clone(),
+                    file.matches.len(),
+                )
+            })
+            .collect();
+        found.sort();
+        assert_eq!(found, [("a.rs".to_string(), 2), ("b.md".to_string(), 1)]);
+
+        let a = files
+            .iter()
+            .filter_map(|m| m.file.as_ref())
+            .find(|f| f.file_id.as_ref().unwrap().path == "a.rs")
+            .unwrap();
+        let second = &a.matches[1];
+        assert_eq!(second.preview, "// TODO: more");
+        assert_eq!((second.preview_start, second.preview_end), (3, 7));
+        assert_eq!(second.range.unwrap().start.unwrap().line, 1);
+
+        let meta = summary.meta.as_ref().unwrap();
+        assert!(meta.is_final);
+        assert!(summary.file.is_none() && summary.error.is_none());
+        assert_eq!(summary.match_count, 3);
+        assert!(!summary.limit_hit);
+
+        // The limit cuts the search short
+        let req = SearchTextRequest {
+            max_results: 1,
+            include_ignored: true,
+            ..request(workspace.id(), "todo")
+        };
+        let messages = collect(&service, req).await;
+        let summary = messages.last().unwrap();
+        assert_eq!(summary.match_count, 1);
+        assert!(summary.limit_hit);
+
+        // Invalid queries end the stream with an error
+        let req = SearchTextRequest {
+            is_regex: true,
+            ..request(workspace.id(), "(")
+        };
+        let messages = collect(&service, req).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].error.as_ref().unwrap().code, "INVALID_ARGUMENT");
+        let messages = collect(&service, request("missing", "todo")).await;
+        assert_eq!(
+            messages[0].error.as_ref().unwrap().code,
+            "WORKSPACE_NOT_FOUND"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_search_ends_with_error() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..200 {
+            fs::write(dir.path().join(format!("{i}.txt")), "match\n".repeat(10)).unwrap();
+        }
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let requests = Arc::new(RequestTracker::new());
+        let (service, _sync) = service(workspaces, requests.clone(), 0);
+
+        let req = SearchTextRequest {
+            request_id: Some(RequestId {
+                value: "search-1".to_string(),
+            }),
+            ..request(workspace.id(), "match")
+        };
+        let mut stream = service
+            .search_text(Request::new(req))
+            .await
+            .unwrap()
+            .into_inner();
+        // The queue fills up while nothing reads the stream
+        assert!(requests.cancel("search-1"));
+
+        let mut last = None;
+        while let Some(message) = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+        {
+            last = Some(message.unwrap());
+        }
+        let last = last.unwrap();
+        assert!(last.meta.unwrap().is_final);
+        assert_eq!(last.error.unwrap().code, "CANCELLED");
+        assert!(last.match_count < 2000);
+    }
+
+    #[tokio::test]
+    async fn test_complete_index_narrows_searched_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            fs::write(dir.path().join(format!("{i}.txt")), "filler\n").unwrap();
+        }
+        fs::write(dir.path().join("lib.rs"), "fn needle() {}\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let (service, _sync) = service(workspaces.clone(), Arc::new(RequestTracker::new()), 0);
+        let id = workspace.id();
+        let searched = |messages: &[SearchTextResponse]| {
+            let summary = messages.last().unwrap();
+            (summary.match_count, summary.files_searched)
+        };
+
+        // Every file is read until the index is complete
+        let messages = collect(&service, request(id, "needle")).await;
+        assert_eq!(searched(&messages), (1, 21));
+
+        service.files.open_workspace(&workspace);
+        for _ in 0..500 {
+            let state = service.files.status(id).unwrap().state;
+            if state == gouide_index::IndexState::Complete {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let messages = collect(&service, request(id, "needle")).await;
+        assert_eq!(searched(&messages), (1, 1));
+
+        // Open buffers are searched too
+        let shared = workspaces.open_buffer(id, "3.txt", None, "").unwrap();
+        shared
+            .write()
+            .apply_edits(&[TextEdit::new(
+                TextRange::new(Position::new(0, 0), Position::new(0, 0)),
+                "needle ".to_string(),
+            )])
+            .unwrap();
+        let messages = collect(&service, request(id, "needle")).await;
+        assert_eq!(searched(&messages), (2, 2));
+
+        // Too short to narrow by, or beyond the index
+        let messages = collect(&service, request(id, "ne")).await;
+        assert_eq!(searched(&messages).1, 21);
+        let req = SearchTextRequest {
+            include_ignored: true,
+            ..request(id, "needle")
+        };
+        let messages = collect(&service, req).await;
+        assert_eq!(searched(&messages), (2, 21));
+    }
+}
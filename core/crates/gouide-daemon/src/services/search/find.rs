@@ -0,0 +1,198 @@
+//! Finding files by a fuzzy match of their paths.
+//!
+//! Queries run against the workspace's [`FileIndexes`] entry on a blocking
+//! thread. Files the workspace has open rank higher, the most recently
+//! opened first. A client's new query for a workspace supersedes the one it
+//! has running, so typing ahead never queues work. Symbol queries run the
+//! same way.
+//!
+//! [`FileIndexes`]: crate::files::FileIndexes
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use gouide_index::FileQuery;
+use gouide_protocol::{Error, FileId, FindFilesRequest, FindFilesSuccess, FoundFile};
+
+use super::{cancelled, SearchService, SOURCE};
+use crate::files::QueryKind;
+use crate::services::errors::{error, workspace_error};
+
+impl SearchService {
+    /// Find a workspace's files for a client.
+    pub(super) async fn find_in_index(
+        &self,
+        req: FindFilesRequest,
+        session: &str,
+    ) -> Result<FindFilesSuccess, Error> {
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+        self.check_indexed(&workspace_id)?;
+        let mut buffers = self
+            .workspaces
+            .list_buffers(&workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?
+            .into_iter()
+            .map(|shared| {
+                let buffer = shared.read();
+                (buffer.opened_at(), buffer.file_id().to_string())
+            })
+            .collect::<Vec<_>>();
+        buffers.sort_unstable_by(|a, b| b.cmp(a));
+        let query = FileQuery {
+            pattern: req.query,
+            max_results: match req.max_results {
+                0 => self.find_max_results,
+                max => max,
+            } as usize,
+            recent: buffers.into_iter().map(|(_, file_id)| file_id).collect(),
+        };
+
+        let request_id = req.request_id.map(|r| r.value).unwrap_or_default();
+        let files = self.files.clone();
+        let found = self
+            .run_query(
+                QueryKind::Files,
+                session,
+                &workspace_id,
+                &request_id,
+                move |workspace_id, cancel| files.find(workspace_id, &query, cancel),
+            )
+            .await?;
+        Ok(FindFilesSuccess {
+            files: found
+                .matches
+                .into_iter()
+                .map(|found| FoundFile {
+                    file_id: Some(FileId { path: found.path }),
+                    score: found.score,
+                    highlights: found.positions,
+                })
+                .collect(),
+            complete: found.complete,
+            file_count: u32::try_from(found.file_count).unwrap_or(u32::MAX),
+        })
+    }
+
+    /// Fail unless a workspace is open and indexed.
+    pub(super) fn check_indexed(&self, workspace_id: &str) -> Result<(), Error> {
+        self.workspaces
+            .workspace(workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        if self.files.is_indexed(workspace_id) {
+            Ok(())
+        } else {
+            Err(error(
+                "NOT_INDEXED",
+                "The workspace's files are not indexed",
+                SOURCE,
+            ))
+        }
+    }
+
+    /// Run a client's query of a workspace's index on a blocking thread,
+    /// superseding its previous one of the same kind. `query` gets the
+    /// workspace ID and a flag to stop at, and returns `None` if it did.
+    pub(super) async fn run_query<T: Send + 'static>(
+        &self,
+        kind: QueryKind,
+        session: &str,
+        workspace_id: &str,
+        request_id: &str,
+        query: impl FnOnce(&str, &AtomicBool) -> Option<T> + Send + 'static,
+    ) -> Result<T, Error> {
+        let mut guard = self.requests.register(request_id);
+        let cancel = self.files.begin_query(kind, session, workspace_id);
+        let search = {
+            let (cancel, workspace_id) = (cancel.clone(), workspace_id.to_string());
+            tokio::task::spawn_blocking(move || query(&workspace_id, &cancel))
+        };
+        let found = tokio::select! {
+            found = search => found.map_err(|e| {
+                error("SEARCH_FAILED", format!("Index query task failed: {e}"), SOURCE)
+            }),
+            () = guard.cancelled() => {
+                cancel.store(true, Ordering::Relaxed);
+                Err(cancelled())
+            }
+        };
+        self.files.end_query(kind, session, workspace_id, &cancel);
+
+        // Nothing else stops a query of an indexed workspace
+        found?.ok_or_else(|| error("SUPERSEDED", "A newer query replaced this one", SOURCE))
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use crate::requests::RequestTracker;
+    use crate::services::search::tests::service;
+    use gouide_protocol::WorkspaceId;
+    use gouide_workspace::WorkspaceManager;
+    use std::fs;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn request(workspace_id: &str, query: &str) -> FindFilesRequest {
+        FindFilesRequest {
+            workspace_id: Some(WorkspaceId {
+                value: workspace_id.to_string(),
+            }),
+            query: query.to_string(),
+            ..FindFilesRequest::default()
+        }
+    }
+
+    fn paths(success: &FindFilesSuccess) -> Vec<&str> {
+        success
+            .files
+            .iter()
+            .map(|file| file.file_id.as_ref().unwrap().path.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_find_files_ranks_open_buffers_first() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/util")).unwrap();
+        fs::write(dir.path().join("src/util/mod.rs"), "").unwrap();
+        fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        fs::write(dir.path().join("src/model.rs"), "").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let (service, _) = service(workspaces.clone(), Arc::new(RequestTracker::new()), 100);
+        let id = workspace.id();
+
+        let Err(error) = service.find_in_index(request(id, "m"), "").await else {
+            panic!("Expected an error");
+        };
+        assert_eq!(error.code, "NOT_INDEXED");
+
+        service.files.open_workspace(&workspace);
+        let mut found = service.find_in_index(request(id, "mod"), "").await.unwrap();
+        for _ in 0..500 {
+            if found.complete {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            found = service.find_in_index(request(id, "mod"), "").await.unwrap();
+        }
+        assert!(found.complete);
+        assert_eq!(found.file_count, 3);
+        assert_eq!(paths(&found), ["src/model.rs", "src/util/mod.rs"]);
+        assert_eq!(found.files[0].highlights, [4, 5, 6]);
+
+        workspaces
+            .open_buffer(id, "src/util/mod.rs", None, "")
+            .unwrap();
+        let found = service.find_in_index(request(id, "mod"), "").await.unwrap();
+        assert_eq!(paths(&found), ["src/util/mod.rs", "src/model.rs"]);
+        let found = service.find_in_index(request(id, ""), "").await.unwrap();
+        assert_eq!(paths(&found), ["src/util/mod.rs"]);
+    }
+}
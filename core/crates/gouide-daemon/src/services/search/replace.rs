@@ -0,0 +1,691 @@
+//! Search and replace.
+//!
+//! A preview runs the search with the replacement and keeps its edits, by
+//! ID, with the version of each open buffer they were computed for. Applying
+//! a preview takes it, and puts it back if that fails, so a preview applies
+//! at most once; the apply checks every selected file before changing any:
+//! a buffer still at its previewed version is edited as is, anything else
+//! must still have the previewed matches, with the same replacements, at
+//! the same positions. Files that are not open are then saved, and written
+//! back if a later one fails, and the open buffers, locked since the check,
+//! are edited last, each in a single version.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use gouide_protocol::{
+    AppliedChange, AppliedChangeKind, BufferId, Error, FileId, FileReplacePreview, ReplaceEdit,
+    ReplaceInFilesRequest, ReplaceInFilesSuccess, ReplaceSelection, SearchTextRequest,
+};
+use gouide_search::{find_replacements, Matcher, TextMatch};
+use gouide_workspace::{
+    Buffer, Position, SaveOptions, SharedBuffer, TextEdit, TextRange, Workspace, WorkspaceError,
+};
+use parking_lot::Mutex;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::{cancelled, SearchService, SOURCE};
+use crate::services::convert::to_proto_text_match;
+use crate::services::edits::io_error;
+use crate::services::errors::{error, invalid_argument, workspace_error};
+
+/// Previews kept for applying; older ones are dropped.
+const MAX_PREVIEWS: usize = 16;
+
+/// A search and replace waiting to be applied.
+struct Preview {
+    workspace_id: String,
+    matcher: Matcher,
+    replacement: String,
+    files: BTreeMap<String, PreviewedFile>,
+}
+
+/// The edits a preview found in one file.
+struct PreviewedFile {
+    /// ID and version of the open buffer the edits were computed for.
+    buffer: Option<(String, u64)>,
+    /// Matches in file order, with the text replacing each.
+    edits: Vec<(TextMatch, String)>,
+}
+
+impl Preview {
+    /// Whether `text` still has a file's previewed matches and replacements.
+    fn still_matches(&self, text: &str, file: &PreviewedFile) -> bool {
+        find_replacements(&self.matcher, text, &self.replacement, file.edits.len()) == file.edits
+    }
+}
+
+/// The selected edits of a previewed file.
+struct Selected<'a> {
+    file_id: String,
+    file: &'a PreviewedFile,
+    /// Edits to apply, end to start.
+    edits: Vec<TextEdit>,
+}
+
+/// A file that is not open, edited in memory and not yet saved.
+struct EditedFile {
+    file_id: String,
+    path: PathBuf,
+    /// Contents before the edit, for writing back.
+    original: Vec<u8>,
+    buffer: Buffer,
+}
+
+/// Previews by ID, oldest first.
+#[derive(Default)]
+pub(super) struct Previews {
+    previews: Mutex<VecDeque<(String, Preview)>>,
+}
+
+impl Previews {
+    /// Keep a preview, returning its ID.
+    fn offer(&self, preview: Preview) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.restore(id.clone(), preview);
+        id
+    }
+
+    /// Take a preview for applying, so no other apply can use it.
+    fn take(&self, id: &str) -> Option<Preview> {
+        let mut previews = self.previews.lock();
+        let index = previews.iter().position(|(offered, _)| offered == id)?;
+        previews.remove(index).map(|(_, preview)| preview)
+    }
+
+    /// Put back a preview that failed to apply.
+    fn restore(&self, id: String, preview: Preview) {
+        let mut previews = self.previews.lock();
+        if previews.len() == MAX_PREVIEWS {
+            previews.pop_front();
+        }
+        previews.push_back((id, preview));
+    }
+}
+
+impl SearchService {
+    /// Search with the replacement and keep the edits for applying.
+    pub(super) async fn preview_replace(
+        &self,
+        req: ReplaceInFilesRequest,
+    ) -> Result<ReplaceInFilesSuccess, Error> {
+        let mut guard = self
+            .requests
+            .register(&req.request_id.map(|r| r.value).unwrap_or_default());
+        let workspace_id = req
+            .workspace_id
+            .clone()
+            .map(|w| w.value)
+            .unwrap_or_default();
+        // Edits to open buffers apply to their text, which is what must be
+        // searched even where it matches the file on disk
+        let buffers = self.open_buffers(&workspace_id, true)?;
+        let search = SearchTextRequest {
+            request_id: None,
+            workspace_id: req.workspace_id,
+            query: req.query,
+            is_regex: req.is_regex,
+            case_sensitive: req.case_sensitive,
+            whole_word: req.whole_word,
+            include: req.include,
+            exclude: req.exclude,
+            include_ignored: req.include_ignored,
+            max_results: req.max_results,
+        };
+        let searcher = self
+            .searcher(search, &buffers)?
+            .with_replacement(req.replacement.clone());
+        let matcher = searcher.matcher().clone();
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let found = Arc::new(Mutex::new(Vec::new()));
+        let search = {
+            let (cancel, found) = (cancel.clone(), found.clone());
+            tokio::task::spawn_blocking(move || {
+                searcher.search(&cancel, |file| {
+                    found.lock().push(file);
+                    true
+                })
+            })
+        };
+        let summary = tokio::select! {
+            summary = search => summary.map_err(|e| {
+                error("SEARCH_FAILED", format!("Search task failed: {e}"), SOURCE)
+            })?,
+            () = guard.cancelled() => {
+                cancel.store(true, Ordering::Relaxed);
+                return Err(cancelled());
+            }
+        };
+
+        let versions: HashMap<_, _> = buffers
+            .into_iter()
+            .map(|buffer| (buffer.file_id, (buffer.buffer_id, buffer.version)))
+            .collect();
+        let found = std::mem::take(&mut *found.lock());
+        let files: BTreeMap<_, _> = found
+            .into_iter()
+            .map(|file| {
+                let previewed = PreviewedFile {
+                    buffer: versions.get(&file.path).cloned(),
+                    edits: file.matches.into_iter().zip(file.replacements).collect(),
+                };
+                (file.path, previewed)
+            })
+            .collect();
+        let previews = files
+            .iter()
+            .map(|(file_id, file)| FileReplacePreview {
+                file_id: Some(FileId {
+                    path: file_id.clone(),
+                }),
+                version: file.buffer.as_ref().map_or(0, |(_, version)| *version),
+                edits: file
+                    .edits
+                    .iter()
+                    .map(|(found, new_text)| ReplaceEdit {
+                        text_match: Some(to_proto_text_match(found.clone())),
+                        new_text: new_text.clone(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        debug!(
+            files = files.len(),
+            matches = summary.matches,
+            limit_hit = summary.limit_hit,
+            "Replace previewed"
+        );
+
+        let preview_id = self.previews.offer(Preview {
+            workspace_id,
+            matcher,
+            replacement: req.replacement,
+            files,
+        });
+        Ok(ReplaceInFilesSuccess {
+            preview_id,
+            files: previews,
+            limit_hit: summary.limit_hit,
+            changes: Vec::new(),
+        })
+    }
+
+    /// Apply the selected edits of a preview, all or none. The preview is
+    /// only used up when that succeeds.
+    pub(super) fn apply_replace(
+        &self,
+        req: ReplaceInFilesRequest,
+        session: &str,
+    ) -> Result<ReplaceInFilesSuccess, Error> {
+        let preview = self.previews.take(&req.preview_id).ok_or_else(|| {
+            error(
+                "PREVIEW_NOT_FOUND",
+                format!(
+                    "Replace preview {} does not exist or was already applied",
+                    req.preview_id
+                ),
+                SOURCE,
+            )
+        })?;
+        let applied = self.apply_preview(&preview, req.selections, session);
+        if applied.is_err() {
+            self.previews.restore(req.preview_id, preview);
+        }
+        applied
+    }
+
+    fn apply_preview(
+        &self,
+        preview: &Preview,
+        selections: Vec<ReplaceSelection>,
+        session: &str,
+    ) -> Result<ReplaceInFilesSuccess, Error> {
+        let selected = select(&preview.files, selections)?;
+        let workspace = self
+            .workspaces
+            .workspace(&preview.workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let open: HashMap<String, SharedBuffer> = self
+            .workspaces
+            .list_buffers(workspace.id())
+            .map_err(|e| workspace_error(&e, SOURCE))?
+            .into_iter()
+            .map(|shared| {
+                let file_id = shared.read().file_id().to_string();
+                (file_id, shared)
+            })
+            .collect();
+
+        let mut buffers = Vec::new();
+        let mut files = Vec::new();
+        for selected in selected {
+            match open.get(&selected.file_id) {
+                Some(shared) => buffers.push((shared.clone(), selected)),
+                None => files.push(self.edit_file(&workspace, preview, selected)?),
+            }
+        }
+
+        // Locked in a fixed order so concurrent applies cannot deadlock
+        buffers.sort_by_cached_key(|(shared, _)| shared.read().id().to_string());
+        let mut locked: Vec<_> = buffers
+            .iter()
+            .map(|(shared, selected)| (shared.write(), selected))
+            .collect();
+        for (buffer, selected) in &locked {
+            if buffer.read_only() {
+                let err = WorkspaceError::ReadOnly(selected.file_id.clone());
+                return Err(workspace_error(&err, SOURCE));
+            }
+            let unchanged = selected
+                .file
+                .buffer
+                .as_ref()
+                .is_some_and(|(id, version)| id == buffer.id() && *version == buffer.version());
+            if !unchanged && !preview.still_matches(&buffer.text(), selected.file) {
+                return Err(stale(&selected.file_id));
+            }
+        }
+
+        let mut written = 0;
+        let saved = files.iter_mut().try_for_each(|file| {
+            file.buffer.save(SaveOptions::default())?;
+            written += 1;
+            Ok(())
+        });
+        if let Err(err) = saved {
+            write_back(&files[..written]);
+            return Err(workspace_error(&err, SOURCE));
+        }
+
+        let mut changes: Vec<_> = files
+            .iter()
+            .map(|file| AppliedChange {
+                kind: AppliedChangeKind::Edited as i32,
+                file_id: Some(FileId {
+                    path: file.file_id.clone(),
+                }),
+                ..AppliedChange::default()
+            })
+            .collect();
+        for (buffer, selected) in &mut locked {
+            let success = self
+                .editor
+                .edit_locked(buffer, &selected.edits, 0, true, session)
+                .map_err(|e| workspace_error(&e, SOURCE))?;
+            changes.push(AppliedChange {
+                kind: AppliedChangeKind::Edited as i32,
+                file_id: Some(FileId {
+                    path: selected.file_id.clone(),
+                }),
+                buffer_id: Some(BufferId {
+                    value: buffer.id().to_string(),
+                }),
+                version: success.version,
+                undo_checkpoint_created: success.undo_checkpoint_created,
+                ..AppliedChange::default()
+            });
+        }
+        drop(locked);
+
+        changes.sort_by(|a, b| {
+            a.file_id
+                .as_ref()
+                .map(|f| &f.path)
+                .cmp(&b.file_id.as_ref().map(|f| &f.path))
+        });
+        debug!(files = changes.len(), "Replace applied");
+        Ok(ReplaceInFilesSuccess {
+            changes,
+            ..ReplaceInFilesSuccess::default()
+        })
+    }
+
+    /// Load a file that is not open, check it and apply its edits in memory.
+    fn edit_file(
+        &self,
+        workspace: &Workspace,
+        preview: &Preview,
+        selected: Selected,
+    ) -> Result<EditedFile, Error> {
+        let file_id = selected.file_id;
+        let path = workspace
+            .resolve_path(&file_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let original = fs::read(&path).map_err(|e| io_error(e, &file_id, SOURCE))?;
+        let mut buffer = Buffer::load(
+            Uuid::new_v4().to_string(),
+            workspace.id().to_string(),
+            file_id.clone(),
+            &path,
+            self.workspaces.limits(),
+        )
+        .map_err(|e| workspace_error(&e, SOURCE))?;
+        if !preview.still_matches(&buffer.text(), selected.file) {
+            return Err(stale(&file_id));
+        }
+        buffer
+            .apply_edits(&selected.edits)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        Ok(EditedFile {
+            file_id,
+            path,
+            original,
+            buffer,
+        })
+    }
+}
+
+/// The edits to apply per file; no selections means all of them.
+fn select(
+    files: &BTreeMap<String, PreviewedFile>,
+    selections: Vec<ReplaceSelection>,
+) -> Result<Vec<Selected<'_>>, Error> {
+    let mut chosen: BTreeMap<String, BTreeSet<usize>> = BTreeMap::new();
+    if selections.is_empty() {
+        for (file_id, file) in files {
+            chosen.insert(file_id.clone(), (0..file.edits.len()).collect());
+        }
+    }
+    for selection in selections {
+        let file_id = selection.file_id.map(|f| f.path).unwrap_or_default();
+        let Some(file) = files.get(&file_id) else {
+            return Err(invalid_argument(
+                format!("{file_id} is not in the preview"),
+                SOURCE,
+            ));
+        };
+        let count = file.edits.len();
+        if let Some(index) = selection
+            .edit_indexes
+            .iter()
+            .find(|i| **i as usize >= count)
+        {
+            return Err(invalid_argument(
+                format!("{file_id} has no edit {index}"),
+                SOURCE,
+            ));
+        }
+        let indexes = chosen.entry(file_id).or_default();
+        if selection.edit_indexes.is_empty() {
+            indexes.extend(0..count);
+        }
+        indexes.extend(selection.edit_indexes.iter().map(|i| *i as usize));
+    }
+
+    Ok(chosen
+        .into_iter()
+        .filter_map(|(file_id, indexes)| {
+            let file = files.get(&file_id)?;
+            // Matches do not overlap, so later ones come first
+            let edits = indexes
+                .iter()
+                .rev()
+                .map(|&index| {
+                    let (found, new_text) = &file.edits[index];
+                    let range = TextRange::new(position(found.start), position(found.end));
+                    TextEdit::new(range, new_text.clone())
+                })
+                .collect();
+            Some(Selected {
+                file_id,
+                file,
+                edits,
+            })
+        })
+        .collect())
+}
+
+const fn position(position: gouide_search::Position) -> Position {
+    Position::new(position.line, position.character)
+}
+
+/// Restore files saved before a later save failed.
+fn write_back(files: &[EditedFile]) {
+    for file in files {
+        if let Err(e) = fs::write(&file.path, &file.original) {
+            warn!(file_id = %file.file_id, error = %e, "Failed to restore file after a failed replace");
+        }
+    }
+}
+
+fn stale(file_id: &str) -> Error {
+    error(
+        "PREVIEW_STALE",
+        format!("{file_id} changed since the replace was previewed. Preview it again."),
+        SOURCE,
+    )
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use crate::requests::RequestTracker;
+    use crate::services::search::tests::service;
+    use gouide_protocol::search_service_server::SearchService as _;
+    use gouide_protocol::{replace_in_files_response, BufferChangeType, WorkspaceId};
+    use gouide_workspace::WorkspaceManager;
+    use tonic::Request;
+
+    fn request(workspace_id: &str) -> ReplaceInFilesRequest {
+        ReplaceInFilesRequest {
+            workspace_id: Some(WorkspaceId {
+                value: workspace_id.to_string(),
+            }),
+            query: r"(\w+)\(\)".to_string(),
+            is_regex: true,
+            case_sensitive: true,
+            replacement: "$1(ctx)".to_string(),
+            ..ReplaceInFilesRequest::default()
+        }
+    }
+
+    fn apply(preview_id: &str, selections: Vec<ReplaceSelection>) -> ReplaceInFilesRequest {
+        ReplaceInFilesRequest {
+            preview_id: preview_id.to_string(),
+            selections,
+            ..ReplaceInFilesRequest::default()
+        }
+    }
+
+    fn selection(file_id: &str, edit_indexes: Vec<u32>) -> ReplaceSelection {
+        ReplaceSelection {
+            file_id: Some(FileId {
+                path: file_id.to_string(),
+            }),
+            edit_indexes,
+        }
+    }
+
+    async fn replace(
+        service: &SearchService,
+        req: ReplaceInFilesRequest,
+    ) -> Result<ReplaceInFilesSuccess, Error> {
+        let response = service
+            .replace_in_files(Request::new(req))
+            .await
+            .unwrap()
+            .into_inner();
+        match response.result.unwrap() {
+            replace_in_files_response::Result::Success(success) => Ok(success),
+            replace_in_files_response::Result::Error(error) => Err(error),
+        }
+    }
+
+    fn insert(line: u32, text: &str) -> TextEdit {
+        let at = Position::new(line, 0);
+        TextEdit::new(TextRange::new(at, at), text.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_preview_then_apply_selected_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.rs"), "fn foo() {}\nfn bar() {}\n").unwrap();
+        fs::write(dir.path().join("b.rs"), "foo();\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let (service, sync) = service(workspaces.clone(), Arc::new(RequestTracker::new()), 0);
+
+        // The open buffer has unsaved edits, which the preview sees
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "b.rs", None, "")
+            .unwrap();
+        buffer
+            .write()
+            .apply_edits(&[insert(1, "baz();\n")])
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let mut changes_rx = sync.subscribe(&buffer_id).unwrap();
+
+        let preview = replace(&service, request(workspace.id())).await.unwrap();
+        assert!(!preview.preview_id.is_empty() && preview.changes.is_empty());
+        let files: Vec<_> = preview
+            .files
+            .iter()
+            .map(|file| {
+                let edits: Vec<_> = file.edits.iter().map(|e| e.new_text.as_str()).collect();
+                (
+                    file.file_id.as_ref().unwrap().path.as_str(),
+                    file.version,
+                    edits,
+                )
+            })
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("a.rs", 0, vec!["foo(ctx)", "bar(ctx)"]),
+                ("b.rs", 2, vec!["foo(ctx)", "baz(ctx)"]),
+            ]
+        );
+        let second = preview.files[0].edits[1].text_match.as_ref().unwrap();
+        assert_eq!(second.preview, "fn bar() {}");
+        assert_eq!(second.range.unwrap().start.unwrap().line, 1);
+
+        // Only the second edit of a.rs, and all of b.rs
+        let applied = replace(
+            &service,
+            apply(
+                &preview.preview_id,
+                vec![selection("a.rs", vec![1]), selection("b.rs", vec![])],
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs")).unwrap(),
+            "fn foo() {}\nfn bar(ctx) {}\n"
+        );
+        assert_eq!(buffer.read().text(), "foo(ctx);\nbaz(ctx);\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("b.rs")).unwrap(),
+            "foo();\n"
+        );
+
+        let changes: Vec<_> = applied
+            .changes
+            .iter()
+            .map(|c| (c.file_id.as_ref().unwrap().path.as_str(), c.version))
+            .collect();
+        assert_eq!(changes, [("a.rs", 0), ("b.rs", 3)]);
+        assert_eq!(
+            applied.changes[1].buffer_id.as_ref().unwrap().value,
+            buffer_id
+        );
+        // The replace undoes as one step in the open buffer
+        assert!(applied.changes[1].undo_checkpoint_created);
+        assert_eq!(buffer.read().undo_checkpoints().collect::<Vec<_>>(), [3]);
+        // One version, so one edit message, for the whole buffer
+        let message = changes_rx.try_recv().unwrap();
+        assert_eq!(message.change_type, BufferChangeType::RemoteEdit as i32);
+        assert_eq!(message.edits.len(), 2);
+        assert!(changes_rx.try_recv().is_err());
+
+        // A preview applies once
+        let err = replace(&service, apply(&preview.preview_id, vec![]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "PREVIEW_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_stale_preview_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.rs"), "fn foo() {}\n").unwrap();
+        fs::write(dir.path().join("b.rs"), "foo();\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let (service, _sync) = service(workspaces.clone(), Arc::new(RequestTracker::new()), 0);
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "b.rs", None, "")
+            .unwrap();
+
+        // Edits after the preview that leave its matches alone are fine
+        let preview = replace(&service, request(workspace.id())).await.unwrap();
+        buffer
+            .write()
+            .apply_edits(&[insert(1, "// end\n")])
+            .unwrap();
+        replace(&service, apply(&preview.preview_id, vec![]))
+            .await
+            .unwrap();
+        assert_eq!(buffer.read().text(), "foo(ctx);\n// end\n");
+
+        // Edits that move a match make the whole apply fail
+        fs::write(dir.path().join("a.rs"), "fn foo() {}\nfn bar() {}\n").unwrap();
+        buffer.write().set_text("foo();\n").unwrap();
+        let preview = replace(&service, request(workspace.id())).await.unwrap();
+        buffer.write().apply_edits(&[insert(0, "\n")]).unwrap();
+        let err = replace(&service, apply(&preview.preview_id, vec![]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "PREVIEW_STALE");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs")).unwrap(),
+            "fn foo() {}\nfn bar() {}\n"
+        );
+
+        // A failed apply keeps the preview, which applies once the match is
+        // back where it was
+        buffer.write().set_text("foo();\n").unwrap();
+        replace(&service, apply(&preview.preview_id, vec![]))
+            .await
+            .unwrap();
+        assert_eq!(buffer.read().text(), "foo(ctx);\n");
+
+        // Selections must name previewed edits
+        fs::write(dir.path().join("a.rs"), "fn foo() {}\nfn bar() {}\n").unwrap();
+        let preview = replace(&service, request(workspace.id())).await.unwrap();
+        let err = replace(
+            &service,
+            apply(&preview.preview_id, vec![selection("a.rs", vec![2])]),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code, "INVALID_ARGUMENT");
+        replace(
+            &service,
+            apply(&preview.preview_id, vec![selection("a.rs", vec![1])]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs")).unwrap(),
+            "fn foo() {}\nfn bar(ctx) {}\n"
+        );
+        let err = replace(&service, apply("missing", vec![]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "PREVIEW_NOT_FOUND");
+    }
+}
@@ -0,0 +1,294 @@
+//! Finding symbols declared in a workspace by a fuzzy match of their names.
+//!
+//! The indexer extracts the symbols of every file it reads and re-reads
+//! files as the watcher reports them changed. Open buffers are tracked by
+//! [`BufferSymbols`]: edits only mark a buffer stale, and the next query
+//! outlines the stale buffers of its workspace from their syntax trees
+//! before searching, so typing costs nothing until someone asks.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use gouide_index::{declarations, SymbolQuery};
+use gouide_protocol::{
+    BufferChangeType, Error, SearchWorkspaceSymbolsRequest, SearchWorkspaceSymbolsSuccess,
+};
+use gouide_syntax::SyntaxManager;
+use gouide_workspace::{Buffer, TextEdit, WorkspaceManager};
+use parking_lot::Mutex;
+
+use super::SearchService;
+use crate::files::{FileIndexes, QueryKind};
+use crate::languages::LanguageRegistry;
+use crate::services::convert::to_proto_workspace_symbol;
+use crate::services::sync::BufferObserver;
+use crate::services::syntax::snapshot;
+
+/// Keeps the symbols of open buffers in the index.
+pub(crate) struct BufferSymbols {
+    workspaces: Arc<WorkspaceManager>,
+    languages: Arc<LanguageRegistry>,
+    syntax: Arc<SyntaxManager>,
+    files: Arc<FileIndexes>,
+    /// Buffers edited since their symbols were last set, by ID, with their
+    /// workspace and file.
+    stale: Mutex<HashMap<String, (String, String)>>,
+    /// Buffers whose symbols are set, by ID, with their workspace and file.
+    indexed: Mutex<HashMap<String, (String, String)>>,
+}
+
+impl BufferSymbols {
+    pub(crate) fn new(
+        workspaces: Arc<WorkspaceManager>,
+        languages: Arc<LanguageRegistry>,
+        syntax: Arc<SyntaxManager>,
+        files: Arc<FileIndexes>,
+    ) -> Self {
+        Self {
+            workspaces,
+            languages,
+            syntax,
+            files,
+            stale: Mutex::new(HashMap::new()),
+            indexed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set the symbols of a workspace's buffers edited since the last call.
+    pub(crate) fn refresh(&self, workspace_id: &str) {
+        let mut stale = self.stale.lock();
+        let buffers: Vec<_> = stale
+            .iter()
+            .filter(|(_, (workspace, _))| workspace == workspace_id)
+            .map(|(buffer_id, (_, file_id))| (buffer_id.clone(), file_id.clone()))
+            .collect();
+        for (buffer_id, _) in &buffers {
+            stale.remove(buffer_id);
+        }
+        drop(stale);
+
+        for (buffer_id, file_id) in buffers {
+            let Ok(snapshot) = snapshot(&self.workspaces, &self.languages, &buffer_id) else {
+                continue;
+            };
+            let symbols = snapshot
+                .language
+                .and_then(|language| {
+                    self.syntax
+                        .outline(&buffer_id, language, snapshot.version, &snapshot.text)
+                        .ok()
+                })
+                .map(|outline| declarations(&outline))
+                .unwrap_or_default();
+
+            // Under the lock, so a buffer closing meanwhile is not given
+            // symbols after `closed` cleared them
+            let mut indexed = self.indexed.lock();
+            if self.workspaces.buffer(&buffer_id).is_err() {
+                continue;
+            }
+            let entry = (workspace_id.to_string(), file_id.clone());
+            if let Some((_, renamed)) = indexed
+                .insert(buffer_id, entry)
+                .filter(|(_, previous)| *previous != file_id)
+            {
+                self.files.set_buffer_symbols(workspace_id, &renamed, None);
+            }
+            self.files
+                .set_buffer_symbols(workspace_id, &file_id, Some(symbols));
+            drop(indexed);
+        }
+    }
+
+    fn mark_stale(&self, buffer: &Buffer) {
+        let entry = (
+            buffer.workspace_id().to_string(),
+            buffer.file_id().to_string(),
+        );
+        self.stale.lock().insert(buffer.id().to_string(), entry);
+    }
+}
+
+impl BufferObserver for BufferSymbols {
+    // An opened buffer holds its file's text, whose symbols are indexed
+    fn opened(&self, _buffer: &Buffer) {}
+
+    fn edited(&self, buffer: &Buffer, _edits: &[TextEdit]) {
+        self.mark_stale(buffer);
+    }
+
+    fn changed(&self, buffer: &Buffer, _change: BufferChangeType) {
+        self.mark_stale(buffer);
+    }
+
+    fn saved(&self, _buffer: &Buffer) {}
+
+    fn closed(&self, buffer_id: &str) {
+        self.stale.lock().remove(buffer_id);
+        let mut indexed = self.indexed.lock();
+        if let Some((workspace_id, file_id)) = indexed.remove(buffer_id) {
+            self.files.set_buffer_symbols(&workspace_id, &file_id, None);
+        }
+        drop(indexed);
+    }
+
+    fn workspace_closed(&self, workspace_id: &str) {
+        self.stale
+            .lock()
+            .retain(|_, (workspace, _)| workspace != workspace_id);
+        self.indexed
+            .lock()
+            .retain(|_, (workspace, _)| workspace != workspace_id);
+    }
+}
+
+impl SearchService {
+    /// Find the symbols declared in a workspace for a client.
+    pub(super) async fn find_symbols(
+        &self,
+        req: SearchWorkspaceSymbolsRequest,
+        session: &str,
+    ) -> Result<SearchWorkspaceSymbolsSuccess, Error> {
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+        self.check_indexed(&workspace_id)?;
+        let query = SymbolQuery {
+            pattern: req.query,
+            max_results: match req.max_results {
+                0 => self.find_max_results,
+                max => max,
+            } as usize,
+        };
+
+        let request_id = req.request_id.map(|r| r.value).unwrap_or_default();
+        let (symbols, files) = (self.symbols.clone(), self.files.clone());
+        let found = self
+            .run_query(
+                QueryKind::Symbols,
+                session,
+                &workspace_id,
+                &request_id,
+                move |workspace_id, cancel| {
+                    symbols.refresh(workspace_id);
+                    files.find_symbols(workspace_id, &query, cancel)
+                },
+            )
+            .await?;
+        Ok(SearchWorkspaceSymbolsSuccess {
+            symbols: found
+                .matches
+                .into_iter()
+                .map(to_proto_workspace_symbol)
+                .collect(),
+            complete: found.complete,
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use crate::requests::RequestTracker;
+    use crate::services::search::tests::service;
+    use gouide_protocol::{SymbolKind as ProtoSymbolKind, WorkspaceId};
+    use gouide_workspace::{Position, TextRange};
+    use std::fs;
+    use std::time::Duration;
+
+    fn request(workspace_id: &str, query: &str) -> SearchWorkspaceSymbolsRequest {
+        SearchWorkspaceSymbolsRequest {
+            workspace_id: Some(WorkspaceId {
+                value: workspace_id.to_string(),
+            }),
+            query: query.to_string(),
+            ..SearchWorkspaceSymbolsRequest::default()
+        }
+    }
+
+    /// `path:container:name` of each symbol found.
+    fn found(success: &SearchWorkspaceSymbolsSuccess) -> Vec<String> {
+        success
+            .symbols
+            .iter()
+            .map(|symbol| {
+                let location = symbol.location.as_ref().unwrap();
+                let path = &location.file_id.as_ref().unwrap().path;
+                format!("{}:{}:{}", path, symbol.container_name, symbol.name)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_symbols_follow_files_and_buffers() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(
+            dir.path().join("src/lib.rs"),
+            "pub struct Parser;\n\nimpl Parser {\n    pub fn parse(&self) {}\n}\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("app.ts"), "export function render() {}\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let (service, sync) = service(workspaces.clone(), Arc::new(RequestTracker::new()), 100);
+        let id = workspace.id();
+
+        let Err(error) = service.find_symbols(request(id, "parse"), "").await else {
+            panic!("Expected an error");
+        };
+        assert_eq!(error.code, "NOT_INDEXED");
+
+        service.files.open_workspace(&workspace);
+        let mut success = service
+            .find_symbols(request(id, "parse"), "")
+            .await
+            .unwrap();
+        for _ in 0..500 {
+            if success.complete {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            success = service
+                .find_symbols(request(id, "parse"), "")
+                .await
+                .unwrap();
+        }
+        assert!(success.complete);
+        assert_eq!(
+            found(&success),
+            ["src/lib.rs:impl Parser:parse", "src/lib.rs::Parser"]
+        );
+        let parse = &success.symbols[0];
+        assert_eq!(parse.kind, ProtoSymbolKind::Method as i32);
+        assert_eq!(parse.highlights, [0, 1, 2, 3, 4]);
+        let range = parse.selection_range.unwrap();
+        assert_eq!(range.start.unwrap().line, 3);
+
+        // Unsaved edits are searched as they are
+        let buffer = workspaces.open_buffer(id, "src/lib.rs", None, "").unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let rename = TextEdit::new(
+            TextRange::new(Position::new(0, 11), Position::new(0, 17)),
+            "Lexer",
+        );
+        service
+            .editor
+            .edit_buffer(&buffer_id, &[rename], 1, false, "")
+            .unwrap();
+        let success = service.find_symbols(request(id, "lex"), "").await.unwrap();
+        assert_eq!(found(&success), ["src/lib.rs::Lexer"]);
+
+        // Closing the buffer goes back to the file
+        workspaces.close_buffer(&buffer_id, "", None, true).unwrap();
+        sync.forget_buffers(&[buffer_id]);
+        let success = service.find_symbols(request(id, "lex"), "").await.unwrap();
+        assert!(found(&success).is_empty());
+        let success = service.find_symbols(request(id, "rend"), "").await.unwrap();
+        assert_eq!(found(&success), ["app.ts::render"]);
+    }
+}
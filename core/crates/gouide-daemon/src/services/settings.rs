@@ -0,0 +1,214 @@
+//! Effective settings lookups and streams.
+//!
+//! Open buffers are resolved with their current text and other files with
+//! the start of the file on disk, so indentation is detected from what the
+//! user sees. A `WatchSettings` stream re-resolves its files whenever a
+//! settings file in the workspace changes, sends the ones whose settings
+//! differ, and ends when the workspace closes.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use gouide_protocol::{DeltaType, StreamMeta, WatchSettingsResponse};
+use gouide_workspace::{Buffer, TextRange, WorkspaceError, WorkspaceManager};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+
+use super::convert::to_proto_settings;
+use super::stream::StreamSender;
+use super::ResponseStream;
+use crate::languages::LanguageRegistry;
+use crate::settings::{EffectiveSettings, SettingsResolver, DETECT_LINES};
+
+/// Bytes of a file read to detect its indentation.
+const DETECT_BYTES: usize = 64 * 1024;
+
+/// Resolves settings for buffers and workspace files.
+#[derive(Clone)]
+pub(super) struct SettingsLookup {
+    workspaces: Arc<WorkspaceManager>,
+    languages: Arc<LanguageRegistry>,
+    settings: Arc<SettingsResolver>,
+}
+
+impl SettingsLookup {
+    pub(super) const fn new(
+        workspaces: Arc<WorkspaceManager>,
+        languages: Arc<LanguageRegistry>,
+        settings: Arc<SettingsResolver>,
+    ) -> Self {
+        Self {
+            workspaces,
+            languages,
+            settings,
+        }
+    }
+
+    /// Settings for an open buffer.
+    pub(super) fn buffer(&self, buffer: &Buffer) -> Result<EffectiveSettings, WorkspaceError> {
+        let workspace = self.workspaces.workspace(buffer.workspace_id())?;
+        let head = buffer.read_range(Some(TextRange::lines(0, DETECT_LINES)), DETECT_BYTES);
+        Ok(self.settings.resolve(
+            workspace.root(),
+            buffer.path(),
+            &self.languages.detect_buffer(buffer),
+            Some(&head.text),
+        ))
+    }
+
+    /// Settings for an open buffer about to be saved as another file.
+    pub(super) fn buffer_as(
+        &self,
+        buffer: &Buffer,
+        file_id: &str,
+    ) -> Result<EffectiveSettings, WorkspaceError> {
+        let workspace = self.workspaces.workspace(buffer.workspace_id())?;
+        let path = workspace.resolve_path(file_id)?;
+        let head = buffer.read_range(Some(TextRange::lines(0, DETECT_LINES)), DETECT_BYTES);
+        Ok(self.settings.resolve(
+            workspace.root(),
+            &path,
+            &self.languages.detect_path(workspace.id(), file_id),
+            Some(&head.text),
+        ))
+    }
+
+    /// Settings for a workspace file, which may be open, on disk or neither.
+    pub(super) fn file(
+        &self,
+        workspace_id: &str,
+        file_id: &str,
+    ) -> Result<EffectiveSettings, WorkspaceError> {
+        let workspace = self.workspaces.workspace(workspace_id)?;
+        let path = workspace.resolve_path(file_id)?;
+        let open = self
+            .workspaces
+            .list_buffers(workspace_id)?
+            .into_iter()
+            .find(|shared| shared.read().file_id() == file_id);
+        if let Some(shared) = open {
+            return self.buffer(&shared.read());
+        }
+        Ok(self.settings.resolve(
+            workspace.root(),
+            &path,
+            &self.languages.detect_path(workspace_id, file_id),
+            read_head(&path).as_deref(),
+        ))
+    }
+
+    /// Settings for several files, resolved on a blocking thread. `None`
+    /// once the workspace is gone.
+    async fn files(
+        &self,
+        workspace_id: &str,
+        file_ids: &[String],
+    ) -> Option<Vec<EffectiveSettings>> {
+        let lookup = self.clone();
+        let workspace_id = workspace_id.to_string();
+        let file_ids = file_ids.to_vec();
+        tokio::task::spawn_blocking(move || {
+            file_ids
+                .iter()
+                .map(|file_id| lookup.file(&workspace_id, file_id))
+                .collect::<Result<_, _>>()
+                .ok()
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+}
+
+/// The start of a file, or `None` if it cannot be read.
+fn read_head(path: &Path) -> Option<String> {
+    let mut head = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(DETECT_BYTES as u64)
+        .read_to_end(&mut head)
+        .ok()?;
+    Some(String::from_utf8_lossy(&head).into_owned())
+}
+
+fn message(
+    file_id: &str,
+    settings: &EffectiveSettings,
+    delta_type: DeltaType,
+) -> WatchSettingsResponse {
+    WatchSettingsResponse {
+        meta: Some(StreamMeta {
+            delta_type: delta_type as i32,
+            ..StreamMeta::default()
+        }),
+        settings: Some(to_proto_settings(file_id, settings)),
+    }
+}
+
+/// Start a settings stream for files of a workspace.
+///
+/// Subscribes before taking the snapshot, so no change is missed.
+pub(super) fn watch_settings(
+    lookup: SettingsLookup,
+    workspace_id: String,
+    file_ids: Vec<String>,
+) -> ResponseStream<WatchSettingsResponse> {
+    let (sender, stream) = StreamSender::channel();
+    let changes = lookup.settings.subscribe();
+    tokio::spawn(run(sender, lookup, workspace_id, file_ids, changes));
+    stream
+}
+
+async fn run(
+    mut sender: StreamSender<WatchSettingsResponse>,
+    lookup: SettingsLookup,
+    workspace_id: String,
+    file_ids: Vec<String>,
+    mut changes: broadcast::Receiver<String>,
+) {
+    let stream_id = sender.stream_id().to_string();
+    debug!(stream_id = %stream_id, "Watching settings");
+
+    let Some(mut current) = lookup.files(&workspace_id, &file_ids).await else {
+        return;
+    };
+    for (file_id, settings) in file_ids.iter().zip(&current) {
+        if !sender
+            .send(message(file_id, settings, DeltaType::Snapshot))
+            .await
+        {
+            return;
+        }
+    }
+
+    loop {
+        let change = tokio::select! {
+            change = changes.recv() => change,
+            () = sender.closed() => break,
+        };
+        match change {
+            Ok(changed) if changed == workspace_id => {
+                let Some(next) = lookup.files(&workspace_id, &file_ids).await else {
+                    break;
+                };
+                for ((file_id, old), new) in file_ids.iter().zip(&current).zip(&next) {
+                    if old != new && !sender.send(message(file_id, new, DeltaType::Update)).await {
+                        return;
+                    }
+                }
+                current = next;
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(skipped)) => {
+                debug!(stream_id = %stream_id, skipped, "Settings subscriber lagged");
+                sender.reset().await;
+                break;
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    debug!(stream_id = %stream_id, "Settings stream ended");
+}
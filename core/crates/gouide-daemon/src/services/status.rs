@@ -0,0 +1,126 @@
+//! Workspace status snapshots and streams.
+//!
+//! A status combines the workspace's indexing progress with its buffer and
+//! watcher state. A `WatchWorkspaceStatus` stream sends the current status,
+//! then follows the indexer: changes arriving within [`STATUS_INTERVAL`] of
+//! the last message are coalesced into the next one, which carries the
+//! latest status. The stream ends when the workspace closes.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use gouide_index::IndexStatus;
+use gouide_protocol::{DeltaType, StreamMeta, WatchWorkspaceStatusResponse, WorkspaceStatus};
+use gouide_workspace::{WorkspaceError, WorkspaceManager};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::debug;
+
+use super::convert::{current_timestamp, to_proto_indexing_state};
+use super::stream::StreamSender;
+use super::{BufferSync, ResponseStream};
+use crate::files::FileIndexes;
+
+/// Shortest time between two status messages of a stream.
+const STATUS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Builds status snapshots of workspaces.
+#[derive(Clone)]
+pub(super) struct StatusLookup {
+    workspaces: Arc<WorkspaceManager>,
+    sync: Arc<BufferSync>,
+    files: Arc<FileIndexes>,
+}
+
+impl StatusLookup {
+    pub(super) const fn new(
+        workspaces: Arc<WorkspaceManager>,
+        sync: Arc<BufferSync>,
+        files: Arc<FileIndexes>,
+    ) -> Self {
+        Self {
+            workspaces,
+            sync,
+            files,
+        }
+    }
+
+    /// Current status of a workspace.
+    pub(super) fn status(&self, workspace_id: &str) -> Result<WorkspaceStatus, WorkspaceError> {
+        let indexing = self.files.status(workspace_id).unwrap_or_default();
+        self.with_indexing(workspace_id, &indexing)
+    }
+
+    /// Status of a workspace with the given indexing status.
+    pub(super) fn with_indexing(
+        &self,
+        workspace_id: &str,
+        indexing: &IndexStatus,
+    ) -> Result<WorkspaceStatus, WorkspaceError> {
+        self.workspaces.workspace(workspace_id)?;
+        Ok(WorkspaceStatus {
+            indexing_state: to_proto_indexing_state(indexing.state),
+            indexing_progress: indexing.progress,
+            file_count: indexing.file_count as u64,
+            open_buffer_count: u32::try_from(self.workspaces.buffer_count(workspace_id))
+                .unwrap_or(u32::MAX),
+            watcher_active: self.sync.is_watching(workspace_id),
+            last_updated: Some(current_timestamp()),
+            indexing_error: indexing.error.clone().unwrap_or_default(),
+        })
+    }
+}
+
+/// Stream the status of a workspace, following `changes` from its indexer.
+pub(super) fn watch_status(
+    lookup: StatusLookup,
+    workspace_id: String,
+    changes: watch::Receiver<IndexStatus>,
+) -> ResponseStream<WatchWorkspaceStatusResponse> {
+    let (sender, stream) = StreamSender::channel();
+    tokio::spawn(run(sender, lookup, workspace_id, changes));
+    stream
+}
+
+async fn run(
+    mut sender: StreamSender<WatchWorkspaceStatusResponse>,
+    lookup: StatusLookup,
+    workspace_id: String,
+    mut changes: watch::Receiver<IndexStatus>,
+) {
+    let stream_id = sender.stream_id().to_string();
+    debug!(stream_id = %stream_id, workspace_id = %workspace_id, "Watching workspace status");
+
+    let mut delta_type = DeltaType::Snapshot;
+    loop {
+        let indexing = changes.borrow_and_update().clone();
+        let Ok(status) = lookup.with_indexing(&workspace_id, &indexing) else {
+            break;
+        };
+        let message = WatchWorkspaceStatusResponse {
+            meta: Some(StreamMeta {
+                delta_type: delta_type as i32,
+                ..StreamMeta::default()
+            }),
+            status: Some(status),
+        };
+        if !sender.send(message).await {
+            break;
+        }
+        delta_type = DeltaType::Update;
+
+        let next = Instant::now() + STATUS_INTERVAL;
+        let changed = tokio::select! {
+            changed = changes.changed() => changed,
+            () = sender.closed() => break,
+        };
+        if changed.is_err() {
+            break;
+        }
+        tokio::select! {
+            () = tokio::time::sleep_until(next) => {}
+            () = sender.closed() => break,
+        }
+    }
+    debug!(stream_id = %stream_id, "Workspace status stream ended");
+}
@@ -0,0 +1,236 @@
+//! Bounded, sequenced server streams.
+//!
+//! Events are published on a `tokio::sync::broadcast` channel per subject
+//! (for example one per buffer). Each subscriber gets its own forwarding task
+//! that stamps messages with [`StreamMeta`] (a per-stream sequence number,
+//! stream ID and timestamp) and hands them to gRPC through a small bounded
+//! queue. A subscriber that falls further behind than the broadcast capacity
+//! receives a final `DELTA_TYPE_RESET_REQUIRED` message and the stream ends;
+//! the client re-fetches state and subscribes again.
+//!
+//! Messages a subscriber should not see (for example its own edits echoed
+//! back) are filtered out before sequencing, so sequence numbers stay gapless.
+
+use gouide_protocol::{
+    CompletionResponse, DeltaType, SearchTextResponse, StreamMeta, WatchBufferChangesResponse,
+    WatchDiagnosticsResponse, WatchDiffResponse, WatchFileTreeResponse, WatchSettingsResponse,
+    WatchSyntaxTokensResponse, WatchWorkspaceStatusResponse,
+};
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+use tracing::debug;
+use uuid::Uuid;
+
+use super::convert::current_timestamp;
+use super::ResponseStream;
+
+/// Messages queued per subscriber between the forwarder and gRPC.
+const SUBSCRIBER_QUEUE: usize = 16;
+
+/// A streamed response message carrying [`StreamMeta`].
+pub(crate) trait StreamMessage: Clone + Default + Send + 'static {
+    /// Mutable access to the message's stream metadata.
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta>;
+}
+
+impl StreamMessage for WatchBufferChangesResponse {
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta> {
+        &mut self.meta
+    }
+}
+
+impl StreamMessage for WatchSyntaxTokensResponse {
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta> {
+        &mut self.meta
+    }
+}
+
+impl StreamMessage for WatchDiagnosticsResponse {
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta> {
+        &mut self.meta
+    }
+}
+
+impl StreamMessage for WatchSettingsResponse {
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta> {
+        &mut self.meta
+    }
+}
+
+impl StreamMessage for WatchWorkspaceStatusResponse {
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta> {
+        &mut self.meta
+    }
+}
+
+impl StreamMessage for WatchFileTreeResponse {
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta> {
+        &mut self.meta
+    }
+}
+
+impl StreamMessage for WatchDiffResponse {
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta> {
+        &mut self.meta
+    }
+}
+
+impl StreamMessage for CompletionResponse {
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta> {
+        &mut self.meta
+    }
+}
+
+impl StreamMessage for SearchTextResponse {
+    fn meta_mut(&mut self) -> &mut Option<StreamMeta> {
+        &mut self.meta
+    }
+}
+
+/// Sending half of a sequenced response stream.
+pub(crate) struct StreamSender<T> {
+    tx: mpsc::Sender<Result<T, Status>>,
+    stream_id: String,
+    sequence: u64,
+}
+
+impl<T: StreamMessage> StreamSender<T> {
+    /// Create a stream with a new ID.
+    pub(crate) fn channel() -> (Self, ResponseStream<T>) {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_QUEUE);
+        let sender = Self {
+            tx,
+            stream_id: Uuid::new_v4().to_string(),
+            sequence: 0,
+        };
+        (sender, Box::pin(ReceiverStream::new(rx)))
+    }
+
+    /// The stream's ID, as stamped on its messages.
+    pub(crate) fn stream_id(&self) -> &str {
+        &self.stream_id
+    }
+
+    /// Stamp and send a message. Returns false if the client went away.
+    pub(crate) async fn send(&mut self, mut message: T) -> bool {
+        self.sequence += 1;
+        stamp(&mut message, &self.stream_id, self.sequence);
+        self.tx.send(Ok(message)).await.is_ok()
+    }
+
+    /// Send a final `DELTA_TYPE_RESET_REQUIRED` message.
+    pub(crate) async fn reset(mut self) {
+        let mut reset = T::default();
+        *reset.meta_mut() = Some(StreamMeta {
+            delta_type: DeltaType::ResetRequired as i32,
+            is_final: true,
+            ..StreamMeta::default()
+        });
+        self.send(reset).await;
+    }
+
+    /// Wait until the client goes away.
+    pub(crate) async fn closed(&self) {
+        self.tx.closed().await;
+    }
+}
+
+/// Stamp stream metadata onto a message, keeping a delta type set by the
+/// publisher.
+fn stamp<T: StreamMessage>(message: &mut T, stream_id: &str, sequence: u64) {
+    let meta = message.meta_mut().get_or_insert_with(|| StreamMeta {
+        delta_type: DeltaType::Update as i32,
+        ..StreamMeta::default()
+    });
+    meta.sequence = sequence;
+    meta.stream_id = stream_id.to_string();
+    meta.timestamp = Some(current_timestamp());
+}
+
+/// Forward a broadcast subscription to a gRPC response stream, skipping
+/// messages for which `skip` returns true.
+///
+/// The stream ends when the publisher is dropped, when the client goes away,
+/// or after a reset message if the subscriber lagged.
+pub(crate) fn forward<T, F>(mut rx: broadcast::Receiver<T>, skip: F) -> ResponseStream<T>
+where
+    T: StreamMessage,
+    F: Fn(&T) -> bool + Send + 'static,
+{
+    let (mut sender, out) = StreamSender::channel();
+
+    tokio::spawn(async move {
+        loop {
+            let message = match rx.recv().await {
+                Ok(message) if skip(&message) => continue,
+                Ok(message) => message,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!(stream_id = %sender.stream_id(), skipped, "Stream subscriber lagged");
+                    sender.reset().await;
+                    break;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if !sender.send(message).await {
+                break;
+            }
+        }
+    });
+
+    out
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use tokio_stream::StreamExt;
+
+    use super::*;
+
+    fn message(version: u64) -> WatchBufferChangesResponse {
+        WatchBufferChangesResponse {
+            version,
+            ..WatchBufferChangesResponse::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward_sequences_messages() {
+        let (tx, rx) = broadcast::channel(8);
+        let mut stream = forward(rx, |m: &WatchBufferChangesResponse| m.version == 2);
+        tx.send(message(1)).unwrap();
+        tx.send(message(2)).unwrap();
+        tx.send(message(3)).unwrap();
+        drop(tx);
+
+        let first = stream.next().await.unwrap().unwrap();
+        let second = stream.next().await.unwrap().unwrap();
+        let (first_meta, second_meta) = (first.meta.unwrap(), second.meta.unwrap());
+        assert_eq!((first_meta.sequence, second_meta.sequence), (1, 2));
+        assert_eq!(first_meta.stream_id, second_meta.stream_id);
+        assert_eq!(first_meta.delta_type, DeltaType::Update as i32);
+        assert_eq!(second.version, 3);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscriber_gets_reset() {
+        let (tx, rx) = broadcast::channel(2);
+        for version in 0..5 {
+            tx.send(message(version)).unwrap();
+        }
+        let mut stream = forward(rx, |_| false);
+
+        let reset = stream.next().await.unwrap().unwrap();
+        let meta = reset.meta.unwrap();
+        assert_eq!(meta.delta_type, DeltaType::ResetRequired as i32);
+        assert!(meta.is_final);
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -0,0 +1,479 @@
+//! Keeping open buffers in sync with the file system and between clients.
+//!
+//! Each open workspace gets a [`FileWatcher`]. When files change underneath
+//! open buffers (another editor, a formatter, `git checkout`), clean buffers
+//! are reloaded and dirty ones are flagged as conflicting. Every change, and
+//! every edit a client applies to a shared buffer, is published to the
+//! buffer's `WatchBufferChanges` subscribers.
+//!
+//! Each batch of file system events is also published per workspace, for
+//! the file tree streams.
+//!
+//! Changes are published while the buffer's write lock is held, so
+//! subscribers see versions in order. The same holds for
+//! [`BufferObserver`]s, which additionally hear about opens and saves.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use gouide_fs::{FileWatcher, FsEvent, FsEventKind, WatchOptions};
+use gouide_protocol::{BufferChangeType, FileId, WatchBufferChangesResponse};
+use gouide_workspace::{Buffer, DiskChange, TextEdit, Workspace, WorkspaceError, WorkspaceManager};
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use super::convert::{to_proto_edit, to_timestamp};
+
+/// Watches open workspaces and publishes buffer changes.
+pub struct BufferSync {
+    workspaces: Arc<WorkspaceManager>,
+    options: WatchOptions,
+    capacity: usize,
+    watchers: Mutex<HashMap<String, FileWatcher>>,
+    channels: Mutex<HashMap<String, broadcast::Sender<WatchBufferChangesResponse>>>,
+    /// Event batches by workspace.
+    file_channels: Mutex<HashMap<String, broadcast::Sender<FileEvents>>>,
+    close_hooks: Mutex<Vec<CloseHook>>,
+    file_hooks: Mutex<Vec<FileHook>>,
+    observers: Mutex<Vec<Arc<dyn BufferObserver>>>,
+}
+
+/// A batch of file system events in a workspace.
+pub(crate) type FileEvents = Arc<Vec<FsEvent>>;
+
+/// Callback run when a buffer is closed.
+type CloseHook = Box<dyn Fn(&str) + Send + Sync>;
+
+/// Callback run with a workspace ID and a batch of file system events in it.
+type FileHook = Box<dyn Fn(&str, &[FsEvent]) + Send + Sync>;
+
+/// Receives the life cycle and every change of open buffers, in version
+/// order, with the buffer locked.
+pub(crate) trait BufferObserver: Send + Sync {
+    /// A buffer was opened (also called when another client joins it).
+    fn opened(&self, buffer: &Buffer);
+    /// A client applied edits. They apply in order, each to the text left
+    /// by the previous one.
+    fn edited(&self, buffer: &Buffer, edits: &[TextEdit]);
+    /// The buffer changed as a whole: reloaded, replaced or renamed.
+    fn changed(&self, buffer: &Buffer, change: BufferChangeType);
+    /// The buffer was saved.
+    fn saved(&self, buffer: &Buffer);
+    /// A buffer was closed.
+    fn closed(&self, buffer_id: &str);
+    /// A workspace was closed (before its buffers are).
+    fn workspace_closed(&self, workspace_id: &str);
+}
+
+impl BufferSync {
+    /// Create a buffer sync with the given watcher debounce and per-buffer
+    /// event capacity.
+    pub fn new(workspaces: Arc<WorkspaceManager>, debounce: Duration, capacity: usize) -> Self {
+        Self {
+            workspaces,
+            options: WatchOptions { debounce },
+            capacity: capacity.max(1),
+            watchers: Mutex::new(HashMap::new()),
+            channels: Mutex::new(HashMap::new()),
+            file_channels: Mutex::new(HashMap::new()),
+            close_hooks: Mutex::new(Vec::new()),
+            file_hooks: Mutex::new(Vec::new()),
+            observers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Start watching a workspace. Does nothing if it is already watched.
+    ///
+    /// A watcher that cannot be started is logged and the workspace stays
+    /// usable without external change detection.
+    pub fn watch_workspace(self: &Arc<Self>, workspace: &Workspace) {
+        let mut watchers = self.watchers.lock();
+        if watchers.contains_key(workspace.id()) {
+            return;
+        }
+
+        let sync: Weak<Self> = Arc::downgrade(self);
+        let workspace_id = workspace.id().to_string();
+        let handler = move |events: Vec<FsEvent>| {
+            if let Some(sync) = sync.upgrade() {
+                sync.handle_events(&workspace_id, &events);
+            }
+        };
+        match FileWatcher::watch(workspace.root(), self.options, handler) {
+            Ok(watcher) => {
+                watchers.insert(workspace.id().to_string(), watcher);
+                drop(watchers);
+            }
+            Err(e) => {
+                drop(watchers);
+                warn!(
+                    workspace_id = %workspace.id(),
+                    error = %e,
+                    "Failed to start file watcher"
+                );
+            }
+        }
+    }
+
+    /// Stop watching a closed workspace.
+    pub fn unwatch_workspace(&self, workspace_id: &str) {
+        if self.watchers.lock().remove(workspace_id).is_some() {
+            debug!(workspace_id = %workspace_id, "File watcher stopped");
+        }
+        self.file_channels.lock().remove(workspace_id);
+        for observer in self.observers() {
+            observer.workspace_closed(workspace_id);
+        }
+    }
+
+    /// Whether a workspace has an active file watcher.
+    pub fn is_watching(&self, workspace_id: &str) -> bool {
+        self.watchers.lock().contains_key(workspace_id)
+    }
+
+    /// Subscribe to changes of an open buffer.
+    pub fn subscribe(
+        &self,
+        buffer_id: &str,
+    ) -> Result<broadcast::Receiver<WatchBufferChangesResponse>, WorkspaceError> {
+        self.workspaces.buffer(buffer_id)?;
+        Ok(self
+            .channels
+            .lock()
+            .entry(buffer_id.to_string())
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe())
+    }
+
+    /// Subscribe to the file system events of a workspace. The receiver
+    /// sees its sender dropped when the workspace is no longer watched.
+    pub(crate) fn subscribe_files(
+        &self,
+        workspace_id: &str,
+    ) -> Result<broadcast::Receiver<FileEvents>, WorkspaceError> {
+        self.workspaces.workspace(workspace_id)?;
+        Ok(self
+            .file_channels
+            .lock()
+            .entry(workspace_id.to_string())
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe())
+    }
+
+    /// Register a callback to run with the ID of every buffer that is closed,
+    /// so per-buffer state kept elsewhere can be released.
+    pub fn on_buffer_closed(&self, hook: impl Fn(&str) + Send + Sync + 'static) {
+        self.close_hooks.lock().push(Box::new(hook));
+    }
+
+    /// Register a callback to run with every batch of file system events in
+    /// a watched workspace, whether or not the files are open.
+    pub fn on_files_changed(&self, hook: impl Fn(&str, &[FsEvent]) + Send + Sync + 'static) {
+        self.file_hooks.lock().push(Box::new(hook));
+    }
+
+    /// Register an observer of every open buffer.
+    pub(crate) fn observe(&self, observer: Arc<dyn BufferObserver>) {
+        self.observers.lock().push(observer);
+    }
+
+    fn observers(&self) -> Vec<Arc<dyn BufferObserver>> {
+        self.observers.lock().clone()
+    }
+
+    /// End the change streams of buffers that were closed and run the close
+    /// callbacks.
+    pub fn forget_buffers(&self, buffer_ids: &[String]) {
+        let mut channels = self.channels.lock();
+        for id in buffer_ids {
+            channels.remove(id);
+        }
+        drop(channels);
+        let hooks = self.close_hooks.lock();
+        for id in buffer_ids {
+            for hook in hooks.iter() {
+                hook(id);
+            }
+        }
+        drop(hooks);
+        for observer in self.observers() {
+            for id in buffer_ids {
+                observer.closed(id);
+            }
+        }
+    }
+
+    /// Handle changes the daemon made to files itself, as if the watcher
+    /// had reported them, so they show without waiting for it.
+    pub(crate) fn files_changed(&self, workspace_id: &str, events: &[FsEvent]) {
+        self.handle_events(workspace_id, events);
+    }
+
+    /// Run the file callbacks for a batch of file system events, then apply
+    /// it to the workspace's open buffers.
+    fn handle_events(&self, workspace_id: &str, events: &[FsEvent]) {
+        for hook in self.file_hooks.lock().iter() {
+            hook(workspace_id, events);
+        }
+        if let Some(channel) = self.file_channels.lock().get(workspace_id) {
+            // No receivers is fine: nobody is watching the tree right now
+            let _ = channel.send(Arc::new(events.to_vec()));
+        }
+        let Ok(workspace) = self.workspaces.workspace(workspace_id) else {
+            return;
+        };
+        let Ok(buffers) = self.workspaces.list_buffers(workspace_id) else {
+            return;
+        };
+        if buffers.is_empty() {
+            return;
+        }
+
+        for event in events {
+            let Some(file_id) = workspace.file_id_of(&event.path) else {
+                continue;
+            };
+            for shared in &buffers {
+                let mut buffer = shared.write();
+                let change = match &event.kind {
+                    FsEventKind::Renamed { from } => {
+                        if let Some(moved) = moved_file_id(&workspace, from, &file_id, &buffer) {
+                            let path = workspace.root().join(&moved);
+                            buffer.rebind(moved, path);
+                            Some(BufferChangeType::Renamed)
+                        } else if is_under(buffer.file_id(), &file_id) {
+                            // Another file was moved over this one
+                            self.sync_buffer(&mut buffer)
+                        } else {
+                            None
+                        }
+                    }
+                    _ if is_under(buffer.file_id(), &file_id) => self.sync_buffer(&mut buffer),
+                    _ => None,
+                };
+                let Some(change) = change else {
+                    continue;
+                };
+
+                info!(
+                    buffer_id = %buffer.id(),
+                    file_id = %buffer.file_id(),
+                    change = ?change,
+                    version = buffer.version(),
+                    "Buffer changed on disk"
+                );
+                self.publish_change(&buffer, change, "");
+            }
+        }
+    }
+
+    /// Reconcile one buffer with disk, returning the change to report.
+    fn sync_buffer(&self, buffer: &mut Buffer) -> Option<BufferChangeType> {
+        match buffer.sync_with_disk(self.workspaces.limits()) {
+            Ok(change) => change_type(change),
+            Err(e) => {
+                warn!(buffer_id = %buffer.id(), error = %e, "Failed to sync buffer");
+                None
+            }
+        }
+    }
+
+    /// Tell observers a client opened the buffer.
+    pub(crate) fn publish_opened(&self, buffer: &Buffer) {
+        for observer in self.observers() {
+            observer.opened(buffer);
+        }
+    }
+
+    /// Tell observers the buffer was saved.
+    pub(crate) fn publish_saved(&self, buffer: &Buffer) {
+        for observer in self.observers() {
+            observer.saved(buffer);
+        }
+    }
+
+    /// Publish a change to the buffer's current state, caused by `origin`.
+    ///
+    /// `MODIFIED` carries the new content, up to the chunk size.
+    pub(crate) fn publish_change(&self, buffer: &Buffer, change: BufferChangeType, origin: &str) {
+        let mut message = message(buffer, change, origin);
+        if change == BufferChangeType::Modified {
+            let content = buffer.read_range(None, self.workspaces.limits().chunk_bytes);
+            message.content_truncated = content.truncated;
+            message.content = content.text;
+        }
+        self.publish(buffer.id(), message);
+        for observer in self.observers() {
+            observer.changed(buffer, change);
+        }
+    }
+
+    /// Publish edits a client just applied to the buffer.
+    pub(crate) fn publish_edits(&self, buffer: &Buffer, edits: &[TextEdit], origin: &str) {
+        let mut message = message(buffer, BufferChangeType::RemoteEdit, origin);
+        message.edits = edits.iter().map(to_proto_edit).collect();
+        self.publish(buffer.id(), message);
+        for observer in self.observers() {
+            observer.edited(buffer, edits);
+        }
+    }
+
+    /// Send a message to the buffer's subscribers, if any.
+    fn publish(&self, buffer_id: &str, message: WatchBufferChangesResponse) {
+        if let Some(sender) = self.channels.lock().get(buffer_id) {
+            // No receivers is fine: nobody is watching this buffer right now
+            let _ = sender.send(message);
+        }
+    }
+}
+
+/// Base change message describing a buffer's current state.
+fn message(buffer: &Buffer, change: BufferChangeType, origin: &str) -> WatchBufferChangesResponse {
+    WatchBufferChangesResponse {
+        meta: None,
+        change_type: change as i32,
+        content: String::new(),
+        content_truncated: false,
+        version: buffer.version(),
+        modified_at: buffer.disk_modified_at().map(to_timestamp),
+        file_id: Some(FileId {
+            path: buffer.file_id().to_string(),
+        }),
+        read_only: buffer.read_only(),
+        edits: Vec::new(),
+        origin_client_id: origin.to_string(),
+    }
+}
+
+/// Map a disk change to the change type reported to clients.
+const fn change_type(change: DiskChange) -> Option<BufferChangeType> {
+    match change {
+        DiskChange::Unchanged => None,
+        DiskChange::Reloaded => Some(BufferChangeType::Modified),
+        DiskChange::Conflict => Some(BufferChangeType::Conflict),
+        DiskChange::Deleted => Some(BufferChangeType::Deleted),
+        DiskChange::Permissions => Some(BufferChangeType::Permissions),
+    }
+}
+
+/// Whether `file_id` is `prefix` itself or inside the directory `prefix`.
+fn is_under(file_id: &str, prefix: &str) -> bool {
+    file_id
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// New file ID of a buffer after `from` was renamed to `to`, if the buffer's
+/// file (or a directory containing it) was the one moved.
+fn moved_file_id(workspace: &Workspace, from: &Path, to: &str, buffer: &Buffer) -> Option<String> {
+    let from = workspace.file_id_of(from)?;
+    let rest = buffer.file_id().strip_prefix(from.as_str())?;
+    (rest.is_empty() || rest.starts_with('/')).then(|| format!("{to}{rest}"))
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn setup(dir: &TempDir) -> (Arc<BufferSync>, Arc<Workspace>, String) {
+        fs::write(dir.path().join("a.txt"), "one\n").unwrap();
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let workspace = workspaces.open_workspace(dir.path(), None, vec![]).unwrap();
+        let buffer = workspaces
+            .open_buffer(workspace.id(), "a.txt", None, "")
+            .unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        let sync = Arc::new(BufferSync::new(workspaces, Duration::from_millis(10), 8));
+        (sync, workspace, buffer_id)
+    }
+
+    fn event(workspace: &Workspace, file_id: &str, kind: FsEventKind) -> FsEvent {
+        FsEvent {
+            path: workspace.root().join(file_id),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_modified_file_reloads_and_publishes() {
+        let dir = TempDir::new().unwrap();
+        let (sync, workspace, buffer_id) = setup(&dir);
+        let mut rx = sync.subscribe(&buffer_id).unwrap();
+
+        fs::write(dir.path().join("a.txt"), "two\n").unwrap();
+        sync.handle_events(
+            workspace.id(),
+            &[event(&workspace, "a.txt", FsEventKind::Modified)],
+        );
+
+        let message = rx.try_recv().unwrap();
+        assert_eq!(message.change_type, BufferChangeType::Modified as i32);
+        assert_eq!(message.content, "two\n");
+        assert_eq!(message.version, 2);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_rename_rebinds_buffer() {
+        let dir = TempDir::new().unwrap();
+        let (sync, workspace, buffer_id) = setup(&dir);
+        let mut rx = sync.subscribe(&buffer_id).unwrap();
+
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::rename(dir.path().join("a.txt"), dir.path().join("src/b.txt")).unwrap();
+        sync.handle_events(
+            workspace.id(),
+            &[event(
+                &workspace,
+                "src/b.txt",
+                FsEventKind::Renamed {
+                    from: workspace.root().join("a.txt"),
+                },
+            )],
+        );
+
+        let message = rx.try_recv().unwrap();
+        assert_eq!(message.change_type, BufferChangeType::Renamed as i32);
+        assert_eq!(message.file_id.unwrap().path, "src/b.txt");
+        let buffer = sync.workspaces.buffer(&buffer_id).unwrap();
+        assert_eq!(buffer.read().file_id(), "src/b.txt");
+    }
+
+    #[test]
+    fn test_forget_ends_subscription() {
+        let dir = TempDir::new().unwrap();
+        let (sync, _workspace, buffer_id) = setup(&dir);
+        let mut rx = sync.subscribe(&buffer_id).unwrap();
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let hook_closed = closed.clone();
+        sync.on_buffer_closed(move |id| hook_closed.lock().push(id.to_string()));
+
+        sync.forget_buffers(std::slice::from_ref(&buffer_id));
+        assert!(matches!(
+            rx.try_recv(),
+            Err(broadcast::error::TryRecvError::Closed)
+        ));
+        assert_eq!(*closed.lock(), vec![buffer_id]);
+        assert!(sync.subscribe("missing").is_err());
+    }
+
+    #[test]
+    fn test_is_under() {
+        assert!(is_under("src/a.rs", "src"));
+        assert!(is_under("src/a.rs", "src/a.rs"));
+        assert!(!is_under("src2/a.rs", "src"));
+    }
+}
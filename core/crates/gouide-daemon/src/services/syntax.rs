@@ -0,0 +1,288 @@
+//! Syntax highlighting and structure for the editor service.
+//!
+//! Tokens and structure are computed on blocking threads from a snapshot of
+//! the buffer, so parsing never holds the buffer lock. A `WatchSyntaxTokens`
+//! stream is a task that wakes on the buffer's change channel, skips edits
+//! that were superseded while it was busy, and sends the re-tokenized lines
+//! that intersect the client's visible range.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use gouide_protocol::{
+    DeltaType, Error, GetSyntaxTokensSuccess, StreamMeta, WatchBufferChangesResponse,
+    WatchSyntaxTokensResponse,
+};
+use gouide_syntax::{Language, SyntaxError, SyntaxManager, Tokens};
+use gouide_workspace::{TextRange, WorkspaceError, WorkspaceManager};
+use parking_lot::Mutex;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, warn};
+
+use super::convert::{to_proto_range, to_proto_token};
+use super::errors::{syntax_error, workspace_error};
+use super::stream::StreamSender;
+use super::ResponseStream;
+use crate::languages::LanguageRegistry;
+
+/// A buffer's text at one version.
+pub(crate) struct Snapshot {
+    /// Buffer version.
+    pub(crate) version: u64,
+    /// Grammar for the buffer's detected language, if one is bundled and the
+    /// buffer is not a memory-mapped large file.
+    pub(crate) language: Option<Language>,
+    /// Buffer text, empty if there is no language.
+    pub(crate) text: String,
+}
+
+/// Copy what syntax analysis needs out of a buffer.
+pub(crate) fn snapshot(
+    workspaces: &WorkspaceManager,
+    languages: &LanguageRegistry,
+    buffer_id: &str,
+) -> Result<Snapshot, WorkspaceError> {
+    let shared = workspaces.buffer(buffer_id)?;
+    let buffer = shared.read();
+    let language = if buffer.is_mapped() {
+        None
+    } else {
+        Language::from_id(&languages.detect_buffer(&buffer))
+    };
+    Ok(Snapshot {
+        version: buffer.version(),
+        language,
+        text: language.map(|_| buffer.text()).unwrap_or_default(),
+    })
+}
+
+/// Syntax tokens for the lines of `range` in a buffer.
+///
+/// Files without a bundled grammar and memory-mapped large files have no
+/// tokens.
+pub(crate) fn syntax_tokens(
+    workspaces: &WorkspaceManager,
+    languages: &LanguageRegistry,
+    syntax: &SyntaxManager,
+    buffer_id: &str,
+    range: TextRange,
+    source: &str,
+) -> Result<GetSyntaxTokensSuccess, Error> {
+    let snapshot =
+        snapshot(workspaces, languages, buffer_id).map_err(|e| workspace_error(&e, source))?;
+    let tokens = match snapshot.language {
+        Some(language) => syntax
+            .tokens(buffer_id, language, snapshot.version, &snapshot.text, range)
+            .map_err(|e| syntax_error(&e, source))?,
+        None => Tokens {
+            tokens: Vec::new(),
+            range,
+        },
+    };
+    Ok(GetSyntaxTokensSuccess {
+        tokens: tokens.tokens.iter().map(to_proto_token).collect(),
+        version: snapshot.version,
+        range: Some(to_proto_range(tokens.range)),
+    })
+}
+
+/// Run `query` on a buffer's syntax tree, returning its result and the
+/// buffer version it is for.
+///
+/// Files without a bundled grammar and memory-mapped large files get
+/// `T::default()`.
+pub(crate) fn structure<T: Default>(
+    workspaces: &WorkspaceManager,
+    languages: &LanguageRegistry,
+    syntax: &SyntaxManager,
+    buffer_id: &str,
+    source: &str,
+    query: impl FnOnce(&SyntaxManager, &str, Language, &Snapshot) -> Result<T, SyntaxError>,
+) -> Result<(T, u64), Error> {
+    let snapshot =
+        snapshot(workspaces, languages, buffer_id).map_err(|e| workspace_error(&e, source))?;
+    let value = match snapshot.language {
+        Some(language) => {
+            query(syntax, buffer_id, language, &snapshot).map_err(|e| syntax_error(&e, source))?
+        }
+        None => T::default(),
+    };
+    Ok((value, snapshot.version))
+}
+
+/// Visible ranges of open token streams, by stream ID.
+#[derive(Default)]
+pub(crate) struct TokenStreams {
+    ranges: Mutex<HashMap<String, watch::Sender<TextRange>>>,
+}
+
+impl TokenStreams {
+    /// Move a stream's visible range. Returns false if there is no such
+    /// stream.
+    pub(crate) fn update(&self, stream_id: &str, range: TextRange) -> bool {
+        self.ranges
+            .lock()
+            .get(stream_id)
+            .is_some_and(|sender| sender.send(range).is_ok())
+    }
+
+    fn insert(&self, stream_id: &str, range: TextRange) -> watch::Receiver<TextRange> {
+        let (tx, rx) = watch::channel(range);
+        self.ranges.lock().insert(stream_id.to_string(), tx);
+        rx
+    }
+
+    fn remove(&self, stream_id: &str) {
+        self.ranges.lock().remove(stream_id);
+    }
+}
+
+/// Everything a token stream needs to compute updates.
+#[derive(Clone)]
+pub(crate) struct TokenSource {
+    pub(crate) workspaces: Arc<WorkspaceManager>,
+    pub(crate) languages: Arc<LanguageRegistry>,
+    pub(crate) syntax: Arc<SyntaxManager>,
+    pub(crate) streams: Arc<TokenStreams>,
+    pub(crate) buffer_id: String,
+}
+
+/// What a token stream sends next.
+enum Update {
+    /// Tokens to send.
+    Send(WatchSyntaxTokensResponse),
+    /// Nothing visible changed up to this version.
+    Unchanged(u64),
+    /// The buffer moved on while tokenizing; start over.
+    Stale,
+}
+
+impl TokenSource {
+    /// Tokens for the visible lines that changed since version `since`, or
+    /// for all of them if `since` is `None`.
+    fn update(&self, visible: TextRange, since: Option<u64>) -> Result<Update, WorkspaceError> {
+        let snapshot = snapshot(&self.workspaces, &self.languages, &self.buffer_id)?;
+        let version = snapshot.version;
+        let tokens = match (snapshot.language, since) {
+            (None, Some(_)) => None,
+            (None, None) => Some(Tokens {
+                tokens: Vec::new(),
+                range: visible,
+            }),
+            (Some(language), None) => self
+                .syntax
+                .tokens(&self.buffer_id, language, version, &snapshot.text, visible)
+                .map_or_else(|e| self.failed(&e), Some),
+            (Some(language), Some(since)) => self
+                .syntax
+                .changed_tokens(
+                    &self.buffer_id,
+                    language,
+                    version,
+                    &snapshot.text,
+                    visible,
+                    since,
+                )
+                .unwrap_or_else(|e| self.failed(&e)),
+        };
+
+        if self.workspaces.buffer(&self.buffer_id)?.read().version() != version {
+            return Ok(Update::Stale);
+        }
+        let Some(tokens) = tokens else {
+            return Ok(Update::Unchanged(version));
+        };
+        let delta_type = if since.is_some() {
+            DeltaType::Update
+        } else {
+            DeltaType::Snapshot
+        };
+        Ok(Update::Send(WatchSyntaxTokensResponse {
+            meta: Some(StreamMeta {
+                delta_type: delta_type as i32,
+                ..StreamMeta::default()
+            }),
+            range: Some(to_proto_range(tokens.range)),
+            tokens: tokens.tokens.iter().map(to_proto_token).collect(),
+            version,
+        }))
+    }
+
+    fn failed(&self, error: &SyntaxError) -> Option<Tokens> {
+        warn!(buffer_id = %self.buffer_id, error = %error, "Tokenizing failed");
+        None
+    }
+}
+
+/// Start a token stream for `visible` in a buffer.
+///
+/// The first message is a snapshot of the visible range. The stream ends
+/// when the buffer is closed or the client goes away.
+pub(crate) fn watch_tokens(
+    source: TokenSource,
+    changes: broadcast::Receiver<WatchBufferChangesResponse>,
+    visible: TextRange,
+) -> ResponseStream<WatchSyntaxTokensResponse> {
+    let (sender, stream) = StreamSender::channel();
+    let ranges = source.streams.insert(sender.stream_id(), visible);
+    tokio::spawn(run(sender, source, changes, ranges));
+    stream
+}
+
+async fn run(
+    mut sender: StreamSender<WatchSyntaxTokensResponse>,
+    source: TokenSource,
+    mut changes: broadcast::Receiver<WatchBufferChangesResponse>,
+    mut visible: watch::Receiver<TextRange>,
+) {
+    let stream_id = sender.stream_id().to_string();
+    debug!(stream_id = %stream_id, buffer_id = %source.buffer_id, "Watching syntax tokens");
+
+    // Version the client's tokens are for; `None` sends a snapshot
+    let mut sent = None;
+    'stream: loop {
+        let range = *visible.borrow_and_update();
+        let job = source.clone();
+        let update = tokio::task::spawn_blocking(move || job.update(range, sent)).await;
+        match update {
+            Ok(Ok(Update::Send(message))) => {
+                let version = message.version;
+                if !sender.send(message).await {
+                    break;
+                }
+                sent = Some(version);
+            }
+            Ok(Ok(Update::Unchanged(version))) => sent = Some(version),
+            Ok(Ok(Update::Stale)) => continue,
+            // The buffer was closed, or the task panicked
+            Ok(Err(_)) | Err(_) => break,
+        }
+
+        tokio::select! {
+            changed = changes.recv() => {
+                if matches!(changed, Err(RecvError::Closed)) {
+                    break;
+                }
+            }
+            changed = visible.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                sent = None;
+            }
+            () = sender.closed() => break,
+        }
+        // Edits queued up while tokenizing are covered by the next update
+        loop {
+            match changes.try_recv() {
+                Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => break 'stream,
+            }
+        }
+    }
+
+    source.streams.remove(&stream_id);
+    debug!(stream_id = %stream_id, "Syntax token stream ended");
+}
@@ -0,0 +1,363 @@
+//! File tree streams.
+//!
+//! A `WatchFileTree` stream sends a snapshot of the watched part of a
+//! workspace's tree, then follows its file watcher. Each batch of events
+//! becomes up to three messages: `REMOVE` with the paths that went away
+//! (the old path of a move included), `ADD` with created entries and
+//! `UPDATE` with changed ones. Entries are looked up when the batch
+//! arrives, so a path created and deleted within it is only removed.
+//!
+//! With git status requested, entries carry their status, and each change
+//! of status sends an `UPDATE` of the entries it affects; a directory that
+//! becomes ignored stands for its contents. What is inside `.git`
+//! directories is never part of the tree.
+
+use std::sync::Arc;
+
+use gouide_fs::FsEventKind;
+use gouide_git::FileStatus;
+use gouide_protocol::{DeltaType, FileEntry, FileId, StreamMeta, WatchFileTreeResponse};
+use gouide_workspace::{DirEntry, EntryKind, ListOptions, WorkspaceError, WorkspaceManager};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::debug;
+
+use super::convert::{to_proto_file_entry, to_proto_git_status};
+use super::stream::StreamSender;
+use super::sync::FileEvents;
+use super::ResponseStream;
+use crate::git::{GitStatuses, StatusChanges};
+use crate::languages::LanguageRegistry;
+
+/// Which part of a workspace's tree a stream watches.
+#[derive(Debug, Clone)]
+pub(super) struct TreeOptions {
+    /// Workspace-relative directory, empty for the whole workspace.
+    pub(super) root: String,
+    /// Levels below the root, 0 for no limit.
+    pub(super) max_depth: u32,
+    pub(super) include_hidden: bool,
+    pub(super) include_git_status: bool,
+}
+
+impl TreeOptions {
+    /// Whether a workspace-relative path is in the watched tree.
+    fn contains(&self, file_id: &str) -> bool {
+        let relative = if self.root.is_empty() {
+            file_id
+        } else {
+            match file_id
+                .strip_prefix(self.root.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(relative) => relative,
+                None => return false,
+            }
+        };
+        let parts: Vec<&str> = relative.split('/').collect();
+        !relative.is_empty()
+            && (self.max_depth == 0 || parts.len() <= self.max_depth as usize)
+            && (self.include_hidden || !parts.iter().any(|part| part.starts_with('.')))
+            && !file_id.split('/').rev().skip(1).any(|part| part == ".git")
+    }
+}
+
+/// Builds file tree entries.
+#[derive(Clone)]
+pub(super) struct TreeLookup {
+    workspaces: Arc<WorkspaceManager>,
+    languages: Arc<LanguageRegistry>,
+    git: Arc<GitStatuses>,
+}
+
+impl TreeLookup {
+    pub(super) const fn new(
+        workspaces: Arc<WorkspaceManager>,
+        languages: Arc<LanguageRegistry>,
+        git: Arc<GitStatuses>,
+    ) -> Self {
+        Self {
+            workspaces,
+            languages,
+            git,
+        }
+    }
+
+    /// Every entry of the watched tree, in tree order.
+    pub(super) fn snapshot(
+        &self,
+        workspace_id: &str,
+        options: &TreeOptions,
+    ) -> Result<Vec<FileEntry>, WorkspaceError> {
+        let listing = ListOptions {
+            include_hidden: options.include_hidden,
+            recursive: true,
+            max_depth: options.max_depth,
+        };
+        let entries = self
+            .workspaces
+            .workspace(workspace_id)?
+            .list_directory(&options.root, listing)?;
+        Ok(entries
+            .iter()
+            .filter(|entry| options.contains(&entry.file_id))
+            .map(|entry| self.entry(workspace_id, entry, options))
+            .collect())
+    }
+
+    /// Messages for a batch of file system events.
+    fn file_changes(
+        &self,
+        workspace_id: &str,
+        events: &FileEvents,
+        options: &TreeOptions,
+    ) -> Vec<WatchFileTreeResponse> {
+        let Ok(workspace) = self.workspaces.workspace(workspace_id) else {
+            return Vec::new();
+        };
+        let (mut removed, mut added, mut updated) = (Vec::new(), Vec::new(), Vec::new());
+        for event in events.iter() {
+            if let FsEventKind::Renamed { from } = &event.kind {
+                if let Some(file_id) = workspace.file_id_of(from) {
+                    if options.contains(&file_id) {
+                        removed.push(removed_entry(file_id));
+                    }
+                }
+            }
+            let Some(file_id) = workspace.file_id_of(&event.path) else {
+                continue;
+            };
+            if !options.contains(&file_id) {
+                continue;
+            }
+            match workspace.entry(&file_id) {
+                Ok(entry) => {
+                    let entry = self.entry(workspace_id, &entry, options);
+                    match event.kind {
+                        FsEventKind::Created | FsEventKind::Renamed { .. } => added.push(entry),
+                        _ => updated.push(entry),
+                    }
+                }
+                Err(_) => removed.push(removed_entry(file_id)),
+            }
+        }
+        [
+            (DeltaType::Remove, removed),
+            (DeltaType::Add, added),
+            (DeltaType::Update, updated),
+        ]
+        .into_iter()
+        .filter_map(|(delta_type, entries)| message(delta_type, entries))
+        .collect()
+    }
+
+    /// An `UPDATE` for the entries whose git status changed, if any are
+    /// in the watched tree.
+    fn status_changes(
+        &self,
+        workspace_id: &str,
+        changes: &StatusChanges,
+        options: &TreeOptions,
+    ) -> Option<WatchFileTreeResponse> {
+        let workspace = self.workspaces.workspace(workspace_id).ok()?;
+        let entries = changes
+            .paths
+            .iter()
+            .filter(|file_id| options.contains(file_id))
+            // Deleted files are already gone from the tree
+            .filter_map(|file_id| workspace.entry(file_id).ok())
+            .map(|entry| self.entry(workspace_id, &entry, options))
+            .collect();
+        message(DeltaType::Update, entries)
+    }
+
+    fn entry(&self, workspace_id: &str, entry: &DirEntry, options: &TreeOptions) -> FileEntry {
+        let language_id = match entry.kind {
+            EntryKind::Directory => String::new(),
+            _ => self.languages.detect_path(workspace_id, &entry.file_id),
+        };
+        let mut proto = to_proto_file_entry(entry, language_id);
+        if options.include_git_status {
+            let status = self.git.status(workspace_id, &entry.file_id);
+            proto.git_status = to_proto_git_status(status);
+            proto.is_ignored = status == Some(FileStatus::Ignored);
+        }
+        proto
+    }
+}
+
+/// A `REMOVE` entry, which only has its path.
+fn removed_entry(file_id: String) -> FileEntry {
+    FileEntry {
+        file_id: Some(FileId { path: file_id }),
+        ..FileEntry::default()
+    }
+}
+
+fn message(delta_type: DeltaType, entries: Vec<FileEntry>) -> Option<WatchFileTreeResponse> {
+    (!entries.is_empty()).then(|| WatchFileTreeResponse {
+        meta: Some(StreamMeta {
+            delta_type: delta_type as i32,
+            ..StreamMeta::default()
+        }),
+        entries,
+    })
+}
+
+/// Stream a workspace's tree from `snapshot`, following `files` and, for
+/// git status, `statuses`.
+pub(super) fn watch_file_tree(
+    lookup: TreeLookup,
+    workspace_id: String,
+    options: TreeOptions,
+    snapshot: Vec<FileEntry>,
+    files: broadcast::Receiver<FileEvents>,
+    statuses: Option<broadcast::Receiver<StatusChanges>>,
+) -> ResponseStream<WatchFileTreeResponse> {
+    let (sender, stream) = StreamSender::channel();
+    let first = WatchFileTreeResponse {
+        meta: Some(StreamMeta {
+            delta_type: DeltaType::Snapshot as i32,
+            ..StreamMeta::default()
+        }),
+        entries: snapshot,
+    };
+    let watch = Watch {
+        lookup,
+        workspace_id,
+        options,
+    };
+    tokio::spawn(run(sender, watch, first, files, statuses));
+    stream
+}
+
+/// What a stream watches.
+struct Watch {
+    lookup: TreeLookup,
+    workspace_id: String,
+    options: TreeOptions,
+}
+
+impl Watch {
+    /// Messages for a change.
+    fn messages(&self, change: &Change) -> Vec<WatchFileTreeResponse> {
+        match change {
+            Change::Files(events) => {
+                self.lookup
+                    .file_changes(&self.workspace_id, events, &self.options)
+            }
+            Change::Statuses(paths) => self
+                .lookup
+                .status_changes(&self.workspace_id, paths, &self.options)
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/// Something that changed the tree.
+enum Change {
+    Files(FileEvents),
+    Statuses(StatusChanges),
+}
+
+async fn run(
+    mut sender: StreamSender<WatchFileTreeResponse>,
+    watch: Watch,
+    first: WatchFileTreeResponse,
+    mut files: broadcast::Receiver<FileEvents>,
+    mut statuses: Option<broadcast::Receiver<StatusChanges>>,
+) {
+    let stream_id = sender.stream_id().to_string();
+    debug!(stream_id = %stream_id, workspace_id = %watch.workspace_id, "Watching file tree");
+    if !sender.send(first).await {
+        return;
+    }
+
+    let watch = Arc::new(watch);
+    loop {
+        let change = tokio::select! {
+            events = files.recv() => events.map(Change::Files),
+            paths = next_statuses(&mut statuses) => match paths {
+                Err(RecvError::Closed) => {
+                    statuses = None;
+                    continue;
+                }
+                paths => paths.map(Change::Statuses),
+            },
+            () = sender.closed() => break,
+        };
+        let change = match change {
+            Ok(change) => change,
+            Err(RecvError::Lagged(skipped)) => {
+                debug!(stream_id = %stream_id, skipped, "File tree subscriber lagged");
+                sender.reset().await;
+                break;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        // Looking entries up reads the disk and waits on git
+        let messages = {
+            let watch = watch.clone();
+            tokio::task::spawn_blocking(move || watch.messages(&change))
+                .await
+                .unwrap_or_default()
+        };
+        for message in messages {
+            if !sender.send(message).await {
+                debug!(stream_id = %stream_id, "File tree stream ended");
+                return;
+            }
+        }
+    }
+    debug!(stream_id = %stream_id, "File tree stream ended");
+}
+
+/// The next status change, or never without git status.
+pub(super) async fn next_statuses(
+    statuses: &mut Option<broadcast::Receiver<StatusChanges>>,
+) -> Result<StatusChanges, RecvError> {
+    match statuses {
+        Some(statuses) => statuses.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_contain_watched_paths() {
+        let options = TreeOptions {
+            root: "src".to_string(),
+            max_depth: 2,
+            include_hidden: false,
+            include_git_status: false,
+        };
+        assert!(options.contains("src/main.rs"));
+        assert!(options.contains("src/bin/tool.rs"));
+        assert!(!options.contains("src"));
+        assert!(!options.contains("srcs/main.rs"));
+        assert!(!options.contains("src/bin/deep/tool.rs"));
+        assert!(!options.contains("src/.env"));
+
+        let all = TreeOptions {
+            root: String::new(),
+            max_depth: 0,
+            include_hidden: true,
+            include_git_status: false,
+        };
+        assert!(all.contains("a/b/c/d.rs"));
+        assert!(all.contains(".git"));
+        assert!(all.contains("vendor/.github/ci.yml"));
+        assert!(!all.contains(".git/HEAD"));
+        assert!(!all.contains("vendor/lib/.git/index"));
+    }
+}
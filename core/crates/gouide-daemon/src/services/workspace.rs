@@ -0,0 +1,1053 @@
+//! Workspace service implementation.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use gouide_index::{IndexStatus, PathFilter};
+use gouide_protocol::workspace_service_server::WorkspaceService as WorkspaceServiceTrait;
+use gouide_protocol::{
+    clear_index_cache_response, close_workspace_response, get_effective_settings_response,
+    get_workspace_status_response, list_directory_response, open_workspace_response,
+    pause_indexing_response, resume_indexing_response, ClearIndexCacheRequest,
+    ClearIndexCacheResponse, CloseWorkspaceRequest, CloseWorkspaceResponse, CloseWorkspaceSuccess,
+    Error, GetEffectiveSettingsRequest, GetEffectiveSettingsResponse, GetWorkspaceStatusRequest,
+    GetWorkspaceStatusResponse, ListDirectoryRequest, ListDirectoryResponse, ListDirectorySuccess,
+    ListLanguagesRequest, ListLanguagesResponse, OpenWorkspaceRequest, OpenWorkspaceResponse,
+    OpenWorkspaceSuccess, PageToken, PaginationResponse, PauseIndexingRequest,
+    PauseIndexingResponse, ResumeIndexingRequest, ResumeIndexingResponse, WatchFileTreeRequest,
+    WatchFileTreeResponse, WatchSettingsRequest, WatchSettingsResponse,
+    WatchWorkspaceStatusRequest, WatchWorkspaceStatusResponse, WorkspaceId, WorkspaceStatus,
+};
+use gouide_workspace::{EntryKind, ListOptions, WorkspaceError, WorkspaceManager};
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use super::convert::{to_proto_file_entry, to_proto_language, to_proto_settings};
+use super::errors::{error, invalid_argument, workspace_error};
+use super::settings::{watch_settings, SettingsLookup};
+use super::status::{watch_status, StatusLookup};
+use super::tree::{watch_file_tree, TreeLookup, TreeOptions};
+use super::{BufferSync, ResponseStream};
+use crate::diagnostics::DiagnosticsStore;
+use crate::files::FileIndexes;
+use crate::git::GitStatuses;
+use crate::languages::{LanguageOverrides, LanguageRegistry};
+use crate::settings::SettingsResolver;
+
+/// Error source label for this service.
+const SOURCE: &str = "workspace";
+
+/// Workspace service for folder and file management.
+pub struct WorkspaceService {
+    workspaces: Arc<WorkspaceManager>,
+    sync: Arc<BufferSync>,
+    languages: Arc<LanguageRegistry>,
+    diagnostics: Arc<DiagnosticsStore>,
+    settings: Arc<SettingsResolver>,
+    files: Arc<FileIndexes>,
+    git: Arc<GitStatuses>,
+    lookup: SettingsLookup,
+    statuses: StatusLookup,
+    tree: TreeLookup,
+    /// Page size for listings when the client does not ask for one.
+    page_size: u32,
+}
+
+impl WorkspaceService {
+    /// Create a new workspace service.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        workspaces: Arc<WorkspaceManager>,
+        sync: Arc<BufferSync>,
+        languages: Arc<LanguageRegistry>,
+        diagnostics: Arc<DiagnosticsStore>,
+        settings: Arc<SettingsResolver>,
+        files: Arc<FileIndexes>,
+        git: Arc<GitStatuses>,
+        page_size: u32,
+    ) -> Self {
+        Self {
+            lookup: SettingsLookup::new(workspaces.clone(), languages.clone(), settings.clone()),
+            statuses: StatusLookup::new(workspaces.clone(), sync.clone(), files.clone()),
+            tree: TreeLookup::new(workspaces.clone(), languages.clone(), git.clone()),
+            workspaces,
+            sync,
+            languages,
+            diagnostics,
+            settings,
+            files,
+            git,
+            page_size,
+        }
+    }
+
+    /// Build the current status snapshot for a workspace.
+    fn status(&self, workspace_id: &str) -> Result<WorkspaceStatus, WorkspaceError> {
+        self.statuses.status(workspace_id)
+    }
+
+    /// Pause or resume indexing a workspace, returning its status after.
+    fn control_indexing(
+        &self,
+        workspace_id: &str,
+        control: impl FnOnce(&FileIndexes, &str) -> Option<IndexStatus>,
+    ) -> Result<WorkspaceStatus, Error> {
+        self.workspaces
+            .workspace(workspace_id)
+            .map_err(|e| workspace_error(&e, SOURCE))?;
+        let indexing = control(&self.files, workspace_id).ok_or_else(not_indexed)?;
+        self.statuses
+            .with_indexing(workspace_id, &indexing)
+            .map_err(|e| workspace_error(&e, SOURCE))
+    }
+}
+
+/// Error for a workspace without an index.
+fn not_indexed() -> Error {
+    error(
+        "NOT_INDEXED",
+        "The workspace's files are not indexed",
+        SOURCE,
+    )
+}
+
+/// List one page of a directory.
+///
+/// The page token is the offset of the page's first entry.
+fn list_directory(
+    workspaces: &WorkspaceManager,
+    languages: &LanguageRegistry,
+    default_page_size: u32,
+    req: &ListDirectoryRequest,
+) -> Result<ListDirectorySuccess, Error> {
+    let workspace_id = req
+        .workspace_id
+        .as_ref()
+        .map(|w| w.value.as_str())
+        .unwrap_or_default();
+    let (page_size, token) = req.pagination.as_ref().map_or((0, ""), |p| {
+        let token = p.page_token.as_ref().map_or("", |t| t.value.as_str());
+        (p.page_size, token)
+    });
+    let page_size = match page_size {
+        0 => default_page_size,
+        size => size,
+    } as usize;
+    let offset = if token.is_empty() {
+        0
+    } else {
+        token
+            .parse::<usize>()
+            .map_err(|_| invalid_argument(format!("Invalid page token: {token}"), SOURCE))?
+    };
+
+    let options = ListOptions {
+        include_hidden: req.include_hidden,
+        recursive: req.recursive,
+        max_depth: req.max_depth,
+    };
+    let entries = workspaces
+        .workspace(workspace_id)
+        .and_then(|workspace| workspace.list_directory(&req.path, options))
+        .map_err(|e| workspace_error(&e, SOURCE))?;
+
+    let total = entries.len();
+    let end = offset.saturating_add(page_size).min(total);
+    let page = entries.get(offset..end).unwrap_or_default();
+    Ok(ListDirectorySuccess {
+        entries: page
+            .iter()
+            .map(|entry| {
+                let language_id = match entry.kind {
+                    EntryKind::Directory => String::new(),
+                    _ => languages.detect_path(workspace_id, &entry.file_id),
+                };
+                to_proto_file_entry(entry, language_id)
+            })
+            .collect(),
+        pagination: Some(PaginationResponse {
+            next_page_token: (end < total).then(|| PageToken {
+                value: end.to_string(),
+            }),
+            total_count: total as u64,
+            total_count_exact: true,
+        }),
+        total_size: entries.iter().map(|entry| entry.size).sum(),
+    })
+}
+
+#[tonic::async_trait]
+impl WorkspaceServiceTrait for WorkspaceService {
+    type WatchFileTreeStream = ResponseStream<WatchFileTreeResponse>;
+    type WatchWorkspaceStatusStream = ResponseStream<WatchWorkspaceStatusResponse>;
+    type WatchSettingsStream = ResponseStream<WatchSettingsResponse>;
+
+    async fn open_workspace(
+        &self,
+        request: Request<OpenWorkspaceRequest>,
+    ) -> Result<Response<OpenWorkspaceResponse>, Status> {
+        let req = request.into_inner();
+        if req.folder_path.is_empty() {
+            return Ok(Response::new(OpenWorkspaceResponse {
+                result: Some(open_workspace_response::Result::Error(invalid_argument(
+                    "folder_path is required",
+                    SOURCE,
+                ))),
+            }));
+        }
+        if let Err(e) = PathFilter::check_globs(&req.exclude_patterns) {
+            return Ok(Response::new(OpenWorkspaceResponse {
+                result: Some(open_workspace_response::Result::Error(invalid_argument(
+                    format!("Invalid exclude pattern: {e}"),
+                    SOURCE,
+                ))),
+            }));
+        }
+        let overrides = match LanguageOverrides::new(&req.language_overrides) {
+            Ok(overrides) => overrides,
+            Err(e) => {
+                return Ok(Response::new(OpenWorkspaceResponse {
+                    result: Some(open_workspace_response::Result::Error(invalid_argument(
+                        format!("Invalid language override: {e}"),
+                        SOURCE,
+                    ))),
+                }));
+            }
+        };
+
+        let result = self
+            .workspaces
+            .open_workspace(
+                Path::new(&req.folder_path),
+                Some(req.name.as_str()),
+                req.exclude_patterns,
+            )
+            .and_then(|workspace| {
+                self.languages.set_overrides(workspace.id(), overrides);
+                self.sync.watch_workspace(&workspace);
+                self.files.open_workspace(&workspace);
+                self.git.open_workspace(&workspace);
+                let status = self.status(workspace.id())?;
+                info!(
+                    workspace_id = %workspace.id(),
+                    root = %workspace.root().display(),
+                    "Workspace opened"
+                );
+                Ok(OpenWorkspaceSuccess {
+                    workspace_id: Some(WorkspaceId {
+                        value: workspace.id().to_string(),
+                    }),
+                    folder_path: workspace.root().to_string_lossy().into_owned(),
+                    name: workspace.name().to_string(),
+                    status: Some(status),
+                })
+            });
+
+        Ok(Response::new(OpenWorkspaceResponse {
+            result: Some(match result {
+                Ok(success) => open_workspace_response::Result::Success(success),
+                Err(e) => open_workspace_response::Result::Error(workspace_error(&e, SOURCE)),
+            }),
+        }))
+    }
+
+    async fn close_workspace(
+        &self,
+        request: Request<CloseWorkspaceRequest>,
+    ) -> Result<Response<CloseWorkspaceResponse>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+        let root = self
+            .workspaces
+            .workspace(&workspace_id)
+            .map(|workspace| workspace.root().to_path_buf());
+
+        let result = match self.workspaces.close_workspace(&workspace_id) {
+            Ok(closed) => {
+                self.sync.unwatch_workspace(&workspace_id);
+                self.sync.forget_buffers(&closed);
+                self.languages.remove_workspace(&workspace_id);
+                self.diagnostics.remove_workspace(&workspace_id);
+                self.files.close_workspace(&workspace_id);
+                self.git.close_workspace(&workspace_id);
+                if let Ok(root) = root {
+                    self.settings.workspace_closed(&workspace_id, &root);
+                }
+                info!(workspace_id = %workspace_id, "Workspace closed");
+                close_workspace_response::Result::Success(CloseWorkspaceSuccess { closed: true })
+            }
+            Err(e) => close_workspace_response::Result::Error(workspace_error(&e, SOURCE)),
+        };
+
+        Ok(Response::new(CloseWorkspaceResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_workspace_status(
+        &self,
+        request: Request<GetWorkspaceStatusRequest>,
+    ) -> Result<Response<GetWorkspaceStatusResponse>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+
+        let result = match self.status(&workspace_id) {
+            Ok(status) => get_workspace_status_response::Result::Status(status),
+            Err(e) => get_workspace_status_response::Result::Error(workspace_error(&e, SOURCE)),
+        };
+
+        Ok(Response::new(GetWorkspaceStatusResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn list_directory(
+        &self,
+        request: Request<ListDirectoryRequest>,
+    ) -> Result<Response<ListDirectoryResponse>, Status> {
+        let req = request.into_inner();
+        let workspaces = self.workspaces.clone();
+        let languages = self.languages.clone();
+        let page_size = self.page_size;
+        let result = tokio::task::spawn_blocking(move || {
+            list_directory(&workspaces, &languages, page_size, &req)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("ListDirectory task failed: {e}")))?;
+
+        let result = match result {
+            Ok(success) => list_directory_response::Result::Success(success),
+            Err(error) => list_directory_response::Result::Error(error),
+        };
+
+        Ok(Response::new(ListDirectoryResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn watch_file_tree(
+        &self,
+        request: Request<WatchFileTreeRequest>,
+    ) -> Result<Response<Self::WatchFileTreeStream>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+        let options = TreeOptions {
+            root: req.root_path.trim_matches('/').to_string(),
+            max_depth: req.max_depth,
+            include_hidden: req.include_hidden,
+            include_git_status: req.include_git_status,
+        };
+
+        // Streams have no error envelope, so failures map to a status.
+        // Subscribing before the snapshot leaves no gap after it.
+        let files = self
+            .sync
+            .subscribe_files(&workspace_id)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        let statuses = options
+            .include_git_status
+            .then(|| self.git.subscribe(&workspace_id))
+            .flatten();
+        let snapshot = {
+            let (tree, workspace_id, options) =
+                (self.tree.clone(), workspace_id.clone(), options.clone());
+            tokio::task::spawn_blocking(move || tree.snapshot(&workspace_id, &options))
+                .await
+                .map_err(|e| Status::internal(format!("WatchFileTree task failed: {e}")))?
+                .map_err(|e| match e {
+                    WorkspaceError::FileNotFound(_) => Status::not_found(e.to_string()),
+                    _ => Status::invalid_argument(e.to_string()),
+                })?
+        };
+
+        Ok(Response::new(watch_file_tree(
+            self.tree.clone(),
+            workspace_id,
+            options,
+            snapshot,
+            files,
+            statuses,
+        )))
+    }
+
+    async fn watch_workspace_status(
+        &self,
+        request: Request<WatchWorkspaceStatusRequest>,
+    ) -> Result<Response<Self::WatchWorkspaceStatusStream>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+
+        // Streams have no error envelope, so failures map to a status
+        self.workspaces
+            .workspace(&workspace_id)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        let changes = self
+            .files
+            .subscribe(&workspace_id)
+            .ok_or_else(|| Status::failed_precondition("The workspace's files are not indexed"))?;
+
+        Ok(Response::new(watch_status(
+            self.statuses.clone(),
+            workspace_id,
+            changes,
+        )))
+    }
+
+    async fn pause_indexing(
+        &self,
+        request: Request<PauseIndexingRequest>,
+    ) -> Result<Response<PauseIndexingResponse>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+
+        let result = match self.control_indexing(&workspace_id, FileIndexes::pause) {
+            Ok(status) => {
+                info!(workspace_id = %workspace_id, "Indexing paused");
+                pause_indexing_response::Result::Status(status)
+            }
+            Err(error) => pause_indexing_response::Result::Error(error),
+        };
+        Ok(Response::new(PauseIndexingResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn resume_indexing(
+        &self,
+        request: Request<ResumeIndexingRequest>,
+    ) -> Result<Response<ResumeIndexingResponse>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+
+        let result = match self.control_indexing(&workspace_id, FileIndexes::resume) {
+            Ok(status) => {
+                info!(workspace_id = %workspace_id, "Indexing resumed");
+                resume_indexing_response::Result::Status(status)
+            }
+            Err(error) => resume_indexing_response::Result::Error(error),
+        };
+        Ok(Response::new(ResumeIndexingResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn clear_index_cache(
+        &self,
+        request: Request<ClearIndexCacheRequest>,
+    ) -> Result<Response<ClearIndexCacheResponse>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+
+        let result = match self.workspaces.workspace(&workspace_id) {
+            Ok(workspace) => {
+                let files = self.files.clone();
+                tokio::task::spawn_blocking(move || files.clear_cache(&workspace))
+                    .await
+                    .map_err(|e| Status::internal(format!("ClearIndexCache task failed: {e}")))?
+                    .map_err(|e| error("CACHE_ERROR", e.to_string(), SOURCE))
+                    .and_then(|indexing| {
+                        let indexing = indexing.ok_or_else(not_indexed)?;
+                        self.statuses
+                            .with_indexing(&workspace_id, &indexing)
+                            .map_err(|e| workspace_error(&e, SOURCE))
+                    })
+            }
+            Err(e) => Err(workspace_error(&e, SOURCE)),
+        };
+        let result = match result {
+            Ok(status) => {
+                info!(workspace_id = %workspace_id, "Index cache cleared");
+                clear_index_cache_response::Result::Status(status)
+            }
+            Err(error) => clear_index_cache_response::Result::Error(error),
+        };
+        Ok(Response::new(ClearIndexCacheResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn list_languages(
+        &self,
+        _request: Request<ListLanguagesRequest>,
+    ) -> Result<Response<ListLanguagesResponse>, Status> {
+        Ok(Response::new(ListLanguagesResponse {
+            languages: self
+                .languages
+                .languages()
+                .iter()
+                .map(to_proto_language)
+                .collect(),
+        }))
+    }
+
+    async fn get_effective_settings(
+        &self,
+        request: Request<GetEffectiveSettingsRequest>,
+    ) -> Result<Response<GetEffectiveSettingsResponse>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+        let file_id = req.file_id.map(|f| f.path).unwrap_or_default();
+
+        let lookup = self.lookup.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            lookup
+                .file(&workspace_id, &file_id)
+                .map(|settings| to_proto_settings(&file_id, &settings))
+        })
+        .await
+        .map_err(|e| Status::internal(format!("GetEffectiveSettings task failed: {e}")))?;
+
+        let result = match result {
+            Ok(settings) => get_effective_settings_response::Result::Settings(settings),
+            Err(e) => get_effective_settings_response::Result::Error(workspace_error(&e, SOURCE)),
+        };
+        Ok(Response::new(GetEffectiveSettingsResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn watch_settings(
+        &self,
+        request: Request<WatchSettingsRequest>,
+    ) -> Result<Response<Self::WatchSettingsStream>, Status> {
+        let req = request.into_inner();
+        let workspace_id = req.workspace_id.map(|w| w.value).unwrap_or_default();
+        let file_ids: Vec<String> = req.file_ids.into_iter().map(|f| f.path).collect();
+
+        // Streams have no error envelope, so failures map to a status
+        let workspace = self
+            .workspaces
+            .workspace(&workspace_id)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        for file_id in &file_ids {
+            workspace
+                .resolve_path(file_id)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        }
+
+        Ok(Response::new(watch_settings(
+            self.lookup.clone(),
+            workspace_id,
+            file_ids,
+        )))
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::time::Duration;
+
+    use gouide_protocol::{
+        DeltaType, FileEntry, FileId, GitFileStatus, IndentStyle, IndexingState, PaginationRequest,
+    };
+    use tempfile::TempDir;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::requests::RequestTracker;
+    use crate::settings::{Settings, EDITORCONFIG, SETTINGS_FILE};
+
+    fn service() -> WorkspaceService {
+        let workspaces = Arc::new(WorkspaceManager::new());
+        let sync = Arc::new(BufferSync::new(
+            workspaces.clone(),
+            Duration::from_millis(10),
+            8,
+        ));
+        let languages = Arc::new(LanguageRegistry::new());
+        let git = Arc::new(GitStatuses::new(8));
+        let tracked = git.clone();
+        sync.on_files_changed(move |workspace_id, events| {
+            tracked.files_changed(workspace_id, events);
+        });
+        WorkspaceService::new(
+            workspaces,
+            sync,
+            languages.clone(),
+            Arc::new(DiagnosticsStore::new(8)),
+            Arc::new(SettingsResolver::new(Settings::default(), 8)),
+            Arc::new(FileIndexes::new(
+                Arc::new(RequestTracker::new()),
+                languages,
+                None,
+            )),
+            git,
+            100,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_open_and_close_workspace() {
+        let dir = TempDir::new().unwrap();
+        let service = service();
+
+        let response = service
+            .open_workspace(Request::new(OpenWorkspaceRequest {
+                request_id: None,
+                folder_path: dir.path().to_string_lossy().into_owned(),
+                name: "demo".to_string(),
+                exclude_patterns: vec![],
+                language_overrides: HashMap::new(),
+            }))
+            .await
+            .unwrap();
+        let Some(open_workspace_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert_eq!(success.name, "demo");
+        let status = success.status.unwrap();
+        assert_eq!(status.open_buffer_count, 0);
+        assert!(status.watcher_active);
+
+        let response = service
+            .close_workspace(Request::new(CloseWorkspaceRequest {
+                request_id: None,
+                workspace_id: success.workspace_id,
+            }))
+            .await
+            .unwrap();
+        assert!(matches!(
+            response.into_inner().result,
+            Some(close_workspace_response::Result::Success(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_watch_indexing_status() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.path().join("debug.log"), "").unwrap();
+        let service = service();
+        let open = |exclude: &str| OpenWorkspaceRequest {
+            request_id: None,
+            folder_path: dir.path().to_string_lossy().into_owned(),
+            name: String::new(),
+            exclude_patterns: vec![exclude.to_string()],
+            language_overrides: HashMap::new(),
+        };
+
+        let response = service
+            .open_workspace(Request::new(open("{")))
+            .await
+            .unwrap();
+        let Some(open_workspace_response::Result::Error(error)) = response.into_inner().result
+        else {
+            panic!("Expected an error");
+        };
+        assert_eq!(error.code, "INVALID_ARGUMENT");
+
+        let response = service
+            .open_workspace(Request::new(open("*.log")))
+            .await
+            .unwrap();
+        let Some(open_workspace_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        let workspace_id = success.workspace_id;
+        let mut stream = service
+            .watch_workspace_status(Request::new(WatchWorkspaceStatusRequest {
+                workspace_id: workspace_id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first.meta.unwrap().delta_type, DeltaType::Snapshot as i32);
+        let mut status = first.status.unwrap();
+        while status.indexing_state != IndexingState::Complete as i32 {
+            let next = tokio::time::timeout(Duration::from_secs(5), stream.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(next.meta.unwrap().delta_type, DeltaType::Update as i32);
+            status = next.status.unwrap();
+        }
+        assert_eq!((status.indexing_progress, status.file_count), (100, 1));
+
+        // Finished indexing has nothing to pause
+        let response = service
+            .pause_indexing(Request::new(PauseIndexingRequest {
+                workspace_id: workspace_id.clone(),
+            }))
+            .await
+            .unwrap();
+        let Some(pause_indexing_response::Result::Status(status)) = response.into_inner().result
+        else {
+            panic!("Expected a status");
+        };
+        assert_eq!(status.indexing_state, IndexingState::Complete as i32);
+        let response = service
+            .resume_indexing(Request::new(ResumeIndexingRequest {
+                workspace_id: Some(WorkspaceId {
+                    value: "missing".to_string(),
+                }),
+            }))
+            .await
+            .unwrap();
+        assert!(matches!(
+            response.into_inner().result,
+            Some(resume_indexing_response::Result::Error(_))
+        ));
+
+        service
+            .close_workspace(Request::new(CloseWorkspaceRequest {
+                request_id: None,
+                workspace_id,
+            }))
+            .await
+            .unwrap();
+        let end = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        assert!(end.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_open_missing_folder() {
+        let service = service();
+
+        let response = service
+            .open_workspace(Request::new(OpenWorkspaceRequest {
+                request_id: None,
+                folder_path: "/definitely/not/here".to_string(),
+                name: String::new(),
+                exclude_patterns: vec![],
+                language_overrides: HashMap::new(),
+            }))
+            .await
+            .unwrap();
+        let Some(open_workspace_response::Result::Error(error)) = response.into_inner().result
+        else {
+            panic!("Expected error");
+        };
+        assert_eq!(error.code, "FILE_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_list_directory_pages_with_languages() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+        fs::write(dir.path().join("Dockerfile"), "FROM scratch\n").unwrap();
+        fs::write(dir.path().join("page.tpl"), "").unwrap();
+        let service = service();
+
+        let response = service
+            .open_workspace(Request::new(OpenWorkspaceRequest {
+                request_id: None,
+                folder_path: dir.path().to_string_lossy().into_owned(),
+                name: String::new(),
+                exclude_patterns: vec![],
+                language_overrides: HashMap::from([("*.tpl".to_string(), "html".to_string())]),
+            }))
+            .await
+            .unwrap();
+        let Some(open_workspace_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+
+        let list = |page_token: &str| ListDirectoryRequest {
+            request_id: None,
+            workspace_id: success.workspace_id.clone(),
+            path: String::new(),
+            pagination: Some(PaginationRequest {
+                page_size: 3,
+                page_token: Some(PageToken {
+                    value: page_token.to_string(),
+                }),
+            }),
+            include_hidden: false,
+            recursive: true,
+            max_depth: 0,
+        };
+        let response = service
+            .list_directory(Request::new(list("")))
+            .await
+            .unwrap();
+        let Some(list_directory_response::Result::Success(first)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        let languages: Vec<(&str, &str)> = first
+            .entries
+            .iter()
+            .map(|e| {
+                (
+                    e.file_id.as_ref().unwrap().path.as_str(),
+                    e.language_id.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            languages,
+            [
+                ("src", ""),
+                ("src/lib.rs", "rust"),
+                ("Dockerfile", "dockerfile")
+            ]
+        );
+        let pagination = first.pagination.unwrap();
+        assert_eq!(pagination.total_count, 4);
+        assert_eq!(first.total_size, 13);
+
+        let response = service
+            .list_directory(Request::new(list(
+                &pagination.next_page_token.unwrap().value,
+            )))
+            .await
+            .unwrap();
+        let Some(list_directory_response::Result::Success(second)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!(second.entries[0].language_id, "html");
+        assert!(second.pagination.unwrap().next_page_token.is_none());
+
+        let response = service
+            .list_languages(Request::new(ListLanguagesRequest {}))
+            .await
+            .unwrap();
+        let languages = response.into_inner().languages;
+        let rust = languages.iter().find(|l| l.language_id == "rust").unwrap();
+        assert!(rust.has_grammar);
+        assert!(languages
+            .iter()
+            .any(|l| l.language_id == "makefile" && !l.has_grammar));
+    }
+
+    async fn next(stream: &mut ResponseStream<WatchSettingsResponse>) -> WatchSettingsResponse {
+        tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_effective_settings_and_updates() {
+        let dir = TempDir::new().unwrap();
+        fs::write(
+            dir.path().join(EDITORCONFIG),
+            "root = true\n[*]\nindent_size = 2\n[*.go]\nindent_style = tab\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("main.py"), "def f():\n\tpass\n").unwrap();
+        let service = service();
+        let response = service
+            .open_workspace(Request::new(OpenWorkspaceRequest {
+                request_id: None,
+                folder_path: dir.path().to_string_lossy().into_owned(),
+                name: String::new(),
+                exclude_patterns: vec![],
+                language_overrides: HashMap::new(),
+            }))
+            .await
+            .unwrap();
+        let Some(open_workspace_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+        let workspace_id = success.workspace_id.unwrap();
+        let file = |path: &str| FileId {
+            path: path.to_string(),
+        };
+
+        let get = |path: &str| GetEffectiveSettingsRequest {
+            request_id: None,
+            workspace_id: Some(workspace_id.clone()),
+            file_id: Some(file(path)),
+        };
+        let response = service
+            .get_effective_settings(Request::new(get("main.py")))
+            .await
+            .unwrap();
+        let Some(get_effective_settings_response::Result::Settings(settings)) =
+            response.into_inner().result
+        else {
+            panic!("Expected settings");
+        };
+        assert_eq!(settings.language_id, "python");
+        // The size is configured, the tabs are detected
+        assert_eq!(settings.indent_style, IndentStyle::Tab as i32);
+        assert_eq!(settings.indent_size, 2);
+        assert_eq!(settings.sources, [".editorconfig", "detected"]);
+
+        let response = service
+            .get_effective_settings(Request::new(get("../outside.rs")))
+            .await
+            .unwrap();
+        let Some(get_effective_settings_response::Result::Error(error)) =
+            response.into_inner().result
+        else {
+            panic!("Expected error");
+        };
+        assert_eq!(error.code, "INVALID_PATH");
+
+        let mut stream = service
+            .watch_settings(Request::new(WatchSettingsRequest {
+                workspace_id: Some(workspace_id.clone()),
+                file_ids: vec![file("main.go"), file("notes.txt")],
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        for path in ["main.go", "notes.txt"] {
+            let message = next(&mut stream).await;
+            assert_eq!(message.meta.unwrap().delta_type, DeltaType::Snapshot as i32);
+            assert_eq!(message.settings.unwrap().file_id.unwrap().path, path);
+        }
+
+        // A workspace settings file for Go changes only main.go
+        let settings_file = dir.path().join(SETTINGS_FILE);
+        fs::create_dir_all(settings_file.parent().unwrap()).unwrap();
+        fs::write(&settings_file, r#"{"languages": {"go": {"tab_width": 8}}}"#).unwrap();
+        service
+            .settings
+            .files_changed(&workspace_id.value, [settings_file.as_path()]);
+        let message = next(&mut stream).await;
+        assert_eq!(message.meta.unwrap().delta_type, DeltaType::Update as i32);
+        let settings = message.settings.unwrap();
+        assert_eq!(settings.file_id.unwrap().path, "main.go");
+        assert_eq!(settings.tab_width, 8);
+
+        // Closing the workspace ends the stream
+        service
+            .close_workspace(Request::new(CloseWorkspaceRequest {
+                request_id: None,
+                workspace_id: Some(workspace_id),
+            }))
+            .await
+            .unwrap();
+        let ended = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        assert!(ended.unwrap().is_none());
+    }
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    /// Read tree messages until one has an entry at `path` matching
+    /// `found`, returning that entry and the delta type of its message.
+    async fn wait_for(
+        stream: &mut ResponseStream<WatchFileTreeResponse>,
+        path: &str,
+        found: impl Fn(&FileEntry) -> bool,
+    ) -> (FileEntry, i32) {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(10), stream.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let delta_type = message.meta.unwrap().delta_type;
+            if let Some(entry) = message
+                .entries
+                .into_iter()
+                .find(|e| e.file_id.as_ref().unwrap().path == path && found(e))
+            {
+                return (entry, delta_type);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch_file_tree_with_git_status() {
+        let dir = TempDir::new().unwrap();
+        git(dir.path(), &["init", "-q"]);
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+        fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+        git(dir.path(), &["add", "-A"]);
+        git(dir.path(), &["commit", "-q", "-m", "initial"]);
+        fs::write(dir.path().join("notes.txt"), "").unwrap();
+        let service = service();
+        let response = service
+            .open_workspace(Request::new(OpenWorkspaceRequest {
+                request_id: None,
+                folder_path: dir.path().to_string_lossy().into_owned(),
+                name: String::new(),
+                exclude_patterns: vec![],
+                language_overrides: HashMap::new(),
+            }))
+            .await
+            .unwrap();
+        let Some(open_workspace_response::Result::Success(success)) = response.into_inner().result
+        else {
+            panic!("Expected success");
+        };
+
+        let request = |root_path: &str| WatchFileTreeRequest {
+            workspace_id: success.workspace_id.clone(),
+            root_path: root_path.to_string(),
+            max_depth: 0,
+            include_hidden: false,
+            include_git_status: true,
+        };
+        let Err(status) = service
+            .watch_file_tree(Request::new(request("missing")))
+            .await
+        else {
+            panic!("Expected an error");
+        };
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let mut stream = service
+            .watch_file_tree(Request::new(request("")))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first.meta.unwrap().delta_type, DeltaType::Snapshot as i32);
+        let paths: Vec<_> = first
+            .entries
+            .iter()
+            .map(|e| e.file_id.as_ref().unwrap().path.as_str())
+            .collect();
+        assert_eq!(paths, ["src", "src/lib.rs", "notes.txt"]);
+        assert_eq!(first.entries[1].language_id, "rust");
+
+        // The snapshot may come before the first status is read
+        let untracked = GitFileStatus::Untracked as i32;
+        if first.entries[2].git_status != untracked {
+            wait_for(&mut stream, "notes.txt", |e| e.git_status == untracked).await;
+        }
+
+        fs::write(dir.path().join("src/lib.rs"), "pub fn g() {}\n").unwrap();
+        let modified = GitFileStatus::Modified as i32;
+        let (entry, delta_type) =
+            wait_for(&mut stream, "src/lib.rs", |e| e.git_status == modified).await;
+        assert_eq!(delta_type, DeltaType::Update as i32);
+        assert_eq!(entry.size, 14);
+
+        fs::write(dir.path().join("build.log"), "").unwrap();
+        let (entry, _) = wait_for(&mut stream, "build.log", |e| e.is_ignored).await;
+        assert_eq!(entry.git_status, GitFileStatus::Ignored as i32);
+
+        fs::remove_file(dir.path().join("notes.txt")).unwrap();
+        let (_, delta_type) = wait_for(&mut stream, "notes.txt", |_| true).await;
+        assert_eq!(delta_type, DeltaType::Remove as i32);
+
+        // Committing clears the status through the index
+        git(dir.path(), &["commit", "-q", "-am", "g"]);
+        let clean = GitFileStatus::Unspecified as i32;
+        wait_for(&mut stream, "src/lib.rs", |e| e.git_status == clean).await;
+    }
+}
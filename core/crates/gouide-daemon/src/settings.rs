@@ -0,0 +1,616 @@
+//! Editor settings resolution.
+//!
+//! The settings in effect for a file are merged from these layers, each
+//! overriding the ones before it:
+//!
+//! 1. Built-in defaults: four-space indentation, nothing trimmed or added.
+//! 2. The daemon's user settings, then their section for the file's
+//!    language.
+//! 3. `.editorconfig` files, from the one nearest the file system root down
+//!    to the one in the file's directory. The search upward stops at a file
+//!    that sets `root = true`.
+//! 4. The workspace settings file ([`SETTINGS_FILE`]), then its section for
+//!    the file's language.
+//!
+//! Indentation no layer sets is detected from the file's content, so an
+//! unconfigured tab-indented file keeps its tabs.
+//!
+//! Settings files inside a workspace are cached until the file watcher
+//! reports a change to them; ones above the workspace root are read on
+//! every resolution, since nothing watches them.
+
+mod editorconfig;
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use gouide_workspace::LineEnding;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+
+use self::editorconfig::EditorConfig;
+pub use self::editorconfig::EDITORCONFIG;
+use crate::formatters::FormatOptions;
+
+/// Workspace settings file, relative to the workspace root.
+pub const SETTINGS_FILE: &str = ".gouide/settings.json";
+
+/// Source name of detected indentation.
+const DETECTED: &str = "detected";
+
+/// Lines looked at to detect indentation.
+pub const DETECT_LINES: u32 = 1000;
+
+/// Indentation used when nothing else sets it.
+const DEFAULT_INDENT_SIZE: u32 = 4;
+
+/// Whether to indent with spaces or tabs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndentStyle {
+    /// Indent with spaces.
+    Space,
+    /// Indent with tabs.
+    Tab,
+}
+
+/// Line ending a file is saved with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndOfLine {
+    /// Unix (`\n`).
+    Lf,
+    /// Windows (`\r\n`).
+    Crlf,
+    /// Old Mac (`\r`).
+    Cr,
+}
+
+impl From<EndOfLine> for LineEnding {
+    fn from(end_of_line: EndOfLine) -> Self {
+        match end_of_line {
+            EndOfLine::Lf => Self::Lf,
+            EndOfLine::Crlf => Self::Crlf,
+            EndOfLine::Cr => Self::Cr,
+        }
+    }
+}
+
+/// Settings one layer sets. Unset ones fall through to the layers below.
+///
+/// Names and meanings follow EditorConfig.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettingsLayer {
+    /// Whether to indent with spaces or tabs.
+    pub indent_style: Option<IndentStyle>,
+    /// Columns per indentation level.
+    pub indent_size: Option<u32>,
+    /// Columns a tab is displayed as; defaults to `indent_size`.
+    pub tab_width: Option<u32>,
+    /// Line ending to save with.
+    pub end_of_line: Option<EndOfLine>,
+    /// Remove whitespace at the end of lines on save.
+    pub trim_trailing_whitespace: Option<bool>,
+    /// End the file with a line break on save.
+    pub insert_final_newline: Option<bool>,
+    /// Remove blank lines at the end of the file when formatting.
+    pub trim_final_newlines: Option<bool>,
+    /// Preferred maximum line length; 0 turns a lower layer's off.
+    pub max_line_length: Option<u32>,
+}
+
+impl SettingsLayer {
+    /// Whether the layer sets nothing.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Override these settings with the ones `other` sets.
+    pub fn merge(&mut self, other: &Self) {
+        *self = Self {
+            indent_style: other.indent_style.or(self.indent_style),
+            indent_size: other.indent_size.or(self.indent_size),
+            tab_width: other.tab_width.or(self.tab_width),
+            end_of_line: other.end_of_line.or(self.end_of_line),
+            trim_trailing_whitespace: other
+                .trim_trailing_whitespace
+                .or(self.trim_trailing_whitespace),
+            insert_final_newline: other.insert_final_newline.or(self.insert_final_newline),
+            trim_final_newlines: other.trim_final_newlines.or(self.trim_final_newlines),
+            max_line_length: other.max_line_length.or(self.max_line_length),
+        };
+    }
+}
+
+/// User or workspace settings: general ones and per-language overrides.
+///
+/// In the workspace settings file:
+///
+/// ```json
+/// {
+///   "editor": { "indent_size": 2, "insert_final_newline": true },
+///   "languages": { "python": { "indent_size": 4 } }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Settings for every file.
+    pub editor: SettingsLayer,
+    /// Settings for files of a language, by language ID.
+    pub languages: HashMap<String, SettingsLayer>,
+}
+
+impl Settings {
+    /// Parse a settings file.
+    pub fn parse(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+}
+
+/// Settings in effect for a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveSettings {
+    /// Language the per-language sections were picked by.
+    pub language_id: String,
+    /// Whether to indent with spaces or tabs.
+    pub indent_style: IndentStyle,
+    /// Columns per indentation level.
+    pub indent_size: u32,
+    /// Columns a tab is displayed as.
+    pub tab_width: u32,
+    /// Line ending to save with (`None` keeps the file's own).
+    pub end_of_line: Option<LineEnding>,
+    /// Remove whitespace at the end of lines on save.
+    pub trim_trailing_whitespace: bool,
+    /// End the file with a line break on save.
+    pub insert_final_newline: bool,
+    /// Remove blank lines at the end of the file when formatting.
+    pub trim_final_newlines: bool,
+    /// Preferred maximum line length (0 for none).
+    pub max_line_length: u32,
+    /// Layers that set at least one value, lowest first.
+    pub sources: Vec<String>,
+    /// Settings files that could not be read or parsed, with the reason.
+    pub problems: Vec<String>,
+}
+
+impl EffectiveSettings {
+    /// Options for formatting the file when the client sends none.
+    pub const fn format_options(&self) -> FormatOptions {
+        FormatOptions {
+            tab_size: self.indent_size,
+            insert_spaces: matches!(self.indent_style, IndentStyle::Space),
+            trim_trailing_whitespace: self.trim_trailing_whitespace,
+            insert_final_newline: self.insert_final_newline,
+            trim_final_newlines: self.trim_final_newlines,
+        }
+    }
+
+    /// Options for the clean-up applied when the file is saved, which never
+    /// touches indentation.
+    pub const fn save_options(&self) -> FormatOptions {
+        FormatOptions {
+            tab_size: self.tab_width,
+            insert_spaces: false,
+            trim_trailing_whitespace: self.trim_trailing_whitespace,
+            insert_final_newline: self.insert_final_newline,
+            trim_final_newlines: false,
+        }
+    }
+}
+
+/// A settings file that was read, or why it could not be. `None` when the
+/// file does not exist.
+type Loaded<T> = Option<Result<Arc<T>, String>>;
+
+/// Parsed settings files by path.
+struct FileCache<T>(Mutex<HashMap<PathBuf, Loaded<T>>>);
+
+impl<T> Default for FileCache<T> {
+    fn default() -> Self {
+        Self(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<T> FileCache<T> {
+    /// Read and parse `path`, or take it from the cache. Files are only
+    /// cached when `cache` is set.
+    fn load(
+        &self,
+        path: &Path,
+        cache: bool,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Loaded<T> {
+        if let Some(loaded) = self.0.lock().get(path) {
+            return loaded.clone();
+        }
+        let loaded = match std::fs::read_to_string(path) {
+            Ok(text) => Some(parse(&text).map(Arc::new)),
+            Err(e) if e.kind() == ErrorKind::NotFound || !path.is_file() => None,
+            Err(e) => Some(Err(e.to_string())),
+        };
+        if cache {
+            self.0.lock().insert(path.to_path_buf(), loaded.clone());
+        }
+        loaded
+    }
+
+    fn remove(&self, path: &Path) {
+        self.0.lock().remove(path);
+    }
+
+    fn remove_under(&self, dir: &Path) {
+        self.0.lock().retain(|path, _| !path.starts_with(dir));
+    }
+}
+
+/// Resolves the settings in effect for files and tells subscribers when
+/// settings files change.
+pub struct SettingsResolver {
+    user: Settings,
+    editorconfigs: FileCache<EditorConfig>,
+    settings_files: FileCache<Settings>,
+    /// IDs of workspaces whose settings files changed.
+    changes: broadcast::Sender<String>,
+}
+
+impl SettingsResolver {
+    /// A resolver with the given user settings, buffering `capacity`
+    /// change notifications per subscriber.
+    pub fn new(user: Settings, capacity: usize) -> Self {
+        Self {
+            user,
+            editorconfigs: FileCache::default(),
+            settings_files: FileCache::default(),
+            changes: broadcast::channel(capacity.max(1)).0,
+        }
+    }
+
+    /// Settings for the file at `path` in the workspace at `root`.
+    ///
+    /// The file does not need to exist. `content`, usually its first
+    /// [`DETECT_LINES`] lines, is used to detect indentation.
+    pub fn resolve(
+        &self,
+        root: &Path,
+        path: &Path,
+        language_id: &str,
+        content: Option<&str>,
+    ) -> EffectiveSettings {
+        let mut layers = vec![("user".to_string(), self.user.editor.clone())];
+        if let Some(layer) = self.user.languages.get(language_id) {
+            layers.push((format!("user [{language_id}]"), layer.clone()));
+        }
+        let mut problems = Vec::new();
+
+        for (config_path, config) in self.editorconfigs(root, path, &mut problems) {
+            let dir = config_path.parent();
+            if let Some(relative) = dir.and_then(|dir| relative_path(dir, path)) {
+                layers.push((display(root, &config_path), config.settings(&relative)));
+            }
+        }
+
+        let settings_path = root.join(SETTINGS_FILE);
+        let loaded = self.settings_files.load(&settings_path, true, |text| {
+            Settings::parse(text).map_err(|e| e.to_string())
+        });
+        match loaded {
+            Some(Ok(settings)) => {
+                layers.push((SETTINGS_FILE.to_string(), settings.editor.clone()));
+                if let Some(layer) = settings.languages.get(language_id) {
+                    layers.push((format!("{SETTINGS_FILE} [{language_id}]"), layer.clone()));
+                }
+            }
+            Some(Err(e)) => problems.push(format!("{SETTINGS_FILE}: {e}")),
+            None => {}
+        }
+
+        let mut merged = SettingsLayer::default();
+        let mut sources = Vec::new();
+        for (source, layer) in layers {
+            if !layer.is_empty() {
+                merged.merge(&layer);
+                sources.push(source);
+            }
+        }
+        if let Some((style, size)) = content
+            .filter(|_| merged.indent_style.is_none() || merged.indent_size.is_none())
+            .and_then(detect_indentation)
+        {
+            let style = *merged.indent_style.get_or_insert(style);
+            if style == IndentStyle::Space && merged.indent_size.is_none() {
+                merged.indent_size = size;
+            }
+            sources.push(DETECTED.to_string());
+        }
+
+        let indent_style = merged.indent_style.unwrap_or(IndentStyle::Space);
+        let tab_width = merged
+            .tab_width
+            .or(merged.indent_size)
+            .unwrap_or(DEFAULT_INDENT_SIZE);
+        EffectiveSettings {
+            language_id: language_id.to_string(),
+            indent_style,
+            indent_size: merged.indent_size.unwrap_or(match indent_style {
+                IndentStyle::Space => DEFAULT_INDENT_SIZE,
+                IndentStyle::Tab => tab_width,
+            }),
+            tab_width,
+            end_of_line: merged.end_of_line.map(LineEnding::from),
+            trim_trailing_whitespace: merged.trim_trailing_whitespace.unwrap_or(false),
+            insert_final_newline: merged.insert_final_newline.unwrap_or(false),
+            trim_final_newlines: merged.trim_final_newlines.unwrap_or(false),
+            max_line_length: merged.max_line_length.unwrap_or(0),
+            sources,
+            problems,
+        }
+    }
+
+    /// `.editorconfig` files that apply to `path`, outermost first.
+    fn editorconfigs(
+        &self,
+        root: &Path,
+        path: &Path,
+        problems: &mut Vec<String>,
+    ) -> Vec<(PathBuf, Arc<EditorConfig>)> {
+        let mut configs = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            let config_path = dir.join(EDITORCONFIG);
+            let loaded = self
+                .editorconfigs
+                .load(&config_path, dir.starts_with(root), |text| {
+                    Ok(EditorConfig::parse(text))
+                });
+            match loaded {
+                Some(Ok(config)) => {
+                    let is_root = config.root;
+                    configs.push((config_path, config));
+                    if is_root {
+                        break;
+                    }
+                }
+                Some(Err(e)) => problems.push(format!("{}: {e}", display(root, &config_path))),
+                None => {}
+            }
+        }
+        configs.reverse();
+        configs
+    }
+
+    /// Forget cached settings files among `paths`, which changed in a
+    /// workspace, and notify subscribers if there were any.
+    pub fn files_changed<'a>(&self, workspace_id: &str, paths: impl IntoIterator<Item = &'a Path>) {
+        let mut changed = false;
+        for path in paths {
+            if path.file_name() == Some(OsStr::new(EDITORCONFIG)) {
+                self.editorconfigs.remove(path);
+                changed = true;
+            } else if path.ends_with(SETTINGS_FILE) {
+                self.settings_files.remove(path);
+                changed = true;
+            }
+        }
+        if changed {
+            // No subscribers is fine
+            let _ = self.changes.send(workspace_id.to_string());
+        }
+    }
+
+    /// Forget a closed workspace's cached settings files and wake its
+    /// subscribers, so their streams can end.
+    pub fn workspace_closed(&self, workspace_id: &str, root: &Path) {
+        self.editorconfigs.remove_under(root);
+        self.settings_files.remove_under(root);
+        let _ = self.changes.send(workspace_id.to_string());
+    }
+
+    /// Subscribe to the IDs of workspaces whose settings files change.
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.changes.subscribe()
+    }
+}
+
+/// Indentation of `text`: the style most indented lines use and, for
+/// spaces, the most common step between consecutive indentation levels.
+///
+/// Steps of one column are ignored, as they are mostly the ` *` of block
+/// comments rather than indentation.
+pub fn detect_indentation(text: &str) -> Option<(IndentStyle, Option<u32>)> {
+    let (mut tabs, mut spaces) = (0usize, 0usize);
+    let mut steps = [0usize; 9];
+    let mut previous = 0;
+    for line in text.lines().take(DETECT_LINES as usize) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with('\t') {
+            tabs += 1;
+            continue;
+        }
+        let width = line.len() - line.trim_start_matches(' ').len();
+        if width > 0 {
+            spaces += 1;
+        }
+        if let Some(count) = width
+            .checked_sub(previous)
+            .and_then(|step| steps.get_mut(step))
+        {
+            *count += 1;
+        }
+        previous = width;
+    }
+
+    if tabs == 0 && spaces == 0 {
+        return None;
+    }
+    if tabs > spaces {
+        return Some((IndentStyle::Tab, None));
+    }
+    let size = (2..steps.len())
+        .filter(|&step| steps[step] > 0)
+        .max_by_key(|&step| (steps[step], std::cmp::Reverse(step)))
+        .and_then(|step| u32::try_from(step).ok());
+    Some((IndentStyle::Space, size))
+}
+
+/// `path` relative to `dir`, with `/` separators.
+fn relative_path(dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(dir).ok()?;
+    Some(
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// How a settings file is named in sources and problems: relative to the
+/// workspace root when inside it.
+fn display(root: &Path, path: &Path) -> String {
+    relative_path(root, path).unwrap_or_else(|| path.display().to_string())
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/gen")).unwrap();
+        fs::create_dir_all(root.join(".gouide")).unwrap();
+        fs::write(
+            root.join(EDITORCONFIG),
+            "root = true\n\n[*]\nindent_style = space\nindent_size = 2\nend_of_line = crlf\n\n\
+             [*.{rs,py}]\nindent_size = 4\nmax_line_length = 100\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("src/gen").join(EDITORCONFIG),
+            "[*.rs]\nindent_style = tab\ntab_width = 8\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join(SETTINGS_FILE),
+            r#"{"editor": {"end_of_line": "lf"}, "languages": {"python": {"indent_size": 3}}}"#,
+        )
+        .unwrap();
+        let user = Settings {
+            editor: SettingsLayer {
+                insert_final_newline: Some(true),
+                indent_size: Some(8),
+                ..SettingsLayer::default()
+            },
+            languages: HashMap::new(),
+        };
+        let resolver = SettingsResolver::new(user, 8);
+
+        let rust = resolver.resolve(root, &root.join("src/main.rs"), "rust", None);
+        assert_eq!(rust.indent_style, IndentStyle::Space);
+        assert_eq!(rust.indent_size, 4);
+        assert_eq!(rust.tab_width, 4);
+        assert_eq!(rust.end_of_line, Some(LineEnding::Lf));
+        assert!(rust.insert_final_newline);
+        assert_eq!(rust.max_line_length, 100);
+        assert_eq!(rust.sources, ["user", ".editorconfig", SETTINGS_FILE]);
+
+        let generated = resolver.resolve(root, &root.join("src/gen/out.rs"), "rust", None);
+        assert_eq!(generated.indent_style, IndentStyle::Tab);
+        assert_eq!(generated.indent_size, 4);
+        assert_eq!(generated.tab_width, 8);
+
+        let python = resolver.resolve(root, &root.join("tool.py"), "python", None);
+        assert_eq!(python.indent_size, 3);
+        assert_eq!(
+            python.sources.last().unwrap(),
+            ".gouide/settings.json [python]"
+        );
+
+        let markdown = resolver.resolve(root, &root.join("README.md"), "markdown", None);
+        assert_eq!(markdown.indent_size, 2);
+        assert_eq!(markdown.max_line_length, 0);
+    }
+
+    #[test]
+    fn test_cached_files_reload_after_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let resolver = SettingsResolver::new(Settings::default(), 8);
+        let mut changes = resolver.subscribe();
+        let path = root.join("a.txt");
+        let settings = resolver.resolve(root, &path, "plaintext", None);
+        assert_eq!(settings.sources, Vec::<String>::new());
+
+        fs::create_dir_all(root.join(".gouide")).unwrap();
+        fs::write(
+            root.join(SETTINGS_FILE),
+            "{\"editor\": {\"indent_sise\": 2}}",
+        )
+        .unwrap();
+        // Still cached as missing
+        assert!(resolver
+            .resolve(root, &path, "plaintext", None)
+            .problems
+            .is_empty());
+
+        resolver.files_changed("w1", [root.join("a.txt").as_path()]);
+        assert!(changes.try_recv().is_err());
+        resolver.files_changed("w1", [root.join(SETTINGS_FILE).as_path()]);
+        assert_eq!(changes.try_recv().unwrap(), "w1");
+        let settings = resolver.resolve(root, &path, "plaintext", None);
+        assert_eq!(settings.problems.len(), 1);
+        assert!(
+            settings.problems[0].contains("indent_sise"),
+            "{:?}",
+            settings.problems
+        );
+        assert_eq!(settings.indent_size, DEFAULT_INDENT_SIZE);
+    }
+
+    #[test]
+    fn test_indentation_detected_when_unset() {
+        let tabs = "fn main() {\n\tlet a = 1;\n\tif a {\n\t\tb();\n\t}\n}\n";
+        assert_eq!(detect_indentation(tabs), Some((IndentStyle::Tab, None)));
+        let two = "a:\n  b:\n    c: 1\n  d: 2\n/*\n *\n */\n";
+        assert_eq!(detect_indentation(two), Some((IndentStyle::Space, Some(2))));
+        assert_eq!(detect_indentation("a\nb\n"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let resolver = SettingsResolver::new(Settings::default(), 8);
+        let settings = resolver.resolve(root, &root.join("main.go"), "go", Some(tabs));
+        assert_eq!(settings.indent_style, IndentStyle::Tab);
+        assert_eq!(settings.sources, [DETECTED]);
+
+        // Configured indentation wins over the content
+        fs::write(
+            root.join(EDITORCONFIG),
+            "root = true\n[*]\nindent_style = space\n",
+        )
+        .unwrap();
+        resolver.files_changed("w1", [root.join(EDITORCONFIG).as_path()]);
+        let settings = resolver.resolve(root, &root.join("main.go"), "go", Some(two));
+        assert_eq!(settings.indent_style, IndentStyle::Space);
+        assert_eq!(settings.indent_size, 2);
+        let settings = resolver.resolve(root, &root.join("main.go"), "go", Some(tabs));
+        assert_eq!(settings.indent_style, IndentStyle::Space);
+        assert_eq!(settings.indent_size, DEFAULT_INDENT_SIZE);
+    }
+}
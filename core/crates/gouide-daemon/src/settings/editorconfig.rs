@@ -0,0 +1,206 @@
+//! `.editorconfig` files.
+//!
+//! Only the properties the daemon acts on are read. Unknown properties and
+//! values, including `unset`, are ignored, as the specification asks.
+//! Section globs follow the specification; `{n..m}` ranges are expanded to
+//! the numbers they cover, up to [`MAX_RANGE`] of them.
+
+use globset::{GlobBuilder, GlobMatcher};
+
+use super::{EndOfLine, IndentStyle, SettingsLayer};
+
+/// File name of EditorConfig files.
+pub const EDITORCONFIG: &str = ".editorconfig";
+
+/// Most numbers a `{n..m}` range in a glob may cover.
+const MAX_RANGE: i64 = 1000;
+
+/// A parsed `.editorconfig` file.
+#[derive(Debug, Default)]
+pub(super) struct EditorConfig {
+    /// Whether the search for more files stops here.
+    pub(super) root: bool,
+    sections: Vec<Section>,
+}
+
+/// A `[glob]` section. Sections whose glob does not compile match nothing.
+#[derive(Debug)]
+struct Section {
+    matcher: Option<GlobMatcher>,
+    settings: SettingsLayer,
+}
+
+impl EditorConfig {
+    /// Parse a file, skipping lines it does not understand.
+    pub(super) fn parse(text: &str) -> Self {
+        let mut config = Self::default();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                config.sections.push(Section {
+                    matcher: matcher(glob),
+                    settings: SettingsLayer::default(),
+                });
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim().to_ascii_lowercase();
+            match config.sections.last_mut() {
+                Some(section) => set(&mut section.settings, &key, &value),
+                None if key == "root" => config.root = value == "true",
+                None => {}
+            }
+        }
+        config
+    }
+
+    /// Settings for a file, given by its path relative to this file's
+    /// directory. Later sections override earlier ones.
+    pub(super) fn settings(&self, relative_path: &str) -> SettingsLayer {
+        let mut settings = SettingsLayer::default();
+        for section in &self.sections {
+            if section
+                .matcher
+                .as_ref()
+                .is_some_and(|m| m.is_match(relative_path))
+            {
+                settings.merge(&section.settings);
+            }
+        }
+        settings
+    }
+}
+
+/// Set a property from its lowercased key and value.
+fn set(settings: &mut SettingsLayer, key: &str, value: &str) {
+    let flag = || match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    };
+    match key {
+        "indent_style" => {
+            settings.indent_style = match value {
+                "space" => Some(IndentStyle::Space),
+                "tab" => Some(IndentStyle::Tab),
+                _ => settings.indent_style,
+            };
+        }
+        // "tab" means the tab width, which is what an unset size resolves
+        // to when indenting with tabs
+        "indent_size" => settings.indent_size = value.parse().ok().or(settings.indent_size),
+        "tab_width" => settings.tab_width = value.parse().ok().or(settings.tab_width),
+        "end_of_line" => {
+            settings.end_of_line = match value {
+                "lf" => Some(EndOfLine::Lf),
+                "crlf" => Some(EndOfLine::Crlf),
+                "cr" => Some(EndOfLine::Cr),
+                _ => settings.end_of_line,
+            };
+        }
+        "trim_trailing_whitespace" => {
+            settings.trim_trailing_whitespace = flag().or(settings.trim_trailing_whitespace);
+        }
+        "insert_final_newline" => {
+            settings.insert_final_newline = flag().or(settings.insert_final_newline);
+        }
+        "max_line_length" => {
+            settings.max_line_length = match value {
+                "off" => Some(0),
+                _ => value.parse().ok().or(settings.max_line_length),
+            };
+        }
+        _ => {}
+    }
+}
+
+/// Compile a section glob. Globs without a `/` match file names at any
+/// depth; others match paths relative to the `.editorconfig` directory.
+fn matcher(glob: &str) -> Option<GlobMatcher> {
+    let glob = expand_ranges(glob);
+    let pattern = match glob.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if glob.contains('/') => glob,
+        None => format!("**/{glob}"),
+    };
+    GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .ok()
+        .map(|glob| glob.compile_matcher())
+}
+
+/// Replace `{n..m}` ranges, which globset does not know, with lists of the
+/// numbers they cover.
+fn expand_ranges(glob: &str) -> String {
+    let mut expanded = String::with_capacity(glob.len());
+    let mut rest = glob;
+    while let Some(open) = rest.find('{') {
+        expanded.push_str(&rest[..=open]);
+        rest = &rest[open + 1..];
+        let range = rest.find('}').and_then(|close| {
+            let (low, high) = rest[..close].split_once("..")?;
+            let (low, high): (i64, i64) = (low.parse().ok()?, high.parse().ok()?);
+            (low.abs_diff(high) < MAX_RANGE.unsigned_abs()).then_some((low, high, close))
+        });
+        if let Some((low, high, close)) = range {
+            let numbers: Vec<String> = (low.min(high)..=low.max(high))
+                .map(|n| n.to_string())
+                .collect();
+            expanded.push_str(&numbers.join(","));
+            rest = &rest[close..];
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sections_match_like_editorconfig() {
+        let config = EditorConfig::parse(
+            "; comment\nroot = TRUE\n\n[*]\nindent_style = space\nindent_size = 2\n\
+             [*.md]\ntrim_trailing_whitespace = false\nmax_line_length = off\n\
+             [Makefile]\nindent_style = tab\nindent_size = tab\n\
+             [/docs/*.txt]\nend_of_line = CRLF\n\
+             [file{1..3}.py]\ninsert_final_newline = true\nunknown = 1\nindent_size = unset\n",
+        );
+        assert!(config.root);
+
+        let markdown = config.settings("deep/dir/README.md");
+        assert_eq!(markdown.indent_size, Some(2));
+        assert_eq!(markdown.trim_trailing_whitespace, Some(false));
+        assert_eq!(markdown.max_line_length, Some(0));
+
+        let makefile = config.settings("src/Makefile");
+        assert_eq!(makefile.indent_style, Some(IndentStyle::Tab));
+        assert_eq!(makefile.indent_size, Some(2));
+
+        assert_eq!(
+            config.settings("docs/a.txt").end_of_line,
+            Some(EndOfLine::Crlf)
+        );
+        // `*` does not cross directories and anchored globs stay anchored
+        assert_eq!(config.settings("docs/x/a.txt").end_of_line, None);
+        assert_eq!(config.settings("src/docs/a.txt").end_of_line, None);
+
+        assert_eq!(config.settings("file2.py").insert_final_newline, Some(true));
+        assert_eq!(config.settings("file4.py").insert_final_newline, None);
+        assert_eq!(config.settings("file2.py").indent_size, Some(2));
+    }
+}
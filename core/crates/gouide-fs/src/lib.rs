@@ -0,0 +1,23 @@
+//! Gouide file system watching.
+//!
+//! This crate wraps the platform file watcher and turns its raw, noisy event
+//! stream into small coalesced batches of [`FsEvent`]s that the daemon can act
+//! on (reloading buffers, updating the file tree).
+
+mod watcher;
+
+use thiserror::Error;
+
+pub use watcher::{FileWatcher, FsEvent, FsEventKind, WatchOptions};
+
+/// Errors that can occur while watching the file system.
+#[derive(Error, Debug)]
+pub enum FsError {
+    /// The platform watcher could not be created or could not watch a path.
+    #[error("Watcher error: {0}")]
+    Watch(#[from] notify::Error),
+
+    /// An I/O error occurred.
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
@@ -0,0 +1,363 @@
+//! Debounced recursive file watcher.
+//!
+//! Platform watchers report every low-level step of a change: an editor's
+//! atomic save is a create, a write and one or two rename events; a `git
+//! checkout` is thousands of removes and creates. The watcher collects events
+//! for a short window after the first one arrives and coalesces them per path,
+//! so consumers see one event per file with its net effect.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{debug, warn};
+
+use crate::FsError;
+
+/// Net effect of a batch of changes on one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsEventKind {
+    /// The path was created.
+    Created,
+    /// The file content changed (or may have changed).
+    Modified,
+    /// The path was removed.
+    Removed,
+    /// The path was moved here from `from`.
+    Renamed {
+        /// Previous location.
+        from: PathBuf,
+    },
+    /// Metadata such as permissions changed.
+    Metadata,
+}
+
+/// A coalesced file system event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsEvent {
+    /// Absolute path the event applies to.
+    pub path: PathBuf,
+    /// What happened to it.
+    pub kind: FsEventKind,
+}
+
+/// Options for a [`FileWatcher`].
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    /// How long to collect events after the first one before delivering the
+    /// batch.
+    pub debounce: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(50),
+        }
+    }
+}
+
+/// A recursive watcher on one directory tree.
+///
+/// Batches are delivered to the handler on a dedicated thread, so the handler
+/// may block (for example to reload a file). Dropping the watcher stops it.
+pub struct FileWatcher {
+    root: PathBuf,
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    /// Start watching `root` recursively.
+    pub fn watch<F>(root: &Path, options: WatchOptions, handler: F) -> Result<Self, FsError>
+    where
+        F: FnMut(Vec<FsEvent>) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+
+        thread::Builder::new()
+            .name("gouide-fs-watcher".to_string())
+            .spawn(move || deliver(&rx, options.debounce, handler))?;
+
+        debug!(root = %root.display(), "File watcher started");
+        Ok(Self {
+            root: root.to_path_buf(),
+            _watcher: watcher,
+        })
+    }
+
+    /// Root of the watched tree.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+/// Collect raw events into debounced batches until the watcher is dropped.
+fn deliver<F>(rx: &mpsc::Receiver<notify::Result<Event>>, debounce: Duration, mut handler: F)
+where
+    F: FnMut(Vec<FsEvent>),
+{
+    while let Ok(first) = rx.recv() {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + debounce;
+        let mut disconnected = false;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining) {
+                Ok(event) => batch.push(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+
+        let events = coalesce(batch.into_iter().filter_map(|event| {
+            event
+                .map_err(|e| warn!(error = %e, "File watcher error"))
+                .ok()
+        }));
+        if !events.is_empty() {
+            handler(events);
+        }
+        if disconnected {
+            break;
+        }
+    }
+}
+
+/// Reduce raw platform events to one net event per path, in first-seen order.
+fn coalesce(raw: impl IntoIterator<Item = Event>) -> Vec<FsEvent> {
+    let mut batch = Batch::default();
+    for event in raw {
+        match event.kind {
+            EventKind::Access(_) => {}
+            EventKind::Create(_) => batch.record_all(event.paths, &FsEventKind::Created),
+            EventKind::Remove(_) => batch.record_all(event.paths, &FsEventKind::Removed),
+            EventKind::Modify(ModifyKind::Metadata(_)) => {
+                batch.record_all(event.paths, &FsEventKind::Metadata);
+            }
+            EventKind::Modify(ModifyKind::Name(mode)) => batch.rename(mode, event.paths),
+            EventKind::Modify(_) | EventKind::Any | EventKind::Other => {
+                batch.record_all(event.paths, &FsEventKind::Modified);
+            }
+        }
+    }
+    batch.finish()
+}
+
+/// Per-path state while coalescing a batch.
+#[derive(Default)]
+struct Batch {
+    order: Vec<PathBuf>,
+    kinds: HashMap<PathBuf, Option<FsEventKind>>,
+    /// Paths first created within this batch.
+    created: HashSet<PathBuf>,
+}
+
+impl Batch {
+    fn record_all(&mut self, paths: Vec<PathBuf>, kind: &FsEventKind) {
+        for path in paths {
+            self.record(path, kind.clone());
+        }
+    }
+
+    fn rename(&mut self, mode: RenameMode, paths: Vec<PathBuf>) {
+        match (mode, paths.as_slice()) {
+            (RenameMode::Both, [from, to]) => {
+                // A file created in this batch and renamed into place (an
+                // atomic save) is a modification of the target, not a move.
+                let transient = self.created.contains(from);
+                self.forget(from);
+                self.forget(to);
+                let kind = if transient {
+                    FsEventKind::Modified
+                } else {
+                    FsEventKind::Renamed { from: from.clone() }
+                };
+                self.record(to.clone(), kind);
+            }
+            (RenameMode::From, _) => self.record_all(paths, &FsEventKind::Removed),
+            (RenameMode::To, _) => self.record_all(paths, &FsEventKind::Created),
+            // Platforms that cannot pair rename halves report each path alone
+            _ => {
+                for path in paths {
+                    let kind = if path.exists() {
+                        FsEventKind::Created
+                    } else {
+                        FsEventKind::Removed
+                    };
+                    self.record(path, kind);
+                }
+            }
+        }
+    }
+
+    fn current(&self, path: &Path) -> Option<&FsEventKind> {
+        self.kinds.get(path).and_then(Option::as_ref)
+    }
+
+    fn forget(&mut self, path: &Path) {
+        if let Some(kind) = self.kinds.get_mut(path) {
+            *kind = None;
+        }
+    }
+
+    fn record(&mut self, path: PathBuf, kind: FsEventKind) {
+        if kind == FsEventKind::Created && self.current(&path).is_none() {
+            self.created.insert(path.clone());
+        }
+        let merged = match (self.current(&path), kind) {
+            // Created and removed again within the batch: nothing to report
+            (Some(FsEventKind::Created), FsEventKind::Removed) => None,
+            (Some(FsEventKind::Created), FsEventKind::Modified | FsEventKind::Metadata) => {
+                Some(FsEventKind::Created)
+            }
+            // Removed and recreated (e.g. by git): the content was replaced
+            (Some(FsEventKind::Removed), FsEventKind::Created | FsEventKind::Modified) => {
+                Some(FsEventKind::Modified)
+            }
+            (
+                Some(FsEventKind::Modified | FsEventKind::Renamed { .. }),
+                FsEventKind::Modified | FsEventKind::Metadata,
+            ) => self.current(&path).cloned(),
+            (_, kind) => Some(kind),
+        };
+        if !self.kinds.contains_key(&path) {
+            self.order.push(path.clone());
+        }
+        self.kinds.insert(path, merged);
+    }
+
+    fn finish(mut self) -> Vec<FsEvent> {
+        self.order
+            .into_iter()
+            .filter_map(|path| {
+                let kind = self.kinds.remove(&path).flatten()?;
+                Some(FsEvent { path, kind })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+
+    use notify::event::{CreateKind, DataChange, MetadataKind, RemoveKind};
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn event(kind: EventKind, paths: &[&str]) -> Event {
+        paths
+            .iter()
+            .fold(Event::new(kind), |event, path| event.add_path(path.into()))
+    }
+
+    fn fs_event(path: &str, kind: FsEventKind) -> FsEvent {
+        FsEvent {
+            path: path.into(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_atomic_save_is_a_modification() {
+        let events = coalesce([
+            event(EventKind::Create(CreateKind::File), &["/w/.a.rs.tmp"]),
+            event(
+                EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+                &["/w/.a.rs.tmp"],
+            ),
+            event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+                &["/w/.a.rs.tmp"],
+            ),
+            event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+                &["/w/a.rs"],
+            ),
+            event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &["/w/.a.rs.tmp", "/w/a.rs"],
+            ),
+        ]);
+        assert_eq!(events, vec![fs_event("/w/a.rs", FsEventKind::Modified)]);
+    }
+
+    #[test]
+    fn test_rename_pairs_halves() {
+        let events = coalesce([
+            event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+                &["/w/old.rs"],
+            ),
+            event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+                &["/w/new.rs"],
+            ),
+            event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &["/w/old.rs", "/w/new.rs"],
+            ),
+        ]);
+        assert_eq!(
+            events,
+            vec![fs_event(
+                "/w/new.rs",
+                FsEventKind::Renamed {
+                    from: "/w/old.rs".into()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_remove_and_recreate_is_a_modification() {
+        let events = coalesce([
+            event(EventKind::Remove(RemoveKind::File), &["/w/a.rs"]),
+            event(EventKind::Create(CreateKind::File), &["/w/a.rs"]),
+            event(EventKind::Create(CreateKind::File), &["/w/tmp"]),
+            event(EventKind::Remove(RemoveKind::File), &["/w/tmp"]),
+            event(
+                EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)),
+                &["/w/b.rs"],
+            ),
+        ]);
+        assert_eq!(
+            events,
+            vec![
+                fs_event("/w/a.rs", FsEventKind::Modified),
+                fs_event("/w/b.rs", FsEventKind::Metadata),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watcher_delivers_batches() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let _watcher = FileWatcher::watch(&root, WatchOptions::default(), move |events| {
+            let _ = tx.send(events);
+        })
+        .unwrap();
+
+        fs::write(root.join("a.txt"), "hello").unwrap();
+        let events = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(events.iter().any(|e| e.path == root.join("a.txt")));
+    }
+}
@@ -0,0 +1,105 @@
+//! Running the `git` executable.
+
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use crate::GitError;
+
+/// Run git in `dir` and return what it printed on stdout.
+///
+/// Commands never take optional locks, so reading status does not contend
+/// with the user's own git commands by refreshing the index, and pathspecs
+/// are taken literally so file names with glob characters work.
+pub(crate) fn git<I, S>(dir: &Path, args: I) -> Result<Vec<u8>, GitError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<S> = args.into_iter().collect();
+    let output = command(dir)
+        .arg("--literal-pathspecs")
+        .args(&args)
+        .stdin(Stdio::null())
+        .output()?;
+    finish(&args, output)
+}
+
+/// Run git in `dir` like [`git`], but with pathspecs left as globs.
+///
+/// `git stash` needs this: with literal pathspecs it stashes untracked
+/// files and then fails to match them when cleaning them out of the work
+/// tree.
+pub(crate) fn git_globbing<I, S>(dir: &Path, args: I) -> Result<Vec<u8>, GitError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<S> = args.into_iter().collect();
+    let output = command(dir).args(&args).stdin(Stdio::null()).output()?;
+    finish(&args, output)
+}
+
+/// Run git in `dir` with `input` on stdin, and return what it printed on
+/// stdout.
+pub(crate) fn git_input<I, S>(dir: &Path, args: I, input: &[u8]) -> Result<Vec<u8>, GitError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<S> = args.into_iter().collect();
+    let mut child = command(dir)
+        .arg("--literal-pathspecs")
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // The commands taking input read all of it before printing anything
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input)?;
+    }
+    finish(&args, child.wait_with_output()?)
+}
+
+fn command(dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command
+        .arg("--no-optional-locks")
+        .current_dir(dir)
+        .env("GIT_OPTIONAL_LOCKS", "0");
+    command
+}
+
+/// Stdout of a finished command, or its error. Some commands explain a
+/// failure on stdout rather than stderr.
+fn finish<S: AsRef<OsStr>>(args: &[S], output: Output) -> Result<Vec<u8>, GitError> {
+    if output.status.success() {
+        return Ok(output.stdout);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(GitError::Command {
+        command: args
+            .first()
+            .map(|arg| arg.as_ref().to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        message: if stderr.is_empty() {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        } else {
+            stderr
+        },
+    })
+}
+
+/// Run git in `dir` and return its stdout as text, without the trailing
+/// newline.
+pub(crate) fn git_text<I, S>(dir: &Path, args: I) -> Result<String, GitError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let stdout = git(dir, args)?;
+    let text = String::from_utf8(stdout).map_err(|e| GitError::Parse(e.to_string()))?;
+    Ok(text.trim_end_matches('\n').to_string())
+}
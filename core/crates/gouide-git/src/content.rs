@@ -0,0 +1,202 @@
+//! Content of files in the index and in `HEAD`.
+//!
+//! A file's repository is found from its own directory, so it is the
+//! innermost one holding the file, nested repositories and submodules
+//! included, without a [`StatusTracker`](crate::StatusTracker).
+
+use std::path::{Path, PathBuf};
+
+use crate::command::{git, git_text};
+use crate::tracker::slash_path;
+use crate::GitError;
+
+/// Version of a file to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Revision {
+    /// The staged version.
+    Index,
+    /// The version in the `HEAD` commit.
+    Head,
+}
+
+/// A file's place in its repository.
+pub(crate) struct Located {
+    /// Top of the work tree, where git runs.
+    pub(crate) toplevel: PathBuf,
+    /// Path of the file relative to the top, with `/` separators.
+    pub(crate) path: String,
+}
+
+/// Find the repository whose work tree holds an absolute path, which need
+/// not exist any more.
+pub(crate) fn locate(path: &Path) -> Result<Located, GitError> {
+    let not_in_repository = || GitError::NotInRepository(path.to_path_buf());
+    let mut dir = path.parent().unwrap_or(path);
+    while !dir.is_dir() {
+        dir = dir.parent().ok_or_else(not_in_repository)?;
+    }
+    let relative = path.strip_prefix(dir).map_err(|_| not_in_repository())?;
+    // Fails outside a work tree, inside a git directory included
+    let output = git_text(dir, ["rev-parse", "--show-toplevel", "--show-prefix"])
+        .map_err(|_| not_in_repository())?;
+    let mut lines = output.lines();
+    let toplevel = lines.next().ok_or_else(not_in_repository)?;
+    let prefix = lines.next().unwrap_or_default();
+    Ok(Located {
+        toplevel: PathBuf::from(toplevel),
+        path: format!("{prefix}{}", slash_path(relative)),
+    })
+}
+
+/// Content of the file at an absolute path in a revision, or `None` if the
+/// revision does not have it.
+///
+/// Fails with [`GitError::NotInRepository`] if the path is not in a
+/// repository's work tree.
+pub fn file_content(path: &Path, revision: Revision) -> Result<Option<Vec<u8>>, GitError> {
+    let file = locate(path)?;
+    let dir = file.toplevel.as_path();
+    let object = match revision {
+        Revision::Index => {
+            let output = git(dir, ["ls-files", "--stage", "-z", "--", &file.path])?;
+            index_entry(&output, &file.path).map(|entry| entry.object)
+        }
+        Revision::Head => {
+            if !has_head(dir) {
+                return Ok(None);
+            }
+            let output = git(dir, ["ls-tree", "-z", "HEAD", "--", &file.path])?;
+            tree_object(&output, &file.path)
+        }
+    };
+    match object {
+        Some(object) => Ok(Some(git(dir, ["cat-file", "blob", object.as_str()])?)),
+        None => Ok(None),
+    }
+}
+
+/// Whether a repository has a commit checked out; a new one has none yet.
+pub(crate) fn has_head(dir: &Path) -> bool {
+    git(dir, ["rev-parse", "--verify", "--quiet", "HEAD"]).is_ok()
+}
+
+/// A file's entry in the index.
+pub(crate) struct IndexEntry {
+    /// File mode, such as `100644`.
+    pub(crate) mode: String,
+    /// The blob.
+    pub(crate) object: String,
+}
+
+/// Entry of a file in `git ls-files --stage -z` output: the merged entry,
+/// or during a conflict the one from our side. A directory lists the files
+/// under it instead, none of them named `name`.
+pub(crate) fn index_entry(output: &[u8], name: &str) -> Option<IndexEntry> {
+    let output = String::from_utf8_lossy(output);
+    let mut stages = Vec::new();
+    for record in output.split('\0').filter(|record| !record.is_empty()) {
+        let (fields, path) = record.split_once('\t')?;
+        if path != name {
+            continue;
+        }
+        let mut fields = fields.split(' ');
+        let (mode, object, stage) = (fields.next()?, fields.next()?, fields.next()?);
+        // Submodules are commits, not files
+        if mode == "160000" {
+            return None;
+        }
+        stages.push((stage, mode, object));
+    }
+    ["0", "2"].iter().find_map(|wanted| {
+        stages
+            .iter()
+            .find(|(stage, _, _)| stage == wanted)
+            .map(|(_, mode, object)| IndexEntry {
+                mode: (*mode).to_string(),
+                object: (*object).to_string(),
+            })
+    })
+}
+
+/// Blob of a file in `git ls-tree -z` output; directories and submodules
+/// are trees and commits.
+fn tree_object(output: &[u8], name: &str) -> Option<String> {
+    String::from_utf8_lossy(output)
+        .split('\0')
+        .filter_map(|record| record.split_once('\t'))
+        .filter(|(_, path)| *path == name)
+        .find_map(|(fields, _)| {
+            let mut fields = fields.split(' ');
+            let (_mode, kind, object) = (fields.next()?, fields.next()?, fields.next()?);
+            (kind == "blob").then(|| object.to_string())
+        })
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn run(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    fn content(path: &Path, revision: Revision) -> Option<String> {
+        file_content(path, revision)
+            .unwrap()
+            .map(|bytes| String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn test_file_content_in_index_and_head() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("src/a.txt");
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(&file, "one\n").unwrap();
+        run(dir.path(), &["init", "-q"]);
+        run(dir.path(), &["add", "-A"]);
+        assert_eq!(content(&file, Revision::Index).as_deref(), Some("one\n"));
+        assert_eq!(content(&file, Revision::Head), None);
+
+        run(dir.path(), &["commit", "-q", "-m", "one"]);
+        fs::write(&file, "two\n").unwrap();
+        run(dir.path(), &["add", "src/a.txt"]);
+        fs::write(&file, "three\n").unwrap();
+        assert_eq!(content(&file, Revision::Index).as_deref(), Some("two\n"));
+        assert_eq!(content(&file, Revision::Head).as_deref(), Some("one\n"));
+
+        fs::write(dir.path().join("src/new.txt"), "new\n").unwrap();
+        assert_eq!(
+            content(&dir.path().join("src/new.txt"), Revision::Index),
+            None
+        );
+        assert_eq!(content(&dir.path().join("src"), Revision::Index), None);
+        assert_eq!(content(&dir.path().join("src"), Revision::Head), None);
+
+        fs::remove_dir_all(dir.path().join("src")).unwrap();
+        assert_eq!(content(&file, Revision::Head).as_deref(), Some("one\n"));
+
+        let outside = TempDir::new().unwrap();
+        assert!(matches!(
+            file_content(&outside.path().join("a.txt"), Revision::Index),
+            Err(GitError::NotInRepository(_))
+        ));
+    }
+}
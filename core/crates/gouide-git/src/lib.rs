@@ -0,0 +1,88 @@
+//! Gouide git integration.
+//!
+//! Status comes from the `git` executable rather than a reimplementation of
+//! its index and ignore handling: a [`StatusTracker`] runs `git status` in
+//! each repository of a workspace and keeps the [`FileStatus`] of every
+//! changed, untracked and ignored path, keyed by workspace-relative path.
+//!
+//! A workspace may sit inside a larger repository, hold several
+//! repositories side by side, or contain nested repositories and
+//! submodules; each gets its own status run, limited to the workspace.
+//! After the first scan, file changes refresh only the paths they touch,
+//! and changes to a repository's index, `HEAD` or refs refresh that
+//! repository alone.
+//!
+//! [`file_content`] reads the staged or committed version of a file, which
+//! open buffers are diffed against, and [`stage`], [`commit`],
+//! [`switch_branch`] and their neighbours change the index, branches and
+//! stashes of the repository holding a path.
+
+mod command;
+mod content;
+mod status;
+mod tracker;
+mod worktree;
+
+use thiserror::Error;
+
+pub use content::{file_content, Revision};
+pub use status::FileStatus;
+pub use tracker::StatusTracker;
+pub use worktree::{
+    branches, commit, create_branch, git_dir, stage, stage_content, stash_pop, stash_push,
+    switch_branch, toplevel, unstage, Branch, ChangeKind, StashPop, WorkTreeChange,
+};
+
+/// Errors from running git.
+#[derive(Error, Debug)]
+pub enum GitError {
+    /// The `git` executable could not be run.
+    #[error("Cannot run git: {0}")]
+    Spawn(#[from] std::io::Error),
+
+    /// A git command exited with an error.
+    #[error("git {command} failed: {message}")]
+    Command {
+        /// The subcommand that failed.
+        command: String,
+        /// What git printed on stderr.
+        message: String,
+    },
+
+    /// A path is not in any repository's work tree.
+    #[error("{} is not in a git repository", .0.display())]
+    NotInRepository(std::path::PathBuf),
+
+    /// A commit was asked for with nothing staged.
+    #[error("Nothing to commit")]
+    NothingToCommit,
+
+    /// A name git does not accept for a branch.
+    #[error("{0:?} is not a valid branch name")]
+    InvalidBranchName(String),
+
+    /// A branch to create already exists.
+    #[error("Branch {0} already exists")]
+    BranchExists(String),
+
+    /// A branch to switch to does not exist.
+    #[error("Branch {0} does not exist")]
+    BranchNotFound(String),
+
+    /// A checkout or stash stopped because it would overwrite local
+    /// changes; the message names the files.
+    #[error("Local changes would be overwritten: {0}")]
+    LocalChanges(String),
+
+    /// A stash was asked for with no local changes.
+    #[error("No local changes to stash")]
+    NothingToStash,
+
+    /// A stash was popped with none saved.
+    #[error("No stash to pop")]
+    NoStash,
+
+    /// Git printed output this crate does not understand.
+    #[error("Unexpected git output: {0}")]
+    Parse(String),
+}
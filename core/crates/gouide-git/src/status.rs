@@ -0,0 +1,154 @@
+//! Parsing `git status --porcelain=v2 -z`.
+
+use crate::GitError;
+
+/// Git status of one path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileStatus {
+    /// Not tracked.
+    Untracked,
+    /// Changed from `HEAD`, in the index or the work tree.
+    Modified,
+    /// New in the index.
+    Added,
+    /// Deleted in the index or the work tree.
+    Deleted,
+    /// Renamed in the index; the status is on the new path.
+    Renamed,
+    /// Copied in the index; the status is on the new path.
+    Copied,
+    /// Ignored by a `.gitignore` or exclude rule.
+    Ignored,
+    /// Unmerged, with conflicts to resolve.
+    Conflict,
+}
+
+/// One record of status output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StatusEntry {
+    /// Path relative to the repository's work tree, without a trailing `/`.
+    pub(crate) path: String,
+    pub(crate) status: FileStatus,
+    /// Whether git reported a directory: an ignored one, or a repository
+    /// nested in an untracked one.
+    pub(crate) directory: bool,
+    /// Whether the path is a submodule.
+    pub(crate) submodule: bool,
+}
+
+/// Parse the output of `git status --porcelain=v2 -z`.
+pub(crate) fn parse(output: &[u8]) -> Result<Vec<StatusEntry>, GitError> {
+    let text = String::from_utf8_lossy(output);
+    let mut records = text.split('\0').filter(|record| !record.is_empty());
+    let mut entries = Vec::new();
+    while let Some(record) = records.next() {
+        let (kind, rest) = record.split_once(' ').unwrap_or((record, ""));
+        let entry = match kind {
+            "#" => continue,
+            "?" => entry(rest, FileStatus::Untracked, false),
+            "!" => entry(rest, FileStatus::Ignored, false),
+            "1" => changed(rest, 7)?,
+            "2" => {
+                // The original path follows as its own record
+                records.next();
+                changed(rest, 8)?
+            }
+            "u" => {
+                let (submodule, path) = fields(rest, 9)?;
+                entry(path, FileStatus::Conflict, submodule)
+            }
+            _ => return Err(GitError::Parse(format!("unknown status record {record:?}"))),
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn entry(path: &str, status: FileStatus, submodule: bool) -> StatusEntry {
+    let trimmed = path.trim_end_matches('/');
+    StatusEntry {
+        path: trimmed.to_string(),
+        status,
+        directory: trimmed.len() != path.len(),
+        submodule,
+    }
+}
+
+/// An ordinary or renamed record, with `count` fields before its path.
+fn changed(rest: &str, count: usize) -> Result<StatusEntry, GitError> {
+    let mut xy = rest.chars();
+    let (x, y) = (xy.next().unwrap_or('.'), xy.next().unwrap_or('.'));
+    let status = match (x, y) {
+        ('R', _) => FileStatus::Renamed,
+        ('C', _) => FileStatus::Copied,
+        ('A', _) => FileStatus::Added,
+        ('D', _) | (_, 'D') => FileStatus::Deleted,
+        _ => FileStatus::Modified,
+    };
+    let (submodule, path) = fields(rest, count)?;
+    Ok(entry(path, status, submodule))
+}
+
+/// Split off the `count` space-separated fields before a record's path,
+/// the first being `XY` and the second the submodule state. Returns
+/// whether the record is a submodule's, and the path.
+fn fields(rest: &str, count: usize) -> Result<(bool, &str), GitError> {
+    let mut parts = rest.splitn(count + 1, ' ');
+    let submodule = parts.nth(1).is_some_and(|sub| sub.starts_with('S'));
+    let path = parts
+        .nth(count - 2)
+        .ok_or_else(|| GitError::Parse(format!("short status record {rest:?}")))?;
+    Ok((submodule, path))
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_records() {
+        let output = [
+            "# branch.oid 1234",
+            "1 .M N... 100644 100644 100644 aaaa aaaa src/main.rs",
+            "1 A. N... 000000 100644 100644 0000 bbbb new file.rs",
+            "1 MD N... 100644 100644 000000 aaaa bbbb gone.rs",
+            "2 R. N... 100644 100644 100644 aaaa aaaa R100 moved.rs",
+            "old.rs",
+            "1 .M SC.. 160000 160000 160000 cccc cccc vendor/lib",
+            "u UU N... 100644 100644 100644 100644 aaaa bbbb cccc both.rs",
+            "? notes.txt",
+            "? nested/",
+            "! target/",
+            "",
+        ]
+        .join("\0");
+        let entries = parse(output.as_bytes()).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.status, e.directory, e.submodule))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("src/main.rs", FileStatus::Modified, false, false),
+                ("new file.rs", FileStatus::Added, false, false),
+                ("gone.rs", FileStatus::Deleted, false, false),
+                ("moved.rs", FileStatus::Renamed, false, false),
+                ("vendor/lib", FileStatus::Modified, false, true),
+                ("both.rs", FileStatus::Conflict, false, false),
+                ("notes.txt", FileStatus::Untracked, false, false),
+                ("nested", FileStatus::Untracked, true, false),
+                ("target", FileStatus::Ignored, true, false),
+            ]
+        );
+
+        assert!(parse(b"x what\0").is_err());
+        assert!(parse(b"1 .M N...\0").is_err());
+    }
+}
@@ -0,0 +1,718 @@
+//! Git status of the files of a workspace, kept current incrementally.
+//!
+//! Git only reports what differs from `HEAD` and the index, so a tracker
+//! holds the changed, untracked and ignored paths of each repository and a
+//! path it does not hold is clean. The first refresh runs a full status in
+//! every repository it finds. Afterwards [`StatusTracker::update`] sorts the
+//! paths a file watcher reports:
+//!
+//! - work tree files are refreshed with a status limited to those paths;
+//! - `.gitignore` changes, and changes to a repository's index, `HEAD`,
+//!   refs or excludes, refresh that whole repository;
+//! - `.git` entries appearing or disappearing, and `.gitmodules` changes,
+//!   look for repositories again.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use tracing::debug;
+
+use crate::command::{git, git_text};
+use crate::status::{parse, StatusEntry};
+use crate::{FileStatus, GitError};
+
+/// Paths refreshed one by one in a batch; a larger batch refreshes its
+/// repository in full, which is as quick and keeps command lines short.
+const MAX_PATHSPECS: usize = 256;
+
+/// Depth of directories searched for repositories in a workspace that is
+/// not itself in one.
+const SEARCH_DEPTH: usize = 2;
+
+/// Entries of a git directory whose changes can change file statuses.
+const STATUS_GIT_FILES: &[&str] = &[
+    "index",
+    "HEAD",
+    "MERGE_HEAD",
+    "CHERRY_PICK_HEAD",
+    "REVERT_HEAD",
+    "packed-refs",
+    "refs",
+    "info",
+];
+
+/// Git status of the files of one workspace.
+pub struct StatusTracker {
+    root: PathBuf,
+    /// Repositories in the workspace, or the one it is in, outermost first.
+    repos: Vec<Repo>,
+    /// Updates that saw a git directory change.
+    revision: u64,
+}
+
+/// One repository and the statuses of its files in the workspace.
+struct Repo {
+    /// Workspace-relative path of the work tree; empty for the repository
+    /// the workspace is in.
+    prefix: String,
+    /// Where git runs.
+    workdir: PathBuf,
+    /// The git directory, under the workspace root when it is inside.
+    git_dir: PathBuf,
+    /// Repository-relative path of the workspace root, when the work tree
+    /// is above it.
+    scope: Option<String>,
+    /// Statuses by workspace-relative path, ignored directories included.
+    statuses: BTreeMap<String, FileStatus>,
+    /// Ignored directories, whose files git does not list.
+    ignored_dirs: BTreeSet<String>,
+    /// Workspace-relative paths of the repositories nested in this one.
+    nested: BTreeSet<String>,
+}
+
+impl StatusTracker {
+    /// Find the repositories of a workspace and read their status.
+    pub fn open(root: &Path) -> Result<Self, GitError> {
+        let mut tracker = Self {
+            root: root.to_path_buf(),
+            repos: Vec::new(),
+            revision: 0,
+        };
+        tracker.refresh()?;
+        Ok(tracker)
+    }
+
+    /// Whether any part of the workspace is in a repository.
+    pub fn is_repository(&self) -> bool {
+        !self.repos.is_empty()
+    }
+
+    /// Git status of a workspace-relative path; `None` when it is clean or
+    /// not in a repository.
+    pub fn status(&self, file_id: &str) -> Option<FileStatus> {
+        let repo = self.owner(file_id).map(|index| &self.repos[index])?;
+        repo.statuses.get(file_id).copied().or_else(|| {
+            ancestors(file_id)
+                .any(|dir| repo.ignored_dirs.contains(dir))
+                .then_some(FileStatus::Ignored)
+        })
+    }
+
+    /// A count that goes up whenever an update sees the index, `HEAD` or
+    /// refs of a repository change, or repositories come and go. Staging or
+    /// committing can change what a file is compared with without changing
+    /// its status.
+    pub const fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Every path with a status, by workspace-relative path. Files in
+    /// ignored directories are not listed, only the directories.
+    pub fn statuses(&self) -> BTreeMap<String, FileStatus> {
+        self.repos
+            .iter()
+            .flat_map(|repo| repo.statuses.iter())
+            .map(|(path, status)| (path.clone(), *status))
+            .collect()
+    }
+
+    /// Git directories outside the workspace, which a watcher of the
+    /// workspace does not see and which [`update`](Self::update) also
+    /// needs changes of.
+    ///
+    /// Directories inside another one, like a submodule's under its
+    /// parent's, are left out.
+    pub fn external_git_dirs(&self) -> Vec<PathBuf> {
+        let external: Vec<&PathBuf> = self
+            .repos
+            .iter()
+            .map(|repo| &repo.git_dir)
+            .filter(|dir| !dir.starts_with(&self.root))
+            .collect();
+        external
+            .iter()
+            .filter(|dir| {
+                !external
+                    .iter()
+                    .any(|other| other != *dir && dir.starts_with(other))
+            })
+            .map(|dir| (*dir).clone())
+            .collect()
+    }
+
+    /// Find the repositories again and read their status in full.
+    ///
+    /// Returns the workspace-relative paths whose status changed. A changed
+    /// ignored directory stands for everything in it.
+    pub fn refresh(&mut self) -> Result<Vec<String>, GitError> {
+        let before = self.statuses();
+        self.repos = discover(&self.root)?;
+        Ok(changes(&before, &self.statuses()))
+    }
+
+    /// Bring statuses up to date after changes to the given absolute paths,
+    /// in the workspace or in a git directory.
+    ///
+    /// Returns the workspace-relative paths whose status changed, as
+    /// [`refresh`](Self::refresh) does.
+    pub fn update(&mut self, paths: &[PathBuf]) -> Result<Vec<String>, GitError> {
+        let mut full = BTreeSet::new();
+        let mut partial: BTreeMap<usize, BTreeSet<String>> = BTreeMap::new();
+        let mut rediscover = false;
+        let mut revised = false;
+        for path in paths {
+            if let Some((index, entry)) = self.git_dir_entry(path) {
+                if STATUS_GIT_FILES.contains(&entry.as_str()) {
+                    full.insert(index);
+                    revised = true;
+                }
+                continue;
+            }
+            let Some(file_id) = file_id(&self.root, path) else {
+                continue;
+            };
+            let name = file_id.rsplit('/').next().unwrap_or_default();
+            if name == ".gitmodules" || file_id.split('/').any(|part| part == ".git") {
+                rediscover = true;
+                continue;
+            }
+            let Some(index) = self.owner(&file_id) else {
+                continue;
+            };
+            let repo = &self.repos[index];
+            if name == ".gitignore" {
+                full.insert(index);
+            } else if ancestors(&file_id).any(|dir| repo.ignored_dirs.contains(dir)) {
+                // Build output churning in an ignored directory stays ignored
+            } else {
+                partial.entry(index).or_default().insert(file_id);
+            }
+        }
+        if !rediscover && full.is_empty() && partial.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let before = self.statuses();
+        if revised || rediscover {
+            self.revision += 1;
+        }
+        if rediscover {
+            self.repos = discover(&self.root)?;
+            return Ok(changes(&before, &self.statuses()));
+        }
+        for (index, file_ids) in partial {
+            if file_ids.len() > MAX_PATHSPECS {
+                full.insert(index);
+            } else if !full.contains(&index) {
+                self.repos[index].update(&file_ids)?;
+            }
+        }
+        for index in full {
+            let nested = self.repos[index].nested.clone();
+            self.repos[index].refresh()?;
+            if self.repos[index].nested != nested {
+                self.repos = discover(&self.root)?;
+                break;
+            }
+        }
+        Ok(changes(&before, &self.statuses()))
+    }
+
+    /// The innermost repository a workspace-relative path is in. A nested
+    /// repository's own directory belongs to the repository around it.
+    fn owner(&self, file_id: &str) -> Option<usize> {
+        self.repos
+            .iter()
+            .enumerate()
+            .filter(|(_, repo)| repo.contains(file_id))
+            .max_by_key(|(_, repo)| repo.prefix.len())
+            .map(|(index, _)| index)
+    }
+
+    /// The repository whose git directory holds an absolute path, and the
+    /// first component of the path inside it.
+    fn git_dir_entry(&self, path: &Path) -> Option<(usize, String)> {
+        let (index, repo) = self
+            .repos
+            .iter()
+            .enumerate()
+            .filter(|(_, repo)| path.starts_with(&repo.git_dir))
+            .max_by_key(|(_, repo)| repo.git_dir.as_os_str().len())?;
+        let entry = path
+            .strip_prefix(&repo.git_dir)
+            .ok()
+            .and_then(|rest| rest.components().next())
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Some((index, entry))
+    }
+}
+
+impl Repo {
+    /// The repository at `prefix` in the workspace, or with an empty
+    /// prefix the one the workspace is in, if any.
+    fn open(root: &Path, prefix: String) -> Result<Option<Self>, GitError> {
+        let dir = if prefix.is_empty() {
+            root.to_path_buf()
+        } else {
+            root.join(&prefix)
+        };
+        if !dir.ancestors().any(|dir| dir.join(".git").exists()) {
+            return Ok(None);
+        }
+        let output = git_text(&dir, ["rev-parse", "--show-toplevel", "--absolute-git-dir"])?;
+        let mut lines = output.lines();
+        let (Some(toplevel), Some(git_dir)) = (lines.next(), lines.next()) else {
+            return Err(GitError::Parse(format!("rev-parse printed {output:?}")));
+        };
+        let toplevel = PathBuf::from(toplevel);
+        let canonical = dir.canonicalize()?;
+        let canonical_root = root.canonicalize()?;
+
+        let scope = if canonical == toplevel.canonicalize()? {
+            None
+        } else {
+            let relative = canonical.strip_prefix(&toplevel).map_err(|_| {
+                GitError::Parse(format!("{} is outside its work tree", dir.display()))
+            })?;
+            Some(slash_path(relative))
+        };
+        // Nested repositories are found at their work tree's top
+        if scope.is_some() && !prefix.is_empty() {
+            return Ok(None);
+        }
+        let git_dir = PathBuf::from(git_dir).canonicalize()?;
+        let git_dir = git_dir
+            .strip_prefix(&canonical_root)
+            .map_or_else(|_| git_dir.clone(), |relative| root.join(relative));
+        let mut repo = Self {
+            prefix,
+            workdir: if scope.is_some() { toplevel } else { dir },
+            git_dir,
+            scope,
+            statuses: BTreeMap::new(),
+            ignored_dirs: BTreeSet::new(),
+            nested: BTreeSet::new(),
+        };
+        repo.refresh()?;
+        Ok(Some(repo))
+    }
+
+    /// Whether a workspace-relative path is in this repository's work tree.
+    fn contains(&self, file_id: &str) -> bool {
+        self.prefix.is_empty()
+            || file_id
+                .strip_prefix(self.prefix.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+    }
+
+    /// Read the status of the whole work tree, and find the repositories
+    /// nested in it.
+    fn refresh(&mut self) -> Result<(), GitError> {
+        let scope: Vec<String> = self.scope.iter().cloned().collect();
+        let entries = self.status(&scope)?;
+        self.statuses.clear();
+        self.ignored_dirs.clear();
+        self.nested = self.submodules();
+        for entry in &entries {
+            let nested = entry.status == FileStatus::Untracked
+                && entry.directory
+                && self.workdir.join(&entry.path).join(".git").exists();
+            if nested || entry.submodule {
+                if let Some(file_id) = self.file_id(&entry.path) {
+                    self.nested.insert(file_id);
+                }
+            }
+        }
+        self.insert(entries);
+        Ok(())
+    }
+
+    /// Read the status of some workspace-relative paths and everything
+    /// under them.
+    fn update(&mut self, file_ids: &BTreeSet<String>) -> Result<(), GitError> {
+        let pathspecs: Vec<String> = file_ids.iter().map(|id| self.repo_path(id)).collect();
+        let entries = self.status(&pathspecs)?;
+        let covered = |path: &String| {
+            file_ids.contains(path) || ancestors(path).any(|dir| file_ids.contains(dir))
+        };
+        self.statuses.retain(|path, _| !covered(path));
+        self.ignored_dirs.retain(|path| !covered(path));
+        self.insert(entries);
+        Ok(())
+    }
+
+    fn insert(&mut self, entries: Vec<StatusEntry>) {
+        for entry in entries {
+            let Some(file_id) = self.file_id(&entry.path) else {
+                continue;
+            };
+            if entry.status == FileStatus::Ignored && entry.directory {
+                self.ignored_dirs.insert(file_id.clone());
+            }
+            self.statuses.insert(file_id, entry.status);
+        }
+    }
+
+    /// Run `git status` on some repository-relative paths, or all of them.
+    fn status(&self, pathspecs: &[String]) -> Result<Vec<StatusEntry>, GitError> {
+        let mut args = vec![
+            "status",
+            "--porcelain=v2",
+            "-z",
+            "--untracked-files=all",
+            "--ignored=matching",
+        ];
+        if !pathspecs.is_empty() {
+            args.push("--");
+            args.extend(pathspecs.iter().map(String::as_str));
+        }
+        parse(&git(&self.workdir, args)?)
+    }
+
+    /// Workspace-relative paths of the checked-out submodules.
+    fn submodules(&self) -> BTreeSet<String> {
+        if !self.workdir.join(".gitmodules").is_file() {
+            return BTreeSet::new();
+        }
+        let args = [
+            "config",
+            "-z",
+            "--file",
+            ".gitmodules",
+            "--get-regexp",
+            r"^submodule\..*\.path$",
+        ];
+        // No submodules is an error exit
+        let output = git(&self.workdir, args).unwrap_or_default();
+        String::from_utf8_lossy(&output)
+            .split('\0')
+            .filter_map(|record| record.split_once('\n'))
+            .filter(|(_, path)| self.workdir.join(path).join(".git").exists())
+            .filter_map(|(_, path)| self.file_id(path.trim_end_matches('/')))
+            .collect()
+    }
+
+    /// Workspace-relative path of a repository-relative one; `None` for
+    /// paths outside the workspace.
+    fn file_id(&self, path: &str) -> Option<String> {
+        match &self.scope {
+            Some(scope) => path
+                .strip_prefix(scope.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+                .map(str::to_string),
+            None if self.prefix.is_empty() => Some(path.to_string()),
+            None => Some(format!("{}/{path}", self.prefix)),
+        }
+    }
+
+    /// Repository-relative path of a workspace-relative one in the
+    /// repository.
+    fn repo_path(&self, file_id: &str) -> String {
+        match &self.scope {
+            Some(scope) => format!("{scope}/{file_id}"),
+            None if self.prefix.is_empty() => file_id.to_string(),
+            None => file_id
+                .strip_prefix(self.prefix.as_str())
+                .map_or(file_id, |rest| rest.trim_start_matches('/'))
+                .to_string(),
+        }
+    }
+}
+
+/// The repositories of a workspace: the one it is in, or those near its
+/// root, and every one nested in those.
+fn discover(root: &Path) -> Result<Vec<Repo>, GitError> {
+    let mut pending = Vec::new();
+    let mut repos = Vec::new();
+    if let Some(repo) = Repo::open(root, String::new())? {
+        pending.push(repo);
+    } else {
+        let mut found = Vec::new();
+        find_repositories(root, "", SEARCH_DEPTH, &mut found);
+        for prefix in found {
+            pending.extend(Repo::open(root, prefix)?);
+        }
+    }
+    while let Some(repo) = pending.pop() {
+        for prefix in &repo.nested {
+            match Repo::open(root, prefix.clone()) {
+                Ok(nested) => pending.extend(nested),
+                // A broken nested repository is left out, not the workspace
+                Err(e) => debug!(prefix = %prefix, error = %e, "Skipping nested repository"),
+            }
+        }
+        repos.push(repo);
+    }
+    repos.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+    debug!(root = %root.display(), repositories = repos.len(), "Git repositories found");
+    Ok(repos)
+}
+
+/// Collect the workspace-relative paths of directories holding a `.git`,
+/// down to `depth` levels, skipping hidden directories.
+fn find_repositories(root: &Path, prefix: &str, depth: usize, found: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(root.join(prefix)) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || !entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            continue;
+        }
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}/{name}")
+        };
+        if entry.path().join(".git").exists() {
+            found.push(path);
+        } else if depth > 1 {
+            find_repositories(root, &path, depth - 1, found);
+        }
+    }
+}
+
+/// Paths whose status differs between two sets of statuses.
+fn changes(
+    before: &BTreeMap<String, FileStatus>,
+    after: &BTreeMap<String, FileStatus>,
+) -> Vec<String> {
+    let changed: BTreeSet<&String> = before
+        .iter()
+        .filter(|(path, status)| after.get(*path) != Some(status))
+        .map(|(path, _)| path)
+        .chain(after.keys().filter(|path| !before.contains_key(*path)))
+        .collect();
+    changed.into_iter().cloned().collect()
+}
+
+/// The directories a workspace-relative path is in, innermost first.
+fn ancestors(file_id: &str) -> impl Iterator<Item = &str> {
+    file_id
+        .match_indices('/')
+        .map(|(end, _)| &file_id[..end])
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+}
+
+/// Workspace-relative path of an absolute path under the root.
+fn file_id(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    (!relative.as_os_str().is_empty()).then(|| slash_path(relative))
+}
+
+/// A relative path with `/` separators, as git prints it.
+pub(crate) fn slash_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::process::Command;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn run(dir: &Path, args: &[&str]) {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "user.name=Test",
+                "-c",
+                "user.email=test@example.com",
+                "-c",
+                "init.defaultBranch=main",
+                "-c",
+                "protocol.file.allow=always",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    fn repo(dir: &Path, files: &[(&str, &str)]) {
+        fs::create_dir_all(dir).unwrap();
+        run(dir, &["init", "-q"]);
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        run(dir, &["add", "-A"]);
+        run(dir, &["commit", "-q", "-m", "initial"]);
+    }
+
+    fn summary(tracker: &StatusTracker) -> Vec<(String, FileStatus)> {
+        tracker.statuses().into_iter().collect()
+    }
+
+    fn entries(items: &[(&str, FileStatus)]) -> Vec<(String, FileStatus)> {
+        items
+            .iter()
+            .map(|(path, status)| ((*path).to_string(), *status))
+            .collect()
+    }
+
+    #[test]
+    fn test_status_of_repository_and_nested_ones() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("ws");
+        repo(&dir.path().join("lib"), &[("lib.rs", "pub fn lib() {}\n")]);
+        repo(
+            &root,
+            &[
+                ("a.txt", "a\n"),
+                ("b.txt", "b\n"),
+                ("gone.txt", "gone\n"),
+                (".gitignore", "target/\n*.log\n"),
+            ],
+        );
+        let lib = dir.path().join("lib");
+        run(
+            &root,
+            &[
+                "submodule",
+                "add",
+                "-q",
+                lib.to_str().unwrap(),
+                "vendor/lib",
+            ],
+        );
+        run(&root, &["commit", "-q", "-m", "submodule"]);
+        repo(&root.join("tools"), &[("run.sh", "echo\n")]);
+
+        fs::write(root.join("a.txt"), "changed\n").unwrap();
+        fs::remove_file(root.join("gone.txt")).unwrap();
+        run(&root, &["mv", "b.txt", "c.txt"]);
+        fs::write(root.join("new.txt"), "new\n").unwrap();
+        fs::write(root.join("staged.txt"), "staged\n").unwrap();
+        run(&root, &["add", "staged.txt"]);
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::write(root.join("target/debug/out"), "").unwrap();
+        fs::write(root.join("tools/run.sh"), "echo hi\n").unwrap();
+        fs::write(root.join("vendor/lib/lib.rs"), "\n").unwrap();
+
+        let tracker = StatusTracker::open(&root).unwrap();
+        assert!(tracker.is_repository());
+        assert_eq!(
+            summary(&tracker),
+            entries(&[
+                ("a.txt", FileStatus::Modified),
+                ("c.txt", FileStatus::Renamed),
+                ("gone.txt", FileStatus::Deleted),
+                ("new.txt", FileStatus::Untracked),
+                ("staged.txt", FileStatus::Added),
+                ("target", FileStatus::Ignored),
+                ("tools", FileStatus::Untracked),
+                ("tools/run.sh", FileStatus::Modified),
+                ("vendor/lib", FileStatus::Modified),
+                ("vendor/lib/lib.rs", FileStatus::Modified),
+            ])
+        );
+        assert_eq!(
+            tracker.status("target/debug/out"),
+            Some(FileStatus::Ignored)
+        );
+        assert_eq!(tracker.status("tools/.gitignore"), None);
+        assert!(tracker.external_git_dirs().is_empty());
+
+        // A workspace inside a repository only sees its part
+        let tracker = StatusTracker::open(&root.join("vendor")).unwrap();
+        assert_eq!(
+            summary(&tracker),
+            entries(&[
+                ("lib", FileStatus::Modified),
+                ("lib/lib.rs", FileStatus::Modified),
+            ])
+        );
+        assert_eq!(tracker.external_git_dirs().len(), 1);
+
+        // Repositories side by side in a plain folder
+        let tracker = StatusTracker::open(dir.path()).unwrap();
+        assert_eq!(
+            tracker.status("ws/tools/run.sh"),
+            Some(FileStatus::Modified)
+        );
+        assert_eq!(tracker.status("lib/lib.rs"), None);
+
+        let empty = TempDir::new().unwrap();
+        let tracker = StatusTracker::open(empty.path()).unwrap();
+        assert!(!tracker.is_repository());
+    }
+
+    #[test]
+    fn test_update_refreshes_changed_paths() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        repo(
+            &root,
+            &[("a.txt", "a\n"), ("src/b.txt", "b\n"), ("src/c.txt", "c\n")],
+        );
+        let mut tracker = StatusTracker::open(&root).unwrap();
+        assert!(summary(&tracker).is_empty());
+
+        fs::write(root.join("a.txt"), "changed\n").unwrap();
+        fs::write(root.join("src/new.txt"), "").unwrap();
+        let changed = tracker
+            .update(&[root.join("a.txt"), root.join("src/new.txt")])
+            .unwrap();
+        assert_eq!(changed, ["a.txt", "src/new.txt"]);
+
+        // Removing a directory refreshes what was in it
+        fs::remove_dir_all(root.join("src")).unwrap();
+        let changed = tracker.update(&[root.join("src")]).unwrap();
+        assert_eq!(changed, ["src/b.txt", "src/c.txt", "src/new.txt"]);
+        assert_eq!(tracker.status("src/b.txt"), Some(FileStatus::Deleted));
+
+        // Staging is seen through the index
+        run(&root, &["add", "a.txt"]);
+        assert!(tracker.update(&[root.join("a.txt")]).unwrap().is_empty());
+        assert_eq!(tracker.revision(), 0);
+        run(&root, &["commit", "-q", "-m", "a"]);
+        let changed = tracker.update(&[root.join(".git/index")]).unwrap();
+        assert_eq!(changed, ["a.txt"]);
+        assert_eq!(tracker.status("a.txt"), None);
+        assert_eq!(tracker.revision(), 1);
+
+        // Ignore rules apply to the whole repository
+        fs::write(root.join(".gitignore"), "*.txt\n").unwrap();
+        fs::write(root.join("notes.txt"), "").unwrap();
+        let changed = tracker.update(&[root.join(".gitignore")]).unwrap();
+        assert_eq!(changed, [".gitignore", "notes.txt"]);
+        assert_eq!(tracker.status("notes.txt"), Some(FileStatus::Ignored));
+
+        // A repository created inside is found
+        repo(&root.join("nested"), &[("x.txt", "x\n")]);
+        fs::write(root.join("nested/x.txt"), "y\n").unwrap();
+        let changed = tracker.update(&[root.join("nested/.git/HEAD")]).unwrap();
+        assert_eq!(changed, ["nested", "nested/x.txt"]);
+        assert!(tracker
+            .update(&[root.join(".git/objects/ab/cdef")])
+            .unwrap()
+            .is_empty());
+        assert_eq!(tracker.revision(), 2);
+    }
+}
@@ -0,0 +1,564 @@
+//! Changes to repositories: staging, commits, branches and stashes.
+//!
+//! Conditions a caller is expected to handle, like a branch name that is
+//! taken or nothing to commit, are checked before git runs, so they come
+//! back as their own [`GitError`] variants instead of git's wording.
+//! Checkouts and stashes report the files they changed in the work tree,
+//! for whoever mirrors the work tree to see them without waiting on a file
+//! watcher.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::command::{git, git_globbing, git_input, git_text};
+use crate::content::{has_head, index_entry, locate};
+use crate::GitError;
+
+/// A local branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    /// Short name, such as `main`.
+    pub name: String,
+    /// Commit the branch points at; empty for the branch of a repository
+    /// with no commits yet.
+    pub commit: String,
+    /// Whether it is checked out.
+    pub current: bool,
+    /// Short name of its upstream branch, if it has one.
+    pub upstream: Option<String>,
+    /// Subject line of its commit.
+    pub subject: String,
+}
+
+/// How a checkout or stash changed a file in the work tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The file was created.
+    Added,
+    /// The file's content or mode changed.
+    Modified,
+    /// The file was removed.
+    Deleted,
+}
+
+/// A file a checkout or stash changed in the work tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkTreeChange {
+    /// Absolute path of the file.
+    pub path: PathBuf,
+    /// How it changed.
+    pub kind: ChangeKind,
+}
+
+/// Outcome of popping a stash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StashPop {
+    /// Files the stash changed.
+    pub changes: Vec<WorkTreeChange>,
+    /// Files left with conflict markers. When there are any, the stash is
+    /// kept.
+    pub conflicts: Vec<PathBuf>,
+}
+
+/// Top of the work tree holding `dir`.
+pub fn toplevel(dir: &Path) -> Result<PathBuf, GitError> {
+    git_text(dir, ["rev-parse", "--show-toplevel"])
+        .map(PathBuf::from)
+        .map_err(|_| GitError::NotInRepository(dir.to_path_buf()))
+}
+
+/// Git directory of the repository holding `dir`, where its index and
+/// `HEAD` are.
+pub fn git_dir(dir: &Path) -> Result<PathBuf, GitError> {
+    let git_dir = git_text(dir, ["rev-parse", "--absolute-git-dir"])
+        .map(PathBuf::from)
+        .map_err(|_| GitError::NotInRepository(dir.to_path_buf()))?;
+    // Spelled the way status tracking spells it
+    Ok(git_dir.canonicalize().unwrap_or(git_dir))
+}
+
+/// Stage the content of files on disk, deletions included.
+pub fn stage(paths: &[PathBuf]) -> Result<(), GitError> {
+    for (toplevel, files) in by_repository(paths)? {
+        git(&toplevel, with_paths(&["add", "-A"], &files))?;
+    }
+    Ok(())
+}
+
+/// Unstage files, leaving the index as `HEAD` has them.
+pub fn unstage(paths: &[PathBuf]) -> Result<(), GitError> {
+    for (toplevel, files) in by_repository(paths)? {
+        if has_head(&toplevel) {
+            git(&toplevel, with_paths(&["reset", "-q"], &files))?;
+        } else {
+            // Without a commit, nothing staged is in HEAD
+            let args = ["rm", "--cached", "-q", "-r", "--ignore-unmatch"];
+            git(&toplevel, with_paths(&args, &files))?;
+        }
+    }
+    Ok(())
+}
+
+/// Stage `content` as a file's content, whatever is on disk. A file not yet
+/// in the index takes its mode from disk.
+pub fn stage_content(path: &Path, content: &[u8]) -> Result<(), GitError> {
+    let file = locate(path)?;
+    let listing = git(
+        &file.toplevel,
+        ["ls-files", "--stage", "-z", "--", &file.path],
+    )?;
+    let mode = index_entry(&listing, &file.path).map_or_else(|| disk_mode(path), |e| e.mode);
+    let object = git_input(&file.toplevel, ["hash-object", "-w", "--stdin"], content)?;
+    let object = String::from_utf8_lossy(&object).trim().to_string();
+    // Paths here are relative to the top, wherever git runs
+    let entry = format!("{mode},{object},{}", file.path);
+    git(
+        &file.toplevel,
+        ["update-index", "--add", "--cacheinfo", &entry],
+    )?;
+    Ok(())
+}
+
+/// Commit what is staged in the repository holding `dir`, returning the new
+/// commit. Amending with an empty message keeps the old one.
+pub fn commit(dir: &Path, message: &str, amend: bool) -> Result<String, GitError> {
+    let toplevel = toplevel(dir)?;
+    // Exits with an error exactly when something is staged
+    if !amend && git(&toplevel, ["diff", "--cached", "--quiet"]).is_ok() {
+        return Err(GitError::NothingToCommit);
+    }
+    let mut args = vec!["commit", "-q"];
+    if amend {
+        args.push("--amend");
+    }
+    if message.is_empty() {
+        args.push("--no-edit");
+    } else {
+        args.extend(["-m", message]);
+    }
+    git(&toplevel, args)?;
+    git_text(&toplevel, ["rev-parse", "HEAD"])
+}
+
+/// Local branches of the repository holding `dir`, by name.
+pub fn branches(dir: &Path) -> Result<Vec<Branch>, GitError> {
+    let toplevel = toplevel(dir)?;
+    let format =
+        "--format=%(refname:short)%00%(objectname)%00%(HEAD)%00%(upstream:short)%00%(subject)";
+    let output = git_text(&toplevel, ["for-each-ref", format, "refs/heads"])?;
+    let mut branches = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.splitn(5, '\0').collect();
+        let [name, commit, head, upstream, subject] = fields[..] else {
+            return Err(GitError::Parse(format!("for-each-ref printed {line:?}")));
+        };
+        branches.push(Branch {
+            name: name.to_string(),
+            commit: commit.to_string(),
+            current: head == "*",
+            upstream: (!upstream.is_empty()).then(|| upstream.to_string()),
+            subject: subject.to_string(),
+        });
+    }
+    // A branch with no commits yet has no ref
+    if let Ok(name) = git_text(&toplevel, ["symbolic-ref", "--short", "-q", "HEAD"]) {
+        if !branches.iter().any(|branch| branch.name == name) {
+            branches.push(Branch {
+                name,
+                commit: String::new(),
+                current: true,
+                upstream: None,
+                subject: String::new(),
+            });
+            branches.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+    }
+    Ok(branches)
+}
+
+/// Create a branch at `start`, a commit or branch name, or at `HEAD`.
+pub fn create_branch(dir: &Path, name: &str, start: Option<&str>) -> Result<(), GitError> {
+    let toplevel = toplevel(dir)?;
+    // Also rules out names git would take for an option
+    if git(&toplevel, ["check-ref-format", "--branch", name]).is_err() {
+        return Err(GitError::InvalidBranchName(name.to_string()));
+    }
+    if branch_exists(&toplevel, name) {
+        return Err(GitError::BranchExists(name.to_string()));
+    }
+    let mut args = vec!["branch", name];
+    args.extend(start);
+    git(&toplevel, args)?;
+    Ok(())
+}
+
+/// Check out a local branch, returning the files that changed. Local
+/// changes the checkout would overwrite stop it before anything changes.
+pub fn switch_branch(dir: &Path, name: &str) -> Result<Vec<WorkTreeChange>, GitError> {
+    let toplevel = toplevel(dir)?;
+    if !branch_exists(&toplevel, name) {
+        return Err(GitError::BranchNotFound(name.to_string()));
+    }
+    let before = revision(&toplevel, "HEAD");
+    git(&toplevel, ["checkout", "-q", name, "--"]).map_err(local_changes)?;
+    let after = git_text(&toplevel, ["rev-parse", "HEAD"])?;
+    before.map_or_else(
+        || tree_files(&toplevel, &after, ChangeKind::Added),
+        |before| changes_between(&toplevel, &before, &after),
+    )
+}
+
+/// Stash the changes to tracked files, and with `include_untracked`
+/// untracked files too, returning the files that changed back.
+pub fn stash_push(
+    dir: &Path,
+    message: &str,
+    include_untracked: bool,
+) -> Result<Vec<WorkTreeChange>, GitError> {
+    let toplevel = toplevel(dir)?;
+    // Stashing undoes these changes, so an addition becomes a deletion
+    let mut changes: Vec<WorkTreeChange> = name_status(
+        &toplevel,
+        &["diff", "--name-status", "-z", "--no-renames", "HEAD"],
+    )?
+    .into_iter()
+    .map(|change| WorkTreeChange {
+        kind: match change.kind {
+            ChangeKind::Added => ChangeKind::Deleted,
+            ChangeKind::Deleted => ChangeKind::Added,
+            ChangeKind::Modified => ChangeKind::Modified,
+        },
+        ..change
+    })
+    .collect();
+    if include_untracked {
+        let output = git(
+            &toplevel,
+            ["ls-files", "--others", "--exclude-standard", "-z"],
+        )?;
+        changes.extend(paths(&toplevel, &output).map(|path| WorkTreeChange {
+            path,
+            kind: ChangeKind::Deleted,
+        }));
+    }
+    if changes.is_empty() {
+        return Err(GitError::NothingToStash);
+    }
+
+    let mut args = vec!["stash", "push", "-q"];
+    if include_untracked {
+        args.push("--include-untracked");
+    }
+    if !message.is_empty() {
+        args.extend(["-m", message]);
+    }
+    git_globbing(&toplevel, args)?;
+    Ok(changes)
+}
+
+/// Apply the latest stash and drop it, unless applying it left conflicts.
+pub fn stash_pop(dir: &Path) -> Result<StashPop, GitError> {
+    let toplevel = toplevel(dir)?;
+    if revision(&toplevel, "refs/stash").is_none() {
+        return Err(GitError::NoStash);
+    }
+    let mut changes = changes_between(&toplevel, "stash@{0}^1", "stash@{0}")?;
+    // Untracked files are kept in a third parent
+    if let Some(untracked) = revision(&toplevel, "stash@{0}^3") {
+        changes.extend(tree_files(&toplevel, &untracked, ChangeKind::Added)?);
+    }
+
+    if let Err(e) = git_globbing(&toplevel, ["stash", "pop", "-q"]) {
+        let output = git(&toplevel, ["diff", "--name-only", "-z", "--diff-filter=U"])?;
+        let conflicts: Vec<PathBuf> = paths(&toplevel, &output).collect();
+        if conflicts.is_empty() {
+            return Err(local_changes(e));
+        }
+        return Ok(StashPop { changes, conflicts });
+    }
+    Ok(StashPop {
+        changes,
+        conflicts: Vec::new(),
+    })
+}
+
+/// Repository-relative paths of files, grouped by the top of their work
+/// tree.
+fn by_repository(paths: &[PathBuf]) -> Result<BTreeMap<PathBuf, Vec<String>>, GitError> {
+    let mut repositories: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for path in paths {
+        let file = locate(path)?;
+        repositories
+            .entry(file.toplevel)
+            .or_default()
+            .push(file.path);
+    }
+    Ok(repositories)
+}
+
+fn with_paths<'a>(args: &[&'a str], paths: &'a [String]) -> Vec<&'a str> {
+    let mut args = args.to_vec();
+    args.push("--");
+    args.extend(paths.iter().map(String::as_str));
+    args
+}
+
+/// Index mode of a file on disk.
+fn disk_mode(path: &Path) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if std::fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+        {
+            return "100755".to_string();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    "100644".to_string()
+}
+
+fn branch_exists(toplevel: &Path, name: &str) -> bool {
+    revision(toplevel, &format!("refs/heads/{name}")).is_some()
+}
+
+/// The commit a revision names, if it names one.
+fn revision(toplevel: &Path, name: &str) -> Option<String> {
+    git_text(toplevel, ["rev-parse", "--verify", "--quiet", name]).ok()
+}
+
+/// A failed command, as [`GitError::LocalChanges`] if it stopped to keep
+/// local changes from being overwritten.
+fn local_changes(error: GitError) -> GitError {
+    match error {
+        GitError::Command { message, .. }
+            if message.contains("would be overwritten") || message.contains("already exists") =>
+        {
+            GitError::LocalChanges(message)
+        }
+        error => error,
+    }
+}
+
+/// Files that differ between two commits.
+fn changes_between(toplevel: &Path, from: &str, to: &str) -> Result<Vec<WorkTreeChange>, GitError> {
+    name_status(
+        toplevel,
+        &["diff", "--name-status", "-z", "--no-renames", from, to],
+    )
+}
+
+/// Every file of a commit's tree.
+fn tree_files(
+    toplevel: &Path,
+    commit: &str,
+    kind: ChangeKind,
+) -> Result<Vec<WorkTreeChange>, GitError> {
+    let output = git(toplevel, ["ls-tree", "-r", "--name-only", "-z", commit])?;
+    Ok(paths(toplevel, &output)
+        .map(|path| WorkTreeChange { path, kind })
+        .collect())
+}
+
+/// Changes in the output of a `--name-status -z` diff without renames,
+/// which alternates status letters and paths.
+fn name_status(toplevel: &Path, args: &[&str]) -> Result<Vec<WorkTreeChange>, GitError> {
+    let output = git(toplevel, args)?;
+    let output = String::from_utf8_lossy(&output);
+    let mut fields = output.split('\0');
+    let mut changes = Vec::new();
+    while let (Some(status), Some(path)) = (fields.next(), fields.next()) {
+        let kind = match status {
+            "A" => ChangeKind::Added,
+            "D" => ChangeKind::Deleted,
+            _ => ChangeKind::Modified,
+        };
+        changes.push(WorkTreeChange {
+            path: toplevel.join(path),
+            kind,
+        });
+    }
+    Ok(changes)
+}
+
+/// Absolute paths in NUL-separated output of repository-relative paths.
+fn paths<'a>(toplevel: &'a Path, output: &'a [u8]) -> impl Iterator<Item = PathBuf> + 'a {
+    output
+        .split(|byte| *byte == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| toplevel.join(String::from_utf8_lossy(path).as_ref()))
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{file_content, Revision};
+
+    fn repository() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for args in [
+            &["init", "-q", "-b", "main"][..],
+            &["config", "user.name", "Test"],
+            &["config", "user.email", "test@example.com"],
+        ] {
+            git(dir.path(), args).unwrap();
+        }
+        dir
+    }
+
+    fn staged(path: &Path) -> Option<String> {
+        file_content(path, Revision::Index)
+            .unwrap()
+            .map(|bytes| String::from_utf8(bytes).unwrap())
+    }
+
+    fn change(path: PathBuf, kind: ChangeKind) -> WorkTreeChange {
+        WorkTreeChange { path, kind }
+    }
+
+    #[test]
+    fn test_stage_unstage_and_commit() {
+        let dir = repository();
+        let root = toplevel(dir.path()).unwrap();
+        let file = root.join("src/a.txt");
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(&file, "one\n").unwrap();
+        let files = std::slice::from_ref(&file);
+
+        assert!(matches!(
+            commit(&root, "empty", false),
+            Err(GitError::NothingToCommit)
+        ));
+        stage(files).unwrap();
+        assert_eq!(staged(&file).as_deref(), Some("one\n"));
+        unstage(files).unwrap();
+        assert_eq!(staged(&file), None);
+
+        stage_content(&file, b"staged\n").unwrap();
+        assert_eq!(staged(&file).as_deref(), Some("staged\n"));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "one\n");
+        let first = commit(&root.join("src"), "first", false).unwrap();
+        assert_eq!(
+            file_content(&file, Revision::Head).unwrap().as_deref(),
+            Some(&b"staged\n"[..])
+        );
+
+        stage(files).unwrap();
+        unstage(files).unwrap();
+        assert_eq!(staged(&file).as_deref(), Some("staged\n"));
+
+        stage(files).unwrap();
+        let amended = commit(&root, "", true).unwrap();
+        assert_ne!(amended, first);
+        let branches = branches(&root).unwrap();
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].name, "main");
+        assert_eq!(branches[0].commit, amended);
+        assert_eq!(branches[0].subject, "first");
+        assert!(branches[0].current);
+    }
+
+    #[test]
+    fn test_branches_and_stashes() {
+        let dir = repository();
+        let root = toplevel(dir.path()).unwrap();
+        assert_eq!(branches(&root).unwrap()[0].commit, "");
+        fs::write(root.join("a.txt"), "one\n").unwrap();
+        fs::write(root.join("b.txt"), "b\n").unwrap();
+        stage(&[root.join("a.txt"), root.join("b.txt")]).unwrap();
+        commit(&root, "one", false).unwrap();
+
+        create_branch(&root, "topic", None).unwrap();
+        assert!(matches!(
+            create_branch(&root, "topic", None),
+            Err(GitError::BranchExists(_))
+        ));
+        assert!(matches!(
+            create_branch(&root, "-x", None),
+            Err(GitError::InvalidBranchName(_))
+        ));
+        assert!(matches!(
+            switch_branch(&root, "missing"),
+            Err(GitError::BranchNotFound(_))
+        ));
+
+        assert!(switch_branch(&root, "topic").unwrap().is_empty());
+        fs::write(root.join("a.txt"), "two\n").unwrap();
+        fs::write(root.join("c.txt"), "c\n").unwrap();
+        fs::remove_file(root.join("b.txt")).unwrap();
+        stage(&[root.join("a.txt"), root.join("b.txt"), root.join("c.txt")]).unwrap();
+        commit(&root, "two", false).unwrap();
+        let names: Vec<(String, bool)> = branches(&root)
+            .unwrap()
+            .into_iter()
+            .map(|branch| (branch.name, branch.current))
+            .collect();
+        assert_eq!(
+            names,
+            [("main".to_string(), false), ("topic".to_string(), true)]
+        );
+
+        fs::write(root.join("a.txt"), "local\n").unwrap();
+        assert!(matches!(
+            switch_branch(&root, "main"),
+            Err(GitError::LocalChanges(_))
+        ));
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "local\n");
+
+        fs::write(root.join("new.txt"), "new\n").unwrap();
+        let mut stashed = stash_push(&root, "wip", true).unwrap();
+        stashed.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            stashed,
+            [
+                change(root.join("a.txt"), ChangeKind::Modified),
+                change(root.join("new.txt"), ChangeKind::Deleted),
+            ]
+        );
+        assert!(!root.join("new.txt").exists());
+        assert!(matches!(
+            stash_push(&root, "", false),
+            Err(GitError::NothingToStash)
+        ));
+
+        let mut switched = switch_branch(&root, "main").unwrap();
+        switched.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            switched,
+            [
+                change(root.join("a.txt"), ChangeKind::Modified),
+                change(root.join("b.txt"), ChangeKind::Added),
+                change(root.join("c.txt"), ChangeKind::Deleted),
+            ]
+        );
+        assert!(root.join("b.txt").exists());
+        assert!(!root.join("c.txt").exists());
+
+        let mut popped = stash_pop(&root).unwrap();
+        popped.changes.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            popped.changes,
+            [
+                change(root.join("a.txt"), ChangeKind::Modified),
+                change(root.join("new.txt"), ChangeKind::Added),
+            ]
+        );
+        assert_eq!(popped.conflicts, [root.join("a.txt")]);
+        assert!(root.join("new.txt").exists());
+
+        // The stash is kept for the conflict to be resolved
+        assert!(git_text(&root, ["stash", "list"]).unwrap().contains("wip"));
+        git(&root, ["stash", "drop", "-q"]).unwrap();
+        assert!(matches!(stash_pop(&root), Err(GitError::NoStash)));
+    }
+}
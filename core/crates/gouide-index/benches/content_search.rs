@@ -0,0 +1,151 @@
+//! Text search over a generated large repository, with and without the
+//! trigram content index.
+//!
+//! The fixture holds `GOUIDE_BENCH_FILES` source-like files (10,000 by
+//! default, about 4 KiB each) in nested directories, generated from a fixed
+//! seed so runs compare. A handful of files contain a rare identifier, the
+//! kind of needle a search for one symbol's uses looks for.
+//!
+//! Run with `cargo bench -p gouide-index`.
+
+#![allow(
+    missing_docs,
+    unused_crate_dependencies,
+    clippy::unwrap_used,
+    clippy::significant_drop_tightening
+)]
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use gouide_index::{Indexer, PathFilter};
+use gouide_search::{Query, SearchOptions, Searcher};
+
+/// Files generated when `GOUIDE_BENCH_FILES` is not set.
+const DEFAULT_FILES: usize = 10_000;
+
+/// Files per directory of the fixture.
+const FILES_PER_DIR: usize = 50;
+
+/// Lines per generated file.
+const LINES_PER_FILE: usize = 80;
+
+/// One file in this many contains the needle.
+const NEEDLE_EVERY: usize = 2_500;
+
+const NEEDLE: &str = "reconcile_ledger_snapshot";
+
+const WORDS: &[&str] = &[
+    "buffer", "cursor", "render", "parse", "token", "index", "query", "state", "event", "layout",
+    "widget", "handle", "config", "value", "result", "error", "stream", "symbol", "scope",
+    "module", "update", "delta", "range", "offset", "line", "column", "width", "height",
+];
+
+/// A small deterministic generator, so every run builds the same fixture.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 33) as usize
+    }
+
+    fn word(&mut self) -> &'static str {
+        WORDS[self.next() % WORDS.len()]
+    }
+}
+
+fn generate(root: &Path, files: usize) {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    let mut text = String::new();
+    for file in 0..files {
+        let dir = root.join(format!(
+            "pkg{:03}/src/mod{:03}",
+            file / (FILES_PER_DIR * 20),
+            file / FILES_PER_DIR
+        ));
+        if file % FILES_PER_DIR == 0 {
+            fs::create_dir_all(&dir).unwrap();
+        }
+        text.clear();
+        for line in 0..LINES_PER_FILE {
+            let (a, b, c) = (rng.word(), rng.word(), rng.word());
+            let n = rng.next() % 1000;
+            writeln!(text, "    let {a}_{b}{line} = {c}.{a}_{n}({b}, {n});").unwrap();
+        }
+        if file % NEEDLE_EVERY == NEEDLE_EVERY / 2 {
+            writeln!(text, "    {NEEDLE}(&mut state);").unwrap();
+        }
+        fs::write(dir.join(format!("file{file:05}.rs")), &text).unwrap();
+    }
+}
+
+fn search(root: &Path, query: &Query, candidates: Option<Vec<String>>) -> usize {
+    let searcher = Searcher::new(root, query, SearchOptions::default()).unwrap();
+    let searcher = match candidates {
+        Some(paths) => searcher.with_candidates(paths),
+        None => searcher,
+    };
+    searcher
+        .search(&AtomicBool::new(false), |_| true)
+        .files_matched
+}
+
+fn content_search(c: &mut Criterion) {
+    let files = env::var("GOUIDE_BENCH_FILES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_FILES);
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    generate(root, files);
+
+    let mut group = c.benchmark_group("index");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+    group.bench_function(BenchmarkId::new("trigrams", files), |b| {
+        b.iter(|| {
+            let filter = PathFilter::new(root, &[], true).unwrap();
+            let indexer = Indexer::new(filter, |_| {}).with_trigrams();
+            indexer.run(&|| false);
+            indexer
+        });
+    });
+    group.finish();
+
+    let filter = PathFilter::new(root, &[], true).unwrap();
+    let indexer = Indexer::new(filter, |_| {}).with_trigrams();
+    indexer.run(&|| false);
+
+    let queries = [
+        ("literal", Query::literal(NEEDLE)),
+        ("regex", Query::regex(r"reconcile_\w+_snapshot\(")),
+        ("common", Query::literal("render.")),
+    ];
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+    for (name, query) in &queries {
+        let trigrams = query.matcher().unwrap().trigrams().clone();
+        let expected = search(root, query, None);
+        assert_eq!(search(root, query, indexer.candidates(&trigrams)), expected);
+
+        group.bench_function(BenchmarkId::new("scan", name), |b| {
+            b.iter(|| search(root, query, None));
+        });
+        group.bench_function(BenchmarkId::new("indexed", name), |b| {
+            b.iter(|| search(root, query, indexer.candidates(&trigrams)));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, content_search);
+criterion_main!(benches);
@@ -0,0 +1,362 @@
+//! On-disk cache of a workspace's index.
+//!
+//! One file per workspace, named after a hash of its root, holds what the
+//! indexer learned about each file. It starts with a magic number, the
+//! format version, the workspace root and a key describing how the data was
+//! derived; a file with anything else is ignored and rewritten. Integers
+//! are little-endian, and strings and trigram filters length-prefixed.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use gouide_search::TrigramFilter;
+use gouide_syntax::SymbolKind;
+use gouide_workspace::{Position, TextRange};
+
+use crate::{FileInfo, IndexedSymbol};
+
+/// Start of every cache file.
+const MAGIC: &[u8; 8] = b"GOUIDX\0\0";
+
+/// Format of the data after the magic number. Bumped on any change.
+const VERSION: u32 = 3;
+
+/// Longest string read, so a corrupt length cannot exhaust memory.
+const MAX_STRING: usize = 64 * 1024;
+
+/// Most words of a trigram filter read, for the same reason.
+const MAX_FILTER_WORDS: usize = 64 * 1024;
+
+/// Symbol kinds by their number in the file.
+const KINDS: [SymbolKind; 21] = [
+    SymbolKind::Module,
+    SymbolKind::Namespace,
+    SymbolKind::Class,
+    SymbolKind::Method,
+    SymbolKind::Property,
+    SymbolKind::Field,
+    SymbolKind::Constructor,
+    SymbolKind::Enum,
+    SymbolKind::Interface,
+    SymbolKind::Function,
+    SymbolKind::Variable,
+    SymbolKind::Constant,
+    SymbolKind::String,
+    SymbolKind::Number,
+    SymbolKind::Boolean,
+    SymbolKind::Array,
+    SymbolKind::Object,
+    SymbolKind::Null,
+    SymbolKind::EnumMember,
+    SymbolKind::Struct,
+    SymbolKind::TypeParameter,
+];
+
+/// Path of the cache file of the workspace at `root` in `dir`.
+pub fn cache_path(dir: &Path, root: &Path) -> PathBuf {
+    // FNV-1a, which unlike the std hasher is stable across releases
+    let hash = root
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    dir.join(format!("{hash:016x}.idx"))
+}
+
+/// Read the cached files of the workspace at `root`. `None` if there is no
+/// cache, or one written by another version, for another root or key.
+pub(crate) fn load(
+    path: &Path,
+    root: &Path,
+    key: &str,
+) -> io::Result<Option<HashMap<String, FileInfo>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut reader = BufReader::new(file);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC
+        || read_u32(&mut reader)? != VERSION
+        || read_string(&mut reader)? != root.to_string_lossy()
+        || read_string(&mut reader)? != key
+    {
+        return Ok(None);
+    }
+
+    let count = read_u64(&mut reader)?;
+    let mut files = HashMap::new();
+    for _ in 0..count {
+        let path = read_string(&mut reader)?;
+        let size = read_u64(&mut reader)?;
+        let modified = if read_u8(&mut reader)? == 0 {
+            None
+        } else {
+            let seconds = read_u64(&mut reader)?;
+            let nanos = read_u32(&mut reader)?;
+            UNIX_EPOCH.checked_add(Duration::new(seconds, nanos))
+        };
+        let language = read_string(&mut reader)?;
+        let count = read_u32(&mut reader)?;
+        let symbols = (0..count)
+            .map(|_| read_symbol(&mut reader))
+            .collect::<io::Result<_>>()?;
+        let trigrams = if read_u8(&mut reader)? == 0 {
+            None
+        } else {
+            Some(read_filter(&mut reader)?)
+        };
+        files.insert(
+            path,
+            FileInfo {
+                size,
+                modified,
+                language,
+                symbols,
+                trigrams,
+            },
+        );
+    }
+    Ok(Some(files))
+}
+
+/// Write the cache of the workspace at `root`, replacing any previous one
+/// at once.
+pub(crate) fn save(
+    path: &Path,
+    root: &Path,
+    key: &str,
+    files: &HashMap<String, FileInfo>,
+) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("idx.tmp");
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    write_string(&mut writer, &root.to_string_lossy())?;
+    write_string(&mut writer, key)?;
+    writer.write_all(&(files.len() as u64).to_le_bytes())?;
+    for (path, info) in files {
+        write_string(&mut writer, path)?;
+        writer.write_all(&info.size.to_le_bytes())?;
+        match info
+            .modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        {
+            Some(since) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&since.as_secs().to_le_bytes())?;
+                writer.write_all(&since.subsec_nanos().to_le_bytes())?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        write_string(&mut writer, &info.language)?;
+        let count = u32::try_from(info.symbols.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        writer.write_all(&count.to_le_bytes())?;
+        for symbol in &info.symbols {
+            write_symbol(&mut writer, symbol)?;
+        }
+        match &info.trigrams {
+            Some(filter) => {
+                writer.write_all(&[1])?;
+                write_filter(&mut writer, filter)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+    }
+    writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    fs::rename(partial, path)
+}
+
+/// Delete a cache file, if there is one.
+pub(crate) fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let len = read_u32(reader)? as usize;
+    if len > MAX_STRING {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "String too long",
+        ));
+    }
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_symbol(reader: &mut impl Read) -> io::Result<IndexedSymbol> {
+    let name = read_string(reader)?;
+    let kind = KINDS
+        .get(usize::from(read_u8(reader)?))
+        .copied()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unknown symbol kind"))?;
+    Ok(IndexedSymbol {
+        name,
+        kind,
+        container: read_string(reader)?,
+        range: read_range(reader)?,
+        selection_range: read_range(reader)?,
+    })
+}
+
+fn read_range(reader: &mut impl Read) -> io::Result<TextRange> {
+    let start = Position::new(read_u32(reader)?, read_u32(reader)?);
+    let end = Position::new(read_u32(reader)?, read_u32(reader)?);
+    Ok(TextRange::new(start, end))
+}
+
+fn read_filter(reader: &mut impl Read) -> io::Result<TrigramFilter> {
+    let len = read_u32(reader)? as usize;
+    if len > MAX_FILTER_WORDS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Trigram filter too long",
+        ));
+    }
+    let words = (0..len)
+        .map(|_| read_u64(reader))
+        .collect::<io::Result<_>>()?;
+    TrigramFilter::from_words(words)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid trigram filter"))
+}
+
+fn write_symbol(writer: &mut impl Write, symbol: &IndexedSymbol) -> io::Result<()> {
+    write_string(writer, &symbol.name)?;
+    let kind = KINDS
+        .iter()
+        .position(|kind| *kind == symbol.kind)
+        .unwrap_or(0);
+    writer.write_all(&[u8::try_from(kind).unwrap_or(0)])?;
+    write_string(writer, &symbol.container)?;
+    write_range(writer, symbol.range)?;
+    write_range(writer, symbol.selection_range)
+}
+
+fn write_range(writer: &mut impl Write, range: TextRange) -> io::Result<()> {
+    for value in [
+        range.start.line,
+        range.start.character,
+        range.end.line,
+        range.end.character,
+    ] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_filter(writer: &mut impl Write, filter: &TrigramFilter) -> io::Result<()> {
+    let words = filter.words();
+    let len =
+        u32::try_from(words.len()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    writer.write_all(&len.to_le_bytes())?;
+    for word in words {
+        writer.write_all(&word.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_string(writer: &mut impl Write, value: &str) -> io::Result<()> {
+    let len =
+        u32::try_from(value.len()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(value.as_bytes())
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_validation() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = Path::new("/work/project");
+        let path = cache_path(dir.path(), root);
+        assert_eq!(path, cache_path(dir.path(), root));
+        assert_ne!(path, cache_path(dir.path(), Path::new("/work/other")));
+        assert!(load(&path, root, "key").unwrap().is_none());
+
+        let files = HashMap::from([
+            (
+                "src/lib.rs".to_string(),
+                FileInfo {
+                    size: 42,
+                    modified: UNIX_EPOCH.checked_add(Duration::new(1_700_000_000, 5)),
+                    language: "rust".to_string(),
+                    symbols: vec![IndexedSymbol {
+                        name: "parse".to_string(),
+                        kind: SymbolKind::Method,
+                        container: "impl Parser".to_string(),
+                        range: TextRange::new(Position::new(3, 4), Position::new(5, 5)),
+                        selection_range: TextRange::new(Position::new(3, 7), Position::new(3, 12)),
+                    }],
+                    trigrams: Some(TrigramFilter::new(b"fn parse() {}")),
+                },
+            ),
+            (
+                "README".to_string(),
+                FileInfo {
+                    size: 0,
+                    modified: None,
+                    language: String::new(),
+                    symbols: Vec::new(),
+                    trigrams: None,
+                },
+            ),
+        ]);
+        save(&path, root, "key", &files).unwrap();
+        assert_eq!(load(&path, root, "key").unwrap().unwrap(), files);
+        assert!(load(&path, root, "other key").unwrap().is_none());
+        assert!(load(&path, Path::new("/work/other"), "key")
+            .unwrap()
+            .is_none());
+
+        // A truncated file is an error, not an empty cache
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        assert!(load(&path, root, "key").is_err());
+
+        remove(&path).unwrap();
+        remove(&path).unwrap();
+        assert!(load(&path, root, "key").unwrap().is_none());
+    }
+}
@@ -0,0 +1,364 @@
+//! Which files of a workspace are indexed.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use ignore::gitignore::{gitconfig_excludes_path, Gitignore, GitignoreBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::{DirEntry, ParallelVisitor, ParallelVisitorBuilder, WalkBuilder, WalkState};
+use parking_lot::Mutex;
+
+use crate::IndexError;
+
+/// Ignore files read in every directory.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Paths handed over at once while scanning.
+const SCAN_BATCH: usize = 1024;
+
+/// Which files of a workspace are indexed.
+///
+/// Everything is, hidden files too, except `.git`, what the exclude globs
+/// match and, unless disabled, what `.gitignore`, `.ignore`,
+/// `.git/info/exclude` and git's global excludes file exclude.
+///
+/// Scans apply these rules the way a text search does. Single paths, such
+/// as those a file watcher reports, are checked against the ignore files of
+/// the directories above them, read once and cached until they change.
+pub struct PathFilter {
+    root: PathBuf,
+    overrides: Override,
+    use_ignore_files: bool,
+    /// Repository and global excludes, rooted at the workspace.
+    excludes: Gitignore,
+    /// Rules of the ignore files by directory, `None` where there are none.
+    dirs: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+    /// Threads a scan uses, 0 for one per CPU.
+    scan_threads: usize,
+}
+
+impl PathFilter {
+    /// Rules for the workspace at `root`. Exclude globs use `.gitignore`
+    /// syntax.
+    pub fn new(
+        root: impl Into<PathBuf>,
+        exclude: &[String],
+        use_ignore_files: bool,
+    ) -> Result<Self, IndexError> {
+        let root = root.into();
+        let overrides = overrides(&root, exclude)?;
+
+        let mut excludes = GitignoreBuilder::new(&root);
+        if use_ignore_files {
+            // Missing files are no error
+            if let Some(global) = gitconfig_excludes_path() {
+                excludes.add(global);
+            }
+            excludes.add(root.join(".git/info/exclude"));
+        }
+        let excludes = excludes.build().unwrap_or_else(|_| Gitignore::empty());
+
+        Ok(Self {
+            root,
+            overrides,
+            use_ignore_files,
+            excludes,
+            dirs: Mutex::new(HashMap::new()),
+            scan_threads: 0,
+        })
+    }
+
+    /// Limit scans to `threads` threads, 0 for one per CPU.
+    #[must_use]
+    pub fn with_scan_threads(mut self, threads: usize) -> Self {
+        self.scan_threads = threads;
+        self
+    }
+
+    /// Check that exclude globs compile, before there is a filter to use
+    /// them in.
+    pub fn check_globs(exclude: &[String]) -> Result<(), IndexError> {
+        overrides(Path::new(""), exclude).map(drop)
+    }
+
+    /// Root of the workspace.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether `path`, a file or directory under the root, is left out.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        if relative.components().any(|c| c.as_os_str() == ".git") {
+            return true;
+        }
+        let mut ancestors = path.ancestors().take_while(|dir| *dir != self.root);
+        if ancestors.any(|p| self.overrides.matched(p, p != path || is_dir).is_ignore()) {
+            return true;
+        }
+        if !self.use_ignore_files {
+            return false;
+        }
+
+        // The nearest rule decides, and a directory's rules cover everything
+        // under it
+        let dirs = path.ancestors().skip(1);
+        for dir in dirs.take_while(|dir| dir.starts_with(&self.root)) {
+            let Some(rules) = self.rules(dir) else {
+                continue;
+            };
+            let matched = rules.matched_path_or_any_parents(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+        self.excludes
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+
+    /// Forget the cached rules of the directory holding `path` if it is an
+    /// ignore file, returning whether it is one.
+    pub fn ignore_file_changed(&self, path: &Path) -> bool {
+        let is_ignore_file = path
+            .file_name()
+            .is_some_and(|name| IGNORE_FILES.iter().any(|file| name == *file));
+        if let (true, Some(dir)) = (is_ignore_file, path.parent()) {
+            self.dirs.lock().remove(dir);
+        }
+        is_ignore_file
+    }
+
+    /// Scan the files under `dir`, a directory under the root, handing
+    /// batches of their root-relative paths (with `/` separators) to
+    /// `on_batch` from the scanning threads. Stops early once `cancel` is
+    /// set.
+    pub fn scan<F>(&self, dir: &Path, cancel: &AtomicBool, on_batch: F)
+    where
+        F: Fn(Vec<String>) + Sync,
+    {
+        let use_ignore_files = self.use_ignore_files;
+        let mut collectors = Collectors {
+            root: &self.root,
+            cancel,
+            on_batch: &on_batch,
+        };
+        WalkBuilder::new(dir)
+            .hidden(false)
+            .parents(use_ignore_files)
+            .ignore(use_ignore_files)
+            .git_ignore(use_ignore_files)
+            .git_global(use_ignore_files)
+            .git_exclude(use_ignore_files)
+            // Respect .gitignore files in folders that are not repositories
+            .require_git(false)
+            .threads(self.scan_threads)
+            .overrides(self.overrides.clone())
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build_parallel()
+            .visit(&mut collectors);
+    }
+
+    /// Rules of the ignore files in `dir`.
+    fn rules(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        if let Some(rules) = self.dirs.lock().get(dir) {
+            return rules.clone();
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut found = false;
+        for name in IGNORE_FILES {
+            let file = dir.join(name);
+            if file.is_file() {
+                builder.add(file);
+                found = true;
+            }
+        }
+        let rules = found.then(|| builder.build().ok()).flatten().map(Arc::new);
+        self.dirs.lock().insert(dir.to_path_buf(), rules.clone());
+        rules
+    }
+}
+
+/// Exclude globs as ignore-style overrides rooted at `root`.
+fn overrides(root: &Path, exclude: &[String]) -> Result<Override, IndexError> {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in exclude {
+        overrides
+            .add(&format!("!{glob}"))
+            .map_err(|e| invalid_glob(glob, &e))?;
+    }
+    overrides.build().map_err(|e| invalid_glob("", &e))
+}
+
+fn invalid_glob(glob: &str, err: &ignore::Error) -> IndexError {
+    IndexError::InvalidGlob {
+        glob: glob.to_string(),
+        message: err.to_string(),
+    }
+}
+
+/// Path of `path` relative to `root`, with `/` separators. `None` for
+/// paths outside the root or that are not valid UTF-8.
+pub(crate) fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let mut joined = String::new();
+    for component in relative.components() {
+        if !joined.is_empty() {
+            joined.push('/');
+        }
+        joined.push_str(component.as_os_str().to_str()?);
+    }
+    Some(joined)
+}
+
+/// Builds a [`Collector`] per scanning thread.
+struct Collectors<'s, F> {
+    root: &'s Path,
+    cancel: &'s AtomicBool,
+    on_batch: &'s F,
+}
+
+impl<'s, F: Fn(Vec<String>) + Sync> ParallelVisitorBuilder<'s> for Collectors<'s, F> {
+    fn build(&mut self) -> Box<dyn ParallelVisitor + 's> {
+        Box::new(Collector {
+            root: self.root,
+            cancel: self.cancel,
+            on_batch: self.on_batch,
+            batch: Vec::with_capacity(SCAN_BATCH),
+        })
+    }
+}
+
+/// Collects one thread's files into batches.
+struct Collector<'s, F: Fn(Vec<String>) + Sync> {
+    root: &'s Path,
+    cancel: &'s AtomicBool,
+    on_batch: &'s F,
+    batch: Vec<String>,
+}
+
+impl<F: Fn(Vec<String>) + Sync> ParallelVisitor for Collector<'_, F> {
+    fn visit(&mut self, entry: Result<DirEntry, ignore::Error>) -> WalkState {
+        if self.cancel.load(Ordering::Relaxed) {
+            return WalkState::Quit;
+        }
+        let Ok(entry) = entry else {
+            return WalkState::Continue;
+        };
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            return WalkState::Continue;
+        }
+        if let Some(path) = relative_path(self.root, entry.path()) {
+            self.batch.push(path);
+            if self.batch.len() == SCAN_BATCH {
+                (self.on_batch)(std::mem::take(&mut self.batch));
+            }
+        }
+        WalkState::Continue
+    }
+}
+
+impl<F: Fn(Vec<String>) + Sync> Drop for Collector<'_, F> {
+    // The walk drops each thread's collector when it is done
+    fn drop(&mut self) {
+        if !self.batch.is_empty() && !self.cancel.load(Ordering::Relaxed) {
+            (self.on_batch)(std::mem::take(&mut self.batch));
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn scan(filter: &PathFilter, dir: &Path) -> Vec<String> {
+        let found = Mutex::new(Vec::new());
+        filter.scan(dir, &AtomicBool::new(false), |batch| {
+            found.lock().extend(batch);
+        });
+        let mut found = found.into_inner();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn test_scan_and_single_paths_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::create_dir_all(root.join("src/gen")).unwrap();
+        fs::write(root.join("src/gen/.gitignore"), "*.rs\n!keep.rs\n").unwrap();
+        for file in [
+            "src/lib.rs",
+            "src/gen/out.rs",
+            "src/gen/keep.rs",
+            "debug.log",
+            "target/app",
+            "vendor/dep.rs",
+            ".env",
+            ".git/HEAD",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let filter = PathFilter::new(root, &["vendor".to_string()], true).unwrap();
+        let scanned = scan(&filter, root);
+        assert_eq!(
+            scanned,
+            [
+                ".env",
+                ".gitignore",
+                "src/gen/.gitignore",
+                "src/gen/keep.rs",
+                "src/lib.rs"
+            ]
+        );
+        for (file, excluded) in [
+            ("src/lib.rs", false),
+            ("src/gen/keep.rs", false),
+            ("src/gen/out.rs", true),
+            ("debug.log", true),
+            ("target/app", true),
+            ("vendor/dep.rs", true),
+            (".git/HEAD", true),
+        ] {
+            assert_eq!(
+                filter.is_excluded(&root.join(file), false),
+                excluded,
+                "{file}"
+            );
+        }
+        assert!(filter.is_excluded(&root.join("target"), true));
+
+        // Changed rules are read again
+        fs::write(root.join("src/gen/.gitignore"), "").unwrap();
+        assert!(filter.ignore_file_changed(&root.join("src/gen/.gitignore")));
+        assert!(!filter.is_excluded(&root.join("src/gen/out.rs"), false));
+        assert_eq!(scan(&filter, &root.join("src/gen")).len(), 3);
+
+        let filter = PathFilter::new(root, &[], false).unwrap();
+        assert!(!filter.is_excluded(&root.join("target/app"), false));
+        assert!(matches!(
+            PathFilter::new(root, &["{".to_string()], true),
+            Err(IndexError::InvalidGlob { .. })
+        ));
+        assert!(PathFilter::check_globs(&["{".to_string()]).is_err());
+        assert!(PathFilter::check_globs(&["*.log".to_string()]).is_ok());
+    }
+}
@@ -0,0 +1,319 @@
+//! Fuzzy matching of paths.
+//!
+//! Each word of a pattern must appear in the path in order, though not
+//! necessarily next to each other. A word matched in the file name ranks
+//! above one spread over directories, and matches at word boundaries and in
+//! runs rank above scattered ones, the way fzf scores them.
+
+/// Score of every matched character.
+const SCORE_MATCH: i32 = 16;
+/// Penalty for the first skipped character in a gap.
+const SCORE_GAP_START: i32 = -3;
+/// Penalty for every further skipped character in a gap.
+const SCORE_GAP_EXTENSION: i32 = -1;
+/// Bonus for a match at the start of the path.
+const BONUS_START: i32 = 10;
+/// Bonus for a match right after a `/`.
+const BONUS_DIRECTORY: i32 = 9;
+/// Bonus for a match after another separator, such as `_`, `-` or `.`.
+const BONUS_BOUNDARY: i32 = 8;
+/// Bonus for matching a separator.
+const BONUS_NON_WORD: i32 = 8;
+/// Bonus for an uppercase letter after a lowercase one, or a digit after a
+/// letter.
+const BONUS_CAMEL: i32 = 7;
+/// Least bonus for a match following another.
+const BONUS_CONSECUTIVE: i32 = 4;
+/// How much more the bonus of a word's first character counts.
+const FIRST_CHAR_MULTIPLIER: i32 = 2;
+/// Bonus for a word matched within the file name.
+const BONUS_FILE_NAME: i32 = 16;
+
+/// A parsed fuzzy pattern.
+pub(crate) struct Pattern {
+    words: Vec<Word>,
+}
+
+/// One whitespace-separated word of a pattern.
+struct Word {
+    chars: Vec<char>,
+    /// Smart case: only words with an uppercase letter match case exactly.
+    case_sensitive: bool,
+}
+
+impl Pattern {
+    pub(crate) fn new(pattern: &str) -> Self {
+        let words = pattern
+            .split_whitespace()
+            .map(|word| {
+                let case_sensitive = word.chars().any(char::is_uppercase);
+                let chars = if case_sensitive {
+                    word.chars().collect()
+                } else {
+                    word.chars().map(fold).collect()
+                };
+                Word {
+                    chars,
+                    case_sensitive,
+                }
+            })
+            .collect();
+        Self { words }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// The [`char_mask`] of the pattern's characters, which a path's mask
+    /// must contain for it to match.
+    pub(crate) fn mask(&self) -> u64 {
+        char_mask(
+            self.words
+                .iter()
+                .flat_map(|word| word.chars.iter().copied()),
+        )
+    }
+
+    /// Score of `path`, or `None` if some word does not match. With
+    /// `positions`, the indexes of the matched characters are added to it,
+    /// unsorted.
+    ///
+    /// The path is given as characters, or as bytes if it is ASCII, which
+    /// saves decoding it.
+    pub(crate) fn score<C: Copy + Into<char>>(
+        &self,
+        path: &[C],
+        mut positions: Option<&mut Vec<usize>>,
+    ) -> Option<u32> {
+        let name_start = path
+            .iter()
+            .rposition(|c| (*c).into() == '/')
+            .map_or(0, |i| i + 1);
+        let mut total = 0;
+        for word in &self.words {
+            let (window, bonus) = match word.window(path, name_start) {
+                Some(window) => (window, BONUS_FILE_NAME),
+                None => (word.window(path, 0)?, 0),
+            };
+            total += word.score(path, window) + bonus;
+            if let Some(positions) = positions.as_deref_mut() {
+                word.positions(path, window, positions);
+            }
+        }
+        u32::try_from(total.max(1)).ok()
+    }
+}
+
+impl Word {
+    fn eq(&self, c: char, q: char) -> bool {
+        if self.case_sensitive {
+            c == q
+        } else {
+            fold(c) == q
+        }
+    }
+
+    /// The tightest window matching the word in `text[from..]`: the earliest
+    /// end of a match, then the latest start for that end.
+    fn window<C: Copy + Into<char>>(&self, text: &[C], from: usize) -> Option<(usize, usize)> {
+        let first = *self.chars.first()?;
+        let mut next = 0;
+        let mut end = None;
+        for (i, &c) in text.iter().enumerate().skip(from) {
+            if self.eq(c.into(), self.chars[next]) {
+                next += 1;
+                if next == self.chars.len() {
+                    end = Some(i);
+                    break;
+                }
+            }
+        }
+        let end = end?;
+        let mut remaining = self.chars.len();
+        let mut start = end;
+        for i in (from..=end).rev() {
+            if self.eq(text[i].into(), self.chars[remaining - 1]) {
+                remaining -= 1;
+                if remaining == 0 {
+                    start = i;
+                    break;
+                }
+            }
+        }
+        debug_assert!(self.eq(text[start].into(), first));
+        Some((start, end))
+    }
+
+    /// Score of the word's match in a window of `text`.
+    fn score<C: Copy + Into<char>>(&self, text: &[C], (start, end): (usize, usize)) -> i32 {
+        let mut score = 0;
+        let mut next = 0;
+        let mut in_gap = false;
+        let mut run = 0;
+        let mut run_bonus = 0;
+        for i in start..=end {
+            let c = text[i].into();
+            if next < self.chars.len() && self.eq(c, self.chars[next]) {
+                let mut bonus = bonus(i.checked_sub(1).map(|p| text[p].into()), c);
+                if run == 0 {
+                    run_bonus = bonus;
+                } else {
+                    // A run keeps the bonus of the boundary it started at
+                    if bonus >= BONUS_BOUNDARY && bonus > run_bonus {
+                        run_bonus = bonus;
+                    }
+                    bonus = bonus.max(run_bonus).max(BONUS_CONSECUTIVE);
+                }
+                score += SCORE_MATCH
+                    + if next == 0 {
+                        bonus * FIRST_CHAR_MULTIPLIER
+                    } else {
+                        bonus
+                    };
+                next += 1;
+                run += 1;
+                in_gap = false;
+            } else {
+                score += if in_gap {
+                    SCORE_GAP_EXTENSION
+                } else {
+                    SCORE_GAP_START
+                };
+                in_gap = true;
+                run = 0;
+            }
+        }
+        score
+    }
+
+    /// Add the indexes of the characters the word matches in a window of
+    /// `text`.
+    fn positions<C: Copy + Into<char>>(
+        &self,
+        text: &[C],
+        (start, end): (usize, usize),
+        positions: &mut Vec<usize>,
+    ) {
+        let mut next = 0;
+        for (i, &c) in text.iter().enumerate().take(end + 1).skip(start) {
+            if next < self.chars.len() && self.eq(c.into(), self.chars[next]) {
+                positions.push(i);
+                next += 1;
+            }
+        }
+    }
+}
+
+/// A bit for each kind of character in `chars`, ignoring case: one per
+/// ASCII letter and digit, a few shared by other ASCII characters and one
+/// for everything else. Checking it rules out most paths a pattern does not
+/// match without scoring them.
+pub(crate) fn char_mask(chars: impl Iterator<Item = char>) -> u64 {
+    chars.fold(0, |mask, c| {
+        let bit = match fold(c) {
+            c @ 'a'..='z' => u32::from(c) - u32::from('a'),
+            c @ '0'..='9' => 26 + u32::from(c) - u32::from('0'),
+            c if c.is_ascii() => 36 + u32::from(c) % 27,
+            _ => 63,
+        };
+        mask | 1 << bit
+    })
+}
+
+/// Bonus for matching `c` after `prev`, `None` at the start of the text.
+fn bonus(prev: Option<char>, c: char) -> i32 {
+    let Some(prev) = prev else {
+        return BONUS_START;
+    };
+    if !is_word(c) {
+        BONUS_NON_WORD
+    } else if prev == '/' {
+        BONUS_DIRECTORY
+    } else if !is_word(prev) {
+        BONUS_BOUNDARY
+    } else if is_camel_hump(prev, c) {
+        BONUS_CAMEL
+    } else {
+        0
+    }
+}
+
+/// An uppercase letter after a lowercase one, or a digit after a letter.
+fn is_camel_hump(prev: char, c: char) -> bool {
+    if c.is_numeric() {
+        !prev.is_numeric()
+    } else {
+        prev.is_lowercase() && c.is_uppercase()
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Case folding for case-insensitive matching.
+fn fold(c: char) -> char {
+    if c.is_ascii() {
+        c.to_ascii_lowercase()
+    } else {
+        c.to_lowercase().next().unwrap_or(c)
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    fn score(pattern: &str, path: &str) -> Option<u32> {
+        let chars: Vec<char> = path.chars().collect();
+        Pattern::new(pattern).score(&chars, None)
+    }
+
+    fn positions(pattern: &str, path: &str) -> Vec<usize> {
+        let chars: Vec<char> = path.chars().collect();
+        let mut positions = Vec::new();
+        Pattern::new(pattern).score(&chars, Some(&mut positions));
+        positions.sort_unstable();
+        positions
+    }
+
+    #[test]
+    fn test_matching() {
+        assert!(score("mnrs", "src/main.rs").is_some());
+        assert!(score("main src", "src/main.rs").is_some());
+        assert!(score("smr", "src/main.rs").is_some());
+        assert!(score("rsm", "src/main.rs").is_none());
+        assert!(score("main lib", "src/main.rs").is_none());
+
+        // Smart case
+        assert!(score("readme", "README.md").is_some());
+        assert!(score("ReadMe", "README.md").is_none());
+        assert!(score("README", "README.md").is_some());
+    }
+
+    #[test]
+    fn test_ranking() {
+        // File name over directories
+        assert!(score("main", "src/main.rs") > score("main", "domain/mod.rs"));
+        // Word boundaries over scattered letters
+        assert!(score("fb", "src/foo_bar.rs") > score("fb", "src/fabric.rs"));
+        assert!(score("fb", "src/FooBar.rs") > score("fb", "src/fabric.rs"));
+        // Runs over gaps
+        assert!(score("lib", "src/lib.rs") > score("lib", "src/list_bin.rs"));
+    }
+
+    #[test]
+    fn test_positions() {
+        assert_eq!(positions("main", "src/main.rs"), [4, 5, 6, 7]);
+        // The tightest window wins
+        assert_eq!(positions("ab", "a/x/a_b.rs"), [4, 6]);
+        assert_eq!(positions("src rs", "src/main.rs"), [0, 1, 2, 9, 10]);
+    }
+}
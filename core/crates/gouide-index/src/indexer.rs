@@ -0,0 +1,871 @@
+//! Background indexing of a workspace.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use gouide_fs::FsEvent;
+use gouide_search::{TrigramFilter, Trigrams};
+use gouide_syntax::Language;
+use parking_lot::{Condvar, Mutex, RwLock, RwLockReadGuard};
+
+use crate::symbols::{self, MAX_SYMBOL_FILE};
+use crate::{
+    cache, IndexError, IndexedSymbol, PathChanges, PathFilter, PathIndex, SymbolMatch, SymbolQuery,
+};
+
+/// Largest file whose trigrams are recorded, in bytes, as large as a text
+/// search reads by default.
+const MAX_TRIGRAM_FILE: u64 = 16 * 1024 * 1024;
+
+/// Files read between checks for pausing and interactive work.
+const INDEX_BATCH: usize = 256;
+
+/// How long indexing waits each time it finds interactive work running.
+const YIELD_WAIT: Duration = Duration::from_millis(5);
+
+/// Waits in a row after which indexing goes on anyway, so a steady stream
+/// of requests cannot starve it.
+const MAX_YIELDS: u32 = 20;
+
+/// What an [`Indexer`] is doing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndexState {
+    /// [`Indexer::run`] has not been called yet.
+    #[default]
+    NotStarted,
+    /// Finding the workspace's files.
+    Scanning,
+    /// Reading the files found.
+    Indexing,
+    /// Every file is indexed; file watcher events keep it that way.
+    Complete,
+    /// Waiting for [`Indexer::resume`].
+    Paused,
+    /// Indexing failed; see [`IndexStatus::error`].
+    Error,
+}
+
+/// Snapshot of an indexer's progress.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexStatus {
+    /// What the indexer is doing.
+    pub state: IndexState,
+    /// Percentage of the files found that have been read, 0 to 100. Stays
+    /// 0 while scanning, since the total is not known yet.
+    pub progress: u32,
+    /// Files found so far.
+    pub file_count: usize,
+    /// Why indexing failed.
+    pub error: Option<String>,
+}
+
+/// What is known about an indexed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    /// Size in bytes.
+    pub size: u64,
+    /// Last modification time, where the platform has one.
+    pub modified: Option<SystemTime>,
+    /// Language ID, empty without a language detector.
+    pub language: String,
+    /// Declarations, in document order. Empty unless the indexer extracts
+    /// symbols.
+    pub symbols: Vec<IndexedSymbol>,
+    /// Filter of the trigrams of the contents. `None` unless the indexer
+    /// records them, and for files too large or that could not be read.
+    pub trigrams: Option<TrigramFilter>,
+}
+
+impl FileInfo {
+    /// Whether a cached entry still describes a file of this size and
+    /// modification time. Files without one are never trusted.
+    fn is_current(&self, size: u64, modified: Option<SystemTime>) -> bool {
+        self.size == size && modified.is_some() && self.modified == modified
+    }
+}
+
+/// Finds the language of a file from its root-relative path.
+type LanguageDetector = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Where an indexer's cache lives.
+struct CacheFile {
+    path: PathBuf,
+    /// Describes how the cached data was derived; a cache with another key
+    /// is not used.
+    key: String,
+}
+
+/// Progress shared between the indexing thread and everyone else.
+#[derive(Default)]
+struct Control {
+    /// Phase reached; never [`IndexState::Paused`].
+    phase: IndexState,
+    paused: bool,
+    progress: u32,
+    error: Option<String>,
+}
+
+/// Indexes one workspace: scans it for files, then reads each file found.
+///
+/// [`run`](Self::run) does the work on the caller's thread and can be
+/// paused, resumed and stopped from any other. File watcher events are
+/// applied with [`apply_events`](Self::apply_events) at any time, before,
+/// during or after the run. Every change of the status is handed to the
+/// callback given to [`new`](Self::new), in order; it is called with
+/// internal locks held, so it must not call back into the indexer.
+pub struct Indexer {
+    filter: PathFilter,
+    paths: RwLock<PathIndex>,
+    /// Length of `paths`, readable while a long query holds its lock.
+    file_count: AtomicUsize,
+    files: RwLock<HashMap<String, FileInfo>>,
+    control: Mutex<Control>,
+    resumed: Condvar,
+    stopped: AtomicBool,
+    on_status: Box<dyn Fn(&IndexStatus) + Send + Sync>,
+    languages: Option<LanguageDetector>,
+    /// Whether files' symbols are extracted.
+    symbols: bool,
+    /// Whether files' trigrams are recorded.
+    trigrams: bool,
+    cache: Option<CacheFile>,
+    /// Set once the cache is cleared, after which it is never written.
+    cache_cleared: Mutex<bool>,
+    /// Whether `files` differs from the cache on disk.
+    dirty: AtomicBool,
+    /// Paths the running scan has found, while a cache pre-filled `paths`.
+    seen: Mutex<Option<HashSet<String>>>,
+    /// Files the last run read afresh rather than took from the cache.
+    reindexed: AtomicUsize,
+}
+
+impl Indexer {
+    /// An indexer of the files `filter` lets through, reporting its status
+    /// to `on_status`.
+    pub fn new(
+        filter: PathFilter,
+        on_status: impl Fn(&IndexStatus) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            filter,
+            paths: RwLock::new(PathIndex::new()),
+            file_count: AtomicUsize::new(0),
+            files: RwLock::new(HashMap::new()),
+            control: Mutex::new(Control::default()),
+            resumed: Condvar::new(),
+            stopped: AtomicBool::new(false),
+            on_status: Box::new(on_status),
+            languages: None,
+            symbols: false,
+            trigrams: false,
+            cache: None,
+            cache_cleared: Mutex::new(false),
+            dirty: AtomicBool::new(false),
+            seen: Mutex::new(None),
+            reindexed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Record the language of each file, as `detect` finds it from the
+    /// file's root-relative path.
+    #[must_use]
+    pub fn with_languages(
+        mut self,
+        detect: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.languages = Some(Box::new(detect));
+        self
+    }
+
+    /// Record the symbols each file declares, found with the grammar of its
+    /// language: the detected one with [`with_languages`](Self::with_languages),
+    /// otherwise the one its extension names.
+    #[must_use]
+    pub const fn with_symbols(mut self) -> Self {
+        self.symbols = true;
+        self
+    }
+
+    /// Record a [`TrigramFilter`] of each text file's contents, so text
+    /// searches can skip files that cannot match (see
+    /// [`candidates`](Self::candidates)).
+    #[must_use]
+    pub const fn with_trigrams(mut self) -> Self {
+        self.trigrams = true;
+        self
+    }
+
+    /// Start runs from the cache file at `path`, and save to it. `key`
+    /// must change whenever the same file could be indexed differently,
+    /// for example with other language rules; the indexer's own options
+    /// are accounted for.
+    #[must_use]
+    pub fn with_cache(mut self, path: PathBuf, key: impl Into<String>) -> Self {
+        self.cache = Some(CacheFile {
+            path,
+            key: key.into(),
+        });
+        self
+    }
+
+    /// Which files are indexed.
+    pub fn filter(&self) -> &PathFilter {
+        &self.filter
+    }
+
+    /// Current status.
+    pub fn status(&self) -> IndexStatus {
+        self.snapshot(&self.control.lock())
+    }
+
+    /// Whether the scan has found every file, so [`paths`](Self::paths)
+    /// lists them all.
+    pub fn is_scanned(&self) -> bool {
+        matches!(
+            self.control.lock().phase,
+            IndexState::Indexing | IndexState::Complete
+        )
+    }
+
+    /// Paths of the files found so far.
+    pub fn paths(&self) -> RwLockReadGuard<'_, PathIndex> {
+        self.paths.read()
+    }
+
+    /// What is known about a file, by root-relative path. `None` until
+    /// the file has been read.
+    pub fn file_info(&self, path: &str) -> Option<FileInfo> {
+        self.files.read().get(path).cloned()
+    }
+
+    /// Symbols matching `query`, best first, from the files indexed so far.
+    /// `buffers` holds the symbols of open buffers by path, which replace
+    /// those of their files. `None` if `cancel` was set before the search
+    /// finished.
+    pub fn find_symbols(
+        &self,
+        query: &SymbolQuery,
+        buffers: &HashMap<String, Vec<IndexedSymbol>>,
+        cancel: &AtomicBool,
+    ) -> Option<Vec<SymbolMatch>> {
+        let files = self.files.read();
+        let on_disk = files
+            .iter()
+            .filter(|(path, _)| !buffers.contains_key(*path))
+            .map(|(path, info)| (path.as_str(), info.symbols.as_slice()));
+        let open = buffers
+            .iter()
+            .map(|(path, symbols)| (path.as_str(), symbols.as_slice()));
+        let found = symbols::find(on_disk.chain(open), query, cancel);
+        drop(files);
+        found
+    }
+
+    /// Paths of the files that may contain every one of `trigrams`, going by
+    /// their filters. `None` unless the indexer records trigrams and has
+    /// read every file, since until then any file could match. Files
+    /// without a filter are always candidates.
+    pub fn candidates(&self, trigrams: &Trigrams) -> Option<Vec<String>> {
+        if !self.trigrams || self.control.lock().phase != IndexState::Complete {
+            return None;
+        }
+        let files = self.files.read();
+        let candidates = files
+            .iter()
+            .filter(|(_, info)| {
+                info.trigrams
+                    .as_ref()
+                    .map_or(true, |filter| filter.may_contain(trigrams))
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        drop(files);
+        Some(candidates)
+    }
+
+    /// Files the last run read afresh, rather than took from the cache.
+    pub fn reindexed(&self) -> usize {
+        self.reindexed.load(Ordering::Relaxed)
+    }
+
+    /// Index the workspace, returning once every file is read or the
+    /// indexer is stopped. Meant for a dedicated thread.
+    ///
+    /// With a cache, its paths are queryable at once while the scan checks
+    /// them, and only files whose size or modification time changed are
+    /// read again. A cache that cannot be read is ignored.
+    ///
+    /// Between batches of work, indexing waits while paused, and briefly
+    /// while `busy` reports interactive work running.
+    pub fn run(&self, busy: &(dyn Fn() -> bool + Sync)) {
+        let root = self.filter.root();
+        if !root.is_dir() {
+            self.fail(format!("{} is not a directory", root.display()));
+            return;
+        }
+
+        let cached = self.load_cache();
+        if !cached.is_empty() {
+            let mut paths = self.paths.write();
+            paths.extend(cached.keys());
+            self.file_count.store(paths.len(), Ordering::Relaxed);
+            drop(paths);
+            *self.seen.lock() = Some(HashSet::new());
+        }
+        self.enter(IndexState::Scanning);
+        self.filter.scan(root, &self.stopped, |batch| {
+            if self.checkpoint(busy) {
+                if let Some(seen) = self.seen.lock().as_mut() {
+                    seen.extend(batch.iter().cloned());
+                }
+                let mut paths = self.paths.write();
+                paths.extend(batch);
+                self.file_count.store(paths.len(), Ordering::Relaxed);
+                drop(paths);
+                self.publish();
+            }
+        });
+        if self.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        // Cached files the scan did not find are gone
+        let seen = self.seen.lock().take();
+        if let Some(seen) = seen {
+            let mut paths = self.paths.write();
+            for path in cached.keys().filter(|path| !seen.contains(*path)) {
+                paths.remove(path);
+            }
+            self.file_count.store(paths.len(), Ordering::Relaxed);
+        }
+
+        self.enter(IndexState::Indexing);
+        let paths: Vec<String> = self.paths.read().paths().map(str::to_string).collect();
+        let (mut done, mut reindexed) = (0, 0);
+        for batch in paths.chunks(INDEX_BATCH) {
+            if !self.checkpoint(busy) {
+                return;
+            }
+            let mut read = Vec::with_capacity(batch.len());
+            for path in batch {
+                let Ok(metadata) = fs::metadata(root.join(path)) else {
+                    continue;
+                };
+                let (size, modified) = (metadata.len(), metadata.modified().ok());
+                match cached.get(path) {
+                    Some(info) if info.is_current(size, modified) => {
+                        read.push((path.clone(), info.clone()));
+                    }
+                    _ if metadata.is_file() => {
+                        reindexed += 1;
+                        read.push((path.clone(), self.index_file(path, size, modified)));
+                    }
+                    _ => {}
+                }
+            }
+            self.files.write().extend(read);
+            done += batch.len();
+            let mut control = self.control.lock();
+            control.progress = u32::try_from(done * 100 / paths.len()).unwrap_or(100);
+            (self.on_status)(&self.snapshot(&control));
+            drop(control);
+        }
+        self.reindexed.store(reindexed, Ordering::Relaxed);
+        if reindexed > 0 || self.files.read().len() != cached.len() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        self.enter(IndexState::Complete);
+    }
+
+    /// Write the index to the cache, if there is one and the index is
+    /// complete and has changed since it was last read or written. Returns
+    /// whether it wrote.
+    pub fn save_cache(&self) -> Result<bool, IndexError> {
+        let Some(cache) = &self.cache else {
+            return Ok(false);
+        };
+        let cleared = self.cache_cleared.lock();
+        if *cleared
+            || self.control.lock().phase != IndexState::Complete
+            || !self.dirty.swap(false, Ordering::Relaxed)
+        {
+            return Ok(false);
+        }
+        let files = self.files.read().clone();
+        let saved = cache::save(
+            &cache.path,
+            self.filter.root(),
+            &self.cache_key(cache),
+            &files,
+        );
+        drop(cleared);
+        if saved.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        saved?;
+        Ok(true)
+    }
+
+    /// Delete the cache file. The indexer never writes it again, so a new
+    /// indexer can rebuild it from scratch.
+    pub fn clear_cache(&self) -> Result<(), IndexError> {
+        let Some(cache) = &self.cache else {
+            return Ok(());
+        };
+        let mut cleared = self.cache_cleared.lock();
+        *cleared = true;
+        cache::remove(&cache.path)?;
+        drop(cleared);
+        Ok(())
+    }
+
+    /// Pause the run at its next batch. Does nothing once it has finished.
+    pub fn pause(&self) -> IndexStatus {
+        let mut control = self.control.lock();
+        if !control.paused
+            && matches!(
+                control.phase,
+                IndexState::NotStarted | IndexState::Scanning | IndexState::Indexing
+            )
+        {
+            control.paused = true;
+            (self.on_status)(&self.snapshot(&control));
+        }
+        let status = self.snapshot(&control);
+        drop(control);
+        status
+    }
+
+    /// Let a paused run go on.
+    pub fn resume(&self) -> IndexStatus {
+        let mut control = self.control.lock();
+        if control.paused {
+            control.paused = false;
+            self.resumed.notify_all();
+            (self.on_status)(&self.snapshot(&control));
+        }
+        let status = self.snapshot(&control);
+        drop(control);
+        status
+    }
+
+    /// Stop the run for good, paused or not.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // Under the lock, so a run about to wait sees the flag first
+        let _control = self.control.lock();
+        self.resumed.notify_all();
+    }
+
+    /// Apply a batch of file system events to the index.
+    pub fn apply_events(&self, events: &[FsEvent]) {
+        // Reads the disk, so before taking any lock
+        let changes = PathChanges::from_events(&self.filter, events);
+        if changes.is_empty() {
+            return;
+        }
+        let read: Vec<_> = changes
+            .added
+            .iter()
+            .filter_map(|path| {
+                let metadata = fs::metadata(self.filter.root().join(path)).ok()?;
+                metadata.is_file().then(|| {
+                    let modified = metadata.modified().ok();
+                    (
+                        path.clone(),
+                        self.index_file(path, metadata.len(), modified),
+                    )
+                })
+            })
+            .collect();
+        if let Some(seen) = self.seen.lock().as_mut() {
+            seen.extend(changes.added.iter().cloned());
+        }
+
+        let mut paths = self.paths.write();
+        let count = paths.len();
+        paths.apply(&changes);
+        self.file_count.store(paths.len(), Ordering::Relaxed);
+        let changed = paths.len() != count;
+        drop(paths);
+        let mut files = self.files.write();
+        for removed in &changes.removed {
+            if files.remove(removed).is_none() {
+                // A directory, or everything
+                let dir = format!("{removed}/");
+                files.retain(|path, _| !removed.is_empty() && !path.starts_with(&dir));
+            }
+        }
+        files.extend(read);
+        drop(files);
+        self.dirty.store(true, Ordering::Relaxed);
+        if changed {
+            self.publish();
+        }
+    }
+
+    /// Read the cache, empty if there is none or it cannot be used.
+    fn load_cache(&self) -> HashMap<String, FileInfo> {
+        let Some(cache) = &self.cache else {
+            return HashMap::new();
+        };
+        if let Ok(Some(files)) =
+            cache::load(&cache.path, self.filter.root(), &self.cache_key(cache))
+        {
+            return files;
+        }
+        // Rewritten once indexing completes
+        self.dirty.store(true, Ordering::Relaxed);
+        HashMap::new()
+    }
+
+    /// Index one file, known to exist with this size and modification
+    /// time.
+    fn index_file(&self, path: &str, size: u64, modified: Option<SystemTime>) -> FileInfo {
+        let language = self
+            .languages
+            .as_ref()
+            .map_or_else(String::new, |detect| detect(path));
+        let grammar = if !self.symbols || size > MAX_SYMBOL_FILE {
+            None
+        } else if self.languages.is_some() {
+            Language::from_id(&language)
+        } else {
+            Language::from_path(path)
+        };
+        let record_trigrams = self.trigrams && size <= MAX_TRIGRAM_FILE;
+        let contents = if grammar.is_some() || record_trigrams {
+            fs::read(self.filter.root().join(path)).ok()
+        } else {
+            None
+        };
+        let symbols = grammar
+            .zip(contents.as_deref())
+            .and_then(|(grammar, contents)| {
+                let text = std::str::from_utf8(contents).ok()?;
+                Some(symbols::extract(grammar, text))
+            })
+            .unwrap_or_default();
+        let trigrams = contents
+            .as_deref()
+            .filter(|_| record_trigrams)
+            .map(TrigramFilter::for_file);
+        FileInfo {
+            size,
+            modified,
+            language,
+            symbols,
+            trigrams,
+        }
+    }
+
+    /// Key of the cache, covering both the caller's key and the indexer's
+    /// options.
+    fn cache_key(&self, cache: &CacheFile) -> String {
+        format!(
+            "{}\nsymbols={}\ntrigrams={}",
+            cache.key, self.symbols, self.trigrams
+        )
+    }
+
+    /// Wait while paused or, for a while, busy. False once stopped.
+    fn checkpoint(&self, busy: &(dyn Fn() -> bool + Sync)) -> bool {
+        let mut control = self.control.lock();
+        while control.paused && !self.stopped.load(Ordering::Relaxed) {
+            self.resumed.wait(&mut control);
+        }
+        drop(control);
+        for _ in 0..MAX_YIELDS {
+            if !busy() {
+                break;
+            }
+            thread::sleep(YIELD_WAIT);
+        }
+        !self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Move on to a new phase.
+    fn enter(&self, phase: IndexState) {
+        let mut control = self.control.lock();
+        control.phase = phase;
+        control.progress = if phase == IndexState::Complete {
+            100
+        } else {
+            0
+        };
+        (self.on_status)(&self.snapshot(&control));
+    }
+
+    /// Give up with an error.
+    fn fail(&self, error: String) {
+        let mut control = self.control.lock();
+        control.phase = IndexState::Error;
+        control.error = Some(error);
+        (self.on_status)(&self.snapshot(&control));
+        drop(control);
+    }
+
+    /// Report the current status.
+    fn publish(&self) {
+        let control = self.control.lock();
+        (self.on_status)(&self.snapshot(&control));
+    }
+
+    fn snapshot(&self, control: &Control) -> IndexStatus {
+        IndexStatus {
+            state: if control.paused {
+                IndexState::Paused
+            } else {
+                control.phase
+            },
+            progress: control.progress,
+            file_count: self.file_count.load(Ordering::Relaxed),
+            error: control.error.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use crate::cache_path;
+    use gouide_fs::FsEventKind;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn write(root: &Path, file: &str, content: &str) {
+        let path = root.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_run_reports_each_phase() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, ".gitignore", "target/\n");
+        write(root, "src/lib.rs", "pub fn lib() {}\n");
+        write(root, "target/app", "");
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let seen = states.clone();
+        let filter = PathFilter::new(root, &[], true).unwrap();
+        let indexer = Indexer::new(filter, move |status| seen.lock().push(status.clone()));
+
+        indexer.run(&|| false);
+        let status = indexer.status();
+        assert_eq!(status.state, IndexState::Complete);
+        assert_eq!((status.progress, status.file_count), (100, 2));
+        assert_eq!(indexer.file_info("src/lib.rs").unwrap().size, 16);
+        assert!(indexer.file_info("target/app").is_none());
+        let mut phases: Vec<_> = states.lock().iter().map(|status| status.state).collect();
+        phases.dedup();
+        assert_eq!(
+            phases,
+            [
+                IndexState::Scanning,
+                IndexState::Indexing,
+                IndexState::Complete
+            ]
+        );
+
+        // Finished runs cannot be paused
+        assert_eq!(indexer.pause().state, IndexState::Complete);
+
+        fs::remove_dir_all(root.join("src")).unwrap();
+        indexer.apply_events(&[FsEvent {
+            path: root.join("src"),
+            kind: FsEventKind::Removed,
+        }]);
+        assert_eq!(indexer.status().file_count, 1);
+        assert!(indexer.file_info("src/lib.rs").is_none());
+        write(root, "README.md", "# Readme\n");
+        indexer.apply_events(&[FsEvent {
+            path: root.join("README.md"),
+            kind: FsEventKind::Created,
+        }]);
+        assert_eq!(indexer.file_info("README.md").unwrap().size, 9);
+        assert_eq!(states.lock().last().unwrap().file_count, 2);
+    }
+
+    #[test]
+    fn test_pause_holds_the_run_until_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", "");
+        let filter = PathFilter::new(dir.path(), &[], true).unwrap();
+        let indexer = Arc::new(Indexer::new(filter, |_| {}));
+        assert_eq!(indexer.pause().state, IndexState::Paused);
+
+        let running = indexer.clone();
+        let run = thread::spawn(move || running.run(&|| false));
+        for _ in 0..50 {
+            if indexer.control.lock().phase == IndexState::Scanning {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(20));
+        let status = indexer.status();
+        assert_eq!((status.state, status.file_count), (IndexState::Paused, 0));
+
+        assert_eq!(indexer.resume().state, IndexState::Scanning);
+        run.join().unwrap();
+        assert_eq!(indexer.status().state, IndexState::Complete);
+
+        // Stopping releases a paused run
+        let filter = PathFilter::new(dir.path(), &[], true).unwrap();
+        let indexer = Arc::new(Indexer::new(filter, |_| {}));
+        indexer.pause();
+        let running = indexer.clone();
+        let run = thread::spawn(move || running.run(&|| false));
+        indexer.stop();
+        run.join().unwrap();
+        assert_ne!(indexer.status().state, IndexState::Complete);
+    }
+
+    #[test]
+    fn test_cache_skips_unchanged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "src/lib.rs", "pub mod a;\n");
+        write(root, "src/a.rs", "");
+        write(root, "notes.txt", "");
+        let cache = cache_path(cache_dir.path(), root);
+        let indexer = |key: &str| {
+            let filter = PathFilter::new(root, &[], true).unwrap();
+            Indexer::new(filter, |_| {})
+                .with_languages(|path| {
+                    let rust = Path::new(path).extension().is_some_and(|e| e == "rs");
+                    if rust { "rust" } else { "plaintext" }.to_string()
+                })
+                .with_cache(cache.clone(), key)
+        };
+
+        let first = indexer("v1");
+        // Nothing to save before the run completes
+        assert!(!first.save_cache().unwrap());
+        first.run(&|| false);
+        assert_eq!(first.reindexed(), 3);
+        assert!(first.save_cache().unwrap());
+        assert!(!first.save_cache().unwrap());
+
+        fs::write(root.join("src/a.rs"), "pub fn a() {}\n").unwrap();
+        fs::remove_file(root.join("notes.txt")).unwrap();
+        write(root, "build.rs", "");
+        let second = indexer("v1");
+        second.run(&|| false);
+        assert_eq!(second.reindexed(), 2);
+        assert_eq!(second.status().file_count, 3);
+        assert!(!second.paths().contains("notes.txt"));
+        assert_eq!(second.file_info("src/lib.rs").unwrap().language, "rust");
+        assert!(second.save_cache().unwrap());
+
+        // Another key reads everything again
+        let third = indexer("v2");
+        third.run(&|| false);
+        assert_eq!(third.reindexed(), 3);
+
+        third.clear_cache().unwrap();
+        assert!(!cache.exists());
+        assert!(!third.save_cache().unwrap());
+        assert!(!cache.exists());
+    }
+
+    #[test]
+    fn test_symbols_follow_files_and_buffers() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "src/lib.rs", "pub fn parse() {}\n");
+        write(root, "app.py", "class Parser:\n    def parse(self): pass\n");
+        write(root, "notes.md", "# parse\n");
+        let filter = PathFilter::new(root, &[], true).unwrap();
+        let indexer = Indexer::new(filter, |_| {}).with_symbols();
+        indexer.run(&|| false);
+        let buffers = Mutex::new(HashMap::new());
+        let find = |pattern: &str| {
+            let query = SymbolQuery {
+                pattern: pattern.to_string(),
+                max_results: 0,
+            };
+            let found = indexer.find_symbols(&query, &buffers.lock(), &AtomicBool::new(false));
+            let found = found.unwrap().into_iter();
+            found
+                .map(|m| format!("{}:{}:{}", m.path, m.symbol.container, m.symbol.name))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            find("parse"),
+            ["app.py:Parser:parse", "src/lib.rs::parse", "app.py::Parser"]
+        );
+
+        fs::write(root.join("src/lib.rs"), "pub fn render() {}\n").unwrap();
+        indexer.apply_events(&[FsEvent {
+            path: root.join("src/lib.rs"),
+            kind: FsEventKind::Modified,
+        }]);
+        assert_eq!(find("render"), ["src/lib.rs::render"]);
+
+        // An open buffer's symbols stand in for its file's
+        let edited = symbols::extract(Language::Rust, "fn draw() {}\n");
+        buffers.lock().insert("src/lib.rs".to_string(), edited);
+        assert!(find("render").is_empty());
+        assert_eq!(find("draw"), ["src/lib.rs::draw"]);
+    }
+
+    #[test]
+    fn test_trigrams_narrow_candidates() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, "src/lib.rs", "pub fn parse_item() {}\n");
+        write(root, "src/main.rs", "fn main() { render(); }\n");
+        write(root, "notes.txt", "Parse items by hand\n");
+        let filter = PathFilter::new(root, &[], true).unwrap();
+        let indexer = Indexer::new(filter, |_| {}).with_trigrams();
+        let trigrams = |pattern: &str| {
+            let matcher = gouide_search::Query::literal(pattern).matcher().unwrap();
+            matcher.trigrams().clone()
+        };
+        let candidates = |pattern: &str| {
+            let mut found = indexer.candidates(&trigrams(pattern)).unwrap();
+            found.sort();
+            found
+        };
+        // Not before every file is read
+        assert!(indexer.candidates(&trigrams("parse")).is_none());
+
+        indexer.run(&|| false);
+        assert_eq!(candidates("parse"), ["notes.txt", "src/lib.rs"]);
+        assert_eq!(candidates("render()"), ["src/main.rs"]);
+        assert!(candidates("lexer").is_empty());
+        // Nothing to narrow by
+        assert_eq!(candidates("fn").len(), 3);
+
+        fs::write(root.join("src/main.rs"), "fn main() { lexer(); }\n").unwrap();
+        indexer.apply_events(&[FsEvent {
+            path: root.join("src/main.rs"),
+            kind: FsEventKind::Modified,
+        }]);
+        assert_eq!(candidates("lexer"), ["src/main.rs"]);
+        assert!(candidates("render").is_empty());
+    }
+
+    #[test]
+    fn test_missing_root_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let filter = PathFilter::new(dir.path().join("gone"), &[], true).unwrap();
+        let indexer = Indexer::new(filter, |_| {});
+        indexer.run(&|| false);
+        let status = indexer.status();
+        assert_eq!(status.state, IndexState::Error);
+        assert!(status.error.unwrap().contains("gone"));
+    }
+}
@@ -0,0 +1,52 @@
+//! Gouide workspace indexes.
+//!
+//! A [`PathIndex`] holds the paths of a workspace's files so quick-open can
+//! fuzzy-match them without touching the disk. It is filled by a scan that
+//! skips what a [`PathFilter`] excludes (`.gitignore` and `.ignore` rules
+//! and the workspace's exclude patterns), then kept current with
+//! [`PathChanges`] worked out from file watcher events under the same rules.
+//!
+//! An [`Indexer`] drives this for one workspace in the background: it scans
+//! for files, then reads each one, reporting its [`IndexStatus`] as it goes
+//! and pausing on request. Reading a source file extracts the symbols it
+//! declares, which are searched by name the same way paths are. Reading
+//! any text file can also record a trigram filter of its contents, which
+//! narrows a text search to the files that may match. What it learns is
+//! kept in a per-workspace cache file, so a later run only reads the files
+//! that changed.
+
+mod cache;
+mod filter;
+mod fuzzy;
+mod indexer;
+mod paths;
+mod symbols;
+
+use thiserror::Error;
+
+// Only the benchmarks use it
+#[cfg(test)]
+use criterion as _;
+
+pub use cache::cache_path;
+pub use filter::PathFilter;
+pub use indexer::{FileInfo, IndexState, IndexStatus, Indexer};
+pub use paths::{FileMatch, FileQuery, PathChanges, PathIndex};
+pub use symbols::{declarations, IndexedSymbol, SymbolMatch, SymbolQuery};
+
+/// Errors that prevent an index from being built.
+#[derive(Error, Debug)]
+pub enum IndexError {
+    /// An exclude glob does not compile.
+    #[error("Invalid glob {glob}: {message}")]
+    InvalidGlob {
+        /// The glob as given.
+        glob: String,
+        /// What is wrong with it.
+        message: String,
+    },
+
+    /// The index cache could not be read or written.
+    #[error("Index cache error: {0}")]
+    Cache(#[from] std::io::Error),
+}
@@ -0,0 +1,562 @@
+//! The paths of a workspace's files, and fuzzy search over them.
+
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use gouide_fs::{FsEvent, FsEventKind};
+use parking_lot::Mutex;
+
+use crate::filter::{relative_path, PathFilter};
+use crate::fuzzy::{char_mask, Pattern};
+
+/// Most recently opened files that rank higher.
+const MAX_RECENT: usize = 50;
+/// Bonus of the most recently opened file; older ones get less.
+const RECENT_BONUS: u32 = 64;
+/// Fewest paths worth a thread of their own.
+const MIN_CHUNK: usize = 20_000;
+/// Most threads one query uses.
+const MAX_THREADS: usize = 8;
+/// Paths matched between checks for cancellation.
+const CANCEL_CHECK: usize = 4096;
+
+/// A fuzzy search of file paths.
+#[derive(Debug, Clone, Default)]
+pub struct FileQuery {
+    /// Words separated by spaces, which must all match in any order. A word
+    /// with an uppercase letter matches case exactly.
+    pub pattern: String,
+    /// Most matches returned, 0 for all.
+    pub max_results: usize,
+    /// Recently opened files, most recent first. They rank above other
+    /// matches of similar score and are the results of an empty pattern.
+    pub recent: Vec<String>,
+}
+
+/// A path matching a [`FileQuery`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMatch {
+    /// Path relative to the workspace root, with `/` separators.
+    pub path: String,
+    /// Higher is better, including any boost for recently opened files.
+    pub score: u32,
+    /// UTF-16 offsets of the matched characters in `path`, ascending.
+    pub positions: Vec<u32>,
+}
+
+/// How the files of a workspace changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathChanges {
+    /// Files or directories that are gone, directories with everything
+    /// under them. Applied before `added`.
+    pub removed: Vec<String>,
+    /// New files. Adding one that is already indexed does nothing.
+    pub added: Vec<String>,
+}
+
+impl PathChanges {
+    /// Work out the changes a batch of watcher events makes, checking new
+    /// paths against `filter` and scanning new directories. This reads the
+    /// disk, so do it before locking the index.
+    pub fn from_events(filter: &PathFilter, events: &[FsEvent]) -> Self {
+        let mut changes = Self::default();
+        for event in events {
+            if let FsEventKind::Renamed { from } = &event.kind {
+                changes.remove(filter, from);
+            }
+            if filter.ignore_file_changed(&event.path) {
+                // Rules changed for everything under the directory
+                if let Some(dir) = event.path.parent() {
+                    changes.remove(filter, dir);
+                    changes.scan(filter, dir);
+                }
+                continue;
+            }
+            match event.kind {
+                FsEventKind::Removed => changes.remove(filter, &event.path),
+                FsEventKind::Created | FsEventKind::Renamed { .. } => {
+                    changes.add(filter, &event.path, true);
+                }
+                // Directories get these when their entries change, which
+                // the entries' own events cover
+                FsEventKind::Modified | FsEventKind::Metadata => {
+                    changes.add(filter, &event.path, false);
+                }
+            }
+        }
+        changes
+    }
+
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
+    }
+
+    fn remove(&mut self, filter: &PathFilter, path: &Path) {
+        if let Some(path) = relative_path(filter.root(), path) {
+            self.removed.push(path);
+        }
+    }
+
+    fn add(&mut self, filter: &PathFilter, path: &Path, scan_dirs: bool) {
+        // Like scans, do not follow links
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            // Gone again by now
+            self.remove(filter, path);
+            return;
+        };
+        if metadata.is_dir() {
+            if scan_dirs && !filter.is_excluded(path, true) {
+                self.scan(filter, path);
+            }
+        } else if metadata.is_file() && !filter.is_excluded(path, false) {
+            if let Some(path) = relative_path(filter.root(), path) {
+                self.added.push(path);
+            }
+        }
+    }
+
+    fn scan(&mut self, filter: &PathFilter, dir: &Path) {
+        let found = Mutex::new(Vec::new());
+        filter.scan(dir, &AtomicBool::new(false), |batch| {
+            found.lock().extend(batch);
+        });
+        self.added.extend(found.into_inner());
+    }
+}
+
+/// The paths of a workspace's files, relative to its root with `/`
+/// separators.
+#[derive(Debug, Default)]
+pub struct PathIndex {
+    paths: Vec<Arc<str>>,
+    /// The `char_mask` of each path.
+    masks: Vec<u64>,
+    slots: HashMap<Arc<str>, usize>,
+}
+
+impl PathIndex {
+    /// An empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of paths.
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Whether there are no paths.
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Whether `path` is indexed.
+    pub fn contains(&self, path: &str) -> bool {
+        self.slots.contains_key(path)
+    }
+
+    /// All paths, in no particular order.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.paths.iter().map(AsRef::as_ref)
+    }
+
+    /// Add a path, returning whether it is new.
+    pub fn insert(&mut self, path: &str) -> bool {
+        if self.slots.contains_key(path) {
+            return false;
+        }
+        let path: Arc<str> = Arc::from(path);
+        self.slots.insert(path.clone(), self.paths.len());
+        self.masks.push(char_mask(path.chars()));
+        self.paths.push(path);
+        true
+    }
+
+    /// Add paths.
+    pub fn extend<I, S>(&mut self, paths: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for path in paths {
+            self.insert(path.as_ref());
+        }
+    }
+
+    /// Remove a path, returning whether it was indexed.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let Some(slot) = self.slots.remove(path) else {
+            return false;
+        };
+        self.paths.swap_remove(slot);
+        self.masks.swap_remove(slot);
+        if let Some(moved) = self.paths.get(slot) {
+            self.slots.insert(moved.clone(), slot);
+        }
+        true
+    }
+
+    /// Apply changes to the files.
+    pub fn apply(&mut self, changes: &PathChanges) {
+        let dirs: HashSet<&str> = changes
+            .removed
+            .iter()
+            .filter(|path| !self.remove(path))
+            .map(String::as_str)
+            .collect();
+        if !dirs.is_empty() {
+            self.remove_dirs(&dirs);
+        }
+        self.extend(&changes.added);
+    }
+
+    /// Remove everything under the directories, `""` being the root.
+    fn remove_dirs(&mut self, dirs: &HashSet<&str>) {
+        if dirs.contains("") {
+            self.paths.clear();
+            self.masks.clear();
+            self.slots.clear();
+            return;
+        }
+        let under = |path: &str| {
+            path.match_indices('/')
+                .any(|(i, _)| dirs.contains(&path[..i]))
+        };
+        let before = self.paths.len();
+        (self.paths, self.masks) = self
+            .paths
+            .drain(..)
+            .zip(self.masks.drain(..))
+            .filter(|(path, _)| !under(path))
+            .unzip();
+        if self.paths.len() != before {
+            self.slots = self
+                .paths
+                .iter()
+                .enumerate()
+                .map(|(slot, path)| (path.clone(), slot))
+                .collect();
+        }
+    }
+
+    /// Paths matching `query`, best first. `None` if `cancel` was set
+    /// before the search finished.
+    pub fn find(&self, query: &FileQuery, cancel: &AtomicBool) -> Option<Vec<FileMatch>> {
+        let limit = match query.max_results {
+            0 => usize::MAX,
+            n => n,
+        };
+        let pattern = Pattern::new(&query.pattern);
+        let recent: Vec<&str> = query
+            .recent
+            .iter()
+            .filter(|path| self.contains(path))
+            .take(MAX_RECENT)
+            .map(String::as_str)
+            .collect();
+        if pattern.is_empty() {
+            let matches = recent.iter().enumerate().take(limit);
+            return Some(
+                matches
+                    .map(|(rank, path)| FileMatch {
+                        path: (*path).to_string(),
+                        score: recent_bonus(rank),
+                        positions: Vec::new(),
+                    })
+                    .collect(),
+            );
+        }
+        let recent: HashMap<&str, u32> = recent
+            .into_iter()
+            .enumerate()
+            .map(|(rank, path)| (path, recent_bonus(rank)))
+            .collect();
+
+        let threads = thread::available_parallelism()
+            .map_or(1, NonZeroUsize::get)
+            .min(MAX_THREADS)
+            .min(self.paths.len() / MIN_CHUNK)
+            .max(1);
+        let chunk = self.paths.len().div_ceil(threads).max(1);
+        let (pattern_ref, recent) = (&pattern, &recent);
+        let mut candidates = if threads == 1 {
+            best(&self.paths, &self.masks, pattern_ref, recent, limit, cancel)?
+        } else {
+            thread::scope(|scope| {
+                let workers: Vec<_> = self
+                    .paths
+                    .chunks(chunk)
+                    .zip(self.masks.chunks(chunk))
+                    .map(|(paths, masks)| {
+                        scope.spawn(move || best(paths, masks, pattern_ref, recent, limit, cancel))
+                    })
+                    .collect();
+                let mut all = Vec::new();
+                for worker in workers {
+                    all.extend(worker.join().ok().flatten()?);
+                }
+                Some(all)
+            })?
+        };
+        candidates.sort_unstable_by(|a, b| b.cmp(a));
+        candidates.truncate(limit);
+
+        let mut chars = Vec::new();
+        let mut positions = Vec::new();
+        let matches = candidates.into_iter().map(|candidate| {
+            chars.clear();
+            chars.extend(candidate.path.chars());
+            positions.clear();
+            pattern.score(&chars, Some(&mut positions));
+            positions.sort_unstable();
+            positions.dedup();
+            FileMatch {
+                path: candidate.path.to_string(),
+                score: candidate.score,
+                positions: utf16_offsets(&chars, &positions),
+            }
+        });
+        Some(matches.collect())
+    }
+}
+
+/// Boost of the recently opened file at `rank`, 0 being the latest.
+fn recent_bonus(rank: usize) -> u32 {
+    let left = u32::try_from(MAX_RECENT.saturating_sub(rank)).unwrap_or(0);
+    RECENT_BONUS * left / u32::try_from(MAX_RECENT).unwrap_or(1)
+}
+
+/// A matching path while searching.
+#[derive(PartialEq, Eq)]
+struct Candidate<'a> {
+    score: u32,
+    path: &'a Arc<str>,
+}
+
+impl Ord for Candidate<'_> {
+    // Greater is better: higher scores, then shorter paths, then by name
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.score
+            .cmp(&other.score)
+            .then_with(|| other.path.len().cmp(&self.path.len()))
+            .then_with(|| other.path.cmp(self.path))
+    }
+}
+
+impl PartialOrd for Candidate<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The `limit` best matches among `paths`, `None` if cancelled.
+fn best<'a>(
+    paths: &'a [Arc<str>],
+    masks: &[u64],
+    pattern: &Pattern,
+    recent: &HashMap<&str, u32>,
+    limit: usize,
+    cancel: &AtomicBool,
+) -> Option<Vec<Candidate<'a>>> {
+    let mut heap = BinaryHeap::new();
+    let mut chars = Vec::new();
+    let wanted = pattern.mask();
+    for (i, (path, mask)) in paths.iter().zip(masks).enumerate() {
+        if i % CANCEL_CHECK == 0 && cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        if mask & wanted != wanted {
+            continue;
+        }
+        let score = if path.is_ascii() {
+            pattern.score(path.as_bytes(), None)
+        } else {
+            chars.clear();
+            chars.extend(path.chars());
+            pattern.score(&chars, None)
+        };
+        let Some(score) = score else {
+            continue;
+        };
+        let score = score + recent.get(path.as_ref()).copied().unwrap_or(0);
+        let candidate = Candidate { score, path };
+        if heap.len() < limit {
+            heap.push(Reverse(candidate));
+        } else if heap.peek().is_some_and(|Reverse(worst)| *worst < candidate) {
+            heap.pop();
+            heap.push(Reverse(candidate));
+        }
+    }
+    Some(
+        heap.into_iter()
+            .map(|Reverse(candidate)| candidate)
+            .collect(),
+    )
+}
+
+/// UTF-16 offsets of the characters at `positions` in `chars`.
+pub(crate) fn utf16_offsets(chars: &[char], positions: &[usize]) -> Vec<u32> {
+    let mut offsets = Vec::with_capacity(positions.len());
+    let mut offset = 0u32;
+    let mut wanted = positions.iter().peekable();
+    for (i, c) in chars.iter().enumerate() {
+        if wanted.peek() == Some(&&i) {
+            offsets.push(offset);
+            wanted.next();
+        }
+        offset += u32::try_from(c.len_utf16()).unwrap_or(1);
+    }
+    offsets
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    fn index(paths: &[&str]) -> PathIndex {
+        let mut index = PathIndex::new();
+        index.extend(paths);
+        index
+    }
+
+    fn find(index: &PathIndex, pattern: &str, recent: &[&str]) -> Vec<String> {
+        let query = FileQuery {
+            pattern: pattern.to_string(),
+            max_results: 10,
+            recent: recent.iter().map(ToString::to_string).collect(),
+        };
+        let found = index.find(&query, &AtomicBool::new(false)).unwrap();
+        found.into_iter().map(|m| m.path).collect()
+    }
+
+    #[test]
+    fn test_find_ranks_and_highlights() {
+        let index = index(&[
+            "src/domain/mod.rs",
+            "src/main.rs",
+            "tests/main_test.rs",
+            "docs/日本/main.md",
+            "README.md",
+        ]);
+        assert_eq!(
+            find(&index, "main", &[]),
+            [
+                "src/main.rs",
+                "tests/main_test.rs",
+                "docs/日本/main.md",
+                "src/domain/mod.rs"
+            ]
+        );
+        assert_eq!(find(&index, "main test", &[]), ["tests/main_test.rs"]);
+
+        // Recently opened files rank higher and are what an empty pattern finds
+        assert_eq!(
+            find(&index, "main", &["tests/main_test.rs"])[0],
+            "tests/main_test.rs"
+        );
+        assert_eq!(
+            find(&index, "", &["README.md", "gone.rs", "src/main.rs"]),
+            ["README.md", "src/main.rs"]
+        );
+
+        let query = FileQuery {
+            pattern: "main".to_string(),
+            max_results: 1,
+            recent: Vec::new(),
+        };
+        let found = index.find(&query, &AtomicBool::new(false)).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].positions, [4, 5, 6, 7]);
+        let query = FileQuery {
+            pattern: "日main".to_string(),
+            ..query
+        };
+        let found = index.find(&query, &AtomicBool::new(false)).unwrap();
+        assert_eq!(found[0].positions, [5, 8, 9, 10, 11]);
+
+        assert!(index.find(&query, &AtomicBool::new(true)).is_none());
+    }
+
+    #[test]
+    fn test_large_index_uses_every_chunk() {
+        let paths: Vec<String> = (0..100_000)
+            .map(|i| format!("dir{}/file{i}.rs", i % 97))
+            .collect();
+        let index = index(&paths.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(find(&index, "file99999", &[]), ["dir89/file99999.rs"]);
+    }
+
+    #[test]
+    fn test_changes_from_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        let filter = PathFilter::new(root, &[], true).unwrap();
+        let index = Mutex::new(PathIndex::new());
+        filter.scan(root, &AtomicBool::new(false), |batch| {
+            index.lock().extend(batch);
+        });
+        let mut index = index.into_inner();
+        assert_eq!(index.len(), 2);
+
+        let event = |path: &str, kind| FsEvent {
+            path: root.join(path),
+            kind,
+        };
+        fs::create_dir_all(root.join("new/deep")).unwrap();
+        fs::write(root.join("new/deep/a.rs"), "").unwrap();
+        fs::write(root.join("debug.log"), "").unwrap();
+        fs::rename(root.join("src"), root.join("lib")).unwrap();
+        let changes = PathChanges::from_events(
+            &filter,
+            &[
+                event("new", FsEventKind::Created),
+                event("debug.log", FsEventKind::Created),
+                event(
+                    "lib",
+                    FsEventKind::Renamed {
+                        from: root.join("src"),
+                    },
+                ),
+            ],
+        );
+        index.apply(&changes);
+        let mut paths: Vec<&str> = index.paths().collect();
+        paths.sort_unstable();
+        assert_eq!(paths, [".gitignore", "lib/lib.rs", "new/deep/a.rs"]);
+
+        // Changed rules rescan their directory
+        fs::write(root.join(".gitignore"), "lib/\n").unwrap();
+        let changes =
+            PathChanges::from_events(&filter, &[event(".gitignore", FsEventKind::Modified)]);
+        index.apply(&changes);
+        let mut paths: Vec<&str> = index.paths().collect();
+        paths.sort_unstable();
+        assert_eq!(paths, [".gitignore", "debug.log", "new/deep/a.rs"]);
+
+        fs::remove_file(root.join("debug.log")).unwrap();
+        let changes =
+            PathChanges::from_events(&filter, &[event("debug.log", FsEventKind::Removed)]);
+        index.apply(&changes);
+        assert!(!index.contains("debug.log"));
+        assert!(index.insert("x.rs"));
+        assert!(!index.insert("x.rs"));
+        assert!(index.remove("x.rs"));
+        assert_eq!(index.len(), 2);
+    }
+}
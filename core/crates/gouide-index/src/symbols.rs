@@ -0,0 +1,320 @@
+//! Symbols declared in a workspace's files, and fuzzy search over them.
+//!
+//! Symbols come from the outlines of the bundled grammars, so they are there
+//! without a language server. Only declarations are kept (modules, types,
+//! functions, constants and the like) at any depth, each with the name of
+//! the symbol it is declared in. Fields, JSON keys and Markdown headings are
+//! left out.
+
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use gouide_syntax::{outline_text, Language, Symbol, SymbolKind};
+use gouide_workspace::TextRange;
+
+use crate::fuzzy::Pattern;
+use crate::paths::utf16_offsets;
+
+/// Largest file whose symbols are extracted, in bytes.
+pub(crate) const MAX_SYMBOL_FILE: u64 = 1024 * 1024;
+
+/// Symbols scored between checks for cancellation.
+const CANCEL_CHECK: usize = 4096;
+
+/// A symbol declared in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedSymbol {
+    /// Symbol name.
+    pub name: String,
+    /// Symbol kind.
+    pub kind: SymbolKind,
+    /// Name of the symbol this one is declared in, empty at the top level.
+    pub container: String,
+    /// The whole declaration.
+    pub range: TextRange,
+    /// The part to select when navigating to the symbol, usually its name.
+    pub selection_range: TextRange,
+}
+
+/// A fuzzy search of symbol names.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolQuery {
+    /// Words separated by spaces, which must all match the name. A word with
+    /// an uppercase letter matches case exactly. An empty pattern matches
+    /// nothing.
+    pub pattern: String,
+    /// Most matches returned, 0 for all.
+    pub max_results: usize,
+}
+
+/// A symbol matching a [`SymbolQuery`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolMatch {
+    /// Path of the file declaring the symbol, relative to the workspace
+    /// root.
+    pub path: String,
+    /// The symbol.
+    pub symbol: IndexedSymbol,
+    /// Higher is better.
+    pub score: u32,
+    /// UTF-16 offsets of the matched characters in the name, ascending.
+    pub positions: Vec<u32>,
+}
+
+/// The declarations of an outline, in document order.
+pub fn declarations(outline: &[Symbol]) -> Vec<IndexedSymbol> {
+    let mut symbols = Vec::new();
+    collect(outline, "", &mut symbols);
+    symbols
+}
+
+fn collect(outline: &[Symbol], container: &str, out: &mut Vec<IndexedSymbol>) {
+    for symbol in outline {
+        if is_declaration(symbol.kind) {
+            out.push(IndexedSymbol {
+                name: symbol.name.clone(),
+                kind: symbol.kind,
+                container: container.to_string(),
+                range: symbol.range,
+                selection_range: symbol.selection_range,
+            });
+        }
+        // Methods of an `impl` block are declared in it, though it is not
+        // a declaration itself
+        collect(&symbol.children, &symbol.name, out);
+    }
+}
+
+/// Declarations in `text`, empty for languages that declare nothing or
+/// text that cannot be parsed.
+pub(crate) fn extract(language: Language, text: &str) -> Vec<IndexedSymbol> {
+    if matches!(
+        language,
+        Language::Json | Language::Toml | Language::Markdown
+    ) {
+        return Vec::new();
+    }
+    outline_text(language, text).map_or_else(|_| Vec::new(), |outline| declarations(&outline))
+}
+
+const fn is_declaration(kind: SymbolKind) -> bool {
+    use SymbolKind as K;
+
+    matches!(
+        kind,
+        K::Module
+            | K::Namespace
+            | K::Class
+            | K::Method
+            | K::Constructor
+            | K::Enum
+            | K::EnumMember
+            | K::Interface
+            | K::Function
+            | K::Variable
+            | K::Constant
+            | K::Struct
+            | K::TypeParameter
+    )
+}
+
+/// Symbols of `files` matching `query`, best first. `None` if `cancel` was
+/// set before the search finished.
+pub(crate) fn find<'a>(
+    files: impl Iterator<Item = (&'a str, &'a [IndexedSymbol])>,
+    query: &SymbolQuery,
+    cancel: &AtomicBool,
+) -> Option<Vec<SymbolMatch>> {
+    let limit = match query.max_results {
+        0 => usize::MAX,
+        n => n,
+    };
+    let pattern = Pattern::new(&query.pattern);
+    if pattern.is_empty() {
+        return Some(Vec::new());
+    }
+
+    let mut heap = BinaryHeap::new();
+    let mut chars = Vec::new();
+    let mut scored = 0;
+    for (path, symbols) in files {
+        for symbol in symbols {
+            scored += 1;
+            if scored % CANCEL_CHECK == 0 && cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            let score = if symbol.name.is_ascii() {
+                pattern.score(symbol.name.as_bytes(), None)
+            } else {
+                chars.clear();
+                chars.extend(symbol.name.chars());
+                pattern.score(&chars, None)
+            };
+            let Some(score) = score else {
+                continue;
+            };
+            let candidate = Candidate {
+                score,
+                path,
+                symbol,
+            };
+            if heap.len() < limit {
+                heap.push(Reverse(candidate));
+            } else if heap.peek().is_some_and(|Reverse(worst)| *worst < candidate) {
+                heap.pop();
+                heap.push(Reverse(candidate));
+            }
+        }
+    }
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let mut candidates: Vec<_> = heap
+        .into_iter()
+        .map(|Reverse(candidate)| candidate)
+        .collect();
+    candidates.sort_unstable_by(|a, b| b.cmp(a));
+    let mut positions = Vec::new();
+    let matches = candidates.into_iter().map(|candidate| {
+        chars.clear();
+        chars.extend(candidate.symbol.name.chars());
+        positions.clear();
+        pattern.score(&chars, Some(&mut positions));
+        positions.sort_unstable();
+        positions.dedup();
+        SymbolMatch {
+            path: candidate.path.to_string(),
+            symbol: candidate.symbol.clone(),
+            score: candidate.score,
+            positions: utf16_offsets(&chars, &positions),
+        }
+    });
+    Some(matches.collect())
+}
+
+/// A matching symbol while searching.
+#[derive(PartialEq, Eq)]
+struct Candidate<'a> {
+    score: u32,
+    path: &'a str,
+    symbol: &'a IndexedSymbol,
+}
+
+impl Ord for Candidate<'_> {
+    // Greater is better: higher scores, then shorter names, then by name,
+    // path and position
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.score
+            .cmp(&other.score)
+            .then_with(|| other.symbol.name.len().cmp(&self.symbol.name.len()))
+            .then_with(|| other.symbol.name.cmp(&self.symbol.name))
+            .then_with(|| other.path.cmp(self.path))
+            .then_with(|| {
+                other
+                    .symbol
+                    .selection_range
+                    .start
+                    .cmp(&self.symbol.selection_range.start)
+            })
+    }
+}
+
+impl PartialOrd for Candidate<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    fn names(symbols: &[IndexedSymbol]) -> Vec<(&str, SymbolKind, &str)> {
+        symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind, s.container.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_keeps_declarations() {
+        let text = "\
+pub struct Parser {
+    input: String,
+}
+
+impl Parser {
+    pub fn parse_item(&self) {}
+}
+
+pub trait Visit {}
+const MAX_DEPTH: usize = 8;
+";
+        let symbols = extract(Language::Rust, text);
+        assert_eq!(
+            names(&symbols),
+            [
+                ("Parser", SymbolKind::Struct, ""),
+                ("parse_item", SymbolKind::Method, "impl Parser"),
+                ("Visit", SymbolKind::Interface, ""),
+                ("MAX_DEPTH", SymbolKind::Constant, ""),
+            ]
+        );
+        assert_eq!(symbols[1].selection_range.start.line, 5);
+
+        let python = extract(Language::Python, "class Shape:\n    def area(self): pass\n");
+        assert_eq!(
+            names(&python),
+            [
+                ("Shape", SymbolKind::Class, ""),
+                ("area", SymbolKind::Method, "Shape"),
+            ]
+        );
+        assert!(extract(Language::Json, "{\"name\": 1}").is_empty());
+        assert!(extract(Language::Markdown, "# Title\n").is_empty());
+    }
+
+    #[test]
+    fn test_find_ranks_matches() {
+        let lib = extract(
+            Language::Rust,
+            "fn parse() {}\nfn parse_item() {}\nfn compare() {}\n",
+        );
+        let util = extract(Language::TypeScript, "export class PathParser {}\n");
+        let files = [("src/lib.rs", lib.as_slice()), ("util.ts", util.as_slice())];
+        let search = |pattern: &str, max_results| {
+            let query = SymbolQuery {
+                pattern: pattern.to_string(),
+                max_results,
+            };
+            find(files.into_iter(), &query, &AtomicBool::new(false)).unwrap()
+        };
+
+        let found = search("parse", 0);
+        let found: Vec<_> = found.iter().map(|m| m.symbol.name.as_str()).collect();
+        assert_eq!(found, ["parse", "parse_item", "PathParser"]);
+        let found = search("pi", 1);
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            (found[0].path.as_str(), found[0].symbol.name.as_str()),
+            ("src/lib.rs", "parse_item")
+        );
+        assert_eq!(found[0].positions, [0, 6]);
+        assert!(search("", 0).is_empty());
+        assert!(search("zzz", 0).is_empty());
+
+        let query = SymbolQuery {
+            pattern: "parse".to_string(),
+            max_results: 0,
+        };
+        assert!(find(files.into_iter(), &query, &AtomicBool::new(true)).is_none());
+    }
+}
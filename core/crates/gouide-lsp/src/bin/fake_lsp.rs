@@ -0,0 +1,358 @@
+//! A scripted language server for tests.
+//!
+//! It keeps the text of open documents and, after every open, change and
+//! save, publishes a warning for each `TODO` in the text (plus an
+//! information diagnostic "saved" after a save), so tests can check what
+//! the server was sent.
+//!
+//! Language requests are answered from the words in the open documents:
+//! hover shows the word at the position, completion offers every word of
+//! the document, the definition of a word is its first occurrence,
+//! references and rename cover every occurrence in every open document,
+//! and signature help always shows `add(a: i32, b: i32)`.
+//!
+//! Options:
+//! - `--sync full|incremental`: the change sync kind it asks for
+//!   (default incremental).
+//! - `--crash-once <path>`: exit with status 1 on the first didOpen if
+//!   `<path>` does not exist, creating it first, so the next start works.
+//! - `--hold-completion`: answer completion requests only when they are
+//!   cancelled, and then publish a diagnostic "cancelled" for the document.
+
+// Shares the library's dependencies but needs only serde_json
+#![allow(unused_crate_dependencies)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+fn main() {
+    let mut sync = 2;
+    let mut crash_once = None;
+    let mut hold_completion = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sync" => {
+                sync = if args.next().as_deref() == Some("full") {
+                    1
+                } else {
+                    2
+                }
+            }
+            "--crash-once" => crash_once = args.next().map(PathBuf::from),
+            "--hold-completion" => hold_completion = true,
+            _ => {}
+        }
+    }
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut documents: BTreeMap<String, String> = BTreeMap::new();
+    // A held completion request and its document
+    let mut held: Option<(Value, String)> = None;
+    while let Some(message) = read(&mut input) {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let version = params["textDocument"]["version"].clone();
+        match method {
+            "initialize" => respond(
+                &message,
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": {
+                            "openClose": true,
+                            "change": sync,
+                            "save": {"includeText": false},
+                        },
+                        "hoverProvider": true,
+                        "completionProvider": {"triggerCharacters": ["."]},
+                        "signatureHelpProvider": {"triggerCharacters": ["(", ","]},
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "renameProvider": true,
+                    },
+                    "serverInfo": {"name": "fake-lsp"},
+                }),
+            ),
+            "textDocument/didOpen" => {
+                if let Some(marker) = &crash_once {
+                    if !marker.exists() {
+                        let _ = std::fs::write(marker, "");
+                        std::process::exit(1);
+                    }
+                }
+                let text = params["textDocument"]["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string();
+                publish(&uri, &version, &text, false);
+                documents.insert(uri, text);
+            }
+            "textDocument/didChange" => {
+                let text = documents.entry(uri.clone()).or_default();
+                for change in params["contentChanges"].as_array().into_iter().flatten() {
+                    let new_text = change["text"].as_str().unwrap_or_default();
+                    if change["range"].is_object() {
+                        let start = offset(text, &change["range"]["start"]);
+                        let end = offset(text, &change["range"]["end"]);
+                        text.replace_range(start..end, new_text);
+                    } else {
+                        *text = new_text.to_string();
+                    }
+                }
+                publish(&uri, &version, text, false);
+            }
+            "textDocument/didSave" => {
+                let text = documents.get(&uri).cloned().unwrap_or_default();
+                publish(&uri, &Value::Null, &text, true);
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                publish(&uri, &Value::Null, "", false);
+            }
+            "textDocument/hover" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                let hover = word_at(text, &params["position"]).map_or(Value::Null, |word| {
+                    json!({
+                        "contents": {"kind": "markdown", "value": format!("`{}`", word.0)},
+                        "range": range(word.1, word.2, word.0.len()),
+                    })
+                });
+                respond(&message, hover);
+            }
+            "textDocument/completion" if hold_completion => {
+                held = Some((message["id"].clone(), uri));
+            }
+            "textDocument/completion" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                let words: BTreeSet<&str> = words(text).map(|word| word.0).collect();
+                let items: Vec<Value> = words
+                    .into_iter()
+                    .map(|word| json!({"label": word, "kind": 6, "detail": "word"}))
+                    .collect();
+                respond(&message, json!({"isIncomplete": false, "items": items}));
+            }
+            "textDocument/signatureHelp" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                let before = &text[..offset(text, &params["position"])];
+                let arguments = &before[before.rfind('(').map_or(before.len(), |i| i + 1)..];
+                respond(
+                    &message,
+                    json!({
+                        "signatures": [{
+                            "label": "add(a: i32, b: i32)",
+                            "parameters": [{"label": [4, 10]}, {"label": "b: i32"}],
+                        }],
+                        "activeSignature": 0,
+                        "activeParameter": arguments.matches(',').count(),
+                    }),
+                );
+            }
+            "textDocument/definition" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                let definition = word_at(text, &params["position"])
+                    .and_then(|word| words(text).find(|other| other.0 == word.0))
+                    .map_or(
+                        Value::Null,
+                        |word| json!({"uri": uri, "range": range(word.1, word.2, word.0.len())}),
+                    );
+                respond(&message, definition);
+            }
+            "textDocument/references" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                let mut locations = Vec::new();
+                if let Some((word, ..)) = word_at(text, &params["position"]) {
+                    for (other, text) in &documents {
+                        for (index, (_, line, column)) in
+                            words(text).filter(|w| w.0 == word).enumerate()
+                        {
+                            let declaration = *other == uri && index == 0;
+                            if declaration && params["context"]["includeDeclaration"] != true {
+                                continue;
+                            }
+                            locations.push(
+                                json!({"uri": other, "range": range(line, column, word.len())}),
+                            );
+                        }
+                    }
+                }
+                respond(&message, Value::Array(locations));
+            }
+            "textDocument/rename" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                let new_name = params["newName"].as_str().unwrap_or_default();
+                let mut changes = serde_json::Map::new();
+                if let Some((word, ..)) = word_at(text, &params["position"]) {
+                    for (other, text) in &documents {
+                        let edits: Vec<Value> = words(text)
+                            .filter(|w| w.0 == word)
+                            .map(|(_, line, column)| {
+                                json!({
+                                    "range": range(line, column, word.len()),
+                                    "newText": new_name,
+                                })
+                            })
+                            .collect();
+                        if !edits.is_empty() {
+                            changes.insert(other.clone(), Value::Array(edits));
+                        }
+                    }
+                }
+                respond(&message, json!({"changes": changes}));
+            }
+            "$/cancelRequest" if held.as_ref().is_some_and(|(id, _)| *id == params["id"]) => {
+                let (id, uri) = held.take().unwrap_or_default();
+                send(&json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {"code": -32800, "message": "cancelled"},
+                }));
+                send(&json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": {"uri": uri, "diagnostics": [{
+                        "range": range(0, 0, 0),
+                        "message": "cancelled",
+                    }]},
+                }));
+            }
+            "shutdown" => respond(&message, Value::Null),
+            "exit" => std::process::exit(0),
+            _ if message.get("id").is_some() => send(&json!({
+                "jsonrpc": "2.0",
+                "id": message["id"],
+                "error": {"code": -32601, "message": format!("Unhandled method: {method}")},
+            })),
+            _ => {}
+        }
+    }
+}
+
+fn read(input: &mut impl BufRead) -> Option<Value> {
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok()?;
+        }
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn send(message: &Value) {
+    let body = message.to_string();
+    let mut out = io::stdout().lock();
+    let _ = write!(out, "Content-Length: {}\r\n\r\n{body}", body.len());
+    let _ = out.flush();
+}
+
+fn respond(request: &Value, result: Value) {
+    let mut response = json!({"jsonrpc": "2.0", "id": request["id"]});
+    response["result"] = result;
+    send(&response);
+}
+
+/// Byte offset of an LSP position (UTF-16 columns).
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0);
+    let character = position["character"].as_u64().unwrap_or(0);
+    let mut start = 0;
+    for _ in 0..line {
+        match text[start..].find('\n') {
+            Some(i) => start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + i;
+        }
+        units += c.len_utf16() as u64;
+    }
+    text.len()
+}
+
+/// Words (identifiers) of a text with their line and column.
+fn words(text: &str) -> impl Iterator<Item = (&str, usize, usize)> {
+    text.lines().enumerate().flat_map(|(line, content)| {
+        content
+            .split(|c: char| !is_word(c))
+            .filter(|word| !word.is_empty())
+            .map(move |word| {
+                // Offset of the slice within the line
+                let column = word.as_ptr() as usize - content.as_ptr() as usize;
+                (word, line, column)
+            })
+    })
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The word touching a position, with its line and column.
+fn word_at(text: &str, position: &Value) -> Option<(String, usize, usize)> {
+    let line = usize::try_from(position["line"].as_u64()?).ok()?;
+    let character = usize::try_from(position["character"].as_u64()?).ok()?;
+    words(text)
+        .find(|&(word, l, column)| l == line && (column..=column + word.len()).contains(&character))
+        .map(|(word, line, column)| (word.to_string(), line, column))
+}
+
+fn range(line: usize, column: usize, length: usize) -> Value {
+    json!({
+        "start": {"line": line, "character": column},
+        "end": {"line": line, "character": column + length},
+    })
+}
+
+fn publish(uri: &str, version: &Value, text: &str, saved: bool) {
+    let mut diagnostics = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        for (column, _) in content.match_indices("TODO") {
+            diagnostics.push(json!({
+                "range": {
+                    "start": {"line": line, "character": column},
+                    "end": {"line": line, "character": column + 4},
+                },
+                "severity": 2,
+                "code": "todo",
+                "source": "fake-lsp",
+                "message": "TODO",
+            }));
+        }
+    }
+    if saved {
+        diagnostics.push(json!({
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 0}},
+            "severity": 3,
+            "message": "saved",
+        }));
+    }
+    let mut params = json!({"uri": uri, "diagnostics": diagnostics});
+    if !version.is_null() {
+        params["version"] = version.clone();
+    }
+    send(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": params,
+    }));
+}
@@ -0,0 +1,101 @@
+//! Language server configuration.
+
+use std::time::Duration;
+
+use serde_json::Value;
+
+/// How to run a language server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Name, unique among configured servers (e.g. "rust-analyzer").
+    pub name: String,
+    /// Program to run, looked up on `PATH` if not absolute.
+    pub command: String,
+    /// Arguments.
+    pub args: Vec<String>,
+    /// Language IDs the server handles.
+    pub languages: Vec<String>,
+    /// `initializationOptions` sent with the initialize request.
+    pub initialization_options: Option<Value>,
+}
+
+impl ServerConfig {
+    /// A server with no arguments or initialization options.
+    pub fn new(
+        name: impl Into<String>,
+        command: impl Into<String>,
+        languages: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            command: command.into(),
+            args: Vec::new(),
+            languages: languages.into_iter().map(Into::into).collect(),
+            initialization_options: None,
+        }
+    }
+
+    /// Whether the server handles a language.
+    pub fn handles(&self, language_id: &str) -> bool {
+        self.languages.iter().any(|l| l == language_id)
+    }
+}
+
+/// When to restart a server that exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Delay before the first restart.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay, which doubles with each restart.
+    pub max_backoff: Duration,
+    /// Consecutive restarts before giving up.
+    pub max_restarts: u32,
+    /// A server that stays up this long has its restart count reset.
+    pub stable_after: Duration,
+    /// How long to wait for the initialize and shutdown requests.
+    pub request_timeout: Duration,
+}
+
+impl RestartPolicy {
+    /// Delay before restart number `attempt` (1-based).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_restarts: 5,
+            stable_after: Duration::from_secs(60),
+            request_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let policy = RestartPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            ..RestartPolicy::default()
+        };
+        let delays: Vec<u128> = (1..=5).map(|n| policy.backoff(n).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+    }
+}
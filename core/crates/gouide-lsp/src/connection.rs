@@ -0,0 +1,252 @@
+//! A running server process and the JSON-RPC plumbing around it.
+//!
+//! Messages are written by a writer task fed from an unbounded channel, so
+//! sending never blocks the caller. A reader task matches responses to
+//! pending requests, answers the few requests servers send to clients, and
+//! hands notifications to the supervisor. When the server's stdout closes
+//! the reader fails every pending request and closes the notification
+//! channel, which is how the supervisor learns the server exited.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, trace, warn};
+
+use crate::config::ServerConfig;
+use crate::transport::{read_message, write_message};
+use crate::LspError;
+
+/// Where a response is delivered.
+pub(crate) type Reply = oneshot::Sender<Result<Value, LspError>>;
+
+type Pending = Arc<Mutex<HashMap<i64, Reply>>>;
+
+/// JSON-RPC "method not found".
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// A notification sent by the server.
+#[derive(Debug)]
+pub(crate) struct Notification {
+    pub(crate) method: String,
+    pub(crate) params: Value,
+}
+
+/// Connection to a server process.
+pub(crate) struct Connection {
+    name: String,
+    outgoing: mpsc::UnboundedSender<Value>,
+    pending: Pending,
+}
+
+impl Connection {
+    /// Start the server in `root`. Returns the connection, its
+    /// notifications and the process.
+    pub(crate) fn spawn(
+        config: &ServerConfig,
+        root: &Path,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<Notification>, Child), LspError> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|source| LspError::Spawn {
+                command: config.command.clone(),
+                source,
+            })?;
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(LspError::Protocol("Server stdio not captured".into()));
+        };
+
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let (notifications, notifications_rx) = mpsc::unbounded_channel();
+        let pending = Pending::default();
+
+        tokio::spawn(write_loop(config.name.clone(), stdin, outgoing_rx));
+        tokio::spawn(read_loop(
+            config.name.clone(),
+            BufReader::new(stdout),
+            outgoing.clone(),
+            notifications,
+            Arc::clone(&pending),
+        ));
+        let name = config.name.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                debug!(server = %name, "{line}");
+            }
+        });
+
+        Ok((
+            Self {
+                name: config.name.clone(),
+                outgoing,
+                pending,
+            },
+            notifications_rx,
+            child,
+        ))
+    }
+
+    /// Send a request whose response goes to `reply`.
+    pub(crate) fn send_request(&self, id: i64, method: &str, params: Value, reply: Reply) {
+        self.pending.lock().insert(id, reply);
+        let mut message = json!({"jsonrpc": "2.0", "id": id, "method": method});
+        message["params"] = params;
+        if self.outgoing.send(message).is_err() {
+            let reply = self.pending.lock().remove(&id);
+            if let Some(reply) = reply {
+                let _ = reply.send(Err(LspError::NotRunning(self.name.clone())));
+            }
+        }
+    }
+
+    /// Send a request and wait for its response.
+    pub(crate) async fn request(
+        &self,
+        id: i64,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> Result<Value, LspError> {
+        let (reply, response) = oneshot::channel();
+        self.send_request(id, method, params, reply);
+        match tokio::time::timeout(timeout, response).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(LspError::NotRunning(self.name.clone())),
+            Err(_) => {
+                self.pending.lock().remove(&id);
+                Err(LspError::Timeout(method.to_string()))
+            }
+        }
+    }
+
+    /// Stop waiting for a request and ask the server to cancel it.
+    pub(crate) fn cancel(&self, id: i64) {
+        if self.pending.lock().remove(&id).is_some() {
+            self.notify("$/cancelRequest", json!({"id": id}));
+        }
+    }
+
+    /// Send a notification.
+    pub(crate) fn notify(&self, method: &str, params: Value) {
+        // A closed channel means the server is gone, which the supervisor
+        // learns from the notification channel
+        let mut message = json!({"jsonrpc": "2.0", "method": method});
+        message["params"] = params;
+        let _ = self.outgoing.send(message);
+    }
+}
+
+async fn write_loop(
+    name: String,
+    mut stdin: ChildStdin,
+    mut outgoing: mpsc::UnboundedReceiver<Value>,
+) {
+    while let Some(message) = outgoing.recv().await {
+        trace!(server = %name, message = %message, "Sending");
+        if let Err(e) = write_message(&mut stdin, &message).await {
+            debug!(server = %name, error = %e, "Failed to write to server");
+            break;
+        }
+    }
+}
+
+async fn read_loop(
+    name: String,
+    mut stdout: BufReader<tokio::process::ChildStdout>,
+    outgoing: mpsc::UnboundedSender<Value>,
+    notifications: mpsc::UnboundedSender<Notification>,
+    pending: Pending,
+) {
+    loop {
+        let mut message = match read_message(&mut stdout).await {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                warn!(server = %name, error = %e, "Failed to read from server");
+                break;
+            }
+        };
+        trace!(server = %name, message = %message, "Received");
+        let id = message.get("id").cloned();
+        let method = message
+            .get("method")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let params = message.get_mut("params").map_or(Value::Null, Value::take);
+        match (id, method) {
+            (Some(id), Some(method)) => {
+                let _ = outgoing.send(answer(&id, &method, &params));
+            }
+            (None, Some(method)) => {
+                let _ = notifications.send(Notification { method, params });
+            }
+            (Some(id), None) => {
+                let Some(reply) = id.as_i64().and_then(|id| pending.lock().remove(&id)) else {
+                    continue;
+                };
+                let _ = reply.send(response_result(message));
+            }
+            (None, None) => debug!(server = %name, "Ignoring message without id or method"),
+        }
+    }
+    debug!(server = %name, "Server output closed");
+    let failed: Vec<Reply> = pending.lock().drain().map(|(_, reply)| reply).collect();
+    for reply in failed {
+        let _ = reply.send(Err(LspError::NotRunning(name.clone())));
+    }
+}
+
+fn response_result(mut message: Value) -> Result<Value, LspError> {
+    if let Some(error) = message.get("error") {
+        return Err(LspError::Server {
+            code: error.get("code").and_then(Value::as_i64).unwrap_or(0),
+            message: error
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        });
+    }
+    Ok(message.get_mut("result").map_or(Value::Null, Value::take))
+}
+
+/// Response to a request from the server. Configuration is answered with
+/// nulls ("no settings"); registrations and progress tokens are accepted
+/// and ignored.
+fn answer(id: &Value, method: &str, params: &Value) -> Value {
+    match method {
+        "workspace/configuration" => {
+            let items = params
+                .get("items")
+                .and_then(Value::as_array)
+                .map_or(0, Vec::len);
+            json!({"jsonrpc": "2.0", "id": id, "result": vec![Value::Null; items]})
+        }
+        "client/registerCapability"
+        | "client/unregisterCapability"
+        | "window/workDoneProgress/create" => {
+            json!({"jsonrpc": "2.0", "id": id, "result": null})
+        }
+        _ => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": METHOD_NOT_FOUND, "message": format!("Unsupported method: {method}")},
+        }),
+    }
+}
@@ -0,0 +1,72 @@
+//! Gouide language server client.
+//!
+//! The daemon runs one stdio language server per workspace and configured
+//! server. Each server is owned by a supervisor task that performs the
+//! initialize handshake, keeps the server's view of open documents in sync,
+//! forwards the notifications it publishes, and restarts it with backoff
+//! when it exits unexpectedly.
+//!
+//! The daemon drives everything through [`LspManager`]: it reports buffers
+//! opening, changing, saving and closing, and reads [`LspEvent`]s from the
+//! channel returned by [`LspManager::new`].
+
+mod config;
+mod connection;
+mod manager;
+mod server;
+mod transport;
+
+use thiserror::Error;
+
+// Used by the integration tests only
+#[cfg(test)]
+use tempfile as _;
+
+pub use config::{RestartPolicy, ServerConfig};
+pub use lsp_types;
+pub use manager::{DocumentSource, LspEvent, LspManager, TextDocument};
+pub use server::{LanguageServer, ServerStatus};
+
+/// Errors that can occur while talking to a language server.
+#[derive(Error, Debug)]
+pub enum LspError {
+    /// The server process could not be started.
+    #[error("Failed to start {command}: {source}")]
+    Spawn {
+        /// Command that failed.
+        command: String,
+        /// Underlying error.
+        source: std::io::Error,
+    },
+
+    /// A message could not be read or written.
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// A message was not valid JSON or not the expected shape.
+    #[error("Invalid message: {0}")]
+    Protocol(String),
+
+    /// The server answered a request with an error.
+    #[error("Server error {code}: {message}")]
+    Server {
+        /// JSON-RPC error code.
+        code: i64,
+        /// Error message.
+        message: String,
+    },
+
+    /// The server did not answer in time.
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+
+    /// The server is not running (starting, restarting or given up).
+    #[error("Language server not running: {0}")]
+    NotRunning(String),
+}
+
+impl From<serde_json::Error> for LspError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Protocol(error.to_string())
+    }
+}
@@ -0,0 +1,234 @@
+//! Language servers for every open workspace.
+//!
+//! Servers start lazily, when the first document of one of their languages
+//! opens in a workspace, and run until the workspace closes. A document is
+//! routed to every configured server that handles its language.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use lsp_types::{PublishDiagnosticsParams, TextDocumentContentChangeEvent, Url};
+use parking_lot::Mutex;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::config::{RestartPolicy, ServerConfig};
+use crate::server::{Command, LanguageServer, Supervisor};
+
+/// An open document as a server should see it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextDocument {
+    /// Document URI.
+    pub uri: Url,
+    /// Language ID.
+    pub language_id: String,
+    /// Version, increasing with every change.
+    pub version: i32,
+    /// Full text.
+    pub text: String,
+}
+
+/// Current contents of open documents, read when a server (re)starts or
+/// needs a full copy of a document.
+pub trait DocumentSource: Send + Sync {
+    /// The document's current state, `None` if it is no longer open.
+    fn document(&self, uri: &Url) -> Option<TextDocument>;
+}
+
+/// Something a language server did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LspEvent {
+    /// A server finished initializing.
+    Started {
+        /// Workspace ID.
+        workspace_id: String,
+        /// Server name.
+        server: String,
+    },
+    /// A server published diagnostics.
+    Diagnostics {
+        /// Workspace ID.
+        workspace_id: String,
+        /// Server name.
+        server: String,
+        /// The published set, replacing the server's previous set for the
+        /// document.
+        params: PublishDiagnosticsParams,
+    },
+    /// A server exited or was shut down. Anything it published is stale.
+    Stopped {
+        /// Workspace ID.
+        workspace_id: String,
+        /// Server name.
+        server: String,
+    },
+}
+
+struct Supervised {
+    server: LanguageServer,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Servers by workspace ID and server name.
+    servers: HashMap<(String, String), Supervised>,
+    /// Servers each open document was sent to.
+    documents: HashMap<Url, Vec<LanguageServer>>,
+}
+
+/// Starts, routes documents to, and stops language servers.
+pub struct LspManager {
+    configs: Vec<ServerConfig>,
+    policy: RestartPolicy,
+    source: Arc<dyn DocumentSource>,
+    events: mpsc::UnboundedSender<LspEvent>,
+    runtime: Handle,
+    state: Mutex<State>,
+}
+
+impl LspManager {
+    /// Create a manager for the configured servers. Must be called within
+    /// a Tokio runtime, where the supervisors will run.
+    pub fn new(
+        configs: Vec<ServerConfig>,
+        policy: RestartPolicy,
+        source: Arc<dyn DocumentSource>,
+    ) -> (Self, mpsc::UnboundedReceiver<LspEvent>) {
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let manager = Self {
+            configs,
+            policy,
+            source,
+            events,
+            runtime: Handle::current(),
+            state: Mutex::new(State::default()),
+        };
+        (manager, events_rx)
+    }
+
+    /// Whether any configured server handles a language.
+    pub fn handles(&self, language_id: &str) -> bool {
+        self.configs.iter().any(|c| c.handles(language_id))
+    }
+
+    /// A document was opened in the workspace rooted at `root`. Starts the
+    /// workspace's servers for its language if they are not running.
+    pub fn did_open(&self, workspace_id: &str, root: &Path, document: TextDocument) {
+        let mut state = self.state.lock();
+        if state.documents.contains_key(&document.uri) {
+            return;
+        }
+        let mut servers = Vec::new();
+        for config in self
+            .configs
+            .iter()
+            .filter(|c| c.handles(&document.language_id))
+        {
+            let key = (workspace_id.to_string(), config.name.clone());
+            let supervised = state.servers.entry(key).or_insert_with(|| {
+                let (supervisor, server) = Supervisor::new(
+                    config.clone(),
+                    root.to_path_buf(),
+                    workspace_id.to_string(),
+                    self.policy,
+                    Arc::clone(&self.source),
+                    self.events.clone(),
+                );
+                let task = self.runtime.spawn(supervisor.run());
+                Supervised { server, task }
+            });
+            supervised.server.send(Command::Open(document.clone()));
+            servers.push(supervised.server.clone());
+        }
+        if !servers.is_empty() {
+            state.documents.insert(document.uri, servers);
+        }
+    }
+
+    /// An open document changed. `changes` apply in order, each to the
+    /// text left by the previous one.
+    pub fn did_change(&self, uri: &Url, version: i32, changes: &[TextDocumentContentChangeEvent]) {
+        self.send(uri, || Command::Change {
+            uri: uri.clone(),
+            version,
+            changes: changes.to_vec(),
+        });
+    }
+
+    /// An open document was saved.
+    pub fn did_save(&self, uri: &Url) {
+        self.send(uri, || Command::Save(uri.clone()));
+    }
+
+    /// A document was closed.
+    pub fn did_close(&self, uri: &Url) {
+        let servers = self.state.lock().documents.remove(uri);
+        for server in servers.into_iter().flatten() {
+            server.send(Command::Close(uri.clone()));
+        }
+    }
+
+    fn send(&self, uri: &Url, command: impl Fn() -> Command) {
+        let state = self.state.lock();
+        for server in state.documents.get(uri).into_iter().flatten() {
+            server.send(command());
+        }
+    }
+
+    /// Servers a document was sent to.
+    pub fn servers_for(&self, uri: &Url) -> Vec<LanguageServer> {
+        self.state
+            .lock()
+            .documents
+            .get(uri)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// A workspace's server by name, if it has been started.
+    pub fn server(&self, workspace_id: &str, name: &str) -> Option<LanguageServer> {
+        self.state
+            .lock()
+            .servers
+            .get(&(workspace_id.to_string(), name.to_string()))
+            .map(|supervised| supervised.server.clone())
+    }
+
+    /// Shut down a closed workspace's servers.
+    pub fn stop_workspace(&self, workspace_id: &str) {
+        let mut state = self.state.lock();
+        state.servers.retain(|(workspace, _), supervised| {
+            if workspace != workspace_id {
+                return true;
+            }
+            supervised.server.send(Command::Shutdown);
+            false
+        });
+        state.documents.retain(|_, servers| {
+            servers.retain(|server| server.workspace_id() != workspace_id);
+            !servers.is_empty()
+        });
+    }
+
+    /// Shut down every server and wait for them to exit.
+    pub async fn shutdown(&self) {
+        let servers: Vec<Supervised> = {
+            let mut state = self.state.lock();
+            state.documents.clear();
+            state
+                .servers
+                .drain()
+                .map(|(_, supervised)| supervised)
+                .collect()
+        };
+        for supervised in &servers {
+            supervised.server.send(Command::Shutdown);
+        }
+        for supervised in servers {
+            let _ = supervised.task.await;
+        }
+    }
+}
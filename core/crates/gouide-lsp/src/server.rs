@@ -0,0 +1,741 @@
+//! One supervised language server.
+//!
+//! Everything sent to a server goes through its supervisor's command
+//! channel, requests included, so a request made right after an edit is
+//! never seen by the server before that edit. The supervisor remembers
+//! which documents are open and at which version; after a restart it
+//! replays them from the [`DocumentSource`], and changes at or below the
+//! version the server already has are dropped.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument, Exit,
+    Initialized, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Initialize, Request, Shutdown};
+use lsp_types::{
+    ClientCapabilities, ClientInfo, CodeActionCapabilityResolveSupport,
+    CodeActionClientCapabilities, CodeActionKind, CodeActionKindLiteralSupport,
+    CodeActionLiteralSupport, CompletionClientCapabilities, CompletionItemCapability,
+    CompletionItemTag, DiagnosticTag, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentFormattingClientCapabilities,
+    DocumentRangeFormattingClientCapabilities, FailureHandlingKind, GotoCapability,
+    HoverClientCapabilities, InitializeParams, InitializeResult, InitializedParams, MarkupKind,
+    ParameterInformationSettings, PublishDiagnosticsClientCapabilities,
+    ReferenceClientCapabilities, RenameClientCapabilities, ResourceOperationKind,
+    ServerCapabilities, SignatureHelpClientCapabilities, SignatureInformationSettings, TagSupport,
+    TextDocumentClientCapabilities, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+    TextDocumentItem, TextDocumentSyncCapability, TextDocumentSyncClientCapabilities,
+    TextDocumentSyncKind, TextDocumentSyncSaveOptions, Url, VersionedTextDocumentIdentifier,
+    WorkspaceClientCapabilities, WorkspaceEditClientCapabilities, WorkspaceFolder,
+};
+use parking_lot::RwLock;
+use serde_json::Value;
+use tokio::process::Child;
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{debug, info, warn};
+
+use crate::config::{RestartPolicy, ServerConfig};
+use crate::connection::{Connection, Notification, Reply};
+use crate::manager::{DocumentSource, LspEvent, TextDocument};
+use crate::LspError;
+
+/// Lifecycle state of a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerStatus {
+    /// Starting for the first time.
+    Starting,
+    /// Initialized and accepting requests.
+    Running,
+    /// Exited unexpectedly; waiting to start again.
+    Restarting {
+        /// Consecutive restart number, from 1.
+        attempt: u32,
+    },
+    /// Exited too many times in a row; no longer restarted.
+    Failed,
+    /// Shut down.
+    Stopped,
+}
+
+/// Work for a supervisor.
+pub(crate) enum Command {
+    Open(TextDocument),
+    Change {
+        uri: Url,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    },
+    Save(Url),
+    Close(Url),
+    Request {
+        id: i64,
+        method: &'static str,
+        params: Value,
+        reply: Reply,
+    },
+    Cancel(i64),
+    Shutdown,
+}
+
+/// Handle to a supervised language server.
+#[derive(Clone)]
+pub struct LanguageServer {
+    name: String,
+    workspace_id: String,
+    commands: mpsc::UnboundedSender<Command>,
+    next_id: Arc<AtomicI64>,
+    status: watch::Receiver<ServerStatus>,
+    capabilities: Arc<RwLock<Option<ServerCapabilities>>>,
+}
+
+impl LanguageServer {
+    /// Configured server name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Workspace the server runs for.
+    pub fn workspace_id(&self) -> &str {
+        &self.workspace_id
+    }
+
+    /// Current lifecycle state.
+    pub fn status(&self) -> ServerStatus {
+        *self.status.borrow()
+    }
+
+    /// Capabilities the running server announced, `None` while it is not
+    /// running.
+    pub fn capabilities(&self) -> Option<ServerCapabilities> {
+        self.capabilities.read().clone()
+    }
+
+    /// Send a request. Dropping the returned future cancels it.
+    pub async fn request<R: Request>(&self, params: R::Params) -> Result<R::Result, LspError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply, response) = oneshot::channel();
+        let command = Command::Request {
+            id,
+            method: R::METHOD,
+            params: serde_json::to_value(params)?,
+            reply,
+        };
+        if self.commands.send(command).is_err() {
+            return Err(LspError::NotRunning(self.name.clone()));
+        }
+        let guard = CancelOnDrop {
+            id,
+            commands: &self.commands,
+            done: false,
+        };
+        let result = response.await;
+        guard.finish();
+        let value = result.map_err(|_| LspError::NotRunning(self.name.clone()))??;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub(crate) fn send(&self, command: Command) {
+        // A closed channel means the supervisor is gone
+        let _ = self.commands.send(command);
+    }
+}
+
+/// Cancels a request whose caller stopped waiting.
+struct CancelOnDrop<'a> {
+    id: i64,
+    commands: &'a mpsc::UnboundedSender<Command>,
+    done: bool,
+}
+
+impl CancelOnDrop<'_> {
+    fn finish(mut self) {
+        self.done = true;
+    }
+}
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.commands.send(Command::Cancel(self.id));
+        }
+    }
+}
+
+/// What the server knows about an open document.
+struct DocumentState {
+    version: i32,
+}
+
+/// How a running server wants documents synced.
+#[derive(Clone, Copy)]
+struct SyncOptions {
+    change: TextDocumentSyncKind,
+    save: bool,
+    save_text: bool,
+}
+
+impl SyncOptions {
+    fn from_capabilities(capabilities: &ServerCapabilities) -> Self {
+        match &capabilities.text_document_sync {
+            Some(TextDocumentSyncCapability::Kind(kind)) => Self {
+                change: *kind,
+                save: false,
+                save_text: false,
+            },
+            Some(TextDocumentSyncCapability::Options(options)) => {
+                let (save, save_text) = match &options.save {
+                    Some(TextDocumentSyncSaveOptions::Supported(save)) => (*save, false),
+                    Some(TextDocumentSyncSaveOptions::SaveOptions(save)) => {
+                        (true, save.include_text.unwrap_or(false))
+                    }
+                    None => (false, false),
+                };
+                Self {
+                    change: options.change.unwrap_or(TextDocumentSyncKind::NONE),
+                    save,
+                    save_text,
+                }
+            }
+            None => Self {
+                change: TextDocumentSyncKind::NONE,
+                save: false,
+                save_text: false,
+            },
+        }
+    }
+}
+
+/// A started server.
+struct Running {
+    connection: Connection,
+    notifications: mpsc::UnboundedReceiver<Notification>,
+    child: Child,
+    sync: SyncOptions,
+}
+
+/// How a running server stopped.
+enum Ended {
+    /// Shut down on request.
+    Shutdown,
+    /// Exited by itself.
+    Crashed,
+}
+
+/// Owner of one server process and its restarts.
+pub(crate) struct Supervisor {
+    config: ServerConfig,
+    root: PathBuf,
+    workspace_id: String,
+    policy: RestartPolicy,
+    source: Arc<dyn DocumentSource>,
+    events: mpsc::UnboundedSender<LspEvent>,
+    commands: mpsc::UnboundedReceiver<Command>,
+    next_id: Arc<AtomicI64>,
+    status: watch::Sender<ServerStatus>,
+    capabilities: Arc<RwLock<Option<ServerCapabilities>>>,
+    documents: HashMap<Url, DocumentState>,
+}
+
+impl Supervisor {
+    /// Create a supervisor and the handle that drives it.
+    pub(crate) fn new(
+        config: ServerConfig,
+        root: PathBuf,
+        workspace_id: String,
+        policy: RestartPolicy,
+        source: Arc<dyn DocumentSource>,
+        events: mpsc::UnboundedSender<LspEvent>,
+    ) -> (Self, LanguageServer) {
+        let (commands_tx, commands) = mpsc::unbounded_channel();
+        let (status, status_rx) = watch::channel(ServerStatus::Starting);
+        let next_id = Arc::new(AtomicI64::new(1));
+        let capabilities = Arc::new(RwLock::new(None));
+        let handle = LanguageServer {
+            name: config.name.clone(),
+            workspace_id: workspace_id.clone(),
+            commands: commands_tx,
+            next_id: Arc::clone(&next_id),
+            status: status_rx,
+            capabilities: Arc::clone(&capabilities),
+        };
+        let supervisor = Self {
+            config,
+            root,
+            workspace_id,
+            policy,
+            source,
+            events,
+            commands,
+            next_id,
+            status,
+            capabilities,
+            documents: HashMap::new(),
+        };
+        (supervisor, handle)
+    }
+
+    /// Run the server until it is shut down or given up on.
+    pub(crate) async fn run(mut self) {
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            match self.start().await {
+                Ok(running) => {
+                    self.status.send_replace(ServerStatus::Running);
+                    self.emit_started();
+                    let exit = self.serve(running).await;
+                    *self.capabilities.write() = None;
+                    self.emit_stopped();
+                    if matches!(exit, Ended::Shutdown) {
+                        break;
+                    }
+                    if started.elapsed() >= self.policy.stable_after {
+                        attempt = 0;
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        server = %self.config.name,
+                        workspace_id = %self.workspace_id,
+                        error = %e,
+                        "Language server failed to start"
+                    );
+                }
+            }
+
+            attempt += 1;
+            if attempt > self.policy.max_restarts {
+                warn!(
+                    server = %self.config.name,
+                    workspace_id = %self.workspace_id,
+                    "Language server keeps exiting, giving up"
+                );
+                self.status.send_replace(ServerStatus::Failed);
+                self.idle(None).await;
+                break;
+            }
+            self.status
+                .send_replace(ServerStatus::Restarting { attempt });
+            if !self.idle(Some(self.policy.backoff(attempt))).await {
+                break;
+            }
+        }
+        self.status.send_replace(ServerStatus::Stopped);
+    }
+
+    fn emit_started(&self) {
+        let _ = self.events.send(LspEvent::Started {
+            workspace_id: self.workspace_id.clone(),
+            server: self.config.name.clone(),
+        });
+    }
+
+    fn emit_stopped(&self) {
+        let _ = self.events.send(LspEvent::Stopped {
+            workspace_id: self.workspace_id.clone(),
+            server: self.config.name.clone(),
+        });
+    }
+
+    fn next_id(&self) -> i64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Spawn and initialize the server, then open every tracked document.
+    async fn start(&mut self) -> Result<Running, LspError> {
+        info!(
+            server = %self.config.name,
+            workspace_id = %self.workspace_id,
+            command = %self.config.command,
+            "Starting language server"
+        );
+        let (connection, notifications, child) = Connection::spawn(&self.config, &self.root)?;
+
+        let root_uri = Url::from_directory_path(&self.root).ok();
+        #[allow(deprecated)] // root_uri is still read by many servers
+        let params = InitializeParams {
+            process_id: Some(std::process::id()),
+            root_uri: root_uri.clone(),
+            initialization_options: self.config.initialization_options.clone(),
+            capabilities: client_capabilities(),
+            workspace_folders: root_uri.map(|uri| {
+                vec![WorkspaceFolder {
+                    uri,
+                    name: self.workspace_id.clone(),
+                }]
+            }),
+            client_info: Some(ClientInfo {
+                name: "gouide".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+            ..InitializeParams::default()
+        };
+        let result = connection
+            .request(
+                self.next_id(),
+                Initialize::METHOD,
+                serde_json::to_value(params)?,
+                self.policy.request_timeout,
+            )
+            .await?;
+        let result: InitializeResult = serde_json::from_value(result)?;
+        connection.notify(
+            Initialized::METHOD,
+            serde_json::to_value(InitializedParams {})?,
+        );
+
+        let sync = SyncOptions::from_capabilities(&result.capabilities);
+        *self.capabilities.write() = Some(result.capabilities);
+
+        let uris: Vec<Url> = self.documents.keys().cloned().collect();
+        for uri in uris {
+            match self.source.document(&uri) {
+                Some(document) => {
+                    if let Some(state) = self.documents.get_mut(&uri) {
+                        state.version = document.version;
+                    }
+                    send_open(&connection, document);
+                }
+                None => {
+                    self.documents.remove(&uri);
+                }
+            }
+        }
+
+        Ok(Running {
+            connection,
+            notifications,
+            child,
+            sync,
+        })
+    }
+
+    /// Serve commands and notifications until the server exits.
+    async fn serve(&mut self, mut running: Running) -> Ended {
+        loop {
+            tokio::select! {
+                command = self.commands.recv() => {
+                    match command {
+                        Some(Command::Shutdown) | None => {
+                            self.stop(running).await;
+                            return Ended::Shutdown;
+                        }
+                        Some(command) => self.handle(&running, command),
+                    }
+                }
+                notification = running.notifications.recv() => {
+                    let Some(notification) = notification else {
+                        let status = running.child.wait().await;
+                        warn!(
+                            server = %self.config.name,
+                            workspace_id = %self.workspace_id,
+                            status = ?status,
+                            "Language server exited"
+                        );
+                        return Ended::Crashed;
+                    };
+                    self.notification(notification);
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, running: &Running, command: Command) {
+        let connection = &running.connection;
+        match command {
+            Command::Open(document) => {
+                if self.documents.contains_key(&document.uri) {
+                    return;
+                }
+                self.documents.insert(
+                    document.uri.clone(),
+                    DocumentState {
+                        version: document.version,
+                    },
+                );
+                send_open(connection, document);
+            }
+            Command::Change {
+                uri,
+                version,
+                changes,
+            } => {
+                let Some(state) = self.documents.get_mut(&uri) else {
+                    return;
+                };
+                if version <= state.version {
+                    return;
+                }
+                state.version = version;
+                let (version, changes) = match running.sync.change {
+                    TextDocumentSyncKind::INCREMENTAL => (version, changes),
+                    TextDocumentSyncKind::FULL => {
+                        let Some(document) = self.source.document(&uri) else {
+                            return;
+                        };
+                        state.version = document.version;
+                        let change = TextDocumentContentChangeEvent {
+                            range: None,
+                            range_length: None,
+                            text: document.text,
+                        };
+                        (document.version, vec![change])
+                    }
+                    _ => return,
+                };
+                let params = DidChangeTextDocumentParams {
+                    text_document: VersionedTextDocumentIdentifier { uri, version },
+                    content_changes: changes,
+                };
+                notify(connection, DidChangeTextDocument::METHOD, &params);
+            }
+            Command::Save(uri) => {
+                if !running.sync.save || !self.documents.contains_key(&uri) {
+                    return;
+                }
+                let text = if running.sync.save_text {
+                    self.source.document(&uri).map(|document| document.text)
+                } else {
+                    None
+                };
+                let params = DidSaveTextDocumentParams {
+                    text_document: TextDocumentIdentifier { uri },
+                    text,
+                };
+                notify(connection, DidSaveTextDocument::METHOD, &params);
+            }
+            Command::Close(uri) => {
+                if self.documents.remove(&uri).is_some() {
+                    let params = DidCloseTextDocumentParams {
+                        text_document: TextDocumentIdentifier { uri },
+                    };
+                    notify(connection, DidCloseTextDocument::METHOD, &params);
+                }
+            }
+            Command::Request {
+                id,
+                method,
+                params,
+                reply,
+            } => connection.send_request(id, method, params, reply),
+            Command::Cancel(id) => connection.cancel(id),
+            // Handled by the caller
+            Command::Shutdown => {}
+        }
+    }
+
+    /// Handle commands while the server is down: track documents, fail
+    /// requests. With a delay, returns `true` once it passes; without,
+    /// runs until shutdown. Returns `false` on shutdown.
+    async fn idle(&mut self, delay: Option<std::time::Duration>) -> bool {
+        let sleep = async {
+            match delay {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(sleep);
+        loop {
+            let command = tokio::select! {
+                () = &mut sleep => return true,
+                command = self.commands.recv() => command,
+            };
+            match command {
+                Some(Command::Shutdown) | None => return false,
+                Some(Command::Open(document)) => {
+                    self.documents.entry(document.uri).or_insert(DocumentState {
+                        version: document.version,
+                    });
+                }
+                Some(Command::Change { uri, version, .. }) => {
+                    if let Some(state) = self.documents.get_mut(&uri) {
+                        state.version = state.version.max(version);
+                    }
+                }
+                Some(Command::Close(uri)) => {
+                    self.documents.remove(&uri);
+                }
+                Some(Command::Request { reply, .. }) => {
+                    let _ = reply.send(Err(LspError::NotRunning(self.config.name.clone())));
+                }
+                Some(Command::Save(_) | Command::Cancel(_)) => {}
+            }
+        }
+    }
+
+    /// Ask the server to exit, killing it if it does not.
+    async fn stop(&self, mut running: Running) {
+        let timeout = self.policy.request_timeout;
+        let shutdown = running
+            .connection
+            .request(self.next_id(), Shutdown::METHOD, Value::Null, timeout)
+            .await;
+        if let Err(e) = shutdown {
+            debug!(server = %self.config.name, error = %e, "Shutdown request failed");
+        }
+        running.connection.notify(Exit::METHOD, Value::Null);
+        if tokio::time::timeout(timeout, running.child.wait())
+            .await
+            .is_err()
+        {
+            let _ = running.child.kill().await;
+        }
+        info!(
+            server = %self.config.name,
+            workspace_id = %self.workspace_id,
+            "Language server stopped"
+        );
+    }
+
+    fn notification(&self, notification: Notification) {
+        match notification.method.as_str() {
+            PublishDiagnostics::METHOD => match serde_json::from_value(notification.params) {
+                Ok(params) => {
+                    let _ = self.events.send(LspEvent::Diagnostics {
+                        workspace_id: self.workspace_id.clone(),
+                        server: self.config.name.clone(),
+                        params,
+                    });
+                }
+                Err(e) => {
+                    debug!(server = %self.config.name, error = %e, "Invalid diagnostics");
+                }
+            },
+            "window/logMessage" | "window/showMessage" => {
+                let message = notification
+                    .params
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                debug!(server = %self.config.name, "{message}");
+            }
+            method => debug!(server = %self.config.name, method, "Ignoring notification"),
+        }
+    }
+}
+
+fn notify(connection: &Connection, method: &str, params: &impl serde::Serialize) {
+    match serde_json::to_value(params) {
+        Ok(params) => connection.notify(method, params),
+        Err(e) => warn!(method, error = %e, "Failed to encode notification"),
+    }
+}
+
+fn send_open(connection: &Connection, document: TextDocument) {
+    let params = DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+            uri: document.uri,
+            language_id: document.language_id,
+            version: document.version,
+            text: document.text,
+        },
+    };
+    notify(connection, DidOpenTextDocument::METHOD, &params);
+}
+
+fn client_capabilities() -> ClientCapabilities {
+    let markup = vec![MarkupKind::Markdown, MarkupKind::PlainText];
+    ClientCapabilities {
+        // Code actions may create, rename and delete files; changes are
+        // applied in order and stop at the first failure
+        workspace: Some(WorkspaceClientCapabilities {
+            workspace_edit: Some(WorkspaceEditClientCapabilities {
+                document_changes: Some(true),
+                resource_operations: Some(vec![
+                    ResourceOperationKind::Create,
+                    ResourceOperationKind::Rename,
+                    ResourceOperationKind::Delete,
+                ]),
+                failure_handling: Some(FailureHandlingKind::Abort),
+                ..WorkspaceEditClientCapabilities::default()
+            }),
+            ..WorkspaceClientCapabilities::default()
+        }),
+        text_document: Some(TextDocumentClientCapabilities {
+            synchronization: Some(TextDocumentSyncClientCapabilities {
+                did_save: Some(true),
+                ..TextDocumentSyncClientCapabilities::default()
+            }),
+            publish_diagnostics: Some(PublishDiagnosticsClientCapabilities {
+                related_information: Some(true),
+                tag_support: Some(TagSupport {
+                    value_set: vec![DiagnosticTag::UNNECESSARY, DiagnosticTag::DEPRECATED],
+                }),
+                version_support: Some(true),
+                ..PublishDiagnosticsClientCapabilities::default()
+            }),
+            hover: Some(HoverClientCapabilities {
+                content_format: Some(markup.clone()),
+                ..HoverClientCapabilities::default()
+            }),
+            completion: Some(CompletionClientCapabilities {
+                completion_item: Some(CompletionItemCapability {
+                    snippet_support: Some(true),
+                    commit_characters_support: Some(true),
+                    documentation_format: Some(markup.clone()),
+                    deprecated_support: Some(true),
+                    preselect_support: Some(true),
+                    tag_support: Some(TagSupport {
+                        value_set: vec![CompletionItemTag::DEPRECATED],
+                    }),
+                    ..CompletionItemCapability::default()
+                }),
+                context_support: Some(true),
+                ..CompletionClientCapabilities::default()
+            }),
+            signature_help: Some(SignatureHelpClientCapabilities {
+                signature_information: Some(SignatureInformationSettings {
+                    documentation_format: Some(markup),
+                    parameter_information: Some(ParameterInformationSettings {
+                        label_offset_support: Some(true),
+                    }),
+                    active_parameter_support: Some(true),
+                }),
+                ..SignatureHelpClientCapabilities::default()
+            }),
+            definition: Some(GotoCapability {
+                link_support: Some(true),
+                ..GotoCapability::default()
+            }),
+            references: Some(ReferenceClientCapabilities::default()),
+            rename: Some(RenameClientCapabilities::default()),
+            code_action: Some(CodeActionClientCapabilities {
+                code_action_literal_support: Some(CodeActionLiteralSupport {
+                    code_action_kind: CodeActionKindLiteralSupport {
+                        value_set: [
+                            CodeActionKind::EMPTY,
+                            CodeActionKind::QUICKFIX,
+                            CodeActionKind::REFACTOR,
+                            CodeActionKind::REFACTOR_EXTRACT,
+                            CodeActionKind::REFACTOR_INLINE,
+                            CodeActionKind::REFACTOR_REWRITE,
+                            CodeActionKind::SOURCE,
+                            CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+                            CodeActionKind::SOURCE_FIX_ALL,
+                        ]
+                        .into_iter()
+                        .map(|kind| kind.as_str().to_string())
+                        .collect(),
+                    },
+                }),
+                is_preferred_support: Some(true),
+                disabled_support: Some(true),
+                data_support: Some(true),
+                resolve_support: Some(CodeActionCapabilityResolveSupport {
+                    properties: vec!["edit".to_string()],
+                }),
+                ..CodeActionClientCapabilities::default()
+            }),
+            formatting: Some(DocumentFormattingClientCapabilities::default()),
+            range_formatting: Some(DocumentRangeFormattingClientCapabilities::default()),
+            ..TextDocumentClientCapabilities::default()
+        }),
+        ..ClientCapabilities::default()
+    }
+}
@@ -0,0 +1,80 @@
+//! Base protocol framing: a `Content-Length` header, a blank line, then a
+//! JSON body.
+
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::LspError;
+
+/// Read one message. `None` at end of stream.
+pub(crate) async fn read_message<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<Value>, LspError> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>().map_err(|_| {
+                    LspError::Protocol(format!("Invalid Content-Length: {}", value.trim()))
+                })?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| LspError::Protocol("Missing Content-Length".into()))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+/// Write one message and flush.
+pub(crate) async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Value,
+) -> Result<(), LspError> {
+    let body = serde_json::to_vec(message)?;
+    writer
+        .write_all(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())
+        .await?;
+    writer.write_all(&body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::BufReader;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let mut bytes = Vec::new();
+        let first = json!({"jsonrpc": "2.0", "method": "initialized", "params": {}});
+        let second = json!({"jsonrpc": "2.0", "id": 1, "result": "héllo"});
+        write_message(&mut bytes, &first).await.unwrap();
+        write_message(&mut bytes, &second).await.unwrap();
+
+        let mut reader = BufReader::new(bytes.as_slice());
+        assert_eq!(read_message(&mut reader).await.unwrap(), Some(first));
+        assert_eq!(read_message(&mut reader).await.unwrap(), Some(second));
+        assert_eq!(read_message(&mut reader).await.unwrap(), None);
+
+        let mut reader = BufReader::new(&b"Content-Type: x\r\n\r\n{}"[..]);
+        assert!(read_message(&mut reader).await.is_err());
+    }
+}
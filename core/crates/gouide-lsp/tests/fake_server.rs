@@ -0,0 +1,395 @@
+//! Supervisor tests against the scripted `fake-lsp` server.
+
+// Integration tests link every dependency of the library
+#![allow(unused_crate_dependencies)]
+#![allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use gouide_lsp::lsp_types::request::{
+    Completion, DocumentHighlightRequest, GotoDefinition, HoverRequest, References, Rename,
+    SignatureHelpRequest,
+};
+use gouide_lsp::lsp_types::{
+    CompletionParams, CompletionResponse, DocumentHighlightParams, GotoDefinitionParams,
+    GotoDefinitionResponse, HoverContents, HoverParams, MarkupContent, MarkupKind, Position, Range,
+    ReferenceContext, ReferenceParams, RenameParams, SignatureHelpParams,
+    TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentPositionParams, Url,
+};
+use gouide_lsp::{
+    DocumentSource, LspError, LspEvent, LspManager, RestartPolicy, ServerConfig, ServerStatus,
+    TextDocument,
+};
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+
+/// Documents as the editor has them.
+#[derive(Default)]
+struct Documents(Mutex<HashMap<Url, TextDocument>>);
+
+impl Documents {
+    fn set(&self, uri: &Url, version: i32, text: &str) -> TextDocument {
+        let document = TextDocument {
+            uri: uri.clone(),
+            language_id: "rust".to_string(),
+            version,
+            text: text.to_string(),
+        };
+        self.0.lock().insert(uri.clone(), document.clone());
+        document
+    }
+}
+
+impl DocumentSource for Documents {
+    fn document(&self, uri: &Url) -> Option<TextDocument> {
+        self.0.lock().get(uri).cloned()
+    }
+}
+
+fn config(args: &[&str]) -> ServerConfig {
+    let mut config = ServerConfig::new("fake", env!("CARGO_BIN_EXE_fake-lsp"), ["rust"]);
+    config.args = args.iter().map(ToString::to_string).collect();
+    config
+}
+
+fn policy() -> RestartPolicy {
+    RestartPolicy {
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+        max_restarts: 2,
+        ..RestartPolicy::default()
+    }
+}
+
+async fn next_event(events: &mut mpsc::UnboundedReceiver<LspEvent>) -> LspEvent {
+    tokio::time::timeout(Duration::from_secs(10), events.recv())
+        .await
+        .expect("timed out waiting for an event")
+        .expect("event channel closed")
+}
+
+/// Messages and version of the next diagnostics event.
+async fn next_diagnostics(
+    events: &mut mpsc::UnboundedReceiver<LspEvent>,
+) -> (Vec<String>, Option<i32>) {
+    loop {
+        if let LspEvent::Diagnostics { params, .. } = next_event(events).await {
+            let messages = params.diagnostics.into_iter().map(|d| d.message).collect();
+            return (messages, params.version);
+        }
+    }
+}
+
+fn uri(root: &Path) -> Url {
+    Url::from_file_path(root.join("main.rs")).unwrap()
+}
+
+fn insert(line: u32, character: u32, text: &str) -> TextDocumentContentChangeEvent {
+    let position = Position::new(line, character);
+    TextDocumentContentChangeEvent {
+        range: Some(Range::new(position, position)),
+        range_length: None,
+        text: text.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_document_sync_feeds_diagnostics() {
+    for sync in ["incremental", "full"] {
+        let dir = tempfile::tempdir().unwrap();
+        let documents = Arc::new(Documents::default());
+        let (manager, mut events) =
+            LspManager::new(vec![config(&["--sync", sync])], policy(), documents.clone());
+        let uri = uri(dir.path());
+
+        let document = documents.set(&uri, 1, "fn main() {}\n// TODO\n");
+        manager.did_open("w1", dir.path(), document);
+        assert!(matches!(
+            next_event(&mut events).await,
+            LspEvent::Started { .. }
+        ));
+        assert_eq!(
+            next_diagnostics(&mut events).await,
+            (vec!["TODO".into()], Some(1))
+        );
+
+        // Incremental changes are applied by the server; full sync sends
+        // the source's text
+        documents.set(&uri, 2, "TODO fn main() {}\n// TODO\n");
+        manager.did_change(&uri, 2, &[insert(0, 0, "TODO ")]);
+        assert_eq!(
+            next_diagnostics(&mut events).await,
+            (vec!["TODO".into(), "TODO".into()], Some(2))
+        );
+
+        // Stale versions are dropped
+        manager.did_change(&uri, 2, &[insert(0, 0, "TODO ")]);
+        manager.did_save(&uri);
+        assert_eq!(
+            next_diagnostics(&mut events).await.0,
+            ["TODO", "TODO", "saved"],
+            "sync {sync}"
+        );
+
+        let server = manager.servers_for(&uri).pop().unwrap();
+        assert_eq!(server.status(), ServerStatus::Running);
+        assert!(server.capabilities().is_some());
+        // Requests the server does not handle fail with its error
+        let highlight = server
+            .request::<DocumentHighlightRequest>(DocumentHighlightParams {
+                text_document_position_params: at(&uri, 0, 0),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await;
+        assert!(matches!(
+            highlight,
+            Err(LspError::Server { code: -32601, .. })
+        ));
+
+        manager.did_close(&uri);
+        assert!(next_diagnostics(&mut events).await.0.is_empty());
+        assert!(manager.servers_for(&uri).is_empty());
+
+        manager.shutdown().await;
+        assert!(matches!(
+            next_event(&mut events).await,
+            LspEvent::Stopped { .. }
+        ));
+        assert_eq!(server.status(), ServerStatus::Stopped);
+    }
+}
+
+fn at(uri: &Url, line: u32, character: u32) -> TextDocumentPositionParams {
+    TextDocumentPositionParams::new(
+        TextDocumentIdentifier::new(uri.clone()),
+        Position::new(line, character),
+    )
+}
+
+#[tokio::test]
+async fn test_language_requests() {
+    let dir = tempfile::tempdir().unwrap();
+    let documents = Arc::new(Documents::default());
+    let (manager, mut events) = LspManager::new(vec![config(&[])], policy(), documents.clone());
+    let main = uri(dir.path());
+    let lib = Url::from_file_path(dir.path().join("lib.rs")).unwrap();
+    manager.did_open(
+        "w1",
+        dir.path(),
+        documents.set(
+            &main,
+            1,
+            "fn add(a: i32, b: i32) {}\nfn main() {\n    add(1, 2);\n}\n",
+        ),
+    );
+    manager.did_open("w1", dir.path(), documents.set(&lib, 1, "use add;\n"));
+    assert!(matches!(
+        next_event(&mut events).await,
+        LspEvent::Started { .. }
+    ));
+    let server = manager.servers_for(&main).pop().unwrap();
+
+    let hover = server
+        .request::<HoverRequest>(HoverParams {
+            text_document_position_params: at(&main, 2, 5),
+            work_done_progress_params: Default::default(),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        hover.contents,
+        HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: "`add`".into(),
+        })
+    );
+    assert_eq!(
+        hover.range,
+        Some(Range::new(Position::new(2, 4), Position::new(2, 7)))
+    );
+
+    let completion = server
+        .request::<Completion>(CompletionParams {
+            text_document_position: at(&main, 2, 5),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        })
+        .await
+        .unwrap();
+    let Some(CompletionResponse::List(list)) = completion else {
+        panic!("expected a completion list: {completion:?}");
+    };
+    let labels: Vec<_> = list.items.iter().map(|item| item.label.as_str()).collect();
+    assert_eq!(labels, ["1", "2", "a", "add", "b", "fn", "i32", "main"]);
+
+    let help = server
+        .request::<SignatureHelpRequest>(SignatureHelpParams {
+            context: None,
+            text_document_position_params: at(&main, 2, 11),
+            work_done_progress_params: Default::default(),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(help.signatures[0].label, "add(a: i32, b: i32)");
+    assert_eq!(help.active_parameter, Some(1));
+
+    let definition = server
+        .request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: at(&main, 2, 5),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .await
+        .unwrap();
+    let Some(GotoDefinitionResponse::Scalar(location)) = definition else {
+        panic!("expected one location: {definition:?}");
+    };
+    assert_eq!(location.range.start, Position::new(0, 3));
+
+    let references = |include_declaration| {
+        server.request::<References>(ReferenceParams {
+            text_document_position: at(&main, 2, 5),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: ReferenceContext {
+                include_declaration,
+            },
+        })
+    };
+    assert_eq!(references(true).await.unwrap().unwrap().len(), 3);
+    let without_declaration = references(false).await.unwrap().unwrap();
+    assert_eq!(without_declaration.len(), 2);
+    assert!(without_declaration
+        .iter()
+        .any(|location| location.uri == lib));
+
+    let rename = server
+        .request::<Rename>(RenameParams {
+            text_document_position: at(&main, 0, 4),
+            new_name: "sum".into(),
+            work_done_progress_params: Default::default(),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    let changes = rename.changes.unwrap();
+    assert_eq!(changes[&main].len(), 2);
+    assert_eq!(changes[&lib].len(), 1);
+    assert_eq!(changes[&lib][0].new_text, "sum");
+
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_dropped_request_is_cancelled_in_the_server() {
+    let dir = tempfile::tempdir().unwrap();
+    let documents = Arc::new(Documents::default());
+    let (manager, mut events) = LspManager::new(
+        vec![config(&["--hold-completion"])],
+        policy(),
+        documents.clone(),
+    );
+    let uri = uri(dir.path());
+    manager.did_open("w1", dir.path(), documents.set(&uri, 1, "fn main() {}\n"));
+    let server = manager.servers_for(&uri).pop().unwrap();
+
+    let completion = server.request::<Completion>(CompletionParams {
+        text_document_position: at(&uri, 0, 1),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+        context: None,
+    });
+    // The server never answers, so the request is dropped unfinished
+    assert!(tokio::time::timeout(Duration::from_millis(200), completion)
+        .await
+        .is_err());
+    // The server reports the cancellation it received
+    loop {
+        if next_diagnostics(&mut events).await.0 == ["cancelled"] {
+            break;
+        }
+    }
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_crashed_server_restarts_and_reopens_documents() {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("crashed");
+    let documents = Arc::new(Documents::default());
+    let (manager, mut events) = LspManager::new(
+        vec![config(&["--crash-once", marker.to_str().unwrap()])],
+        policy(),
+        documents.clone(),
+    );
+    let uri = uri(dir.path());
+
+    let document = documents.set(&uri, 1, "// TODO\n");
+    manager.did_open("w1", dir.path(), document);
+    assert!(matches!(
+        next_event(&mut events).await,
+        LspEvent::Started { .. }
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        LspEvent::Stopped { .. }
+    ));
+    assert!(marker.exists());
+
+    // The edit made while the server was down is in the replayed text
+    documents.set(&uri, 2, "// TODO\n// TODO\n");
+    manager.did_change(&uri, 2, &[insert(1, 0, "// TODO\n")]);
+    assert!(matches!(
+        next_event(&mut events).await,
+        LspEvent::Started { .. }
+    ));
+    assert_eq!(
+        next_diagnostics(&mut events).await,
+        (vec!["TODO".into(), "TODO".into()], Some(2))
+    );
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_server_that_cannot_start_is_given_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let documents = Arc::new(Documents::default());
+    let mut config = config(&[]);
+    config.command = dir.path().join("missing").to_string_lossy().into_owned();
+    let (manager, _events) = LspManager::new(vec![config], policy(), documents.clone());
+    let uri = uri(dir.path());
+
+    manager.did_open("w1", dir.path(), documents.set(&uri, 1, ""));
+    let server = manager.servers_for(&uri).pop().unwrap();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while server.status() != ServerStatus::Failed {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    let hover = server
+        .request::<HoverRequest>(HoverParams {
+            text_document_position_params: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri.clone()),
+                Position::new(0, 0),
+            ),
+            work_done_progress_params: Default::default(),
+        })
+        .await;
+    assert!(matches!(hover, Err(LspError::NotRunning(_))));
+    manager.shutdown().await;
+}
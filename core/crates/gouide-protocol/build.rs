@@ -9,6 +9,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "../../../protocol/gouide/v1/handshake.proto",
         "../../../protocol/gouide/v1/workspace.proto",
         "../../../protocol/gouide/v1/editor.proto",
+        "../../../protocol/gouide/v1/language.proto",
+        "../../../protocol/gouide/v1/search.proto",
+        "../../../protocol/gouide/v1/git.proto",
     ];
 
     // Re-run if any proto file changes
@@ -15,7 +15,7 @@
 #[allow(clippy::all, missing_docs)]
 pub mod gouide {
     /// Generated protocol version 1 types.
-    #[allow(clippy::all, missing_docs)]
+    #[allow(clippy::all, clippy::use_self, missing_docs)]
     pub mod v1 {
         tonic::include_proto!("gouide.v1");
     }
@@ -0,0 +1,47 @@
+//! Gouide workspace text search.
+//!
+//! A [`Searcher`] walks a workspace folder on several threads, skipping what
+//! `.gitignore` and `.ignore` files exclude, and runs a [`Query`] over every
+//! text file it finds. Results are handed over one file at a time, as soon
+//! as each file is searched, so callers can stream them; the search stops
+//! early when cancelled or once enough matches are found.
+//!
+//! On large workspaces, an index of each file's [`TrigramFilter`] lets a
+//! search skip the files a query's [`Trigrams`] rule out, reading only the
+//! candidates it is given (see [`Searcher::with_candidates`]).
+//!
+//! Match positions use UTF-16 columns, like the rest of the protocol, and
+//! come with a preview of the line they start on.
+
+mod query;
+mod searcher;
+mod text;
+mod trigrams;
+
+use thiserror::Error;
+
+pub use query::{Matcher, Query};
+pub use searcher::{FileMatches, SearchOptions, SearchSummary, Searcher};
+pub use text::{find_matches, find_replacements, Position, Preview, TextMatch, PREVIEW_CHARS};
+pub use trigrams::{TrigramFilter, Trigrams};
+
+/// Errors that prevent a search from starting.
+#[derive(Error, Debug)]
+pub enum SearchError {
+    /// The query has no pattern.
+    #[error("Search query is empty")]
+    EmptyQuery,
+
+    /// The regular expression does not compile.
+    #[error("Invalid regular expression: {0}")]
+    InvalidPattern(String),
+
+    /// An include or exclude glob does not compile.
+    #[error("Invalid glob {glob}: {message}")]
+    InvalidGlob {
+        /// The glob as given.
+        glob: String,
+        /// What is wrong with it.
+        message: String,
+    },
+}
@@ -0,0 +1,223 @@
+//! Search queries.
+
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{SearchError, Trigrams};
+
+/// Most memory a compiled pattern may use.
+const REGEX_SIZE_LIMIT: usize = 32 * 1024 * 1024;
+
+/// What to search for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    /// Text or regular expression to find.
+    pub pattern: String,
+    /// Treat the pattern as a regular expression instead of literal text.
+    pub is_regex: bool,
+    /// Match letter case exactly.
+    pub case_sensitive: bool,
+    /// Only match whole words.
+    pub whole_word: bool,
+}
+
+impl Query {
+    /// A case-insensitive search for literal text.
+    pub fn literal(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            ..Self::default()
+        }
+    }
+
+    /// A case-insensitive search for a regular expression.
+    pub fn regex(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            is_regex: true,
+            ..Self::default()
+        }
+    }
+
+    /// Compile the query.
+    ///
+    /// Regular expressions use the syntax of the `regex` crate, with `^` and
+    /// `$` matching at line breaks (`\r\n` included). `.` does not match
+    /// line breaks, but classes such as `\s` do, so a match may span lines.
+    pub fn matcher(&self) -> Result<Matcher, SearchError> {
+        if self.pattern.is_empty() {
+            return Err(SearchError::EmptyQuery);
+        }
+        let pattern = if self.is_regex {
+            self.pattern.clone()
+        } else {
+            regex::escape(&self.pattern)
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .multi_line(true)
+            .crlf(true)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|e| SearchError::InvalidPattern(e.to_string()))?;
+        Ok(Matcher {
+            trigrams: Trigrams::for_pattern(regex.as_str(), self.case_sensitive),
+            regex,
+            is_regex: self.is_regex,
+            whole_word: self.whole_word,
+        })
+    }
+}
+
+/// A compiled [`Query`].
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    regex: Regex,
+    trigrams: Trigrams,
+    is_regex: bool,
+    whole_word: bool,
+}
+
+impl Matcher {
+    /// The compiled regular expression, for callers that need its capture
+    /// groups.
+    pub const fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// Trigrams every match contains, which rule out files that cannot
+    /// match (see [`TrigramFilter`](crate::TrigramFilter)).
+    pub const fn trigrams(&self) -> &Trigrams {
+        &self.trigrams
+    }
+
+    /// Byte ranges of the matches in `text`, in order.
+    ///
+    /// Empty matches are skipped. For whole-word queries, a match counts
+    /// only when the characters around it are not word characters, which
+    /// unlike `\b` also works for patterns that start or end with
+    /// punctuation.
+    pub fn find_iter<'t>(&'t self, text: &'t str) -> impl Iterator<Item = Range<usize>> + 't {
+        self.regex
+            .find_iter(text)
+            .map(|m| m.range())
+            .filter(move |range| self.accepts(text, range))
+    }
+
+    /// Byte ranges of the matches in `text` with the text replacing each.
+    ///
+    /// For regular expressions, `$1`, `${1}` and `${name}` in `replacement`
+    /// stand for capture groups and `$$` for a dollar sign; literal queries
+    /// insert `replacement` as is.
+    pub fn replace_iter<'t>(
+        &'t self,
+        text: &'t str,
+        replacement: &'t str,
+    ) -> impl Iterator<Item = (Range<usize>, String)> + 't {
+        self.regex.captures_iter(text).filter_map(move |captures| {
+            let range = captures.get(0)?.range();
+            if !self.accepts(text, &range) {
+                return None;
+            }
+            let new_text = if self.is_regex {
+                let mut expanded = String::new();
+                captures.expand(replacement, &mut expanded);
+                expanded
+            } else {
+                replacement.to_string()
+            };
+            Some((range, new_text))
+        })
+    }
+
+    fn accepts(&self, text: &str, range: &Range<usize>) -> bool {
+        !range.is_empty() && (!self.whole_word || is_whole_word(text, range))
+    }
+}
+
+/// Whether the text around `range` does not continue a word.
+fn is_whole_word(text: &str, range: &Range<usize>) -> bool {
+    let before = text[..range.start].chars().next_back();
+    let after = text[range.end..].chars().next();
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    fn find(query: &Query, text: &str) -> Vec<String> {
+        let matcher = query.matcher().unwrap();
+        matcher
+            .find_iter(text)
+            .map(|range| text[range].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_query_modes() {
+        let text = "Foo foo.bar food (x) a+b";
+
+        // Literal text is escaped and case-insensitive by default
+        assert_eq!(find(&Query::literal("foo"), text), ["Foo", "foo", "foo"]);
+        assert_eq!(find(&Query::literal("a+b"), text), ["a+b"]);
+
+        let exact = Query {
+            case_sensitive: true,
+            ..Query::literal("Foo")
+        };
+        assert_eq!(find(&exact, text), ["Foo"]);
+
+        let word = Query {
+            whole_word: true,
+            ..Query::literal("foo")
+        };
+        assert_eq!(find(&word, text), ["Foo", "foo"]);
+        // Punctuation at the edges of the pattern still needs a boundary
+        let word = Query {
+            whole_word: true,
+            ..Query::literal("(x)")
+        };
+        assert_eq!(find(&word, text), ["(x)"]);
+
+        assert_eq!(find(&Query::regex(r"fo+d\b"), text), ["food"]);
+        assert_eq!(find(&Query::regex("^a$"), "b\r\na\r\n"), ["a"]);
+        // Empty matches are not results
+        assert!(find(&Query::regex("z*"), text).is_empty());
+
+        let replaced: Vec<_> = Query::regex(r"(\w+)\.(?<field>\w+)")
+            .matcher()
+            .unwrap()
+            .replace_iter(text, "${field}_$1 $$")
+            .map(|(range, new_text)| (text[range].to_string(), new_text))
+            .collect();
+        assert_eq!(replaced, [("foo.bar".to_string(), "bar_foo $".to_string())]);
+        let replaced: Vec<_> = Query::literal("a+b")
+            .matcher()
+            .unwrap()
+            .replace_iter(text, "$1")
+            .map(|(_, new_text)| new_text)
+            .collect();
+        assert_eq!(replaced, ["$1"]);
+
+        assert!(matches!(
+            Query::literal("").matcher(),
+            Err(SearchError::EmptyQuery)
+        ));
+        assert!(matches!(
+            Query::regex("(").matcher(),
+            Err(SearchError::InvalidPattern(_))
+        ));
+    }
+}
@@ -0,0 +1,595 @@
+//! Searching a folder.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::{WalkBuilder, WalkState};
+
+use crate::text::{find_matches, find_replacements};
+use crate::{Matcher, Query, SearchError, TextMatch};
+
+/// Bytes at the start of a file checked for NUL to tell binary files apart.
+const BINARY_CHECK_BYTES: usize = 8 * 1024;
+
+/// Where to search and when to stop.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Globs a file must match one of, empty for all files. Globs use
+    /// `.gitignore` syntax: without a `/` they match a name at any depth.
+    pub include: Vec<String>,
+    /// Globs of files and directories to skip, in the same syntax.
+    pub exclude: Vec<String>,
+    /// Skip what `.gitignore`, `.ignore` and git's exclude files exclude.
+    pub use_ignore_files: bool,
+    /// Stop after this many matches, 0 for no limit.
+    pub max_results: usize,
+    /// Skip files larger than this many bytes.
+    pub max_file_size: u64,
+    /// Threads to search with, 0 to pick from the number of CPUs.
+    pub threads: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            use_ignore_files: true,
+            max_results: 0,
+            max_file_size: 16 * 1024 * 1024,
+            threads: 0,
+        }
+    }
+}
+
+/// Matches in one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMatches {
+    /// Path relative to the searched folder, with `/` separators.
+    pub path: String,
+    /// Matches in file order.
+    pub matches: Vec<TextMatch>,
+    /// The text replacing each match, when searching with a replacement
+    /// (see [`Searcher::with_replacement`]); empty otherwise.
+    pub replacements: Vec<String>,
+}
+
+/// How a search went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SearchSummary {
+    /// Text files searched.
+    pub files_searched: usize,
+    /// Files with at least one match.
+    pub files_matched: usize,
+    /// Matches found.
+    pub matches: usize,
+    /// Whether the search stopped at `max_results`.
+    pub limit_hit: bool,
+    /// Whether the search was cancelled before it finished.
+    pub cancelled: bool,
+}
+
+/// A query ready to run over a folder.
+pub struct Searcher {
+    root: PathBuf,
+    matcher: Matcher,
+    options: SearchOptions,
+    overrides: Override,
+    contents: HashMap<String, String>,
+    replacement: Option<String>,
+    candidates: Option<Vec<String>>,
+}
+
+impl Searcher {
+    /// Prepare a search of the folder `root`.
+    pub fn new(
+        root: impl Into<PathBuf>,
+        query: &Query,
+        options: SearchOptions,
+    ) -> Result<Self, SearchError> {
+        let root = root.into();
+        let matcher = query.matcher()?;
+        let mut overrides = OverrideBuilder::new(&root);
+        let globs = options
+            .include
+            .iter()
+            .map(|glob| (glob, glob.clone()))
+            .chain(
+                options
+                    .exclude
+                    .iter()
+                    .map(|glob| (glob, format!("!{glob}"))),
+            );
+        for (glob, pattern) in globs {
+            overrides
+                .add(&pattern)
+                .map_err(|e| SearchError::InvalidGlob {
+                    glob: glob.clone(),
+                    message: e.to_string(),
+                })?;
+        }
+        let overrides = overrides.build().map_err(|e| SearchError::InvalidGlob {
+            glob: String::new(),
+            message: e.to_string(),
+        })?;
+        Ok(Self {
+            root,
+            matcher,
+            options,
+            overrides,
+            contents: HashMap::new(),
+            replacement: None,
+            candidates: None,
+        })
+    }
+
+    /// Search `contents` instead of the file on disk for these paths, such
+    /// as open buffers with unsaved edits. Keys are paths relative to the
+    /// root with `/` separators.
+    #[must_use]
+    pub fn with_contents(mut self, contents: HashMap<String, String>) -> Self {
+        self.contents = contents;
+        self
+    }
+
+    /// Also work out the text replacing each match (see
+    /// [`Matcher::replace_iter`]).
+    #[must_use]
+    pub fn with_replacement(mut self, replacement: impl Into<String>) -> Self {
+        self.replacement = Some(replacement.into());
+        self
+    }
+
+    /// Search only these files instead of walking the folder, such as those
+    /// an index of [`TrigramFilter`](crate::TrigramFilter)s leaves for the
+    /// query. Paths are relative to the root with `/` separators and should
+    /// be files the walk would reach; include and exclude globs and the
+    /// size limit still apply.
+    #[must_use]
+    pub fn with_candidates(mut self, paths: Vec<String>) -> Self {
+        self.candidates = Some(paths);
+        self
+    }
+
+    /// The compiled query.
+    pub const fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    /// Run the search, calling `on_file` from the search threads for each
+    /// file with matches, in no particular order.
+    ///
+    /// The search stops once `cancel` is set, `max_results` matches are
+    /// found (the last file's matches are cut short to fit), or `on_file`
+    /// returns false.
+    pub fn search<F>(&self, cancel: &AtomicBool, on_file: F) -> SearchSummary
+    where
+        F: Fn(FileMatches) -> bool + Sync,
+    {
+        let max_results = match self.options.max_results {
+            0 => usize::MAX,
+            max => max,
+        };
+        let counts = Counts::default();
+        let stopped = AtomicBool::new(false);
+        let search = Search {
+            max_results,
+            counts: &counts,
+            stopped: &stopped,
+            cancel,
+            on_file: &on_file,
+        };
+        match &self.candidates {
+            Some(paths) => self.search_candidates(paths, &search),
+            None => self.walk(&search),
+        }
+
+        let matches = counts.matches.load(Ordering::SeqCst).min(max_results);
+        SearchSummary {
+            files_searched: counts.files_searched.load(Ordering::Relaxed),
+            files_matched: counts.files_matched.load(Ordering::Relaxed),
+            matches,
+            limit_hit: matches >= max_results,
+            cancelled: cancel.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Search the files a walk of the folder finds.
+    fn walk<F>(&self, search: &Search<'_, F>)
+    where
+        F: Fn(FileMatches) -> bool + Sync,
+    {
+        let use_ignore_files = self.options.use_ignore_files;
+        WalkBuilder::new(&self.root)
+            .hidden(false)
+            .parents(use_ignore_files)
+            .ignore(use_ignore_files)
+            .git_ignore(use_ignore_files)
+            .git_global(use_ignore_files)
+            .git_exclude(use_ignore_files)
+            // Respect .gitignore files in folders that are not repositories
+            .require_git(false)
+            .max_filesize(Some(self.options.max_file_size))
+            .overrides(self.overrides.clone())
+            .threads(self.options.threads)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build_parallel()
+            .run(|| {
+                Box::new(move |entry| {
+                    if search.is_over() {
+                        return WalkState::Quit;
+                    }
+                    let Ok(entry) = entry else {
+                        return WalkState::Continue;
+                    };
+                    if !entry.file_type().is_some_and(|t| t.is_file()) {
+                        return WalkState::Continue;
+                    }
+                    if self.visit(entry.path(), search) {
+                        WalkState::Continue
+                    } else {
+                        WalkState::Quit
+                    }
+                })
+            });
+    }
+
+    /// Search the given files, on as many threads as a walk would use.
+    fn search_candidates<F>(&self, paths: &[String], search: &Search<'_, F>)
+    where
+        F: Fn(FileMatches) -> bool + Sync,
+    {
+        let threads = match self.options.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get().min(12)),
+            n => n,
+        }
+        .min(paths.len())
+        .max(1);
+        let next = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    while !search.is_over() {
+                        let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                            break;
+                        };
+                        if self.is_overridden(path) {
+                            continue;
+                        }
+                        let path = self.root.join(path);
+                        let Ok(metadata) = fs::symlink_metadata(&path) else {
+                            continue;
+                        };
+                        if !metadata.is_file() || metadata.len() > self.options.max_file_size {
+                            continue;
+                        }
+                        if !self.visit(&path, search) {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    /// Whether the include and exclude globs rule out a file, as a walk
+    /// would: it does not enter excluded directories.
+    fn is_overridden(&self, path: &str) -> bool {
+        if self.overrides.is_empty() {
+            return false;
+        }
+        let mut dirs = path.match_indices('/').map(|(end, _)| &path[..end]);
+        dirs.any(|dir| {
+            self.overrides
+                .matched(self.root.join(dir), true)
+                .is_ignore()
+        }) || self
+            .overrides
+            .matched(self.root.join(path), false)
+            .is_ignore()
+    }
+
+    /// Search one file and hand over its matches. False once the search
+    /// should stop.
+    fn visit<F>(&self, path: &Path, search: &Search<'_, F>) -> bool
+    where
+        F: Fn(FileMatches) -> bool + Sync,
+    {
+        let counts = search.counts;
+        let Some(mut file) = self.search_file(path, search.max_results, counts) else {
+            return true;
+        };
+
+        // Reserve room for the matches under the limit
+        let found = file.matches.len();
+        let before = counts.matches.fetch_add(found, Ordering::SeqCst);
+        let room = search.max_results.saturating_sub(before);
+        if room == 0 {
+            return false;
+        }
+        file.matches.truncate(room);
+        file.replacements.truncate(room);
+        counts.files_matched.fetch_add(1, Ordering::Relaxed);
+        let full = found >= room;
+        if !(search.on_file)(file) || full {
+            search.stopped.store(true, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Matches in one file, `None` if it has none or is not a text file.
+    fn search_file(&self, path: &Path, max_results: usize, counts: &Counts) -> Option<FileMatches> {
+        let relative = relative_path(&self.root, path)?;
+        let text = match self.contents.get(&relative) {
+            Some(text) => Cow::Borrowed(text.as_str()),
+            None => Cow::Owned(read_text(path)?),
+        };
+        counts.files_searched.fetch_add(1, Ordering::Relaxed);
+
+        // Matches beyond the limit would be dropped anyway
+        let room = max_results.saturating_sub(counts.matches.load(Ordering::SeqCst));
+        let limit = room.max(1);
+        let (matches, replacements) = self.replacement.as_ref().map_or_else(
+            || (find_matches(&self.matcher, &text, limit), Vec::new()),
+            |replacement| {
+                find_replacements(&self.matcher, &text, replacement, limit)
+                    .into_iter()
+                    .unzip()
+            },
+        );
+        (!matches.is_empty()).then_some(FileMatches {
+            path: relative,
+            matches,
+            replacements,
+        })
+    }
+}
+
+/// A running search, shared by its threads.
+struct Search<'a, F> {
+    max_results: usize,
+    counts: &'a Counts,
+    /// Set once the limit is reached or `on_file` asks to stop.
+    stopped: &'a AtomicBool,
+    cancel: &'a AtomicBool,
+    on_file: &'a F,
+}
+
+impl<F> Search<'_, F> {
+    fn is_over(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed) || self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+/// The text of a file, `None` if it cannot be read or looks binary.
+fn read_text(path: &Path) -> Option<String> {
+    let bytes = fs::read(path).ok()?;
+    if is_binary(&bytes) {
+        return None;
+    }
+    Some(match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    })
+}
+
+/// Whether contents look binary: a NUL near the start.
+pub(crate) fn is_binary(contents: &[u8]) -> bool {
+    let head = &contents[..contents.len().min(BINARY_CHECK_BYTES)];
+    memchr::memchr(0, head).is_some()
+}
+
+#[derive(Default)]
+struct Counts {
+    files_searched: AtomicUsize,
+    files_matched: AtomicUsize,
+    matches: AtomicUsize,
+}
+
+/// `path` relative to `root`, with `/` separators.
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn write(root: &Path, path: &str, content: &[u8]) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Paths and match counts found, sorted.
+    fn run(searcher: &Searcher) -> (Vec<(String, usize)>, SearchSummary) {
+        let found = Mutex::new(Vec::new());
+        let summary = searcher.search(&AtomicBool::new(false), |file| {
+            found.lock().unwrap().push((file.path, file.matches.len()));
+            true
+        });
+        let mut found = found.into_inner().unwrap();
+        found.sort();
+        (found, summary)
+    }
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, ".gitignore", b"target/\n*.log\n");
+        write(root, "src/main.rs", b"fn main() {\n    hello();\n}\n");
+        write(root, "src/lib.rs", b"pub fn hello() {}\n// hello hello\n");
+        write(root, "README.md", b"Say hello\n");
+        write(root, "target/out.rs", b"hello\n");
+        write(root, "debug.log", b"hello\n");
+        write(root, "image.bin", b"hello\0\x01");
+        write(root, ".git/HEAD", b"hello\n");
+        write(root, ".github/ci.yml", b"hello\n");
+        dir
+    }
+
+    #[test]
+    fn test_search_respects_ignore_files_and_globs() {
+        let dir = tree();
+        let query = Query::literal("hello");
+
+        let searcher = Searcher::new(dir.path(), &query, SearchOptions::default()).unwrap();
+        let (found, summary) = run(&searcher);
+        assert_eq!(
+            found,
+            [
+                (".github/ci.yml".to_string(), 1),
+                ("README.md".to_string(), 1),
+                ("src/lib.rs".to_string(), 3),
+                ("src/main.rs".to_string(), 1),
+            ]
+        );
+        assert_eq!(summary.matches, 6);
+        assert_eq!(summary.files_matched, 4);
+        assert!(!summary.limit_hit && !summary.cancelled);
+
+        let options = SearchOptions {
+            include: vec!["*.rs".to_string()],
+            exclude: vec!["main.rs".to_string()],
+            use_ignore_files: false,
+            ..SearchOptions::default()
+        };
+        let searcher = Searcher::new(dir.path(), &query, options).unwrap();
+        let (found, _) = run(&searcher);
+        assert_eq!(
+            found,
+            [
+                ("src/lib.rs".to_string(), 3),
+                ("target/out.rs".to_string(), 1),
+            ]
+        );
+
+        let options = SearchOptions {
+            include: vec!["[".to_string()],
+            ..SearchOptions::default()
+        };
+        assert!(matches!(
+            Searcher::new(dir.path(), &query, options),
+            Err(SearchError::InvalidGlob { .. })
+        ));
+    }
+
+    #[test]
+    fn test_search_stops_at_limit_and_on_cancel() {
+        let dir = tree();
+        let query = Query::literal("hello");
+        let options = SearchOptions {
+            max_results: 2,
+            threads: 1,
+            ..SearchOptions::default()
+        };
+        let searcher = Searcher::new(dir.path(), &query, options).unwrap();
+        let (found, summary) = run(&searcher);
+        assert_eq!(found.iter().map(|(_, n)| n).sum::<usize>(), 2);
+        assert_eq!(summary.matches, 2);
+        assert!(summary.limit_hit);
+
+        let searcher = Searcher::new(dir.path(), &query, SearchOptions::default()).unwrap();
+        let summary = searcher.search(&AtomicBool::new(true), |_| panic!("cancelled"));
+        assert!(summary.cancelled);
+        assert_eq!(summary.matches, 0);
+    }
+
+    #[test]
+    fn test_search_with_replacement() {
+        let dir = tree();
+        let searcher = Searcher::new(
+            dir.path(),
+            &Query::regex(r"(\w+)\(\)"),
+            SearchOptions::default(),
+        )
+        .unwrap()
+        .with_replacement("$1(ctx)");
+        let files = Mutex::new(Vec::new());
+        searcher.search(&AtomicBool::new(false), |file| {
+            files.lock().unwrap().push(file);
+            true
+        });
+        let mut files = files.into_inner().unwrap();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(files[0].path, "src/lib.rs");
+        assert_eq!(files[0].replacements, ["hello(ctx)"]);
+        assert_eq!(files[1].replacements, ["main(ctx)", "hello(ctx)"]);
+        assert_eq!(files[1].matches[1].start.line, 1);
+    }
+
+    #[test]
+    fn test_search_prefers_given_contents() {
+        let dir = tree();
+        let contents = HashMap::from([("README.md".to_string(), "no match".to_string())]);
+        let searcher = Searcher::new(
+            dir.path(),
+            &Query::literal("hello"),
+            SearchOptions::default(),
+        )
+        .unwrap()
+        .with_contents(contents);
+        let (found, _) = run(&searcher);
+        assert!(found.iter().all(|(path, _)| path != "README.md"));
+    }
+
+    #[test]
+    fn test_search_only_candidates() {
+        let dir = tree();
+        let query = Query::literal("hello");
+        let candidates = vec![
+            "src/lib.rs".to_string(),
+            "src/main.rs".to_string(),
+            "image.bin".to_string(),
+            "gone.rs".to_string(),
+        ];
+        let searcher = Searcher::new(dir.path(), &query, SearchOptions::default())
+            .unwrap()
+            .with_candidates(candidates.clone());
+        let (found, summary) = run(&searcher);
+        assert_eq!(
+            found,
+            [
+                ("src/lib.rs".to_string(), 3),
+                ("src/main.rs".to_string(), 1)
+            ]
+        );
+        assert_eq!(summary.files_searched, 2);
+
+        // Globs apply as they would to a walk
+        let options = SearchOptions {
+            exclude: vec!["src".to_string()],
+            ..SearchOptions::default()
+        };
+        let searcher = Searcher::new(dir.path(), &query, options)
+            .unwrap()
+            .with_candidates(candidates.clone());
+        assert!(run(&searcher).0.is_empty());
+        let options = SearchOptions {
+            include: vec!["main.*".to_string()],
+            ..SearchOptions::default()
+        };
+        let searcher = Searcher::new(dir.path(), &query, options)
+            .unwrap()
+            .with_candidates(candidates);
+        assert_eq!(run(&searcher).0, [("src/main.rs".to_string(), 1)]);
+    }
+}
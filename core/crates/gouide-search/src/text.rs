@@ -0,0 +1,240 @@
+//! Matches in a text and their positions.
+
+use std::ops::Range;
+
+use memchr::memchr2;
+
+use crate::Matcher;
+
+/// Most characters of a line kept in a preview.
+pub const PREVIEW_CHARS: usize = 250;
+
+/// Characters kept before the match when a long line is shortened.
+const PREVIEW_CONTEXT: usize = 40;
+
+/// A position in a text. Lines break at `\n`, `\r\n` and `\r`, as in
+/// buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Position {
+    /// 0-based line.
+    pub line: u32,
+    /// 0-based column in UTF-16 code units.
+    pub character: u32,
+}
+
+/// The line a match starts on, shortened around the match if it is long.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Preview {
+    /// Line text, without its line break.
+    pub text: String,
+    /// Start of the match in `text`, in UTF-16 code units.
+    pub start: u32,
+    /// End of the match in `text`, in UTF-16 code units. Matches that run
+    /// past the preview end with it.
+    pub end: u32,
+}
+
+/// A match in a text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextMatch {
+    /// Start of the match.
+    pub start: Position,
+    /// End of the match (exclusive).
+    pub end: Position,
+    /// The line the match starts on.
+    pub preview: Preview,
+}
+
+/// Find up to `limit` matches in `text`, 0 meaning no limit.
+pub fn find_matches(matcher: &Matcher, text: &str, limit: usize) -> Vec<TextMatch> {
+    let mut cursor = LineCursor::new(text);
+    matcher
+        .find_iter(text)
+        .take(no_limit(limit))
+        .map(|range| cursor.text_match(range))
+        .collect()
+}
+
+/// Find up to `limit` matches in `text`, 0 meaning no limit, with the text
+/// replacing each (see [`Matcher::replace_iter`]).
+pub fn find_replacements(
+    matcher: &Matcher,
+    text: &str,
+    replacement: &str,
+    limit: usize,
+) -> Vec<(TextMatch, String)> {
+    let mut cursor = LineCursor::new(text);
+    matcher
+        .replace_iter(text, replacement)
+        .take(no_limit(limit))
+        .map(|(range, new_text)| (cursor.text_match(range), new_text))
+        .collect()
+}
+
+const fn no_limit(limit: usize) -> usize {
+    if limit == 0 {
+        usize::MAX
+    } else {
+        limit
+    }
+}
+
+/// Walks a text forward, converting byte offsets to positions.
+struct LineCursor<'a> {
+    text: &'a str,
+    line: u32,
+    line_start: usize,
+    // Last converted offset on the current line and its column, so many
+    // matches on one long line do not rescan it
+    column_offset: usize,
+    column: u32,
+}
+
+impl<'a> LineCursor<'a> {
+    const fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            line: 0,
+            line_start: 0,
+            column_offset: 0,
+            column: 0,
+        }
+    }
+
+    /// The match at `range`, which must not start before the last one
+    /// ended.
+    fn text_match(&mut self, range: Range<usize>) -> TextMatch {
+        let start = self.position(range.start);
+        let preview = preview(self.text, self.line_start, range.start, range.end);
+        let end = self.position(range.end);
+        TextMatch {
+            start,
+            end,
+            preview,
+        }
+    }
+
+    /// Position of `offset`, which must not be before the last one.
+    fn position(&mut self, offset: usize) -> Position {
+        let bytes = self.text.as_bytes();
+        while let Some(found) = memchr2(b'\n', b'\r', &bytes[self.line_start..offset]) {
+            let line_break = self.line_start + found;
+            let next = if bytes[line_break] == b'\r' && bytes.get(line_break + 1) == Some(&b'\n') {
+                line_break + 2
+            } else {
+                line_break + 1
+            };
+            if next > offset {
+                // Between the `\r` and `\n` of a line break
+                break;
+            }
+            self.line += 1;
+            self.line_start = next;
+            self.column_offset = next;
+            self.column = 0;
+        }
+        self.column += utf16_len(&self.text[self.column_offset..offset]);
+        self.column_offset = offset;
+        Position {
+            line: self.line,
+            character: self.column,
+        }
+    }
+}
+
+/// Preview of the line starting at `line_start` for the match
+/// `start..end`.
+fn preview(text: &str, line_start: usize, start: usize, end: usize) -> Preview {
+    let rest = &text[line_start..];
+    let line = &rest[..memchr2(b'\n', b'\r', rest.as_bytes()).unwrap_or(rest.len())];
+    let (start, end) = (start - line_start, (end - line_start).min(line.len()));
+
+    let (from, to) = if line.len() <= PREVIEW_CHARS {
+        (0, line.len())
+    } else {
+        let from = line[..start]
+            .char_indices()
+            .rev()
+            .take(PREVIEW_CONTEXT)
+            .last()
+            .map_or(start, |(i, _)| i);
+        let to = line[from..]
+            .char_indices()
+            .nth(PREVIEW_CHARS)
+            .map_or(line.len(), |(i, _)| from + i);
+        (from, to)
+    };
+    Preview {
+        text: line[from..to].to_string(),
+        start: utf16_len(&line[from..start]),
+        end: utf16_len(&line[from..end.clamp(start, to)]),
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn utf16_len(text: &str) -> u32 {
+    text.chars().map(char::len_utf16).sum::<usize>() as u32
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use crate::Query;
+
+    const fn pos(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    #[test]
+    fn test_positions_and_previews() {
+        let matcher = Query::literal("ab").matcher().unwrap();
+        let text = "ab ab\r\n  \u{1F600}ab\rx\nab";
+        let matches = find_matches(&matcher, text, 0);
+        let found: Vec<_> = matches
+            .iter()
+            .map(|m| (m.start, m.end, m.preview.start, m.preview.end))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (pos(0, 0), pos(0, 2), 0, 2),
+                (pos(0, 3), pos(0, 5), 3, 5),
+                // The emoji is two UTF-16 code units
+                (pos(1, 4), pos(1, 6), 4, 6),
+                (pos(3, 0), pos(3, 2), 0, 2),
+            ]
+        );
+        assert_eq!(matches[0].preview.text, "ab ab");
+        assert_eq!(matches[2].preview.text, "  \u{1F600}ab");
+        assert_eq!(find_matches(&matcher, text, 2).len(), 2);
+
+        // Matches spanning lines end on a later line; the preview stops at
+        // the first line break
+        let matcher = Query::regex(r"b\s+x").matcher().unwrap();
+        let matches = find_matches(&matcher, "ab\n  x", 0);
+        assert_eq!((matches[0].start, matches[0].end), (pos(0, 1), pos(1, 3)));
+        assert_eq!(matches[0].preview.text, "ab");
+        assert_eq!((matches[0].preview.start, matches[0].preview.end), (1, 2));
+    }
+
+    #[test]
+    fn test_long_lines_are_shortened_around_the_match() {
+        let matcher = Query::literal("needle").matcher().unwrap();
+        let text = format!("{}needle{}", "a".repeat(1000), "b".repeat(1000));
+        let matches = find_matches(&matcher, &text, 0);
+        let preview = &matches[0].preview;
+        assert_eq!(matches[0].start, pos(0, 1000));
+        assert_eq!(preview.text.chars().count(), PREVIEW_CHARS);
+        assert_eq!(preview.start, 40);
+        assert_eq!(
+            &preview.text[preview.start as usize..preview.end as usize],
+            "needle"
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! Trigram filters, which rule out files a query cannot match without
+//! searching them.
+//!
+//! A [`TrigramFilter`] records the three-byte sequences of a file's text in
+//! a bit set sized to the file, and a query's [`Trigrams`] are sequences
+//! every match contains. A file whose filter lacks one of them has no
+//! match. Filters may claim sequences a file does not have, so the files
+//! left still need searching, but never deny one it has.
+//!
+//! Both sides fold ASCII letters to lowercase, so one filter serves queries
+//! of either case, and fold the Kelvin sign and long s to `k` and `s`,
+//! which case-insensitive patterns match them as. Other text is compared
+//! byte for byte; a case-insensitive letter outside ASCII contributes no
+//! trigrams, since its other cases are spelled with other bytes.
+
+use std::borrow::Cow;
+
+use regex_syntax::hir::{Class, Hir, HirKind};
+use regex_syntax::ParserBuilder;
+
+use crate::searcher::is_binary;
+
+/// Filter bits per distinct trigram of a file, before rounding up to a
+/// power of two. One bit in eight to sixteen is set, so each trigram of a
+/// query passes a file without it about one time in ten.
+const BITS_PER_TRIGRAM: usize = 8;
+
+/// Smallest filter, in bits.
+const MIN_BITS: usize = 64;
+
+/// Largest filter, in bits. Files with more trigrams than this holds are
+/// ruled out less often.
+const MAX_BITS: usize = 1 << 20;
+
+/// Most characters a class may hold and still stand for one letter in any
+/// case.
+const MAX_CASE_CLASS: u32 = 4;
+
+/// Kelvin sign, matched by `k` without case.
+const KELVIN: &[u8] = "\u{212A}".as_bytes();
+
+/// Latin small letter long s, matched by `s` without case.
+const LONG_S: &[u8] = "\u{17F}".as_bytes();
+
+/// The trigrams of a file's text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrigramFilter {
+    /// Bit set whose length in bits is a power of two.
+    words: Vec<u64>,
+}
+
+impl TrigramFilter {
+    /// The filter of `text`.
+    pub fn new(text: &[u8]) -> Self {
+        let trigrams = trigrams_of(text);
+        let bits = (trigrams.len() * BITS_PER_TRIGRAM)
+            .next_power_of_two()
+            .clamp(MIN_BITS, MAX_BITS);
+        let mut words = vec![0; bits / 64];
+        for trigram in trigrams {
+            let bit = slot(trigram, bits);
+            words[bit / 64] |= 1 << (bit % 64);
+        }
+        Self { words }
+    }
+
+    /// The filter of a file's contents as a search reads them: binary files
+    /// are never searched, so their filter lets no query through.
+    pub fn for_file(contents: &[u8]) -> Self {
+        if is_binary(contents) {
+            return Self {
+                words: vec![0; MIN_BITS / 64],
+            };
+        }
+        // Invalid UTF-8 is searched as replacement characters
+        match String::from_utf8_lossy(contents) {
+            Cow::Borrowed(text) => Self::new(text.as_bytes()),
+            Cow::Owned(text) => Self::new(text.as_bytes()),
+        }
+    }
+
+    /// A filter stored as its [`words`](Self::words). `None` if they cannot
+    /// be one.
+    pub fn from_words(words: Vec<u64>) -> Option<Self> {
+        (words.len().is_power_of_two() && words.len() * 64 >= MIN_BITS).then_some(Self { words })
+    }
+
+    /// The bit set, for storing the filter.
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Whether the text may contain every one of `trigrams`. Always true
+    /// for no trigrams.
+    pub fn may_contain(&self, trigrams: &Trigrams) -> bool {
+        let bits = self.words.len() * 64;
+        trigrams.0.iter().all(|&trigram| {
+            let bit = slot(trigram, bits);
+            self.words[bit / 64] & (1 << (bit % 64)) != 0
+        })
+    }
+}
+
+/// Trigrams every match of a query contains.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trigrams(Vec<u32>);
+
+impl Trigrams {
+    /// The trigrams required by a regular expression compiled with these
+    /// flags, as [`Query::matcher`](crate::Query::matcher) does. Empty when
+    /// the pattern does not pin any down, for example when it is shorter
+    /// than three characters or an alternation.
+    pub(crate) fn for_pattern(pattern: &str, case_sensitive: bool) -> Self {
+        let Ok(hir) = ParserBuilder::new()
+            .case_insensitive(!case_sensitive)
+            .multi_line(true)
+            .crlf(true)
+            .build()
+            .parse(pattern)
+        else {
+            return Self::default();
+        };
+        let mut literals = Vec::new();
+        if let Some(exact) = required(&hir, &mut literals) {
+            literals.push(exact);
+        }
+        let mut trigrams: Vec<u32> = literals
+            .iter()
+            .flat_map(|literal| trigrams_of(literal))
+            .collect();
+        trigrams.sort_unstable();
+        trigrams.dedup();
+        Self(trigrams)
+    }
+
+    /// Whether the query can rule out no file.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Distinct trigrams of `text` once folded, unordered.
+fn trigrams_of(text: &[u8]) -> Vec<u32> {
+    let mut trigrams = Vec::new();
+    let mut dedup_at = 1 << 16;
+    let (mut window, mut len) = (0_u32, 0);
+    for byte in Folded::new(text) {
+        window = (window << 8 | u32::from(byte)) & 0x00FF_FFFF;
+        len += 1;
+        if len < 3 {
+            continue;
+        }
+        trigrams.push(window);
+        // Keeps a large file's repeats from piling up
+        if trigrams.len() >= dedup_at {
+            trigrams.sort_unstable();
+            trigrams.dedup();
+            dedup_at = dedup_at.max(trigrams.len() * 2);
+        }
+    }
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+/// Bit of a filter of `bits` bits that records `trigram`.
+fn slot(trigram: u32, bits: usize) -> usize {
+    // Fibonacci hashing: the top bits of the product are well mixed
+    let hash = trigram.wrapping_mul(0x9E37_79B9);
+    (hash >> (32 - bits.trailing_zeros())) as usize
+}
+
+/// Bytes of text folded for trigrams.
+struct Folded<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Folded<'a> {
+    const fn new(text: &'a [u8]) -> Self {
+        Self { text, at: 0 }
+    }
+}
+
+impl Iterator for Folded<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let rest = &self.text[self.at..];
+        let (&byte, _) = rest.split_first()?;
+        let (folded, len) = if rest.starts_with(KELVIN) {
+            (b'k', KELVIN.len())
+        } else if rest.starts_with(LONG_S) {
+            (b's', LONG_S.len())
+        } else {
+            (byte.to_ascii_lowercase(), 1)
+        };
+        self.at += len;
+        Some(folded)
+    }
+}
+
+/// Collect into `literals` text that every match of `hir` contains. If
+/// every match is exactly the same text, return it instead, so the caller
+/// can join it to its neighbours.
+fn required(hir: &Hir, literals: &mut Vec<Vec<u8>>) -> Option<Vec<u8>> {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => Some(Vec::new()),
+        HirKind::Literal(literal) => Some(literal.0.to_vec()),
+        HirKind::Class(class) => single_letter(class),
+        HirKind::Capture(capture) => required(&capture.sub, literals),
+        HirKind::Repetition(repetition) => {
+            if repetition.min == 0 {
+                return None;
+            }
+            if let Some(exact) = required(&repetition.sub, literals) {
+                literals.push(exact);
+            }
+            None
+        }
+        HirKind::Concat(parts) => {
+            let (mut run, mut exact) = (Vec::new(), true);
+            for part in parts {
+                if let Some(text) = required(part, literals) {
+                    run.extend(text);
+                } else {
+                    literals.push(std::mem::take(&mut run));
+                    exact = false;
+                }
+            }
+            if exact {
+                return Some(run);
+            }
+            literals.push(run);
+            None
+        }
+        // Text required by one branch is not required by the others
+        HirKind::Alternation(_) => None,
+    }
+}
+
+/// The folded text of a class whose characters all fold to it, as a
+/// case-insensitive letter's class does.
+fn single_letter(class: &Class) -> Option<Vec<u8>> {
+    let mut folded: Option<Vec<u8>> = None;
+    let mut agrees = |text: &[u8]| {
+        let text: Vec<u8> = Folded::new(text).collect();
+        if let Some(first) = &folded {
+            return *first == text;
+        }
+        folded = Some(text);
+        true
+    };
+    match class {
+        Class::Unicode(class) => {
+            let count: u32 = class
+                .ranges()
+                .iter()
+                .map(|range| u32::from(range.end()) - u32::from(range.start()) + 1)
+                .sum();
+            if count > MAX_CASE_CLASS {
+                return None;
+            }
+            let mut buffer = [0; 4];
+            for range in class.ranges() {
+                for c in range.start()..=range.end() {
+                    if !agrees(c.encode_utf8(&mut buffer).as_bytes()) {
+                        return None;
+                    }
+                }
+            }
+        }
+        Class::Bytes(class) => {
+            let count: u32 = class
+                .ranges()
+                .iter()
+                .map(|range| u32::from(range.end()) - u32::from(range.start()) + 1)
+                .sum();
+            if count > MAX_CASE_CLASS {
+                return None;
+            }
+            for range in class.ranges() {
+                for byte in range.start()..=range.end() {
+                    if !agrees(&[byte]) {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+    folded
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+    use crate::Query;
+
+    /// Whether a file with `text` passes the filter for `query`, checked
+    /// against whether it has a match.
+    fn passes(query: &Query, text: &str) -> bool {
+        let matcher = query.matcher().unwrap();
+        let passes = TrigramFilter::new(text.as_bytes()).may_contain(matcher.trigrams());
+        // Filters never rule out a file with a match
+        assert!(passes || matcher.find_iter(text).next().is_none());
+        passes
+    }
+
+    fn trigrams(query: &Query) -> usize {
+        query.matcher().unwrap().trigrams().0.len()
+    }
+
+    #[test]
+    fn test_filters_rule_out_files_without_the_text() {
+        let text = "fn parse_item(input: &str) -> Item {\n    Item::Struct\n}\n";
+        assert!(passes(&Query::literal("parse_item"), text));
+        assert!(passes(&Query::literal("ITEM::STRUCT"), text));
+        assert!(!passes(&Query::literal("parse_items"), text));
+        let exact = Query {
+            case_sensitive: true,
+            ..Query::literal("item::struct")
+        };
+        // Case is folded, so the filter lets it through to the search
+        assert!(passes(&exact, text));
+        assert!(!passes(&Query::literal("render"), text));
+
+        assert!(passes(&Query::regex(r"parse_\w+\(input"), text));
+        assert!(!passes(&Query::regex(r"parse_\w+\(output"), text));
+        assert!(passes(&Query::regex(r"(?:Struct|Enum)"), text));
+        assert!(passes(&Query::regex(r"fn\s+render|Item"), text));
+        assert!(!passes(&Query::regex(r"(items){2}"), text));
+        assert!(passes(&Query::regex(r"\bItem\b"), text));
+
+        // Letters matched without case in other scripts
+        assert!(passes(&Query::literal("kelvin"), "\u{212A}elvin"));
+        assert!(passes(&Query::literal("mass"), "ma\u{17F}s"));
+        assert!(passes(&Query::literal("ÉTÉ"), "été"));
+        let exact = Query {
+            case_sensitive: true,
+            ..Query::literal("été")
+        };
+        assert!(!passes(&exact, "hiver"));
+
+        let binary = TrigramFilter::for_file(b"parse\0item");
+        assert!(!binary.may_contain(Query::literal("parse").matcher().unwrap().trigrams()));
+        let filter = TrigramFilter::new(text.as_bytes());
+        assert_eq!(
+            TrigramFilter::from_words(filter.words().to_vec()),
+            Some(filter)
+        );
+        assert!(TrigramFilter::from_words(vec![0; 3]).is_none());
+    }
+
+    #[test]
+    fn test_trigrams_of_queries() {
+        assert_eq!(trigrams(&Query::literal("abcd")), 2);
+        assert_eq!(trigrams(&Query::literal("ab")), 0);
+        assert_eq!(trigrams(&Query::regex("a.*b")), 0);
+        assert_eq!(trigrams(&Query::regex("(abc)?def")), 1);
+        assert_eq!(trigrams(&Query::regex("abc|abd")), 0);
+        assert_eq!(trigrams(&Query::regex("x(abc)+y")), 1);
+        assert_eq!(trigrams(&Query::regex("[a-z]+_item")), 3);
+        // Folded alike in both cases
+        assert_eq!(
+            Query::literal("Parse").matcher().unwrap().trigrams(),
+            Query {
+                case_sensitive: true,
+                ..Query::literal("pARSE")
+            }
+            .matcher()
+            .unwrap()
+            .trigrams()
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Mapping of highlight query captures to semantic token types.
+//!
+//! Grammars name their captures after the conventional tree-sitter highlight
+//! scopes (`keyword`, `function.method`, `comment.documentation`, ...). The
+//! first component picks the token type and the rest may add modifiers.
+
+/// Kind of a syntax token.
+///
+/// Mirrors the protocol's `TokenType` so that clients can map tokens to
+/// theme colors the same way as LSP semantic tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenType {
+    /// Comment.
+    Comment,
+    /// String literal.
+    String,
+    /// Language keyword (also boolean and null literals).
+    Keyword,
+    /// Numeric literal.
+    Number,
+    /// Regular expression literal.
+    Regexp,
+    /// Operator.
+    Operator,
+    /// Namespace or module.
+    Namespace,
+    /// Type name.
+    Type,
+    /// Struct type.
+    Struct,
+    /// Class type.
+    Class,
+    /// Interface type.
+    Interface,
+    /// Enum type.
+    Enum,
+    /// Enum member.
+    EnumMember,
+    /// Generic type parameter.
+    TypeParameter,
+    /// Function.
+    Function,
+    /// Method.
+    Method,
+    /// Macro, attribute or decorator.
+    Macro,
+    /// Variable.
+    Variable,
+    /// Function parameter.
+    Parameter,
+    /// Property or field.
+    Property,
+    /// Label.
+    Label,
+    /// Punctuation.
+    Punctuation,
+}
+
+/// Token modifier bits, matching the protocol's `TokenModifier` values.
+pub mod modifiers {
+    /// Symbol declaration.
+    pub const DECLARATION: u32 = 1;
+    /// Symbol definition.
+    pub const DEFINITION: u32 = 2;
+    /// Read-only symbol (constants).
+    pub const READONLY: u32 = 4;
+    /// Static member.
+    pub const STATIC: u32 = 8;
+    /// Deprecated symbol.
+    pub const DEPRECATED: u32 = 16;
+    /// Abstract member.
+    pub const ABSTRACT: u32 = 32;
+    /// Async function.
+    pub const ASYNC: u32 = 64;
+    /// Symbol being modified.
+    pub const MODIFICATION: u32 = 128;
+    /// Documentation comment.
+    pub const DOCUMENTATION: u32 = 256;
+    /// Standard library or built-in symbol.
+    pub const DEFAULT_LIBRARY: u32 = 512;
+}
+
+/// A highlighted span on a single line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntaxToken {
+    /// 0-based line number.
+    pub line: u32,
+    /// Start column in UTF-16 code units.
+    pub start_character: u32,
+    /// Length in UTF-16 code units.
+    pub length: u32,
+    /// Token type.
+    pub token_type: TokenType,
+    /// Bitmask of [`modifiers`].
+    pub modifiers: u32,
+}
+
+/// What a capture does to the text it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Highlight {
+    /// Paint the text as a token.
+    Token(TokenType, u32),
+    /// Remove highlighting painted by an enclosing capture (`@none`).
+    Clear,
+}
+
+/// Classify a capture by its name and the kind of the captured node.
+///
+/// Returns `None` for captures that should be ignored, such as `@embedded`
+/// or scopes without a token type; the enclosing highlight then shows.
+pub(crate) fn classify(capture: &str, node_kind: &str) -> Option<Highlight> {
+    use modifiers::{DEFAULT_LIBRARY, DOCUMENTATION, READONLY};
+    use TokenType as T;
+
+    let token = |token_type, modifiers| Some(Highlight::Token(token_type, modifiers));
+    let (scope, rest) = capture.split_once('.').unwrap_or((capture, ""));
+    match (scope, rest) {
+        ("none", _) => Some(Highlight::Clear),
+        ("comment", "documentation") => token(T::Comment, DOCUMENTATION),
+        ("comment", _) => token(T::Comment, 0),
+        ("string", "special.key") | ("property" | "field", _) => token(T::Property, 0),
+        ("string", "regexp" | "regex") => token(T::Regexp, 0),
+        // Markdown code spans and links are `text.*`
+        ("string" | "character", _) | ("text", "literal" | "uri") => token(T::String, 0),
+        ("text", "reference") | ("label", _) => token(T::Label, 0),
+        ("number" | "float", _) => token(T::Number, 0),
+        // Some grammars capture numeric literals as built-in constants
+        ("constant", "builtin") if is_numeric(node_kind) => token(T::Number, 0),
+        ("keyword" | "boolean" | "conditional" | "repeat" | "include" | "exception", _)
+        | ("text", "title")
+        | ("constant", "builtin") => token(T::Keyword, 0),
+        ("constant", _) => token(T::Variable, READONLY),
+        ("operator", _) => token(T::Operator, 0),
+        ("punctuation", _) => token(T::Punctuation, 0),
+        ("namespace" | "module", _) => token(T::Namespace, 0),
+        ("type", "builtin") => token(T::Type, DEFAULT_LIBRARY),
+        ("type", "parameter") => token(T::TypeParameter, 0),
+        ("type" | "constructor" | "tag", _) => token(T::Type, 0),
+        ("function", "builtin") => token(T::Function, DEFAULT_LIBRARY),
+        ("function", "method") | ("method", _) => token(T::Method, 0),
+        ("function", "macro") | ("attribute", _) => token(T::Macro, 0),
+        ("function", _) => token(T::Function, 0),
+        ("variable", "builtin") => token(T::Variable, DEFAULT_LIBRARY),
+        ("variable", "parameter") | ("parameter", _) => token(T::Parameter, 0),
+        ("variable", _) => token(T::Variable, 0),
+        _ => None,
+    }
+}
+
+fn is_numeric(node_kind: &str) -> bool {
+    ["integer", "float", "number"]
+        .iter()
+        .any(|kind| node_kind.contains(kind))
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_captures() {
+        assert_eq!(
+            classify("comment.documentation", "line_comment"),
+            Some(Highlight::Token(
+                TokenType::Comment,
+                modifiers::DOCUMENTATION
+            ))
+        );
+        assert_eq!(
+            classify("function.method", "field_identifier"),
+            Some(Highlight::Token(TokenType::Method, 0))
+        );
+        assert_eq!(
+            classify("constant.builtin", "integer_literal"),
+            Some(Highlight::Token(TokenType::Number, 0))
+        );
+        assert_eq!(
+            classify("constant.builtin", "true"),
+            Some(Highlight::Token(TokenType::Keyword, 0))
+        );
+        assert_eq!(
+            classify("none", "code_fence_content"),
+            Some(Highlight::Clear)
+        );
+        assert_eq!(classify("embedded", "template_substitution"), None);
+    }
+}
@@ -0,0 +1,173 @@
+//! Bundled tree-sitter grammars.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+/// A language with a bundled grammar and highlight query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Language {
+    /// Rust.
+    Rust,
+    /// TypeScript.
+    TypeScript,
+    /// TypeScript with JSX.
+    Tsx,
+    /// JavaScript, including JSX.
+    JavaScript,
+    /// JSON.
+    Json,
+    /// TOML.
+    Toml,
+    /// Markdown (block structure).
+    Markdown,
+    /// Python.
+    Python,
+}
+
+impl Language {
+    /// Every bundled language.
+    pub const ALL: [Self; 8] = [
+        Self::Rust,
+        Self::TypeScript,
+        Self::Tsx,
+        Self::JavaScript,
+        Self::Json,
+        Self::Toml,
+        Self::Markdown,
+        Self::Python,
+    ];
+
+    /// Detect the language of a file from its name.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        let language = match extension.to_ascii_lowercase().as_str() {
+            "rs" => Self::Rust,
+            "ts" | "mts" | "cts" => Self::TypeScript,
+            "tsx" => Self::Tsx,
+            "js" | "mjs" | "cjs" | "jsx" => Self::JavaScript,
+            "json" | "jsonc" => Self::Json,
+            "toml" => Self::Toml,
+            "md" | "markdown" => Self::Markdown,
+            "py" | "pyi" => Self::Python,
+            _ => return None,
+        };
+        Some(language)
+    }
+
+    /// Grammar for a language identifier, accepting dialects a bundled
+    /// grammar can parse ("javascriptreact", "jsonc").
+    pub fn from_id(id: &str) -> Option<Self> {
+        let language = match id {
+            "javascriptreact" => Self::JavaScript,
+            "jsonc" => Self::Json,
+            _ => return Self::ALL.into_iter().find(|language| language.id() == id),
+        };
+        Some(language)
+    }
+
+    /// Language identifier (LSP-style, e.g. "rust", "typescriptreact").
+    pub const fn id(self) -> &'static str {
+        match self {
+            Self::Rust => "rust",
+            Self::TypeScript => "typescript",
+            Self::Tsx => "typescriptreact",
+            Self::JavaScript => "javascript",
+            Self::Json => "json",
+            Self::Toml => "toml",
+            Self::Markdown => "markdown",
+            Self::Python => "python",
+        }
+    }
+
+    /// The tree-sitter grammar.
+    pub(crate) fn grammar(self) -> tree_sitter::Language {
+        match self {
+            Self::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Self::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Self::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Self::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            Self::Json => tree_sitter_json::LANGUAGE.into(),
+            Self::Toml => tree_sitter_toml_ng::LANGUAGE.into(),
+            Self::Markdown => tree_sitter_md::LANGUAGE.into(),
+            Self::Python => tree_sitter_python::LANGUAGE.into(),
+        }
+    }
+
+    /// Source of the highlight query.
+    ///
+    /// TypeScript's query only covers what it adds to JavaScript, so the
+    /// JavaScript query is appended; earlier patterns take precedence.
+    pub(crate) fn highlights_query(self) -> Cow<'static, str> {
+        match self {
+            Self::Rust => tree_sitter_rust::HIGHLIGHTS_QUERY.into(),
+            Self::TypeScript => format!(
+                "{}\n{}",
+                tree_sitter_typescript::HIGHLIGHTS_QUERY,
+                tree_sitter_javascript::HIGHLIGHT_QUERY
+            )
+            .into(),
+            Self::Tsx => format!(
+                "{}\n{}\n{}",
+                tree_sitter_typescript::HIGHLIGHTS_QUERY,
+                tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+                tree_sitter_javascript::HIGHLIGHT_QUERY
+            )
+            .into(),
+            Self::JavaScript => format!(
+                "{}\n{}",
+                tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+                tree_sitter_javascript::HIGHLIGHT_QUERY
+            )
+            .into(),
+            Self::Json => tree_sitter_json::HIGHLIGHTS_QUERY.into(),
+            Self::Toml => tree_sitter_toml_ng::HIGHLIGHTS_QUERY.into(),
+            Self::Markdown => tree_sitter_md::HIGHLIGHT_QUERY_BLOCK.into(),
+            Self::Python => tree_sitter_python::HIGHLIGHTS_QUERY.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(Language::from_path("src/main.rs"), Some(Language::Rust));
+        assert_eq!(Language::from_path("App.TSX"), Some(Language::Tsx));
+        assert_eq!(Language::from_path("README.md"), Some(Language::Markdown));
+        assert_eq!(Language::from_path("Makefile"), None);
+        assert_eq!(Language::from_path("image.png"), None);
+    }
+
+    #[test]
+    fn test_from_id() {
+        for language in Language::ALL {
+            assert_eq!(Language::from_id(language.id()), Some(language));
+        }
+        assert_eq!(Language::from_id("jsonc"), Some(Language::Json));
+        assert_eq!(Language::from_id("makefile"), None);
+    }
+
+    #[test]
+    fn test_bundled_queries_compile() {
+        for language in Language::ALL {
+            let source = language.highlights_query();
+            if let Err(e) = tree_sitter::Query::new(&language.grammar(), &source) {
+                panic!("{language}: {e}");
+            }
+        }
+    }
+}
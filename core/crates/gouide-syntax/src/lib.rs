@@ -0,0 +1,49 @@
+//! Gouide syntax analysis.
+//!
+//! This crate keeps an incremental tree-sitter syntax tree per open buffer.
+//! Edits applied to a buffer are fed to its tree as they happen, so the next
+//! query only reparses the changed region. Highlight queries bundled with each
+//! grammar are mapped onto protocol-agnostic [`TokenType`]s and modifiers. The
+//! same trees give folding ranges, outlines, bracket pairs and selection
+//! ranges. Files that are not open can be outlined from their text alone.
+
+mod highlight;
+mod language;
+mod lines;
+mod manager;
+mod outline;
+mod structure;
+
+use thiserror::Error;
+
+pub use highlight::{modifiers, SyntaxToken, TokenType};
+pub use language::Language;
+pub use manager::{SyntaxManager, Tokens};
+pub use outline::{outline_text, Symbol, SymbolKind};
+pub use structure::{BracketPair, FoldKind, FoldingRange};
+
+/// Errors that can occur during syntax analysis.
+#[derive(Error, Debug)]
+pub enum SyntaxError {
+    /// A bundled grammar is incompatible with the tree-sitter runtime.
+    #[error("Grammar for {language} is incompatible: {message}")]
+    Grammar {
+        /// Language identifier.
+        language: &'static str,
+        /// Description of the problem.
+        message: String,
+    },
+
+    /// A bundled highlight query failed to compile.
+    #[error("Highlight query for {language} is invalid: {message}")]
+    Query {
+        /// Language identifier.
+        language: &'static str,
+        /// Description of the problem.
+        message: String,
+    },
+
+    /// The parser gave up (it was cancelled or timed out).
+    #[error("Parsing {0} failed")]
+    ParseFailed(String),
+}
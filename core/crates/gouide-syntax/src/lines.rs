@@ -0,0 +1,94 @@
+//! Conversion between tree-sitter byte points and protocol positions.
+
+use gouide_workspace::Position;
+use tree_sitter::Point;
+
+/// Byte offsets of line starts, splitting on `\n`, `\r\n` and `\r` like the
+/// buffer does.
+pub(crate) struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        let bytes = text.as_bytes();
+        let mut starts = vec![0];
+        let mut i = 0;
+        while let Some(offset) = memchr::memchr2(b'\n', b'\r', &bytes[i..]) {
+            i += offset;
+            if bytes[i] == b'\r' && bytes.get(i + 1) == Some(&b'\n') {
+                i += 1;
+            }
+            i += 1;
+            starts.push(i);
+        }
+        Self { text, starts }
+    }
+
+    pub(crate) const fn text(&self) -> &'a str {
+        self.text
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn last_line(&self) -> u32 {
+        (self.starts.len() - 1) as u32
+    }
+
+    /// Bytes of a line including its terminator.
+    pub(crate) fn span(&self, line: u32) -> std::ops::Range<usize> {
+        let line = line as usize;
+        let end = self
+            .starts
+            .get(line + 1)
+            .copied()
+            .unwrap_or(self.text.len());
+        self.starts[line]..end
+    }
+
+    /// Bytes of a line without its terminator.
+    pub(crate) fn content(&self, line: u32) -> std::ops::Range<usize> {
+        let span = self.span(line);
+        let content = self.text[span.clone()].trim_end_matches(['\n', '\r']);
+        span.start..span.start + content.len()
+    }
+
+    /// Position (UTF-16 column) of a byte offset.
+    pub(crate) fn position(&self, byte: usize) -> Position {
+        let byte = byte.min(self.text.len());
+        let line = self.starts.partition_point(|&start| start <= byte) - 1;
+        #[allow(clippy::cast_possible_truncation)]
+        let line = line as u32;
+        let content = self.content(line);
+        let end = byte.clamp(content.start, content.end);
+        Position::new(line, utf16_len(&self.text[content.start..end]))
+    }
+
+    /// Tree-sitter point of a position. Columns past the end of the line are
+    /// clamped to it.
+    pub(crate) fn point(&self, position: Position) -> Point {
+        let line = position.line.min(self.last_line());
+        let content = self.content(line);
+        let mut character = 0;
+        let mut column = 0;
+        for c in self.text[content].chars() {
+            if character >= position.character {
+                break;
+            }
+            character += utf16_len(c.encode_utf8(&mut [0; 4]));
+            column += c.len_utf8();
+        }
+        Point::new(line as usize, column)
+    }
+}
+
+/// Line of a tree-sitter point.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) const fn row(point: Point) -> u32 {
+    point.row as u32
+}
+
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn utf16_len(text: &str) -> u32 {
+    text.chars().map(char::len_utf16).sum::<usize>() as u32
+}
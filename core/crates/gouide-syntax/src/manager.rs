@@ -0,0 +1,724 @@
+//! Per-buffer syntax trees and token queries.
+
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use gouide_workspace::{ByteEdit, BytePoint, Position, TextRange};
+use parking_lot::Mutex;
+use streaming_iterator::StreamingIterator;
+use tracing::debug;
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, Tree};
+
+use crate::highlight::{classify, Highlight, SyntaxToken, TokenType};
+use crate::lines::{row, utf16_len, LineIndex};
+use crate::outline::{outline, Symbol};
+use crate::structure::{
+    bracket_pairs, folding_ranges, selection_ranges, BracketPair, FoldingRange,
+};
+use crate::{Language, SyntaxError};
+
+/// Tokens for part of a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tokens {
+    /// Tokens in document order, each on a single line.
+    pub tokens: Vec<SyntaxToken>,
+    /// Range actually covered. Requests are expanded to whole lines.
+    pub range: TextRange,
+}
+
+/// Keeps an incremental syntax tree per buffer.
+///
+/// Trees are built lazily on the first token request. After that, every
+/// applied edit is replayed onto the tree with [`SyntaxManager::edit`], and the
+/// next request reparses only what changed. If an edit is missed (the buffer
+/// was reloaded from disk, or versions skipped), the tree is rebuilt.
+#[derive(Default)]
+pub struct SyntaxManager {
+    /// Compiled highlight queries by language.
+    queries: Mutex<HashMap<Language, Arc<Query>>>,
+    /// Documents by buffer ID.
+    documents: Mutex<HashMap<String, Arc<Mutex<Document>>>>,
+}
+
+impl SyntaxManager {
+    /// Create an empty syntax manager.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record edits that took a buffer to `version`.
+    ///
+    /// `edits` must be the edits applied to `version - 1`, in order.
+    pub fn edit(&self, buffer_id: &str, version: u64, edits: &[ByteEdit]) {
+        let Some(document) = self.documents.lock().get(buffer_id).cloned() else {
+            return;
+        };
+        document.lock().edit(version, edits);
+    }
+
+    /// Tokens for the lines of `range` in `text`, the content of a buffer at
+    /// `version`.
+    pub fn tokens(
+        &self,
+        buffer_id: &str,
+        language: Language,
+        version: u64,
+        text: &str,
+        range: TextRange,
+    ) -> Result<Tokens, SyntaxError> {
+        let query = self.query(language)?;
+        self.with_document(buffer_id, language, version, text, |document, lines| {
+            let first = range.start.line.min(lines.last_line());
+            let last = range.end.line.clamp(first, lines.last_line());
+            document.highlight(&query, text, lines, first, last)
+        })
+    }
+
+    /// Tokens for the lines of `visible` whose highlighting may have changed
+    /// since the buffer was at version `since`.
+    ///
+    /// Returns `None` if nothing in `visible` changed. Lines below an edit
+    /// that added or removed lines count as changed, since their positions
+    /// moved. If the changes since `since` are no longer known, all of
+    /// `visible` is returned.
+    pub fn changed_tokens(
+        &self,
+        buffer_id: &str,
+        language: Language,
+        version: u64,
+        text: &str,
+        visible: TextRange,
+        since: u64,
+    ) -> Result<Option<Tokens>, SyntaxError> {
+        let query = self.query(language)?;
+        self.with_document(buffer_id, language, version, text, |document, lines| {
+            let changed = document.changed_since(since)?;
+            let first = changed.first.max(visible.start.line);
+            let last = changed.last.min(visible.end.line).min(lines.last_line());
+            (first <= last).then(|| document.highlight(&query, text, lines, first, last))
+        })
+    }
+
+    /// Folding ranges of a buffer at `version`.
+    pub fn folding_ranges(
+        &self,
+        buffer_id: &str,
+        language: Language,
+        version: u64,
+        text: &str,
+    ) -> Result<Vec<FoldingRange>, SyntaxError> {
+        self.with_tree(buffer_id, language, version, text, |root, lines| {
+            folding_ranges(language, root, lines)
+        })
+    }
+
+    /// Outline of a buffer at `version`.
+    pub fn outline(
+        &self,
+        buffer_id: &str,
+        language: Language,
+        version: u64,
+        text: &str,
+    ) -> Result<Vec<Symbol>, SyntaxError> {
+        self.with_tree(buffer_id, language, version, text, |root, lines| {
+            outline(language, root, lines)
+        })
+    }
+
+    /// Bracket pairs of a buffer at `version` with a bracket on the lines of
+    /// `range`.
+    pub fn bracket_pairs(
+        &self,
+        buffer_id: &str,
+        language: Language,
+        version: u64,
+        text: &str,
+        range: TextRange,
+    ) -> Result<Vec<BracketPair>, SyntaxError> {
+        self.with_tree(buffer_id, language, version, text, |root, lines| {
+            bracket_pairs(root, lines, range.start.line, range.end.line)
+        })
+    }
+
+    /// Selection-expansion ranges of a buffer at `version` for each of
+    /// `positions`, innermost first.
+    pub fn selection_ranges(
+        &self,
+        buffer_id: &str,
+        language: Language,
+        version: u64,
+        text: &str,
+        positions: &[Position],
+    ) -> Result<Vec<Vec<TextRange>>, SyntaxError> {
+        self.with_tree(buffer_id, language, version, text, |root, lines| {
+            positions
+                .iter()
+                .map(|&position| selection_ranges(root, lines, position))
+                .collect()
+        })
+    }
+
+    /// Forget a buffer's tree.
+    pub fn close(&self, buffer_id: &str) {
+        self.documents.lock().remove(buffer_id);
+    }
+
+    /// Number of buffers with a syntax tree.
+    pub fn document_count(&self) -> usize {
+        self.documents.lock().len()
+    }
+
+    /// Run `f` on a buffer's document, parsed up to `version`.
+    fn with_document<R>(
+        &self,
+        buffer_id: &str,
+        language: Language,
+        version: u64,
+        text: &str,
+        f: impl FnOnce(&Document, &LineIndex<'_>) -> R,
+    ) -> Result<R, SyntaxError> {
+        let document = {
+            let mut documents = self.documents.lock();
+            let document = match documents.entry(buffer_id.to_string()) {
+                Entry::Occupied(entry) => Arc::clone(entry.get()),
+                Entry::Vacant(entry) => {
+                    Arc::clone(entry.insert(Arc::new(Mutex::new(Document::new(language)?))))
+                }
+            };
+            drop(documents);
+            document
+        };
+        let mut document = document.lock();
+        document.parse(language, version, text)?;
+        let result = f(&document, &LineIndex::new(text));
+        drop(document);
+        Ok(result)
+    }
+
+    /// Run `f` on the root of a buffer's tree, parsed up to `version`.
+    fn with_tree<R: Default>(
+        &self,
+        buffer_id: &str,
+        language: Language,
+        version: u64,
+        text: &str,
+        f: impl FnOnce(Node<'_>, &LineIndex<'_>) -> R,
+    ) -> Result<R, SyntaxError> {
+        self.with_document(buffer_id, language, version, text, |document, lines| {
+            document
+                .tree
+                .as_ref()
+                .map_or_else(R::default, |tree| f(tree.root_node(), lines))
+        })
+    }
+
+    /// The compiled highlight query for a language.
+    fn query(&self, language: Language) -> Result<Arc<Query>, SyntaxError> {
+        let mut queries = self.queries.lock();
+        if let Some(query) = queries.get(&language) {
+            return Ok(Arc::clone(query));
+        }
+        let query = Query::new(&language.grammar(), &language.highlights_query()).map_err(|e| {
+            SyntaxError::Query {
+                language: language.id(),
+                message: e.to_string(),
+            }
+        })?;
+        let query = Arc::new(query);
+        queries.insert(language, Arc::clone(&query));
+        drop(queries);
+        Ok(query)
+    }
+}
+
+/// Changes kept per document for [`SyntaxManager::changed_tokens`].
+const CHANGE_HISTORY: usize = 64;
+
+/// An inclusive range of lines. `last == u32::MAX` reaches the end of the
+/// document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LineSpan {
+    first: u32,
+    last: u32,
+}
+
+impl LineSpan {
+    const ALL: Self = Self {
+        first: 0,
+        last: u32::MAX,
+    };
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            first: self.first.min(other.first),
+            last: self.last.max(other.last),
+        }
+    }
+
+    /// Lines an edit touches in the new text, including every line below it
+    /// if it added or removed lines.
+    fn of_edit(edit: &ByteEdit) -> Self {
+        let last = if edit.old_end.line == edit.new_end.line {
+            edit.new_end.line
+        } else {
+            u32::MAX
+        };
+        Self {
+            first: edit.start.line,
+            last,
+        }
+    }
+}
+
+/// Lines whose syntax changed between two parsed versions.
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    from: u64,
+    to: u64,
+    lines: LineSpan,
+}
+
+/// Syntax state of one buffer.
+struct Document {
+    language: Language,
+    parser: Parser,
+    /// Tree for `version`, if any. It may have been edited since it was
+    /// parsed.
+    tree: Option<Tree>,
+    /// Buffer version the tree describes.
+    version: u64,
+    /// Version that was last parsed.
+    parsed_version: u64,
+    /// Lines touched by edits since the last parse, if the tree was edited.
+    edited: Option<LineSpan>,
+    /// Recent parses, oldest first.
+    changes: VecDeque<Change>,
+}
+
+impl Document {
+    fn new(language: Language) -> Result<Self, SyntaxError> {
+        let mut parser = Parser::new();
+        set_language(&mut parser, language)?;
+        Ok(Self {
+            language,
+            parser,
+            tree: None,
+            version: 0,
+            parsed_version: 0,
+            edited: None,
+            changes: VecDeque::new(),
+        })
+    }
+
+    fn edit(&mut self, version: u64, edits: &[ByteEdit]) {
+        match self.tree.as_mut() {
+            Some(tree) if self.version + 1 == version => {
+                for edit in edits {
+                    tree.edit(&input_edit(edit));
+                    let lines = LineSpan::of_edit(edit);
+                    self.edited = Some(self.edited.map_or(lines, |e| e.union(lines)));
+                }
+            }
+            _ => {
+                self.tree = None;
+                self.edited = None;
+            }
+        }
+        self.version = version;
+    }
+
+    /// Bring the tree up to date with `text` at `version`.
+    fn parse(&mut self, language: Language, version: u64, text: &str) -> Result<(), SyntaxError> {
+        if language != self.language {
+            // The buffer was renamed to a file of another language
+            set_language(&mut self.parser, language)?;
+            self.language = language;
+            self.tree = None;
+        }
+        if self.tree.is_some() && self.version == version && self.edited.is_none() {
+            return Ok(());
+        }
+
+        let old = self.tree.take().filter(|_| self.version == version);
+        debug!(
+            language = %language,
+            version,
+            incremental = old.is_some(),
+            "Parsing buffer"
+        );
+        let tree = self
+            .parser
+            .parse(text, old.as_ref())
+            .ok_or_else(|| SyntaxError::ParseFailed(language.id().to_string()))?;
+        let lines = match (&old, self.edited) {
+            (Some(old), Some(edited)) => old.changed_ranges(&tree).fold(edited, |lines, range| {
+                lines.union(LineSpan {
+                    first: row(range.start_point),
+                    last: row(range.end_point),
+                })
+            }),
+            _ => LineSpan::ALL,
+        };
+
+        if self.changes.len() == CHANGE_HISTORY {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(Change {
+            from: self.parsed_version,
+            to: version,
+            lines,
+        });
+        self.tree = Some(tree);
+        self.version = version;
+        self.parsed_version = version;
+        self.edited = None;
+        Ok(())
+    }
+
+    /// Lines changed since `since`, or `None` if the parsed version is
+    /// `since`.
+    fn changed_since(&self, since: u64) -> Option<LineSpan> {
+        if since == self.parsed_version {
+            return None;
+        }
+        let mut changed: Option<LineSpan> = None;
+        let mut at = self.parsed_version;
+        for change in self.changes.iter().rev() {
+            if change.to != at || change.from >= change.to {
+                break;
+            }
+            changed = Some(changed.map_or(change.lines, |c| c.union(change.lines)));
+            at = change.from;
+            if at == since {
+                return changed;
+            }
+        }
+        Some(LineSpan::ALL)
+    }
+
+    /// Highlighted tokens for lines `first..=last` of the parsed tree.
+    fn highlight(
+        &self,
+        query: &Query,
+        text: &str,
+        lines: &LineIndex<'_>,
+        first: u32,
+        last: u32,
+    ) -> Tokens {
+        let tokens = self.tree.as_ref().map_or_else(Vec::new, |tree| {
+            highlight(query, tree.root_node(), text, lines, first, last)
+        });
+        let end_character = utf16_len(&text[lines.content(last)]);
+        Tokens {
+            tokens,
+            range: TextRange::new(Position::new(first, 0), Position::new(last, end_character)),
+        }
+    }
+}
+
+pub(crate) fn set_language(parser: &mut Parser, language: Language) -> Result<(), SyntaxError> {
+    parser
+        .set_language(&language.grammar())
+        .map_err(|e| SyntaxError::Grammar {
+            language: language.id(),
+            message: e.to_string(),
+        })
+}
+
+fn input_edit(edit: &ByteEdit) -> InputEdit {
+    let point = |p: BytePoint| Point::new(p.line as usize, p.column);
+    InputEdit {
+        start_byte: edit.start_byte,
+        old_end_byte: edit.old_end_byte,
+        new_end_byte: edit.new_end_byte,
+        start_position: point(edit.start),
+        old_end_position: point(edit.old_end),
+        new_end_position: point(edit.new_end),
+    }
+}
+
+/// Run the highlight query over lines `first..=last` and split the result
+/// into single-line tokens.
+///
+/// Captures are painted outermost first, so nested captures (an escape in a
+/// string) override their parents. When several patterns capture the same
+/// node, the most specific scope wins (`comment.documentation` over
+/// `comment`), then the first pattern in the query.
+fn highlight(
+    query: &Query,
+    root: Node<'_>,
+    text: &str,
+    lines: &LineIndex<'_>,
+    first: u32,
+    last: u32,
+) -> Vec<SyntaxToken> {
+    let start = lines.span(first).start;
+    let end = lines.span(last).end;
+
+    let mut spans = Vec::new();
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(start..end);
+    let names = query.capture_names();
+    let mut captures = cursor.captures(query, root, text.as_bytes());
+    while let Some((found, index)) = captures.next() {
+        let capture = found.captures[*index];
+        let node = capture.node;
+        let name = names[capture.index as usize];
+        let Some(highlight) = classify(name, node.kind()) else {
+            continue;
+        };
+        let (from, to) = (node.start_byte().max(start), node.end_byte().min(end));
+        if from < to {
+            let specificity = Reverse(name.matches('.').count());
+            spans.push((from, to, specificity, found.pattern_index, highlight));
+        }
+    }
+    spans.sort_by_key(|&(from, to, specificity, pattern, _)| {
+        (from, Reverse(to), specificity, pattern)
+    });
+
+    let mut paint: Vec<Option<(TokenType, u32)>> = vec![None; end - start];
+    let mut previous = None;
+    for (from, to, _, _, highlight) in spans {
+        if previous == Some((from, to)) {
+            continue;
+        }
+        previous = Some((from, to));
+        let style = match highlight {
+            Highlight::Token(token_type, modifiers) => Some((token_type, modifiers)),
+            Highlight::Clear => None,
+        };
+        paint[from - start..to - start].fill(style);
+    }
+
+    let mut tokens = Vec::new();
+    for line in first..=last {
+        let content = lines.content(line);
+        let mut character = 0;
+        let mut i = content.start;
+        while i < content.end {
+            let style = paint[i - start];
+            let mut j = i + 1;
+            while j < content.end && paint[j - start] == style {
+                j += 1;
+            }
+            let length = utf16_len(&text[i..j]);
+            if let Some((token_type, modifiers)) = style {
+                tokens.push(SyntaxToken {
+                    line,
+                    start_character: character,
+                    length,
+                    token_type,
+                    modifiers,
+                });
+            }
+            character += length;
+            i = j;
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use crate::modifiers;
+
+    use super::*;
+
+    fn lines(first: u32, last: u32) -> TextRange {
+        TextRange::new(Position::new(first, 0), Position::new(last, 0))
+    }
+
+    /// (line, text, type) for each token.
+    fn describe(text: &str, tokens: &[SyntaxToken]) -> Vec<(u32, String, TokenType)> {
+        let lines: Vec<&str> = text.lines().collect();
+        tokens
+            .iter()
+            .map(|t| {
+                let line: Vec<u16> = lines[t.line as usize].encode_utf16().collect();
+                let start = t.start_character as usize;
+                let word = String::from_utf16(&line[start..start + t.length as usize]).unwrap();
+                (t.line, word, t.token_type)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rust_tokens() {
+        let manager = SyntaxManager::new();
+        let text = "/// Docs\nfn main() {\n    let s = \"héllo\"; // x\n}\n";
+        let result = manager
+            .tokens("b1", Language::Rust, 1, text, lines(0, 2))
+            .unwrap();
+        let tokens = describe(text, &result.tokens);
+
+        assert!(tokens.contains(&(0, "/// Docs".to_string(), TokenType::Comment)));
+        assert_eq!(
+            result.tokens[0].modifiers & modifiers::DOCUMENTATION,
+            modifiers::DOCUMENTATION
+        );
+        assert!(tokens.contains(&(1, "fn".to_string(), TokenType::Keyword)));
+        assert!(tokens.contains(&(1, "main".to_string(), TokenType::Function)));
+        assert!(tokens.contains(&(2, "\"héllo\"".to_string(), TokenType::String)));
+        assert!(tokens.contains(&(2, "// x".to_string(), TokenType::Comment)));
+        assert!(tokens.iter().all(|(line, _, _)| *line <= 2));
+        assert_eq!(
+            result.range,
+            TextRange::new(Position::new(0, 0), Position::new(2, 25))
+        );
+    }
+
+    #[test]
+    fn test_incremental_edit() {
+        let manager = SyntaxManager::new();
+        let before = "let a = 1;\n";
+        manager
+            .tokens("b1", Language::TypeScript, 1, before, lines(0, 0))
+            .unwrap();
+
+        // Replace "1" with "'one'"
+        let after = "let a = 'one';\n";
+        manager.edit(
+            "b1",
+            2,
+            &[ByteEdit {
+                start_byte: 8,
+                old_end_byte: 9,
+                new_end_byte: 13,
+                start: BytePoint::new(0, 8),
+                old_end: BytePoint::new(0, 9),
+                new_end: BytePoint::new(0, 13),
+            }],
+        );
+        let result = manager
+            .tokens("b1", Language::TypeScript, 2, after, lines(0, 0))
+            .unwrap();
+        assert!(describe(after, &result.tokens).contains(&(
+            0,
+            "'one'".to_string(),
+            TokenType::String
+        )));
+
+        manager.close("b1");
+        assert_eq!(manager.document_count(), 0);
+    }
+
+    #[test]
+    fn test_changed_tokens_cover_edited_lines() {
+        let manager = SyntaxManager::new();
+        let before = "fn a() {}\nfn b() {}\nfn c() {}\n";
+        manager
+            .tokens("b1", Language::Rust, 1, before, lines(0, 0))
+            .unwrap();
+
+        // Rename b to bb: only line 1 changes
+        let after = "fn a() {}\nfn bb() {}\nfn c() {}\n";
+        manager.edit(
+            "b1",
+            2,
+            &[ByteEdit {
+                start_byte: 13,
+                old_end_byte: 14,
+                new_end_byte: 15,
+                start: BytePoint::new(1, 3),
+                old_end: BytePoint::new(1, 4),
+                new_end: BytePoint::new(1, 5),
+            }],
+        );
+        let changed = manager
+            .changed_tokens("b1", Language::Rust, 2, after, lines(0, 3), 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(changed.range.start.line, 1);
+        assert_eq!(changed.range.end.line, 1);
+        assert!(describe(after, &changed.tokens).contains(&(
+            1,
+            "bb".to_string(),
+            TokenType::Function
+        )));
+
+        // Nothing new since version 2, and nothing changed on line 2
+        assert_eq!(
+            manager
+                .changed_tokens("b1", Language::Rust, 2, after, lines(0, 3), 2)
+                .unwrap(),
+            None
+        );
+
+        // Inserting a line moves everything below it
+        let inserted = "fn a() {}\n\nfn bb() {}\nfn c() {}\n";
+        manager.edit(
+            "b1",
+            3,
+            &[ByteEdit {
+                start_byte: 10,
+                old_end_byte: 10,
+                new_end_byte: 11,
+                start: BytePoint::new(1, 0),
+                old_end: BytePoint::new(1, 0),
+                new_end: BytePoint::new(2, 0),
+            }],
+        );
+        let changed = manager
+            .changed_tokens("b1", Language::Rust, 3, inserted, lines(3, 3), 2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(changed.range.start.line, 3);
+        assert!(describe(inserted, &changed.tokens).contains(&(
+            3,
+            "c".to_string(),
+            TokenType::Function
+        )));
+        assert_eq!(
+            manager
+                .changed_tokens("b1", Language::Rust, 3, inserted, lines(0, 0), 2)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_missed_edit_rebuilds_tree() {
+        let manager = SyntaxManager::new();
+        manager
+            .tokens("b1", Language::Python, 1, "x = 1\n", lines(0, 0))
+            .unwrap();
+        // Version 2 was never reported as an edit
+        manager.edit("b1", 3, &[]);
+        let text = "def f():\n    pass\n";
+        let result = manager
+            .tokens("b1", Language::Python, 3, text, lines(0, 1))
+            .unwrap();
+        let tokens = describe(text, &result.tokens);
+        assert!(tokens.contains(&(0, "def".to_string(), TokenType::Keyword)));
+        assert!(tokens.contains(&(1, "pass".to_string(), TokenType::Keyword)));
+    }
+
+    #[test]
+    fn test_markdown_and_json_tokens() {
+        let manager = SyntaxManager::new();
+        let text = "# Title\n\n```\ncode\n```\n";
+        let result = manager
+            .tokens("md", Language::Markdown, 1, text, lines(0, 4))
+            .unwrap();
+        let tokens = describe(text, &result.tokens);
+        assert!(tokens.contains(&(0, "Title".to_string(), TokenType::Keyword)));
+        assert!(!tokens.iter().any(|(line, _, _)| *line == 3));
+
+        let text = "{\"key\": true}";
+        let result = manager
+            .tokens("json", Language::Json, 1, text, lines(0, 0))
+            .unwrap();
+        let tokens = describe(text, &result.tokens);
+        assert!(tokens.contains(&(0, "\"key\"".to_string(), TokenType::Property)));
+        assert!(tokens.contains(&(0, "true".to_string(), TokenType::Keyword)));
+    }
+}
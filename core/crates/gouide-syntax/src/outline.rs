@@ -0,0 +1,566 @@
+//! Document outlines.
+//!
+//! Each language lists the nodes that declare something worth showing in an
+//! outline. A symbol's children are the symbols declared inside it, so an
+//! `impl` block holds its methods and a Markdown section its subsections.
+
+use gouide_workspace::TextRange;
+use tree_sitter::{Node, Parser};
+
+use crate::lines::LineIndex;
+use crate::manager::set_language;
+use crate::structure::range;
+use crate::{Language, SyntaxError};
+
+/// Kind of a symbol, a subset of the LSP symbol kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    /// Module.
+    Module,
+    /// Namespace.
+    Namespace,
+    /// Class.
+    Class,
+    /// Method.
+    Method,
+    /// Property.
+    Property,
+    /// Struct or class field.
+    Field,
+    /// Constructor.
+    Constructor,
+    /// Enum.
+    Enum,
+    /// Interface or trait.
+    Interface,
+    /// Function or macro.
+    Function,
+    /// Variable.
+    Variable,
+    /// Constant.
+    Constant,
+    /// String value, or a Markdown heading.
+    String,
+    /// Number value.
+    Number,
+    /// Boolean value.
+    Boolean,
+    /// Array value.
+    Array,
+    /// Object value, table, or `impl` block.
+    Object,
+    /// Null value.
+    Null,
+    /// Enum member.
+    EnumMember,
+    /// Struct.
+    Struct,
+    /// Type alias.
+    TypeParameter,
+}
+
+/// A symbol in a document outline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// Symbol name.
+    pub name: String,
+    /// Extra information, such as a function's signature.
+    pub detail: String,
+    /// Symbol kind.
+    pub kind: SymbolKind,
+    /// The whole declaration.
+    pub range: TextRange,
+    /// The part to select when navigating to the symbol, usually its name.
+    pub selection_range: TextRange,
+    /// Symbols declared inside this one.
+    pub children: Vec<Self>,
+}
+
+/// The outline of a tree.
+pub(crate) fn outline(language: Language, root: Node<'_>, lines: &LineIndex<'_>) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    collect(language, root, lines, &mut symbols);
+    symbols
+}
+
+/// The outline of `text`, parsed from scratch. Meant for files that are not
+/// open, which have no tree to reuse.
+pub fn outline_text(language: Language, text: &str) -> Result<Vec<Symbol>, SyntaxError> {
+    let mut parser = Parser::new();
+    set_language(&mut parser, language)?;
+    let tree = parser
+        .parse(text, None)
+        .ok_or_else(|| SyntaxError::ParseFailed(language.id().to_string()))?;
+    Ok(outline(language, tree.root_node(), &LineIndex::new(text)))
+}
+
+fn collect(language: Language, node: Node<'_>, lines: &LineIndex<'_>, out: &mut Vec<Symbol>) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        match declaration(language, child, lines) {
+            Some(mut symbol) => {
+                collect(language, child, lines, &mut symbol.children);
+                out.push(symbol);
+            }
+            None => collect(language, child, lines, out),
+        }
+    }
+}
+
+/// What a node declares.
+struct Declaration<'tree> {
+    kind: SymbolKind,
+    /// Node holding the name.
+    name: Node<'tree>,
+    /// Name, if it is not just the name node's text.
+    label: Option<String>,
+    /// Node spanning the range shown as detail.
+    detail: Option<(Node<'tree>, Node<'tree>)>,
+}
+
+impl<'tree> Declaration<'tree> {
+    const fn new(kind: SymbolKind, name: Node<'tree>) -> Self {
+        Self {
+            kind,
+            name,
+            label: None,
+            detail: None,
+        }
+    }
+
+    fn signature(mut self, node: Node<'tree>) -> Self {
+        if let Some(parameters) = node.child_by_field_name("parameters") {
+            let end = node
+                .child_by_field_name("return_type")
+                .unwrap_or(parameters);
+            self.detail = Some((parameters, end));
+        }
+        self
+    }
+
+    fn typed(mut self, node: Node<'tree>) -> Self {
+        self.detail = node.child_by_field_name("type").map(|t| (t, t));
+        self
+    }
+}
+
+fn declaration(language: Language, node: Node<'_>, lines: &LineIndex<'_>) -> Option<Symbol> {
+    let text = lines.text();
+    let declaration = match language {
+        Language::Rust => rust(node, text),
+        Language::TypeScript | Language::Tsx | Language::JavaScript => script(node, text),
+        Language::Python => python(node, text),
+        Language::Json => json(node, text),
+        Language::Toml => toml(node, text),
+        Language::Markdown => markdown(node, text),
+    }?;
+
+    let name = declaration
+        .label
+        .unwrap_or_else(|| text[declaration.name.byte_range()].to_string());
+    let detail = declaration
+        .detail
+        .map(|(start, end)| collapse(&text[start.start_byte()..end.end_byte()]))
+        .unwrap_or_default();
+    Some(Symbol {
+        name: collapse(&name),
+        detail,
+        kind: declaration.kind,
+        range: range(node, lines),
+        selection_range: range(declaration.name, lines),
+        children: Vec::new(),
+    })
+}
+
+fn rust<'tree>(node: Node<'tree>, text: &str) -> Option<Declaration<'tree>> {
+    use SymbolKind as K;
+
+    let name = node.child_by_field_name("name");
+    let in_impl = || {
+        node.parent()
+            .filter(|p| p.kind() == "declaration_list")
+            .and_then(|p| p.parent())
+            .is_some_and(|p| matches!(p.kind(), "impl_item" | "trait_item"))
+    };
+    let declaration = match node.kind() {
+        "mod_item" => Declaration::new(K::Module, name?),
+        "struct_item" | "union_item" => Declaration::new(K::Struct, name?),
+        "enum_item" => Declaration::new(K::Enum, name?),
+        "enum_variant" => Declaration::new(K::EnumMember, name?),
+        "trait_item" => Declaration::new(K::Interface, name?),
+        "function_item" | "function_signature_item" => {
+            let kind = if in_impl() { K::Method } else { K::Function };
+            Declaration::new(kind, name?).signature(node)
+        }
+        "field_declaration" => Declaration::new(K::Field, name?).typed(node),
+        "const_item" => Declaration::new(K::Constant, name?).typed(node),
+        "static_item" => Declaration::new(K::Variable, name?).typed(node),
+        "type_item" | "associated_type" => Declaration::new(K::TypeParameter, name?),
+        "macro_definition" => Declaration::new(K::Function, name?),
+        "impl_item" => {
+            let self_type = node.child_by_field_name("type")?;
+            let mut declaration = Declaration::new(K::Object, self_type);
+            declaration.label = Some(node.child_by_field_name("trait").map_or_else(
+                || format!("impl {}", node_text(self_type, text)),
+                |t| {
+                    format!(
+                        "impl {} for {}",
+                        node_text(t, text),
+                        node_text(self_type, text)
+                    )
+                },
+            ));
+            declaration
+        }
+        _ => return None,
+    };
+    Some(declaration)
+}
+
+fn script<'tree>(node: Node<'tree>, text: &str) -> Option<Declaration<'tree>> {
+    use SymbolKind as K;
+
+    let name = node.child_by_field_name("name");
+    let declaration = match node.kind() {
+        "function_declaration" | "generator_function_declaration" | "function_signature" => {
+            Declaration::new(K::Function, name?).signature(node)
+        }
+        "class_declaration" | "abstract_class_declaration" | "class" => {
+            Declaration::new(K::Class, name?)
+        }
+        "method_definition" | "method_signature" | "abstract_method_signature" => {
+            let name = name?;
+            let kind = if node_text(name, text) == "constructor" {
+                K::Constructor
+            } else {
+                K::Method
+            };
+            Declaration::new(kind, name).signature(node)
+        }
+        "public_field_definition" | "property_signature" => {
+            Declaration::new(K::Property, name?).typed(node)
+        }
+        "field_definition" => Declaration::new(K::Property, node.child_by_field_name("property")?),
+        "interface_declaration" => Declaration::new(K::Interface, name?),
+        "enum_declaration" => Declaration::new(K::Enum, name?),
+        "enum_assignment" => Declaration::new(K::EnumMember, name?),
+        "property_identifier" if node.parent()?.kind() == "enum_body" => {
+            Declaration::new(K::EnumMember, node)
+        }
+        "type_alias_declaration" => Declaration::new(K::TypeParameter, name?),
+        "internal_module" => Declaration::new(K::Namespace, name?),
+        "module" => Declaration::new(K::Module, name?),
+        "variable_declarator" => variable(node)?,
+        _ => return None,
+    };
+    Some(declaration)
+}
+
+/// Variables holding functions or classes anywhere, and other variables at
+/// the top level.
+fn variable(node: Node<'_>) -> Option<Declaration<'_>> {
+    use SymbolKind as K;
+
+    let name = node.child_by_field_name("name")?;
+    if name.kind() != "identifier" {
+        // Destructuring
+        return None;
+    }
+    let value = node.child_by_field_name("value");
+    let kind = match value.map(|v| v.kind()) {
+        Some("arrow_function" | "function_expression" | "generator_function") => {
+            return Some(Declaration::new(K::Function, name).signature(value?));
+        }
+        Some("class") => K::Class,
+        _ => {
+            let statement = node.parent()?;
+            let container = statement.parent()?;
+            let top_level = container.kind() == "program"
+                || (container.kind() == "export_statement"
+                    && container.parent()?.kind() == "program");
+            if !top_level {
+                return None;
+            }
+            let constant = statement.child(0).is_some_and(|k| k.kind() == "const");
+            if constant {
+                K::Constant
+            } else {
+                K::Variable
+            }
+        }
+    };
+    Some(Declaration::new(kind, name))
+}
+
+fn python<'tree>(node: Node<'tree>, text: &str) -> Option<Declaration<'tree>> {
+    use SymbolKind as K;
+
+    let declaration = match node.kind() {
+        "class_definition" => Declaration::new(K::Class, node.child_by_field_name("name")?),
+        "function_definition" => {
+            let name = node.child_by_field_name("name")?;
+            let definition = node
+                .parent()
+                .filter(|p| p.kind() == "decorated_definition")
+                .unwrap_or(node);
+            let in_class = definition
+                .parent()
+                .filter(|p| p.kind() == "block")
+                .and_then(|p| p.parent())
+                .is_some_and(|p| p.kind() == "class_definition");
+            let kind = match (in_class, node_text(name, text)) {
+                (true, "__init__") => K::Constructor,
+                (true, _) => K::Method,
+                (false, _) => K::Function,
+            };
+            Declaration::new(kind, name).signature(node)
+        }
+        // Module-level assignments
+        "assignment" => {
+            let statement = node.parent()?;
+            if statement.kind() != "expression_statement" || statement.parent()?.kind() != "module"
+            {
+                return None;
+            }
+            let name = node.child_by_field_name("left")?;
+            if name.kind() != "identifier" {
+                return None;
+            }
+            let constant = node_text(name, text).chars().all(|c| !c.is_lowercase());
+            let kind = if constant { K::Constant } else { K::Variable };
+            Declaration::new(kind, name).typed(node)
+        }
+        _ => return None,
+    };
+    Some(declaration)
+}
+
+fn json<'tree>(node: Node<'tree>, text: &str) -> Option<Declaration<'tree>> {
+    match node.kind() {
+        "pair" => {
+            let key = node.child_by_field_name("key")?;
+            let mut declaration =
+                Declaration::new(value_kind(node.child_by_field_name("value")?.kind()), key);
+            declaration.label = Some(unquote(node_text(key, text)).to_string());
+            Some(declaration)
+        }
+        // Containers in arrays are named by index
+        "object" | "array" => {
+            let array = node.parent().filter(|p| p.kind() == "array")?;
+            let mut cursor = array.walk();
+            let index = array
+                .named_children(&mut cursor)
+                .filter(|n| n.kind() != "comment")
+                .position(|n| n == node)?;
+            let mut declaration = Declaration::new(value_kind(node.kind()), node);
+            declaration.label = Some(index.to_string());
+            Some(declaration)
+        }
+        _ => None,
+    }
+}
+
+fn toml<'tree>(node: Node<'tree>, text: &str) -> Option<Declaration<'tree>> {
+    let kind = match node.kind() {
+        "table" => SymbolKind::Object,
+        "table_array_element" => SymbolKind::Array,
+        "pair" => value_kind(node.named_child(1)?.kind()),
+        _ => return None,
+    };
+    let key = node.named_child(0)?;
+    let mut declaration = Declaration::new(kind, key);
+    declaration.label = Some(unquote(node_text(key, text)).to_string());
+    Some(declaration)
+}
+
+fn markdown<'tree>(node: Node<'tree>, text: &str) -> Option<Declaration<'tree>> {
+    if node.kind() != "section" {
+        return None;
+    }
+    let heading = node
+        .named_child(0)
+        .filter(|h| matches!(h.kind(), "atx_heading" | "setext_heading"))?;
+    let content = heading.child_by_field_name("heading_content")?;
+    let mut declaration = Declaration::new(SymbolKind::String, heading);
+    declaration.label = Some(node_text(content, text).trim().to_string());
+    Some(declaration)
+}
+
+/// Symbol kind for a JSON or TOML value node.
+fn value_kind(kind: &str) -> SymbolKind {
+    match kind {
+        "object" | "inline_table" => SymbolKind::Object,
+        "array" => SymbolKind::Array,
+        "number" | "integer" | "float" => SymbolKind::Number,
+        "true" | "false" | "boolean" => SymbolKind::Boolean,
+        "null" => SymbolKind::Null,
+        _ => SymbolKind::String,
+    }
+}
+
+fn node_text<'t>(node: Node<'_>, text: &'t str) -> &'t str {
+    &text[node.byte_range()]
+}
+
+/// Strip the quotes from a key.
+fn unquote(key: &str) -> &str {
+    key.strip_prefix(['"', '\''])
+        .and_then(|k| k.strip_suffix(['"', '\'']))
+        .unwrap_or(key)
+}
+
+/// Collapse runs of whitespace, including newlines, to single spaces.
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use gouide_workspace::Position;
+
+    use super::*;
+
+    fn symbols(language: Language, text: &str) -> Vec<Symbol> {
+        outline_text(language, text).unwrap()
+    }
+
+    /// (depth, name, kind) in document order.
+    fn flatten(symbols: &[Symbol]) -> Vec<(usize, String, SymbolKind)> {
+        fn walk(symbols: &[Symbol], depth: usize, out: &mut Vec<(usize, String, SymbolKind)>) {
+            for symbol in symbols {
+                out.push((depth, symbol.name.clone(), symbol.kind));
+                walk(&symbol.children, depth + 1, out);
+            }
+        }
+        let mut out = Vec::new();
+        walk(symbols, 0, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_rust_outline() {
+        let text = "\
+struct Point {
+    x: i32,
+}
+
+impl Display for Point {
+    fn fmt(&self, f: &mut Formatter<'_>)
+        -> fmt::Result {
+        fn helper() {}
+        Ok(())
+    }
+}
+
+const MAX: u32 = 1;
+";
+        let symbols = symbols(Language::Rust, text);
+        assert_eq!(
+            flatten(&symbols),
+            vec![
+                (0, "Point".to_string(), SymbolKind::Struct),
+                (1, "x".to_string(), SymbolKind::Field),
+                (0, "impl Display for Point".to_string(), SymbolKind::Object),
+                (1, "fmt".to_string(), SymbolKind::Method),
+                (2, "helper".to_string(), SymbolKind::Function),
+                (0, "MAX".to_string(), SymbolKind::Constant),
+            ]
+        );
+
+        let method = &symbols[1].children[0];
+        assert_eq!(
+            method.detail,
+            "(&self, f: &mut Formatter<'_>) -> fmt::Result"
+        );
+        assert_eq!(method.range.start, Position::new(5, 4));
+        assert_eq!(method.range.end, Position::new(9, 5));
+        assert_eq!(method.selection_range.start, Position::new(5, 7));
+        assert_eq!(symbols[0].children[0].detail, "i32");
+    }
+
+    #[test]
+    fn test_script_and_python_outline() {
+        let text = "\
+import x from 'x';
+export class A {
+  constructor() {}
+  run(n: number): void {}
+}
+export const handler = (event) => {};
+const LIMIT = 3;
+function f() { let local = 1; }
+";
+        assert_eq!(
+            flatten(&symbols(Language::TypeScript, text)),
+            vec![
+                (0, "A".to_string(), SymbolKind::Class),
+                (1, "constructor".to_string(), SymbolKind::Constructor),
+                (1, "run".to_string(), SymbolKind::Method),
+                (0, "handler".to_string(), SymbolKind::Function),
+                (0, "LIMIT".to_string(), SymbolKind::Constant),
+                (0, "f".to_string(), SymbolKind::Function),
+            ]
+        );
+
+        let text = "\
+VERSION = 1
+class A:
+    def __init__(self): pass
+    @property
+    def name(self) -> str: pass
+def main(): pass
+";
+        assert_eq!(
+            flatten(&symbols(Language::Python, text)),
+            vec![
+                (0, "VERSION".to_string(), SymbolKind::Constant),
+                (0, "A".to_string(), SymbolKind::Class),
+                (1, "__init__".to_string(), SymbolKind::Constructor),
+                (1, "name".to_string(), SymbolKind::Method),
+                (0, "main".to_string(), SymbolKind::Function),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_and_markdown_outline() {
+        let text = r#"{"name": "x", "items": [{"id": 1}], "ok": true}"#;
+        assert_eq!(
+            flatten(&symbols(Language::Json, text)),
+            vec![
+                (0, "name".to_string(), SymbolKind::String),
+                (0, "items".to_string(), SymbolKind::Array),
+                (1, "0".to_string(), SymbolKind::Object),
+                (2, "id".to_string(), SymbolKind::Number),
+                (0, "ok".to_string(), SymbolKind::Boolean),
+            ]
+        );
+
+        let text = "[package]\nname = \"x\"\n\n[[bin]]\npath = \"a\"\n";
+        assert_eq!(
+            flatten(&symbols(Language::Toml, text)),
+            vec![
+                (0, "package".to_string(), SymbolKind::Object),
+                (1, "name".to_string(), SymbolKind::String),
+                (0, "bin".to_string(), SymbolKind::Array),
+                (1, "path".to_string(), SymbolKind::String),
+            ]
+        );
+
+        let text = "# Title\n\nIntro\n\n## Usage\n\nText\n\n# Other\n";
+        assert_eq!(
+            flatten(&symbols(Language::Markdown, text)),
+            vec![
+                (0, "Title".to_string(), SymbolKind::String),
+                (1, "Usage".to_string(), SymbolKind::String),
+                (0, "Other".to_string(), SymbolKind::String),
+            ]
+        );
+    }
+}
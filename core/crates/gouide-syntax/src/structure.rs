@@ -0,0 +1,416 @@
+//! Folding ranges, bracket pairs and selection ranges.
+//!
+//! These work on any grammar: brackets are the anonymous `(`, `[` and `{`
+//! tokens matched among siblings, so they never pair across a string or
+//! comment. Languages only add which nodes are comments, imports, or
+//! indentation-delimited blocks.
+
+use std::cmp::Reverse;
+
+use gouide_workspace::{Position, TextRange};
+use tree_sitter::Node;
+
+use crate::lines::{row, LineIndex};
+use crate::Language;
+
+/// What a folding range folds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FoldKind {
+    /// A block of code or a document section.
+    Region,
+    /// A comment or a run of line comments.
+    Comment,
+    /// A run of import statements.
+    Imports,
+}
+
+/// Lines that can be folded away.
+///
+/// Folding hides lines `start_line + 1..=end_line`; a closing bracket on its
+/// own line stays visible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldingRange {
+    /// Line that stays visible.
+    pub start_line: u32,
+    /// Last hidden line.
+    pub end_line: u32,
+    /// What is folded.
+    pub kind: FoldKind,
+}
+
+/// A matching pair of brackets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BracketPair {
+    /// The opening bracket.
+    pub open: TextRange,
+    /// The closing bracket.
+    pub close: TextRange,
+    /// Number of pairs enclosing this one.
+    pub depth: u32,
+}
+
+/// Node kinds that fold differently per language.
+struct FoldNodes {
+    comments: &'static [&'static str],
+    imports: &'static [&'static str],
+    /// Blocks without brackets; they fold from their parent's first line.
+    indented: &'static [&'static str],
+    /// Nodes folded from their own first line.
+    sections: &'static [&'static str],
+}
+
+const fn fold_nodes(language: Language) -> FoldNodes {
+    let (comments, imports, indented, sections): (&[&str], &[&str], &[&str], &[&str]) =
+        match language {
+            Language::Rust => (
+                &["line_comment", "block_comment"],
+                &["use_declaration", "extern_crate_declaration"],
+                &[],
+                &[],
+            ),
+            Language::TypeScript | Language::Tsx | Language::JavaScript => {
+                (&["comment"], &["import_statement"], &[], &[])
+            }
+            Language::Python => (
+                &["comment"],
+                &[
+                    "import_statement",
+                    "import_from_statement",
+                    "future_import_statement",
+                ],
+                &["block"],
+                &[],
+            ),
+            Language::Json => (&["comment"], &[], &[], &[]),
+            Language::Toml => (&["comment"], &[], &[], &["table", "table_array_element"]),
+            Language::Markdown => (&[], &[], &[], &["section", "fenced_code_block"]),
+        };
+    FoldNodes {
+        comments,
+        imports,
+        indented,
+        sections,
+    }
+}
+
+/// Folding ranges of a whole tree, sorted by start line. When several ranges
+/// start on the same line, only the largest is kept.
+pub(crate) fn folding_ranges(
+    language: Language,
+    root: Node<'_>,
+    lines: &LineIndex<'_>,
+) -> Vec<FoldingRange> {
+    let nodes = fold_nodes(language);
+    let mut ranges = Vec::new();
+    let mut push = |start_line: u32, end_line: u32, kind| {
+        if end_line > start_line {
+            ranges.push(FoldingRange {
+                start_line,
+                end_line,
+                kind,
+            });
+        }
+    };
+
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if nodes.sections.contains(&node.kind()) {
+            push(
+                row(node.start_position()),
+                last_line(node, lines),
+                FoldKind::Region,
+            );
+        }
+        if nodes.indented.contains(&node.kind()) {
+            if let Some(parent) = node.parent() {
+                push(
+                    row(parent.start_position()),
+                    last_line(node, lines),
+                    FoldKind::Region,
+                );
+            }
+        }
+
+        // Runs of comments or imports: (kind, first line, last line)
+        let mut run: Option<(FoldKind, u32, u32)> = None;
+        let mut opens: Vec<Node<'_>> = Vec::new();
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            let kind = if nodes.comments.contains(&child.kind()) {
+                Some(FoldKind::Comment)
+            } else if nodes.imports.contains(&child.kind()) {
+                Some(FoldKind::Imports)
+            } else {
+                None
+            };
+            let (first, last) = (row(child.start_position()), last_line(child, lines));
+            run = match (run, kind) {
+                // Line comments only join a run on the next line
+                (Some((FoldKind::Comment, start, end)), Some(FoldKind::Comment))
+                    if first == end + 1 =>
+                {
+                    Some((FoldKind::Comment, start, last))
+                }
+                (Some((FoldKind::Imports, start, _)), Some(FoldKind::Imports)) => {
+                    Some((FoldKind::Imports, start, last))
+                }
+                (previous, kind) => {
+                    if let Some((kind, start, end)) = previous {
+                        push(start, end, kind);
+                    }
+                    kind.map(|kind| (kind, first, last))
+                }
+            };
+
+            match bracket(child) {
+                Some(Bracket::Open(_)) => opens.push(child),
+                Some(Bracket::Close(close)) => {
+                    if let Some(open) = pop_matching(&mut opens, close) {
+                        // Keep the line with the closing bracket visible
+                        let close_line = row(child.start_position());
+                        push(
+                            row(open.start_position()),
+                            close_line.saturating_sub(1),
+                            FoldKind::Region,
+                        );
+                    }
+                }
+                None => {
+                    if child.child_count() > 0 {
+                        stack.push(child);
+                    }
+                }
+            }
+        }
+        if let Some((kind, start, end)) = run {
+            push(start, end, kind);
+        }
+    }
+
+    ranges.sort_by_key(|range| (range.start_line, Reverse(range.end_line)));
+    ranges.dedup_by_key(|range| range.start_line);
+    ranges
+}
+
+/// Bracket pairs with at least one bracket on lines `first..=last`, in
+/// document order of their opening brackets.
+pub(crate) fn bracket_pairs(
+    root: Node<'_>,
+    lines: &LineIndex<'_>,
+    first: u32,
+    last: u32,
+) -> Vec<BracketPair> {
+    let visible = |node: Node<'_>| (first..=last).contains(&row(node.start_position()));
+    let mut pairs = Vec::new();
+    let mut stack = vec![(root, 0)];
+    while let Some((node, depth)) = stack.pop() {
+        let mut opens: Vec<Node<'_>> = Vec::new();
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            let inner = depth + u32::try_from(opens.len()).unwrap_or(u32::MAX);
+            match bracket(child) {
+                Some(Bracket::Open(_)) => opens.push(child),
+                Some(Bracket::Close(close)) => {
+                    if let Some(open) = pop_matching(&mut opens, close) {
+                        if visible(open) || visible(child) {
+                            pairs.push(BracketPair {
+                                open: range(open, lines),
+                                close: range(child, lines),
+                                depth: inner - 1,
+                            });
+                        }
+                    }
+                }
+                None => {
+                    let overlaps =
+                        row(child.start_position()) <= last && row(child.end_position()) >= first;
+                    if overlaps && child.child_count() > 0 {
+                        stack.push((child, inner));
+                    }
+                }
+            }
+        }
+    }
+    pairs.sort_by_key(|pair| pair.open.start);
+    pairs
+}
+
+/// Ranges to grow a selection through from `position`, innermost first. Each
+/// range contains the previous one; the last is the whole document.
+pub(crate) fn selection_ranges(
+    root: Node<'_>,
+    lines: &LineIndex<'_>,
+    position: Position,
+) -> Vec<TextRange> {
+    let point = lines.point(position);
+    let mut ranges: Vec<TextRange> = Vec::new();
+    let mut node = root.descendant_for_point_range(point, point);
+    while let Some(current) = node {
+        let range = range(current, lines);
+        if ranges.last() != Some(&range) {
+            ranges.push(range);
+        }
+        node = current.parent();
+    }
+    ranges
+}
+
+enum Bracket {
+    Open(char),
+    Close(char),
+}
+
+/// The bracket a node is, if it is a real (not error-recovery) bracket token.
+fn bracket(node: Node<'_>) -> Option<Bracket> {
+    if node.is_named() || node.is_missing() {
+        return None;
+    }
+    match node.kind() {
+        "(" => Some(Bracket::Open('(')),
+        "[" => Some(Bracket::Open('[')),
+        "{" => Some(Bracket::Open('{')),
+        ")" => Some(Bracket::Close('(')),
+        "]" => Some(Bracket::Close('[')),
+        "}" => Some(Bracket::Close('{')),
+        _ => None,
+    }
+}
+
+/// Pop the innermost open bracket if it matches `close`.
+fn pop_matching<'tree>(opens: &mut Vec<Node<'tree>>, close: char) -> Option<Node<'tree>> {
+    let open = *opens.last()?;
+    if matches!(bracket(open), Some(Bracket::Open(c)) if c == close) {
+        opens.pop()
+    } else {
+        None
+    }
+}
+
+/// Last line of a node's text, ignoring trailing whitespace and newlines.
+pub(crate) fn last_line(node: Node<'_>, lines: &LineIndex<'_>) -> u32 {
+    let text = &lines.text()[node.byte_range()];
+    let end = node.start_byte() + text.trim_end().len();
+    lines.position(end.max(node.start_byte())).line
+}
+
+/// Range of a node.
+pub(crate) fn range(node: Node<'_>, lines: &LineIndex<'_>) -> TextRange {
+    TextRange::new(
+        lines.position(node.start_byte()),
+        lines.position(node.end_byte()),
+    )
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use tree_sitter::{Parser, Tree};
+
+    use super::*;
+
+    fn parse(language: Language, text: &str) -> Tree {
+        let mut parser = Parser::new();
+        parser.set_language(&language.grammar()).unwrap();
+        parser.parse(text, None).unwrap()
+    }
+
+    fn folds(language: Language, text: &str) -> Vec<(u32, u32, FoldKind)> {
+        let tree = parse(language, text);
+        folding_ranges(language, tree.root_node(), &LineIndex::new(text))
+            .into_iter()
+            .map(|r| (r.start_line, r.end_line, r.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_rust_folding_ranges() {
+        let text = "\
+use std::fmt;
+use std::io;
+
+// One
+// Two
+fn main() {
+    let v = vec![
+        1,
+    ];
+}
+";
+        assert_eq!(
+            folds(Language::Rust, text),
+            vec![
+                (0, 1, FoldKind::Imports),
+                (3, 4, FoldKind::Comment),
+                (5, 8, FoldKind::Region),
+                (6, 7, FoldKind::Region),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_indented_and_section_folding_ranges() {
+        let python = "def f():\n    x = 1\n    return x\n\n\ny = 2\n";
+        assert_eq!(
+            folds(Language::Python, python),
+            vec![(0, 2, FoldKind::Region)]
+        );
+
+        let markdown = "# A\n\ntext\n\n## B\n\nmore\n\n# C\n";
+        assert_eq!(
+            folds(Language::Markdown, markdown),
+            vec![(0, 6, FoldKind::Region), (4, 6, FoldKind::Region)]
+        );
+    }
+
+    #[test]
+    fn test_bracket_pairs() {
+        let text = "fn f(a: [u8; 2]) {\n    let s = \"(\";\n    g(a[0]);\n}\n";
+        let tree = parse(Language::Rust, text);
+        let lines = LineIndex::new(text);
+        let pairs: Vec<_> = bracket_pairs(tree.root_node(), &lines, 0, 3)
+            .into_iter()
+            .map(|p| (p.open.start, p.close.start, p.depth))
+            .collect();
+        let at = Position::new;
+        assert_eq!(
+            pairs,
+            vec![
+                (at(0, 4), at(0, 15), 0),
+                (at(0, 8), at(0, 14), 1),
+                (at(0, 17), at(3, 0), 0),
+                (at(2, 5), at(2, 10), 1),
+                (at(2, 7), at(2, 9), 2),
+            ]
+        );
+
+        // Pairs with no bracket in the range are left out
+        let pairs = bracket_pairs(tree.root_node(), &lines, 2, 2);
+        assert_eq!(pairs.len(), 2);
+    }
+
+    #[test]
+    fn test_selection_ranges() {
+        let text = "fn f() {\n    g(1 + 2);\n}\n";
+        let tree = parse(Language::Rust, text);
+        let lines = LineIndex::new(text);
+        let ranges = selection_ranges(tree.root_node(), &lines, Position::new(1, 6));
+        let texts: Vec<&str> = ranges
+            .iter()
+            .map(|r| {
+                let start = lines.span(r.start.line).start + r.start.character as usize;
+                let end = lines.span(r.end.line).start + r.end.character as usize;
+                &text[start..end]
+            })
+            .collect();
+        assert_eq!(texts[0], "1");
+        assert_eq!(texts[1], "1 + 2");
+        assert_eq!(texts[2], "(1 + 2)");
+        assert_eq!(texts[3], "g(1 + 2)");
+        assert_eq!(*texts.last().unwrap(), text);
+    }
+}
@@ -0,0 +1,1364 @@
+//! Open file buffers.
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use ropey::Rope;
+
+use crate::mapped::MappedText;
+use crate::ot::{transform_edits, Priority};
+use crate::text::{
+    trim_line_ending, utf16_col_to_byte, utf16_len, ByteEdit, BytePoint, Position, TextEdit,
+    TextRange,
+};
+use crate::WorkspaceError;
+
+/// UTF-8 byte order mark.
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Encoding label for plain UTF-8.
+pub const ENCODING_UTF8: &str = "utf-8";
+
+/// Encoding label for UTF-8 with a byte order mark.
+pub const ENCODING_UTF8_BOM: &str = "utf-8-bom";
+
+/// Limits that control how files are loaded into buffers.
+#[derive(Debug, Clone, Copy)]
+pub struct BufferLimits {
+    /// Files larger than this are opened read-only in memory-mapped mode.
+    pub large_file_threshold: u64,
+    /// Maximum bytes of content returned in a single response.
+    pub chunk_bytes: usize,
+    /// Number of recent versions whose edits are kept for rebasing.
+    pub edit_history: usize,
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        Self {
+            large_file_threshold: 16 * 1024 * 1024, // 16MB
+            chunk_bytes: 1024 * 1024,               // 1MB
+            edit_history: 256,
+        }
+    }
+}
+
+/// Result of [`Buffer::apply_edits_at`].
+#[derive(Debug, Clone)]
+pub struct AppliedEdits {
+    /// Cursor position after each edit.
+    pub cursors: Vec<Position>,
+    /// The edits as applied to the current version, one per requested edit.
+    pub edits: Vec<TextEdit>,
+    /// The same edits in byte coordinates.
+    pub byte_edits: Vec<ByteEdit>,
+    /// Whether the edits were rebased over concurrent changes.
+    pub rebased: bool,
+}
+
+/// Line ending style of a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    /// Unix (`\n`).
+    #[default]
+    Lf,
+    /// Windows (`\r\n`).
+    Crlf,
+    /// Old Mac (`\r`).
+    Cr,
+}
+
+impl LineEnding {
+    /// Detect the line ending from the first line break in `bytes`.
+    pub fn detect(bytes: &[u8]) -> Self {
+        match memchr::memchr2(b'\n', b'\r', bytes) {
+            Some(i) if bytes[i] == b'\r' => {
+                if bytes.get(i + 1) == Some(&b'\n') {
+                    Self::Crlf
+                } else {
+                    Self::Cr
+                }
+            }
+            _ => Self::Lf,
+        }
+    }
+
+    /// The line terminator characters.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+
+    /// Rewrite every line break in `text` to this style.
+    pub fn normalize(self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(i) = memchr::memchr2(b'\n', b'\r', rest.as_bytes()) {
+            out.push_str(&rest[..i]);
+            out.push_str(self.as_str());
+            let len = if rest[i..].starts_with("\r\n") { 2 } else { 1 };
+            rest = &rest[i + len..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Options for saving a buffer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SaveOptions {
+    /// Encoding to write (`None` keeps the buffer's encoding).
+    pub encoding: Option<&'static str>,
+    /// Line ending to convert to (`None` writes the text as-is).
+    pub line_ending: Option<LineEnding>,
+}
+
+/// Result of saving a buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct SaveOutcome {
+    /// Buffer version that was written.
+    pub version: u64,
+    /// Modification time of the file after the write.
+    pub modified_at: Option<SystemTime>,
+    /// Whether the save created a new file.
+    pub created: bool,
+}
+
+/// Buffer content and version captured by [`Buffer::savepoint`].
+pub struct Savepoint {
+    rope: Option<Rope>,
+    version: u64,
+    last_modified_at: SystemTime,
+    history: VecDeque<(u64, Vec<TextEdit>)>,
+    undo_checkpoints: VecDeque<u64>,
+    line_ending: LineEnding,
+    encoding: &'static str,
+}
+
+/// Backing storage for buffer text.
+enum Storage {
+    /// Editable in-memory text.
+    Text(Rope),
+    /// Read-only memory-mapped file.
+    Mapped(MappedText),
+}
+
+impl Storage {
+    fn len_bytes(&self) -> usize {
+        match self {
+            Self::Text(rope) => rope.len_bytes(),
+            Self::Mapped(mapped) => mapped.len_bytes(),
+        }
+    }
+
+    fn line_count(&self) -> u32 {
+        match self {
+            Self::Text(rope) => u32::try_from(rope.len_lines()).unwrap_or(u32::MAX),
+            Self::Mapped(mapped) => mapped.line_count(),
+        }
+    }
+
+    fn line_to_byte(&self, line: u32) -> usize {
+        match self {
+            Self::Text(rope) => {
+                let line = (line as usize).min(rope.len_lines());
+                rope.line_to_byte(line)
+            }
+            Self::Mapped(mapped) => mapped.line_to_byte(line),
+        }
+    }
+
+    fn byte_to_line(&self, byte: usize) -> u32 {
+        match self {
+            Self::Text(rope) => {
+                u32::try_from(rope.byte_to_line(byte.min(rope.len_bytes()))).unwrap_or(u32::MAX)
+            }
+            Self::Mapped(mapped) => mapped.byte_to_line(byte),
+        }
+    }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        match self {
+            Self::Text(rope) => {
+                let start = rope.byte_to_char(start);
+                let end = rope.byte_to_char(end);
+                rope.slice(start..end).to_string()
+            }
+            Self::Mapped(mapped) => mapped.text(start, end),
+        }
+    }
+
+    fn floor_char_boundary(&self, byte: usize) -> usize {
+        match self {
+            Self::Text(rope) => {
+                let byte = byte.min(rope.len_bytes());
+                rope.char_to_byte(rope.byte_to_char(byte))
+            }
+            Self::Mapped(mapped) => mapped.floor_char_boundary(byte),
+        }
+    }
+
+    /// Replace a byte range of editable text. Mapped storage is never edited.
+    fn replace(&mut self, start: usize, end: usize, text: &str) {
+        if let Self::Text(rope) = self {
+            let start = rope.byte_to_char(start);
+            let end = rope.byte_to_char(end);
+            rope.remove(start..end);
+            rope.insert(start, text);
+        }
+    }
+
+    /// Position at the very end of the document.
+    fn end_position(&self) -> Position {
+        let last = self.line_count().saturating_sub(1);
+        let character = match self {
+            Self::Text(_) => {
+                let start = self.line_to_byte(last);
+                utf16_len(trim_line_ending(&self.text(start, self.len_bytes())))
+            }
+            Self::Mapped(mapped) => mapped.last_line_len(),
+        };
+        Position::new(last, character)
+    }
+
+    /// Clamp a position into the document and return it with its byte offset.
+    fn resolve(&self, position: Position) -> (Position, usize) {
+        if position.line >= self.line_count() {
+            return (self.end_position(), self.len_bytes());
+        }
+        let line_start = self.line_to_byte(position.line);
+        if position.character == 0 {
+            return (position, line_start);
+        }
+
+        // A UTF-16 unit never needs more than 3 UTF-8 bytes, so only a bounded
+        // prefix of the line has to be decoded even for enormous lines.
+        let line_end = self.line_to_byte(position.line + 1);
+        let prefix_end = line_end.min(line_start + position.character as usize * 3 + 4);
+        let prefix_end = self.floor_char_boundary(prefix_end);
+        let prefix = self.text(line_start, prefix_end);
+        let content = if prefix_end == line_end {
+            trim_line_ending(&prefix)
+        } else {
+            prefix.as_str()
+        };
+        let byte = utf16_col_to_byte(content, position.character);
+        let character = utf16_len(&content[..byte]);
+        (Position::new(position.line, character), line_start + byte)
+    }
+}
+
+/// Outcome of reconciling a buffer with its file on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskChange {
+    /// Nothing the client needs to know about changed.
+    Unchanged,
+    /// The buffer was clean and has been reloaded from disk.
+    Reloaded,
+    /// The file changed on disk but the buffer has unsaved changes, so it was
+    /// left as-is.
+    Conflict,
+    /// The file no longer exists. The buffer keeps its content and is dirty.
+    Deleted,
+    /// Only the file's permissions changed.
+    Permissions,
+}
+
+/// Content read from a buffer, possibly limited to a window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferContent {
+    /// The text in `range`.
+    pub text: String,
+    /// The range actually returned (clamped and possibly shortened).
+    pub range: TextRange,
+    /// Total number of lines in the buffer.
+    pub line_count: u32,
+    /// Whether the requested range was cut short by the size limit.
+    pub truncated: bool,
+}
+
+/// An open file buffer.
+pub struct Buffer {
+    id: String,
+    workspace_id: String,
+    file_id: String,
+    path: PathBuf,
+    storage: Storage,
+    encoding: &'static str,
+    line_ending: LineEnding,
+    version: u64,
+    saved_version: u64,
+    read_only: bool,
+    total_size: u64,
+    opened_at: SystemTime,
+    last_modified_at: SystemTime,
+    disk_modified_at: Option<SystemTime>,
+    /// Edits that produced each recent version, oldest first.
+    history: VecDeque<(u64, Vec<TextEdit>)>,
+    history_limit: usize,
+    /// Versions that end an undo group, oldest first.
+    undo_checkpoints: VecDeque<u64>,
+}
+
+impl Buffer {
+    /// Load a file from disk into a new buffer.
+    ///
+    /// Files above `limits.large_file_threshold` are memory-mapped and
+    /// opened read-only; everything else is decoded into an editable rope.
+    pub fn load(
+        id: String,
+        workspace_id: String,
+        file_id: String,
+        path: &Path,
+        limits: &BufferLimits,
+    ) -> Result<Self, WorkspaceError> {
+        let metadata = fs::metadata(path).map_err(|e| WorkspaceError::from_io(e, &file_id))?;
+        if !metadata.is_file() {
+            return Err(WorkspaceError::InvalidPath(format!(
+                "{file_id} is not a regular file"
+            )));
+        }
+
+        let total_size = metadata.len();
+        let mut read_only = metadata.permissions().readonly();
+        let (storage, encoding, line_ending) = if total_size > limits.large_file_threshold {
+            let file = File::open(path).map_err(|e| WorkspaceError::from_io(e, &file_id))?;
+            let mapped = MappedText::open(&file)?;
+            let head = mapped.text(0, mapped.floor_char_boundary(64 * 1024));
+            read_only = true;
+            (
+                Storage::Mapped(mapped),
+                ENCODING_UTF8,
+                LineEnding::detect(head.as_bytes()),
+            )
+        } else {
+            let bytes = fs::read(path).map_err(|e| WorkspaceError::from_io(e, &file_id))?;
+            let (bytes, encoding) = if bytes.starts_with(UTF8_BOM) {
+                (bytes[UTF8_BOM.len()..].to_vec(), ENCODING_UTF8_BOM)
+            } else {
+                (bytes, ENCODING_UTF8)
+            };
+            let line_ending = LineEnding::detect(&bytes);
+            let text = String::from_utf8(bytes).map_err(|_| {
+                WorkspaceError::UnsupportedEncoding(format!("{file_id} is not valid UTF-8"))
+            })?;
+            (Storage::Text(Rope::from_str(&text)), encoding, line_ending)
+        };
+
+        let now = SystemTime::now();
+        Ok(Self {
+            id,
+            workspace_id,
+            file_id,
+            path: path.to_path_buf(),
+            storage,
+            encoding,
+            line_ending,
+            version: 1,
+            saved_version: 1,
+            read_only,
+            total_size,
+            opened_at: now,
+            last_modified_at: now,
+            disk_modified_at: metadata.modified().ok(),
+            history: VecDeque::new(),
+            history_limit: limits.edit_history,
+            undo_checkpoints: VecDeque::new(),
+        })
+    }
+
+    /// Buffer identifier.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Workspace the buffer belongs to.
+    pub fn workspace_id(&self) -> &str {
+        &self.workspace_id
+    }
+
+    /// Workspace-relative file path.
+    pub fn file_id(&self) -> &str {
+        &self.file_id
+    }
+
+    /// Absolute path on disk.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Detected encoding label.
+    pub fn encoding(&self) -> &'static str {
+        self.encoding
+    }
+
+    /// Detected line ending style.
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Current buffer version.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Whether the buffer has changes that have not been saved to disk.
+    pub fn is_dirty(&self) -> bool {
+        self.version != self.saved_version
+    }
+
+    /// Check a client's expected version against the buffer.
+    ///
+    /// An expected version of 0 means the client did not specify one.
+    pub fn check_version(&self, expected: u64) -> Result<(), WorkspaceError> {
+        if expected != 0 && expected != self.version {
+            return Err(WorkspaceError::VersionConflict {
+                expected,
+                actual: self.version,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the buffer is read-only.
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Whether the buffer is backed by a read-only memory map.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.storage, Storage::Mapped(_))
+    }
+
+    /// Size of the file in bytes when it was loaded.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Size of the current content in bytes.
+    pub fn len_bytes(&self) -> usize {
+        self.storage.len_bytes()
+    }
+
+    /// Total number of lines.
+    pub fn line_count(&self) -> u32 {
+        self.storage.line_count()
+    }
+
+    /// When the buffer was opened.
+    pub fn opened_at(&self) -> SystemTime {
+        self.opened_at
+    }
+
+    /// When the buffer content last changed.
+    pub fn last_modified_at(&self) -> SystemTime {
+        self.last_modified_at
+    }
+
+    /// Modification time of the file on disk when last loaded or saved.
+    pub fn disk_modified_at(&self) -> Option<SystemTime> {
+        self.disk_modified_at
+    }
+
+    /// Read buffer content, optionally restricted to `range`.
+    ///
+    /// At most `max_bytes` are returned. When the range is larger, it is cut
+    /// at the last line boundary that fits (or mid-line for a single very long
+    /// line) and `truncated` is set so the client can fetch the rest lazily.
+    pub fn read_range(&self, range: Option<TextRange>, max_bytes: usize) -> BufferContent {
+        let line_count = self.storage.line_count();
+        let range = range.unwrap_or_else(|| TextRange::lines(0, line_count));
+        let (start, start_byte) = self.storage.resolve(range.start);
+        let (mut end, mut end_byte) = self.storage.resolve(range.end.max(range.start));
+
+        let truncated = end_byte - start_byte > max_bytes;
+        if truncated {
+            let limit = start_byte + max_bytes;
+            let line = self.storage.byte_to_line(limit);
+            let line_start = self.storage.line_to_byte(line);
+            if line_start > start_byte {
+                end = Position::new(line, 0);
+                end_byte = line_start;
+            } else {
+                end_byte = self.storage.floor_char_boundary(limit).max(start_byte);
+                let cut = self.storage.text(start_byte, end_byte);
+                end = Position::new(start.line, start.character + utf16_len(&cut));
+            }
+        }
+
+        BufferContent {
+            text: self.storage.text(start_byte, end_byte),
+            range: TextRange::new(start, end),
+            line_count,
+            truncated,
+        }
+    }
+
+    /// Full buffer content as a string.
+    pub fn text(&self) -> String {
+        self.storage.text(0, self.storage.len_bytes())
+    }
+
+    /// Reconcile the buffer with the current state of its file on disk.
+    ///
+    /// Called when the file watcher reports a change. Clean buffers are
+    /// reloaded (bumping the version); dirty buffers are never overwritten.
+    /// A change that leaves the file identical to the buffer (our own save,
+    /// a `touch`, a branch switch that restores the same content) is
+    /// reported as [`DiskChange::Unchanged`].
+    pub fn sync_with_disk(&mut self, limits: &BufferLimits) -> Result<DiskChange, WorkspaceError> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if self.disk_modified_at.is_none() {
+                    return Ok(DiskChange::Unchanged);
+                }
+                self.disk_modified_at = None;
+                self.saved_version = 0;
+                return Ok(DiskChange::Deleted);
+            }
+            Err(e) => return Err(WorkspaceError::from_io(e, &self.file_id)),
+        };
+
+        let permissions_changed =
+            !self.is_mapped() && metadata.permissions().readonly() != self.read_only;
+        if permissions_changed {
+            self.read_only = metadata.permissions().readonly();
+        }
+        let modified_at = metadata.modified().ok();
+        if modified_at == self.disk_modified_at && metadata.len() == self.total_size {
+            return Ok(if permissions_changed {
+                DiskChange::Permissions
+            } else {
+                DiskChange::Unchanged
+            });
+        }
+
+        let disk = Self::load(
+            self.id.clone(),
+            self.workspace_id.clone(),
+            self.file_id.clone(),
+            &self.path,
+            limits,
+        )?;
+        let same_content = !self.is_mapped() && !disk.is_mapped() && disk.text() == self.text();
+        let was_deleted = self.disk_modified_at.is_none();
+        self.disk_modified_at = disk.disk_modified_at;
+        self.total_size = disk.total_size;
+
+        if same_content {
+            self.saved_version = self.version;
+            // A file restored with the buffer's content clears the deletion
+            return Ok(if was_deleted {
+                DiskChange::Reloaded
+            } else if permissions_changed {
+                DiskChange::Permissions
+            } else {
+                DiskChange::Unchanged
+            });
+        }
+        if self.is_dirty() {
+            return Ok(DiskChange::Conflict);
+        }
+
+        self.adopt(disk);
+        Ok(DiskChange::Reloaded)
+    }
+
+    /// Replace the buffer with its file's content, discarding unsaved
+    /// changes.
+    ///
+    /// This is how a client resolves a [`DiskChange::Conflict`] in favour of
+    /// the file. The reload is a new version, like any other change.
+    pub fn reload(&mut self, limits: &BufferLimits) -> Result<(), WorkspaceError> {
+        let disk = Self::load(
+            self.id.clone(),
+            self.workspace_id.clone(),
+            self.file_id.clone(),
+            &self.path,
+            limits,
+        )?;
+        self.adopt(disk);
+        Ok(())
+    }
+
+    /// Take over the content of a freshly loaded copy of the file.
+    fn adopt(&mut self, disk: Self) {
+        self.storage = disk.storage;
+        self.encoding = disk.encoding;
+        self.line_ending = disk.line_ending;
+        self.read_only = disk.read_only;
+        self.disk_modified_at = disk.disk_modified_at;
+        self.total_size = disk.total_size;
+        self.touch();
+        self.saved_version = self.version;
+    }
+
+    /// Point the buffer at a new file after the file was moved.
+    pub fn rebind(&mut self, file_id: String, path: PathBuf) {
+        self.file_id = file_id;
+        self.path = path;
+    }
+
+    /// Apply edits in order as a single new version.
+    ///
+    /// Each edit's range refers to the text produced by the edits before it.
+    /// Returns the position just after each inserted text, which clients use
+    /// as the cursor after the edit.
+    pub fn apply_edits(&mut self, edits: &[TextEdit]) -> Result<Vec<Position>, WorkspaceError> {
+        Ok(self.apply_edits_at(0, edits)?.cursors)
+    }
+
+    /// Apply edits made against `base_version` as a single new version.
+    ///
+    /// Edits based on an older version are transformed against everything
+    /// applied since, as long as that is still in the edit history. A base
+    /// version of 0 means the current version.
+    pub fn apply_edits_at(
+        &mut self,
+        base_version: u64,
+        edits: &[TextEdit],
+    ) -> Result<AppliedEdits, WorkspaceError> {
+        self.ensure_writable()?;
+        if let Some(edit) = edits.iter().find(|e| e.range.end < e.range.start) {
+            return Err(WorkspaceError::InvalidRange(format!(
+                "{:?} ends before it starts",
+                edit.range
+            )));
+        }
+
+        let rebased = base_version != 0 && base_version != self.version;
+        let edits = if rebased {
+            let since = self.edits_since(base_version)?;
+            transform_edits(edits, &since, Priority::Second)
+        } else {
+            edits.to_vec()
+        };
+
+        let mut cursors = Vec::with_capacity(edits.len());
+        let mut applied = Vec::with_capacity(edits.len());
+        let mut byte_edits = Vec::with_capacity(edits.len());
+        for edit in edits {
+            let (start, start_byte) = self.storage.resolve(edit.range.start);
+            let (end, end_byte) = self.storage.resolve(edit.range.end);
+            let start_point = BytePoint::new(
+                start.line,
+                start_byte - self.storage.line_to_byte(start.line),
+            );
+            byte_edits.push(ByteEdit {
+                start_byte,
+                old_end_byte: end_byte,
+                new_end_byte: start_byte + edit.new_text.len(),
+                start: start_point,
+                old_end: BytePoint::new(end.line, end_byte - self.storage.line_to_byte(end.line)),
+                new_end: start_point.advance(&edit.new_text),
+            });
+            self.storage.replace(start_byte, end_byte, &edit.new_text);
+            cursors.push(start.advance(&edit.new_text));
+            applied.push(TextEdit::new(TextRange::new(start, end), edit.new_text));
+        }
+        if !applied.is_empty() {
+            self.bump();
+            if self.history_limit > 0 {
+                if self.history.len() == self.history_limit {
+                    self.history.pop_front();
+                }
+                self.history.push_back((self.version, applied.clone()));
+            }
+        }
+        Ok(AppliedEdits {
+            cursors,
+            edits: applied,
+            byte_edits,
+            rebased,
+        })
+    }
+
+    /// Mark the current version as the end of an undo group, so undoing
+    /// goes back to it in one step.
+    ///
+    /// As many checkpoints are kept as versions in the edit history.
+    /// Returns whether a checkpoint was created: none is when the current
+    /// version already ends a group or the history is disabled.
+    pub fn create_undo_checkpoint(&mut self) -> bool {
+        if self.history_limit == 0 || self.undo_checkpoints.back() == Some(&self.version) {
+            return false;
+        }
+        if self.undo_checkpoints.len() == self.history_limit {
+            self.undo_checkpoints.pop_front();
+        }
+        self.undo_checkpoints.push_back(self.version);
+        true
+    }
+
+    /// Versions that end an undo group, oldest first.
+    pub fn undo_checkpoints(&self) -> impl Iterator<Item = u64> + '_ {
+        self.undo_checkpoints.iter().copied()
+    }
+
+    /// All edits applied after `base_version`, in order.
+    fn edits_since(&self, base_version: u64) -> Result<Vec<TextEdit>, WorkspaceError> {
+        let conflict = WorkspaceError::VersionConflict {
+            expected: base_version,
+            actual: self.version,
+        };
+        if base_version > self.version {
+            return Err(conflict);
+        }
+        // History must cover every version after the base without gaps
+        let first = self
+            .history
+            .iter()
+            .position(|(version, _)| *version == base_version + 1)
+            .ok_or(conflict)?;
+        Ok(self
+            .history
+            .iter()
+            .skip(first)
+            .flat_map(|(_, edits)| edits.iter().cloned())
+            .collect())
+    }
+
+    /// Replace the whole content, bumping the version if it changed.
+    pub fn set_text(&mut self, text: &str) -> Result<(), WorkspaceError> {
+        self.ensure_writable()?;
+        if self.text() != text {
+            self.storage = Storage::Text(Rope::from_str(text));
+            self.touch();
+        }
+        Ok(())
+    }
+
+    /// Write the buffer to its file.
+    pub fn save(&mut self, options: SaveOptions) -> Result<SaveOutcome, WorkspaceError> {
+        self.write(options)
+    }
+
+    /// Write the buffer to a new file and rebind the buffer to it.
+    pub fn save_as(
+        &mut self,
+        file_id: String,
+        path: PathBuf,
+        options: SaveOptions,
+    ) -> Result<SaveOutcome, WorkspaceError> {
+        let previous = (
+            std::mem::replace(&mut self.file_id, file_id),
+            std::mem::replace(&mut self.path, path),
+        );
+        let result = self.write(options);
+        if result.is_err() {
+            (self.file_id, self.path) = previous;
+        }
+        result
+    }
+
+    /// Capture the content and version, to [`Self::roll_back`] to when a
+    /// change made ahead of a save has to be undone because the save failed.
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint {
+            rope: match &self.storage {
+                Storage::Text(rope) => Some(rope.clone()),
+                Storage::Mapped(_) => None,
+            },
+            version: self.version,
+            last_modified_at: self.last_modified_at,
+            history: self.history.clone(),
+            undo_checkpoints: self.undo_checkpoints.clone(),
+            line_ending: self.line_ending,
+            encoding: self.encoding,
+        }
+    }
+
+    /// Go back to a savepoint, as if nothing had changed since.
+    pub fn roll_back(&mut self, savepoint: Savepoint) {
+        if let Some(rope) = savepoint.rope {
+            self.storage = Storage::Text(rope);
+        }
+        self.version = savepoint.version;
+        self.last_modified_at = savepoint.last_modified_at;
+        self.history = savepoint.history;
+        self.undo_checkpoints = savepoint.undo_checkpoints;
+        self.line_ending = savepoint.line_ending;
+        self.encoding = savepoint.encoding;
+    }
+
+    /// Write the file, leaving the buffer as it was if that fails.
+    fn write(&mut self, options: SaveOptions) -> Result<SaveOutcome, WorkspaceError> {
+        self.ensure_writable()?;
+        let savepoint = self.savepoint();
+        let result = self.try_write(options);
+        if result.is_err() {
+            self.roll_back(savepoint);
+        }
+        result
+    }
+
+    fn try_write(&mut self, options: SaveOptions) -> Result<SaveOutcome, WorkspaceError> {
+        if let Some(line_ending) = options.line_ending {
+            let text = self.text();
+            let normalized = line_ending.normalize(&text);
+            if normalized != text {
+                self.storage = Storage::Text(Rope::from_str(&normalized));
+                self.touch();
+            }
+            self.line_ending = line_ending;
+        }
+        if let Some(encoding) = options.encoding {
+            self.encoding = encoding;
+        }
+
+        let Storage::Text(rope) = &self.storage else {
+            return Err(WorkspaceError::ReadOnly(self.file_id.clone()));
+        };
+        let created = !self.path.exists();
+        let bom = self.encoding == ENCODING_UTF8_BOM;
+        write_atomically(&self.path, bom, rope)
+            .map_err(|e| WorkspaceError::from_io(e, &self.file_id))?;
+
+        let metadata = fs::metadata(&self.path).ok();
+        self.saved_version = self.version;
+        self.disk_modified_at = metadata.as_ref().and_then(|m| m.modified().ok());
+        self.total_size = metadata.map_or(0, |m| m.len());
+        Ok(SaveOutcome {
+            version: self.version,
+            modified_at: self.disk_modified_at,
+            created,
+        })
+    }
+
+    fn ensure_writable(&self) -> Result<(), WorkspaceError> {
+        if self.read_only || self.is_mapped() {
+            return Err(WorkspaceError::ReadOnly(self.file_id.clone()));
+        }
+        Ok(())
+    }
+
+    /// Record a content change other than an edit.
+    ///
+    /// Such changes cannot be rebased over, so this clears the edit
+    /// history.
+    fn touch(&mut self) {
+        self.bump();
+        self.history.clear();
+    }
+
+    /// Move to a new version; edits then record themselves in the history.
+    fn bump(&mut self) {
+        self.version += 1;
+        self.last_modified_at = SystemTime::now();
+    }
+}
+
+/// Write `rope` to `path` via a temporary file and rename, so readers never
+/// see a half-written file.
+fn write_atomically(path: &Path, bom: bool, rope: &Rope) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let temp = path.with_file_name(format!(".{}.gouide-save", name.to_string_lossy()));
+
+    let result = write_file(&temp, path, bom, rope).and_then(|()| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+fn write_file(temp: &Path, target: &Path, bom: bool, rope: &Rope) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(temp)?);
+    if bom {
+        writer.write_all(UTF8_BOM)?;
+    }
+    for chunk in rope.chunks() {
+        writer.write_all(chunk.as_bytes())?;
+    }
+    let file = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    if let Ok(metadata) = fs::metadata(target) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fmt::Write as _;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn load(dir: &TempDir, name: &str, content: &[u8], limits: &BufferLimits) -> Buffer {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        Buffer::load(
+            "b1".to_string(),
+            "w1".to_string(),
+            name.to_string(),
+            &path,
+            limits,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_load_small_file() {
+        let dir = TempDir::new().unwrap();
+        let buffer = load(&dir, "a.txt", b"one\r\ntwo\r\n", &BufferLimits::default());
+
+        assert!(!buffer.is_mapped());
+        assert_eq!(buffer.line_ending(), LineEnding::Crlf);
+        assert_eq!(buffer.encoding(), ENCODING_UTF8);
+        assert_eq!(buffer.line_count(), 3);
+        assert_eq!(buffer.version(), 1);
+
+        let content = buffer.read_range(None, usize::MAX);
+        assert_eq!(content.text, "one\r\ntwo\r\n");
+        assert!(!content.truncated);
+        assert_eq!(content.range.end, Position::new(2, 0));
+    }
+
+    #[test]
+    fn test_bom_is_stripped() {
+        let dir = TempDir::new().unwrap();
+        let buffer = load(&dir, "bom.txt", b"\xef\xbb\xbfhi", &BufferLimits::default());
+        assert_eq!(buffer.encoding(), ENCODING_UTF8_BOM);
+        assert_eq!(buffer.text(), "hi");
+    }
+
+    #[test]
+    fn test_invalid_utf8_rejected() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("bin.dat");
+        fs::write(&path, b"\xff\xfe\x00").unwrap();
+        let result = Buffer::load(
+            "b1".to_string(),
+            "w1".to_string(),
+            "bin.dat".to_string(),
+            &path,
+            &BufferLimits::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(WorkspaceError::UnsupportedEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_range_by_lines_and_characters() {
+        let dir = TempDir::new().unwrap();
+        let buffer = load(
+            &dir,
+            "r.txt",
+            "zero\nuné\ntwo\n".as_bytes(),
+            &BufferLimits::default(),
+        );
+
+        let lines = buffer.read_range(Some(TextRange::lines(1, 2)), usize::MAX);
+        assert_eq!(lines.text, "uné\n");
+        assert_eq!(lines.line_count, 4);
+
+        let partial = buffer.read_range(
+            Some(TextRange::new(Position::new(1, 2), Position::new(2, 1))),
+            usize::MAX,
+        );
+        assert_eq!(partial.text, "é\nt");
+
+        // Out-of-range requests are clamped to the document end
+        let clamped = buffer.read_range(Some(TextRange::lines(2, 99)), usize::MAX);
+        assert_eq!(clamped.text, "two\n");
+        assert_eq!(clamped.range.end, Position::new(3, 0));
+    }
+
+    #[test]
+    fn test_read_range_truncates_at_line_boundary() {
+        let dir = TempDir::new().unwrap();
+        let content = (0..100).fold(String::new(), |mut out, i| {
+            writeln!(out, "line {i:03}").unwrap();
+            out
+        });
+        let buffer = load(&dir, "t.txt", content.as_bytes(), &BufferLimits::default());
+
+        // Each line is 9 bytes; 40 bytes fit four whole lines
+        let head = buffer.read_range(None, 40);
+        assert!(head.truncated);
+        assert_eq!(head.range.end, Position::new(4, 0));
+        assert_eq!(head.text.lines().count(), 4);
+    }
+
+    #[test]
+    fn test_read_range_truncates_long_line() {
+        let dir = TempDir::new().unwrap();
+        let buffer = load(
+            &dir,
+            "long.txt",
+            "ééééé".as_bytes(),
+            &BufferLimits::default(),
+        );
+
+        let head = buffer.read_range(None, 5);
+        assert!(head.truncated);
+        assert_eq!(head.text, "éé");
+        assert_eq!(head.range.end, Position::new(0, 2));
+    }
+
+    #[test]
+    fn test_large_file_is_mapped_read_only() {
+        let dir = TempDir::new().unwrap();
+        let limits = BufferLimits {
+            large_file_threshold: 1024,
+            chunk_bytes: 256,
+            ..BufferLimits::default()
+        };
+        let content = (0..2000).fold(String::new(), |mut out, i| {
+            writeln!(out, "log entry {i}").unwrap();
+            out
+        });
+        let buffer = load(&dir, "big.log", content.as_bytes(), &limits);
+
+        assert!(buffer.is_mapped());
+        assert!(buffer.read_only());
+        assert_eq!(buffer.total_size(), content.len() as u64);
+        assert_eq!(buffer.line_count(), 2001);
+
+        let window = buffer.read_range(Some(TextRange::lines(1500, 1502)), limits.chunk_bytes);
+        assert_eq!(window.text, "log entry 1500\nlog entry 1501\n");
+        assert!(!window.truncated);
+    }
+
+    #[test]
+    fn test_edits_mark_buffer_dirty_until_saved() {
+        let dir = TempDir::new().unwrap();
+        let mut buffer = load(&dir, "e.rs", b"fn main() {}\n", &BufferLimits::default());
+        assert!(!buffer.is_dirty());
+
+        let cursors = buffer
+            .apply_edits(&[
+                TextEdit::new(
+                    TextRange::new(Position::new(0, 3), Position::new(0, 7)),
+                    "run",
+                ),
+                TextEdit::new(
+                    TextRange::new(Position::new(0, 10), Position::new(0, 10)),
+                    "\n",
+                ),
+            ])
+            .unwrap();
+        assert_eq!(buffer.text(), "fn run() {\n}\n");
+        assert_eq!(cursors, vec![Position::new(0, 6), Position::new(1, 0)]);
+        assert_eq!(buffer.version(), 2);
+        assert!(buffer.is_dirty());
+
+        let outcome = buffer.save(SaveOptions::default()).unwrap();
+        assert_eq!(outcome.version, 2);
+        assert!(!outcome.created);
+        assert!(!buffer.is_dirty());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("e.rs")).unwrap(),
+            "fn run() {\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_range_rejected_without_changes() {
+        let dir = TempDir::new().unwrap();
+        let mut buffer = load(&dir, "e.txt", b"abc", &BufferLimits::default());
+        let result = buffer.apply_edits(&[
+            TextEdit::new(TextRange::lines(0, 0), "x"),
+            TextEdit::new(TextRange::new(Position::new(0, 2), Position::new(0, 1)), ""),
+        ]);
+        assert!(matches!(result, Err(WorkspaceError::InvalidRange(_))));
+        assert_eq!(buffer.text(), "abc");
+        assert_eq!(buffer.version(), 1);
+    }
+
+    #[test]
+    fn test_save_preserves_bom_and_converts_line_endings() {
+        let dir = TempDir::new().unwrap();
+        let mut buffer = load(
+            &dir,
+            "w.txt",
+            b"\xef\xbb\xbfa\nb\n",
+            &BufferLimits::default(),
+        );
+        buffer
+            .save(SaveOptions {
+                encoding: None,
+                line_ending: Some(LineEnding::Crlf),
+            })
+            .unwrap();
+
+        assert_eq!(buffer.line_ending(), LineEnding::Crlf);
+        assert_eq!(
+            fs::read(dir.path().join("w.txt")).unwrap(),
+            b"\xef\xbb\xbfa\r\nb\r\n"
+        );
+        assert!(!buffer.is_dirty());
+    }
+
+    #[test]
+    fn test_failed_save_leaves_buffer_as_it_was() {
+        let dir = TempDir::new().unwrap();
+        let mut buffer = load(&dir, "f.txt", b"a\nb\n", &BufferLimits::default());
+        // The temporary file cannot be created where a directory is
+        fs::create_dir(dir.path().join(".f.txt.gouide-save")).unwrap();
+
+        // An edit made ahead of the save is rolled back with it
+        let savepoint = buffer.savepoint();
+        buffer
+            .apply_edits(&[TextEdit::new(TextRange::lines(0, 0), "x")])
+            .unwrap();
+        let result = buffer.save(SaveOptions {
+            encoding: None,
+            line_ending: Some(LineEnding::Crlf),
+        });
+        assert!(matches!(result, Err(WorkspaceError::Io(_))));
+        assert_eq!(buffer.text(), "xa\nb\n");
+        assert_eq!(buffer.version(), 2);
+        assert_eq!(buffer.line_ending(), LineEnding::Lf);
+
+        buffer.roll_back(savepoint);
+        assert_eq!(buffer.text(), "a\nb\n");
+        assert_eq!(buffer.version(), 1);
+        assert!(!buffer.is_dirty());
+        assert_eq!(fs::read(dir.path().join("f.txt")).unwrap(), b"a\nb\n");
+    }
+
+    #[test]
+    fn test_save_as_rebinds_buffer() {
+        let dir = TempDir::new().unwrap();
+        let mut buffer = load(&dir, "old.txt", b"data", &BufferLimits::default());
+        let outcome = buffer
+            .save_as(
+                "new.txt".to_string(),
+                dir.path().join("new.txt"),
+                SaveOptions::default(),
+            )
+            .unwrap();
+
+        assert!(outcome.created);
+        assert_eq!(buffer.file_id(), "new.txt");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("new.txt")).unwrap(),
+            "data"
+        );
+    }
+
+    #[test]
+    fn test_line_ending_normalize() {
+        assert_eq!(LineEnding::Lf.normalize("a\r\nb\rc\n"), "a\nb\nc\n");
+        assert_eq!(LineEnding::Crlf.normalize("a\nb"), "a\r\nb");
+    }
+
+    #[test]
+    fn test_sync_reloads_clean_buffer() {
+        let dir = TempDir::new().unwrap();
+        let limits = BufferLimits::default();
+        let mut buffer = load(&dir, "s.txt", b"old\n", &limits);
+        assert_eq!(
+            buffer.sync_with_disk(&limits).unwrap(),
+            DiskChange::Unchanged
+        );
+
+        fs::write(dir.path().join("s.txt"), "new content\n").unwrap();
+        assert_eq!(
+            buffer.sync_with_disk(&limits).unwrap(),
+            DiskChange::Reloaded
+        );
+        assert_eq!(buffer.text(), "new content\n");
+        assert_eq!(buffer.version(), 2);
+        assert!(!buffer.is_dirty());
+    }
+
+    #[test]
+    fn test_sync_keeps_dirty_buffer() {
+        let dir = TempDir::new().unwrap();
+        let limits = BufferLimits::default();
+        let mut buffer = load(&dir, "s.txt", b"old\n", &limits);
+        buffer
+            .apply_edits(&[TextEdit::new(TextRange::lines(0, 0), "mine ")])
+            .unwrap();
+
+        fs::write(dir.path().join("s.txt"), "theirs\n").unwrap();
+        assert_eq!(
+            buffer.sync_with_disk(&limits).unwrap(),
+            DiskChange::Conflict
+        );
+        assert_eq!(buffer.text(), "mine old\n");
+        assert!(buffer.is_dirty());
+
+        // The same change is only reported once
+        assert_eq!(
+            buffer.sync_with_disk(&limits).unwrap(),
+            DiskChange::Unchanged
+        );
+
+        // Reloading resolves the conflict in favour of the file
+        buffer.reload(&limits).unwrap();
+        assert_eq!(buffer.text(), "theirs\n");
+        assert_eq!(buffer.version(), 3);
+        assert!(!buffer.is_dirty());
+    }
+
+    #[test]
+    fn test_sync_detects_deletion_and_permissions() {
+        let dir = TempDir::new().unwrap();
+        let limits = BufferLimits::default();
+        let path = dir.path().join("s.txt");
+        let mut buffer = load(&dir, "s.txt", b"data", &limits);
+
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions.clone()).unwrap();
+        assert_eq!(
+            buffer.sync_with_disk(&limits).unwrap(),
+            DiskChange::Permissions
+        );
+        assert!(buffer.read_only());
+
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(&path, permissions).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(buffer.sync_with_disk(&limits).unwrap(), DiskChange::Deleted);
+        assert!(buffer.is_dirty());
+        assert_eq!(buffer.text(), "data");
+    }
+
+    #[test]
+    fn test_stale_edits_are_rebased() {
+        let dir = TempDir::new().unwrap();
+        let limits = BufferLimits::default();
+        let mut buffer = load(&dir, "r.txt", b"fn main() {}\n", &limits);
+
+        // Other clients insert two lines at the top (versions 2 and 3)
+        for line in ["// b\n", "// a\n"] {
+            buffer
+                .apply_edits(&[TextEdit::new(TextRange::lines(0, 0), line)])
+                .unwrap();
+        }
+        // This client still thinks it is at version 1 and renames `main`
+        let main = TextRange::new(Position::new(0, 3), Position::new(0, 7));
+        let applied = buffer
+            .apply_edits_at(1, &[TextEdit::new(main, "start")])
+            .unwrap();
+        assert!(applied.rebased);
+        assert_eq!(
+            applied.edits[0].range,
+            TextRange::new(Position::new(2, 3), Position::new(2, 7))
+        );
+        assert_eq!(buffer.text(), "// a\n// b\nfn start() {}\n");
+        assert_eq!(buffer.version(), 4);
+
+        // An edit from version 2 sees the rename and the line above it
+        let end = TextRange::new(Position::new(1, 12), Position::new(1, 12));
+        buffer
+            .apply_edits_at(2, &[TextEdit::new(end, " // end")])
+            .unwrap();
+        assert_eq!(buffer.text(), "// a\n// b\nfn start() {} // end\n");
+
+        assert!(matches!(
+            buffer.apply_edits_at(9, &[TextEdit::new(main, "x")]),
+            Err(WorkspaceError::VersionConflict { .. })
+        ));
+
+        // A whole-content replacement cannot be rebased over
+        buffer.set_text("new\n").unwrap();
+        assert!(matches!(
+            buffer.apply_edits_at(5, &[TextEdit::new(main, "x")]),
+            Err(WorkspaceError::VersionConflict { .. })
+        ));
+    }
+
+    #[test]
+    fn test_edit_history_keeps_the_configured_versions() {
+        let dir = TempDir::new().unwrap();
+        let limits = BufferLimits {
+            edit_history: 3,
+            ..BufferLimits::default()
+        };
+        let mut buffer = load(&dir, "h.txt", b"", &limits);
+        let insert = |buffer: &mut Buffer, base: u64| {
+            buffer.apply_edits_at(base, &[TextEdit::new(TextRange::lines(0, 0), "x")])
+        };
+
+        // Versions 2 to 4 fill the history, which still reaches version 1
+        for _ in 0..3 {
+            insert(&mut buffer, 0).unwrap();
+        }
+        assert_eq!(buffer.version(), 4);
+        insert(&mut buffer, 1).unwrap();
+
+        // That edit made version 5 and pushed version 2 out
+        assert!(matches!(
+            insert(&mut buffer, 1),
+            Err(WorkspaceError::VersionConflict { .. })
+        ));
+        insert(&mut buffer, 2).unwrap();
+        assert_eq!(buffer.text(), "xxxxx");
+    }
+
+    #[test]
+    fn test_undo_checkpoints_end_groups_of_edits() {
+        let dir = TempDir::new().unwrap();
+        let limits = BufferLimits {
+            edit_history: 2,
+            ..BufferLimits::default()
+        };
+        let mut buffer = load(&dir, "u.txt", b"", &limits);
+        let insert = TextEdit::new(TextRange::lines(0, 0), "x");
+
+        buffer.apply_edits(std::slice::from_ref(&insert)).unwrap();
+        assert!(buffer.create_undo_checkpoint());
+        // Nothing changed since, so there is no new group to end
+        assert!(!buffer.create_undo_checkpoint());
+
+        buffer.apply_edits(std::slice::from_ref(&insert)).unwrap();
+        buffer.apply_edits(&[insert]).unwrap();
+        assert!(buffer.create_undo_checkpoint());
+        assert_eq!(buffer.undo_checkpoints().collect::<Vec<_>>(), [2, 4]);
+
+        // Old checkpoints go as the history does
+        buffer.set_text("y").unwrap();
+        assert!(buffer.create_undo_checkpoint());
+        assert_eq!(buffer.undo_checkpoints().collect::<Vec<_>>(), [4, 5]);
+    }
+
+    #[test]
+    fn test_applied_edits_report_byte_coordinates() {
+        let dir = TempDir::new().unwrap();
+        let limits = BufferLimits::default();
+        let mut buffer = load(&dir, "b.txt", "aé\nbc\n".as_bytes(), &limits);
+
+        // Replace "éb" (spanning the line break) with "x\nyz"
+        let range = TextRange::new(Position::new(0, 1), Position::new(1, 1));
+        let applied = buffer
+            .apply_edits_at(0, &[TextEdit::new(range, "x\nyz")])
+            .unwrap();
+        assert_eq!(
+            applied.byte_edits,
+            vec![ByteEdit {
+                start_byte: 1,
+                old_end_byte: 5,
+                new_end_byte: 5,
+                start: BytePoint::new(0, 1),
+                old_end: BytePoint::new(1, 1),
+                new_end: BytePoint::new(1, 2),
+            }]
+        );
+        assert_eq!(buffer.text(), "ax\nyzc\n");
+    }
+}
@@ -0,0 +1,424 @@
+//! Minimal edits between two versions of a text.
+//!
+//! [`diff`] compares lines with Myers' algorithm, then narrows each block of
+//! changed lines to the characters that actually differ. Reformatting a file
+//! therefore yields edits that only touch what changed, and cursors or other
+//! clients' edits elsewhere in the buffer stay where they are.
+//!
+//! [`line_hunks`] stops at the changed lines, for showing a diff.
+
+use std::ops::Range;
+
+use crate::text::{trim_line_ending, utf16_col_to_byte, utf16_len, Position, TextEdit, TextRange};
+
+/// Most line differences to look for before giving up on a minimal diff and
+/// replacing the whole changed region at once.
+const MAX_DIFFERENCES: usize = 1000;
+
+/// Edits that turn `old` into `new`, ordered from the end of the text to the
+/// start so they can be applied one after another.
+pub fn diff(old: &str, new: &str) -> Vec<TextEdit> {
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let (prefix, hunks) = changed_lines(&old_lines, &new_lines);
+    let (a, b) = (&old_lines[prefix..], &new_lines[prefix..]);
+    hunks
+        .iter()
+        .rev()
+        .flat_map(|hunk| {
+            let line = prefix + hunk.old.start;
+            let (old, new) = (&a[hunk.old.clone()], &b[hunk.new.clone()]);
+            if old.len() == new.len() {
+                // Changed in place, as reformatting mostly does: diff each line
+                old.iter()
+                    .zip(new)
+                    .enumerate()
+                    .rev()
+                    .flat_map(|(i, (old, new))| line_edits(to_u32(line + i), old, new))
+                    .collect()
+            } else {
+                vec![narrow(
+                    Position::new(to_u32(line), 0),
+                    &old.concat(),
+                    &new.concat(),
+                )]
+            }
+        })
+        .filter(|edit| !edit.range.is_empty() || !edit.new_text.is_empty())
+        .fold(Vec::new(), merge_touching)
+}
+
+/// A block of changed lines: lines `old` of the old text were replaced by
+/// lines `new` of the new text. One of the two is empty for lines only
+/// added or only removed, and then starts where they would be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineHunk {
+    /// Lines of the old text replaced (0-based).
+    pub old: Range<usize>,
+    /// Lines of the new text replacing them (0-based).
+    pub new: Range<usize>,
+}
+
+/// Changed blocks of lines between two texts, in line order. Lines are
+/// compared with their line breaks.
+pub fn line_hunks(old: &str, new: &str) -> Vec<LineHunk> {
+    let (prefix, hunks) = changed_lines(&lines(old), &lines(new));
+    hunks
+        .into_iter()
+        .map(|hunk| LineHunk {
+            old: prefix + hunk.old.start..prefix + hunk.old.end,
+            new: prefix + hunk.new.start..prefix + hunk.new.end,
+        })
+        .collect()
+}
+
+/// `old` with some of the [`line_hunks`] from it to `new` applied, in line
+/// order, and the rest of it left as it was.
+pub fn apply_line_hunks(old: &str, new: &str, hunks: &[LineHunk]) -> String {
+    let (old, new) = (lines(old), lines(new));
+    let mut text = String::new();
+    let mut next = 0;
+    for hunk in hunks {
+        text.extend(old[next..hunk.old.start].iter().copied());
+        text.extend(new[hunk.new.clone()].iter().copied());
+        next = hunk.old.end;
+    }
+    text.extend(old[next..].iter().copied());
+    text
+}
+
+/// Changed blocks of lines, relative to the number of leading lines that
+/// are the same, which is returned with them.
+///
+/// Leading and trailing lines that did not change never reach Myers, so
+/// an edit only compares the lines between the first and last it touched.
+fn changed_lines(old: &[&str], new: &[&str]) -> (usize, Vec<Hunk>) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    if a.is_empty() && b.is_empty() {
+        return (prefix, Vec::new());
+    }
+    let hunks = myers(a, b).unwrap_or_else(|| {
+        vec![Hunk {
+            old: 0..a.len(),
+            new: 0..b.len(),
+        }]
+    });
+    (prefix, hunks)
+}
+
+/// Append `edit`, which comes before the last edit in the text, merging the
+/// two when they touch. Applied separately, the later edit could turn a CR
+/// that ends the earlier one's range into half of a CRLF.
+fn merge_touching(mut edits: Vec<TextEdit>, edit: TextEdit) -> Vec<TextEdit> {
+    match edits.last_mut() {
+        Some(last) if last.range.start == edit.range.end => {
+            last.range.start = edit.range.start;
+            last.new_text.insert_str(0, &edit.new_text);
+        }
+        _ => edits.push(edit),
+    }
+    edits
+}
+
+/// Apply edits one after another to `text`, as a buffer would. Positions
+/// past the end of a line or of the text clamp to it.
+pub fn apply_edits(text: &str, edits: &[TextEdit]) -> String {
+    edits.iter().fold(text.to_string(), |mut text, edit| {
+        let start = offset(&text, edit.range.start);
+        let end = offset(&text, edit.range.end).max(start);
+        text.replace_range(start..end, &edit.new_text);
+        text
+    })
+}
+
+/// Lines of `text`, each with its line break. CR, LF and CRLF all end a
+/// line, as they do for positions.
+fn lines(text: &str) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while let Some(found) = memchr::memchr2(b'\n', b'\r', &bytes[start..]) {
+        let mut end = start + found + 1;
+        if bytes[end - 1] == b'\r' && bytes.get(end) == Some(&b'\n') {
+            end += 1;
+        }
+        lines.push(&text[start..end]);
+        start = end;
+    }
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
+/// Byte offset of a position in `text`.
+fn offset(text: &str, position: Position) -> usize {
+    let lines = lines(text);
+    let line = position.line as usize;
+    let start: usize = lines.iter().take(line).map(|line| line.len()).sum();
+    lines.get(line).map_or(text.len(), |content| {
+        start + utf16_col_to_byte(trim_line_ending(content), position.character)
+    })
+}
+
+/// A block of changes: `old` items replaced by `new` items.
+#[derive(Debug, PartialEq, Eq)]
+struct Hunk {
+    old: Range<usize>,
+    new: Range<usize>,
+}
+
+/// Changed blocks between two sequences, or `None` when they differ in more
+/// than [`MAX_DIFFERENCES`] items.
+///
+/// `x` indexes `old` and `y` indexes `new`. Diagonal `x - y` is stored at
+/// index `x - y + max`, so every index stays unsigned. Each round keeps only
+/// the diagonals it can reach, which bounds the trace by the square of the
+/// number of differences.
+fn myers<T: PartialEq>(old: &[T], new: &[T]) -> Option<Vec<Hunk>> {
+    let max = old.len() + new.len();
+    let mut furthest = vec![0usize; 2 * max + 2];
+    let mut trace = Vec::new();
+    for round in 0..=max.min(MAX_DIFFERENCES) {
+        trace.push(furthest[max - round..=max + round].to_vec());
+        for diagonal in (max - round..=max + round).step_by(2) {
+            let down = diagonal == max - round
+                || (diagonal != max + round && furthest[diagonal - 1] < furthest[diagonal + 1]);
+            let mut x = if down {
+                furthest[diagonal + 1]
+            } else {
+                furthest[diagonal - 1] + 1
+            };
+            let mut y = x + max - diagonal;
+            while old.get(x).is_some_and(|item| new.get(y) == Some(item)) {
+                x += 1;
+                y += 1;
+            }
+            furthest[diagonal] = x;
+            if x >= old.len() && y >= new.len() {
+                return Some(backtrack(&trace, old.len(), new.len()));
+            }
+        }
+    }
+    None
+}
+
+/// Walk a Myers trace back from the end, collecting the changed blocks.
+fn backtrack(trace: &[Vec<usize>], old_len: usize, new_len: usize) -> Vec<Hunk> {
+    let max = old_len + new_len;
+    let (mut x, mut y) = (old_len, new_len);
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for (round, furthest) in trace.iter().enumerate().skip(1).rev() {
+        let at = |diagonal: usize| furthest[diagonal + round - max];
+        let diagonal = x + max - y;
+        let inserted = diagonal == max - round
+            || (diagonal != max + round && at(diagonal - 1) < at(diagonal + 1));
+        let prev = if inserted { diagonal + 1 } else { diagonal - 1 };
+        let (prev_x, prev_y) = (at(prev), at(prev) + max - prev);
+        // The move ends here; any equal items after it are the snake
+        let (end_x, end_y) = if inserted {
+            (prev_x, prev_y + 1)
+        } else {
+            (prev_x + 1, prev_y)
+        };
+        match hunks.last_mut() {
+            Some(last) if last.old.start == end_x && last.new.start == end_y => {
+                last.old.start = prev_x;
+                last.new.start = prev_y;
+            }
+            _ => hunks.push(Hunk {
+                old: prev_x..end_x,
+                new: prev_y..end_y,
+            }),
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    hunks.reverse();
+    hunks
+}
+
+/// Edits within a changed line that starts at `line`, from the end of the
+/// line to the start. The line break is compared on its own, so a CRLF is
+/// never split.
+fn line_edits(line: u32, old: &str, new: &str) -> Vec<TextEdit> {
+    let (old_content, new_content) = (trim_line_ending(old), trim_line_ending(new));
+    let (old_break, new_break) = (&old[old_content.len()..], &new[new_content.len()..]);
+    let mut edits = Vec::new();
+    if old_break != new_break {
+        let end = Position::new(line, utf16_len(old_content));
+        edits.push(TextEdit::new(
+            TextRange::new(end, end.advance(old_break)),
+            new_break,
+        ));
+    }
+
+    let a: Vec<char> = old_content.chars().collect();
+    let b: Vec<char> = new_content.chars().collect();
+    let Some(hunks) = myers(&a, &b) else {
+        edits.push(narrow(Position::new(line, 0), old_content, new_content));
+        return edits;
+    };
+    let columns: Vec<u32> = std::iter::once(0)
+        .chain(a.iter().scan(0, |column, c| {
+            *column += to_u32(c.len_utf16());
+            Some(*column)
+        }))
+        .collect();
+    edits.extend(hunks.iter().rev().map(|hunk| {
+        TextEdit::new(
+            TextRange::new(
+                Position::new(line, columns[hunk.old.start]),
+                Position::new(line, columns[hunk.old.end]),
+            ),
+            b[hunk.new.clone()].iter().collect::<String>(),
+        )
+    }));
+    edits
+}
+
+/// Edit replacing `old`, which starts at `start`, with `new`, narrowed to
+/// the characters that differ.
+fn narrow(start: Position, old: &str, new: &str) -> TextEdit {
+    let mut prefix = common_len(old.chars(), new.chars());
+    // A position cannot point between the two halves of a CRLF
+    if old[..prefix].ends_with('\r') && old[prefix..].starts_with('\n') {
+        prefix -= 1;
+    }
+    let from = start.advance(&old[..prefix]);
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let mut suffix = common_len(old.chars().rev(), new.chars().rev());
+    if old[..old.len() - suffix].ends_with('\r') && old[old.len() - suffix..].starts_with('\n') {
+        suffix -= 1;
+    }
+    let removed = &old[..old.len() - suffix];
+    TextEdit::new(
+        TextRange::new(from, from.advance(removed)),
+        &new[..new.len() - suffix],
+    )
+}
+
+fn to_u32(n: usize) -> u32 {
+    u32::try_from(n).unwrap_or(u32::MAX)
+}
+
+/// Length in bytes of the common start of two character sequences.
+fn common_len(a: impl Iterator<Item = char>, b: impl Iterator<Item = char>) -> usize {
+    a.zip(b)
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum()
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn edit(start: (u32, u32), end: (u32, u32), new_text: &str) -> TextEdit {
+        TextEdit::new(
+            TextRange::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+            new_text,
+        )
+    }
+
+    #[test]
+    fn test_edits_only_touch_changes() {
+        let old = "fn main() {\n  let a = 1;  \n\n\n  a\n}\n";
+        let new = "fn main() {\n    let a = 1;\n\n    a\n}\n";
+        let edits = diff(old, new);
+        assert_eq!(
+            edits,
+            [
+                edit((3, 0), (4, 0), "  "),
+                edit((1, 12), (1, 14), ""),
+                edit((1, 2), (1, 2), "  "),
+            ]
+        );
+        assert_eq!(apply_edits(old, &edits), new);
+        assert!(diff(new, new).is_empty());
+    }
+
+    #[test]
+    fn test_edits_respect_utf16_and_crlf() {
+        let edits = diff("é  x\r\nb\r\n", "é x\r\nb\n");
+        // The CRLF is replaced whole, and columns count UTF-16 units
+        assert_eq!(
+            edits,
+            [edit((1, 1), (2, 0), "\n"), edit((0, 2), (0, 3), "")]
+        );
+        assert_eq!(apply_edits("é  x\r\nb\r\n", &edits), "é x\r\nb\n");
+    }
+
+    #[test]
+    fn test_line_hunks() {
+        let hunks = line_hunks("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n");
+        assert_eq!(
+            hunks,
+            [
+                LineHunk {
+                    old: 1..2,
+                    new: 1..2
+                },
+                LineHunk {
+                    old: 4..4,
+                    new: 4..5
+                },
+            ]
+        );
+        assert_eq!(
+            line_hunks("a\nb\nc\n", "a\nc\n"),
+            [LineHunk {
+                old: 1..2,
+                new: 1..1
+            }]
+        );
+        assert_eq!(
+            line_hunks("", "a\n"),
+            [LineHunk {
+                old: 0..0,
+                new: 0..1
+            }]
+        );
+        assert!(line_hunks("a\nb", "a\nb").is_empty());
+    }
+
+    #[test]
+    fn test_apply_line_hunks() {
+        let (old, new) = ("a\nb\nc\nd", "a\nB\nc\nd\ne\n");
+        let hunks = line_hunks(old, new);
+        assert_eq!(apply_line_hunks(old, new, &hunks), new);
+        assert_eq!(apply_line_hunks(old, new, &hunks[..1]), "a\nB\nc\nd");
+        assert_eq!(apply_line_hunks(old, new, &hunks[1..]), "a\nb\nc\nd\ne\n");
+        assert_eq!(apply_line_hunks(old, new, &[]), old);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_diff_turns_old_into_new(
+            old in "[ab \t\r\n]{0,40}",
+            new in "[ab \t\r\n]{0,40}",
+        ) {
+            let edits = diff(&old, &new);
+            prop_assert_eq!(apply_edits(&old, &edits), new);
+            // Edits run from the end of the text to the start
+            for pair in edits.windows(2) {
+                prop_assert!(pair[1].range.end <= pair[0].range.start);
+            }
+        }
+    }
+}
@@ -3,10 +3,28 @@
 //! This crate provides the workspace abstraction for the Gouide daemon,
 //! including file management, buffer tracking, and workspace state.
 
-#![allow(dead_code)]
+mod buffer;
+mod diff;
+mod listing;
+mod manager;
+mod mapped;
+mod ot;
+mod text;
+mod workspace;
 
 use thiserror::Error;
 
+pub use buffer::{
+    AppliedEdits, Buffer, BufferContent, BufferLimits, DiskChange, LineEnding, SaveOptions,
+    SaveOutcome, Savepoint, ENCODING_UTF8, ENCODING_UTF8_BOM,
+};
+pub use diff::{apply_edits, apply_line_hunks, diff, line_hunks, LineHunk};
+pub use listing::{DirEntry, EntryKind, ListOptions};
+pub use manager::{CloseOutcome, SharedBuffer, WorkspaceManager};
+pub use ot::{transform, transform_edits, Priority};
+pub use text::{ByteEdit, BytePoint, Position, TextEdit, TextRange};
+pub use workspace::Workspace;
+
 /// Errors that can occur during workspace operations.
 #[derive(Error, Debug)]
 pub enum WorkspaceError {
@@ -18,32 +36,60 @@ pub enum WorkspaceError {
     #[error("Buffer not found: {0}")]
     BufferNotFound(String),
 
+    /// A client-specified buffer ID is already bound to another file.
+    #[error("Buffer ID already in use: {0}")]
+    BufferIdInUse(String),
+
+    /// Buffer has unsaved changes and the operation would discard them.
+    #[error("Buffer has unsaved changes: {0}")]
+    BufferDirty(String),
+
+    /// Buffer cannot be modified.
+    #[error("Buffer is read-only: {0}")]
+    ReadOnly(String),
+
+    /// The client's expected version does not match the buffer.
+    #[error("Version conflict: expected {expected}, buffer is at {actual}")]
+    VersionConflict {
+        /// Version the client based its request on.
+        expected: u64,
+        /// Current buffer version.
+        actual: u64,
+    },
+
+    /// An edit range is malformed.
+    #[error("Invalid range: {0}")]
+    InvalidRange(String),
+
+    /// File does not exist.
+    #[error("File not found: {0}")]
+    FileNotFound(String),
+
+    /// Access to the file was denied.
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    /// Path is malformed or outside the workspace.
+    #[error("Invalid path: {0}")]
+    InvalidPath(String),
+
+    /// File content is not in a supported encoding.
+    #[error("Unsupported encoding: {0}")]
+    UnsupportedEncoding(String),
+
     /// An I/O error occurred during workspace operations.
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
 
-/// Placeholder workspace manager.
-///
-/// Future implementation will handle:
-/// - Opening and managing workspaces
-/// - Buffer lifecycle (open, modify, save, close)
-/// - File watching integration
-/// - Index coordination
-pub struct WorkspaceManager {
-    // Will hold workspace state
-}
-
-impl WorkspaceManager {
-    /// Create a new workspace manager.
-    pub fn new() -> Self {
-        Self {}
-    }
-}
-
-impl Default for WorkspaceManager {
-    fn default() -> Self {
-        Self::new()
+impl WorkspaceError {
+    /// Map an I/O error on `path` to the most specific variant.
+    pub(crate) fn from_io(error: std::io::Error, path: &str) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => Self::FileNotFound(path.to_string()),
+            std::io::ErrorKind::PermissionDenied => Self::PermissionDenied(path.to_string()),
+            _ => Self::Io(error),
+        }
     }
 }
 
@@ -0,0 +1,266 @@
+//! Directory listings.
+
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::{Workspace, WorkspaceError};
+
+/// Kind of a directory entry. Symlinks are reported as such and never
+/// followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Regular file.
+    File,
+    /// Directory.
+    Directory,
+    /// Symbolic link.
+    Symlink,
+}
+
+/// An entry in a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Workspace-relative path.
+    pub file_id: String,
+    /// File name.
+    pub name: String,
+    /// Workspace-relative path of the containing directory, empty at the
+    /// root.
+    pub parent: String,
+    /// Entry kind.
+    pub kind: EntryKind,
+    /// Size in bytes, 0 for directories.
+    pub size: u64,
+    /// Last modification time, if the platform reports one.
+    pub modified_at: Option<SystemTime>,
+    /// Number of children for directories (hidden ones included), 0 for
+    /// others.
+    pub child_count: u32,
+}
+
+/// What to include in a listing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ListOptions {
+    /// Include entries whose name starts with a dot.
+    pub include_hidden: bool,
+    /// Descend into subdirectories.
+    pub recursive: bool,
+    /// Levels below the listed directory to descend when recursive, 0 for
+    /// no limit.
+    pub max_depth: u32,
+}
+
+impl Workspace {
+    /// List a directory, `""` being the workspace root.
+    ///
+    /// Entries are in tree order: directories before files, each group
+    /// sorted by case-insensitive name, and a recursive listing places a
+    /// directory's contents right after it.
+    pub fn list_directory(
+        &self,
+        dir: &str,
+        options: ListOptions,
+    ) -> Result<Vec<DirEntry>, WorkspaceError> {
+        let dir = dir.trim_matches('/');
+        let path = if dir.is_empty() {
+            self.root().to_path_buf()
+        } else {
+            self.resolve_path(dir)?
+        };
+        if !path.is_dir() {
+            return Err(if path.exists() {
+                WorkspaceError::InvalidPath(format!("{dir} is not a directory"))
+            } else {
+                WorkspaceError::FileNotFound(dir.to_string())
+            });
+        }
+
+        let mut entries = Vec::new();
+        list(&path, dir, options, 1, &mut entries)?;
+        Ok(entries)
+    }
+
+    /// The entry of one path, as a listing of its directory would have it.
+    pub fn entry(&self, file_id: &str) -> Result<DirEntry, WorkspaceError> {
+        let file_id = file_id.trim_matches('/');
+        let path = self.resolve_path(file_id)?;
+        let metadata =
+            fs::symlink_metadata(&path).map_err(|e| WorkspaceError::from_io(e, file_id))?;
+        let (parent, name) = file_id.rsplit_once('/').unwrap_or(("", file_id));
+        Ok(dir_entry(&path, name.to_string(), parent, &metadata))
+    }
+}
+
+fn list(
+    path: &Path,
+    parent: &str,
+    options: ListOptions,
+    depth: u32,
+    entries: &mut Vec<DirEntry>,
+) -> Result<(), WorkspaceError> {
+    let mut children = read_dir(path, parent)?
+        .into_iter()
+        .filter(|entry| options.include_hidden || !entry.name.starts_with('.'))
+        .collect::<Vec<_>>();
+    children.sort_by(|a, b| {
+        (a.kind != EntryKind::Directory)
+            .cmp(&(b.kind != EntryKind::Directory))
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    let descend = options.recursive && (options.max_depth == 0 || depth < options.max_depth);
+    for child in children {
+        let subdir = (descend && child.kind == EntryKind::Directory)
+            .then(|| (path.join(&child.name), child.file_id.clone()));
+        entries.push(child);
+        if let Some((path, file_id)) = subdir {
+            // A directory removed mid-listing is just skipped
+            match list(&path, &file_id, options, depth + 1, entries) {
+                Ok(()) | Err(WorkspaceError::FileNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read one directory without sorting or filtering.
+fn read_dir(path: &Path, parent: &str) -> Result<Vec<DirEntry>, WorkspaceError> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path).map_err(|e| WorkspaceError::from_io(e, parent))? {
+        let Ok(entry) = entry else { continue };
+        // Entries that vanish between readdir and stat are skipped
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        entries.push(dir_entry(&entry.path(), name, parent, &metadata));
+    }
+    Ok(entries)
+}
+
+/// The entry of a path named `name` in the directory `parent`, from its
+/// metadata, which must not follow symlinks.
+fn dir_entry(path: &Path, name: String, parent: &str, metadata: &fs::Metadata) -> DirEntry {
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        EntryKind::Symlink
+    } else if file_type.is_dir() {
+        EntryKind::Directory
+    } else {
+        EntryKind::File
+    };
+    let child_count = if kind == EntryKind::Directory {
+        fs::read_dir(path).map_or(0, |children| {
+            u32::try_from(children.count()).unwrap_or(u32::MAX)
+        })
+    } else {
+        0
+    };
+    DirEntry {
+        file_id: if parent.is_empty() {
+            name.clone()
+        } else {
+            format!("{parent}/{name}")
+        },
+        name,
+        parent: parent.to_string(),
+        kind,
+        size: if kind == EntryKind::File {
+            metadata.len()
+        } else {
+            0
+        },
+        modified_at: metadata.modified().ok(),
+        child_count,
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn workspace(dir: &TempDir) -> Workspace {
+        Workspace::new(
+            "w1".to_string(),
+            dir.path().to_path_buf(),
+            "w".to_string(),
+            vec![],
+        )
+    }
+
+    fn ids(entries: &[DirEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.file_id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_list_directory() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("src/nested")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.path().join("src/nested/a.rs"), "").unwrap();
+        fs::write(dir.path().join("README.md"), "# hi\n").unwrap();
+        fs::write(dir.path().join("build.rs"), "").unwrap();
+        fs::write(dir.path().join(".env"), "").unwrap();
+        let workspace = workspace(&dir);
+
+        let top = workspace
+            .list_directory("", ListOptions::default())
+            .unwrap();
+        assert_eq!(ids(&top), ["src", "build.rs", "README.md"]);
+        assert_eq!(top[0].kind, EntryKind::Directory);
+        assert_eq!(top[0].child_count, 2);
+        assert_eq!(top[2].size, 5);
+
+        let hidden = ListOptions {
+            include_hidden: true,
+            ..ListOptions::default()
+        };
+        assert_eq!(workspace.list_directory("", hidden).unwrap().len(), 4);
+
+        let all = ListOptions {
+            recursive: true,
+            ..ListOptions::default()
+        };
+        let listed = workspace.list_directory("src", all).unwrap();
+        assert_eq!(
+            ids(&listed),
+            ["src/nested", "src/nested/a.rs", "src/main.rs"]
+        );
+        assert_eq!(listed[1].parent, "src/nested");
+
+        let shallow = ListOptions {
+            recursive: true,
+            max_depth: 1,
+            ..ListOptions::default()
+        };
+        assert_eq!(workspace.list_directory("", shallow).unwrap().len(), 3);
+
+        assert!(matches!(
+            workspace.list_directory("missing", all),
+            Err(WorkspaceError::FileNotFound(_))
+        ));
+        assert!(matches!(
+            workspace.list_directory("build.rs", all),
+            Err(WorkspaceError::InvalidPath(_))
+        ));
+        assert!(workspace.list_directory("../", all).is_err());
+
+        assert_eq!(workspace.entry("src/nested/a.rs").unwrap(), listed[1]);
+        assert_eq!(workspace.entry("src").unwrap(), top[0]);
+        assert!(matches!(
+            workspace.entry("src/gone.rs"),
+            Err(WorkspaceError::FileNotFound(_))
+        ));
+    }
+}
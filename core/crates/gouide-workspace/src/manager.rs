@@ -0,0 +1,499 @@
+//! Registry of open workspaces and buffers.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use uuid::Uuid;
+
+use crate::buffer::{Buffer, BufferLimits, SaveOptions};
+use crate::workspace::Workspace;
+use crate::WorkspaceError;
+
+/// A buffer shared between the services that operate on it.
+pub type SharedBuffer = Arc<RwLock<Buffer>>;
+
+/// An open buffer and the client sessions holding it open.
+struct OpenBuffer {
+    buffer: SharedBuffer,
+    sessions: HashSet<String>,
+}
+
+/// Result of closing a buffer for one session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloseOutcome {
+    /// Whether the session held the buffer. `false` means there was
+    /// nothing to close.
+    pub closed: bool,
+    /// Whether the buffer was saved before closing.
+    pub saved: bool,
+    /// Whether the buffer was released. `false` means other sessions still
+    /// hold it open.
+    pub released: bool,
+}
+
+/// Workspace manager.
+///
+/// Owns every open workspace and buffer in the daemon. Lookups are cheap and
+/// synchronous; loading and saving file content is blocking I/O and should be
+/// run off the async runtime by callers.
+///
+/// Buffers are reference-counted by client session: a buffer stays open until
+/// every session that opened it has closed it (or disconnected).
+pub struct WorkspaceManager {
+    limits: BufferLimits,
+    workspaces: RwLock<HashMap<String, Arc<Workspace>>>,
+    buffers: RwLock<HashMap<String, OpenBuffer>>,
+}
+
+impl WorkspaceManager {
+    /// Create a new workspace manager.
+    pub fn new() -> Self {
+        Self::with_limits(BufferLimits::default())
+    }
+
+    /// Create a workspace manager with custom buffer limits.
+    pub fn with_limits(limits: BufferLimits) -> Self {
+        Self {
+            limits,
+            workspaces: RwLock::new(HashMap::new()),
+            buffers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Buffer limits in effect.
+    pub fn limits(&self) -> &BufferLimits {
+        &self.limits
+    }
+
+    /// Open a workspace folder.
+    ///
+    /// Opening a folder that is already open returns the existing workspace,
+    /// so several clients attached to the daemon share one session.
+    pub fn open_workspace(
+        &self,
+        folder_path: &Path,
+        name: Option<&str>,
+        exclude_patterns: Vec<String>,
+    ) -> Result<Arc<Workspace>, WorkspaceError> {
+        let display = folder_path.display().to_string();
+        let root = folder_path
+            .canonicalize()
+            .map_err(|e| WorkspaceError::from_io(e, &display))?;
+        if !root.is_dir() {
+            return Err(WorkspaceError::InvalidPath(format!(
+                "{display} is not a directory"
+            )));
+        }
+
+        let mut workspaces = self.workspaces.write();
+        if let Some(existing) = workspaces.values().find(|w| w.root() == root) {
+            return Ok(existing.clone());
+        }
+
+        let name = name.filter(|n| !n.is_empty()).map_or_else(
+            || {
+                root.file_name()
+                    .map_or_else(|| display.clone(), |n| n.to_string_lossy().into_owned())
+            },
+            ToString::to_string,
+        );
+        let workspace = Arc::new(Workspace::new(
+            Uuid::new_v4().to_string(),
+            root,
+            name,
+            exclude_patterns,
+        ));
+        workspaces.insert(workspace.id().to_string(), workspace.clone());
+        drop(workspaces);
+
+        Ok(workspace)
+    }
+
+    /// Close a workspace and drop all of its buffers. Returns the IDs of the
+    /// dropped buffers.
+    pub fn close_workspace(&self, workspace_id: &str) -> Result<Vec<String>, WorkspaceError> {
+        if self.workspaces.write().remove(workspace_id).is_none() {
+            return Err(WorkspaceError::NotFound(workspace_id.to_string()));
+        }
+        let mut closed = Vec::new();
+        self.buffers.write().retain(|id, open| {
+            let keep = open.buffer.read().workspace_id() != workspace_id;
+            if !keep {
+                closed.push(id.clone());
+            }
+            keep
+        });
+        Ok(closed)
+    }
+
+    /// Get a workspace by ID.
+    pub fn workspace(&self, workspace_id: &str) -> Result<Arc<Workspace>, WorkspaceError> {
+        self.workspaces
+            .read()
+            .get(workspace_id)
+            .cloned()
+            .ok_or_else(|| WorkspaceError::NotFound(workspace_id.to_string()))
+    }
+
+    /// Open a file into a buffer for `session`, or return the buffer it is
+    /// already open in.
+    ///
+    /// If `buffer_id` is `None` the daemon assigns one. A client-specified ID
+    /// that is already bound to a different file is rejected.
+    pub fn open_buffer(
+        &self,
+        workspace_id: &str,
+        file_id: &str,
+        buffer_id: Option<String>,
+        session: &str,
+    ) -> Result<SharedBuffer, WorkspaceError> {
+        let workspace = self.workspace(workspace_id)?;
+        let path = workspace.resolve_path(file_id)?;
+
+        if let Some(existing) =
+            self.attach_open(workspace_id, file_id, buffer_id.as_deref(), session)?
+        {
+            return Ok(existing);
+        }
+
+        let id = buffer_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let buffer = Buffer::load(
+            id.clone(),
+            workspace_id.to_string(),
+            file_id.to_string(),
+            &path,
+            &self.limits,
+        )?;
+
+        // Another request may have opened the same file while we were loading.
+        if let Some(existing) = self.attach_open(workspace_id, file_id, None, session)? {
+            return Ok(existing);
+        }
+        let shared = Arc::new(RwLock::new(buffer));
+        self.buffers.write().insert(
+            id,
+            OpenBuffer {
+                buffer: shared.clone(),
+                sessions: HashSet::from([session.to_string()]),
+            },
+        );
+
+        Ok(shared)
+    }
+
+    /// Find an already-open buffer for a file and add `session` to its holders.
+    fn attach_open(
+        &self,
+        workspace_id: &str,
+        file_id: &str,
+        buffer_id: Option<&str>,
+        session: &str,
+    ) -> Result<Option<SharedBuffer>, WorkspaceError> {
+        let mut buffers = self.buffers.write();
+        if let Some(id) = buffer_id {
+            if let Some(open) = buffers.get(id) {
+                let same_file = {
+                    let guard = open.buffer.read();
+                    guard.workspace_id() == workspace_id && guard.file_id() == file_id
+                };
+                if !same_file {
+                    return Err(WorkspaceError::BufferIdInUse(id.to_string()));
+                }
+            }
+        }
+        let existing = buffers
+            .values_mut()
+            .find(|open| {
+                let b = open.buffer.read();
+                b.workspace_id() == workspace_id && b.file_id() == file_id
+            })
+            .map(|open| {
+                open.sessions.insert(session.to_string());
+                open.buffer.clone()
+            });
+        drop(buffers);
+        Ok(existing)
+    }
+
+    /// Get an open buffer by ID.
+    pub fn buffer(&self, buffer_id: &str) -> Result<SharedBuffer, WorkspaceError> {
+        self.buffers
+            .read()
+            .get(buffer_id)
+            .map(|open| open.buffer.clone())
+            .ok_or_else(|| WorkspaceError::BufferNotFound(buffer_id.to_string()))
+    }
+
+    /// Open buffers in a workspace.
+    pub fn list_buffers(&self, workspace_id: &str) -> Result<Vec<SharedBuffer>, WorkspaceError> {
+        self.workspace(workspace_id)?;
+        Ok(self
+            .buffers
+            .read()
+            .values()
+            .filter(|open| open.buffer.read().workspace_id() == workspace_id)
+            .map(|open| open.buffer.clone())
+            .collect())
+    }
+
+    /// Number of client sessions holding a buffer open.
+    pub fn session_count(&self, buffer_id: &str) -> usize {
+        self.buffers
+            .read()
+            .get(buffer_id)
+            .map_or(0, |open| open.sessions.len())
+    }
+
+    /// Whether `session` holds a buffer open.
+    pub fn holds(&self, buffer_id: &str, session: &str) -> bool {
+        self.buffers
+            .read()
+            .get(buffer_id)
+            .is_some_and(|open| open.sessions.contains(session))
+    }
+
+    /// Close a buffer for `session`.
+    ///
+    /// The buffer is only released once no other session holds it. Releasing
+    /// a dirty buffer fails with [`WorkspaceError::BufferDirty`] unless
+    /// `save_before_close` (write it first, with those options) or `force`
+    /// (discard the changes) is set. A session that does not hold the
+    /// buffer changes nothing.
+    pub fn close_buffer(
+        &self,
+        buffer_id: &str,
+        session: &str,
+        save_before_close: Option<SaveOptions>,
+        force: bool,
+    ) -> Result<CloseOutcome, WorkspaceError> {
+        let shared = self.buffer(buffer_id)?;
+        if !self.holds(buffer_id, session) {
+            return Ok(CloseOutcome {
+                closed: false,
+                saved: false,
+                released: false,
+            });
+        }
+        let saved = match save_before_close {
+            Some(options) if shared.read().is_dirty() => {
+                shared.write().save(options)?;
+                true
+            }
+            _ => false,
+        };
+
+        let mut buffers = self.buffers.write();
+        let open = buffers
+            .get_mut(buffer_id)
+            .ok_or_else(|| WorkspaceError::BufferNotFound(buffer_id.to_string()))?;
+        if open.sessions.iter().any(|s| s != session) {
+            open.sessions.remove(session);
+            return Ok(CloseOutcome {
+                closed: true,
+                saved,
+                released: false,
+            });
+        }
+
+        {
+            let buffer = open.buffer.read();
+            if buffer.is_dirty() && !force {
+                return Err(WorkspaceError::BufferDirty(buffer.file_id().to_string()));
+            }
+        }
+        buffers.remove(buffer_id);
+        drop(buffers);
+
+        Ok(CloseOutcome {
+            closed: true,
+            saved,
+            released: true,
+        })
+    }
+
+    /// Drop every buffer hold of a disconnected session.
+    ///
+    /// Clean buffers nobody else holds are released. Dirty ones are kept so a
+    /// reconnecting client can recover its unsaved work. Returns the IDs of
+    /// the released buffers.
+    pub fn release_session(&self, session: &str) -> Vec<String> {
+        let mut released = Vec::new();
+        self.buffers.write().retain(|id, open| {
+            if !open.sessions.remove(session) || !open.sessions.is_empty() {
+                return true;
+            }
+            let keep = open.buffer.read().is_dirty();
+            if !keep {
+                released.push(id.clone());
+            }
+            keep
+        });
+        released
+    }
+
+    /// Number of open buffers in a workspace.
+    pub fn buffer_count(&self, workspace_id: &str) -> usize {
+        self.buffers
+            .read()
+            .values()
+            .filter(|open| open.buffer.read().workspace_id() == workspace_id)
+            .count()
+    }
+}
+
+impl Default for WorkspaceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn setup() -> (TempDir, WorkspaceManager, String) {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.rs"), "fn main() {}\n").unwrap();
+        let manager = WorkspaceManager::new();
+        let workspace = manager.open_workspace(dir.path(), None, vec![]).unwrap();
+        let id = workspace.id().to_string();
+        (dir, manager, id)
+    }
+
+    #[test]
+    fn test_open_workspace_twice_is_shared() {
+        let (dir, manager, id) = setup();
+        let again = manager.open_workspace(dir.path(), None, vec![]).unwrap();
+        assert_eq!(again.id(), id);
+    }
+
+    #[test]
+    fn test_open_buffer_shares_file() {
+        let (_dir, manager, id) = setup();
+        let first = manager.open_buffer(&id, "a.rs", None, "s1").unwrap();
+        let second = manager.open_buffer(&id, "a.rs", None, "s1").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(manager.buffer_count(&id), 1);
+    }
+
+    #[test]
+    fn test_open_buffer_missing_file() {
+        let (_dir, manager, id) = setup();
+        let result = manager.open_buffer(&id, "missing.rs", None, "s1");
+        assert!(matches!(result, Err(WorkspaceError::FileNotFound(_))));
+    }
+
+    #[test]
+    fn test_buffer_id_bound_to_other_file() {
+        let (dir, manager, id) = setup();
+        fs::write(dir.path().join("b.rs"), "").unwrap();
+        manager
+            .open_buffer(&id, "a.rs", Some("mine".to_string()), "s1")
+            .unwrap();
+        let result = manager.open_buffer(&id, "b.rs", Some("mine".to_string()), "s1");
+        assert!(matches!(result, Err(WorkspaceError::BufferIdInUse(_))));
+    }
+
+    #[test]
+    fn test_close_workspace_drops_buffers() {
+        let (_dir, manager, id) = setup();
+        let buffer = manager.open_buffer(&id, "a.rs", None, "s1").unwrap();
+        let buffer_id = buffer.read().id().to_string();
+
+        assert_eq!(
+            manager.close_workspace(&id).unwrap(),
+            vec![buffer_id.clone()]
+        );
+        assert!(manager.buffer(&buffer_id).is_err());
+        assert!(manager.workspace(&id).is_err());
+    }
+
+    fn edit(buffer: &SharedBuffer) {
+        buffer
+            .write()
+            .apply_edits(&[crate::TextEdit::new(crate::TextRange::lines(0, 0), "// ")])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_buffer_stays_open_while_other_session_holds_it() {
+        let (_dir, manager, id) = setup();
+        let buffer = manager.open_buffer(&id, "a.rs", None, "window-1").unwrap();
+        manager.open_buffer(&id, "a.rs", None, "window-2").unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        assert_eq!(manager.session_count(&buffer_id), 2);
+
+        // Dirty, but another window still has it open
+        edit(&buffer);
+        let outcome = manager
+            .close_buffer(&buffer_id, "window-1", None, false)
+            .unwrap();
+        assert!(outcome.closed);
+        assert!(!outcome.released);
+        assert!(manager.buffer(&buffer_id).is_ok());
+        assert_eq!(manager.session_count(&buffer_id), 1);
+
+        // Closing again, or from a window that never opened it, is a no-op
+        for session in ["window-1", "window-3"] {
+            let outcome = manager
+                .close_buffer(&buffer_id, session, None, true)
+                .unwrap();
+            assert!(!outcome.closed && !outcome.released);
+        }
+        assert_eq!(manager.session_count(&buffer_id), 1);
+
+        // The last holder must decide what happens to the changes
+        let result = manager.close_buffer(&buffer_id, "window-2", None, false);
+        assert!(matches!(result, Err(WorkspaceError::BufferDirty(_))));
+        let outcome = manager
+            .close_buffer(&buffer_id, "window-2", None, true)
+            .unwrap();
+        assert!(outcome.released);
+        assert!(!outcome.saved);
+        assert!(manager.buffer(&buffer_id).is_err());
+    }
+
+    #[test]
+    fn test_save_before_close() {
+        let (dir, manager, id) = setup();
+        let buffer = manager.open_buffer(&id, "a.rs", None, "s1").unwrap();
+        let buffer_id = buffer.read().id().to_string();
+        edit(&buffer);
+
+        let outcome = manager
+            .close_buffer(&buffer_id, "s1", Some(SaveOptions::default()), false)
+            .unwrap();
+        assert!(outcome.saved);
+        assert!(outcome.released);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs")).unwrap(),
+            "// fn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_release_session_keeps_dirty_buffers() {
+        let (dir, manager, id) = setup();
+        fs::write(dir.path().join("b.rs"), "").unwrap();
+        let clean = manager.open_buffer(&id, "a.rs", None, "s1").unwrap();
+        let dirty = manager.open_buffer(&id, "b.rs", None, "s1").unwrap();
+        edit(&dirty);
+
+        let released = manager.release_session("s1");
+        assert_eq!(released, vec![clean.read().id().to_string()]);
+        assert!(manager.buffer(dirty.read().id()).is_ok());
+        assert_eq!(manager.list_buffers(&id).unwrap().len(), 1);
+    }
+}
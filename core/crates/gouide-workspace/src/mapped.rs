@@ -0,0 +1,164 @@
+//! Read-only memory-mapped storage for very large files.
+//!
+//! Large files (multi-hundred-megabyte logs, generated data) are never copied
+//! onto the heap. Instead the file is mapped and a sparse line index is built
+//! so that any line window can be located without rescanning the whole file.
+
+// Memory mapping requires an unsafe call; the invariants are documented below.
+#![allow(unsafe_code)]
+
+use std::fs::File;
+use std::io;
+
+use memmap2::Mmap;
+
+use crate::text::{trim_line_ending, utf16_len};
+
+/// Number of lines between two entries of the sparse line index.
+const LINE_INDEX_STRIDE: usize = 1024;
+
+/// A read-only, memory-mapped text file.
+///
+/// Only `\n` and `\r\n` line endings are recognised in mapped mode. Invalid
+/// UTF-8 sequences are replaced with U+FFFD when text is extracted.
+pub(crate) struct MappedText {
+    map: Mmap,
+    /// Byte offset of every `LINE_INDEX_STRIDE`-th line start.
+    line_index: Vec<usize>,
+    line_count: u32,
+    /// UTF-16 length of the last line (cached so the document end is cheap).
+    last_line_len: u32,
+}
+
+impl MappedText {
+    /// Map a file and build its sparse line index.
+    pub(crate) fn open(file: &File) -> io::Result<Self> {
+        // SAFETY: the mapping is read-only and never handed out as `&'static`.
+        // If another process truncates the file while it is mapped, reads may
+        // fault; this is the accepted trade-off of mapped mode and the reason
+        // mapped buffers are read-only and reloaded on external change.
+        let map = unsafe { Mmap::map(file)? };
+
+        let mut line_index = vec![0];
+        let mut lines = 1usize;
+        for newline in memchr::memchr_iter(b'\n', &map) {
+            if lines % LINE_INDEX_STRIDE == 0 {
+                line_index.push(newline + 1);
+            }
+            lines += 1;
+        }
+
+        let last_start = memchr::memrchr(b'\n', &map).map_or(0, |i| i + 1);
+        let last_line = String::from_utf8_lossy(&map[last_start..]);
+        let last_line_len = utf16_len(trim_line_ending(&last_line));
+
+        Ok(Self {
+            map,
+            line_index,
+            line_count: u32::try_from(lines).unwrap_or(u32::MAX),
+            last_line_len,
+        })
+    }
+
+    /// Length of the mapped file in bytes.
+    pub(crate) fn len_bytes(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Total number of lines.
+    pub(crate) fn line_count(&self) -> u32 {
+        self.line_count
+    }
+
+    /// UTF-16 length of the last line.
+    pub(crate) fn last_line_len(&self) -> u32 {
+        self.last_line_len
+    }
+
+    /// Byte offset of the start of `line` (the file length if past the end).
+    pub(crate) fn line_to_byte(&self, line: u32) -> usize {
+        if line >= self.line_count {
+            return self.map.len();
+        }
+        let line = line as usize;
+        let checkpoint = line / LINE_INDEX_STRIDE;
+        let mut offset = self.line_index[checkpoint];
+        for _ in 0..line % LINE_INDEX_STRIDE {
+            match memchr::memchr(b'\n', &self.map[offset..]) {
+                Some(i) => offset += i + 1,
+                None => return self.map.len(),
+            }
+        }
+        offset
+    }
+
+    /// Line containing the given byte offset.
+    pub(crate) fn byte_to_line(&self, byte: usize) -> u32 {
+        let byte = byte.min(self.map.len());
+        let checkpoint = self.line_index.partition_point(|&start| start <= byte) - 1;
+        let start = self.line_index[checkpoint];
+        let newlines = memchr::memchr_iter(b'\n', &self.map[start..byte]).count();
+        u32::try_from(checkpoint * LINE_INDEX_STRIDE + newlines).unwrap_or(u32::MAX)
+    }
+
+    /// Decode a byte range as text.
+    pub(crate) fn text(&self, start: usize, end: usize) -> String {
+        String::from_utf8_lossy(&self.map[start..end]).into_owned()
+    }
+
+    /// Move `byte` back to the nearest UTF-8 character boundary.
+    pub(crate) fn floor_char_boundary(&self, mut byte: usize) -> usize {
+        byte = byte.min(self.map.len());
+        while byte > 0 && byte < self.map.len() && (self.map[byte] & 0xc0) == 0x80 {
+            byte -= 1;
+        }
+        byte
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use std::fmt::Write as _;
+    use std::io::Write;
+
+    use super::*;
+
+    fn mapped(content: &str) -> (tempfile::NamedTempFile, MappedText) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        let text = MappedText::open(file.as_file()).unwrap();
+        (file, text)
+    }
+
+    #[test]
+    fn test_line_index_across_checkpoints() {
+        let content = (0..5000).fold(String::new(), |mut out, i| {
+            writeln!(out, "line {i}").unwrap();
+            out
+        });
+        let (_file, text) = mapped(&content);
+
+        assert_eq!(text.line_count(), 5001);
+        let start = text.line_to_byte(3000);
+        let end = text.line_to_byte(3001);
+        assert_eq!(text.text(start, end), "line 3000\n");
+        assert_eq!(text.byte_to_line(start), 3000);
+        assert_eq!(text.byte_to_line(start + 3), 3000);
+        assert_eq!(text.line_to_byte(6000), content.len());
+    }
+
+    #[test]
+    fn test_last_line_len_and_boundaries() {
+        let (_file, text) = mapped("a\nhé😀");
+        assert_eq!(text.line_count(), 2);
+        assert_eq!(text.last_line_len(), 4);
+        // Byte 6 is inside the 4-byte emoji that starts at byte 5
+        assert_eq!(text.floor_char_boundary(6), 5);
+    }
+}
@@ -0,0 +1,299 @@
+//! Operational transformation of concurrent text edits.
+//!
+//! Two clients editing the same buffer both base their edits on the version
+//! they last saw. When an edit arrives for an older version, it is transformed
+//! against every edit applied since, so it lands where its author intended.
+//!
+//! Each [`TextEdit`] replaces one range. Transforming edit `x` against an edit
+//! `y` that was applied first follows these rules, which make the result
+//! independent of arrival order (the convergence property):
+//!
+//! - Replaced regions are merged; inserted texts are ordered by where their
+//!   ranges start (then end). Fully identical ranges are ordered by priority.
+//! - An edit strictly inside a region replaced by the other one is dropped:
+//!   the enclosing replacement wins.
+
+use crate::text::{Position, TextEdit, TextRange};
+
+/// Which edit goes first when two edits target the same range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// The edit being transformed goes first.
+    First,
+    /// The edit it is transformed against goes first.
+    Second,
+}
+
+impl Priority {
+    /// The priority seen from the other edit.
+    #[must_use]
+    pub const fn flip(self) -> Self {
+        match self {
+            Self::First => Self::Second,
+            Self::Second => Self::First,
+        }
+    }
+}
+
+/// Transform `edits` (applied in order) so they apply after `applied`.
+///
+/// Both sequences must be based on the same document. Edits that are
+/// swallowed by a concurrent replacement become empty no-op edits, so the
+/// result always has one edit per input edit.
+pub fn transform_edits(
+    edits: &[TextEdit],
+    applied: &[TextEdit],
+    priority: Priority,
+) -> Vec<TextEdit> {
+    let mut edits = edits.to_vec();
+    for other in applied {
+        // `other` moves through `edits` as each one is applied before it
+        let mut other = other.clone();
+        for edit in &mut edits {
+            let transformed = transform(edit, &other, priority);
+            other = transform(&other, edit, priority.flip());
+            *edit = transformed;
+        }
+    }
+    edits
+}
+
+/// Transform `edit` so it applies after `applied`.
+pub fn transform(edit: &TextEdit, applied: &TextEdit, priority: Priority) -> TextEdit {
+    let (x, y) = (edit.range, applied.range);
+
+    // Swallowed by the other replacement
+    if y.start < x.start && x.end < y.end {
+        return TextEdit::new(TextRange::new(y.start, y.start), "");
+    }
+    // Swallows the other replacement, including its text
+    if x.start < y.start && y.end < x.end {
+        return TextEdit::new(
+            TextRange::new(x.start, shift(x.end, applied)),
+            edit.new_text.clone(),
+        );
+    }
+
+    let before = match (x.start, x.end).cmp(&(y.start, y.end)) {
+        std::cmp::Ordering::Less => true,
+        std::cmp::Ordering::Greater => false,
+        std::cmp::Ordering::Equal => priority == Priority::First,
+    };
+    let start = map(x.start, applied, before);
+    let end = map(x.end, applied, before).max(start);
+    TextEdit::new(TextRange::new(start, end), edit.new_text.clone())
+}
+
+/// Map a position through `applied`. Positions inside or on the edges of
+/// the replaced range land before or after the inserted text.
+fn map(position: Position, applied: &TextEdit, before: bool) -> Position {
+    let range = applied.range;
+    if position < range.start {
+        position
+    } else if position > range.end || !before {
+        shift(position, applied)
+    } else {
+        range.start
+    }
+}
+
+/// Move a position at or after the end of `applied`'s range by the size
+/// difference of the replacement.
+///
+/// Client positions are not clamped to the text yet, so one past the end
+/// of a line or of the text saturates instead of overflowing; the buffer
+/// clamps it when the edit is applied.
+fn shift(position: Position, applied: &TextEdit) -> Position {
+    let end = applied.range.end;
+    let inserted_end = applied.range.start.advance(&applied.new_text);
+    if position <= end {
+        inserted_end
+    } else if position.line == end.line {
+        Position::new(
+            inserted_end.line,
+            inserted_end
+                .character
+                .saturating_add(position.character - end.character),
+        )
+    } else {
+        Position::new(
+            (position.line - end.line).saturating_add(inserted_end.line),
+            position.character,
+        )
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Byte offset of a position in ASCII text.
+    fn offset(text: &str, position: Position) -> usize {
+        let line_start: usize = text
+            .split_inclusive('\n')
+            .take(position.line as usize)
+            .map(str::len)
+            .sum();
+        line_start + position.character as usize
+    }
+
+    /// Position of a byte offset in ASCII text.
+    fn position(text: &str, offset: usize) -> Position {
+        Position::new(0, 0).advance(&text[..offset])
+    }
+
+    fn apply(text: &str, edits: &[TextEdit]) -> String {
+        edits.iter().fold(text.to_string(), |mut text, edit| {
+            let start = offset(&text, edit.range.start);
+            let end = offset(&text, edit.range.end);
+            text.replace_range(start..end, &edit.new_text);
+            text
+        })
+    }
+
+    fn edit(text: &str, start: usize, end: usize, new_text: &str) -> TextEdit {
+        TextEdit::new(
+            TextRange::new(position(text, start), position(text, end)),
+            new_text,
+        )
+    }
+
+    #[test]
+    fn test_insert_before_shifts_later_edit() {
+        let doc = "one\ntwo\n";
+        let applied = edit(doc, 0, 0, "zero\n");
+        let rebased = transform(&edit(doc, 4, 7, "TWO"), &applied, Priority::Second);
+        assert_eq!(
+            rebased.range,
+            TextRange::new(Position::new(2, 0), Position::new(2, 3))
+        );
+        assert_eq!(
+            apply(&apply(doc, &[applied]), &[rebased]),
+            "zero\none\nTWO\n"
+        );
+    }
+
+    #[test]
+    fn test_same_position_inserts_follow_priority() {
+        let doc = "ab";
+        let mine = edit(doc, 1, 1, "X");
+        let theirs = edit(doc, 1, 1, "Y");
+        let rebased = transform(&mine, &theirs, Priority::Second);
+        assert_eq!(apply(&apply(doc, &[theirs]), &[rebased]), "aYXb");
+    }
+
+    #[test]
+    fn test_enclosing_replacement_wins() {
+        let doc = "abcdef";
+        let inner = edit(doc, 2, 3, "X");
+        let outer = edit(doc, 1, 5, "Y");
+        let rebased = transform(&inner, &outer, Priority::Second);
+        assert!(rebased.range.is_empty() && rebased.new_text.is_empty());
+        assert_eq!(
+            apply(
+                &apply(doc, std::slice::from_ref(&inner)),
+                &[transform(&outer, &inner, Priority::First)]
+            ),
+            "aYf"
+        );
+    }
+
+    /// A document of short ASCII lines.
+    fn document() -> impl Strategy<Value = String> {
+        prop::collection::vec("[a-c]{0,4}", 1..5).prop_map(|lines| lines.join("\n"))
+    }
+
+    /// An edit valid on `doc`, described by fractions of its length.
+    fn edit_strategy() -> impl Strategy<Value = (f64, f64, String)> {
+        (0.0..=1.0f64, 0.0..=1.0f64, "[xy\n]{0,3}")
+    }
+
+    fn make_edit(doc: &str, (a, b, new_text): &(f64, f64, String)) -> TextEdit {
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let at = |f: f64| (f * doc.len() as f64) as usize;
+        let (start, end) = (at(a.min(*b)), at(a.max(*b)));
+        edit(doc, start, end, new_text)
+    }
+
+    /// Build a sequence of edits where each is valid on the document
+    /// produced by the previous ones.
+    fn make_edits(doc: &str, specs: &[(f64, f64, String)]) -> Vec<TextEdit> {
+        let mut text = doc.to_string();
+        specs
+            .iter()
+            .map(|spec| {
+                let edit = make_edit(&text, spec);
+                text = apply(&text, std::slice::from_ref(&edit));
+                edit
+            })
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_single_edits_converge(
+            doc in document(),
+            a in edit_strategy(),
+            b in edit_strategy(),
+        ) {
+            let a = make_edit(&doc, &a);
+            let b = make_edit(&doc, &b);
+            let a_then_b = apply(&doc, &[a.clone(), transform(&b, &a, Priority::First)]);
+            let b_then_a = apply(&doc, &[b.clone(), transform(&a, &b, Priority::Second)]);
+            prop_assert_eq!(a_then_b, b_then_a);
+        }
+
+        #[test]
+        fn prop_edit_sequences_converge(
+            doc in document(),
+            a in prop::collection::vec(edit_strategy(), 1..4),
+            b in prop::collection::vec(edit_strategy(), 1..4),
+        ) {
+            let a = make_edits(&doc, &a);
+            let b = make_edits(&doc, &b);
+            let mut a_then_b = apply(&doc, &a);
+            a_then_b = apply(&a_then_b, &transform_edits(&b, &a, Priority::First));
+            let mut b_then_a = apply(&doc, &b);
+            b_then_a = apply(&b_then_a, &transform_edits(&a, &b, Priority::Second));
+            prop_assert_eq!(a_then_b, b_then_a);
+        }
+
+        #[test]
+        fn prop_out_of_range_positions_do_not_overflow(
+            line in prop::sample::select(vec![0, 1, u32::MAX - 1, u32::MAX]),
+            character in prop::sample::select(vec![0, 1, u32::MAX - 1, u32::MAX]),
+            new_text in "[ab\n]{0,4}",
+        ) {
+            // Inserting lines and characters before a far-off position
+            // pushes it past the largest one
+            let origin = Position::new(0, 0);
+            let inserted = origin.advance(&new_text);
+            let applied = TextEdit::new(TextRange::new(origin, origin), new_text);
+            let far = Position::new(line, character);
+            let stale = TextEdit::new(TextRange::new(far, far), "x");
+            let rebased = transform(&stale, &applied, Priority::Second);
+            let clamp = |value: u64| u32::try_from(value).unwrap_or(u32::MAX);
+            let expected = if line == 0 {
+                Position::new(
+                    inserted.line,
+                    clamp(u64::from(inserted.character) + u64::from(character)),
+                )
+            } else {
+                Position::new(clamp(u64::from(line) + u64::from(inserted.line)), character)
+            };
+            prop_assert_eq!(rebased.range, TextRange::new(expected, expected));
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Text coordinates used by buffers.
+//!
+//! Positions follow the protocol convention: 0-based lines and UTF-16 code
+//! unit offsets within a line, so they can be handed to LSP servers as-is.
+
+/// A position in a text document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Position {
+    /// 0-based line number.
+    pub line: u32,
+    /// 0-based character offset in UTF-16 code units.
+    pub character: u32,
+}
+
+impl Position {
+    /// Create a new position.
+    pub const fn new(line: u32, character: u32) -> Self {
+        Self { line, character }
+    }
+
+    /// Position reached after inserting `text` at this position.
+    #[must_use]
+    pub fn advance(self, text: &str) -> Self {
+        let mut line = self.line;
+        let mut last_line_start = None;
+        let bytes = text.as_bytes();
+        let mut i = 0;
+        while let Some(offset) = memchr::memchr2(b'\n', b'\r', &bytes[i..]) {
+            i += offset;
+            if bytes[i] == b'\r' && bytes.get(i + 1) == Some(&b'\n') {
+                i += 1;
+            }
+            i += 1;
+            line += 1;
+            last_line_start = Some(i);
+        }
+        last_line_start.map_or_else(
+            || Self::new(line, self.character + utf16_len(text)),
+            |start| Self::new(line, utf16_len(&text[start..])),
+        )
+    }
+}
+
+/// A position in byte coordinates: a line and a byte offset within it.
+///
+/// This is what incremental parsers such as tree-sitter work with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct BytePoint {
+    /// 0-based line number.
+    pub line: u32,
+    /// Byte offset within the line.
+    pub column: usize,
+}
+
+impl BytePoint {
+    /// Create a new byte point.
+    pub const fn new(line: u32, column: usize) -> Self {
+        Self { line, column }
+    }
+
+    /// Point reached after inserting `text` at this point.
+    #[must_use]
+    pub fn advance(self, text: &str) -> Self {
+        let bytes = text.as_bytes();
+        let mut line = self.line;
+        let mut last_line_start = None;
+        let mut i = 0;
+        while let Some(offset) = memchr::memchr2(b'\n', b'\r', &bytes[i..]) {
+            i += offset;
+            if bytes[i] == b'\r' && bytes.get(i + 1) == Some(&b'\n') {
+                i += 1;
+            }
+            i += 1;
+            line += 1;
+            last_line_start = Some(i);
+        }
+        last_line_start.map_or_else(
+            || Self::new(line, self.column + text.len()),
+            |start| Self::new(line, text.len() - start),
+        )
+    }
+}
+
+/// An applied edit in byte coordinates, for updating incremental parsers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteEdit {
+    /// Byte offset where the edit starts.
+    pub start_byte: usize,
+    /// Byte offset where the replaced text ended, before the edit.
+    pub old_end_byte: usize,
+    /// Byte offset where the inserted text ends, after the edit.
+    pub new_end_byte: usize,
+    /// Point where the edit starts.
+    pub start: BytePoint,
+    /// Point where the replaced text ended, before the edit.
+    pub old_end: BytePoint,
+    /// Point where the inserted text ends, after the edit.
+    pub new_end: BytePoint,
+}
+
+/// A half-open range in a text document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TextRange {
+    /// Start position (inclusive).
+    pub start: Position,
+    /// End position (exclusive).
+    pub end: Position,
+}
+
+impl TextRange {
+    /// Create a new range.
+    pub const fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+
+    /// Range covering whole lines `start_line..end_line`.
+    pub const fn lines(start_line: u32, end_line: u32) -> Self {
+        Self {
+            start: Position::new(start_line, 0),
+            end: Position::new(end_line, 0),
+        }
+    }
+
+    /// Whether the range is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+}
+
+/// A replacement of a range of text.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TextEdit {
+    /// Range to replace (empty to insert).
+    pub range: TextRange,
+    /// Replacement text (empty to delete).
+    pub new_text: String,
+}
+
+impl TextEdit {
+    /// Create a new edit.
+    pub fn new(range: TextRange, new_text: impl Into<String>) -> Self {
+        Self {
+            range,
+            new_text: new_text.into(),
+        }
+    }
+}
+
+/// Byte offset of a UTF-16 column within a single line of text.
+///
+/// Columns past the end of the line clamp to the line length, and columns
+/// that fall inside a surrogate pair snap to the start of that character.
+pub(crate) fn utf16_col_to_byte(line: &str, character: u32) -> usize {
+    let mut units = 0u32;
+    for (byte, ch) in line.char_indices() {
+        let width = u32::try_from(ch.len_utf16()).unwrap_or(2);
+        if units + width > character {
+            return byte;
+        }
+        units += width;
+    }
+    line.len()
+}
+
+/// Length of a string in UTF-16 code units.
+pub(crate) fn utf16_len(text: &str) -> u32 {
+    u32::try_from(text.encode_utf16().count()).unwrap_or(u32::MAX)
+}
+
+/// Strip a trailing line terminator (`\n`, `\r\n` or `\r`).
+pub(crate) fn trim_line_ending(line: &str) -> &str {
+    line.strip_suffix("\r\n")
+        .or_else(|| line.strip_suffix('\n'))
+        .or_else(|| line.strip_suffix('\r'))
+        .unwrap_or(line)
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16_col_to_byte() {
+        assert_eq!(utf16_col_to_byte("hello", 2), 2);
+        assert_eq!(utf16_col_to_byte("hello", 99), 5);
+        // 'é' is 2 bytes but 1 UTF-16 unit
+        assert_eq!(utf16_col_to_byte("héllo", 2), 3);
+        // '😀' is 4 bytes and 2 UTF-16 units
+        assert_eq!(utf16_col_to_byte("a😀b", 3), 5);
+        assert_eq!(utf16_col_to_byte("a😀b", 2), 1);
+    }
+
+    #[test]
+    fn test_trim_line_ending() {
+        assert_eq!(trim_line_ending("abc\r\n"), "abc");
+        assert_eq!(trim_line_ending("abc\n"), "abc");
+        assert_eq!(trim_line_ending("abc\r"), "abc");
+        assert_eq!(trim_line_ending("abc"), "abc");
+    }
+
+    #[test]
+    fn test_position_advance() {
+        let start = Position::new(2, 4);
+        assert_eq!(start.advance(""), start);
+        assert_eq!(start.advance("ab😀"), Position::new(2, 8));
+        assert_eq!(start.advance("x\ny"), Position::new(3, 1));
+        assert_eq!(start.advance("a\r\nbc\r"), Position::new(4, 0));
+    }
+}
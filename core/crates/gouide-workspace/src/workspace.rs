@@ -0,0 +1,125 @@
+//! Workspace sessions.
+
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::WorkspaceError;
+
+/// An open workspace folder.
+#[derive(Debug)]
+pub struct Workspace {
+    id: String,
+    root: PathBuf,
+    name: String,
+    exclude_patterns: Vec<String>,
+    opened_at: SystemTime,
+}
+
+impl Workspace {
+    pub(crate) fn new(
+        id: String,
+        root: PathBuf,
+        name: String,
+        exclude_patterns: Vec<String>,
+    ) -> Self {
+        Self {
+            id,
+            root,
+            name,
+            exclude_patterns,
+            opened_at: SystemTime::now(),
+        }
+    }
+
+    /// Workspace identifier.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Canonical absolute root folder.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Display name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Patterns excluded from watching and indexing.
+    pub fn exclude_patterns(&self) -> &[String] {
+        &self.exclude_patterns
+    }
+
+    /// When the workspace was opened.
+    pub fn opened_at(&self) -> SystemTime {
+        self.opened_at
+    }
+
+    /// Resolve a workspace-relative file path to an absolute path.
+    ///
+    /// Rejects absolute paths and any `..` component so a client can never
+    /// reach outside the workspace root.
+    pub fn resolve_path(&self, file_id: &str) -> Result<PathBuf, WorkspaceError> {
+        let relative = Path::new(file_id);
+        if file_id.is_empty() {
+            return Err(WorkspaceError::InvalidPath("empty file path".to_string()));
+        }
+        for component in relative.components() {
+            match component {
+                Component::Normal(_) | Component::CurDir => {}
+                _ => {
+                    return Err(WorkspaceError::InvalidPath(format!(
+                        "{file_id} must be a workspace-relative path"
+                    )));
+                }
+            }
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// Workspace-relative file ID of an absolute path, if it is inside the
+    /// workspace.
+    pub fn file_id_of(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        if relative.as_os_str().is_empty() {
+            return None;
+        }
+        Some(
+            relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+        )
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::uninlined_format_args
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_path_rejects_escapes() {
+        let workspace = Workspace::new(
+            "w1".to_string(),
+            PathBuf::from("/repo"),
+            "repo".to_string(),
+            vec![],
+        );
+
+        assert_eq!(
+            workspace.resolve_path("src/main.rs").unwrap(),
+            PathBuf::from("/repo/src/main.rs")
+        );
+        assert!(workspace.resolve_path("../etc/passwd").is_err());
+        assert!(workspace.resolve_path("/etc/passwd").is_err());
+        assert!(workspace.resolve_path("").is_err());
+    }
+}